@@ -29,9 +29,9 @@ use crate::math::{amm, amm_spread, bn, cp_curve, quote_asset::*};
 
 use crate::state::events::CurveRecord;
 use crate::state::oracle::OraclePriceData;
-use crate::state::perp_market::{PerpMarket, AMM};
+use crate::state::perp_market::{PerpMarket, PoolBalance, AMM};
 use crate::state::spot_market::{SpotBalance, SpotBalanceType, SpotMarket};
-use crate::state::user::{SpotPosition, User};
+use crate::state::user::SpotPosition;
 use crate::validate;
 
 #[cfg(test)]
@@ -383,6 +383,18 @@ pub fn get_fee_pool_tokens(
     .cast()
 }
 
+/// balance of one of the perp market's pools as a quote amount
+fn get_pool_quote_amount(
+    market: &PerpMarket,
+    pool: &PoolBalance,
+    spot_market: &SpotMarket,
+) -> DriftResult<i128> {
+    market.get_quote_amount(
+        get_token_amount(pool.balance(), spot_market, pool.balance_type())?.cast()?,
+        spot_market,
+    )
+}
+
 fn calculate_revenue_pool_transfer(
     market: &PerpMarket,
     spot_market: &SpotMarket,
@@ -454,12 +466,17 @@ fn calculate_revenue_pool_transfer(
             .saturating_sub(market.insurance_claim.revenue_withdraw_since_last_settle)
             .cast::<u128>()?
             .min(
-                get_token_amount(
-                    spot_market.revenue_pool.scaled_balance,
-                    spot_market,
-                    &SpotBalanceType::Deposit,
-                )?
-                .cast()?,
+                market
+                    .get_quote_amount(
+                        get_token_amount(
+                            spot_market.revenue_pool.scaled_balance,
+                            spot_market,
+                            &SpotBalanceType::Deposit,
+                        )?
+                        .cast()?,
+                        spot_market,
+                    )?
+                    .cast()?,
             )
             .min(
                 market
@@ -489,12 +506,8 @@ pub fn update_pool_balances(
     now: i64,
 ) -> DriftResult<i128> {
     // current spot_market balance of amm fee pool
-    let amm_fee_pool_token_amount = get_token_amount(
-        market.amm.fee_pool.balance(),
-        spot_market,
-        market.amm.fee_pool.balance_type(),
-    )?
-    .cast::<i128>()?;
+    let amm_fee_pool_token_amount =
+        get_pool_quote_amount(market, &market.amm.fee_pool, spot_market)?;
 
     let mut fraction_for_amm = 100;
 
@@ -511,7 +524,7 @@ pub fn update_pool_balances(
 
         if pnl_pool_addition < 0 {
             transfer_spot_balances(
-                pnl_pool_addition.abs(),
+                market.get_quote_token_amount(pnl_pool_addition.abs(), spot_market)?,
                 spot_market,
                 &mut market.amm.fee_pool,
                 &mut market.pnl_pool,
@@ -526,18 +539,12 @@ pub fn update_pool_balances(
             .safe_add(market.amm.total_liquidation_fee)?
             .safe_sub(market.amm.total_fee_withdrawn)?;
 
-        let amm_fee_pool_token_amount = get_token_amount(
-            market.amm.fee_pool.balance(),
-            spot_market,
-            market.amm.fee_pool.balance_type(),
-        )?;
+        let amm_fee_pool_token_amount =
+            get_pool_quote_amount(market, &market.amm.fee_pool, spot_market)?.cast::<u128>()?;
 
         if amm_fee_pool_token_amount < amm_target_min_fee_pool_token_amount {
-            let pnl_pool_token_amount = get_token_amount(
-                market.pnl_pool.balance(),
-                spot_market,
-                market.pnl_pool.balance_type(),
-            )?;
+            let pnl_pool_token_amount =
+                get_pool_quote_amount(market, &market.pnl_pool, spot_market)?.cast::<u128>()?;
 
             let pnl_pool_removal = amm_target_min_fee_pool_token_amount
                 .safe_sub(amm_fee_pool_token_amount)?
@@ -545,7 +552,7 @@ pub fn update_pool_balances(
 
             if pnl_pool_removal > 0 {
                 transfer_spot_balances(
-                    pnl_pool_removal.cast::<i128>()?,
+                    market.get_quote_token_amount(pnl_pool_removal.cast()?, spot_market)?,
                     spot_market,
                     &mut market.pnl_pool,
                     &mut market.amm.fee_pool,
@@ -553,11 +560,8 @@ pub fn update_pool_balances(
             }
         }
 
-        let amm_fee_pool_token_amount_after = get_token_amount(
-            market.amm.fee_pool.balance(),
-            spot_market,
-            market.amm.fee_pool.balance_type(),
-        )?;
+        let amm_fee_pool_token_amount_after =
+            get_pool_quote_amount(market, &market.amm.fee_pool, spot_market)?.cast::<u128>()?;
 
         let terminal_state_surplus = market
             .amm
@@ -588,7 +592,9 @@ pub fn update_pool_balances(
         match revenue_pool_transfer.cmp(&0) {
             Ordering::Greater => {
                 transfer_spot_balance_to_revenue_pool(
-                    revenue_pool_transfer.unsigned_abs(),
+                    market
+                        .get_quote_token_amount(revenue_pool_transfer, spot_market)?
+                        .unsigned_abs(),
                     spot_market,
                     &mut market.amm.fee_pool,
                 )?;
@@ -600,7 +606,9 @@ pub fn update_pool_balances(
             }
             Ordering::Less => {
                 transfer_revenue_pool_to_spot_balance(
-                    revenue_pool_transfer.unsigned_abs(),
+                    market
+                        .get_quote_token_amount(revenue_pool_transfer.abs(), spot_market)?
+                        .unsigned_abs(),
                     spot_market,
                     &mut market.amm.fee_pool,
                 )?;
@@ -623,27 +631,29 @@ pub fn update_pool_balances(
     }
 
     // market pnl pool pays (what it can to) user_unsettled_pnl and pnl_to_settle_to_amm
-    let pnl_pool_token_amount = get_token_amount(
-        market.pnl_pool.balance(),
-        spot_market,
-        market.pnl_pool.balance_type(),
-    )?;
+    let pnl_pool_token_amount = get_pool_quote_amount(market, &market.pnl_pool, spot_market)?;
 
     let pnl_to_settle_with_user = if user_unsettled_pnl > 0 {
-        min(user_unsettled_pnl, pnl_pool_token_amount.cast::<i128>()?)
+        min(user_unsettled_pnl, pnl_pool_token_amount)
     } else {
         let token_amount = user_quote_position.get_signed_token_amount(spot_market)?;
 
         // dont settle negative pnl to spot borrows when utilization is high (> 80%)
-        let max_withdraw_amount =
-            -get_max_withdraw_for_market_with_token_amount(spot_market, token_amount, false)?
-                .cast::<i128>()?;
+        let max_withdraw_amount = -market.get_quote_amount(
+            get_max_withdraw_for_market_with_token_amount(spot_market, token_amount, false)?
+                .cast()?,
+            spot_market,
+        )?;
 
         max_withdraw_amount.max(user_unsettled_pnl)
     };
 
-    let pnl_fraction_for_amm = if fraction_for_amm > 0 && pnl_to_settle_with_user < 0 {
-        let pnl_fraction_for_amm = pnl_to_settle_with_user.safe_div(fraction_for_amm)?;
+    // the pools move by the same token amount the user's quote balance is settled with
+    let token_amount_to_settle_with_user =
+        market.get_quote_token_amount(pnl_to_settle_with_user, spot_market)?;
+
+    let pnl_fraction_for_amm = if fraction_for_amm > 0 && token_amount_to_settle_with_user < 0 {
+        let pnl_fraction_for_amm = token_amount_to_settle_with_user.safe_div(fraction_for_amm)?;
         update_spot_balances(
             pnl_fraction_for_amm.unsigned_abs(),
             &SpotBalanceType::Deposit,
@@ -656,7 +666,8 @@ pub fn update_pool_balances(
         0
     };
 
-    let pnl_to_settle_with_market = -(pnl_to_settle_with_user.safe_sub(pnl_fraction_for_amm)?);
+    let pnl_to_settle_with_market =
+        -(token_amount_to_settle_with_user.safe_sub(pnl_fraction_for_amm)?);
 
     update_spot_balances(
         pnl_to_settle_with_market.unsigned_abs(),
@@ -678,18 +689,11 @@ pub fn update_pool_balances(
 pub fn update_pnl_pool_and_user_balance(
     market: &mut PerpMarket,
     bank: &mut SpotMarket,
-    user: &mut User,
+    user_quote_position: &mut dyn SpotBalance,
     unrealized_pnl_with_fee: i128,
 ) -> DriftResult<i128> {
     let pnl_to_settle_with_user = if unrealized_pnl_with_fee > 0 {
-        unrealized_pnl_with_fee.min(
-            get_token_amount(
-                market.pnl_pool.scaled_balance,
-                bank,
-                market.pnl_pool.balance_type(),
-            )?
-            .cast()?,
-        )
+        unrealized_pnl_with_fee.min(get_pool_quote_amount(market, &market.pnl_pool, bank)?)
     } else {
         unrealized_pnl_with_fee
     };
@@ -716,13 +720,11 @@ pub fn update_pnl_pool_and_user_balance(
         return Ok(0);
     }
 
-    let user_spot_position = user.get_quote_spot_position_mut();
-
     transfer_spot_balances(
-        pnl_to_settle_with_user,
+        market.get_quote_token_amount(pnl_to_settle_with_user, bank)?,
         bank,
         &mut market.pnl_pool,
-        user_spot_position,
+        user_quote_position,
     )?;
 
     Ok(pnl_to_settle_with_user)
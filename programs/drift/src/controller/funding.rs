@@ -14,7 +14,7 @@ use crate::math::casting::Cast;
 use crate::math::constants::{
     FUNDING_RATE_BUFFER, FUNDING_RATE_OFFSET_DENOMINATOR, ONE_HOUR_I128, TWENTY_FOUR_HOUR,
 };
-use crate::math::funding::{calculate_funding_payment, calculate_funding_rate_long_short};
+use crate::math::funding::{calculate_funding_rate_long_short, calculate_perp_funding_payment};
 use crate::math::helpers::on_the_hour_update;
 use crate::math::safe_math::SafeMath;
 use crate::math::stats::calculate_new_twap;
@@ -56,10 +56,8 @@ pub fn settle_funding_payment(
             .last_cumulative_funding_rate
             .cast()?
     {
-        let market_funding_payment = calculate_funding_payment(
-            amm_cumulative_funding_rate,
-            &user.perp_positions[position_index],
-        )?;
+        let market_funding_payment =
+            calculate_perp_funding_payment(market, &user.perp_positions[position_index])?;
 
         user.update_cumulative_perp_funding(market_funding_payment)?;
 
@@ -115,10 +113,8 @@ pub fn settle_funding_payments(
                 .last_cumulative_funding_rate
                 .cast()?
         {
-            let market_funding_payment = calculate_funding_payment(
-                amm_cumulative_funding_rate,
-                &user.perp_positions[position_index],
-            )?;
+            let market_funding_payment =
+                calculate_perp_funding_payment(market, &user.perp_positions[position_index])?;
 
             user.update_cumulative_perp_funding(market_funding_payment)?;
 
@@ -182,8 +178,11 @@ pub fn update_funding_rate(
         market.amm.funding_period,
     )?;
 
-    let valid_funding_update =
-        !funding_paused && !block_funding_rate_update && (time_until_next_update == 0);
+    // options and prediction markets don't pay funding
+    let valid_funding_update = !funding_paused
+        && !block_funding_rate_update
+        && !market.is_orderbook_only()
+        && (time_until_next_update == 0);
 
     if valid_funding_update {
         let oracle_price_data = oracle_map.get_price_data(&market.amm.oracle)?;
@@ -813,10 +813,15 @@ pub fn resolve_perp_pnl_deficit(
         market.insurance_claim.quote_max_insurance,
     )?;
 
+    let insurance_vault_value = market.get_quote_amount(
+        insurance_vault_amount.saturating_sub(1).cast()?,
+        spot_market,
+    )?;
+
     let insurance_withdraw = excess_user_pnl_imbalance
         .min(max_revenue_withdraw_per_period)
         .min(max_insurance_withdraw)
-        .min(insurance_vault_amount.saturating_sub(1).cast()?);
+        .min(insurance_vault_value);
 
     validate!(
         insurance_withdraw > 0,
@@ -852,8 +857,11 @@ pub fn resolve_perp_pnl_deficit(
 
     market.insurance_claim.last_revenue_withdraw_ts = now;
 
+    let insurance_withdraw_token_amount =
+        market.get_quote_token_amount(insurance_withdraw, spot_market)?;
+
     update_spot_balances(
-        insurance_withdraw.cast()?,
+        insurance_withdraw_token_amount.cast()?,
         &SpotBalanceType::Deposit,
         spot_market,
         &mut market.pnl_pool,
@@ -864,7 +872,7 @@ pub fn resolve_perp_pnl_deficit(
         ts: now,
         spot_market_index: spot_market.market_index,
         perp_market_index: market.market_index,
-        amount: -insurance_withdraw.cast()?,
+        amount: -insurance_withdraw_token_amount.cast()?,
         user_if_factor: spot_market.insurance_fund.user_factor,
         total_if_factor: spot_market.insurance_fund.total_factor,
         vault_amount_before: vault_amount,
@@ -873,5 +881,5 @@ pub fn resolve_perp_pnl_deficit(
         total_if_shares_after: spot_market.insurance_fund.total_shares,
     });
 
-    insurance_withdraw.cast()
+    insurance_withdraw_token_amount.cast()
 }
@@ -9,21 +9,22 @@ use crate::controller::lp::burn_lp_shares;
 use crate::controller::orders;
 use crate::controller::position::{
     get_position_index, update_position_and_market, update_quote_asset_amount,
-    update_quote_asset_and_break_even_amount, PositionDirection,
+    update_quote_asset_and_break_even_amount, update_settled_pnl, PositionDirection,
 };
 use crate::controller::repeg::update_amm_and_check_validity;
 use crate::controller::spot_balance::{
-    update_revenue_pool_balances, update_spot_balances, update_spot_market_and_check_validity,
+    transfer_spot_balances, update_quote_spot_market_for_perp_market, update_revenue_pool_balances,
+    update_spot_balances, update_spot_market_and_check_validity,
     update_spot_market_cumulative_interest,
 };
 use crate::controller::spot_position::update_spot_balances_and_cumulative_deposits;
 use crate::error::{DriftResult, ErrorCode};
 use crate::get_then_update_id;
-use crate::math::bankruptcy::is_user_bankrupt;
+use crate::math::bankruptcy::{is_isolated_perp_position_bankrupt, is_user_bankrupt};
 use crate::math::casting::Cast;
 use crate::math::constants::{
     LIQUIDATION_FEE_PRECISION_U128, LIQUIDATION_PCT_PRECISION, QUOTE_PRECISION,
-    QUOTE_PRECISION_I128, QUOTE_PRECISION_U64, QUOTE_SPOT_MARKET_INDEX, SPOT_WEIGHT_PRECISION,
+    QUOTE_PRECISION_I128, QUOTE_PRECISION_U64, SPOT_WEIGHT_PRECISION,
 };
 use crate::math::liquidation::{
     calculate_asset_transfer_for_liability_transfer,
@@ -84,13 +85,151 @@ pub fn liquidate_perp(
     slot: u64,
     now: i64,
     state: &State,
+) -> DriftResult {
+    let collateral = if user.is_isolated_perp_market(market_index) {
+        PerpLiquidationCollateral::Isolated { market_index }
+    } else {
+        PerpLiquidationCollateral::Cross
+    };
+
+    liquidate_perp_with_collateral(
+        collateral,
+        market_index,
+        liquidator_max_base_asset_amount,
+        limit_price,
+        user,
+        user_key,
+        user_stats,
+        liquidator,
+        liquidator_key,
+        liquidator_stats,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        slot,
+        now,
+        state,
+    )
+}
+
+/// Where the collateral backing a liquidated perp position comes from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PerpLiquidationCollateral {
+    /// The user's cross margin account. The user enters liquidation status and is liquidated gradually
+    Cross,
+    /// The isolated perp position's own collateral. The rest of the account isn't checked or touched,
+    /// and the user doesn't enter liquidation status
+    Isolated { market_index: u16 },
+}
+
+impl PerpLiquidationCollateral {
+    fn margin_context(
+        self,
+        liquidation_margin_buffer_ratio: u32,
+        market_index: u16,
+    ) -> DriftResult<MarginContext> {
+        let context = MarginContext::liquidation(liquidation_margin_buffer_ratio);
+        match self {
+            PerpLiquidationCollateral::Cross => {
+                context.track_market_margin_requirement(MarketIdentifier::perp(market_index))
+            }
+            PerpLiquidationCollateral::Isolated { .. } => Ok(context),
+        }
+    }
+
+    fn meets_margin_requirement(self, margin_calculation: &MarginCalculation) -> bool {
+        match self {
+            PerpLiquidationCollateral::Cross => margin_calculation.meets_cross_margin_requirement(),
+            PerpLiquidationCollateral::Isolated { market_index } => {
+                margin_calculation.meets_isolated_margin_requirement(market_index)
+            }
+        }
+    }
+
+    fn can_exit_liquidation(self, margin_calculation: &MarginCalculation) -> DriftResult<bool> {
+        match self {
+            PerpLiquidationCollateral::Cross => margin_calculation.can_exit_liquidation(),
+            PerpLiquidationCollateral::Isolated { market_index } => {
+                margin_calculation.can_exit_isolated_liquidation(market_index)
+            }
+        }
+    }
+
+    fn margin_shortage(self, margin_calculation: &MarginCalculation) -> DriftResult<u128> {
+        match self {
+            PerpLiquidationCollateral::Cross => margin_calculation.margin_shortage(),
+            PerpLiquidationCollateral::Isolated { market_index } => {
+                margin_calculation.isolated_margin_shortage(market_index)
+            }
+        }
+    }
+
+    /// The part of the margin shortage the liquidated market is responsible for
+    fn market_margin_shortage(
+        self,
+        margin_calculation: &MarginCalculation,
+        margin_shortage: u128,
+    ) -> DriftResult<u128> {
+        match self {
+            PerpLiquidationCollateral::Cross => {
+                margin_calculation.tracked_market_margin_shortage(margin_shortage)
+            }
+            PerpLiquidationCollateral::Isolated { .. } => Ok(margin_shortage),
+        }
+    }
+
+    fn margin_requirement(self, margin_calculation: &MarginCalculation) -> u128 {
+        match self {
+            PerpLiquidationCollateral::Cross => margin_calculation.margin_requirement,
+            PerpLiquidationCollateral::Isolated { market_index } => margin_calculation
+                .get_isolated_margin_calculation(market_index)
+                .map_or(0, |isolated| isolated.margin_requirement),
+        }
+    }
+
+    fn total_collateral(self, margin_calculation: &MarginCalculation) -> i128 {
+        match self {
+            PerpLiquidationCollateral::Cross => margin_calculation.total_collateral,
+            PerpLiquidationCollateral::Isolated { market_index } => margin_calculation
+                .get_isolated_margin_calculation(market_index)
+                .map_or(0, |isolated| isolated.total_collateral),
+        }
+    }
+
+    fn is_bankrupt(self, user: &User) -> bool {
+        match self {
+            PerpLiquidationCollateral::Cross => user.is_bankrupt(),
+            PerpLiquidationCollateral::Isolated { market_index } => {
+                is_isolated_perp_position_bankrupt(user, market_index)
+            }
+        }
+    }
+}
+
+fn liquidate_perp_with_collateral(
+    collateral: PerpLiquidationCollateral,
+    market_index: u16,
+    liquidator_max_base_asset_amount: u64,
+    limit_price: Option<u64>,
+    user: &mut User,
+    user_key: &Pubkey,
+    user_stats: &mut UserStats,
+    liquidator: &mut User,
+    liquidator_key: &Pubkey,
+    liquidator_stats: &mut UserStats,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    slot: u64,
+    now: i64,
+    state: &State,
 ) -> DriftResult {
     let liquidation_margin_buffer_ratio = state.liquidation_margin_buffer_ratio;
     let initial_pct_to_liquidate = state.initial_pct_to_liquidate as u128;
     let liquidation_duration = state.liquidation_duration as u128;
 
     validate!(
-        !user.is_bankrupt(),
+        !collateral.is_bankrupt(user),
         ErrorCode::UserBankrupt,
         "user bankrupt",
     )?;
@@ -133,14 +272,16 @@ pub fn liquidate_perp(
         perp_market_map,
         spot_market_map,
         oracle_map,
-        MarginContext::liquidation(liquidation_margin_buffer_ratio)
-            .track_market_margin_requirement(MarketIdentifier::perp(market_index))?,
+        collateral.margin_context(liquidation_margin_buffer_ratio, market_index)?,
     )?;
 
-    if !user.is_being_liquidated() && margin_calculation.meets_margin_requirement() {
+    let is_being_liquidated =
+        collateral == PerpLiquidationCollateral::Cross && user.is_being_liquidated();
+
+    if !is_being_liquidated && collateral.meets_margin_requirement(&margin_calculation) {
         msg!("margin calculation: {:?}", margin_calculation);
         return Err(ErrorCode::SufficientCollateral);
-    } else if user.is_being_liquidated() && margin_calculation.can_exit_liquidation()? {
+    } else if is_being_liquidated && margin_calculation.can_exit_liquidation()? {
         user.exit_liquidation();
         return Ok(());
     }
@@ -163,7 +304,12 @@ pub fn liquidate_perp(
             e
         })?;
 
-    let liquidation_id = user.enter_liquidation(slot)?;
+    let liquidation_id = match collateral {
+        PerpLiquidationCollateral::Cross => user.enter_liquidation(slot)?,
+        PerpLiquidationCollateral::Isolated { .. } => {
+            get_then_update_id!(user, next_liquidation_id)
+        }
+    };
     let mut margin_freed = 0_u64;
 
     let position_index = get_position_index(&user.perp_positions, market_index)?;
@@ -174,6 +320,12 @@ pub fn liquidate_perp(
         ErrorCode::PositionDoesntHaveOpenPositionOrOrders
     )?;
 
+    // isolated collateral only backs the orders in its own market
+    let (market_type_to_cancel, market_index_to_cancel) = match collateral {
+        PerpLiquidationCollateral::Cross => (None, None),
+        PerpLiquidationCollateral::Isolated { .. } => (Some(MarketType::Perp), Some(market_index)),
+    };
+
     let canceled_order_ids = orders::cancel_orders(
         user,
         user_key,
@@ -184,8 +336,8 @@ pub fn liquidate_perp(
         now,
         slot,
         OrderActionExplanation::Liquidation,
-        None,
-        None,
+        market_type_to_cancel,
+        market_index_to_cancel,
         None,
     )?;
 
@@ -201,12 +353,15 @@ pub fn liquidate_perp(
         Some(DriftAction::Liquidate),
     )?;
 
-    let oracle_price = if market.status == MarketStatus::Settlement {
+    let underlying_oracle_price = if market.status == MarketStatus::Settlement {
         market.expiry_price
     } else {
         oracle_price_data.price
     };
 
+    // options are liquidated at their intrinsic value
+    let oracle_price = market.get_valuation_price(underlying_oracle_price)?;
+
     drop(market);
 
     // burning lp shares = removing open bids/asks
@@ -240,28 +395,30 @@ pub fn liquidate_perp(
                 perp_market_map,
                 spot_market_map,
                 oracle_map,
-                MarginContext::liquidation(liquidation_margin_buffer_ratio)
-                    .track_market_margin_requirement(MarketIdentifier::perp(market_index))?,
+                collateral.margin_context(liquidation_margin_buffer_ratio, market_index)?,
             )?;
 
-        let initial_margin_shortage = margin_calculation.margin_shortage()?;
-        let new_margin_shortage = intermediate_margin_calculation.margin_shortage()?;
+        let initial_margin_shortage = collateral.margin_shortage(&margin_calculation)?;
+        let new_margin_shortage = collateral.margin_shortage(&intermediate_margin_calculation)?;
 
         margin_freed = initial_margin_shortage
             .saturating_sub(new_margin_shortage)
             .cast::<u64>()?;
-        user.increment_margin_freed(margin_freed)?;
 
-        if intermediate_margin_calculation.can_exit_liquidation()? {
+        if collateral == PerpLiquidationCollateral::Cross {
+            user.increment_margin_freed(margin_freed)?;
+        }
+
+        if collateral.can_exit_liquidation(&intermediate_margin_calculation)? {
             emit!(LiquidationRecord {
                 ts: now,
                 liquidation_id,
                 liquidation_type: LiquidationType::LiquidatePerp,
                 user: *user_key,
                 liquidator: *liquidator_key,
-                margin_requirement: margin_calculation.margin_requirement,
-                total_collateral: margin_calculation.total_collateral,
-                bankrupt: user.is_bankrupt(),
+                margin_requirement: collateral.margin_requirement(&margin_calculation),
+                total_collateral: collateral.total_collateral(&margin_calculation),
+                bankrupt: collateral.is_bankrupt(user),
                 canceled_order_ids,
                 margin_freed,
                 liquidate_perp: LiquidatePerpRecord {
@@ -273,7 +430,9 @@ pub fn liquidate_perp(
                 ..LiquidationRecord::default()
             });
 
-            user.exit_liquidation();
+            if collateral == PerpLiquidationCollateral::Cross {
+                user.exit_liquidation();
+            }
             return Ok(());
         }
 
@@ -299,7 +458,7 @@ pub fn liquidate_perp(
     )?;
 
     let oracle_price_too_divergent = is_oracle_too_divergent_with_twap_5min(
-        oracle_price,
+        underlying_oracle_price,
         perp_market_map
             .get_ref(&market_index)?
             .amm
@@ -327,14 +486,15 @@ pub fn liquidate_perp(
 
     let margin_ratio_with_buffer = margin_ratio.safe_add(liquidation_margin_buffer_ratio)?;
 
-    let margin_shortage = intermediate_margin_calculation.margin_shortage()?;
+    let margin_shortage = collateral.margin_shortage(&intermediate_margin_calculation)?;
 
     let market = perp_market_map.get_ref(&market_index)?;
     let quote_spot_market = spot_market_map.get_ref(&market.quote_spot_market_index)?;
-    let quote_oracle_price = oracle_map.get_price_data(&quote_spot_market.oracle)?.price;
+    let quote_oracle_price =
+        market.get_quote_asset_price(oracle_map.get_price_data(&quote_spot_market.oracle)?.price);
     let liquidator_fee = market.liquidator_fee;
     let if_liquidation_fee = calculate_perp_if_fee(
-        intermediate_margin_calculation.tracked_market_margin_shortage(margin_shortage)?,
+        collateral.market_margin_shortage(&intermediate_margin_calculation, margin_shortage)?,
         user_base_asset_amount,
         margin_ratio_with_buffer,
         liquidator_fee,
@@ -356,13 +516,17 @@ pub fn liquidate_perp(
     drop(market);
     drop(quote_spot_market);
 
-    let max_pct_allowed = calculate_max_pct_to_liquidate(
-        user,
-        margin_shortage,
-        slot,
-        initial_pct_to_liquidate,
-        liquidation_duration,
-    )?;
+    // an isolated position isn't in liquidation status, so it isn't liquidated gradually
+    let max_pct_allowed = match collateral {
+        PerpLiquidationCollateral::Cross => calculate_max_pct_to_liquidate(
+            user,
+            margin_shortage,
+            slot,
+            initial_pct_to_liquidate,
+            liquidation_duration,
+        )?,
+        PerpLiquidationCollateral::Isolated { .. } => LIQUIDATION_PCT_PRECISION,
+    };
     let max_base_asset_amount_allowed_to_be_transferred =
         base_asset_amount_to_cover_margin_shortage
             .cast::<u128>()?
@@ -502,25 +666,32 @@ pub fn liquidate_perp(
         )
     };
 
-    let margin_freed_for_perp_position = calculate_margin_freed(
-        user,
+    if collateral == PerpLiquidationCollateral::Cross {
+        let margin_freed_for_perp_position = calculate_margin_freed(
+            user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            liquidation_margin_buffer_ratio,
+            margin_shortage,
+        )?;
+        margin_freed = margin_freed.safe_add(margin_freed_for_perp_position)?;
+        user.increment_margin_freed(margin_freed_for_perp_position)?;
+
+        if base_asset_amount >= base_asset_amount_to_cover_margin_shortage {
+            user.exit_liquidation();
+        } else if is_user_bankrupt(user) {
+            user.enter_bankruptcy();
+        }
+    }
+
+    let liquidator_meets_initial_margin_requirement = meets_initial_margin_requirement(
+        liquidator,
         perp_market_map,
         spot_market_map,
         oracle_map,
-        liquidation_margin_buffer_ratio,
-        margin_shortage,
+        MarketIdentifier::perp(market_index),
     )?;
-    margin_freed = margin_freed.safe_add(margin_freed_for_perp_position)?;
-    user.increment_margin_freed(margin_freed_for_perp_position)?;
-
-    if base_asset_amount >= base_asset_amount_to_cover_margin_shortage {
-        user.exit_liquidation();
-    } else if is_user_bankrupt(user) {
-        user.enter_bankruptcy();
-    }
-
-    let liquidator_meets_initial_margin_requirement =
-        meets_initial_margin_requirement(liquidator, perp_market_map, spot_market_map, oracle_map)?;
 
     validate!(
         liquidator_meets_initial_margin_requirement,
@@ -533,6 +704,10 @@ pub fn liquidate_perp(
     let liquidator_order_id = get_then_update_id!(liquidator, next_order_id);
     let fill_record_id = {
         let mut market = perp_market_map.get_ref_mut(&market_index)?;
+        market.update_last_fill_price(
+            calculate_fill_price(base_asset_value, base_asset_amount, BASE_PRECISION_U64)?,
+            now,
+        )?;
         get_then_update_id!(market, next_fill_record_id)
     };
 
@@ -625,9 +800,9 @@ pub fn liquidate_perp(
         liquidation_type: LiquidationType::LiquidatePerp,
         user: *user_key,
         liquidator: *liquidator_key,
-        margin_requirement: margin_calculation.margin_requirement,
-        total_collateral: margin_calculation.total_collateral,
-        bankrupt: user.is_bankrupt(),
+        margin_requirement: collateral.margin_requirement(&margin_calculation),
+        total_collateral: collateral.total_collateral(&margin_calculation),
+        bankrupt: collateral.is_bankrupt(user),
         canceled_order_ids,
         margin_freed,
         liquidate_perp: LiquidatePerpRecord {
@@ -836,7 +1011,7 @@ pub fn liquidate_spot(
             .track_market_margin_requirement(MarketIdentifier::spot(liability_market_index))?,
     )?;
 
-    if !user.is_being_liquidated() && margin_calculation.meets_margin_requirement() {
+    if !user.is_being_liquidated() && margin_calculation.meets_cross_margin_requirement() {
         msg!("margin calculation: {:?}", margin_calculation);
         return Err(ErrorCode::SufficientCollateral);
     } else if user.is_being_liquidated() && margin_calculation.can_exit_liquidation()? {
@@ -1133,8 +1308,13 @@ pub fn liquidate_spot(
         user.enter_bankruptcy();
     }
 
-    let liquidator_meets_initial_margin_requirement =
-        meets_initial_margin_requirement(liquidator, perp_market_map, spot_market_map, oracle_map)?;
+    let liquidator_meets_initial_margin_requirement = meets_initial_margin_requirement(
+        liquidator,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        MarketIdentifier::spot(liability_market_index),
+    )?;
 
     validate!(
         liquidator_meets_initial_margin_requirement,
@@ -1195,6 +1375,12 @@ pub fn liquidate_borrow_for_perp_pnl(
         "user bankrupt",
     )?;
 
+    validate!(
+        !user.is_isolated_perp_market(perp_market_index),
+        ErrorCode::InvalidIsolatedPerpPosition,
+        "isolated perp position pnl cant be liquidated against cross margin",
+    )?;
+
     validate!(
         !liquidator.is_bankrupt(),
         ErrorCode::UserBankrupt,
@@ -1297,7 +1483,8 @@ pub fn liquidate_borrow_for_perp_pnl(
         let market = perp_market_map.get_ref(&perp_market_index)?;
 
         let quote_spot_market = spot_market_map.get_ref(&market.quote_spot_market_index)?;
-        let quote_price = oracle_map.get_price_data(&quote_spot_market.oracle)?.price;
+        let quote_price = market
+            .get_quote_asset_price(oracle_map.get_price_data(&quote_spot_market.oracle)?.price);
 
         let pnl_asset_weight =
             market.get_unrealized_asset_weight(pnl, MarginRequirementType::Maintenance)?;
@@ -1370,7 +1557,7 @@ pub fn liquidate_borrow_for_perp_pnl(
         MarginContext::liquidation(liquidation_margin_buffer_ratio),
     )?;
 
-    if !user.is_being_liquidated() && margin_calculation.meets_margin_requirement() {
+    if !user.is_being_liquidated() && margin_calculation.meets_cross_margin_requirement() {
         msg!("margin calculation {:?}", margin_calculation);
         return Err(ErrorCode::SufficientCollateral);
     } else if user.is_being_liquidated() && margin_calculation.can_exit_liquidation()? {
@@ -1597,8 +1784,13 @@ pub fn liquidate_borrow_for_perp_pnl(
         user.enter_bankruptcy();
     }
 
-    let liquidator_meets_initial_margin_requirement =
-        meets_initial_margin_requirement(liquidator, perp_market_map, spot_market_map, oracle_map)?;
+    let liquidator_meets_initial_margin_requirement = meets_initial_margin_requirement(
+        liquidator,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        MarketIdentifier::spot(liability_market_index),
+    )?;
 
     validate!(
         liquidator_meets_initial_margin_requirement,
@@ -1664,6 +1856,12 @@ pub fn liquidate_perp_pnl_for_deposit(
         "user bankrupt",
     )?;
 
+    validate!(
+        !user.is_isolated_perp_market(perp_market_index),
+        ErrorCode::InvalidIsolatedPerpPosition,
+        "isolated perp position pnl cant be liquidated against cross margin",
+    )?;
+
     validate!(
         !liquidator.is_bankrupt(),
         ErrorCode::UserBankrupt,
@@ -1823,7 +2021,8 @@ pub fn liquidate_perp_pnl_for_deposit(
         let market = perp_market_map.get_ref(&perp_market_index)?;
 
         let quote_spot_market = spot_market_map.get_ref(&market.quote_spot_market_index)?;
-        let quote_price = oracle_map.get_price_data(&quote_spot_market.oracle)?.price;
+        let quote_price = market
+            .get_quote_asset_price(oracle_map.get_price_data(&quote_spot_market.oracle)?.price);
 
         (
             unsettled_pnl.unsigned_abs(),
@@ -1846,7 +2045,7 @@ pub fn liquidate_perp_pnl_for_deposit(
         MarginContext::liquidation(liquidation_margin_buffer_ratio),
     )?;
 
-    if !user.is_being_liquidated() && margin_calculation.meets_margin_requirement() {
+    if !user.is_being_liquidated() && margin_calculation.meets_cross_margin_requirement() {
         msg!("margin calculation {:?}", margin_calculation);
         return Err(ErrorCode::SufficientCollateral);
     } else if user.is_being_liquidated() && margin_calculation.can_exit_liquidation()? {
@@ -2092,8 +2291,13 @@ pub fn liquidate_perp_pnl_for_deposit(
         user.enter_bankruptcy();
     }
 
-    let liquidator_meets_initial_margin_requirement =
-        meets_initial_margin_requirement(liquidator, perp_market_map, spot_market_map, oracle_map)?;
+    let liquidator_meets_initial_margin_requirement = meets_initial_margin_requirement(
+        liquidator,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        MarketIdentifier::perp(perp_market_index),
+    )?;
 
     validate!(
         liquidator_meets_initial_margin_requirement,
@@ -2142,15 +2346,34 @@ pub fn resolve_perp_bankruptcy(
     now: i64,
     insurance_fund_vault_balance: u64,
 ) -> DriftResult<u64> {
-    if !user.is_bankrupt() && is_user_bankrupt(user) {
-        user.enter_bankruptcy();
-    }
+    let is_isolated_position = user.is_isolated_perp_market(market_index);
 
-    validate!(
-        user.is_bankrupt(),
-        ErrorCode::UserNotBankrupt,
-        "user not bankrupt",
-    )?;
+    if is_isolated_position {
+        apply_isolated_perp_position_collateral_to_loss(
+            market_index,
+            user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+        )?;
+
+        validate!(
+            is_isolated_perp_position_bankrupt(user, market_index),
+            ErrorCode::UserNotBankrupt,
+            "isolated perp position not bankrupt",
+        )?;
+    } else {
+        if !user.is_bankrupt() && is_user_bankrupt(user) {
+            user.enter_bankruptcy();
+        }
+
+        validate!(
+            user.is_bankrupt(),
+            ErrorCode::UserNotBankrupt,
+            "user not bankrupt",
+        )?;
+    }
 
     validate!(
         !liquidator.is_being_liquidated(),
@@ -2209,17 +2432,28 @@ pub fn resolve_perp_bankruptcy(
     // spot market's insurance fund draw attempt here (before social loss)
     // subtract 1 from available insurance_fund_vault_balance so deposits in insurance vault always remains >= 1
 
-    let if_payment = {
+    let (if_payment, if_payment_token_amount) = {
         let mut perp_market = perp_market_map.get_ref_mut(&market_index)?;
+        let spot_market = &mut spot_market_map.get_ref_mut(&perp_market.quote_spot_market_index)?;
+        let oracle_price_data = oracle_map.get_price_data(&spot_market.oracle)?;
+        update_spot_market_cumulative_interest(spot_market, Some(oracle_price_data), now)?;
+
         let max_insurance_withdraw = perp_market
             .insurance_claim
             .quote_max_insurance
             .safe_sub(perp_market.insurance_claim.quote_settled_insurance)?
             .cast::<u128>()?;
 
+        let insurance_fund_vault_value = perp_market
+            .get_quote_amount(
+                insurance_fund_vault_balance.saturating_sub(1).cast()?,
+                spot_market,
+            )?
+            .cast::<u128>()?;
+
         let if_payment = loss
             .unsigned_abs()
-            .min(insurance_fund_vault_balance.saturating_sub(1).cast()?)
+            .min(insurance_fund_vault_value)
             .min(max_insurance_withdraw);
 
         perp_market.insurance_claim.quote_settled_insurance = perp_market
@@ -2228,19 +2462,19 @@ pub fn resolve_perp_bankruptcy(
             .safe_add(if_payment.cast()?)?;
 
         // move if payment to pnl pool
-        let spot_market = &mut spot_market_map.get_ref_mut(&QUOTE_SPOT_MARKET_INDEX)?;
-        let oracle_price_data = oracle_map.get_price_data(&spot_market.oracle)?;
-        update_spot_market_cumulative_interest(spot_market, Some(oracle_price_data), now)?;
+        let if_payment_token_amount = perp_market
+            .get_quote_token_amount(if_payment.cast()?, spot_market)?
+            .unsigned_abs();
 
         update_spot_balances(
-            if_payment,
+            if_payment_token_amount,
             &SpotBalanceType::Deposit,
             spot_market,
             &mut perp_market.pnl_pool,
             false,
         )?;
 
-        if_payment
+        (if_payment, if_payment_token_amount)
     };
 
     let losses_remaining: i128 = loss.safe_add(if_payment.cast::<i128>()?)?;
@@ -2252,11 +2486,13 @@ pub fn resolve_perp_bankruptcy(
 
     let fee_pool_payment: i128 = if losses_remaining < 0 {
         let perp_market = &mut perp_market_map.get_ref_mut(&market_index)?;
-        let spot_market = &mut spot_market_map.get_ref_mut(&QUOTE_SPOT_MARKET_INDEX)?;
+        let spot_market = &mut spot_market_map.get_ref_mut(&perp_market.quote_spot_market_index)?;
         let fee_pool_tokens = get_fee_pool_tokens(perp_market, spot_market)?;
         msg!("fee_pool_tokens={:?}", fee_pool_tokens);
 
-        losses_remaining.abs().min(fee_pool_tokens.cast()?)
+        losses_remaining
+            .abs()
+            .min(perp_market.get_quote_amount(fee_pool_tokens, spot_market)?)
     } else {
         0
     };
@@ -2268,10 +2504,12 @@ pub fn resolve_perp_bankruptcy(
 
     if fee_pool_payment > 0 {
         let perp_market = &mut perp_market_map.get_ref_mut(&market_index)?;
-        let spot_market = &mut spot_market_map.get_ref_mut(&QUOTE_SPOT_MARKET_INDEX)?;
+        let spot_market = &mut spot_market_map.get_ref_mut(&perp_market.quote_spot_market_index)?;
         msg!("fee_pool_payment={:?}", fee_pool_payment);
         update_spot_balances(
-            fee_pool_payment.unsigned_abs(),
+            perp_market
+                .get_quote_token_amount(fee_pool_payment, spot_market)?
+                .unsigned_abs(),
             &SpotBalanceType::Borrow,
             spot_market,
             &mut perp_market.amm.fee_pool,
@@ -2326,7 +2564,9 @@ pub fn resolve_perp_bankruptcy(
     }
 
     // exit bankruptcy
-    if !is_user_bankrupt(user) {
+    if is_isolated_position {
+        user.try_exit_isolated_perp_position(market_index);
+    } else if !is_user_bankrupt(user) {
         user.exit_bankruptcy();
     }
 
@@ -2352,7 +2592,82 @@ pub fn resolve_perp_bankruptcy(
         ..LiquidationRecord::default()
     });
 
-    if_payment.cast()
+    if_payment_token_amount.cast()
+}
+
+/// Before the insurance fund and social loss cover it, an isolated perp position's remaining collateral is
+/// moved into the pnl pool to pay down the position's negative pnl
+fn apply_isolated_perp_position_collateral_to_loss(
+    market_index: u16,
+    user: &mut User,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+) -> DriftResult {
+    let position_index = get_position_index(&user.perp_positions, market_index)?;
+    let perp_position = &user.perp_positions[position_index];
+
+    validate!(
+        perp_position.base_asset_amount == 0
+            && !perp_position.has_open_order()
+            && !perp_position.is_lp(),
+        ErrorCode::UserNotBankrupt,
+        "isolated perp position must be closed before resolving bankruptcy",
+    )?;
+
+    if perp_position.quote_asset_amount >= 0 {
+        return Ok(());
+    }
+
+    let mut perp_market = perp_market_map.get_ref_mut(&market_index)?;
+    let spot_market = &mut spot_market_map.get_ref_mut(&perp_market.quote_spot_market_index)?;
+    update_quote_spot_market_for_perp_market(
+        &perp_market,
+        spot_market,
+        oracle_map,
+        now,
+        Some(DriftAction::Liquidate),
+    )?;
+
+    let mut isolated_balance = user.get_isolated_perp_position_balance(market_index)?;
+    let collateral = isolated_balance
+        .get_token_amount(spot_market)?
+        .cast::<i128>()?;
+    let collateral_value = perp_market.get_quote_amount(collateral, spot_market)?;
+    let loss = perp_position
+        .quote_asset_amount
+        .unsigned_abs()
+        .cast::<i128>()?;
+
+    // all of the collateral is used unless it's worth more than the loss
+    let (payment, token_payment) = if collateral_value <= loss {
+        (collateral_value, collateral)
+    } else {
+        (loss, perp_market.get_quote_token_amount(loss, spot_market)?)
+    };
+
+    if payment == 0 {
+        return Ok(());
+    }
+
+    transfer_spot_balances(
+        token_payment,
+        spot_market,
+        &mut isolated_balance,
+        &mut perp_market.pnl_pool,
+    )?;
+    user.update_isolated_perp_position_balance(market_index, &isolated_balance)?;
+
+    update_quote_asset_amount(
+        &mut user.perp_positions[position_index],
+        &mut perp_market,
+        payment.cast()?,
+    )?;
+
+    update_settled_pnl(user, position_index, -payment.cast::<i64>()?)?;
+
+    Ok(())
 }
 
 pub fn resolve_spot_bankruptcy(
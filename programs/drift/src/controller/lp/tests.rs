@@ -27,7 +27,7 @@ use crate::math::position::{
     get_position_update_type,
     PositionUpdateType,
 };
-use crate::state::margin_calculation::{MarginCalculation, MarginContext};
+use crate::state::margin_calculation::{MarginCalculation, MarginContext, MarketIdentifier};
 use crate::state::oracle::{HistoricalOracleData, OracleSource};
 use crate::state::oracle::{OraclePriceData, StrictOraclePrice};
 use crate::state::oracle_map::OracleMap;
@@ -690,8 +690,13 @@ fn test_lp_margin_calc() {
         user.perp_positions[0].last_cumulative_funding_rate != market.amm.last_funding_rate_long
     );
 
-    let result =
-        meets_maintenance_margin_requirement(&user, &market_map, &spot_market_map, &mut oracle_map);
+    let result = meets_maintenance_margin_requirement(
+        &user,
+        &market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        MarketIdentifier::perp(0),
+    );
 
     assert_eq!(result.unwrap(), true);
 
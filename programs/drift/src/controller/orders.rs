@@ -12,40 +12,45 @@ use crate::controller::lp::burn_lp_shares;
 use crate::controller::position;
 use crate::controller::position::{
     add_new_position, decrease_open_bids_and_asks, get_position_index, increase_open_bids_and_asks,
-    update_lp_market_position, update_position_and_market, update_quote_asset_amount,
-    PositionDirection,
+    resize_open_bids_and_asks, update_lp_market_position, update_position_and_market,
+    update_quote_asset_amount, PositionDirection,
 };
 use crate::controller::spot_balance::{
     update_spot_balances, update_spot_market_cumulative_interest,
 };
 use crate::controller::spot_position::{
     decrease_spot_open_bids_and_asks, increase_spot_open_bids_and_asks,
-    update_spot_balances_and_cumulative_deposits,
+    resize_spot_open_bids_and_asks, update_spot_balances_and_cumulative_deposits,
 };
 use crate::error::DriftResult;
 use crate::error::ErrorCode;
 use crate::get_struct_values;
 use crate::get_then_update_id;
+use crate::load;
 use crate::load_mut;
 use crate::math::amm_jit::calculate_amm_jit_liquidity;
 use crate::math::auction::{calculate_auction_params_for_trigger_order, calculate_auction_prices};
 use crate::math::casting::Cast;
-use crate::math::constants::{BASE_PRECISION_U64, PERP_DECIMALS, QUOTE_SPOT_MARKET_INDEX};
+use crate::math::constants::{
+    BASE_PRECISION_U64, MAX_PREDICTION_MARKET_PRICE, PERP_DECIMALS, QUOTE_SPOT_MARKET_INDEX,
+};
 use crate::math::fees::{determine_user_fee_tier, ExternalFillFees, FillFees};
 use crate::math::fulfillment::{
     determine_perp_fulfillment_methods, determine_spot_fulfillment_methods,
 };
 use crate::math::liquidation::validate_user_not_being_liquidated;
 use crate::math::matching::{
-    are_orders_same_market_but_different_sides, calculate_fill_for_matched_orders,
-    calculate_filler_multiplier_for_matched_orders, do_orders_cross, is_maker_for_taker,
+    are_orders_same_market_but_different_sides, calculate_batch_auction_clearing_price,
+    calculate_fill_for_matched_orders, calculate_filler_multiplier_for_matched_orders,
+    calculate_maker_fill_allocations, do_orders_cross, is_maker_for_taker,
 };
 use crate::math::oracle::{is_oracle_valid_for_action, DriftAction, OracleValidity};
 use crate::math::safe_math::SafeMath;
 use crate::math::spot_balance::{get_signed_token_amount, get_token_amount};
 use crate::math::{amm, fees, margin::*, orders::*};
 use crate::state::order_params::{
-    ModifyOrderParams, ModifyOrderPolicy, OrderParams, PlaceOrderOptions, PostOnlyParam,
+    ComboOrderParams, ModifyOrderParams, ModifyOrderPolicy, OrderParams, PlaceOrderOptions,
+    PostOnlyParam, QuoteSetParams, TriggerMarketParams, TwapParams,
 };
 
 use crate::math::amm::calculate_amm_available_liquidity;
@@ -59,12 +64,15 @@ use crate::state::events::{
 use crate::state::events::{OrderAction, OrderActionExplanation};
 use crate::state::fill_mode::FillMode;
 use crate::state::fulfillment::{PerpFulfillmentMethod, SpotFulfillmentMethod};
-use crate::state::margin_calculation::{MarginCalculation, MarginContext};
+use crate::state::fulfillment_params::drift::MatchFulfillmentParams;
+use crate::state::margin_calculation::{MarginCalculation, MarginContext, MarketIdentifier};
+use crate::state::market_maker_protection::UserMarketMakerProtection;
 use crate::state::oracle::{OraclePriceData, StrictOraclePrice};
 use crate::state::oracle_map::OracleMap;
 use crate::state::paused_operations::PerpOperation;
-use crate::state::perp_market::{AMMLiquiditySplit, MarketStatus, PerpMarket};
+use crate::state::perp_market::{AMMLiquiditySplit, MarketStatus, MatchingPolicy, PerpMarket};
 use crate::state::perp_market_map::PerpMarketMap;
+use crate::state::rfq::Rfq;
 use crate::state::spot_fulfillment_params::{ExternalSpotFill, SpotFulfillmentParams};
 use crate::state::spot_market::{SpotBalanceType, SpotMarket};
 use crate::state::spot_market_map::SpotMarketMap;
@@ -72,14 +80,16 @@ use crate::state::state::FeeStructure;
 use crate::state::state::*;
 use crate::state::traits::Size;
 use crate::state::user::{
-    AssetType, Order, OrderStatus, OrderTriggerCondition, OrderType, UserStats,
+    AssetType, Order, OrderBitFlag, OrderStatus, OrderTriggerCondition, OrderType,
+    SelfTradePreventionMode, UserStats,
 };
 use crate::state::user::{MarketType, User};
 use crate::state::user_map::{UserMap, UserStatsMap};
 use crate::validate;
 use crate::validation;
 use crate::validation::order::{
-    validate_order, validate_order_for_force_reduce_only, validate_spot_order,
+    validate_order, validate_order_for_force_reduce_only, validate_order_params_bit_flags,
+    validate_spot_order,
 };
 
 #[cfg(test)]
@@ -90,6 +100,43 @@ mod amm_jit_tests;
 #[cfg(test)]
 mod amm_lp_jit_tests;
 
+/// Users with market maker protection can't post maker orders in a market while its protection is
+/// triggered, so their quotes stay pulled until they reset it
+pub fn validate_market_maker_protection_not_triggered(
+    user: &User,
+    user_key: &Pubkey,
+    market_maker_protection: Option<&UserMarketMakerProtection>,
+    market_index: u16,
+) -> DriftResult {
+    if !user.has_market_maker_protection {
+        return Ok(());
+    }
+
+    let market_maker_protection = market_maker_protection.ok_or_else(|| {
+        msg!("market maker protection not provided for user {}", user_key);
+        ErrorCode::InvalidMarketMakerProtection
+    })?;
+
+    validate!(
+        market_maker_protection.user == *user_key,
+        ErrorCode::InvalidMarketMakerProtection,
+        "market maker protection is for user {} not {}",
+        market_maker_protection.user,
+        user_key
+    )?;
+
+    validate!(
+        !market_maker_protection.is_triggered(market_index),
+        ErrorCode::MarketMakerProtectionTriggered,
+        "market maker protection triggered for user {} in market {}",
+        user_key,
+        market_index
+    )?;
+
+    Ok(())
+}
+
+/// Returns whether the placed order is risk increasing
 pub fn place_perp_order(
     state: &State,
     user: &mut User,
@@ -100,7 +147,7 @@ pub fn place_perp_order(
     clock: &Clock,
     mut params: OrderParams,
     mut options: PlaceOrderOptions,
-) -> DriftResult {
+) -> DriftResult<bool> {
     let now = clock.unix_timestamp;
     let slot = clock.slot;
 
@@ -134,19 +181,35 @@ pub fn place_perp_order(
         )?;
     }
 
-    let new_order_index = user
-        .orders
-        .iter()
-        .position(|order| order.status.eq(&OrderStatus::Init))
-        .ok_or(ErrorCode::MaxNumberOfOrders)?;
-
-    if params.user_order_id > 0 {
-        let user_order_id_already_used = user
+    let new_order_index = match options.replace_order_index {
+        Some(order_index) => {
+            let order = &user.orders[order_index];
+            validate!(
+                order.status == OrderStatus::Open
+                    && order.market_type == MarketType::Perp
+                    && order.market_index == params.market_index
+                    && order.direction == params.direction
+                    && order.post_only,
+                ErrorCode::InvalidOrder,
+                "order {} can not be replaced in place",
+                order.order_id
+            )?;
+            order_index
+        }
+        None => user
             .orders
             .iter()
-            .position(|order| order.user_order_id == params.user_order_id);
+            .position(|order| order.status.eq(&OrderStatus::Init))
+            .ok_or(ErrorCode::MaxNumberOfOrders)?,
+    };
 
-        if user_order_id_already_used.is_some() {
+    if params.user_order_id > 0 {
+        let user_order_id_already_used =
+            user.orders.iter().enumerate().any(|(order_index, order)| {
+                order_index != new_order_index && order.user_order_id == params.user_order_id
+            });
+
+        if user_order_id_already_used {
             msg!("user_order_id is already in use {}", params.user_order_id);
             return Err(ErrorCode::UserOrderIdAlreadyInUse);
         }
@@ -156,21 +219,32 @@ pub fn place_perp_order(
     let market = &perp_market_map.get_ref(&market_index)?;
     let force_reduce_only = market.is_reduce_only()?;
 
-    validate!(
-        !matches!(market.status, MarketStatus::Initialized),
-        ErrorCode::MarketBeingInitialized,
-        "Market is being initialized"
-    )?;
+    validate_perp_market_for_order(&market, &params, now)?;
 
-    validate!(
-        !market.is_in_settlement(now),
-        ErrorCode::MarketPlaceOrderPaused,
-        "Market is in settlement mode",
-    )?;
+    if params.post_only != PostOnlyParam::None {
+        validate_market_maker_protection_not_triggered(
+            user,
+            &user_key,
+            options.market_maker_protection,
+            market_index,
+        )?;
+    }
 
     let position_index = get_position_index(&user.perp_positions, market_index)
         .or_else(|_| add_new_position(&mut user.perp_positions, market_index))?;
 
+    // quote sized orders are converted to base here and keep their quote asset amount (see Order::set_quote_size)
+    let quote_asset_amount = if params.is_quote_sized() {
+        let quote_asset_amount = params.base_asset_amount;
+        params.base_asset_amount = params.get_base_asset_amount_for_quote_size(
+            oracle_map.get_price_data(&market.amm.oracle)?.price,
+            BASE_PRECISION_U64,
+        )?;
+        Some(quote_asset_amount)
+    } else {
+        None
+    };
+
     // Increment open orders for existing position
     let (existing_position_direction, order_base_asset_amount) = {
         validate!(
@@ -207,7 +281,9 @@ pub fn place_perp_order(
     let oracle_price_data = oracle_map.get_price_data(&market.amm.oracle)?;
 
     // updates auction params for crossing limit orders w/out auction duration
-    params.update_perp_auction_params(market, oracle_price_data.price)?;
+    if !market.is_orderbook_only() {
+        params.update_perp_auction_params(&market, oracle_price_data.price)?;
+    }
 
     let (auction_start_price, auction_end_price, auction_duration) = get_auction_params(
         &params,
@@ -216,6 +292,12 @@ pub fn place_perp_order(
         state.min_perp_auction_duration,
     )?;
 
+    validate!(
+        !params.is_good_til_slot() || params.max_ts.is_some(),
+        ErrorCode::InvalidOrder,
+        "good til slot order must set max_ts to its last slot"
+    )?;
+
     let max_ts = match params.max_ts {
         Some(max_ts) => max_ts,
         None => match params.order_type {
@@ -226,9 +308,12 @@ pub fn place_perp_order(
         },
     };
 
-    if max_ts != 0 && max_ts < now {
+    if max_ts != 0 && params.is_good_til_slot() && max_ts < slot.cast()? {
+        msg!("max_ts ({}) < slot ({}), skipping order", max_ts, slot);
+        return Ok(false);
+    } else if max_ts != 0 && !params.is_good_til_slot() && max_ts < now {
         msg!("max_ts ({}) < now ({}), skipping order", max_ts, now);
-        return Ok(());
+        return Ok(false);
     }
 
     validate!(
@@ -237,12 +322,43 @@ pub fn place_perp_order(
         "must be perp order"
     )?;
 
-    let new_order = Order {
+    validate_order_params_bit_flags(params.bit_flags)?;
+
+    let twap_params = if params.order_type == OrderType::Twap {
+        let twap_params = params.twap_params.ok_or_else(|| {
+            msg!("Twap order must have twap params");
+            ErrorCode::InvalidTwapOrder
+        })?;
+
+        Some(TwapParams {
+            slice_interval: twap_params.slice_interval,
+            slice_base_asset_amount: standardize_base_asset_amount(
+                twap_params.slice_base_asset_amount,
+                market.amm.order_step_size,
+            )?,
+        })
+    } else {
+        None
+    };
+
+    let trigger_price = standardize_price(
+        params.trigger_price.unwrap_or(0),
+        market.amm.order_tick_size,
+        params.direction,
+    )?;
+
+    // orders updated in place keep their order id
+    let order_id = match options.replace_order_index {
+        Some(order_index) => user.orders[order_index].order_id,
+        None => get_then_update_id!(user, next_order_id),
+    };
+
+    let mut new_order = Order {
         status: OrderStatus::Open,
         order_type: params.order_type,
         market_type: params.market_type,
         slot,
-        order_id: get_then_update_id!(user, next_order_id),
+        order_id,
         user_order_id: params.user_order_id,
         market_index: params.market_index,
         price: get_price_for_perp_order(
@@ -257,11 +373,7 @@ pub fn place_perp_order(
         quote_asset_amount_filled: 0,
         direction: params.direction,
         reduce_only: params.reduce_only || force_reduce_only,
-        trigger_price: standardize_price(
-            params.trigger_price.unwrap_or(0),
-            market.amm.order_tick_size,
-            params.direction,
-        )?,
+        trigger_price,
         trigger_condition: params.trigger_condition,
         post_only: params.post_only != PostOnlyParam::None,
         oracle_price_offset: params.oracle_price_offset.unwrap_or(0),
@@ -270,21 +382,85 @@ pub fn place_perp_order(
         auction_end_price,
         auction_duration,
         max_ts,
-        padding: [0; 3],
+        bit_flags: params.bit_flags & OrderBitFlag::USER_SETTABLE,
+        linked_order_group: params.linked_order_group,
+        trigger_reference_price: params.trigger_reference_price.unwrap_or_default(),
     };
 
+    new_order.set_auction_curve(params.auction_curve.unwrap_or(market.default_auction_curve));
+
+    if let Some(quote_asset_amount) = quote_asset_amount {
+        new_order.set_quote_size(quote_asset_amount);
+    }
+
+    if params.is_linked_order_entry() {
+        new_order.set_linked_order_entry();
+    }
+
+    if let Some(twap_params) = twap_params {
+        new_order.set_twap_params(
+            twap_params.slice_interval,
+            twap_params.slice_base_asset_amount,
+        )?;
+    }
+
+    if let Some(trigger_market_params) = params.trigger_market_params {
+        validate!(
+            trigger_market_params.market_type != MarketType::Perp
+                || trigger_market_params.market_index != market_index,
+            ErrorCode::InvalidOrder,
+            "cross market trigger must reference a different market"
+        )?;
+
+        new_order.set_trigger_market(
+            trigger_market_params.market_type,
+            trigger_market_params.market_index,
+        );
+
+        if let Some(trigger_price_change) = trigger_market_params.trigger_price_change {
+            validate!(
+                params.trigger_price.is_none(),
+                ErrorCode::InvalidOrderTrigger,
+                "cross market trigger can't set both a trigger price and a trigger price change"
+            )?;
+
+            let reference_price = get_cross_market_trigger_reference_price(
+                &new_order,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+            )?;
+            let (trigger_price, trigger_condition) =
+                calculate_trigger_price_for_price_change(reference_price, trigger_price_change)?;
+            new_order.trigger_price = trigger_price;
+            new_order.trigger_condition = trigger_condition;
+        }
+    }
+
     let valid_oracle_price = Some(oracle_map.get_price_data(&market.amm.oracle)?.price);
-    match validate_order(&new_order, market, valid_oracle_price, slot) {
+    match validate_order(&new_order, &market, valid_oracle_price, slot) {
         Ok(()) => {}
         Err(ErrorCode::PlacePostOnlyLimitFailure)
             if params.post_only == PostOnlyParam::TryPostOnly =>
         {
             // just want place to succeeds without error if TryPostOnly
-            return Ok(());
+            return Ok(false);
         }
         Err(err) => return Err(err),
     };
 
+    if let Some(order_index) = options.replace_order_index {
+        // release the order being replaced, its open orders and open bids/asks are re-added for the new order below
+        let replaced_order = user.orders[order_index];
+        decrease_open_bids_and_asks(
+            &mut user.perp_positions[position_index],
+            &replaced_order.direction,
+            replaced_order.get_base_asset_amount_unfilled(None)?,
+        )?;
+        user.decrement_open_orders(replaced_order.has_auction());
+        user.perp_positions[position_index].open_orders -= 1;
+    }
+
     let risk_increasing = is_new_order_risk_increasing(
         &new_order,
         user.perp_positions[position_index].base_asset_amount,
@@ -313,6 +489,7 @@ pub fn place_perp_order(
             spot_market_map,
             oracle_map,
             options.risk_increasing,
+            &[MarketIdentifier::perp(market_index)],
         )?;
     }
 
@@ -323,28 +500,8 @@ pub fn place_perp_order(
         )?;
     }
 
-    let max_oi = market.amm.max_open_interest;
-    if max_oi != 0 && risk_increasing {
-        let oi_plus_order = match params.direction {
-            PositionDirection::Long => market
-                .amm
-                .base_asset_amount_long
-                .safe_add(order_base_asset_amount.cast()?)?
-                .unsigned_abs(),
-            PositionDirection::Short => market
-                .amm
-                .base_asset_amount_short
-                .safe_sub(order_base_asset_amount.cast()?)?
-                .unsigned_abs(),
-        };
-
-        validate!(
-            oi_plus_order <= max_oi,
-            ErrorCode::MaxOpenInterest,
-            "Order Base Amount={} could breach Max Open Interest for Perp Market={}",
-            order_base_asset_amount,
-            params.market_index
-        )?;
+    if risk_increasing {
+        validate_perp_order_open_interest(&market, params.direction, order_base_asset_amount)?;
     }
 
     let (taker, taker_order, maker, maker_order) =
@@ -382,6 +539,249 @@ pub fn place_perp_order(
 
     user.update_last_active_slot(slot);
 
+    Ok(risk_increasing)
+}
+
+fn validate_perp_market_for_order(
+    market: &PerpMarket,
+    params: &OrderParams,
+    now: i64,
+) -> DriftResult {
+    validate!(
+        !matches!(market.status, MarketStatus::Initialized),
+        ErrorCode::MarketBeingInitialized,
+        "Market is being initialized"
+    )?;
+
+    validate!(
+        !market.is_in_settlement(now),
+        ErrorCode::MarketPlaceOrderPaused,
+        "Market is in settlement mode",
+    )?;
+
+    // option prices are premiums and prediction market prices are bounded,
+    // so nothing can be priced off the oracle or the amm
+    if market.is_orderbook_only() {
+        validate!(
+            params.order_type == OrderType::Limit
+                && params.oracle_price_offset.unwrap_or(0) == 0
+                && !params.is_quote_sized(),
+            ErrorCode::InvalidOrder,
+            "orderbook only markets only support limit orders"
+        )?;
+    }
+
+    if market.is_prediction_market() {
+        validate!(
+            params.price <= MAX_PREDICTION_MARKET_PRICE,
+            ErrorCode::InvalidOrderLimitPrice,
+            "prediction market price {} above max {}",
+            params.price,
+            MAX_PREDICTION_MARKET_PRICE
+        )?;
+    }
+
+    Ok(())
+}
+
+fn validate_perp_order_open_interest(
+    market: &PerpMarket,
+    direction: PositionDirection,
+    base_asset_amount: u64,
+) -> DriftResult {
+    let max_oi = market.amm.max_open_interest;
+    if max_oi == 0 {
+        return Ok(());
+    }
+
+    let oi_plus_order = match direction {
+        PositionDirection::Long => market
+            .amm
+            .base_asset_amount_long
+            .safe_add(base_asset_amount.cast()?)?
+            .unsigned_abs(),
+        PositionDirection::Short => market
+            .amm
+            .base_asset_amount_short
+            .safe_sub(base_asset_amount.cast()?)?
+            .unsigned_abs(),
+    };
+
+    validate!(
+        oi_plus_order <= max_oi,
+        ErrorCode::MaxOpenInterest,
+        "Order Base Amount={} could breach Max Open Interest for Perp Market={}",
+        base_asset_amount,
+        market.market_index
+    )?;
+
+    Ok(())
+}
+
+/// Places a two-sided quote set as post only limit orders. Existing post only limit orders in the market
+/// are updated in place, keeping their order ids, and any left over are cancelled
+pub fn place_perp_quote_set(
+    state: &State,
+    user: &mut User,
+    user_key: Pubkey,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    clock: &Clock,
+    params: &QuoteSetParams,
+    market_maker_protection: Option<&UserMarketMakerProtection>,
+) -> DriftResult {
+    let now = clock.unix_timestamp;
+    let slot = clock.slot;
+
+    validate!(
+        params.bids.len().safe_add(params.asks.len())? <= user.orders.len(),
+        ErrorCode::MaxNumberOfOrders,
+        "max {} quote levels",
+        user.orders.len()
+    )?;
+
+    let market_index = params.market_index;
+
+    let order_tick_size = {
+        let market = perp_market_map.get_ref(&market_index)?;
+
+        validate!(
+            !market.is_reduce_only()?,
+            ErrorCode::MarketPlaceOrderPaused,
+            "Market is reduce only",
+        )?;
+
+        market.amm.order_tick_size
+    };
+
+    let mut risk_increasing = false;
+    for (direction, levels) in [
+        (PositionDirection::Long, &params.bids),
+        (PositionDirection::Short, &params.asks),
+    ] {
+        let mut existing_quote_indexes = user
+            .orders
+            .iter()
+            .enumerate()
+            .filter(|(_, order)| {
+                order.status == OrderStatus::Open
+                    && order.market_type == MarketType::Perp
+                    && order.market_index == market_index
+                    && order.order_type == OrderType::Limit
+                    && order.post_only
+                    && !order.reduce_only
+                    && order.direction == direction
+            })
+            .map(|(order_index, _)| order_index)
+            .collect::<Vec<usize>>()
+            .into_iter()
+            .peekable();
+
+        for level in levels.iter() {
+            let (price, oracle_price_offset) = match params.reference_price {
+                Some(reference_price) => {
+                    let price = reference_price
+                        .cast::<i64>()?
+                        .safe_add(level.price_offset.cast()?)?;
+
+                    validate!(
+                        price > 0,
+                        ErrorCode::InvalidOrderLimitPrice,
+                        "quote price must be greater than 0"
+                    )?;
+
+                    (price.cast()?, None)
+                }
+                None => (
+                    0_u64,
+                    Some(
+                        standardize_price_i64(
+                            level.price_offset.cast()?,
+                            order_tick_size.cast()?,
+                            direction,
+                        )?
+                        .cast()?,
+                    ),
+                ),
+            };
+
+            let order_params = OrderParams {
+                order_type: OrderType::Limit,
+                market_type: MarketType::Perp,
+                direction,
+                base_asset_amount: level.base_asset_amount,
+                price,
+                market_index,
+                post_only: PostOnlyParam::MustPostOnly,
+                oracle_price_offset,
+                ..OrderParams::default()
+            };
+
+            let replace_order_index = existing_quote_indexes.peek().copied();
+
+            // margin is checked once every level is placed
+            let options = PlaceOrderOptions {
+                enforce_margin_check: false,
+                try_expire_orders: false,
+                replace_order_index,
+                market_maker_protection,
+                ..PlaceOrderOptions::default()
+            };
+
+            match place_perp_order(
+                state,
+                user,
+                user_key,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                clock,
+                order_params,
+                options,
+            ) {
+                Ok(order_risk_increasing) => {
+                    risk_increasing = risk_increasing || order_risk_increasing;
+                    if replace_order_index.is_some() {
+                        existing_quote_indexes.next();
+                    }
+                }
+                // levels that would cross are skipped, same as TryPostOnly
+                Err(ErrorCode::PlacePostOnlyLimitFailure) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        // cancel the existing quotes that weren't updated
+        for order_index in existing_quote_indexes {
+            cancel_order(
+                order_index,
+                user,
+                &user_key,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                now,
+                slot,
+                OrderActionExplanation::None,
+                None,
+                0,
+                false,
+            )?;
+        }
+    }
+
+    meets_place_order_margin_requirement(
+        user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        risk_increasing,
+        &[MarketIdentifier::perp(market_index)],
+    )?;
+
+    user.update_last_active_slot(slot);
+
     Ok(())
 }
 
@@ -665,6 +1065,7 @@ pub fn cancel_order(
         }
 
         user.perp_positions[position_index].open_orders -= 1;
+
         user.orders[order_index] = Order::default();
     } else {
         let spot_position_index = user.get_spot_position_index(order_market_index)?;
@@ -686,23 +1087,220 @@ pub fn cancel_order(
     Ok(())
 }
 
-pub enum ModifyOrderId {
-    UserOrderId(u8),
-    OrderId(u32),
-}
-
-pub fn modify_order(
-    order_id: ModifyOrderId,
-    modify_order_params: ModifyOrderParams,
-    user_loader: &AccountLoader<User>,
-    state: &State,
+/// Cancels the other open orders in the linked order group of the completely filled/triggered order
+pub fn cancel_linked_orders(
+    user: &mut User,
+    user_key: &Pubkey,
+    order_id: u32,
+    linked_order_group: u8,
     perp_market_map: &PerpMarketMap,
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
-    clock: &Clock,
+    now: i64,
+    slot: u64,
+    filler_key: Option<&Pubkey>,
 ) -> DriftResult {
-    let user_key = user_loader.key();
-    let mut user = load_mut!(user_loader)?;
+    if linked_order_group == 0 {
+        return Ok(());
+    }
+
+    for order_index in 0..user.orders.len() {
+        let order = &user.orders[order_index];
+        if order.status != OrderStatus::Open
+            || order.order_id == order_id
+            || order.linked_order_group != linked_order_group
+        {
+            continue;
+        }
+
+        cancel_order(
+            order_index,
+            user,
+            user_key,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::LinkedOrderFilledOrTriggered,
+            filler_key,
+            0,
+            false,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Applies the taker's self trade prevention mode to a crossing maker order from the same authority
+/// Returns the base amount the taker order must be decremented by
+pub fn apply_self_trade_prevention(
+    maker: &mut User,
+    maker_key: &Pubkey,
+    maker_order_index: usize,
+    taker_base_asset_amount_unfilled: u64,
+    self_trade_prevention_mode: SelfTradePreventionMode,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+    slot: u64,
+    filler_key: Option<&Pubkey>,
+) -> DriftResult<u64> {
+    let maker_base_asset_amount_unfilled =
+        maker.orders[maker_order_index].get_base_asset_amount_unfilled(None)?;
+
+    let (maker_base_asset_amount_decrement, taker_base_asset_amount_decrement) =
+        match self_trade_prevention_mode {
+            SelfTradePreventionMode::None => (0, 0),
+            SelfTradePreventionMode::CancelTaker => (0, taker_base_asset_amount_unfilled),
+            SelfTradePreventionMode::CancelMaker => (maker_base_asset_amount_unfilled, 0),
+            SelfTradePreventionMode::CancelBoth => (
+                maker_base_asset_amount_unfilled,
+                taker_base_asset_amount_unfilled,
+            ),
+            SelfTradePreventionMode::DecrementAndCancel => {
+                let decrement =
+                    maker_base_asset_amount_unfilled.min(taker_base_asset_amount_unfilled);
+                (decrement, decrement)
+            }
+        };
+
+    if maker_base_asset_amount_decrement == 0 {
+        return Ok(taker_base_asset_amount_decrement);
+    }
+
+    if maker_base_asset_amount_decrement >= maker_base_asset_amount_unfilled {
+        cancel_order(
+            maker_order_index,
+            maker,
+            maker_key,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::SelfTradePrevention,
+            filler_key,
+            0,
+            false,
+        )?;
+    } else {
+        decrement_order_base_asset_amount(
+            maker,
+            maker_order_index,
+            maker_base_asset_amount_decrement,
+        )?;
+    }
+
+    Ok(taker_base_asset_amount_decrement)
+}
+
+/// Reduces the size of an open order without cancelling it, updating the position's open bids/asks
+pub fn decrement_order_base_asset_amount(
+    user: &mut User,
+    order_index: usize,
+    base_asset_amount: u64,
+) -> DriftResult {
+    let (order_id, market_index, market_type, direction) = get_struct_values!(
+        user.orders[order_index],
+        order_id,
+        market_index,
+        market_type,
+        direction
+    );
+
+    validate!(
+        base_asset_amount < user.orders[order_index].get_base_asset_amount_unfilled(None)?,
+        ErrorCode::InvalidOrder,
+        "Can not decrement order by more than its unfilled base asset amount"
+    )?;
+
+    user.orders[order_index].base_asset_amount = user.orders[order_index]
+        .base_asset_amount
+        .safe_sub(base_asset_amount)?;
+
+    if market_type == MarketType::Perp {
+        let position_index = get_position_index(&user.perp_positions, market_index)?;
+        decrease_open_bids_and_asks(
+            &mut user.perp_positions[position_index],
+            &direction,
+            base_asset_amount,
+        )?;
+    } else {
+        let spot_position_index = user.get_spot_position_index(market_index)?;
+        decrease_spot_open_bids_and_asks(
+            &mut user.spot_positions[spot_position_index],
+            &direction,
+            base_asset_amount,
+        )?;
+    }
+
+    msg!(
+        "Decremented order {} base asset amount by {}",
+        order_id,
+        base_asset_amount
+    );
+
+    Ok(())
+}
+
+/// Cancels or decrements the taker order after self trade prevention. Returns true if the order was cancelled
+fn handle_taker_self_trade_prevention(
+    user: &mut User,
+    user_key: &Pubkey,
+    order_index: usize,
+    base_asset_amount: u64,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    now: i64,
+    slot: u64,
+    filler_key: &Pubkey,
+) -> DriftResult<bool> {
+    if base_asset_amount >= user.orders[order_index].get_base_asset_amount_unfilled(None)? {
+        cancel_order(
+            order_index,
+            user,
+            user_key,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::SelfTradePrevention,
+            Some(filler_key),
+            0,
+            false,
+        )?;
+
+        return Ok(true);
+    }
+
+    decrement_order_base_asset_amount(user, order_index, base_asset_amount)?;
+
+    Ok(false)
+}
+
+pub enum ModifyOrderId {
+    UserOrderId(u8),
+    OrderId(u32),
+}
+
+/// Returns whether the modified order is risk increasing
+pub fn modify_order(
+    order_id: ModifyOrderId,
+    modify_order_params: ModifyOrderParams,
+    user_loader: &AccountLoader<User>,
+    state: &State,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    clock: &Clock,
+    options: PlaceOrderOptions,
+) -> DriftResult<bool> {
+    let user_key = user_loader.key();
+    let mut user = load_mut!(user_loader)?;
 
     let order_index = match order_id {
         ModifyOrderId::UserOrderId(user_order_id) => {
@@ -713,7 +1311,7 @@ pub fn modify_order(
                     if modify_order_params.policy == Some(ModifyOrderPolicy::MustModify) {
                         return Err(e);
                     } else {
-                        return Ok(());
+                        return Ok(false);
                     }
                 }
             }
@@ -725,7 +1323,7 @@ pub fn modify_order(
                 if modify_order_params.policy == Some(ModifyOrderPolicy::MustModify) {
                     return Err(e);
                 } else {
-                    return Ok(());
+                    return Ok(false);
                 }
             }
         },
@@ -753,7 +1351,7 @@ pub fn modify_order(
     let order_params =
         merge_modify_order_params_with_existing_order(&existing_order, &modify_order_params)?;
 
-    if order_params.market_type == MarketType::Perp {
+    let risk_increasing = if order_params.market_type == MarketType::Perp {
         place_perp_order(
             state,
             &mut user,
@@ -763,8 +1361,8 @@ pub fn modify_order(
             oracle_map,
             clock,
             order_params,
-            PlaceOrderOptions::default(),
-        )?;
+            options,
+        )?
     } else {
         place_spot_order(
             state,
@@ -775,11 +1373,11 @@ pub fn modify_order(
             oracle_map,
             clock,
             order_params,
-            PlaceOrderOptions::default(),
-        )?;
-    }
+            options,
+        )?
+    };
 
-    Ok(())
+    Ok(risk_increasing)
 }
 
 fn merge_modify_order_params_with_existing_order(
@@ -792,9 +1390,14 @@ fn merge_modify_order_params_with_existing_order(
         .direction
         .unwrap_or(existing_order.direction);
     let user_order_id = existing_order.user_order_id;
-    let base_asset_amount = modify_order_params
-        .base_asset_amount
-        .unwrap_or(existing_order.get_base_asset_amount_unfilled(None)?);
+    // for quote sized orders, base_asset_amount is the quote asset amount
+    let base_asset_amount = match modify_order_params.base_asset_amount {
+        Some(base_asset_amount) => base_asset_amount,
+        None if existing_order.is_quote_sized() => {
+            existing_order.get_quote_asset_amount_unfilled()?
+        }
+        None => existing_order.get_base_asset_amount_unfilled(None)?,
+    };
     let price = modify_order_params.price.unwrap_or(existing_order.price);
     let market_index = existing_order.market_index;
     let reduce_only = modify_order_params
@@ -809,9 +1412,13 @@ fn merge_modify_order_params_with_existing_order(
         });
     let immediate_or_cancel = false;
     let max_ts = modify_order_params.max_ts.or(Some(existing_order.max_ts));
-    let trigger_price = modify_order_params
-        .trigger_price
-        .or(Some(existing_order.trigger_price));
+    let trigger_price = if existing_order.is_quote_sized() {
+        None
+    } else {
+        modify_order_params
+            .trigger_price
+            .or(Some(existing_order.trigger_price))
+    };
     let trigger_condition =
         modify_order_params
             .trigger_condition
@@ -826,6 +1433,24 @@ fn merge_modify_order_params_with_existing_order(
     let oracle_price_offset = modify_order_params
         .oracle_price_offset
         .or(Some(existing_order.oracle_price_offset));
+    let twap_params = if existing_order.is_twap() {
+        Some(TwapParams {
+            slice_interval: existing_order.get_twap_slice_interval(),
+            slice_base_asset_amount: existing_order.get_twap_slice_base_asset_amount()?,
+        })
+    } else {
+        None
+    };
+    let trigger_market_params = if existing_order.is_cross_market_trigger() {
+        let (market_type, market_index) = existing_order.get_trigger_market()?;
+        Some(TriggerMarketParams {
+            market_type,
+            market_index,
+            trigger_price_change: None,
+        })
+    } else {
+        None
+    };
     let (auction_duration, auction_start_price, auction_end_price) =
         if modify_order_params.auction_duration.is_some()
             && modify_order_params.auction_start_price.is_some()
@@ -858,6 +1483,12 @@ fn merge_modify_order_params_with_existing_order(
         auction_duration,
         auction_start_price,
         auction_end_price,
+        bit_flags: existing_order.bit_flags & OrderBitFlag::ORDER_PARAMS,
+        linked_order_group: existing_order.linked_order_group,
+        twap_params,
+        trigger_reference_price: Some(existing_order.trigger_reference_price),
+        trigger_market_params,
+        auction_curve: Some(existing_order.auction_curve()),
     })
 }
 
@@ -924,6 +1555,14 @@ pub fn fill_perp_order(
         "Market fills paused",
     )?;
 
+    if market.is_in_batch_auction_mode() && !user.orders[order_index].post_only {
+        msg!(
+            "Market {} in batch auction mode, taker orders are filled by clear_perp_batch_auction",
+            market_index
+        );
+        return Ok(0);
+    }
+
     drop(market);
 
     validate!(
@@ -968,6 +1607,7 @@ pub fn fill_perp_order(
         let market = &mut perp_market_map.get_ref_mut(&market_index)?;
         amm_is_available &= !market.is_operation_paused(PerpOperation::AmmFill);
         amm_is_available &= !market.has_too_much_drawdown()?;
+        amm_is_available &= !market.is_orderbook_only();
         validation::perp_market::validate_perp_market(market)?;
         validate!(
             !market.is_in_settlement(now),
@@ -1015,13 +1655,15 @@ pub fn fill_perp_order(
         (None, None)
     };
 
-    let maker_orders_info = get_maker_orders_info(
+    let (maker_orders_info, taker_self_trade_base_asset_amount) = get_maker_orders_info(
         perp_market_map,
         spot_market_map,
         oracle_map,
         makers_and_referrer,
         &user_key,
+        &user.authority,
         &user.orders[order_index],
+        user_stats.self_trade_prevention_mode,
         &mut filler.as_deref_mut(),
         &filler_key,
         state.perp_fee_structure.flat_filler_fee,
@@ -1031,6 +1673,23 @@ pub fn fill_perp_order(
         slot,
     )?;
 
+    if taker_self_trade_base_asset_amount > 0
+        && handle_taker_self_trade_prevention(
+            user,
+            &user_key,
+            order_index,
+            taker_self_trade_base_asset_amount,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            &filler_key,
+        )?
+    {
+        return Ok(0);
+    }
+
     let referrer_info = get_referrer_info(
         user_stats,
         &user_key,
@@ -1057,7 +1716,7 @@ pub fn fill_perp_order(
 
     validate_perp_fill_possible(state, user, order_index, slot, makers_and_referrer.0.len())?;
 
-    let should_expire_order = should_expire_order_before_fill(user, order_index, now)?;
+    let should_expire_order = should_expire_order_before_fill(user, order_index, now, slot)?;
 
     let position_index =
         get_position_index(&user.perp_positions, user.orders[order_index].market_index)?;
@@ -1107,6 +1766,14 @@ pub fn fill_perp_order(
         return Ok(0);
     }
 
+    let is_twap = user.orders[order_index].is_twap();
+    if is_twap && user.orders[order_index].get_twap_base_asset_amount_available(slot)? == 0 {
+        msg!("Twap order has no slice available to fill");
+        return Ok(0);
+    }
+
+    let linked_order_group = user.orders[order_index].get_linked_order_group_to_cancel();
+
     let (base_asset_amount, quote_asset_amount) = fulfill_perp_order(
         user,
         order_index,
@@ -1132,19 +1799,48 @@ pub fn fill_perp_order(
         fill_mode,
     )?;
 
+    // the order is removed from the user account once it's completely filled
+    let order_completely_filled = user.orders[order_index].order_id != order_id;
+
     if base_asset_amount != 0 {
         let fill_price =
             calculate_fill_price(quote_asset_amount, base_asset_amount, BASE_PRECISION_U64)?;
 
-        validate_fill_price_within_price_bands(
-            fill_price,
-            order_direction,
-            oracle_price,
-            oracle_twap_5min,
-            perp_market_map.get_ref(&market_index)?.margin_ratio_initial,
-            state
-                .oracle_guard_rails
-                .max_oracle_twap_5min_percent_divergence(),
+        let market = perp_market_map.get_ref(&market_index)?;
+
+        if market.is_option() {
+            validate_fill_price_within_option_premium_bands(
+                fill_price,
+                market.option_type,
+                market.strike_price,
+                oracle_price,
+                market.margin_ratio_initial,
+            )?;
+        } else if market.is_prediction_market() {
+            validate_fill_price_within_prediction_market_bounds(fill_price)?;
+        } else {
+            validate_fill_price_within_price_bands(
+                fill_price,
+                order_direction,
+                oracle_price,
+                oracle_twap_5min,
+                market.margin_ratio_initial,
+                state
+                    .oracle_guard_rails
+                    .max_oracle_twap_5min_percent_divergence(),
+            )?;
+        }
+    }
+
+    // twap slices are released over time and each one needs a keeper to fill it
+    if is_twap && base_asset_amount != 0 {
+        let mut market = perp_market_map.get_ref_mut(&market_index)?;
+        pay_keeper_flat_reward_for_perps(
+            user,
+            filler.as_deref_mut(),
+            market.deref_mut(),
+            state.perp_fee_structure.flat_filler_fee,
+            slot,
         )?;
     }
 
@@ -1192,6 +1888,21 @@ pub fn fill_perp_order(
         return Ok(0);
     }
 
+    if order_completely_filled {
+        cancel_linked_orders(
+            user,
+            &user_key,
+            order_id,
+            linked_order_group,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            Some(&filler_key),
+        )?;
+    }
+
     {
         let market = perp_market_map.get_ref(&market_index)?;
 
@@ -1230,6 +1941,28 @@ pub fn fill_perp_order(
     Ok(base_asset_amount)
 }
 
+pub fn validate_fill_or_kill_order_filled(
+    user: &User,
+    order_id: u32,
+    order_base_asset_amount: u64,
+    base_asset_amount_filled: u64,
+) -> DriftResult {
+    let order_exists = user.orders.iter().any(|order| order.order_id == order_id);
+
+    // order can be removed without being filled, e.g. by self trade prevention or if it expires
+    if order_exists || base_asset_amount_filled < order_base_asset_amount {
+        msg!(
+            "fill_or_kill order {} was not completely filled: {} / {}",
+            order_id,
+            base_asset_amount_filled,
+            order_base_asset_amount
+        );
+        return Err(ErrorCode::FillOrKillOrderNotFilled);
+    }
+
+    Ok(())
+}
+
 pub fn validate_market_within_price_band(
     market: &PerpMarket,
     state: &State,
@@ -1299,7 +2032,9 @@ fn get_maker_orders_info(
     oracle_map: &mut OracleMap,
     makers_and_referrer: &UserMap,
     taker_key: &Pubkey,
+    taker_authority: &Pubkey,
     taker_order: &Order,
+    self_trade_prevention_mode: SelfTradePreventionMode,
     filler: &mut Option<&mut User>,
     filler_key: &Pubkey,
     filler_reward: u64,
@@ -1307,11 +2042,23 @@ fn get_maker_orders_info(
     jit_maker_order_id: Option<u32>,
     now: i64,
     slot: u64,
-) -> DriftResult<Vec<(Pubkey, usize, u64)>> {
+) -> DriftResult<(Vec<(Pubkey, usize, u64)>, u64)> {
     let maker_direction = taker_order.direction.opposite();
 
     let mut maker_orders_info = Vec::with_capacity(16);
 
+    let taker_price = taker_order.get_limit_price(
+        Some(oracle_price),
+        None,
+        slot,
+        perp_market_map
+            .get_ref(&taker_order.market_index)?
+            .amm
+            .order_tick_size,
+    )?;
+    let taker_base_asset_amount_unfilled = taker_order.get_base_asset_amount_unfilled(None)?;
+    let mut taker_base_asset_amount_decrement = 0_u64;
+
     for (maker_key, user_account_loader) in makers_and_referrer.0.iter() {
         if maker_key == taker_key {
             continue;
@@ -1344,6 +2091,7 @@ fn get_maker_orders_info(
 
         let initial_margin_ratio = market.margin_ratio_initial;
         let step_size = market.amm.order_step_size;
+        let is_orderbook_only = market.is_orderbook_only();
 
         drop(market);
 
@@ -1367,16 +2115,15 @@ fn get_maker_orders_info(
                 }
             }
 
-            let breaches_oracle_price_limits = {
-                limit_price_breaches_maker_oracle_price_bands(
+            let breaches_oracle_price_limits = !is_orderbook_only
+                && limit_price_breaches_maker_oracle_price_bands(
                     maker_order_price,
                     maker_order.direction,
                     oracle_price,
                     initial_margin_ratio,
-                )?
-            };
+                )?;
 
-            let should_expire_order = should_expire_order(&maker, maker_order_index, now)?;
+            let should_expire_order = should_expire_order(&maker, maker_order_index, now, slot)?;
 
             let existing_base_asset_amount = maker
                 .get_perp_position(maker.orders[maker_order_index].market_index)?
@@ -1429,6 +2176,36 @@ fn get_maker_orders_info(
                 continue;
             }
 
+            let is_self_trade = self_trade_prevention_mode != SelfTradePreventionMode::None
+                && maker.authority == *taker_authority
+                && taker_price.map_or(true, |taker_price| {
+                    do_orders_cross(maker_direction, maker_order_price, taker_price)
+                });
+
+            if is_self_trade {
+                let decrement = apply_self_trade_prevention(
+                    maker.deref_mut(),
+                    maker_key,
+                    maker_order_index,
+                    taker_base_asset_amount_unfilled.safe_sub(taker_base_asset_amount_decrement)?,
+                    self_trade_prevention_mode,
+                    perp_market_map,
+                    spot_market_map,
+                    oracle_map,
+                    now,
+                    slot,
+                    Some(filler_key),
+                )?;
+                taker_base_asset_amount_decrement =
+                    taker_base_asset_amount_decrement.safe_add(decrement)?;
+
+                if taker_base_asset_amount_decrement >= taker_base_asset_amount_unfilled {
+                    return Ok((maker_orders_info, taker_base_asset_amount_decrement));
+                }
+
+                continue;
+            }
+
             insert_maker_order_info(
                 &mut maker_orders_info,
                 (*maker_key, maker_order_index, maker_order_price),
@@ -1437,16 +2214,85 @@ fn get_maker_orders_info(
         }
     }
 
-    Ok(maker_orders_info)
+    Ok((maker_orders_info, taker_base_asset_amount_decrement))
 }
 
-#[inline(always)]
-fn insert_maker_order_info(
-    maker_orders_info: &mut Vec<(Pubkey, usize, u64)>,
-    maker_order_info: (Pubkey, usize, u64),
-    direction: PositionDirection,
-) {
-    let price = maker_order_info.2;
+/// Splits the taker order between the makers quoting the same price as the given maker
+/// according to the market's matching policy. Makers are given time priority by order slot.
+/// Makers pulled by market maker protection can't be filled so they're allocated nothing
+fn get_maker_fill_allocations_for_price_level(
+    maker_key: &Pubkey,
+    maker_order_index: u16,
+    maker_orders_info: &[(Pubkey, usize, u64)],
+    makers_and_referrer: &UserMap,
+    taker_base_asset_amount: u64,
+    market: &PerpMarket,
+) -> DriftResult<Vec<((Pubkey, u16), u64)>> {
+    let price = maker_orders_info
+        .iter()
+        .find(|(key, index, _)| key == maker_key && *index == maker_order_index as usize)
+        .map(|(_, _, price)| *price)
+        .safe_unwrap()?;
+
+    let mut makers_at_price_level = Vec::with_capacity(maker_orders_info.len());
+    for (key, index, maker_price) in maker_orders_info.iter() {
+        if *maker_price != price {
+            continue;
+        }
+
+        let maker = makers_and_referrer.get_ref(key)?;
+        let maker_order = &maker.orders[*index];
+        let maker_pulled = maker.has_market_maker_protection
+            && makers_and_referrer
+                .get_market_maker_protection_mut(key)?
+                .map_or(true, |market_maker_protection| {
+                    market_maker_protection.is_triggered(market.market_index)
+                });
+        let maker_base_asset_amount = if maker_pulled {
+            0
+        } else {
+            let maker_existing_position = maker
+                .get_perp_position(market.market_index)?
+                .base_asset_amount;
+            maker_order.get_base_asset_amount_unfilled(Some(maker_existing_position))?
+        };
+
+        makers_at_price_level.push((
+            (*key, *index as u16),
+            (maker_order.slot, maker_order.order_id),
+            maker_base_asset_amount,
+        ));
+    }
+
+    makers_at_price_level.sort_by_key(|(_, time_priority, _)| *time_priority);
+
+    let maker_base_asset_amounts: Vec<u64> = makers_at_price_level
+        .iter()
+        .map(|(_, _, base_asset_amount)| *base_asset_amount)
+        .collect();
+
+    let allocations = calculate_maker_fill_allocations(
+        market.matching_policy,
+        &maker_base_asset_amounts,
+        taker_base_asset_amount,
+        market.top_of_book_allocation,
+        market.amm.order_step_size,
+    )?;
+
+    Ok(makers_at_price_level
+        .iter()
+        .zip(allocations)
+        .map(|((maker, _, _), allocation)| (*maker, allocation))
+        .collect())
+}
+
+#[inline(always)]
+fn insert_maker_order_info(
+    maker_orders_info: &mut Vec<(Pubkey, usize, u64)>,
+    maker_order_info: (Pubkey, usize, u64),
+    direction: PositionDirection,
+) {
+    let price = maker_order_info.2;
     let index = match maker_orders_info.binary_search_by(|item| match direction {
         PositionDirection::Short => item.2.cmp(&price),
         PositionDirection::Long => price.cmp(&item.2),
@@ -1565,6 +2411,11 @@ fn fulfill_perp_order(
     let mut base_asset_amount = 0_u64;
     let mut quote_asset_amount = 0_u64;
     let mut maker_fills: BTreeMap<Pubkey, i64> = BTreeMap::new();
+    let mut linked_maker_orders: Vec<(Pubkey, u32, u8)> = vec![];
+    let mut market_maker_protection_makers: Vec<Pubkey> = vec![];
+    let mut maker_fill_allocations: BTreeMap<(Pubkey, u16), u64> = BTreeMap::new();
+    // what makers at the current price level didn't fill of their allocation is passed on to the next maker
+    let mut maker_fill_allocation_shortfall = 0_u64;
     let maker_direction = user.orders[user_order_index].direction.opposite();
     for fulfillment_method in fulfillment_methods.iter() {
         if user.orders[user_order_index].status != OrderStatus::Open {
@@ -1608,7 +2459,64 @@ fn fulfill_perp_order(
                 (fill_base_asset_amount, fill_quote_asset_amount)
             }
             PerpFulfillmentMethod::Match(maker_key, maker_order_index) => {
+                let maker_base_asset_amount_limit = if market.matching_policy
+                    == MatchingPolicy::PriceTime
+                {
+                    None
+                } else {
+                    if !maker_fill_allocations.contains_key(&(*maker_key, *maker_order_index)) {
+                        let taker_existing_position =
+                            user.get_perp_position(market_index)?.base_asset_amount;
+                        let taker_base_asset_amount = user.orders[user_order_index]
+                            .get_base_asset_amount_unfilled(Some(taker_existing_position))?;
+
+                        maker_fill_allocations.extend(get_maker_fill_allocations_for_price_level(
+                            maker_key,
+                            *maker_order_index,
+                            maker_orders_info,
+                            makers_and_referrer,
+                            taker_base_asset_amount,
+                            &market,
+                        )?);
+                        maker_fill_allocation_shortfall = 0;
+                    }
+
+                    Some(
+                        maker_fill_allocations
+                            .get(&(*maker_key, *maker_order_index))
+                            .copied()
+                            .unwrap_or(0)
+                            .safe_add(maker_fill_allocation_shortfall)?,
+                    )
+                };
+
                 let mut maker = makers_and_referrer.get_ref_mut(maker_key)?;
+
+                let mut maker_market_maker_protection =
+                    makers_and_referrer.get_market_maker_protection_mut(maker_key)?;
+
+                if maker.has_market_maker_protection {
+                    match maker_market_maker_protection.as_deref() {
+                        None => {
+                            msg!(
+                                "market maker protection not provided for maker {}",
+                                maker_key
+                            );
+                            continue;
+                        }
+                        // orders posted after protection triggered are pulled until the maker resets
+                        Some(market_maker_protection)
+                            if market_maker_protection.is_triggered(market_index) =>
+                        {
+                            if !market_maker_protection_makers.contains(maker_key) {
+                                market_maker_protection_makers.push(*maker_key);
+                            }
+                            continue;
+                        }
+                        Some(_) => {}
+                    }
+                }
+
                 let mut maker_stats = if maker.authority == user.authority {
                     None
                 } else {
@@ -1622,6 +2530,10 @@ fn fulfill_perp_order(
                     Some(&maker),
                 )?;
 
+                let maker_order_id = maker.orders[*maker_order_index as usize].order_id;
+                let maker_linked_order_group =
+                    maker.orders[*maker_order_index as usize].get_linked_order_group_to_cancel();
+
                 let (fill_base_asset_amount, fill_quote_asset_amount, maker_fill_base_asset_amount) =
                     fulfill_perp_order_with_match(
                         market.deref_mut(),
@@ -1645,8 +2557,16 @@ fn fulfill_perp_order(
                         slot,
                         fee_structure,
                         oracle_map,
+                        maker_base_asset_amount_limit,
+                        None,
+                        false,
                     )?;
 
+                if let Some(maker_base_asset_amount_limit) = maker_base_asset_amount_limit {
+                    maker_fill_allocation_shortfall =
+                        maker_base_asset_amount_limit.saturating_sub(fill_base_asset_amount);
+                }
+
                 if maker_fill_base_asset_amount != 0 {
                     update_maker_fills_map(
                         &mut maker_fills,
@@ -1654,6 +2574,40 @@ fn fulfill_perp_order(
                         maker_direction,
                         maker_fill_base_asset_amount,
                     )?;
+
+                    // the maker order is removed once it's completely filled
+                    let maker_order_completely_filled =
+                        maker.orders[*maker_order_index as usize].order_id != maker_order_id;
+                    if maker_linked_order_group != 0 && maker_order_completely_filled {
+                        linked_maker_orders.push((
+                            *maker_key,
+                            maker_order_id,
+                            maker_linked_order_group,
+                        ));
+                    }
+
+                    if let Some(market_maker_protection) =
+                        maker_market_maker_protection.as_deref_mut()
+                    {
+                        let market_maker_protection_triggered = market_maker_protection
+                            .update_market(
+                                market_index,
+                                maker_fill_base_asset_amount,
+                                maker_direction,
+                                slot,
+                            )?;
+
+                        if market_maker_protection_triggered
+                            && !market_maker_protection_makers.contains(maker_key)
+                        {
+                            msg!(
+                                "market maker protection triggered for maker {} in market {}",
+                                maker_key,
+                                market_index
+                            );
+                            market_maker_protection_makers.push(*maker_key);
+                        }
+                    }
                 }
 
                 (fill_base_asset_amount, fill_quote_asset_amount)
@@ -1667,6 +2621,40 @@ fn fulfill_perp_order(
             .update_volume_24h(fill_quote_asset_amount, user_order_direction, now)?;
     }
 
+    for (maker_key, maker_order_id, maker_linked_order_group) in linked_maker_orders {
+        let mut maker = makers_and_referrer.get_ref_mut(&maker_key)?;
+        cancel_linked_orders(
+            &mut maker,
+            &maker_key,
+            maker_order_id,
+            maker_linked_order_group,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            Some(filler_key),
+        )?;
+    }
+
+    for maker_key in market_maker_protection_makers {
+        let mut maker = makers_and_referrer.get_ref_mut(&maker_key)?;
+        cancel_orders(
+            &mut maker,
+            &maker_key,
+            Some(filler_key),
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::MarketMakerProtectionTriggered,
+            Some(MarketType::Perp),
+            Some(market_index),
+            None,
+        )?;
+    }
+
     validate!(
         (base_asset_amount > 0) == (quote_asset_amount > 0),
         ErrorCode::DefaultError,
@@ -1685,26 +2673,30 @@ fn fulfill_perp_order(
         base_asset_amount
     )?;
 
-    let taker_margin_calculation =
-        calculate_margin_requirement_and_total_collateral_and_liability_info(
-            user,
-            perp_market_map,
-            spot_market_map,
-            oracle_map,
-            MarginContext::standard(if user_order_position_decreasing {
-                MarginRequirementType::Maintenance
-            } else {
-                MarginRequirementType::Fill
-            }),
-        )?;
+    if fill_mode != FillMode::PlaceAndTakeCombo {
+        let taker_margin_calculation =
+            calculate_margin_requirement_and_total_collateral_and_liability_info(
+                user,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                MarginContext::standard(if user_order_position_decreasing {
+                    MarginRequirementType::Maintenance
+                } else {
+                    MarginRequirementType::Fill
+                }),
+            )?;
 
-    if !taker_margin_calculation.meets_margin_requirement() {
-        msg!(
-            "taker breached fill requirements (margin requirement {}) (total_collateral {})",
-            taker_margin_calculation.margin_requirement,
-            taker_margin_calculation.total_collateral
-        );
-        return Err(ErrorCode::InsufficientCollateral);
+        if !taker_margin_calculation
+            .meets_market_margin_requirement(MarketIdentifier::perp(market_index))
+        {
+            msg!(
+                "taker breached fill requirements (margin requirement {}) (total_collateral {})",
+                taker_margin_calculation.margin_requirement,
+                taker_margin_calculation.total_collateral
+            );
+            return Err(ErrorCode::InsufficientCollateral);
+        }
     }
 
     for (maker_key, maker_base_asset_amount_filled) in maker_fills {
@@ -1725,7 +2717,9 @@ fn fulfill_perp_order(
                 MarginContext::standard(margin_type),
             )?;
 
-        if !maker_margin_calculation.meets_margin_requirement() {
+        if !maker_margin_calculation
+            .meets_market_margin_requirement(MarketIdentifier::perp(market_index))
+        {
             msg!(
                 "maker ({}) breached fill requirements (margin requirement {}) (total_collateral {})",
                 maker_key,
@@ -1840,6 +2834,13 @@ pub fn fulfill_perp_order_with_amm(
                 fee_tier,
             )?;
 
+            let base_asset_amount = if user.orders[order_index].is_twap() {
+                base_asset_amount
+                    .min(user.orders[order_index].get_twap_base_asset_amount_available(slot)?)
+            } else {
+                base_asset_amount
+            };
+
             let fill_price = if user.orders[order_index].post_only {
                 limit_price
             } else {
@@ -2035,10 +3036,11 @@ pub fn fulfill_perp_order_with_amm(
         filler.update_last_active_slot(slot);
     }
 
-    update_order_after_fill(
+    let base_asset_amount_resized = update_order_after_fill(
         &mut user.orders[order_index],
         base_asset_amount,
         quote_asset_amount,
+        market.amm.order_step_size,
     )?;
 
     decrease_open_bids_and_asks(
@@ -2047,9 +3049,20 @@ pub fn fulfill_perp_order_with_amm(
         base_asset_amount,
     )?;
 
+    resize_open_bids_and_asks(
+        &mut user.perp_positions[position_index],
+        &order_direction,
+        base_asset_amount_resized,
+    )?;
+
     let (taker, taker_order, maker, maker_order) =
         get_taker_and_maker_for_order_record(user_key, &user.orders[order_index]);
 
+    market.update_last_fill_price(
+        calculate_fill_price(quote_asset_amount, base_asset_amount, BASE_PRECISION_U64)?,
+        now,
+    )?;
+
     let fill_record_id = get_then_update_id!(market, next_fill_record_id);
     let order_action_explanation = match (override_base_asset_amount, override_fill_price) {
         (Some(_), Some(_)) => liquidity_split.get_order_action_explanation(),
@@ -2115,6 +3128,9 @@ pub fn fulfill_perp_order_with_match(
     slot: u64,
     fee_structure: &FeeStructure,
     oracle_map: &mut OracleMap,
+    maker_base_asset_amount_limit: Option<u64>,
+    fill_price: Option<u64>,
+    maker_is_taker: bool,
 ) -> DriftResult<(u64, u64, u64)> {
     if !are_orders_same_market_but_different_sides(
         &maker.orders[maker_order_index],
@@ -2135,7 +3151,7 @@ pub fn fulfill_perp_order_with_match(
             &taker_direction,
             amm_available_liquidity,
             oracle_price,
-            taker.orders[taker_order_index].seconds_til_expiry(now),
+            taker.orders[taker_order_index].seconds_til_expiry(now, slot)?,
         )?
     };
 
@@ -2144,19 +3160,33 @@ pub fn fulfill_perp_order_with_match(
         .base_asset_amount;
     let taker_base_asset_amount = taker.orders[taker_order_index]
         .get_base_asset_amount_unfilled(Some(taker_existing_position))?;
+    let taker_base_asset_amount = if taker.orders[taker_order_index].is_twap() {
+        taker_base_asset_amount
+            .min(taker.orders[taker_order_index].get_twap_base_asset_amount_available(slot)?)
+    } else {
+        taker_base_asset_amount
+    };
 
-    let maker_price = maker.orders[maker_order_index].force_get_limit_price(
-        Some(oracle_price),
-        None,
-        slot,
-        market.amm.order_tick_size,
-    )?;
+    // batch auctions fill every order at the uniform clearing price
+    let maker_price = match fill_price {
+        Some(fill_price) => fill_price,
+        None => maker.orders[maker_order_index].force_get_limit_price(
+            Some(oracle_price),
+            None,
+            slot,
+            market.amm.order_tick_size,
+        )?,
+    };
     let maker_direction = maker.orders[maker_order_index].direction;
     let maker_existing_position = maker
         .get_perp_position(market.market_index)?
         .base_asset_amount;
     let maker_base_asset_amount = maker.orders[maker_order_index]
         .get_base_asset_amount_unfilled(Some(maker_existing_position))?;
+    let maker_base_asset_amount = match maker_base_asset_amount_limit {
+        Some(limit) => maker_base_asset_amount.min(limit),
+        None => maker_base_asset_amount,
+    };
 
     let orders_cross = do_orders_cross(maker_direction, maker_price, taker_price);
 
@@ -2193,16 +3223,20 @@ pub fn fulfill_perp_order_with_match(
     let mut total_quote_asset_amount = 0_u64;
     let mut total_base_asset_amount = 0_u64;
 
-    let (jit_base_asset_amount, amm_liquidity_split) = calculate_amm_jit_liquidity(
-        market,
-        taker_direction,
-        maker_price,
-        valid_oracle_price,
-        base_asset_amount,
-        taker_base_asset_amount,
-        maker_base_asset_amount,
-        taker.orders[taker_order_index].has_limit_price(slot)?,
-    )?;
+    let (jit_base_asset_amount, amm_liquidity_split) = if fill_price.is_none() {
+        calculate_amm_jit_liquidity(
+            market,
+            taker_direction,
+            maker_price,
+            valid_oracle_price,
+            base_asset_amount,
+            taker_base_asset_amount,
+            maker_base_asset_amount,
+            taker.orders[taker_order_index].has_limit_price(slot)?,
+        )?
+    } else {
+        (0, AMMLiquiditySplit::ProtocolOwned)
+    };
 
     if jit_base_asset_amount > 0 {
         let (base_asset_amount_filled_by_amm, quote_asset_amount_filled_by_amm) =
@@ -2238,6 +3272,12 @@ pub fn fulfill_perp_order_with_match(
 
     let taker_base_asset_amount = taker.orders[taker_order_index]
         .get_base_asset_amount_unfilled(Some(taker_existing_position))?;
+    let taker_base_asset_amount = if taker.orders[taker_order_index].is_twap() {
+        taker_base_asset_amount
+            .min(taker.orders[taker_order_index].get_twap_base_asset_amount_available(slot)?)
+    } else {
+        taker_base_asset_amount
+    };
 
     let (base_asset_amount_fulfilled_by_maker, quote_asset_amount) =
         calculate_fill_for_matched_orders(
@@ -2344,6 +3384,23 @@ pub fn fulfill_perp_order_with_match(
         market.fee_adjustment,
     )?;
 
+    // a taker in the maker slot pays its own taker fee rather than earning the rebate
+    let (maker_rebate, maker_fee, fee_to_market) = if maker_is_taker {
+        let maker_fee = fees::calculate_taker_fee_for_maker(
+            maker_stats.as_deref().unwrap_or(&*taker_stats),
+            quote_asset_amount,
+            fee_structure,
+            &MarketType::Perp,
+            market.fee_adjustment,
+        )?;
+        let fee_to_market = fee_to_market
+            .safe_add(maker_rebate.cast()?)?
+            .safe_add(maker_fee.cast()?)?;
+        (0, maker_fee, fee_to_market)
+    } else {
+        (maker_rebate, 0, fee_to_market)
+    };
+
     // Increment the markets house's total fee variables
     market.amm.total_fee = market.amm.total_fee.safe_add(fee_to_market.cast()?)?;
     market.amm.total_exchange_fee = market
@@ -2371,13 +3428,15 @@ pub fn fulfill_perp_order_with_match(
     controller::position::update_quote_asset_and_break_even_amount(
         &mut maker.perp_positions[maker_position_index],
         market,
-        maker_rebate.cast()?,
+        maker_rebate.cast::<i64>()?.safe_sub(maker_fee.cast()?)?,
     )?;
 
     if let Some(maker_stats) = maker_stats {
         maker_stats.increment_total_rebate(maker_rebate)?;
+        maker_stats.increment_total_fees(maker_fee)?;
     } else {
         taker_stats.increment_total_rebate(maker_rebate)?;
+        taker_stats.increment_total_fees(maker_fee)?;
     }
 
     if let Some(filler) = filler {
@@ -2410,10 +3469,11 @@ pub fn fulfill_perp_order_with_match(
         }
     }
 
-    update_order_after_fill(
+    let taker_base_asset_amount_resized = update_order_after_fill(
         &mut taker.orders[taker_order_index],
         base_asset_amount_fulfilled_by_maker,
         quote_asset_amount,
+        market.amm.order_step_size,
     )?;
 
     decrease_open_bids_and_asks(
@@ -2422,10 +3482,17 @@ pub fn fulfill_perp_order_with_match(
         base_asset_amount_fulfilled_by_maker,
     )?;
 
-    update_order_after_fill(
+    resize_open_bids_and_asks(
+        &mut taker.perp_positions[taker_position_index],
+        &taker.orders[taker_order_index].direction,
+        taker_base_asset_amount_resized,
+    )?;
+
+    let maker_base_asset_amount_resized = update_order_after_fill(
         &mut maker.orders[maker_order_index],
         base_asset_amount_fulfilled_by_maker,
         quote_asset_amount,
+        market.amm.order_step_size,
     )?;
 
     decrease_open_bids_and_asks(
@@ -2434,13 +3501,28 @@ pub fn fulfill_perp_order_with_match(
         base_asset_amount_fulfilled_by_maker,
     )?;
 
+    resize_open_bids_and_asks(
+        &mut maker.perp_positions[maker_position_index],
+        &maker.orders[maker_order_index].direction,
+        maker_base_asset_amount_resized,
+    )?;
+
+    market.update_last_fill_price(
+        calculate_fill_price(
+            quote_asset_amount,
+            base_asset_amount_fulfilled_by_maker,
+            BASE_PRECISION_U64,
+        )?,
+        now,
+    )?;
+
     let fill_record_id = get_then_update_id!(market, next_fill_record_id);
     let order_action_explanation = if maker.orders[maker_order_index].is_jit_maker() {
         OrderActionExplanation::OrderFilledWithMatchJit
     } else {
         OrderActionExplanation::OrderFilledWithMatch
     };
-    let order_action_record = get_order_action_record(
+    let mut order_action_record = get_order_action_record(
         now,
         OrderAction::Fill,
         order_action_explanation,
@@ -2461,6 +3543,9 @@ pub fn fulfill_perp_order_with_match(
         Some(maker.orders[maker_order_index]),
         oracle_map.get_price_data(&market.amm.oracle)?.price,
     )?;
+    if maker_is_taker {
+        order_action_record.maker_fee = Some(maker_fee.cast()?);
+    }
     emit_stack::<_, { OrderActionRecord::SIZE }>(order_action_record)?;
 
     if taker.orders[taker_order_index].get_base_asset_amount_unfilled(None)? == 0 {
@@ -2484,22 +3569,44 @@ pub fn fulfill_perp_order_with_match(
     ))
 }
 
+/// Returns the change in the order's unfilled base asset amount from resizing a quote sized order
 pub fn update_order_after_fill(
     order: &mut Order,
     base_asset_amount: u64,
     quote_asset_amount: u64,
-) -> DriftResult {
+    step_size: u64,
+) -> DriftResult<i64> {
     order.base_asset_amount_filled = order.base_asset_amount_filled.safe_add(base_asset_amount)?;
 
     order.quote_asset_amount_filled = order
         .quote_asset_amount_filled
         .safe_add(quote_asset_amount)?;
 
+    // quote sized orders are resized so the unfilled base is the unfilled quote at the fill price
+    let base_asset_amount_resized = if order.is_quote_sized() {
+        let base_asset_amount_unfilled = order.get_base_asset_amount_unfilled(None)?;
+        let new_base_asset_amount_unfilled =
+            calculate_quote_sized_order_base_asset_amount_unfilled(
+                order,
+                base_asset_amount,
+                quote_asset_amount,
+                step_size,
+            )?;
+        order.base_asset_amount = order
+            .base_asset_amount_filled
+            .safe_add(new_base_asset_amount_unfilled)?;
+        new_base_asset_amount_unfilled
+            .cast::<i64>()?
+            .safe_sub(base_asset_amount_unfilled.cast()?)?
+    } else {
+        0
+    };
+
     if order.get_base_asset_amount_unfilled(None)? == 0 {
         order.status = OrderStatus::Filled;
     }
 
-    Ok(())
+    Ok(base_asset_amount_resized)
 }
 
 #[allow(clippy::type_complexity)]
@@ -2514,89 +3621,1305 @@ fn get_taker_and_maker_for_order_record(
     }
 }
 
-pub fn trigger_order(
-    order_id: u32,
+struct BatchAuctionOrder {
+    user_key: Pubkey,
+    order_index: usize,
+    limit_price: Option<u64>,
+    base_asset_amount: u64,
+    slot: u64,
+}
+
+/// Clears the taker orders placed in completed batch auction windows at a single uniform price.
+/// Opposing takers are crossed against each other, both paying taker fees, then the imbalance is
+/// filled by resting makers and the amm. Only the passed in users are cleared, takers left out wait for a
+/// later clear, and users who can't cover their orders have the risk increasing ones cancelled
+pub fn clear_perp_batch_auction(
+    market_index: u16,
     state: &State,
-    user: &AccountLoader<User>,
-    spot_market_map: &SpotMarketMap,
     perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
+    users: &UserMap,
+    users_stats: &UserStatsMap,
     filler: &AccountLoader<User>,
+    filler_stats: &AccountLoader<UserStats>,
     clock: &Clock,
 ) -> DriftResult {
     let now = clock.unix_timestamp;
     let slot = clock.slot;
 
-    let filler_key = filler.key();
-    let user_key = user.key();
-    let user = &mut load_mut!(user)?;
+    let (
+        oracle_price,
+        reserve_price_before,
+        amm_bid_price,
+        amm_ask_price,
+        tick_size,
+        window_start_slot,
+    ) = {
+        let market = perp_market_map.get_ref(&market_index)?;
 
-    let order_index = user
-        .orders
-        .iter()
-        .position(|order| order.order_id == order_id)
-        .ok_or_else(print_error!(ErrorCode::OrderDoesNotExist))?;
+        validate!(
+            market.is_in_batch_auction_mode(),
+            ErrorCode::BatchAuctionNotEnabled,
+            "Market {} not in batch auction mode",
+            market_index
+        )?;
 
-    let (order_status, market_index, market_type) =
-        get_struct_values!(user.orders[order_index], status, market_index, market_type);
+        validate!(
+            matches!(
+                market.status,
+                MarketStatus::Active | MarketStatus::ReduceOnly
+            ),
+            ErrorCode::MarketFillOrderPaused,
+            "Market not active",
+        )?;
 
-    validate!(
-        order_status == OrderStatus::Open,
-        ErrorCode::OrderNotOpen,
-        "Order not open"
-    )?;
+        validate!(
+            !market.is_operation_paused(PerpOperation::Fill),
+            ErrorCode::MarketFillOrderPaused,
+            "Market fills paused",
+        )?;
 
-    validate!(
-        user.orders[order_index].must_be_triggered(),
-        ErrorCode::OrderNotTriggerable,
-        "Order is not triggerable"
-    )?;
+        validation::perp_market::validate_perp_market(&market)?;
+        validate!(
+            !market.is_in_settlement(now),
+            ErrorCode::MarketFillOrderPaused,
+            "Market is in settlement mode",
+        )?;
 
-    validate!(
-        !user.orders[order_index].triggered(),
-        ErrorCode::OrderNotTriggerable,
-        "Order is already triggered"
-    )?;
+        let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
+            MarketType::Perp,
+            market.market_index,
+            &market.amm.oracle,
+            market.amm.historical_oracle_data.last_oracle_price_twap,
+            market.get_max_confidence_interval_multiplier()?,
+        )?;
 
-    validate!(
-        market_type == MarketType::Perp,
-        ErrorCode::InvalidOrderMarketType,
-        "Order must be a perp order"
-    )?;
+        validate!(
+            is_oracle_valid_for_action(oracle_validity, Some(DriftAction::FillOrderMatch))?,
+            ErrorCode::InvalidOracle,
+            "OracleValidity for perp marketIndex={} invalid for batch auction",
+            market_index
+        )?;
 
-    validate_user_not_being_liquidated(
-        user,
-        perp_market_map,
-        spot_market_map,
-        oracle_map,
-        state.liquidation_margin_buffer_ratio,
-    )?;
+        let reserve_price_before = market.amm.reserve_price()?;
 
-    validate!(!user.is_bankrupt(), ErrorCode::UserBankrupt)?;
+        let amm_is_available = !state.amm_paused()?
+            && !market.is_operation_paused(PerpOperation::AmmFill)
+            && !market.has_too_much_drawdown()?
+            && !market.is_orderbook_only();
 
-    let mut perp_market = perp_market_map.get_ref_mut(&market_index)?;
-    let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
-        MarketType::Perp,
-        perp_market.market_index,
-        &perp_market.amm.oracle,
-        perp_market
-            .amm
-            .historical_oracle_data
-            .last_oracle_price_twap,
-        perp_market.get_max_confidence_interval_multiplier()?,
-    )?;
+        let (amm_bid_price, amm_ask_price) = if amm_is_available {
+            (
+                Some(market.amm.bid_price(reserve_price_before)?),
+                Some(market.amm.ask_price(reserve_price_before)?),
+            )
+        } else {
+            (None, None)
+        };
 
-    let is_oracle_valid =
+        let window_start_slot = market
+            .get_batch_auction_window(slot)?
+            .cast::<u64>()?
+            .safe_mul(market.batch_auction_duration.cast()?)?;
+
+        (
+            oracle_price_data.price,
+            reserve_price_before,
+            amm_bid_price,
+            amm_ask_price,
+            market.amm.order_tick_size,
+            window_start_slot,
+        )
+    };
+
+    let filler_key = filler.key();
+
+    let mut taker_bids: Vec<BatchAuctionOrder> = vec![];
+    let mut taker_asks: Vec<BatchAuctionOrder> = vec![];
+    let mut maker_bids: Vec<BatchAuctionOrder> = vec![];
+    let mut maker_asks: Vec<BatchAuctionOrder> = vec![];
+
+    for (user_key, user_account_loader) in users.0.iter() {
+        let mut user = load_mut!(user_account_loader)?;
+
+        if user.is_being_liquidated() || user.is_bankrupt() {
+            continue;
+        }
+
+        if user.get_perp_position(market_index).is_err() {
+            continue;
+        }
+
+        {
+            let mut market = perp_market_map.get_ref_mut(&market_index)?;
+            controller::lp::settle_funding_payment_then_lp(&mut user, user_key, &mut market, now)?;
+        }
+
+        // a user who can't cover their open orders has the risk increasing ones cancelled
+        // rather than failing the whole batch
+        if !meets_initial_margin_requirement(
+            &user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            MarketIdentifier::perp(market_index),
+        )? {
+            let position_base_asset_amount =
+                user.get_perp_position(market_index)?.base_asset_amount;
+            for order_index in 0..user.orders.len() {
+                let order = &user.orders[order_index];
+                if order.status != OrderStatus::Open
+                    || order.market_type != MarketType::Perp
+                    || order.market_index != market_index
+                    || order.reduce_only
+                {
+                    continue;
+                }
+
+                let is_risk_increasing = match order.direction {
+                    PositionDirection::Long => position_base_asset_amount >= 0,
+                    PositionDirection::Short => position_base_asset_amount <= 0,
+                };
+
+                if is_risk_increasing {
+                    cancel_order(
+                        order_index,
+                        &mut user,
+                        user_key,
+                        perp_market_map,
+                        spot_market_map,
+                        oracle_map,
+                        now,
+                        slot,
+                        OrderActionExplanation::InsufficientFreeCollateral,
+                        Some(&filler_key),
+                        0,
+                        false,
+                    )?;
+                }
+            }
+        }
+
+        let existing_base_asset_amount = user.get_perp_position(market_index)?.base_asset_amount;
+
+        for order_index in 0..user.orders.len() {
+            let order = &user.orders[order_index];
+            if order.status != OrderStatus::Open
+                || order.market_type != MarketType::Perp
+                || order.market_index != market_index
+                || (order.must_be_triggered() && !order.triggered())
+                || should_expire_order(&user, order_index, now, slot)?
+            {
+                continue;
+            }
+
+            let base_asset_amount =
+                order.get_base_asset_amount_unfilled(Some(existing_base_asset_amount))?;
+
+            if base_asset_amount == 0 {
+                continue;
+            }
+
+            let batch_auction_order = BatchAuctionOrder {
+                user_key: *user_key,
+                order_index,
+                limit_price: order.get_limit_price(Some(oracle_price), None, slot, tick_size)?,
+                base_asset_amount,
+                slot: order.slot,
+            };
+
+            match (order.post_only, order.direction) {
+                (true, _) if batch_auction_order.limit_price.is_none() => {}
+                (true, PositionDirection::Long) => maker_bids.push(batch_auction_order),
+                (true, PositionDirection::Short) => maker_asks.push(batch_auction_order),
+                // takers wait for their auction window to complete
+                (false, _) if order.slot >= window_start_slot => {}
+                (false, PositionDirection::Long) => taker_bids.push(batch_auction_order),
+                (false, PositionDirection::Short) => taker_asks.push(batch_auction_order),
+            }
+        }
+    }
+
+    if taker_bids.is_empty() && taker_asks.is_empty() {
+        msg!("No taker orders to clear for perp market {}", market_index);
+        return Ok(());
+    }
+
+    taker_bids.sort_by_key(|order| order.slot);
+    taker_asks.sort_by_key(|order| order.slot);
+    maker_bids.sort_by(|a, b| b.limit_price.cmp(&a.limit_price).then(a.slot.cmp(&b.slot)));
+    maker_asks.sort_by(|a, b| a.limit_price.cmp(&b.limit_price).then(a.slot.cmp(&b.slot)));
+
+    let taker_levels = |orders: &[BatchAuctionOrder]| -> Vec<(Option<u64>, u64)> {
+        orders
+            .iter()
+            .map(|order| (order.limit_price, order.base_asset_amount))
+            .collect()
+    };
+    let maker_levels = |orders: &[BatchAuctionOrder]| -> Vec<(u64, u64)> {
+        orders
+            .iter()
+            .map(|order| (order.limit_price.unwrap_or(0), order.base_asset_amount))
+            .collect()
+    };
+
+    let clearing_price = match calculate_batch_auction_clearing_price(
+        &taker_levels(&taker_bids),
+        &taker_levels(&taker_asks),
+        &maker_levels(&maker_bids),
+        &maker_levels(&maker_asks),
+        amm_bid_price,
+        amm_ask_price,
+        oracle_price.cast()?,
+        tick_size,
+    )? {
+        Some(clearing_price) => clearing_price,
+        None => {
+            msg!("No orders cross for perp market {}", market_index);
+            return Ok(());
+        }
+    };
+
+    msg!(
+        "Clearing perp market {} batch auction at {}",
+        market_index,
+        clearing_price
+    );
+
+    // orders that don't cross the clearing price wait for the next auction
+    taker_bids.retain(|order| {
+        order
+            .limit_price
+            .map_or(true, |price| price >= clearing_price)
+    });
+    taker_asks.retain(|order| {
+        order
+            .limit_price
+            .map_or(true, |price| price <= clearing_price)
+    });
+    maker_bids.retain(|order| {
+        order
+            .limit_price
+            .map_or(false, |price| price >= clearing_price)
+    });
+    maker_asks.retain(|order| {
+        order
+            .limit_price
+            .map_or(false, |price| price <= clearing_price)
+    });
+
+    let (mut filler, mut filler_stats) = if users.0.contains_key(&filler_key) {
+        (None, None)
+    } else {
+        let filler = load_mut!(filler)?;
+        if users_stats.0.contains_key(&filler.authority) {
+            (None, None)
+        } else {
+            (Some(filler), Some(load_mut!(filler_stats)?))
+        }
+    };
+
+    let mut fills: BTreeMap<Pubkey, i64> = BTreeMap::new();
+    let mut total_base_asset_amount = 0_u64;
+    let mut market_maker_protection_makers: Vec<Pubkey> = vec![];
+
+    // cross opposing takers, both sides pay taker fees since neither rested on the book
+    let mut bid_index = 0;
+    let mut ask_index = 0;
+    while bid_index < taker_bids.len() && ask_index < taker_asks.len() {
+        let bid_is_maker = taker_bids[bid_index].slot <= taker_asks[ask_index].slot;
+
+        if taker_bids[bid_index].user_key == taker_asks[ask_index].user_key {
+            // the later order waits for the next auction
+            if bid_is_maker {
+                ask_index += 1;
+            } else {
+                bid_index += 1;
+            }
+            continue;
+        }
+
+        let (taker_order, maker_order) = if bid_is_maker {
+            (&taker_asks[ask_index], &taker_bids[bid_index])
+        } else {
+            (&taker_bids[bid_index], &taker_asks[ask_index])
+        };
+
+        let base_asset_amount = fill_batch_auction_order_with_match(
+            market_index,
+            taker_order,
+            maker_order,
+            clearing_price,
+            users,
+            users_stats,
+            &mut filler.as_deref_mut(),
+            &mut filler_stats.as_deref_mut(),
+            &filler_key,
+            perp_market_map,
+            oracle_map,
+            &state.perp_fee_structure,
+            reserve_price_before,
+            oracle_price,
+            true,
+            &mut market_maker_protection_makers,
+            now,
+            slot,
+        )?;
+
+        if base_asset_amount == 0 {
+            if bid_is_maker {
+                ask_index += 1;
+            } else {
+                bid_index += 1;
+            }
+            continue;
+        }
+
+        update_maker_fills_map(
+            &mut fills,
+            &taker_bids[bid_index].user_key,
+            PositionDirection::Long,
+            base_asset_amount,
+        )?;
+        update_maker_fills_map(
+            &mut fills,
+            &taker_asks[ask_index].user_key,
+            PositionDirection::Short,
+            base_asset_amount,
+        )?;
+        total_base_asset_amount = total_base_asset_amount.safe_add(base_asset_amount)?;
+
+        taker_bids[bid_index].base_asset_amount = taker_bids[bid_index]
+            .base_asset_amount
+            .saturating_sub(base_asset_amount);
+        taker_asks[ask_index].base_asset_amount = taker_asks[ask_index]
+            .base_asset_amount
+            .saturating_sub(base_asset_amount);
+
+        if taker_bids[bid_index].base_asset_amount == 0 {
+            bid_index += 1;
+        }
+
+        if taker_asks[ask_index].base_asset_amount == 0 {
+            ask_index += 1;
+        }
+    }
+
+    // fill the imbalance against makers and then the amm
+    let residuals = [
+        (
+            &mut taker_bids[bid_index..],
+            &mut maker_asks,
+            PositionDirection::Long,
+        ),
+        (
+            &mut taker_asks[ask_index..],
+            &mut maker_bids,
+            PositionDirection::Short,
+        ),
+    ];
+    for (taker_orders, maker_orders, taker_direction) in residuals {
+        for taker_order in taker_orders.iter_mut() {
+            for maker_order in maker_orders.iter_mut() {
+                if taker_order.base_asset_amount == 0 {
+                    break;
+                }
+
+                if maker_order.base_asset_amount == 0
+                    || maker_order.user_key == taker_order.user_key
+                {
+                    continue;
+                }
+
+                let base_asset_amount = fill_batch_auction_order_with_match(
+                    market_index,
+                    taker_order,
+                    maker_order,
+                    clearing_price,
+                    users,
+                    users_stats,
+                    &mut filler.as_deref_mut(),
+                    &mut filler_stats.as_deref_mut(),
+                    &filler_key,
+                    perp_market_map,
+                    oracle_map,
+                    &state.perp_fee_structure,
+                    reserve_price_before,
+                    oracle_price,
+                    false,
+                    &mut market_maker_protection_makers,
+                    now,
+                    slot,
+                )?;
+
+                update_maker_fills_map(
+                    &mut fills,
+                    &taker_order.user_key,
+                    taker_direction,
+                    base_asset_amount,
+                )?;
+                update_maker_fills_map(
+                    &mut fills,
+                    &maker_order.user_key,
+                    taker_direction.opposite(),
+                    base_asset_amount,
+                )?;
+                total_base_asset_amount = total_base_asset_amount.safe_add(base_asset_amount)?;
+
+                taker_order.base_asset_amount = taker_order
+                    .base_asset_amount
+                    .saturating_sub(base_asset_amount);
+                maker_order.base_asset_amount = maker_order
+                    .base_asset_amount
+                    .saturating_sub(base_asset_amount);
+            }
+
+            if taker_order.base_asset_amount == 0 || amm_bid_price.is_none() {
+                continue;
+            }
+
+            let base_asset_amount = fill_batch_auction_order_with_amm(
+                market_index,
+                taker_order,
+                clearing_price,
+                users,
+                users_stats,
+                &mut filler.as_deref_mut(),
+                &mut filler_stats.as_deref_mut(),
+                &filler_key,
+                perp_market_map,
+                oracle_map,
+                &state.perp_fee_structure,
+                reserve_price_before,
+                now,
+                slot,
+            )?;
+
+            update_maker_fills_map(
+                &mut fills,
+                &taker_order.user_key,
+                taker_direction,
+                base_asset_amount,
+            )?;
+            total_base_asset_amount = total_base_asset_amount.safe_add(base_asset_amount)?;
+        }
+    }
+
+    if total_base_asset_amount > 0 {
+        perp_market_map
+            .get_ref_mut(&market_index)?
+            .update_last_fill_price(clearing_price, now)?;
+    }
+
+    for maker_key in market_maker_protection_makers {
+        let mut maker = users.get_ref_mut(&maker_key)?;
+        cancel_orders(
+            &mut maker,
+            &maker_key,
+            Some(&filler_key),
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            OrderActionExplanation::MarketMakerProtectionTriggered,
+            Some(MarketType::Perp),
+            Some(market_index),
+            None,
+        )?;
+    }
+
+    for (user_key, base_asset_amount_filled) in fills {
+        if base_asset_amount_filled == 0 {
+            continue;
+        }
+
+        let user = users.get_ref(&user_key)?;
+
+        let margin_type =
+            select_margin_type_for_perp_maker(&user, base_asset_amount_filled, market_index)?;
+
+        let margin_calculation =
+            calculate_margin_requirement_and_total_collateral_and_liability_info(
+                &user,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                MarginContext::standard(margin_type),
+            )?;
+
+        if !margin_calculation.meets_market_margin_requirement(MarketIdentifier::perp(market_index))
+        {
+            msg!(
+                "user ({}) breached fill requirements (margin requirement {}) (total_collateral {})",
+                user_key,
+                margin_calculation.margin_requirement,
+                margin_calculation.total_collateral
+            );
+            return Err(ErrorCode::InsufficientCollateral);
+        }
+    }
+
+    Ok(())
+}
+
+fn fill_batch_auction_order_with_match(
+    market_index: u16,
+    taker_order: &BatchAuctionOrder,
+    maker_order: &BatchAuctionOrder,
+    clearing_price: u64,
+    users: &UserMap,
+    users_stats: &UserStatsMap,
+    filler: &mut Option<&mut User>,
+    filler_stats: &mut Option<&mut UserStats>,
+    filler_key: &Pubkey,
+    perp_market_map: &PerpMarketMap,
+    oracle_map: &mut OracleMap,
+    fee_structure: &FeeStructure,
+    reserve_price_before: u64,
+    oracle_price: i64,
+    maker_is_taker: bool,
+    market_maker_protection_makers: &mut Vec<Pubkey>,
+    now: i64,
+    slot: u64,
+) -> DriftResult<u64> {
+    let mut market = perp_market_map.get_ref_mut(&market_index)?;
+    let mut taker = users.get_ref_mut(&taker_order.user_key)?;
+    let mut maker = users.get_ref_mut(&maker_order.user_key)?;
+
+    // market maker protection only applies to resting maker orders, not opposing takers
+    let mut maker_market_maker_protection = if maker_is_taker {
+        None
+    } else {
+        users.get_market_maker_protection_mut(&maker_order.user_key)?
+    };
+
+    if !maker_is_taker && maker.has_market_maker_protection {
+        match maker_market_maker_protection.as_deref() {
+            None => {
+                msg!(
+                    "market maker protection not provided for maker {}",
+                    maker_order.user_key
+                );
+                return Ok(0);
+            }
+            // orders posted after protection triggered are pulled until the maker resets
+            Some(market_maker_protection) if market_maker_protection.is_triggered(market_index) => {
+                if !market_maker_protection_makers.contains(&maker_order.user_key) {
+                    market_maker_protection_makers.push(maker_order.user_key);
+                }
+                return Ok(0);
+            }
+            Some(_) => {}
+        }
+    }
+
+    let mut taker_stats = users_stats.get_ref_mut(&taker.authority)?;
+    let mut maker_stats = if maker.authority == taker.authority {
+        None
+    } else {
+        Some(users_stats.get_ref_mut(&maker.authority)?)
+    };
+
+    let taker_direction = taker.orders[taker_order.order_index].direction;
+
+    let (base_asset_amount, quote_asset_amount, maker_base_asset_amount) =
+        fulfill_perp_order_with_match(
+            market.deref_mut(),
+            &mut taker,
+            &mut taker_stats,
+            taker_order.order_index,
+            &taker_order.user_key,
+            &mut maker,
+            &mut maker_stats.as_deref_mut(),
+            maker_order.order_index,
+            &maker_order.user_key,
+            filler,
+            filler_stats,
+            filler_key,
+            &mut None,
+            &mut None,
+            reserve_price_before,
+            Some(oracle_price),
+            Some(clearing_price),
+            now,
+            slot,
+            fee_structure,
+            oracle_map,
+            None,
+            Some(clearing_price),
+            maker_is_taker,
+        )?;
+
+    if maker_base_asset_amount != 0 {
+        if let Some(market_maker_protection) = maker_market_maker_protection.as_deref_mut() {
+            let market_maker_protection_triggered = market_maker_protection.update_market(
+                market_index,
+                maker_base_asset_amount,
+                taker_direction.opposite(),
+                slot,
+            )?;
+
+            if market_maker_protection_triggered
+                && !market_maker_protection_makers.contains(&maker_order.user_key)
+            {
+                msg!(
+                    "market maker protection triggered for maker {} in market {}",
+                    maker_order.user_key,
+                    market_index
+                );
+                market_maker_protection_makers.push(maker_order.user_key);
+            }
+        }
+    }
+
+    market
+        .amm
+        .update_volume_24h(quote_asset_amount, taker_direction, now)?;
+
+    Ok(base_asset_amount)
+}
+
+fn fill_batch_auction_order_with_amm(
+    market_index: u16,
+    taker_order: &BatchAuctionOrder,
+    clearing_price: u64,
+    users: &UserMap,
+    users_stats: &UserStatsMap,
+    filler: &mut Option<&mut User>,
+    filler_stats: &mut Option<&mut UserStats>,
+    filler_key: &Pubkey,
+    perp_market_map: &PerpMarketMap,
+    oracle_map: &mut OracleMap,
+    fee_structure: &FeeStructure,
+    reserve_price_before: u64,
+    now: i64,
+    slot: u64,
+) -> DriftResult<u64> {
+    let mut market = perp_market_map.get_ref_mut(&market_index)?;
+    let mut user = users.get_ref_mut(&taker_order.user_key)?;
+    let mut user_stats = users_stats.get_ref_mut(&user.authority)?;
+
+    let existing_base_asset_amount = user.get_perp_position(market_index)?.base_asset_amount;
+    let fee_tier = determine_user_fee_tier(&user_stats, fee_structure, &MarketType::Perp)?;
+    let (base_asset_amount, _) = calculate_base_asset_amount_for_amm_to_fulfill(
+        &user.orders[taker_order.order_index],
+        &market,
+        Some(clearing_price),
+        Some(clearing_price),
+        existing_base_asset_amount,
+        fee_tier,
+    )?;
+
+    if base_asset_amount == 0 {
+        return Ok(0);
+    }
+
+    let taker_direction = user.orders[taker_order.order_index].direction;
+
+    let (base_asset_amount, quote_asset_amount) = fulfill_perp_order_with_amm(
+        &mut user,
+        &mut user_stats,
+        taker_order.order_index,
+        market.deref_mut(),
+        oracle_map,
+        reserve_price_before,
+        now,
+        slot,
+        &taker_order.user_key,
+        filler_key,
+        filler,
+        filler_stats,
+        &mut None,
+        &mut None,
+        fee_structure,
+        Some(clearing_price),
+        Some(base_asset_amount),
+        Some(clearing_price),
+        AMMLiquiditySplit::Shared,
+    )?;
+
+    market
+        .amm
+        .update_volume_24h(quote_asset_amount, taker_direction, now)?;
+
+    Ok(base_asset_amount)
+}
+
+/// Places and takes each leg of a combo order as an immediate or cancel limit order. Margin isn't
+/// checked until every leg is filled, so one leg can offset the risk another adds
+pub fn place_and_take_combo_order<'a>(
+    state: &State,
+    user: &AccountLoader<User>,
+    user_stats: &AccountLoader<UserStats>,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    makers_and_referrer: &UserMap,
+    makers_and_referrer_stats: &UserStatsMap,
+    spot_vault_accounts: &[AccountInfo<'a>],
+    clock: &Clock,
+    params: &ComboOrderParams,
+) -> DriftResult {
+    params.validate()?;
+
+    let user_key = user.key();
+    let mut spot_leg_count: usize = 0;
+    let mut risk_increasing = false;
+    let mut leg_markets = Vec::with_capacity(params.legs.len());
+
+    for leg_index in 0..params.legs.len() {
+        let leg_params = params.get_leg_order_params(leg_index)?;
+        let market_index = leg_params.market_index;
+        let market_type = leg_params.market_type;
+        leg_markets.push(MarketIdentifier {
+            market_type,
+            market_index,
+        });
+
+        let options = PlaceOrderOptions {
+            enforce_margin_check: false,
+            ..PlaceOrderOptions::default()
+        };
+
+        let leg_risk_increasing = match market_type {
+            MarketType::Perp => {
+                controller::repeg::update_amm(
+                    market_index,
+                    perp_market_map,
+                    oracle_map,
+                    state,
+                    clock,
+                )?;
+
+                place_perp_order(
+                    state,
+                    &mut load_mut!(user)?,
+                    user_key,
+                    perp_market_map,
+                    spot_market_map,
+                    oracle_map,
+                    clock,
+                    leg_params,
+                    options,
+                )?
+            }
+            MarketType::Spot => place_spot_order(
+                state,
+                &mut load_mut!(user)?,
+                user_key,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                clock,
+                leg_params,
+                options,
+            )?,
+        };
+        risk_increasing = risk_increasing || leg_risk_increasing;
+
+        let order_id = load!(user)?.get_last_order_id();
+        let order_base_asset_amount = load!(user)?
+            .get_order(order_id)
+            .map_or(0, |order| order.base_asset_amount);
+
+        let base_asset_amount_filled = match market_type {
+            MarketType::Perp => fill_perp_order(
+                order_id,
+                state,
+                user,
+                user_stats,
+                spot_market_map,
+                perp_market_map,
+                oracle_map,
+                user,
+                user_stats,
+                makers_and_referrer,
+                makers_and_referrer_stats,
+                None,
+                clock,
+                FillMode::PlaceAndTakeCombo,
+            )?,
+            MarketType::Spot => {
+                let vault_accounts = spot_vault_accounts
+                    .get(spot_leg_count * 2..spot_leg_count * 2 + 2)
+                    .ok_or(ErrorCode::InvalidFulfillmentConfig)?;
+                spot_leg_count = spot_leg_count.safe_add(1)?;
+
+                let mut fulfillment_params = {
+                    let base_market = spot_market_map.get_ref(&market_index)?;
+                    let quote_market = spot_market_map.get_quote_spot_market()?;
+                    MatchFulfillmentParams::new(
+                        &mut vault_accounts.iter().peekable(),
+                        &base_market,
+                        &quote_market,
+                    )?
+                };
+
+                let base_asset_amount_filled = fill_spot_order(
+                    order_id,
+                    state,
+                    user,
+                    user_stats,
+                    spot_market_map,
+                    perp_market_map,
+                    oracle_map,
+                    user,
+                    user_stats,
+                    makers_and_referrer,
+                    makers_and_referrer_stats,
+                    None,
+                    clock,
+                    &mut fulfillment_params,
+                    FillMode::PlaceAndTakeCombo,
+                )?;
+
+                let base_market = spot_market_map.get_ref(&market_index)?;
+                let quote_market = spot_market_map.get_quote_spot_market()?;
+                fulfillment_params.validate_vault_amounts(&base_market, &quote_market)?;
+
+                base_asset_amount_filled
+            }
+        };
+
+        let order_exists = load!(user)?
+            .orders
+            .iter()
+            .any(|order| order.order_id == order_id);
+
+        // the leg's order is immediate or cancel, so it must be gone and completely filled
+        if order_exists || base_asset_amount_filled < order_base_asset_amount {
+            msg!(
+                "combo leg {} (order {}) was not completely filled",
+                leg_index,
+                order_id
+            );
+            return Err(print_error!(ErrorCode::ComboOrderNotFilled)());
+        }
+    }
+
+    meets_place_order_margin_requirement(
+        &load!(user)?,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        risk_increasing,
+        &leg_markets,
+    )
+}
+
+/// Settles an accepted rfq quote as a single maker/taker fill at the quoted price.
+/// Orders are placed for both sides and whatever can't be filled is cancelled. The taker passes the
+/// price they accepted so a maker re-quoting before the accept lands can't change it
+pub fn fill_rfq_quote(
+    state: &State,
+    rfq: &Rfq,
+    expected_price: u64,
+    taker: &mut User,
+    taker_key: &Pubkey,
+    taker_stats: &mut UserStats,
+    maker: &mut User,
+    maker_key: &Pubkey,
+    maker_stats: &mut UserStats,
+    mut maker_market_maker_protection: Option<&mut UserMarketMakerProtection>,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    clock: &Clock,
+) -> DriftResult {
+    let now = clock.unix_timestamp;
+    let slot = clock.slot;
+    let market_index = rfq.market_index;
+
+    let quote = rfq.get_quote(maker_key, now)?;
+
+    validate!(
+        quote.price == expected_price,
+        ErrorCode::InvalidRfq,
+        "rfq quote price {} != expected price {}",
+        quote.price,
+        expected_price
+    )?;
+
+    validate!(
+        taker.authority != maker.authority,
+        ErrorCode::InvalidRfq,
+        "taker and maker authority must be different"
+    )?;
+
+    {
+        let market = perp_market_map.get_ref(&market_index)?;
+        let oracle_price = oracle_map.get_price_data(&market.amm.oracle)?.price;
+
+        validate!(
+            market.is_orderbook_only()
+                || !limit_price_breaches_maker_oracle_price_bands(
+                    quote.price,
+                    rfq.direction.opposite(),
+                    oracle_price,
+                    market.margin_ratio_initial,
+                )?,
+            ErrorCode::PriceBandsBreached,
+            "rfq quote price {} breaches oracle price bands",
+            quote.price
+        )?;
+    }
+
+    // the maker's quote is pulled like its resting orders once its protection is triggered
+    validate_market_maker_protection_not_triggered(
+        maker,
+        maker_key,
+        maker_market_maker_protection.as_deref(),
+        market_index,
+    )?;
+
+    let order_params = OrderParams {
+        order_type: OrderType::Limit,
+        market_type: MarketType::Perp,
+        direction: rfq.direction,
+        base_asset_amount: rfq.base_asset_amount,
+        price: quote.price,
+        market_index,
+        ..OrderParams::default()
+    };
+
+    place_perp_order(
+        state,
+        taker,
+        *taker_key,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        clock,
+        order_params,
+        // margin is checked once the fill is done
+        PlaceOrderOptions {
+            enforce_margin_check: false,
+            ..PlaceOrderOptions::default()
+        },
+    )?;
+    let taker_order_id = taker.get_last_order_id();
+    let taker_order_index = taker.get_order_index(taker_order_id)?;
+
+    place_perp_order(
+        state,
+        maker,
+        *maker_key,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        clock,
+        OrderParams {
+            direction: rfq.direction.opposite(),
+            ..order_params
+        },
+        PlaceOrderOptions {
+            enforce_margin_check: false,
+            ..PlaceOrderOptions::default()
+        },
+    )?;
+    let maker_order_id = maker.get_last_order_id();
+    let maker_order_index = maker.get_order_index(maker_order_id)?;
+
+    let base_asset_amount = {
+        let mut market = perp_market_map.get_ref_mut(&market_index)?;
+        let reserve_price_before = market.amm.reserve_price()?;
+        let oracle_price = oracle_map.get_price_data(&market.amm.oracle)?.price;
+
+        let (base_asset_amount, quote_asset_amount, _) = fulfill_perp_order_with_match(
+            market.deref_mut(),
+            taker,
+            taker_stats,
+            taker_order_index,
+            taker_key,
+            maker,
+            &mut Some(maker_stats),
+            maker_order_index,
+            maker_key,
+            &mut None,
+            &mut None,
+            taker_key,
+            &mut None,
+            &mut None,
+            reserve_price_before,
+            Some(oracle_price),
+            Some(quote.price),
+            now,
+            slot,
+            &state.perp_fee_structure,
+            oracle_map,
+            None,
+            Some(quote.price),
+            false,
+        )?;
+
+        market
+            .amm
+            .update_volume_24h(quote_asset_amount, rfq.direction, now)?;
+
+        if base_asset_amount > 0 {
+            market.update_last_fill_price(quote.price, now)?;
+        }
+
+        base_asset_amount
+    };
+
+    validate!(
+        base_asset_amount > 0,
+        ErrorCode::InvalidRfq,
+        "rfq quote could not be filled"
+    )?;
+
+    for (user, user_key, order_id) in [
+        (&mut *taker, taker_key, taker_order_id),
+        (&mut *maker, maker_key, maker_order_id),
+    ] {
+        if let Ok(order_index) = user.get_order_index(order_id) {
+            cancel_order(
+                order_index,
+                user,
+                user_key,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                now,
+                slot,
+                OrderActionExplanation::None,
+                None,
+                0,
+                false,
+            )?;
+        }
+    }
+
+    if let Some(market_maker_protection) = maker_market_maker_protection.as_deref_mut() {
+        let market_maker_protection_triggered = market_maker_protection.update_market(
+            market_index,
+            base_asset_amount,
+            rfq.direction.opposite(),
+            slot,
+        )?;
+
+        if market_maker_protection_triggered {
+            msg!(
+                "market maker protection triggered for maker {} in market {}",
+                maker_key,
+                market_index
+            );
+
+            cancel_orders(
+                maker,
+                maker_key,
+                None,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                now,
+                slot,
+                OrderActionExplanation::MarketMakerProtectionTriggered,
+                Some(MarketType::Perp),
+                Some(market_index),
+                None,
+            )?;
+        }
+    }
+
+    for (user, user_key, direction) in [
+        (&*taker, taker_key, rfq.direction),
+        (&*maker, maker_key, rfq.direction.opposite()),
+    ] {
+        let base_asset_amount_filled = match direction {
+            PositionDirection::Long => base_asset_amount.cast::<i64>()?,
+            PositionDirection::Short => -base_asset_amount.cast::<i64>()?,
+        };
+
+        let margin_type =
+            select_margin_type_for_perp_maker(user, base_asset_amount_filled, market_index)?;
+
+        let margin_calculation =
+            calculate_margin_requirement_and_total_collateral_and_liability_info(
+                user,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                MarginContext::standard(margin_type),
+            )?;
+
+        if !margin_calculation.meets_market_margin_requirement(MarketIdentifier::perp(market_index))
+        {
+            msg!(
+                "user ({}) breached fill requirements (margin requirement {}) (total_collateral {})",
+                user_key,
+                margin_calculation.margin_requirement,
+                margin_calculation.total_collateral
+            );
+            return Err(ErrorCode::InsufficientCollateral);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn trigger_order(
+    order_id: u32,
+    state: &State,
+    user: &AccountLoader<User>,
+    spot_market_map: &SpotMarketMap,
+    perp_market_map: &PerpMarketMap,
+    oracle_map: &mut OracleMap,
+    filler: &AccountLoader<User>,
+    clock: &Clock,
+) -> DriftResult {
+    let now = clock.unix_timestamp;
+    let slot = clock.slot;
+
+    let filler_key = filler.key();
+    let user_key = user.key();
+    let user = &mut load_mut!(user)?;
+
+    let order_index = user
+        .orders
+        .iter()
+        .position(|order| order.order_id == order_id)
+        .ok_or_else(print_error!(ErrorCode::OrderDoesNotExist))?;
+
+    let (order_status, market_index, market_type) =
+        get_struct_values!(user.orders[order_index], status, market_index, market_type);
+
+    validate!(
+        order_status == OrderStatus::Open,
+        ErrorCode::OrderNotOpen,
+        "Order not open"
+    )?;
+
+    validate!(
+        user.orders[order_index].must_be_triggered(),
+        ErrorCode::OrderNotTriggerable,
+        "Order is not triggerable"
+    )?;
+
+    validate!(
+        !user.orders[order_index].triggered(),
+        ErrorCode::OrderNotTriggerable,
+        "Order is already triggered"
+    )?;
+
+    validate!(
+        market_type == MarketType::Perp,
+        ErrorCode::InvalidOrderMarketType,
+        "Order must be a perp order"
+    )?;
+
+    validate_user_not_being_liquidated(
+        user,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        state.liquidation_margin_buffer_ratio,
+    )?;
+
+    validate!(!user.is_bankrupt(), ErrorCode::UserBankrupt)?;
+
+    let cross_market_trigger_reference_price = if user.orders[order_index].is_cross_market_trigger()
+    {
+        Some(get_cross_market_trigger_reference_price(
+            &user.orders[order_index],
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+        )?)
+    } else {
+        None
+    };
+
+    let mut perp_market = perp_market_map.get_ref_mut(&market_index)?;
+    let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
+        MarketType::Perp,
+        perp_market.market_index,
+        &perp_market.amm.oracle,
+        perp_market
+            .amm
+            .historical_oracle_data
+            .last_oracle_price_twap,
+        perp_market.get_max_confidence_interval_multiplier()?,
+    )?;
+
+    let is_oracle_valid =
         is_oracle_valid_for_action(oracle_validity, Some(DriftAction::TriggerOrder))?;
 
     validate!(is_oracle_valid, ErrorCode::InvalidOracle)?;
 
     let oracle_price = oracle_price_data.price;
 
+    let trigger_reference_price = match cross_market_trigger_reference_price {
+        Some(trigger_reference_price) => trigger_reference_price,
+        None => {
+            get_perp_trigger_reference_price(&user.orders[order_index], oracle_price, &perp_market)?
+        }
+    };
+
+    let trigger_price_before = user.orders[order_index].trigger_price;
+    let is_trailing_stop = user.orders[order_index].is_trailing_stop();
+    let trigger_price_updated = is_trailing_stop
+        && update_trailing_stop_trigger_price(
+            &mut user.orders[order_index],
+            trigger_reference_price,
+            perp_market.amm.order_tick_size,
+        )?;
+
     let can_trigger = order_satisfies_trigger_condition(
         &user.orders[order_index],
-        oracle_price.unsigned_abs().cast()?,
+        trigger_reference_price.unsigned_abs().cast()?,
     )?;
+
+    if trigger_price_updated && !can_trigger {
+        // keepers are paid for ratcheting trailing stops so the trigger price keeps up with the oracle.
+        // small updates are persisted without a reward so cranking every tick can't drain the user
+        let filler_reward = if user_key != filler_key
+            && is_trailing_stop_update_paid(
+                trigger_price_before,
+                user.orders[order_index].trigger_price,
+                oracle_price,
+            )? {
+            let mut filler = load_mut!(filler)?;
+            pay_keeper_flat_reward_for_perps(
+                user,
+                Some(&mut filler),
+                &mut perp_market,
+                state.perp_fee_structure.flat_filler_fee,
+                slot,
+            )?
+        } else {
+            0
+        };
+
+        let order_action_record = get_order_action_record(
+            now,
+            OrderAction::Trigger,
+            OrderActionExplanation::TrailingStopTriggerPriceUpdated,
+            market_index,
+            Some(filler_key),
+            None,
+            Some(filler_reward),
+            None,
+            None,
+            Some(filler_reward),
+            None,
+            None,
+            None,
+            None,
+            Some(user_key),
+            Some(user.orders[order_index]),
+            None,
+            None,
+            oracle_price,
+        )?;
+        emit!(order_action_record);
+
+        return Ok(());
+    }
+
     validate!(can_trigger, ErrorCode::OrderDidNotSatisfyTriggerCondition)?;
 
     let worst_case_base_asset_amount_before = user
@@ -2672,8 +4995,16 @@ pub fn trigger_order(
 
     // If order increases risk and user is below initial margin, cancel it
     if is_risk_increasing && !user.orders[order_index].reduce_only {
-        let meets_initial_margin_requirement =
-            meets_initial_margin_requirement(user, perp_market_map, spot_market_map, oracle_map)?;
+        let meets_initial_margin_requirement = meets_initial_margin_requirement(
+            user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            MarketIdentifier {
+                market_type,
+                market_index,
+            },
+        )?;
 
         if !meets_initial_margin_requirement {
             cancel_order(
@@ -2693,6 +5024,21 @@ pub fn trigger_order(
         }
     }
 
+    // if the order was canceled above, the linked order group is reset and nothing is canceled
+    let linked_order_group = user.orders[order_index].get_linked_order_group_to_cancel();
+    cancel_linked_orders(
+        user,
+        &user_key,
+        order_id,
+        linked_order_group,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        now,
+        slot,
+        Some(&filler_key),
+    )?;
+
     user.update_last_active_slot(slot);
 
     Ok(())
@@ -2715,6 +5061,15 @@ fn update_trigger_order_params(
 
     order.slot = slot;
 
+    if order.has_oracle_price_offset() {
+        // trailing offset no longer needed once triggered and would otherwise be used as limit price offset
+        order.oracle_price_offset = 0;
+        order.bit_flags &= !(OrderBitFlag::TrailingStopPercentageOffset as u8);
+    }
+
+    // reference market no longer needed once triggered and auction params are overwritten below
+    order.bit_flags &= !(OrderBitFlag::CrossMarketTrigger as u8);
+
     let (auction_duration, auction_start_price, auction_end_price) =
         calculate_auction_params_for_trigger_order(
             order,
@@ -2737,6 +5092,27 @@ fn update_trigger_order_params(
     Ok(())
 }
 
+fn update_trailing_stop_trigger_price(
+    order: &mut Order,
+    oracle_price: i64,
+    tick_size: u64,
+) -> DriftResult<bool> {
+    let trigger_price = calculate_trailing_stop_trigger_price(order, oracle_price, tick_size)?;
+
+    if trigger_price == order.trigger_price {
+        return Ok(false);
+    }
+
+    msg!(
+        "updating trailing stop trigger price {} -> {}",
+        order.trigger_price,
+        trigger_price
+    );
+    order.trigger_price = trigger_price;
+
+    Ok(true)
+}
+
 pub fn force_cancel_orders(
     state: &State,
     user_account_loader: &AccountLoader<User>,
@@ -2786,6 +5162,14 @@ pub fn force_cancel_orders(
         let market_index = user.orders[order_index].market_index;
         let market_type = user.orders[order_index].market_type;
 
+        // orders backed by collateral that still meets its requirement are left alone
+        if margin_calc.meets_market_margin_requirement(MarketIdentifier {
+            market_type,
+            market_index,
+        }) {
+            continue;
+        }
+
         let fee = match market_type {
             MarketType::Spot => {
                 let spot_market = spot_market_map.get_ref(&market_index)?;
@@ -2851,6 +5235,62 @@ pub fn force_cancel_orders(
     Ok(())
 }
 
+/// Cancels all of a user's open orders once their dead man's switch deadline has passed
+pub fn cancel_orders_after_deadline(
+    state: &State,
+    user_account_loader: &AccountLoader<User>,
+    spot_market_map: &SpotMarketMap,
+    perp_market_map: &PerpMarketMap,
+    oracle_map: &mut OracleMap,
+    filler: &AccountLoader<User>,
+    clock: &Clock,
+) -> DriftResult {
+    let now = clock.unix_timestamp;
+    let slot = clock.slot;
+
+    let filler_key = filler.key();
+    let user_key = user_account_loader.key();
+    let user = &mut load_mut!(user_account_loader)?;
+    let filler = &mut load_mut!(filler)?;
+
+    validate!(
+        user.can_cancel_orders_after_deadline(now),
+        ErrorCode::CancelOrdersDeadlineNotReached,
+        "cancel_orders_after_ts ({}) not reached, now = {}",
+        user.cancel_orders_after_ts,
+        now
+    )?;
+
+    let canceled_order_ids = cancel_orders(
+        user,
+        &user_key,
+        Some(&filler_key),
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        now,
+        slot,
+        OrderActionExplanation::CancelOrdersAfterDeadline,
+        None,
+        None,
+        None,
+    )?;
+
+    user.cancel_orders_after_ts = 0;
+
+    if !canceled_order_ids.is_empty() {
+        pay_keeper_flat_reward_for_spot(
+            user,
+            Some(filler),
+            spot_market_map.get_quote_spot_market_mut()?.deref_mut(),
+            state.spot_fee_structure.flat_filler_fee,
+            slot,
+        )?;
+    }
+
+    Ok(())
+}
+
 pub fn can_reward_user_with_perp_pnl(user: &mut Option<&mut User>, market_index: u16) -> bool {
     match user.as_mut() {
         Some(user) => user.force_get_perp_position_mut(market_index).is_ok(),
@@ -2912,7 +5352,8 @@ pub fn burn_user_lp_shares_for_risk_reduction(
     let quote_oracle = spot_market_map
         .get_ref(&market.quote_spot_market_index)?
         .oracle;
-    let quote_oracle_price = oracle_map.get_price_data(&quote_oracle)?.price;
+    let quote_oracle_price =
+        market.get_quote_asset_price(oracle_map.get_price_data(&quote_oracle)?.price);
 
     let oracle_price_data = oracle_map.get_price_data(&market.amm.oracle)?;
 
@@ -3052,6 +5493,7 @@ pub fn pay_keeper_flat_reward_for_spot(
     Ok(filler_reward)
 }
 
+/// Returns whether the placed order is risk increasing
 pub fn place_spot_order(
     state: &State,
     user: &mut User,
@@ -3060,9 +5502,9 @@ pub fn place_spot_order(
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
     clock: &Clock,
-    params: OrderParams,
+    mut params: OrderParams,
     mut options: PlaceOrderOptions,
-) -> DriftResult {
+) -> DriftResult<bool> {
     let now = clock.unix_timestamp;
     let slot = clock.slot;
 
@@ -3096,6 +5538,12 @@ pub fn place_spot_order(
         )?;
     }
 
+    validate!(
+        !params.is_good_til_slot() || params.max_ts.is_some(),
+        ErrorCode::InvalidOrder,
+        "good til slot order must set max_ts to its last slot"
+    )?;
+
     let max_ts = match params.max_ts {
         Some(max_ts) => max_ts,
         None => match params.order_type {
@@ -3104,9 +5552,12 @@ pub fn place_spot_order(
         },
     };
 
-    if max_ts != 0 && max_ts < now {
+    if max_ts != 0 && params.is_good_til_slot() && max_ts < slot.cast()? {
+        msg!("max_ts ({}) < slot ({}), skipping order", max_ts, slot);
+        return Ok(false);
+    } else if max_ts != 0 && !params.is_good_til_slot() && max_ts < now {
         msg!("max_ts ({}) < now ({}), skipping order", max_ts, now);
-        return Ok(());
+        return Ok(false);
     }
 
     let new_order_index = user
@@ -3148,6 +5599,18 @@ pub fn place_spot_order(
 
     let oracle_price_data = *oracle_map.get_price_data(&spot_market.oracle)?;
 
+    // quote sized orders are converted to base here and keep their quote asset amount (see Order::set_quote_size)
+    let quote_asset_amount = if params.is_quote_sized() {
+        let quote_asset_amount = params.base_asset_amount;
+        params.base_asset_amount = params.get_base_asset_amount_for_quote_size(
+            oracle_price_data.price,
+            spot_market.get_precision(),
+        )?;
+        Some(quote_asset_amount)
+    } else {
+        None
+    };
+
     // Increment open orders for existing position
     let (existing_position_direction, order_base_asset_amount) = {
         validate!(
@@ -3211,7 +5674,9 @@ pub fn place_spot_order(
         "must be spot order"
     )?;
 
-    let new_order = Order {
+    validate_order_params_bit_flags(params.bit_flags)?;
+
+    let mut new_order = Order {
         status: OrderStatus::Open,
         order_type: params.order_type,
         market_type: params.market_type,
@@ -3239,9 +5704,54 @@ pub fn place_spot_order(
         auction_end_price,
         auction_duration,
         max_ts,
-        padding: [0; 3],
+        bit_flags: params.bit_flags & OrderBitFlag::USER_SETTABLE,
+        linked_order_group: params.linked_order_group,
+        trigger_reference_price: params.trigger_reference_price.unwrap_or_default(),
     };
 
+    new_order.set_auction_curve(params.auction_curve.unwrap_or_default());
+
+    if let Some(quote_asset_amount) = quote_asset_amount {
+        new_order.set_quote_size(quote_asset_amount);
+    }
+
+    if params.is_linked_order_entry() {
+        new_order.set_linked_order_entry();
+    }
+
+    if let Some(trigger_market_params) = params.trigger_market_params {
+        validate!(
+            trigger_market_params.market_type != MarketType::Spot
+                || trigger_market_params.market_index != market_index,
+            ErrorCode::InvalidOrder,
+            "cross market trigger must reference a different market"
+        )?;
+
+        new_order.set_trigger_market(
+            trigger_market_params.market_type,
+            trigger_market_params.market_index,
+        );
+
+        if let Some(trigger_price_change) = trigger_market_params.trigger_price_change {
+            validate!(
+                params.trigger_price.is_none(),
+                ErrorCode::InvalidOrderTrigger,
+                "cross market trigger can't set both a trigger price and a trigger price change"
+            )?;
+
+            let reference_price = get_cross_market_trigger_reference_price(
+                &new_order,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+            )?;
+            let (trigger_price, trigger_condition) =
+                calculate_trigger_price_for_price_change(reference_price, trigger_price_change)?;
+            new_order.trigger_price = trigger_price;
+            new_order.trigger_condition = trigger_condition;
+        }
+    }
+
     validate_spot_order(
         &new_order,
         spot_market.order_step_size,
@@ -3275,6 +5785,7 @@ pub fn place_spot_order(
             spot_market_map,
             oracle_map,
             options.risk_increasing,
+            &[MarketIdentifier::spot(market_index)],
         )?;
     }
 
@@ -3322,7 +5833,7 @@ pub fn place_spot_order(
 
     user.update_last_active_slot(slot);
 
-    Ok(())
+    Ok(risk_increasing)
 }
 
 pub fn fill_spot_order(
@@ -3340,6 +5851,7 @@ pub fn fill_spot_order(
     jit_maker_order_id: Option<u32>,
     clock: &Clock,
     fulfillment_params: &mut dyn SpotFulfillmentParams,
+    fill_mode: FillMode,
 ) -> DriftResult<u64> {
     let now = clock.unix_timestamp;
     let slot = clock.slot;
@@ -3425,13 +5937,15 @@ pub fn fill_spot_order(
     let oracle_price = oracle_map
         .get_price_data(&spot_market_map.get_ref_mut(&order_market_index)?.oracle)?
         .price;
-    let maker_order_info = get_spot_maker_orders_info(
+    let (maker_order_info, taker_self_trade_base_asset_amount) = get_spot_maker_orders_info(
         perp_market_map,
         spot_market_map,
         oracle_map,
         makers_and_referrer,
         &user_key,
+        &user.authority,
         &user.orders[order_index],
+        user_stats.self_trade_prevention_mode,
         &mut filler.as_deref_mut(),
         &filler_key,
         state.spot_fee_structure.flat_filler_fee,
@@ -3441,6 +5955,23 @@ pub fn fill_spot_order(
         slot,
     )?;
 
+    if taker_self_trade_base_asset_amount > 0
+        && handle_taker_self_trade_prevention(
+            user,
+            &user_key,
+            order_index,
+            taker_self_trade_base_asset_amount,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            now,
+            slot,
+            &filler_key,
+        )?
+    {
+        return Ok(0);
+    }
+
     {
         let mut quote_market = spot_market_map.get_quote_spot_market_mut()?;
         let oracle_price_data = oracle_map.get_price_data(&quote_market.oracle)?;
@@ -3471,7 +6002,7 @@ pub fn fill_spot_order(
         }
     }
 
-    let should_expire_order = should_expire_order_before_fill(user, order_index, now)?;
+    let should_expire_order = should_expire_order_before_fill(user, order_index, now, slot)?;
 
     let should_cancel_reduce_only = if user.orders[order_index].reduce_only {
         let market_index = user.orders[order_index].market_index;
@@ -3523,6 +6054,8 @@ pub fn fill_spot_order(
         return Ok(0);
     }
 
+    let linked_order_group = user.orders[order_index].get_linked_order_group_to_cancel();
+
     let (base_asset_amount, quote_asset_amount) = fulfill_spot_order(
         user,
         order_index,
@@ -3541,6 +6074,7 @@ pub fn fill_spot_order(
         slot,
         &state.spot_fee_structure,
         fulfillment_params,
+        fill_mode,
     )?;
 
     if base_asset_amount != 0 {
@@ -3565,6 +6099,24 @@ pub fn fill_spot_order(
                 .oracle_guard_rails
                 .max_oracle_twap_5min_percent_divergence(),
         )?;
+        drop(spot_market);
+
+        // the order is removed from the user account once it's completely filled
+        let order_completely_filled = user.orders[order_index].order_id != order_id;
+        if order_completely_filled {
+            cancel_linked_orders(
+                user,
+                &user_key,
+                order_id,
+                linked_order_group,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                now,
+                slot,
+                Some(&filler_key),
+            )?;
+        }
     }
 
     let is_open = user.orders[order_index].status == OrderStatus::Open;
@@ -3645,7 +6197,9 @@ fn get_spot_maker_orders_info(
     oracle_map: &mut OracleMap,
     makers_and_referrer: &UserMap,
     taker_key: &Pubkey,
+    taker_authority: &Pubkey,
     taker_order: &Order,
+    self_trade_prevention_mode: SelfTradePreventionMode,
     filler: &mut Option<&mut User>,
     filler_key: &Pubkey,
     filler_reward: u64,
@@ -3653,11 +6207,22 @@ fn get_spot_maker_orders_info(
     jit_maker_order_id: Option<u32>,
     now: i64,
     slot: u64,
-) -> DriftResult<Vec<(Pubkey, usize, u64)>> {
+) -> DriftResult<(Vec<(Pubkey, usize, u64)>, u64)> {
     let maker_direction = taker_order.direction.opposite();
 
     let mut maker_orders_info = Vec::with_capacity(16);
 
+    let taker_price = taker_order.get_limit_price(
+        Some(oracle_price),
+        None,
+        slot,
+        spot_market_map
+            .get_ref(&taker_order.market_index)?
+            .order_tick_size,
+    )?;
+    let taker_base_asset_amount_unfilled = taker_order.get_base_asset_amount_unfilled(None)?;
+    let mut taker_base_asset_amount_decrement = 0_u64;
+
     for (maker_key, user_account_loader) in makers_and_referrer.0.iter() {
         if maker_key == taker_key {
             continue;
@@ -3724,7 +6289,7 @@ fn get_spot_maker_orders_info(
                 )?
             };
 
-            let should_expire_order = should_expire_order(&maker, maker_order_index, now)?;
+            let should_expire_order = should_expire_order(&maker, maker_order_index, now, slot)?;
 
             let should_cancel_reduce_only_order = should_cancel_reduce_only_order(
                 &maker.orders[maker_order_index],
@@ -3772,6 +6337,36 @@ fn get_spot_maker_orders_info(
                 continue;
             }
 
+            let is_self_trade = self_trade_prevention_mode != SelfTradePreventionMode::None
+                && maker.authority == *taker_authority
+                && taker_price.map_or(true, |taker_price| {
+                    do_orders_cross(maker_direction, maker_order_price, taker_price)
+                });
+
+            if is_self_trade {
+                let decrement = apply_self_trade_prevention(
+                    maker.deref_mut(),
+                    maker_key,
+                    maker_order_index,
+                    taker_base_asset_amount_unfilled.safe_sub(taker_base_asset_amount_decrement)?,
+                    self_trade_prevention_mode,
+                    perp_market_map,
+                    spot_market_map,
+                    oracle_map,
+                    now,
+                    slot,
+                    Some(filler_key),
+                )?;
+                taker_base_asset_amount_decrement =
+                    taker_base_asset_amount_decrement.safe_add(decrement)?;
+
+                if taker_base_asset_amount_decrement >= taker_base_asset_amount_unfilled {
+                    return Ok((maker_orders_info, taker_base_asset_amount_decrement));
+                }
+
+                continue;
+            }
+
             insert_maker_order_info(
                 &mut maker_orders_info,
                 (*maker_key, maker_order_index, maker_order_price),
@@ -3780,7 +6375,7 @@ fn get_spot_maker_orders_info(
         }
     }
 
-    Ok(maker_orders_info)
+    Ok((maker_orders_info, taker_base_asset_amount_decrement))
 }
 
 fn fulfill_spot_order(
@@ -3801,6 +6396,7 @@ fn fulfill_spot_order(
     slot: u64,
     fee_structure: &FeeStructure,
     fulfillment_params: &mut dyn SpotFulfillmentParams,
+    fill_mode: FillMode,
 ) -> DriftResult<(u64, u64)> {
     let base_market_index = user.orders[user_order_index].market_index;
     let order_direction = user.orders[user_order_index].direction;
@@ -3851,6 +6447,7 @@ fn fulfill_spot_order(
     let mut base_asset_amount = 0_u64;
     let mut quote_asset_amount = 0_u64;
     let mut maker_fills: BTreeMap<Pubkey, i64> = BTreeMap::new();
+    let mut linked_maker_orders: Vec<(Pubkey, u32, u8)> = vec![];
     let maker_direction = user.orders[user_order_index].direction.opposite();
     for fulfillment_method in fulfillment_methods.iter() {
         if user.orders[user_order_index].status != OrderStatus::Open {
@@ -3866,6 +6463,10 @@ fn fulfill_spot_order(
                     Some(makers_and_referrer_stats.get_ref_mut(&maker.authority)?)
                 };
 
+                let maker_order_id = maker.orders[*maker_order_index as usize].order_id;
+                let maker_linked_order_group =
+                    maker.orders[*maker_order_index as usize].get_linked_order_group_to_cancel();
+
                 let (base_filled, quote_filled) = fulfill_spot_order_with_match(
                     &mut base_market,
                     &mut quote_market,
@@ -3893,6 +6494,17 @@ fn fulfill_spot_order(
                         maker_direction,
                         base_filled,
                     )?;
+
+                    // the maker order is removed once it's completely filled
+                    let maker_order_completely_filled =
+                        maker.orders[*maker_order_index as usize].order_id != maker_order_id;
+                    if maker_linked_order_group != 0 && maker_order_completely_filled {
+                        linked_maker_orders.push((
+                            *maker_key,
+                            maker_order_id,
+                            maker_linked_order_group,
+                        ));
+                    }
                 }
 
                 (base_filled, quote_filled)
@@ -3983,22 +6595,40 @@ fn fulfill_spot_order(
     drop(base_market);
     drop(quote_market);
 
-    let taker_margin_calculation =
-        calculate_margin_requirement_and_total_collateral_and_liability_info(
-            user,
+    for (maker_key, maker_order_id, maker_linked_order_group) in linked_maker_orders {
+        let mut maker = makers_and_referrer.get_ref_mut(&maker_key)?;
+        cancel_linked_orders(
+            &mut maker,
+            &maker_key,
+            maker_order_id,
+            maker_linked_order_group,
             perp_market_map,
             spot_market_map,
             oracle_map,
-            MarginContext::standard(margin_type),
+            now,
+            slot,
+            Some(filler_key),
         )?;
+    }
 
-    if !taker_margin_calculation.meets_margin_requirement() {
-        msg!(
-            "taker breached maintenance requirements (margin requirement {}) (total_collateral {})",
-            taker_margin_calculation.margin_requirement,
-            taker_margin_calculation.total_collateral
-        );
-        return Err(ErrorCode::InsufficientCollateral);
+    if fill_mode != FillMode::PlaceAndTakeCombo {
+        let taker_margin_calculation =
+            calculate_margin_requirement_and_total_collateral_and_liability_info(
+                user,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
+                MarginContext::standard(margin_type),
+            )?;
+
+        if !taker_margin_calculation.meets_cross_margin_requirement() {
+            msg!(
+                "taker breached maintenance requirements (margin requirement {}) (total_collateral {})",
+                taker_margin_calculation.margin_requirement,
+                taker_margin_calculation.total_collateral
+            );
+            return Err(ErrorCode::InsufficientCollateral);
+        }
     }
 
     for (maker_key, _) in maker_fills {
@@ -4057,7 +6687,7 @@ fn fulfill_spot_order(
                 MarginContext::standard(margin_type),
             )?;
 
-        if !maker_margin_calculation.meets_margin_requirement() {
+        if !maker_margin_calculation.meets_cross_margin_requirement() {
             msg!(
                     "maker ({}) breached maintenance requirements (margin requirement {}) (total_collateral {})",
                     maker_key,
@@ -4271,10 +6901,11 @@ pub fn fulfill_spot_order_with_match(
 
     taker.update_cumulative_spot_fees(-taker_fee.cast()?)?;
 
-    update_order_after_fill(
+    let taker_base_asset_amount_resized = update_order_after_fill(
         &mut taker.orders[taker_order_index],
         base_asset_amount,
         quote_asset_amount,
+        base_market.order_step_size,
     )?;
 
     let taker_order_direction = taker.orders[taker_order_index].direction;
@@ -4284,6 +6915,12 @@ pub fn fulfill_spot_order_with_match(
         base_asset_amount,
     )?;
 
+    resize_spot_open_bids_and_asks(
+        &mut taker.spot_positions[taker_spot_position_index],
+        &taker_order_direction,
+        taker_base_asset_amount_resized,
+    )?;
+
     taker_stats.update_taker_volume_30d(quote_asset_amount, now)?;
 
     taker_stats.increment_total_fees(taker_fee)?;
@@ -4314,10 +6951,11 @@ pub fn fulfill_spot_order_with_match(
 
     maker.update_cumulative_spot_fees(maker_rebate.cast()?)?;
 
-    update_order_after_fill(
+    let maker_base_asset_amount_resized = update_order_after_fill(
         &mut maker.orders[maker_order_index],
         base_asset_amount,
         quote_asset_amount,
+        base_market.order_step_size,
     )?;
 
     let maker_order_direction = maker.orders[maker_order_index].direction;
@@ -4327,6 +6965,12 @@ pub fn fulfill_spot_order_with_match(
         base_asset_amount,
     )?;
 
+    resize_spot_open_bids_and_asks(
+        &mut maker.spot_positions[maker_spot_position_index],
+        &maker_order_direction,
+        maker_base_asset_amount_resized,
+    )?;
+
     if let Some(maker_stats) = maker_stats {
         maker_stats.update_maker_volume_30d(quote_asset_amount, now)?;
         maker_stats.increment_total_rebate(maker_rebate)?;
@@ -4582,10 +7226,11 @@ pub fn fulfill_spot_order_with_external_market(
 
     taker_stats.increment_total_fees(taker_fee.cast()?)?;
 
-    update_order_after_fill(
+    let taker_base_asset_amount_resized = update_order_after_fill(
         &mut taker.orders[taker_order_index],
         base_asset_amount_filled,
         quote_asset_amount_filled,
+        base_market.order_step_size,
     )?;
 
     let taker_order_direction = taker.orders[taker_order_index].direction;
@@ -4595,6 +7240,12 @@ pub fn fulfill_spot_order_with_external_market(
         base_asset_amount_filled,
     )?;
 
+    resize_spot_open_bids_and_asks(
+        taker.force_get_spot_position_mut(base_market.market_index)?,
+        &taker_order_direction,
+        taker_base_asset_amount_resized,
+    )?;
+
     if let (Some(filler), Some(filler_stats)) = (filler, filler_stats) {
         if filler_reward > 0 {
             update_spot_balances(
@@ -4663,6 +7314,60 @@ pub fn fulfill_spot_order_with_external_market(
     Ok((base_asset_amount_filled, quote_asset_amount_filled))
 }
 
+/// The reference market and its oracle must be passed in the remaining accounts by the keeper
+fn get_cross_market_trigger_reference_price(
+    order: &Order,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+) -> DriftResult<i64> {
+    let (trigger_market_type, trigger_market_index) = order.get_trigger_market()?;
+
+    match trigger_market_type {
+        MarketType::Perp => {
+            let perp_market = perp_market_map.get_ref(&trigger_market_index)?;
+            let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
+                MarketType::Perp,
+                perp_market.market_index,
+                &perp_market.amm.oracle,
+                perp_market
+                    .amm
+                    .historical_oracle_data
+                    .last_oracle_price_twap,
+                perp_market.get_max_confidence_interval_multiplier()?,
+            )?;
+
+            validate!(
+                is_oracle_valid_for_action(oracle_validity, Some(DriftAction::TriggerOrder))?,
+                ErrorCode::InvalidOracle,
+                "OracleValidity for trigger perp marketIndex={} invalid for TriggerOrder",
+                trigger_market_index
+            )?;
+
+            get_perp_trigger_reference_price(order, oracle_price_data.price, &perp_market)
+        }
+        MarketType::Spot => {
+            let spot_market = spot_market_map.get_ref(&trigger_market_index)?;
+            let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
+                MarketType::Spot,
+                spot_market.market_index,
+                &spot_market.oracle,
+                spot_market.historical_oracle_data.last_oracle_price_twap,
+                spot_market.get_max_confidence_interval_multiplier()?,
+            )?;
+
+            validate!(
+                is_oracle_valid_for_action(oracle_validity, Some(DriftAction::TriggerOrder))?,
+                ErrorCode::InvalidOracle,
+                "OracleValidity for trigger spot marketIndex={} invalid for TriggerOrder",
+                trigger_market_index
+            )?;
+
+            get_spot_trigger_reference_price(order, oracle_price_data.price, &spot_market)
+        }
+    }
+}
+
 pub fn trigger_spot_order(
     order_id: u32,
     state: &State,
@@ -4723,6 +7428,18 @@ pub fn trigger_spot_order(
 
     validate!(!user.is_bankrupt(), ErrorCode::UserBankrupt)?;
 
+    let cross_market_trigger_reference_price = if user.orders[order_index].is_cross_market_trigger()
+    {
+        Some(get_cross_market_trigger_reference_price(
+            &user.orders[order_index],
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+        )?)
+    } else {
+        None
+    };
+
     let spot_market = spot_market_map.get_ref(&market_index)?;
     let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
         MarketType::Spot,
@@ -4749,10 +7466,74 @@ pub fn trigger_spot_order(
 
     let oracle_price = oracle_price_data.price;
 
+    let trigger_reference_price = match cross_market_trigger_reference_price {
+        Some(trigger_reference_price) => trigger_reference_price,
+        None => {
+            get_spot_trigger_reference_price(&user.orders[order_index], oracle_price, &spot_market)?
+        }
+    };
+
+    let trigger_price_before = user.orders[order_index].trigger_price;
+    let is_trailing_stop = user.orders[order_index].is_trailing_stop();
+    let trigger_price_updated = is_trailing_stop
+        && update_trailing_stop_trigger_price(
+            &mut user.orders[order_index],
+            trigger_reference_price,
+            spot_market.order_tick_size,
+        )?;
+
     let can_trigger = order_satisfies_trigger_condition(
         &user.orders[order_index],
-        oracle_price.unsigned_abs().cast()?,
+        trigger_reference_price.unsigned_abs().cast()?,
     )?;
+
+    if trigger_price_updated && !can_trigger {
+        // keepers are paid for ratcheting trailing stops so the trigger price keeps up with the oracle.
+        // small updates are persisted without a reward so cranking every tick can't drain the user
+        let filler_reward = if user_key != filler_key
+            && is_trailing_stop_update_paid(
+                trigger_price_before,
+                user.orders[order_index].trigger_price,
+                oracle_price,
+            )? {
+            let mut filler = load_mut!(filler)?;
+            pay_keeper_flat_reward_for_spot(
+                user,
+                Some(&mut filler),
+                &mut spot_market_map.get_quote_spot_market_mut()?,
+                state.spot_fee_structure.flat_filler_fee,
+                slot,
+            )?
+        } else {
+            0
+        };
+
+        let order_action_record = get_order_action_record(
+            now,
+            OrderAction::Trigger,
+            OrderActionExplanation::TrailingStopTriggerPriceUpdated,
+            market_index,
+            Some(filler_key),
+            None,
+            Some(filler_reward),
+            None,
+            None,
+            Some(filler_reward),
+            None,
+            None,
+            None,
+            None,
+            Some(user_key),
+            Some(user.orders[order_index]),
+            None,
+            None,
+            oracle_price,
+        )?;
+        emit!(order_action_record);
+
+        return Ok(());
+    }
+
     validate!(can_trigger, ErrorCode::OrderDidNotSatisfyTriggerCondition)?;
 
     let position_index = user.get_spot_position_index(market_index)?;
@@ -4844,8 +7625,16 @@ pub fn trigger_spot_order(
 
     // If order is risk increasing and user is below initial margin, cancel it
     if is_risk_increasing && !user.orders[order_index].reduce_only {
-        let meets_initial_margin_requirement =
-            meets_initial_margin_requirement(user, perp_market_map, spot_market_map, oracle_map)?;
+        let meets_initial_margin_requirement = meets_initial_margin_requirement(
+            user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            MarketIdentifier {
+                market_type,
+                market_index,
+            },
+        )?;
 
         if !meets_initial_margin_requirement {
             cancel_order(
@@ -4865,6 +7654,21 @@ pub fn trigger_spot_order(
         }
     }
 
+    // if the order was canceled above, the linked order group is reset and nothing is canceled
+    let linked_order_group = user.orders[order_index].get_linked_order_group_to_cancel();
+    cancel_linked_orders(
+        user,
+        &user_key,
+        order_id,
+        linked_order_group,
+        perp_market_map,
+        spot_market_map,
+        oracle_map,
+        now,
+        slot,
+        Some(&filler_key),
+    )?;
+
     user.update_last_active_slot(slot);
 
     Ok(())
@@ -4880,7 +7684,7 @@ pub fn expire_orders(
     slot: u64,
 ) -> DriftResult {
     for order_index in 0..user.orders.len() {
-        if !should_expire_order(user, order_index, now)? {
+        if !should_expire_order(user, order_index, now, slot)? {
             continue;
         }
 
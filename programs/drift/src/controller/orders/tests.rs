@@ -130,6 +130,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -249,6 +252,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -368,6 +374,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -487,6 +496,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -606,6 +618,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -691,6 +706,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -777,6 +795,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -863,6 +884,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -949,6 +973,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -1055,6 +1082,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -1164,6 +1194,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -1280,6 +1313,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -1397,6 +1433,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -1538,6 +1577,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -1654,6 +1696,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -1772,6 +1817,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut oracle_map,
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -1912,6 +1960,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut oracle_map,
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -2045,6 +2096,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut oracle_map,
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -2184,6 +2238,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut oracle_map,
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -2311,6 +2368,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -2437,6 +2497,9 @@ pub mod fulfill_order_with_maker_order {
             slot,
             &fee_structure,
             &mut get_oracle_map(),
+            None,
+            None,
+            false,
         )
         .unwrap();
 
@@ -4765,7 +4828,7 @@ pub mod fill_order {
 
     use anchor_lang::prelude::{AccountLoader, Clock};
 
-    use crate::controller::orders::fill_perp_order;
+    use crate::controller::orders::{fill_perp_order, validate_fill_or_kill_order_filled};
     use crate::controller::position::PositionDirection;
     use crate::create_anchor_account_info;
     use crate::math::constants::{
@@ -4780,7 +4843,9 @@ pub mod fill_order {
     use crate::state::spot_market::{SpotBalanceType, SpotMarket};
     use crate::state::spot_market_map::SpotMarketMap;
     use crate::state::state::State;
-    use crate::state::user::{MarketType, OrderStatus, OrderType, SpotPosition, User, UserStats};
+    use crate::state::user::{
+        MarketType, OrderBitFlag, OrderStatus, OrderType, SpotPosition, User, UserStats,
+    };
     use crate::test_utils::*;
     use crate::test_utils::{
         create_account_info, get_orders, get_positions, get_pyth_price, get_spot_positions,
@@ -4790,6 +4855,7 @@ pub mod fill_order {
     use super::*;
     use crate::error::ErrorCode;
     use crate::state::fill_mode::FillMode;
+    use crate::state::market_maker_protection::{MarketMakerProtection, UserMarketMakerProtection};
     use crate::state::user_map::{UserMap, UserStatsMap};
 
     #[test]
@@ -4998,7 +5064,7 @@ pub mod fill_order {
     }
 
     #[test]
-    fn fallback_maker_order_id() {
+    fn fill_or_kill_order_partially_filled() {
         let clock = Clock {
             slot: 56,
             epoch_start_timestamp: 0,
@@ -5089,15 +5155,12 @@ pub mod fill_order {
                 market_index: 0,
                 order_id: 1,
                 status: OrderStatus::Open,
-                order_type: OrderType::Market,
+                order_type: OrderType::Limit,
                 direction: PositionDirection::Long,
-                market_type: MarketType::Perp,
                 base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
-                auction_start_price: 0,
-                auction_end_price: 100 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                price: 100 * PRICE_PRECISION_U64,
+                price: 99 * PRICE_PRECISION_U64,
+                bit_flags: OrderBitFlag::FillOrKill as u8,
                 ..Order::default()
             }),
             perp_positions: get_positions(PerpPosition {
@@ -5125,26 +5188,24 @@ pub mod fill_order {
         let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
         let maker_authority =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
-        let maker_order_id = 1;
         let mut maker = User {
             authority: maker_authority,
             orders: get_orders(Order {
                 market_index: 0,
-                order_id: maker_order_id,
+                order_id: 1,
                 status: OrderStatus::Open,
                 order_type: OrderType::Limit,
-                market_type: MarketType::Perp,
                 direction: PositionDirection::Short,
-                base_asset_amount: BASE_PRECISION_U64,
+                base_asset_amount: (BASE_PRECISION_U64 / 2),
                 slot: 0,
-                price: 100 * PRICE_PRECISION_U64,
+                price: 99 * PRICE_PRECISION_U64,
                 post_only: true,
                 ..Order::default()
             }),
             perp_positions: get_positions(PerpPosition {
                 market_index: 0,
                 open_orders: 1,
-                open_asks: -BASE_PRECISION_I64,
+                open_asks: -BASE_PRECISION_I64 / 2,
                 ..PerpPosition::default()
             }),
             spot_positions: get_spot_positions(SpotPosition {
@@ -5194,33 +5255,62 @@ pub mod fill_order {
             &maker_and_referrer_stats,
             None,
             &clock,
-            FillMode::Fill,
+            FillMode::PlaceAndTake,
         )
         .unwrap();
 
-        assert_eq!(base_asset_amount, 1000000000);
+        assert_eq!(base_asset_amount, BASE_PRECISION_U64 / 2);
+
+        let user = user_account_loader.load().unwrap();
+        let result =
+            validate_fill_or_kill_order_filled(&user, 1, BASE_PRECISION_U64, base_asset_amount);
+        assert_eq!(result, Err(ErrorCode::FillOrKillOrderNotFilled));
     }
 
     #[test]
-    fn expire_order() {
+    fn fill_or_kill_order_completely_filled() {
+        let clock = Clock {
+            slot: 56,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
         let mut market = PerpMarket {
             amm: AMM {
-                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
                 base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
                 quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
-                bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
-                ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
-                ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
                 sqrt_k: 100 * AMM_RESERVE_PRECISION,
                 peg_multiplier: 100 * PEG_PRECISION,
                 max_slippage_ratio: 100,
                 max_fill_reserve_fraction: 100,
-                order_step_size: 10000000,
+                order_step_size: 1000,
                 order_tick_size: 1,
-                max_base_asset_reserve: 200 * AMM_RESERVE_PRECISION,
-                min_base_asset_reserve: 50 * AMM_RESERVE_PRECISION,
-                historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
                 ..AMM::default()
             },
             margin_ratio_initial: 1000,
@@ -5228,9 +5318,25 @@ pub mod fill_order {
             status: MarketStatus::Initialized,
             ..PerpMarket::default()
         };
-
         market.status = MarketStatus::Active;
-
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
         create_anchor_account_info!(market, PerpMarket, market_account_info);
         let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
@@ -5240,28 +5346,25 @@ pub mod fill_order {
             cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
             decimals: 6,
             initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
             ..SpotMarket::default()
         };
         create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
         let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
 
-        let mut oracle_map = get_oracle_map();
-
         let mut user = User {
-            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(),
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(), // different authority than filler
             orders: get_orders(Order {
                 market_index: 0,
                 order_id: 1,
                 status: OrderStatus::Open,
-                order_type: OrderType::Market,
+                order_type: OrderType::Limit,
                 direction: PositionDirection::Long,
                 base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
-                auction_start_price: 0,
-                auction_end_price: 102 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                price: 102 * PRICE_PRECISION_U64,
-                max_ts: 10,
+                price: 99 * PRICE_PRECISION_U64,
+                bit_flags: OrderBitFlag::FillOrKill as u8,
                 ..Order::default()
             }),
             perp_positions: get_positions(PerpPosition {
@@ -5286,6 +5389,47 @@ pub mod fill_order {
         let user_stats_account_loader: AccountLoader<UserStats> =
             AccountLoader::try_from(&user_stats_account_info).unwrap();
 
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        let maker_authority =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let mut maker = User {
+            authority: maker_authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                price: 99 * PRICE_PRECISION_U64,
+                post_only: true,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let mut maker_stats = UserStats {
+            authority: maker_authority,
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+
         let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
         create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
         let filler_account_loader: AccountLoader<User> =
@@ -5301,14 +5445,6 @@ pub mod fill_order {
             ..State::default()
         };
 
-        let clock = Clock {
-            slot: 11,
-            epoch_start_timestamp: 0,
-            epoch: 0,
-            leader_schedule_epoch: 0,
-            unix_timestamp: 11,
-        };
-
         let base_asset_amount = fill_perp_order(
             1,
             &state,
@@ -5319,29 +5455,26 @@ pub mod fill_order {
             &mut oracle_map,
             &filler_account_loader,
             &filler_stats_account_loader,
-            &UserMap::empty(),
-            &UserStatsMap::empty(),
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
             None,
             &clock,
-            FillMode::Fill,
+            FillMode::PlaceAndTake,
         )
         .unwrap();
 
-        let user_after = user_account_loader.load().unwrap();
-        assert_eq!(base_asset_amount, 0);
-        assert_eq!(user_after.perp_positions[0].open_orders, 0);
-        assert_eq!(user_after.perp_positions[0].open_bids, 0);
-        assert_eq!(user_after.perp_positions[0].quote_asset_amount, -10000);
-        assert_eq!(user_after.orders[0], Order::default()); // order canceled
+        assert_eq!(base_asset_amount, BASE_PRECISION_U64);
 
-        let filler_after = filler_account_loader.load().unwrap();
-        assert_eq!(filler_after.perp_positions[0].quote_asset_amount, 10000);
+        let user = user_account_loader.load().unwrap();
+        let result =
+            validate_fill_or_kill_order_filled(&user, 1, BASE_PRECISION_U64, base_asset_amount);
+        assert_eq!(result, Ok(()));
     }
 
     #[test]
-    fn max_open_interest() {
+    fn market_maker_protection_pulls_maker_orders_in_market() {
         let clock = Clock {
-            slot: 6,
+            slot: 56,
             epoch_start_timestamp: 0,
             epoch: 0,
             leader_schedule_epoch: 0,
@@ -5372,7 +5505,6 @@ pub mod fill_order {
                 order_step_size: 1000,
                 order_tick_size: 1,
                 oracle: oracle_price_key,
-                max_open_interest: 100,
                 max_spread: 1000,
                 base_spread: 0,
                 long_spread: 0,
@@ -5426,18 +5558,16 @@ pub mod fill_order {
         let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
 
         let mut user = User {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(), // different authority than filler
             orders: get_orders(Order {
                 market_index: 0,
                 order_id: 1,
                 status: OrderStatus::Open,
-                order_type: OrderType::Market,
+                order_type: OrderType::Limit,
                 direction: PositionDirection::Long,
                 base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
-                auction_start_price: 0,
-                auction_end_price: 102 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                price: 102 * PRICE_PRECISION_U64,
+                price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
             perp_positions: get_positions(PerpPosition {
@@ -5462,22 +5592,106 @@ pub mod fill_order {
         let user_stats_account_loader: AccountLoader<UserStats> =
             AccountLoader::try_from(&user_stats_account_info).unwrap();
 
-        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
-        let filler_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&user_account_info).unwrap();
-
-        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
-        let filler_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&filler_stats_account_info).unwrap();
-
-        let state = State {
-            min_perp_auction_duration: 1,
-            default_market_order_time_in_force: 10,
-            ..State::default()
-        };
-
-        let err = fill_perp_order(
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        let maker_authority =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let mut maker = User {
+            authority: maker_authority,
+            orders: get_orders!(
+                Order {
+                    market_index: 0,
+                    order_id: 1,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64 / 2,
+                    slot: 0,
+                    price: 99 * PRICE_PRECISION_U64,
+                    post_only: true,
+                    ..Order::default()
+                },
+                Order {
+                    market_index: 0,
+                    order_id: 2,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64 / 2,
+                    slot: 0,
+                    price: 100 * PRICE_PRECISION_U64,
+                    post_only: true,
+                    ..Order::default()
+                }
+            ),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            has_market_maker_protection: true,
+            ..User::default()
+        };
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let mut makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let mut user_market_maker_protection = UserMarketMakerProtection {
+            user: maker_key,
+            ..UserMarketMakerProtection::default()
+        };
+        user_market_maker_protection.markets[0] = MarketMakerProtection {
+            market_index: 0,
+            window_slots: 10,
+            max_fills: 1,
+            ..MarketMakerProtection::default()
+        };
+        user_market_maker_protection.markets[1] = MarketMakerProtection {
+            market_index: 1,
+            window_slots: 10,
+            max_fills: 1,
+            ..MarketMakerProtection::default()
+        };
+        create_anchor_account_info!(
+            user_market_maker_protection,
+            UserMarketMakerProtection,
+            user_market_maker_protection_account_info
+        );
+        makers_and_referrers
+            .insert_market_maker_protection(
+                maker_key,
+                AccountLoader::try_from(&user_market_maker_protection_account_info).unwrap(),
+            )
+            .unwrap();
+
+        let mut maker_stats = UserStats {
+            authority: maker_authority,
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            min_perp_auction_duration: 1,
+            default_market_order_time_in_force: 10,
+            ..State::default()
+        };
+
+        fill_perp_order(
             1,
             &state,
             &user_account_loader,
@@ -5487,832 +5701,1219 @@ pub mod fill_order {
             &mut oracle_map,
             &filler_account_loader,
             &filler_stats_account_loader,
-            &UserMap::empty(),
-            &UserStatsMap::empty(),
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
             None,
             &clock,
-            FillMode::Fill,
+            FillMode::PlaceAndTake,
+        )
+        .unwrap();
+
+        let maker = makers_and_referrers.get_ref(&maker_key).unwrap();
+        // maker only filled until protection triggered, then its orders in the market are pulled
+        assert_eq!(
+            maker.perp_positions[0].base_asset_amount,
+            -BASE_PRECISION_I64 / 2
         );
+        assert_eq!(maker.orders[0], Order::default());
+        assert_eq!(maker.orders[1], Order::default());
 
-        assert_eq!(err, Err(ErrorCode::MaxOpenInterest));
+        let user_market_maker_protection = makers_and_referrers
+            .get_market_maker_protection_mut(&maker_key)
+            .unwrap()
+            .unwrap();
+        assert!(user_market_maker_protection.is_triggered(0));
+        assert!(!user_market_maker_protection.is_triggered(1));
     }
-}
-
-#[cfg(test)]
-pub mod fulfill_spot_order_with_match {
-    use crate::controller::orders::fulfill_spot_order_with_match;
-    use crate::controller::position::PositionDirection;
-    use crate::math::constants::{
-        LAMPORTS_PER_SOL_I64, LAMPORTS_PER_SOL_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64,
-        QUOTE_PRECISION_U64, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
-    };
-    use crate::math::spot_balance::calculate_utilization;
-    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
-    use crate::state::user::{MarketType, Order, OrderType, SpotPosition, User, UserStats};
-    use crate::test_utils::get_orders;
-    use crate::SPOT_UTILIZATION_PRECISION;
-
-    use super::*;
 
     #[test]
-    fn long_taker_order_fulfilled_start_of_auction() {
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
+    fn maker_skipped_without_market_maker_protection_account() {
+        let clock = Clock {
+            slot: 56,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
         };
-        let mut taker = User {
-            orders: get_orders(Order {
-                market_index: 1,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Market,
-                direction: PositionDirection::Long,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                slot: 0,
-                auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 200 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                ..Order::default()
-            }),
-            spot_positions: taker_spot_positions,
-            ..User::default()
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
         };
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
         };
-        let mut maker = User {
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = User {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(), // different authority than filler
             orders: get_orders(Order {
-                market_index: 1,
-                post_only: true,
-                market_type: MarketType::Spot,
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
                 price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
-            spot_positions: maker_spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let mut base_market = SpotMarket {
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_base_market()
-        };
-        let mut quote_market = SpotMarket {
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
-        };
-
-        let now = 1_i64;
-        let slot = 1_u64;
-
-        let fee_structure = get_fee_structure();
-
-        let (taker_key, maker_key, filler_key) = get_user_keys();
-
-        let mut taker_stats = UserStats::default();
-        let mut maker_stats = UserStats::default();
-
-        fulfill_spot_order_with_match(
-            &mut base_market,
-            &mut quote_market,
-            &mut taker,
-            &mut taker_stats,
-            0,
-            &taker_key,
-            &mut maker,
-            &mut Some(&mut maker_stats),
-            0,
-            &maker_key,
-            None,
-            None,
-            &filler_key,
-            now,
-            slot,
-            &mut get_oracle_map(),
-            &fee_structure,
-        )
-        .unwrap();
-
-        let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 950000000);
-
-        let taker_base_position = taker.spot_positions[1];
-        assert_eq!(
-            taker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
-        );
-        assert_eq!(taker_base_position.open_bids, 0);
-        assert_eq!(taker_base_position.open_orders, 0);
-
-        assert_eq!(taker_stats.taker_volume_30d, 100000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
-
-        let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
-
-        let maker_base_position = maker.spot_positions[1];
-        assert_eq!(maker_base_position.scaled_balance, 0);
-        assert_eq!(maker_base_position.open_bids, 0);
-        assert_eq!(maker_base_position.open_orders, 0);
-
-        assert_eq!(maker_stats.maker_volume_30d, 100000000);
-        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
-
-        assert_eq!(base_market.total_spot_fee, 20000);
-        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
-    }
-
-    #[test]
-    fn long_taker_order_fulfilled_middle_of_auction() {
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 161 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut taker = User {
-            orders: get_orders(Order {
-                market_index: 1,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Market,
-                direction: PositionDirection::Long,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                slot: 0,
-                auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 200 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                ..Order::default()
-            }),
-            spot_positions: taker_spot_positions,
-            ..User::default()
-        };
+        create_anchor_account_info!(UserStats::default(), UserStats, user_stats_account_info);
+        let user_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&user_stats_account_info).unwrap();
 
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        let maker_authority =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let mut maker = User {
-            orders: get_orders(Order {
-                market_index: 1,
-                post_only: true,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 160 * PRICE_PRECISION_U64,
-                ..Order::default()
+            authority: maker_authority,
+            orders: get_orders!(
+                Order {
+                    market_index: 0,
+                    order_id: 1,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64 / 2,
+                    slot: 0,
+                    price: 99 * PRICE_PRECISION_U64,
+                    post_only: true,
+                    ..Order::default()
+                },
+                Order {
+                    market_index: 0,
+                    order_id: 2,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64 / 2,
+                    slot: 0,
+                    price: 100 * PRICE_PRECISION_U64,
+                    post_only: true,
+                    ..Order::default()
+                }
+            ),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
             }),
-            spot_positions: maker_spot_positions,
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            has_market_maker_protection: true,
             ..User::default()
         };
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
 
-        let mut base_market = SpotMarket {
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_base_market()
-        };
-        let mut quote_market = SpotMarket {
-            deposit_balance: 161 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
+        let mut maker_stats = UserStats {
+            authority: maker_authority,
+            ..UserStats::default()
         };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
 
-        let now = 3_i64;
-        let slot = 3_u64;
-
-        let fee_structure = get_fee_structure();
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let (taker_key, maker_key, filler_key) = get_user_keys();
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
 
-        let mut taker_stats = UserStats::default();
-        let mut maker_stats = UserStats::default();
+        let state = State {
+            min_perp_auction_duration: 1,
+            default_market_order_time_in_force: 10,
+            ..State::default()
+        };
 
-        fulfill_spot_order_with_match(
-            &mut base_market,
-            &mut quote_market,
-            &mut taker,
-            &mut taker_stats,
-            0,
-            &taker_key,
-            &mut maker,
-            &mut Some(&mut maker_stats),
-            0,
-            &maker_key,
-            None,
+        fill_perp_order(
+            1,
+            &state,
+            &user_account_loader,
+            &user_stats_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
             None,
-            &filler_key,
-            now,
-            slot,
-            &mut get_oracle_map(),
-            &fee_structure,
+            &clock,
+            FillMode::PlaceAndTake,
         )
         .unwrap();
 
-        let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 920000000);
-
-        let taker_base_position = taker.spot_positions[1];
-        assert_eq!(
-            taker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
-        );
-        assert_eq!(taker_base_position.open_bids, 0);
-        assert_eq!(taker_base_position.open_orders, 0);
-
-        assert_eq!(taker_stats.taker_volume_30d, 160000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 80000);
-
-        let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 160048000000);
-
-        let maker_base_position = maker.spot_positions[1];
-        assert_eq!(maker_base_position.scaled_balance, 0);
-        assert_eq!(maker_base_position.open_bids, 0);
-        assert_eq!(maker_base_position.open_orders, 0);
-
-        assert_eq!(maker_stats.maker_volume_30d, 160000000);
-        assert_eq!(maker_stats.fees.total_fee_rebate, 48000);
-
-        assert_eq!(base_market.total_spot_fee, 32000);
-        assert_eq!(base_market.spot_fee_pool.scaled_balance, 32000000);
+        let maker = makers_and_referrers.get_ref(&maker_key).unwrap();
+        assert_eq!(maker.perp_positions[0].base_asset_amount, 0);
+        assert_eq!(maker.orders[0].status, OrderStatus::Open);
+        assert_eq!(maker.orders[1].status, OrderStatus::Open);
     }
 
     #[test]
-    fn short_taker_order_fulfilled_start_of_auction() {
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
+    fn linked_orders_not_canceled_on_partial_fill() {
+        let clock = Clock {
+            slot: 56,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
         };
-        let mut taker = User {
-            orders: get_orders(Order {
-                market_index: 1,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Market,
-                direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                slot: 0,
-                auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                ..Order::default()
-            }),
-            spot_positions: taker_spot_positions,
-            ..User::default()
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
         };
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[0] = SpotPosition {
+        let mut spot_market = SpotMarket {
             market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
         };
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut taker_orders = [Order::default(); 32];
+        taker_orders[0] = Order {
+            market_index: 0,
+            order_id: 1,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            direction: PositionDirection::Long,
+            base_asset_amount: BASE_PRECISION_U64,
+            slot: 0,
+            price: 99 * PRICE_PRECISION_U64,
+            linked_order_group: 1,
+            ..Order::default()
+        };
+        taker_orders[1] = Order {
+            market_index: 0,
+            order_id: 2,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
+            slot: 0,
+            price: 110 * PRICE_PRECISION_U64,
+            reduce_only: true,
+            linked_order_group: 1,
+            ..Order::default()
+        };
+        let mut user = User {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(), // different authority than filler
+            orders: taker_orders,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                open_bids: BASE_PRECISION_I64,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
         };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, user_stats_account_info);
+        let user_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&user_stats_account_info).unwrap();
+
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        let maker_authority =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let mut maker = User {
+            authority: maker_authority,
             orders: get_orders(Order {
-                market_index: 1,
-                post_only: true,
-                market_type: MarketType::Spot,
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 100 * PRICE_PRECISION_U64,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64 / 2,
+                slot: 0,
+                price: 99 * PRICE_PRECISION_U64,
+                post_only: true,
                 ..Order::default()
             }),
-            spot_positions: maker_spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -BASE_PRECISION_I64 / 2,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
 
-        let mut base_market = SpotMarket {
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_base_market()
-        };
-        let mut quote_market = SpotMarket {
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
+        let mut maker_stats = UserStats {
+            authority: maker_authority,
+            ..UserStats::default()
         };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
 
-        let now = 1_i64;
-        let slot = 1_u64;
-
-        let fee_structure = get_fee_structure();
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let (taker_key, maker_key, filler_key) = get_user_keys();
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
 
-        let mut taker_stats = UserStats::default();
-        let mut maker_stats = UserStats::default();
+        let state = State {
+            min_perp_auction_duration: 1,
+            default_market_order_time_in_force: 10,
+            ..State::default()
+        };
 
-        fulfill_spot_order_with_match(
-            &mut base_market,
-            &mut quote_market,
-            &mut taker,
-            &mut taker_stats,
-            0,
-            &taker_key,
-            &mut maker,
-            &mut Some(&mut maker_stats),
-            0,
-            &maker_key,
-            None,
+        let base_asset_amount = fill_perp_order(
+            1,
+            &state,
+            &user_account_loader,
+            &user_stats_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
             None,
-            &filler_key,
-            now,
-            slot,
-            &mut get_oracle_map(),
-            &fee_structure,
+            &clock,
+            FillMode::PlaceAndTake,
         )
         .unwrap();
 
-        let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 99950000000);
+        assert_eq!(base_asset_amount, BASE_PRECISION_U64 / 2);
 
-        let taker_base_position = taker.spot_positions[1];
-        assert_eq!(taker_base_position.scaled_balance, 0);
-        assert_eq!(taker_base_position.open_bids, 0);
-        assert_eq!(taker_base_position.open_orders, 0);
-
-        assert_eq!(taker_stats.taker_volume_30d, 100000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
-
-        let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 1030000000);
-
-        let maker_base_position = maker.spot_positions[1];
+        // order partially filled, linked order remains open
+        let user = user_account_loader.load().unwrap();
         assert_eq!(
-            maker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
+            user.orders[0].base_asset_amount_filled,
+            BASE_PRECISION_U64 / 2
         );
-        assert_eq!(maker_base_position.open_bids, 0);
-        assert_eq!(maker_base_position.open_orders, 0);
-
-        assert_eq!(maker_stats.maker_volume_30d, 100000000);
-        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
-
-        assert_eq!(base_market.total_spot_fee, 20000);
-        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
+        assert_eq!(user.orders[1].status, OrderStatus::Open);
+        assert_eq!(user.orders[1].order_id, 2);
     }
 
     #[test]
-    fn short_taker_order_fulfilled_middle_of_auction() {
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
+    fn twap_order_fills_released_slices() {
+        let clock = Clock {
+            slot: 56,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
         };
-        let mut taker = User {
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = User {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(), // different authority than filler
             orders: get_orders(Order {
-                market_index: 1,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Market,
-                direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                slot: 0,
-                auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Twap,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 50,
+                price: 99 * PRICE_PRECISION_U64,
+                // slice interval of 10 slots
+                trigger_price: 10,
+                // slice base asset amount
+                auction_start_price: BASE_PRECISION_I64 / 2,
                 ..Order::default()
             }),
-            spot_positions: taker_spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
+        create_anchor_account_info!(UserStats::default(), UserStats, user_stats_account_info);
+        let user_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&user_stats_account_info).unwrap();
+
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        let maker_authority =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let mut maker = User {
+            authority: maker_authority,
             orders: get_orders(Order {
-                market_index: 1,
-                post_only: true,
-                market_type: MarketType::Spot,
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 70 * PRICE_PRECISION_U64,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                price: 99 * PRICE_PRECISION_U64,
+                post_only: true,
                 ..Order::default()
             }),
-            spot_positions: maker_spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
 
-        let mut base_market = SpotMarket {
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_base_market()
+        let mut maker_stats = UserStats {
+            authority: maker_authority,
+            ..UserStats::default()
         };
-        let mut quote_market = SpotMarket {
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            min_perp_auction_duration: 1,
+            default_market_order_time_in_force: 10,
+            ..State::default()
         };
 
-        let now = 3_i64;
-        let slot = 3_u64;
+        let base_asset_amount = fill_perp_order(
+            1,
+            &state,
+            &user_account_loader,
+            &user_stats_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
+            None,
+            &clock,
+            FillMode::Fill,
+        )
+        .unwrap();
 
-        let fee_structure = get_fee_structure();
+        assert_eq!(base_asset_amount, BASE_PRECISION_U64 / 2);
 
-        let (taker_key, maker_key, filler_key) = get_user_keys();
+        // only the first slice is released at slot 56
+        let user = user_account_loader.load().unwrap();
+        assert_eq!(user.orders[0].status, OrderStatus::Open);
+        assert_eq!(
+            user.orders[0].base_asset_amount_filled,
+            BASE_PRECISION_U64 / 2
+        );
+        assert_eq!(
+            user.orders[0]
+                .get_twap_base_asset_amount_available(clock.slot)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            user.orders[0]
+                .get_twap_base_asset_amount_available(60)
+                .unwrap(),
+            BASE_PRECISION_U64 / 2
+        );
 
-        let mut taker_stats = UserStats::default();
-        let mut maker_stats = UserStats::default();
+        // filler is paid the flat keeper reward for the slice on top of the fill reward
+        let filler = filler_account_loader.load().unwrap();
+        assert!(
+            filler.perp_positions[0].quote_asset_amount
+                >= state.perp_fee_structure.flat_filler_fee as i64
+        );
+    }
 
-        fulfill_spot_order_with_match(
-            &mut base_market,
-            &mut quote_market,
-            &mut taker,
-            &mut taker_stats,
-            0,
-            &taker_key,
-            &mut maker,
-            &mut Some(&mut maker_stats),
-            0,
-            &maker_key,
-            None,
-            None,
-            &filler_key,
-            now,
-            slot,
-            &mut get_oracle_map(),
-            &fee_structure,
-        )
-        .unwrap();
-
-        let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 69965000000);
-
-        let taker_base_position = taker.spot_positions[1];
-        assert_eq!(taker_base_position.scaled_balance, 0);
-        assert_eq!(taker_base_position.open_bids, 0);
-        assert_eq!(taker_base_position.open_orders, 0);
-
-        assert_eq!(taker_stats.taker_volume_30d, 70000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 35000);
-
-        let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 31021000000);
+    #[test]
+    fn fallback_maker_order_id() {
+        let clock = Clock {
+            slot: 56,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
 
-        let maker_base_position = maker.spot_positions[1];
-        assert_eq!(
-            maker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
         );
-        assert_eq!(maker_base_position.open_bids, 0);
-        assert_eq!(maker_base_position.open_orders, 0);
-
-        assert_eq!(maker_stats.maker_volume_30d, 70000000);
-        assert_eq!(maker_stats.fees.total_fee_rebate, 21000);
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        assert_eq!(base_market.total_spot_fee, 14000);
-        assert_eq!(base_market.spot_fee_pool.scaled_balance, 14000000);
-    }
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-    #[test]
-    fn long_taker_order_auction_price_does_not_satisfy_maker() {
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
+        let mut spot_market = SpotMarket {
             market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
         };
-        let mut taker = User {
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = User {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(), // different authority than filler
             orders: get_orders(Order {
-                market_index: 1,
-                market_type: MarketType::Spot,
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
                 order_type: OrderType::Market,
                 direction: PositionDirection::Long,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                market_type: MarketType::Perp,
+                base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
-                auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 200 * PRICE_PRECISION_I64,
+                auction_start_price: 0,
+                auction_end_price: 100 * PRICE_PRECISION_I64,
                 auction_duration: 5,
+                price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
-            spot_positions: taker_spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
+        create_anchor_account_info!(UserStats::default(), UserStats, user_stats_account_info);
+        let user_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&user_stats_account_info).unwrap();
+
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        let maker_authority =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let maker_order_id = 1;
         let mut maker = User {
+            authority: maker_authority,
             orders: get_orders(Order {
-                market_index: 1,
-                post_only: true,
-                market_type: MarketType::Spot,
+                market_index: 0,
+                order_id: maker_order_id,
+                status: OrderStatus::Open,
                 order_type: OrderType::Limit,
+                market_type: MarketType::Perp,
                 direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 201 * PRICE_PRECISION_U64,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                post_only: true,
                 ..Order::default()
             }),
-            spot_positions: maker_spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
 
-        let mut base_market = SpotMarket {
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_base_market()
-        };
-        let mut quote_market = SpotMarket {
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
+        let mut maker_stats = UserStats {
+            authority: maker_authority,
+            ..UserStats::default()
         };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
 
-        let now = 1_i64;
-        let slot = 1_u64;
-
-        let fee_structure = get_fee_structure();
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let (taker_key, maker_key, filler_key) = get_user_keys();
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
 
-        let mut taker_stats = UserStats::default();
-        let mut maker_stats = UserStats::default();
+        let state = State {
+            min_perp_auction_duration: 1,
+            default_market_order_time_in_force: 10,
+            ..State::default()
+        };
 
-        let (base_asset_amount, _) = fulfill_spot_order_with_match(
-            &mut base_market,
-            &mut quote_market,
-            &mut taker,
-            &mut taker_stats,
-            0,
-            &taker_key,
-            &mut maker,
-            &mut Some(&mut maker_stats),
-            0,
-            &maker_key,
-            None,
+        let base_asset_amount = fill_perp_order(
+            1,
+            &state,
+            &user_account_loader,
+            &user_stats_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
             None,
-            &filler_key,
-            now,
-            slot,
-            &mut get_oracle_map(),
-            &fee_structure,
+            &clock,
+            FillMode::Fill,
         )
         .unwrap();
 
-        assert_eq!(base_asset_amount, 0)
+        assert_eq!(base_asset_amount, 1000000000);
     }
 
     #[test]
-    fn short_taker_order_auction_price_does_not_satisfy_maker() {
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut taker = User {
-            orders: get_orders(Order {
-                market_index: 1,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Market,
-                direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                slot: 0,
-                auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                ..Order::default()
-            }),
-            spot_positions: taker_spot_positions,
-            ..User::default()
+    fn expire_order() {
+        let mut market = PerpMarket {
+            amm: AMM {
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 10000000,
+                order_tick_size: 1,
+                max_base_asset_reserve: 200 * AMM_RESERVE_PRECISION,
+                min_base_asset_reserve: 50 * AMM_RESERVE_PRECISION,
+                historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
         };
 
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[0] = SpotPosition {
+        market.status = MarketStatus::Active;
+
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
             market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            ..SpotMarket::default()
         };
-        let mut maker = User {
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut oracle_map = get_oracle_map();
+
+        let mut user = User {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(),
             orders: get_orders(Order {
-                market_index: 1,
-                post_only: true,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Market,
                 direction: PositionDirection::Long,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 49 * PRICE_PRECISION_U64,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                auction_start_price: 0,
+                auction_end_price: 102 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 102 * PRICE_PRECISION_U64,
+                max_ts: 10,
                 ..Order::default()
             }),
-            spot_positions: maker_spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let mut base_market = SpotMarket {
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_base_market()
-        };
-        let mut quote_market = SpotMarket {
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
-        };
+        create_anchor_account_info!(UserStats::default(), UserStats, user_stats_account_info);
+        let user_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&user_stats_account_info).unwrap();
 
-        let now = 3_i64;
-        let slot = 3_u64;
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let fee_structure = get_fee_structure();
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
 
-        let (taker_key, maker_key, filler_key) = get_user_keys();
+        let state = State {
+            min_perp_auction_duration: 1,
+            default_market_order_time_in_force: 10,
+            ..State::default()
+        };
 
-        let mut taker_stats = UserStats::default();
-        let mut maker_stats = UserStats::default();
+        let clock = Clock {
+            slot: 11,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 11,
+        };
 
-        let (base_asset_amount, _) = fulfill_spot_order_with_match(
-            &mut base_market,
-            &mut quote_market,
-            &mut taker,
-            &mut taker_stats,
-            0,
-            &taker_key,
-            &mut maker,
-            &mut Some(&mut maker_stats),
-            0,
-            &maker_key,
-            None,
+        let base_asset_amount = fill_perp_order(
+            1,
+            &state,
+            &user_account_loader,
+            &user_stats_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &UserMap::empty(),
+            &UserStatsMap::empty(),
             None,
-            &filler_key,
-            now,
-            slot,
-            &mut get_oracle_map(),
-            &fee_structure,
+            &clock,
+            FillMode::Fill,
         )
         .unwrap();
 
+        let user_after = user_account_loader.load().unwrap();
         assert_eq!(base_asset_amount, 0);
+        assert_eq!(user_after.perp_positions[0].open_orders, 0);
+        assert_eq!(user_after.perp_positions[0].open_bids, 0);
+        assert_eq!(user_after.perp_positions[0].quote_asset_amount, -10000);
+        assert_eq!(user_after.orders[0], Order::default()); // order canceled
+
+        let filler_after = filler_account_loader.load().unwrap();
+        assert_eq!(filler_after.perp_positions[0].quote_asset_amount, 10000);
     }
 
     #[test]
-    fn maker_taker_same_direction() {
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut taker = User {
-            orders: get_orders(Order {
-                market_index: 1,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Market,
-                direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                slot: 0,
-                auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                ..Order::default()
-            }),
-            spot_positions: taker_spot_positions,
-            ..User::default()
+    fn max_open_interest() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
         };
 
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_open_interest: 100,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
         };
-        let mut maker = User {
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut user = User {
             orders: get_orders(Order {
-                market_index: 1,
-                post_only: true,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 70 * PRICE_PRECISION_U64,
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                auction_start_price: 0,
+                auction_end_price: 102 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 102 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
-            spot_positions: maker_spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let mut base_market = SpotMarket {
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_base_market()
-        };
-        let mut quote_market = SpotMarket {
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
-        };
-
-        let now = 3_i64;
-        let slot = 3_u64;
+        create_anchor_account_info!(UserStats::default(), UserStats, user_stats_account_info);
+        let user_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&user_stats_account_info).unwrap();
 
-        let fee_structure = get_fee_structure();
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let (taker_key, maker_key, filler_key) = get_user_keys();
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
 
-        let mut taker_stats = UserStats::default();
-        let mut maker_stats = UserStats::default();
+        let state = State {
+            min_perp_auction_duration: 1,
+            default_market_order_time_in_force: 10,
+            ..State::default()
+        };
 
-        let (base_asset_amount, _) = fulfill_spot_order_with_match(
-            &mut base_market,
-            &mut quote_market,
-            &mut taker,
-            &mut taker_stats,
-            0,
-            &taker_key,
-            &mut maker,
-            &mut Some(&mut maker_stats),
-            0,
-            &maker_key,
-            None,
+        let err = fill_perp_order(
+            1,
+            &state,
+            &user_account_loader,
+            &user_stats_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &UserMap::empty(),
+            &UserStatsMap::empty(),
             None,
-            &filler_key,
-            now,
-            slot,
-            &mut get_oracle_map(),
-            &fee_structure,
-        )
-        .unwrap();
+            &clock,
+            FillMode::Fill,
+        );
 
-        assert_eq!(base_asset_amount, 0);
+        assert_eq!(err, Err(ErrorCode::MaxOpenInterest));
     }
+}
+
+#[cfg(test)]
+pub mod fulfill_spot_order_with_match {
+    use crate::controller::orders::fulfill_spot_order_with_match;
+    use crate::controller::position::PositionDirection;
+    use crate::math::constants::{
+        LAMPORTS_PER_SOL_I64, LAMPORTS_PER_SOL_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64,
+        QUOTE_PRECISION_U64, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
+    };
+    use crate::math::spot_balance::calculate_utilization;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::user::{MarketType, Order, OrderType, SpotPosition, User, UserStats};
+    use crate::test_utils::get_orders;
+    use crate::SPOT_UTILIZATION_PRECISION;
+
+    use super::*;
 
     #[test]
-    fn maker_taker_different_market_index() {
+    fn long_taker_order_fulfilled_start_of_auction() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
         taker_spot_positions[0] = SpotPosition {
             market_index: 0,
@@ -6354,7 +6955,7 @@ pub mod fulfill_spot_order_with_match {
         };
         let mut maker = User {
             orders: get_orders(Order {
-                market_index: 2,
+                market_index: 1,
                 post_only: true,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
@@ -6386,7 +6987,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        let (base_asset_amount, _) = fulfill_spot_order_with_match(
+        fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -6407,22 +7008,48 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_asset_amount, 0);
+        let taker_quote_position = taker.spot_positions[0];
+        assert_eq!(taker_quote_position.scaled_balance, 950000000);
+
+        let taker_base_position = taker.spot_positions[1];
+        assert_eq!(
+            taker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(taker_base_position.open_bids, 0);
+        assert_eq!(taker_base_position.open_orders, 0);
+
+        assert_eq!(taker_stats.taker_volume_30d, 100000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
+
+        let maker_quote_position = maker.spot_positions[0];
+        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
+
+        let maker_base_position = maker.spot_positions[1];
+        assert_eq!(maker_base_position.scaled_balance, 0);
+        assert_eq!(maker_base_position.open_bids, 0);
+        assert_eq!(maker_base_position.open_orders, 0);
+
+        assert_eq!(maker_stats.maker_volume_30d, 100000000);
+        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
+
+        assert_eq!(base_market.total_spot_fee, 20000);
+        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
     }
 
     #[test]
-    fn long_taker_order_bigger_than_maker() {
+    fn long_taker_order_fulfilled_middle_of_auction() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
         taker_spot_positions[0] = SpotPosition {
             market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            scaled_balance: 161 * SPOT_BALANCE_PRECISION_U64,
             balance_type: SpotBalanceType::Deposit,
             ..SpotPosition::default()
         };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
             open_orders: 1,
-            open_bids: 100 * LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
@@ -6431,7 +7058,7 @@ pub mod fulfill_spot_order_with_match {
                 market_type: MarketType::Spot,
                 order_type: OrderType::Market,
                 direction: PositionDirection::Long,
-                base_asset_amount: 100 * LAMPORTS_PER_SOL_U64,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
                 auction_start_price: 100 * PRICE_PRECISION_I64,
                 auction_end_price: 200 * PRICE_PRECISION_I64,
@@ -6459,7 +7086,7 @@ pub mod fulfill_spot_order_with_match {
                 order_type: OrderType::Limit,
                 direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 100 * PRICE_PRECISION_U64,
+                price: 160 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
             spot_positions: maker_spot_positions,
@@ -6471,12 +7098,12 @@ pub mod fulfill_spot_order_with_match {
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            deposit_balance: 161 * SPOT_BALANCE_PRECISION,
             ..SpotMarket::default_quote_market()
         };
 
-        let now = 1_i64;
-        let slot = 1_u64;
+        let now = 3_i64;
+        let slot = 3_u64;
 
         let fee_structure = get_fee_structure();
 
@@ -6485,7 +7112,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        let (base_asset_amount, _) = fulfill_spot_order_with_match(
+        fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -6506,60 +7133,44 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_asset_amount, LAMPORTS_PER_SOL_U64);
-
         let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 950000000);
+        assert_eq!(taker_quote_position.scaled_balance, 920000000);
 
         let taker_base_position = taker.spot_positions[1];
         assert_eq!(
             taker_base_position.scaled_balance,
             SPOT_BALANCE_PRECISION_U64
         );
-        assert_eq!(taker_base_position.open_bids, 99 * LAMPORTS_PER_SOL_I64);
-        assert_eq!(taker_base_position.open_orders, 1);
+        assert_eq!(taker_base_position.open_bids, 0);
+        assert_eq!(taker_base_position.open_orders, 0);
 
-        let taker_order = taker.orders[0];
-        assert_eq!(taker_order.base_asset_amount_filled, LAMPORTS_PER_SOL_U64);
-        assert_eq!(
-            taker_order.quote_asset_amount_filled,
-            100 * QUOTE_PRECISION_U64
-        );
-
-        assert_eq!(taker_stats.taker_volume_30d, 100000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
+        assert_eq!(taker_stats.taker_volume_30d, 160000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 80000);
 
         let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
+        assert_eq!(maker_quote_position.scaled_balance, 160048000000);
 
         let maker_base_position = maker.spot_positions[1];
         assert_eq!(maker_base_position.scaled_balance, 0);
-        assert_eq!(maker_base_position.open_asks, 0);
+        assert_eq!(maker_base_position.open_bids, 0);
         assert_eq!(maker_base_position.open_orders, 0);
 
-        let maker_order = maker.orders[0];
-        assert_eq!(maker_order, Order::default());
-
-        assert_eq!(maker_stats.maker_volume_30d, 100000000);
-        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
+        assert_eq!(maker_stats.maker_volume_30d, 160000000);
+        assert_eq!(maker_stats.fees.total_fee_rebate, 48000);
 
-        assert_eq!(base_market.total_spot_fee, 20000);
-        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
+        assert_eq!(base_market.total_spot_fee, 32000);
+        assert_eq!(base_market.spot_fee_pool.scaled_balance, 32000000);
     }
 
     #[test]
-    fn long_taker_order_smaller_than_maker() {
+    fn short_taker_order_fulfilled_start_of_auction() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
@@ -6567,11 +7178,11 @@ pub mod fulfill_spot_order_with_match {
                 market_index: 1,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Market,
-                direction: PositionDirection::Long,
+                direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
                 auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 200 * PRICE_PRECISION_I64,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
                 auction_duration: 5,
                 ..Order::default()
             }),
@@ -6580,12 +7191,16 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_asks: -100 * LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
@@ -6594,8 +7209,8 @@ pub mod fulfill_spot_order_with_match {
                 post_only: true,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
-                base_asset_amount: 100 * LAMPORTS_PER_SOL_U64,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
                 price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
@@ -6622,7 +7237,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        let (base_asset_amount, _) = fulfill_spot_order_with_match(
+        fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -6643,39 +7258,27 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_asset_amount, LAMPORTS_PER_SOL_U64);
-
         let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 950000000);
+        assert_eq!(taker_quote_position.scaled_balance, 99950000000);
 
         let taker_base_position = taker.spot_positions[1];
-        assert_eq!(
-            taker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
-        );
+        assert_eq!(taker_base_position.scaled_balance, 0);
         assert_eq!(taker_base_position.open_bids, 0);
         assert_eq!(taker_base_position.open_orders, 0);
 
-        let taker_order = taker.orders[0];
-        assert_eq!(taker_order, Order::default());
-
         assert_eq!(taker_stats.taker_volume_30d, 100000000);
         assert_eq!(taker_stats.fees.total_fee_paid, 50000);
 
         let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
+        assert_eq!(maker_quote_position.scaled_balance, 1030000000);
 
         let maker_base_position = maker.spot_positions[1];
-        assert_eq!(maker_base_position.scaled_balance, 0);
-        assert_eq!(maker_base_position.open_asks, -99 * LAMPORTS_PER_SOL_I64);
-        assert_eq!(maker_base_position.open_orders, 1);
-
-        let maker_order = maker.orders[0];
-        assert_eq!(maker_order.base_asset_amount_filled, LAMPORTS_PER_SOL_U64);
         assert_eq!(
-            maker_order.quote_asset_amount_filled,
-            100 * QUOTE_PRECISION_U64
+            maker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
         );
+        assert_eq!(maker_base_position.open_bids, 0);
+        assert_eq!(maker_base_position.open_orders, 0);
 
         assert_eq!(maker_stats.maker_volume_30d, 100000000);
         assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
@@ -6685,18 +7288,14 @@ pub mod fulfill_spot_order_with_match {
     }
 
     #[test]
-    fn double_dutch_auction() {
+    fn short_taker_order_fulfilled_middle_of_auction() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
@@ -6704,11 +7303,11 @@ pub mod fulfill_spot_order_with_match {
                 market_index: 1,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Market,
-                direction: PositionDirection::Long,
+                direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
                 auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 200 * PRICE_PRECISION_I64,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
                 auction_duration: 5,
                 ..Order::default()
             }),
@@ -6717,12 +7316,16 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
@@ -6730,12 +7333,10 @@ pub mod fulfill_spot_order_with_match {
                 market_index: 1,
                 post_only: true,
                 market_type: MarketType::Spot,
-                order_type: OrderType::Market,
-                direction: PositionDirection::Short,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
-                auction_start_price: 200 * PRICE_PRECISION_I64,
-                auction_end_price: 100 * PRICE_PRECISION_I64,
-                auction_duration: 5,
+                price: 70 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
             spot_positions: maker_spot_positions,
@@ -6751,8 +7352,8 @@ pub mod fulfill_spot_order_with_match {
             ..SpotMarket::default_quote_market()
         };
 
-        let now = 5_i64;
-        let slot = 5_u64;
+        let now = 3_i64;
+        let slot = 3_u64;
 
         let fee_structure = get_fee_structure();
 
@@ -6783,36 +7384,36 @@ pub mod fulfill_spot_order_with_match {
         .unwrap();
 
         let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 950000000);
+        assert_eq!(taker_quote_position.scaled_balance, 69965000000);
 
         let taker_base_position = taker.spot_positions[1];
-        assert_eq!(
-            taker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
-        );
+        assert_eq!(taker_base_position.scaled_balance, 0);
         assert_eq!(taker_base_position.open_bids, 0);
         assert_eq!(taker_base_position.open_orders, 0);
 
-        assert_eq!(taker_stats.taker_volume_30d, 100000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
+        assert_eq!(taker_stats.taker_volume_30d, 70000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 35000);
 
         let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
+        assert_eq!(maker_quote_position.scaled_balance, 31021000000);
 
         let maker_base_position = maker.spot_positions[1];
-        assert_eq!(maker_base_position.scaled_balance, 0);
+        assert_eq!(
+            maker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
+        );
         assert_eq!(maker_base_position.open_bids, 0);
         assert_eq!(maker_base_position.open_orders, 0);
 
-        assert_eq!(maker_stats.maker_volume_30d, 100000000);
-        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
+        assert_eq!(maker_stats.maker_volume_30d, 70000000);
+        assert_eq!(maker_stats.fees.total_fee_rebate, 21000);
 
-        assert_eq!(base_market.total_spot_fee, 20000);
-        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
+        assert_eq!(base_market.total_spot_fee, 14000);
+        assert_eq!(base_market.spot_fee_pool.scaled_balance, 14000000);
     }
 
     #[test]
-    fn taker_bid_crosses_maker_ask() {
+    fn long_taker_order_auction_price_does_not_satisfy_maker() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
         taker_spot_positions[0] = SpotPosition {
             market_index: 0,
@@ -6830,11 +7431,13 @@ pub mod fulfill_spot_order_with_match {
             orders: get_orders(Order {
                 market_index: 1,
                 market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
+                order_type: OrderType::Market,
                 direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
-                price: 100 * PRICE_PRECISION_U64,
+                auction_start_price: 100 * PRICE_PRECISION_I64,
+                auction_end_price: 200 * PRICE_PRECISION_I64,
+                auction_duration: 5,
                 ..Order::default()
             }),
             spot_positions: taker_spot_positions,
@@ -6858,7 +7461,7 @@ pub mod fulfill_spot_order_with_match {
                 order_type: OrderType::Limit,
                 direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 100 * PRICE_PRECISION_U64,
+                price: 201 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
             spot_positions: maker_spot_positions,
@@ -6884,7 +7487,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        fulfill_spot_order_with_match(
+        let (base_asset_amount, _) = fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -6905,37 +7508,11 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 950000000);
-
-        let taker_base_position = taker.spot_positions[1];
-        assert_eq!(
-            taker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
-        );
-        assert_eq!(taker_base_position.open_bids, 0);
-        assert_eq!(taker_base_position.open_orders, 0);
-
-        assert_eq!(taker_stats.taker_volume_30d, 100000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
-
-        let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
-
-        let maker_base_position = maker.spot_positions[1];
-        assert_eq!(maker_base_position.scaled_balance, 0);
-        assert_eq!(maker_base_position.open_bids, 0);
-        assert_eq!(maker_base_position.open_orders, 0);
-
-        assert_eq!(maker_stats.maker_volume_30d, 100000000);
-        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
-
-        assert_eq!(base_market.total_spot_fee, 20000);
-        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
+        assert_eq!(base_asset_amount, 0)
     }
 
     #[test]
-    fn taker_ask_crosses_maker_bid() {
+    fn short_taker_order_auction_price_does_not_satisfy_maker() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
@@ -6949,11 +7526,13 @@ pub mod fulfill_spot_order_with_match {
             orders: get_orders(Order {
                 market_index: 1,
                 market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
+                order_type: OrderType::Market,
                 direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
-                price: 100 * PRICE_PRECISION_U64,
+                auction_start_price: 100 * PRICE_PRECISION_I64,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_duration: 5,
                 ..Order::default()
             }),
             spot_positions: taker_spot_positions,
@@ -6981,7 +7560,7 @@ pub mod fulfill_spot_order_with_match {
                 order_type: OrderType::Limit,
                 direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 100 * PRICE_PRECISION_U64,
+                price: 49 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
             spot_positions: maker_spot_positions,
@@ -6997,8 +7576,8 @@ pub mod fulfill_spot_order_with_match {
             ..SpotMarket::default_quote_market()
         };
 
-        let now = 1_i64;
-        let slot = 1_u64;
+        let now = 3_i64;
+        let slot = 3_u64;
 
         let fee_structure = get_fee_structure();
 
@@ -7007,7 +7586,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        fulfill_spot_order_with_match(
+        let (base_asset_amount, _) = fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -7028,48 +7607,18 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 99950000000);
-
-        let taker_base_position = taker.spot_positions[1];
-        assert_eq!(taker_base_position.scaled_balance, 0);
-        assert_eq!(taker_base_position.open_bids, 0);
-        assert_eq!(taker_base_position.open_orders, 0);
-
-        assert_eq!(taker_stats.taker_volume_30d, 100000000);
-        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
-
-        let maker_quote_position = maker.spot_positions[0];
-        assert_eq!(maker_quote_position.scaled_balance, 1030000000);
-
-        let maker_base_position = maker.spot_positions[1];
-        assert_eq!(
-            maker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
-        );
-        assert_eq!(maker_base_position.open_bids, 0);
-        assert_eq!(maker_base_position.open_orders, 0);
-
-        assert_eq!(maker_stats.maker_volume_30d, 100000000);
-        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
-
-        assert_eq!(base_market.total_spot_fee, 20000);
-        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
+        assert_eq!(base_asset_amount, 0);
     }
 
     #[test]
-    fn zero_price_market_order_cant_match() {
+    fn maker_taker_same_direction() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
@@ -7077,11 +7626,12 @@ pub mod fulfill_spot_order_with_match {
                 market_index: 1,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Market,
-                direction: PositionDirection::Long,
+                direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
-                price: 0,
-                auction_duration: 0,
+                auction_start_price: 100 * PRICE_PRECISION_I64,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_duration: 5,
                 ..Order::default()
             }),
             spot_positions: taker_spot_positions,
@@ -7089,12 +7639,16 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
+        maker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
             balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        };
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
             open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
@@ -7105,7 +7659,7 @@ pub mod fulfill_spot_order_with_match {
                 order_type: OrderType::Limit,
                 direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 100 * PRICE_PRECISION_U64,
+                price: 70 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
             spot_positions: maker_spot_positions,
@@ -7121,8 +7675,8 @@ pub mod fulfill_spot_order_with_match {
             ..SpotMarket::default_quote_market()
         };
 
-        let now = 1_i64;
-        let slot = 1_u64;
+        let now = 3_i64;
+        let slot = 3_u64;
 
         let fee_structure = get_fee_structure();
 
@@ -7156,25 +7710,31 @@ pub mod fulfill_spot_order_with_match {
     }
 
     #[test]
-    fn taker_short_selling_base_no_borrow_liquidity() {
+    fn maker_taker_different_market_index() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 0,
             open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
             orders: get_orders(Order {
                 market_index: 1,
                 market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
-                price: 100 * PRICE_PRECISION_U64,
+                auction_start_price: 100 * PRICE_PRECISION_I64,
+                auction_end_price: 200 * PRICE_PRECISION_I64,
+                auction_duration: 5,
                 ..Order::default()
             }),
             spot_positions: taker_spot_positions,
@@ -7182,25 +7742,21 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
             orders: get_orders(Order {
-                market_index: 1,
+                market_index: 2,
                 post_only: true,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
+                direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
@@ -7210,7 +7766,7 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut base_market = SpotMarket {
-            deposit_balance: 0,
+            deposit_balance: SPOT_BALANCE_PRECISION,
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
@@ -7228,7 +7784,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        let (base_filled, _) = fulfill_spot_order_with_match(
+        let (base_asset_amount, _) = fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -7249,29 +7805,35 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_filled, 0);
+        assert_eq!(base_asset_amount, 0);
     }
 
     #[test]
-    fn taker_short_selling_base_small_borrow_liquidity() {
+    fn long_taker_order_bigger_than_maker() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 0,
             open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
+            open_bids: 100 * LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
             orders: get_orders(Order {
                 market_index: 1,
                 market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
+                base_asset_amount: 100 * LAMPORTS_PER_SOL_U64,
                 slot: 0,
-                price: 100 * PRICE_PRECISION_U64,
+                auction_start_price: 100 * PRICE_PRECISION_I64,
+                auction_end_price: 200 * PRICE_PRECISION_I64,
+                auction_duration: 5,
                 ..Order::default()
             }),
             spot_positions: taker_spot_positions,
@@ -7279,16 +7841,12 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
@@ -7297,7 +7855,7 @@ pub mod fulfill_spot_order_with_match {
                 post_only: true,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
+                direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
@@ -7308,13 +7866,10 @@ pub mod fulfill_spot_order_with_match {
 
         let mut base_market = SpotMarket {
             deposit_balance: SPOT_BALANCE_PRECISION,
-            deposit_token_twap: SPOT_BALANCE_PRECISION as u64,
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
             deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            deposit_token_twap: 101 * QUOTE_PRECISION_U64,
-
             ..SpotMarket::default_quote_market()
         };
 
@@ -7328,7 +7883,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        let (base_filled, _) = fulfill_spot_order_with_match(
+        let (base_asset_amount, _) = fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -7349,29 +7904,73 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_filled, 166666666);
+        assert_eq!(base_asset_amount, LAMPORTS_PER_SOL_U64);
+
+        let taker_quote_position = taker.spot_positions[0];
+        assert_eq!(taker_quote_position.scaled_balance, 950000000);
+
+        let taker_base_position = taker.spot_positions[1];
+        assert_eq!(
+            taker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(taker_base_position.open_bids, 99 * LAMPORTS_PER_SOL_I64);
+        assert_eq!(taker_base_position.open_orders, 1);
+
+        let taker_order = taker.orders[0];
+        assert_eq!(taker_order.base_asset_amount_filled, LAMPORTS_PER_SOL_U64);
+        assert_eq!(
+            taker_order.quote_asset_amount_filled,
+            100 * QUOTE_PRECISION_U64
+        );
+
+        assert_eq!(taker_stats.taker_volume_30d, 100000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
+
+        let maker_quote_position = maker.spot_positions[0];
+        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
+
+        let maker_base_position = maker.spot_positions[1];
+        assert_eq!(maker_base_position.scaled_balance, 0);
+        assert_eq!(maker_base_position.open_asks, 0);
+        assert_eq!(maker_base_position.open_orders, 0);
+
+        let maker_order = maker.orders[0];
+        assert_eq!(maker_order, Order::default());
+
+        assert_eq!(maker_stats.maker_volume_30d, 100000000);
+        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
+
+        assert_eq!(base_market.total_spot_fee, 20000);
+        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
     }
 
     #[test]
-    fn maker_short_selling_quote_no_borrow_liquidity() {
+    fn long_taker_order_smaller_than_maker() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
             orders: get_orders(Order {
                 market_index: 1,
                 market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
-                price: 100 * PRICE_PRECISION_U64,
+                auction_start_price: 100 * PRICE_PRECISION_I64,
+                auction_end_price: 200 * PRICE_PRECISION_I64,
+                auction_duration: 5,
                 ..Order::default()
             }),
             spot_positions: taker_spot_positions,
@@ -7379,16 +7978,12 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 0,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -100 * LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
@@ -7397,8 +7992,8 @@ pub mod fulfill_spot_order_with_match {
                 post_only: true,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                direction: PositionDirection::Short,
+                base_asset_amount: 100 * LAMPORTS_PER_SOL_U64,
                 price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
@@ -7411,7 +8006,7 @@ pub mod fulfill_spot_order_with_match {
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
-            deposit_balance: 0,
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
             ..SpotMarket::default_quote_market()
         };
 
@@ -7425,7 +8020,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        let (base_filled, _) = fulfill_spot_order_with_match(
+        let (base_asset_amount, _) = fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -7446,29 +8041,73 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_filled, 0);
+        assert_eq!(base_asset_amount, LAMPORTS_PER_SOL_U64);
+
+        let taker_quote_position = taker.spot_positions[0];
+        assert_eq!(taker_quote_position.scaled_balance, 950000000);
+
+        let taker_base_position = taker.spot_positions[1];
+        assert_eq!(
+            taker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(taker_base_position.open_bids, 0);
+        assert_eq!(taker_base_position.open_orders, 0);
+
+        let taker_order = taker.orders[0];
+        assert_eq!(taker_order, Order::default());
+
+        assert_eq!(taker_stats.taker_volume_30d, 100000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
+
+        let maker_quote_position = maker.spot_positions[0];
+        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
+
+        let maker_base_position = maker.spot_positions[1];
+        assert_eq!(maker_base_position.scaled_balance, 0);
+        assert_eq!(maker_base_position.open_asks, -99 * LAMPORTS_PER_SOL_I64);
+        assert_eq!(maker_base_position.open_orders, 1);
+
+        let maker_order = maker.orders[0];
+        assert_eq!(maker_order.base_asset_amount_filled, LAMPORTS_PER_SOL_U64);
+        assert_eq!(
+            maker_order.quote_asset_amount_filled,
+            100 * QUOTE_PRECISION_U64
+        );
+
+        assert_eq!(maker_stats.maker_volume_30d, 100000000);
+        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
+
+        assert_eq!(base_market.total_spot_fee, 20000);
+        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
     }
 
     #[test]
-    fn maker_short_selling_quote_little_borrow_liquidity() {
+    fn double_dutch_auction() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
             orders: get_orders(Order {
                 market_index: 1,
                 market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
-                price: 100 * PRICE_PRECISION_U64,
+                auction_start_price: 100 * PRICE_PRECISION_I64,
+                auction_end_price: 200 * PRICE_PRECISION_I64,
+                auction_duration: 5,
                 ..Order::default()
             }),
             spot_positions: taker_spot_positions,
@@ -7476,16 +8115,12 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 0,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
@@ -7493,10 +8128,12 @@ pub mod fulfill_spot_order_with_match {
                 market_index: 1,
                 post_only: true,
                 market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
-                price: 100 * PRICE_PRECISION_U64,
+                auction_start_price: 200 * PRICE_PRECISION_I64,
+                auction_end_price: 100 * PRICE_PRECISION_I64,
+                auction_duration: 5,
                 ..Order::default()
             }),
             spot_positions: maker_spot_positions,
@@ -7505,17 +8142,15 @@ pub mod fulfill_spot_order_with_match {
 
         let mut base_market = SpotMarket {
             deposit_balance: SPOT_BALANCE_PRECISION,
-            deposit_token_twap: SPOT_BALANCE_PRECISION as u64,
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
-            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
-            deposit_token_twap: 100 * QUOTE_PRECISION_U64,
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
             ..SpotMarket::default_quote_market()
         };
 
-        let now = 1_i64;
-        let slot = 1_u64;
+        let now = 5_i64;
+        let slot = 5_u64;
 
         let fee_structure = get_fee_structure();
 
@@ -7524,7 +8159,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        let (base_filled, _) = fulfill_spot_order_with_match(
+        fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -7545,15 +8180,41 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_filled, 166666660);
+        let taker_quote_position = taker.spot_positions[0];
+        assert_eq!(taker_quote_position.scaled_balance, 950000000);
+
+        let taker_base_position = taker.spot_positions[1];
+        assert_eq!(
+            taker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(taker_base_position.open_bids, 0);
+        assert_eq!(taker_base_position.open_orders, 0);
+
+        assert_eq!(taker_stats.taker_volume_30d, 100000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
+
+        let maker_quote_position = maker.spot_positions[0];
+        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
+
+        let maker_base_position = maker.spot_positions[1];
+        assert_eq!(maker_base_position.scaled_balance, 0);
+        assert_eq!(maker_base_position.open_bids, 0);
+        assert_eq!(maker_base_position.open_orders, 0);
+
+        assert_eq!(maker_stats.maker_volume_30d, 100000000);
+        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
+
+        assert_eq!(base_market.total_spot_fee, 20000);
+        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
     }
 
     #[test]
-    fn taker_short_selling_quote_no_borrow_liquidity() {
+    fn taker_bid_crosses_maker_ask() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
         taker_spot_positions[0] = SpotPosition {
             market_index: 0,
-            scaled_balance: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
             balance_type: SpotBalanceType::Deposit,
             ..SpotPosition::default()
         };
@@ -7604,12 +8265,10 @@ pub mod fulfill_spot_order_with_match {
 
         let mut base_market = SpotMarket {
             deposit_balance: SPOT_BALANCE_PRECISION,
-            deposit_token_twap: SPOT_BALANCE_PRECISION as u64,
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
-            deposit_balance: 0,
-            deposit_token_twap: 0,
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
             ..SpotMarket::default_quote_market()
         };
 
@@ -7623,7 +8282,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        let (base_filled, _) = fulfill_spot_order_with_match(
+        fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -7644,22 +8303,44 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_filled, 0);
+        let taker_quote_position = taker.spot_positions[0];
+        assert_eq!(taker_quote_position.scaled_balance, 950000000);
+
+        let taker_base_position = taker.spot_positions[1];
+        assert_eq!(
+            taker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(taker_base_position.open_bids, 0);
+        assert_eq!(taker_base_position.open_orders, 0);
+
+        assert_eq!(taker_stats.taker_volume_30d, 100000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
+
+        let maker_quote_position = maker.spot_positions[0];
+        assert_eq!(maker_quote_position.scaled_balance, 100030000000);
+
+        let maker_base_position = maker.spot_positions[1];
+        assert_eq!(maker_base_position.scaled_balance, 0);
+        assert_eq!(maker_base_position.open_bids, 0);
+        assert_eq!(maker_base_position.open_orders, 0);
+
+        assert_eq!(maker_stats.maker_volume_30d, 100000000);
+        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
+
+        assert_eq!(base_market.total_spot_fee, 20000);
+        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
     }
 
     #[test]
-    fn taker_short_selling_quote_little_borrow_liquidity() {
+    fn taker_ask_crosses_maker_bid() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 0,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
@@ -7667,7 +8348,7 @@ pub mod fulfill_spot_order_with_match {
                 market_index: 1,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
+                direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
                 price: 100 * PRICE_PRECISION_U64,
@@ -7678,12 +8359,16 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
@@ -7692,7 +8377,7 @@ pub mod fulfill_spot_order_with_match {
                 post_only: true,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
+                direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
@@ -7703,12 +8388,10 @@ pub mod fulfill_spot_order_with_match {
 
         let mut base_market = SpotMarket {
             deposit_balance: SPOT_BALANCE_PRECISION,
-            deposit_token_twap: SPOT_BALANCE_PRECISION as u64,
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
-            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
-            deposit_token_twap: 100 * QUOTE_PRECISION_U64,
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
             ..SpotMarket::default_quote_market()
         };
 
@@ -7722,7 +8405,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        let (base_filled, _) = fulfill_spot_order_with_match(
+        fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -7743,11 +8426,37 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_filled, 166666660);
+        let taker_quote_position = taker.spot_positions[0];
+        assert_eq!(taker_quote_position.scaled_balance, 99950000000);
+
+        let taker_base_position = taker.spot_positions[1];
+        assert_eq!(taker_base_position.scaled_balance, 0);
+        assert_eq!(taker_base_position.open_bids, 0);
+        assert_eq!(taker_base_position.open_orders, 0);
+
+        assert_eq!(taker_stats.taker_volume_30d, 100000000);
+        assert_eq!(taker_stats.fees.total_fee_paid, 50000);
+
+        let maker_quote_position = maker.spot_positions[0];
+        assert_eq!(maker_quote_position.scaled_balance, 1030000000);
+
+        let maker_base_position = maker.spot_positions[1];
+        assert_eq!(
+            maker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(maker_base_position.open_bids, 0);
+        assert_eq!(maker_base_position.open_orders, 0);
+
+        assert_eq!(maker_stats.maker_volume_30d, 100000000);
+        assert_eq!(maker_stats.fees.total_fee_rebate, 30000);
+
+        assert_eq!(base_market.total_spot_fee, 20000);
+        assert_eq!(base_market.spot_fee_pool.scaled_balance, 20000000);
     }
 
     #[test]
-    fn maker_short_selling_base_no_borrow_liquidity() {
+    fn zero_price_market_order_cant_match() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
         taker_spot_positions[0] = SpotPosition {
             market_index: 0,
@@ -7765,11 +8474,12 @@ pub mod fulfill_spot_order_with_match {
             orders: get_orders(Order {
                 market_index: 1,
                 market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
+                order_type: OrderType::Market,
                 direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
-                price: 100 * PRICE_PRECISION_U64,
+                price: 0,
+                auction_duration: 0,
                 ..Order::default()
             }),
             spot_positions: taker_spot_positions,
@@ -7780,7 +8490,7 @@ pub mod fulfill_spot_order_with_match {
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
             balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 0,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
             open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
@@ -7801,7 +8511,7 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut base_market = SpotMarket {
-            deposit_balance: 0,
+            deposit_balance: SPOT_BALANCE_PRECISION,
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
@@ -7819,7 +8529,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        let (base_filled, _) = fulfill_spot_order_with_match(
+        let (base_asset_amount, _) = fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -7840,22 +8550,18 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_filled, 0);
+        assert_eq!(base_asset_amount, 0);
     }
 
     #[test]
-    fn maker_short_selling_base_little_borrow_liquidity() {
+    fn taker_short_selling_base_no_borrow_liquidity() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 0,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
@@ -7863,7 +8569,7 @@ pub mod fulfill_spot_order_with_match {
                 market_index: 1,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
+                direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
                 price: 100 * PRICE_PRECISION_U64,
@@ -7874,12 +8580,16 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 0,
             open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
@@ -7888,7 +8598,7 @@ pub mod fulfill_spot_order_with_match {
                 post_only: true,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
+                direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
@@ -7898,13 +8608,11 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut base_market = SpotMarket {
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            deposit_token_twap: SPOT_BALANCE_PRECISION as u64,
+            deposit_balance: 0,
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
             deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            deposit_token_twap: 101 * QUOTE_PRECISION_U64,
             ..SpotMarket::default_quote_market()
         };
 
@@ -7939,22 +8647,18 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        assert_eq!(base_filled, 166666666);
+        assert_eq!(base_filled, 0);
     }
 
     #[test]
-    fn max_utilization() {
+    fn taker_short_selling_base_small_borrow_liquidity() {
         let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
-            market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
         taker_spot_positions[1] = SpotPosition {
             market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 0,
             open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
+            open_asks: -LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut taker = User {
@@ -7962,7 +8666,7 @@ pub mod fulfill_spot_order_with_match {
                 market_index: 1,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Long,
+                direction: PositionDirection::Short,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 slot: 0,
                 price: 100 * PRICE_PRECISION_U64,
@@ -7973,12 +8677,16 @@ pub mod fulfill_spot_order_with_match {
         };
 
         let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
         maker_spot_positions[1] = SpotPosition {
             market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
             open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
+            open_bids: LAMPORTS_PER_SOL_I64,
             ..SpotPosition::default()
         };
         let mut maker = User {
@@ -7987,7 +8695,7 @@ pub mod fulfill_spot_order_with_match {
                 post_only: true,
                 market_type: MarketType::Spot,
                 order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
+                direction: PositionDirection::Long,
                 base_asset_amount: LAMPORTS_PER_SOL_U64,
                 price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
@@ -7998,33 +8706,16 @@ pub mod fulfill_spot_order_with_match {
 
         let mut base_market = SpotMarket {
             deposit_balance: SPOT_BALANCE_PRECISION,
-            borrow_balance: SPOT_BALANCE_PRECISION,
-            utilization_twap: SPOT_UTILIZATION_PRECISION as u64,
+            deposit_token_twap: SPOT_BALANCE_PRECISION as u64,
             ..SpotMarket::default_base_market()
         };
         let mut quote_market = SpotMarket {
             deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            borrow_balance: 101 * SPOT_BALANCE_PRECISION,
-            utilization_twap: SPOT_UTILIZATION_PRECISION as u64,
+            deposit_token_twap: 101 * QUOTE_PRECISION_U64,
+
             ..SpotMarket::default_quote_market()
         };
 
-        let base_utilization = calculate_utilization(
-            base_market.get_deposits().unwrap(),
-            base_market.get_borrows().unwrap(),
-        )
-        .unwrap();
-
-        assert_eq!(base_utilization, SPOT_UTILIZATION_PRECISION);
-
-        let quote_utilization = calculate_utilization(
-            base_market.get_deposits().unwrap(),
-            base_market.get_borrows().unwrap(),
-        )
-        .unwrap();
-
-        assert_eq!(quote_utilization, SPOT_UTILIZATION_PRECISION);
-
         let now = 1_i64;
         let slot = 1_u64;
 
@@ -8035,7 +8726,7 @@ pub mod fulfill_spot_order_with_match {
         let mut taker_stats = UserStats::default();
         let mut maker_stats = UserStats::default();
 
-        fulfill_spot_order_with_match(
+        let (base_filled, _) = fulfill_spot_order_with_match(
             &mut base_market,
             &mut quote_market,
             &mut taker,
@@ -8056,237 +8747,3290 @@ pub mod fulfill_spot_order_with_match {
         )
         .unwrap();
 
-        let taker_quote_position = taker.spot_positions[0];
-        assert_eq!(taker_quote_position.scaled_balance, 950000000);
-
-        let taker_base_position = taker.spot_positions[1];
-        assert_eq!(
-            taker_base_position.scaled_balance,
-            SPOT_BALANCE_PRECISION_U64
-        );
-        assert_eq!(taker_base_position.open_bids, 0);
-        assert_eq!(taker_base_position.open_orders, 0);
-
-        let base_utilization = calculate_utilization(
-            base_market.get_deposits().unwrap(),
-            base_market.get_borrows().unwrap(),
-        )
-        .unwrap();
-
-        assert_eq!(base_utilization, SPOT_UTILIZATION_PRECISION);
-
-        let quote_utilization = calculate_utilization(
-            base_market.get_deposits().unwrap(),
-            base_market.get_borrows().unwrap(),
-        )
-        .unwrap();
-
-        assert_eq!(quote_utilization, SPOT_UTILIZATION_PRECISION);
+        assert_eq!(base_filled, 166666666);
     }
-}
 
-pub mod fulfill_spot_order {
-    use std::str::FromStr;
+    #[test]
+    fn maker_short_selling_quote_no_borrow_liquidity() {
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 0,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            ..User::default()
+        };
+
+        let mut base_market = SpotMarket {
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            ..SpotMarket::default_base_market()
+        };
+        let mut quote_market = SpotMarket {
+            deposit_balance: 0,
+            ..SpotMarket::default_quote_market()
+        };
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+        let mut maker_stats = UserStats::default();
+
+        let (base_filled, _) = fulfill_spot_order_with_match(
+            &mut base_market,
+            &mut quote_market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            None,
+            None,
+            &filler_key,
+            now,
+            slot,
+            &mut get_oracle_map(),
+            &fee_structure,
+        )
+        .unwrap();
+
+        assert_eq!(base_filled, 0);
+    }
+
+    #[test]
+    fn maker_short_selling_quote_little_borrow_liquidity() {
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 0,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            ..User::default()
+        };
+
+        let mut base_market = SpotMarket {
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            deposit_token_twap: SPOT_BALANCE_PRECISION as u64,
+            ..SpotMarket::default_base_market()
+        };
+        let mut quote_market = SpotMarket {
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            deposit_token_twap: 100 * QUOTE_PRECISION_U64,
+            ..SpotMarket::default_quote_market()
+        };
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+        let mut maker_stats = UserStats::default();
+
+        let (base_filled, _) = fulfill_spot_order_with_match(
+            &mut base_market,
+            &mut quote_market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            None,
+            None,
+            &filler_key,
+            now,
+            slot,
+            &mut get_oracle_map(),
+            &fee_structure,
+        )
+        .unwrap();
+
+        assert_eq!(base_filled, 166666660);
+    }
+
+    #[test]
+    fn taker_short_selling_quote_no_borrow_liquidity() {
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 0,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            ..User::default()
+        };
+
+        let mut base_market = SpotMarket {
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            deposit_token_twap: SPOT_BALANCE_PRECISION as u64,
+            ..SpotMarket::default_base_market()
+        };
+        let mut quote_market = SpotMarket {
+            deposit_balance: 0,
+            deposit_token_twap: 0,
+            ..SpotMarket::default_quote_market()
+        };
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+        let mut maker_stats = UserStats::default();
+
+        let (base_filled, _) = fulfill_spot_order_with_match(
+            &mut base_market,
+            &mut quote_market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            None,
+            None,
+            &filler_key,
+            now,
+            slot,
+            &mut get_oracle_map(),
+            &fee_structure,
+        )
+        .unwrap();
+
+        assert_eq!(base_filled, 0);
+    }
+
+    #[test]
+    fn taker_short_selling_quote_little_borrow_liquidity() {
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 0,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            ..User::default()
+        };
+
+        let mut base_market = SpotMarket {
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            deposit_token_twap: SPOT_BALANCE_PRECISION as u64,
+            ..SpotMarket::default_base_market()
+        };
+        let mut quote_market = SpotMarket {
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            deposit_token_twap: 100 * QUOTE_PRECISION_U64,
+            ..SpotMarket::default_quote_market()
+        };
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+        let mut maker_stats = UserStats::default();
+
+        let (base_filled, _) = fulfill_spot_order_with_match(
+            &mut base_market,
+            &mut quote_market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            None,
+            None,
+            &filler_key,
+            now,
+            slot,
+            &mut get_oracle_map(),
+            &fee_structure,
+        )
+        .unwrap();
+
+        assert_eq!(base_filled, 166666660);
+    }
+
+    #[test]
+    fn maker_short_selling_base_no_borrow_liquidity() {
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 0,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            ..User::default()
+        };
+
+        let mut base_market = SpotMarket {
+            deposit_balance: 0,
+            ..SpotMarket::default_base_market()
+        };
+        let mut quote_market = SpotMarket {
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            ..SpotMarket::default_quote_market()
+        };
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+        let mut maker_stats = UserStats::default();
+
+        let (base_filled, _) = fulfill_spot_order_with_match(
+            &mut base_market,
+            &mut quote_market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            None,
+            None,
+            &filler_key,
+            now,
+            slot,
+            &mut get_oracle_map(),
+            &fee_structure,
+        )
+        .unwrap();
+
+        assert_eq!(base_filled, 0);
+    }
+
+    #[test]
+    fn maker_short_selling_base_little_borrow_liquidity() {
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 0,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            ..User::default()
+        };
+
+        let mut base_market = SpotMarket {
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            deposit_token_twap: SPOT_BALANCE_PRECISION as u64,
+            ..SpotMarket::default_base_market()
+        };
+        let mut quote_market = SpotMarket {
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            deposit_token_twap: 101 * QUOTE_PRECISION_U64,
+            ..SpotMarket::default_quote_market()
+        };
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+        let mut maker_stats = UserStats::default();
+
+        let (base_filled, _) = fulfill_spot_order_with_match(
+            &mut base_market,
+            &mut quote_market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            None,
+            None,
+            &filler_key,
+            now,
+            slot,
+            &mut get_oracle_map(),
+            &fee_structure,
+        )
+        .unwrap();
+
+        assert_eq!(base_filled, 166666666);
+    }
+
+    #[test]
+    fn max_utilization() {
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            ..User::default()
+        };
+
+        let mut base_market = SpotMarket {
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            borrow_balance: SPOT_BALANCE_PRECISION,
+            utilization_twap: SPOT_UTILIZATION_PRECISION as u64,
+            ..SpotMarket::default_base_market()
+        };
+        let mut quote_market = SpotMarket {
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            borrow_balance: 101 * SPOT_BALANCE_PRECISION,
+            utilization_twap: SPOT_UTILIZATION_PRECISION as u64,
+            ..SpotMarket::default_quote_market()
+        };
+
+        let base_utilization = calculate_utilization(
+            base_market.get_deposits().unwrap(),
+            base_market.get_borrows().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(base_utilization, SPOT_UTILIZATION_PRECISION);
+
+        let quote_utilization = calculate_utilization(
+            base_market.get_deposits().unwrap(),
+            base_market.get_borrows().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(quote_utilization, SPOT_UTILIZATION_PRECISION);
+
+        let now = 1_i64;
+        let slot = 1_u64;
+
+        let fee_structure = get_fee_structure();
+
+        let (taker_key, maker_key, filler_key) = get_user_keys();
+
+        let mut taker_stats = UserStats::default();
+        let mut maker_stats = UserStats::default();
+
+        fulfill_spot_order_with_match(
+            &mut base_market,
+            &mut quote_market,
+            &mut taker,
+            &mut taker_stats,
+            0,
+            &taker_key,
+            &mut maker,
+            &mut Some(&mut maker_stats),
+            0,
+            &maker_key,
+            None,
+            None,
+            &filler_key,
+            now,
+            slot,
+            &mut get_oracle_map(),
+            &fee_structure,
+        )
+        .unwrap();
+
+        let taker_quote_position = taker.spot_positions[0];
+        assert_eq!(taker_quote_position.scaled_balance, 950000000);
+
+        let taker_base_position = taker.spot_positions[1];
+        assert_eq!(
+            taker_base_position.scaled_balance,
+            SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(taker_base_position.open_bids, 0);
+        assert_eq!(taker_base_position.open_orders, 0);
+
+        let base_utilization = calculate_utilization(
+            base_market.get_deposits().unwrap(),
+            base_market.get_borrows().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(base_utilization, SPOT_UTILIZATION_PRECISION);
+
+        let quote_utilization = calculate_utilization(
+            base_market.get_deposits().unwrap(),
+            base_market.get_borrows().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(quote_utilization, SPOT_UTILIZATION_PRECISION);
+    }
+}
+
+pub mod fulfill_spot_order {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::{AccountLoader, Clock};
+
+    use crate::controller::orders::fill_spot_order;
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        LAMPORTS_PER_SOL_I64, LAMPORTS_PER_SOL_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64,
+        SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
+    };
+    use crate::state::fill_mode::FillMode;
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_fulfillment_params::TestFulfillmentParams;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{MarketType, OrderStatus, OrderType, SpotPosition, User, UserStats};
+    use crate::state::user_map::{UserMap, UserStatsMap};
+    use crate::test_utils::get_pyth_price;
+    use crate::test_utils::*;
+
+    use super::*;
+
+    // Add back if we check free collateral in fill again
+    // #[test]
+    // fn fulfill_with_negative_free_collateral() {
+    //     let clock = Clock {
+    //         slot: 6,
+    //         epoch_start_timestamp: 0,
+    //         epoch: 0,
+    //         leader_schedule_epoch: 0,
+    //         unix_timestamp: 0,
+    //     };
+    //
+    //     let mut oracle_price = get_pyth_price(100, 6);
+    //     let oracle_price_key =
+    //         Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+    //     let pyth_program = crate::ids::pyth_program::id();
+    //     create_account_info!(
+    //         oracle_price,
+    //         &oracle_price_key,
+    //         &pyth_program,
+    //         oracle_account_info
+    //     );
+    //     let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+    //
+    //     let perp_market_map = PerpMarketMap::empty();
+    //
+    //     let mut base_market = SpotMarket {
+    //         oracle: oracle_price_key,
+    //         market_index: 1,
+    //         deposit_balance: SPOT_BALANCE_PRECISION,
+    //         ..SpotMarket::default_base_market()
+    //     };
+    //     create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
+    //     let mut quote_market = SpotMarket {
+    //         deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+    //         ..SpotMarket::default_quote_market()
+    //     };
+    //     create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
+    //     let spot_market_map = SpotMarketMap::load_multiple(
+    //         vec![&base_market_account_info, &quote_market_account_info],
+    //         true,
+    //     )
+    //     .unwrap();
+    //
+    //     let mut taker_spot_positions = [SpotPosition::default(); 8];
+    //     taker_spot_positions[0] = SpotPosition {
+    //         market_index: 0,
+    //         scaled_balance: SPOT_BALANCE_PRECISION_U64,
+    //         balance_type: SpotBalanceType::Deposit,
+    //         ..SpotPosition::default()
+    //     };
+    //     taker_spot_positions[1] = SpotPosition {
+    //         market_index: 1,
+    //         open_orders: 1,
+    //         open_bids: LAMPORTS_PER_SOL_I64,
+    //         ..SpotPosition::default()
+    //     };
+    //     let mut taker = User {
+    //         orders: get_orders(Order {
+    //             order_id: 1,
+    //             market_index: 1,
+    //             market_type: MarketType::Spot,
+    //             order_type: OrderType::Market,
+    //             status: OrderStatus::Open,
+    //             direction: PositionDirection::Long,
+    //             base_asset_amount: LAMPORTS_PER_SOL_U64,
+    //             slot: 0,
+    //             auction_start_price: 100 * PRICE_PRECISION_U64,
+    //             auction_end_price: 200 * PRICE_PRECISION_U64,
+    //             auction_duration: 5,
+    //             price: 100 * PRICE_PRECISION_U64,
+    //             ..Order::default()
+    //         }),
+    //         spot_positions: taker_spot_positions,
+    //         ..User::default()
+    //     };
+    //
+    //     create_anchor_account_info!(taker, User, taker_account_info);
+    //     let taker_account_loader: AccountLoader<User> =
+    //         AccountLoader::try_from(&taker_account_info).unwrap();
+    //
+    //     create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
+    //     let taker_stats_account_loader: AccountLoader<UserStats> =
+    //         AccountLoader::try_from(&taker_stats_account_info).unwrap();
+    //
+    //     let mut maker_spot_positions = [SpotPosition::default(); 8];
+    //     maker_spot_positions[1] = SpotPosition {
+    //         market_index: 1,
+    //         balance_type: SpotBalanceType::Deposit,
+    //         scaled_balance: SPOT_BALANCE_PRECISION_U64,
+    //         open_orders: 1,
+    //         open_asks: -LAMPORTS_PER_SOL_I64 / 2,
+    //         ..SpotPosition::default()
+    //     };
+    //     let mut maker = User {
+    //         orders: get_orders(Order {
+    //             order_id: 1,
+    //             market_index: 1,
+    //             post_only: true,
+    //             market_type: MarketType::Spot,
+    //             order_type: OrderType::Limit,
+    //             status: OrderStatus::Open,
+    //             direction: PositionDirection::Short,
+    //             base_asset_amount: LAMPORTS_PER_SOL_U64 / 2,
+    //             price: 100 * PRICE_PRECISION_U64,
+    //             ..Order::default()
+    //         }),
+    //         spot_positions: maker_spot_positions,
+    //         ..User::default()
+    //     };
+    //
+    //     let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+    //     create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+    //     let maker_account_loader: AccountLoader<User> =
+    //         AccountLoader::try_from(&maker_account_info).unwrap();
+    //
+    //     create_anchor_account_info!(UserStats::default(), UserStats, maker_stats_account_info);
+    //     let maker_stats_account_loader: AccountLoader<UserStats> =
+    //         AccountLoader::try_from(&maker_stats_account_info).unwrap();
+    //
+    //     let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+    //     create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+    //     let filler_account_loader: AccountLoader<User> =
+    //         AccountLoader::try_from(&user_account_info).unwrap();
+    //
+    //     create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+    //     let filler_stats_account_loader: AccountLoader<UserStats> =
+    //         AccountLoader::try_from(&filler_stats_account_info).unwrap();
+    //
+    //     let state = State {
+    //         default_spot_auction_duration: 1,
+    //         ..State::default()
+    //     };
+    //
+    //     let free_collateral =
+    //         calculate_free_collateral(&taker, &perp_market_map, &spot_market_map, &mut oracle_map)
+    //             .unwrap();
+    //     assert_eq!(free_collateral, -19000000);
+    //
+    //     let base_asset_amount = fill_spot_order(
+    //         1,
+    //         &state,
+    //         &taker_account_loader,
+    //         &taker_stats_account_loader,
+    //         &spot_market_map,
+    //         &perp_market_map,
+    //         &mut oracle_map,
+    //         &filler_account_loader,
+    //         &filler_stats_account_loader,
+    //         Some(&maker_account_loader),
+    //         Some(&maker_stats_account_loader),
+    //         Some(1),
+    //         &clock,
+    //         &mut None,
+    //     )
+    //     .unwrap();
+    //
+    //     assert_eq!(base_asset_amount, 0); // cancel should be canceled
+    //     let taker_after = taker_account_loader.load().unwrap();
+    //     assert_eq!(taker_after.orders[0], Order::default()); // order canceled
+    //
+    //     let free_collateral = calculate_free_collateral(
+    //         &taker_after,
+    //         &perp_market_map,
+    //         &spot_market_map,
+    //         &mut oracle_map,
+    //     )
+    //     .unwrap();
+    //     assert_eq!(free_collateral, 1000000);
+    //
+    //     let maker_after = maker_account_loader.load().unwrap();
+    //     assert_eq!(*maker_after, maker); // maker should not have changed
+    // }
+
+    #[test]
+    fn fulfill_users_with_multiple_orders_and_markets() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let perp_market_map = PerpMarketMap::empty();
+
+        let mut base_market = SpotMarket {
+            market_index: 1,
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            oracle: oracle_price_key,
+            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
+            ..SpotMarket::default_base_market()
+        };
+        create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
+        let mut second_base_market = SpotMarket {
+            market_index: 2,
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
+            ..SpotMarket::default_base_market()
+        };
+        create_anchor_account_info!(
+            second_base_market,
+            SpotMarket,
+            second_base_market_account_info
+        );
+        let mut quote_market = SpotMarket {
+            market_index: 0,
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            ..SpotMarket::default_quote_market()
+        };
+        create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![
+                &base_market_account_info,
+                &quote_market_account_info,
+                &second_base_market_account_info,
+            ],
+            true,
+        )
+        .unwrap();
+
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[2] = SpotPosition {
+            market_index: 2,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker_orders = [Order::default(); 32];
+        taker_orders[0] = Order {
+            order_id: 1,
+            market_index: 1,
+            market_type: MarketType::Spot,
+            order_type: OrderType::Market,
+            status: OrderStatus::Open,
+            direction: PositionDirection::Long,
+            base_asset_amount: LAMPORTS_PER_SOL_U64,
+            slot: 0,
+            auction_start_price: 100 * PRICE_PRECISION_I64,
+            auction_end_price: 200 * PRICE_PRECISION_I64,
+            auction_duration: 5,
+            price: 100 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        taker_orders[1] = Order {
+            order_id: 2,
+            market_index: 2,
+            market_type: MarketType::Spot,
+            order_type: OrderType::Market,
+            status: OrderStatus::Open,
+            direction: PositionDirection::Long,
+            base_asset_amount: LAMPORTS_PER_SOL_U64,
+            slot: 0,
+            auction_start_price: 100 * PRICE_PRECISION_I64,
+            auction_end_price: 200 * PRICE_PRECISION_I64,
+            auction_duration: 5,
+            price: 100 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        let mut taker = User {
+            orders: taker_orders,
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        create_anchor_account_info!(taker, User, taker_account_info);
+        let taker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&taker_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
+        let taker_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&taker_stats_account_info).unwrap();
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 2,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64 / 2,
+            ..SpotPosition::default()
+        };
+        maker_spot_positions[2] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker_orders = [Order::default(); 32];
+        maker_orders[0] = Order {
+            order_id: 2,
+            market_index: 2,
+            post_only: true,
+            market_type: MarketType::Spot,
+            order_type: OrderType::Limit,
+            status: OrderStatus::Open,
+            direction: PositionDirection::Short,
+            base_asset_amount: LAMPORTS_PER_SOL_U64,
+            price: 100 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        maker_orders[1] = Order {
+            order_id: 1,
+            market_index: 1,
+            post_only: true,
+            market_type: MarketType::Spot,
+            order_type: OrderType::Limit,
+            status: OrderStatus::Open,
+            direction: PositionDirection::Short,
+            base_asset_amount: LAMPORTS_PER_SOL_U64,
+            price: 100 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        let mut maker = User {
+            orders: maker_orders,
+            spot_positions: maker_spot_positions,
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..User::default()
+        };
+
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let maker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&maker_account_info).unwrap();
+
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let mut maker_stats = UserStats {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&maker_stats_account_info).unwrap();
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            default_spot_auction_duration: 1,
+            ..State::default()
+        };
+
+        let mut expected_taker = taker;
+        expected_taker.orders[0] = Order::default();
+        expected_taker.spot_positions[0] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 900000000,
+            cumulative_deposits: -100000000,
+            ..SpotPosition::default()
+        };
+        expected_taker.spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            cumulative_deposits: 1000000000,
+            ..SpotPosition::default()
+        };
+        expected_taker.cumulative_spot_fees = -100000;
+        expected_taker.last_active_slot = clock.slot;
+
+        let mut expected_maker = maker;
+        expected_maker.orders[1] = Order::default();
+        expected_maker.spot_positions[0] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100020000000,
+            cumulative_deposits: 100000000,
+            ..SpotPosition::default()
+        };
+        expected_maker.spot_positions[2] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 0,
+            cumulative_deposits: -1000000000,
+            ..SpotPosition::default()
+        };
+        expected_maker.cumulative_spot_fees = 20000;
+        expected_maker.last_active_slot = clock.slot;
+
+        let base_asset_amount = fill_spot_order(
+            1,
+            &state,
+            &taker_account_loader,
+            &taker_stats_account_loader,
+            &spot_market_map,
+            &perp_market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
+            None,
+            &clock,
+            &mut TestFulfillmentParams {},
+            FillMode::Fill,
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, 1000000000); // full order filled by maker
+        let taker_after = taker_account_loader.load().unwrap();
+        assert_eq!(*taker_after, expected_taker);
+
+        let taker_stats_after = taker_stats_account_loader.load().unwrap();
+        assert_eq!(taker_stats_after.fees.total_fee_paid, 100000);
+
+        let maker_after = maker_account_loader.load().unwrap();
+        assert_eq!(*maker_after, expected_maker);
+
+        let maker_stats_after = maker_stats_account_loader.load().unwrap();
+        assert_eq!(maker_stats_after.fees.total_fee_rebate, 20000);
+    }
+
+    #[test]
+    fn fulfill_users_with_multiple_maker_orders() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let perp_market_map = PerpMarketMap::empty();
+
+        let mut base_market = SpotMarket {
+            market_index: 1,
+            deposit_balance: 2 * SPOT_BALANCE_PRECISION,
+            oracle: oracle_price_key,
+            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
+            ..SpotMarket::default_base_market()
+        };
+        create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
+        let mut quote_market = SpotMarket {
+            market_index: 0,
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            ..SpotMarket::default_quote_market()
+        };
+        create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&base_market_account_info, &quote_market_account_info],
+            true,
+        )
+        .unwrap();
+
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 201 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: 2 * LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker_orders = [Order::default(); 32];
+        taker_orders[0] = Order {
+            order_id: 1,
+            market_index: 1,
+            market_type: MarketType::Spot,
+            order_type: OrderType::Market,
+            status: OrderStatus::Open,
+            direction: PositionDirection::Long,
+            base_asset_amount: 2 * LAMPORTS_PER_SOL_U64,
+            slot: 0,
+            auction_start_price: 100 * PRICE_PRECISION_I64,
+            auction_end_price: 200 * PRICE_PRECISION_I64,
+            auction_duration: 5,
+            price: 100 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        let mut taker = User {
+            orders: taker_orders,
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        create_anchor_account_info!(taker, User, taker_account_info);
+        let taker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&taker_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
+        let taker_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&taker_stats_account_info).unwrap();
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker_orders = [Order::default(); 32];
+        maker_orders[1] = Order {
+            order_id: 1,
+            market_index: 1,
+            post_only: true,
+            market_type: MarketType::Spot,
+            order_type: OrderType::Limit,
+            status: OrderStatus::Open,
+            direction: PositionDirection::Short,
+            base_asset_amount: LAMPORTS_PER_SOL_U64,
+            price: 100 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        let mut maker = User {
+            orders: maker_orders,
+            spot_positions: maker_spot_positions,
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..User::default()
+        };
+
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let maker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&maker_account_info).unwrap();
+
+        let mut second_maker_spot_positions = [SpotPosition::default(); 8];
+        second_maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut second_maker_orders = [Order::default(); 32];
+        second_maker_orders[1] = Order {
+            order_id: 1,
+            market_index: 1,
+            post_only: true,
+            market_type: MarketType::Spot,
+            order_type: OrderType::Limit,
+            status: OrderStatus::Open,
+            direction: PositionDirection::Short,
+            base_asset_amount: LAMPORTS_PER_SOL_U64,
+            price: 100 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        let mut second_maker = User {
+            orders: second_maker_orders,
+            spot_positions: second_maker_spot_positions,
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..User::default()
+        };
+
+        let second_maker_key =
+            Pubkey::from_str("My11111111111111111111111111111111111111114").unwrap();
+        create_anchor_account_info!(
+            second_maker,
+            &second_maker_key,
+            User,
+            second_maker_account_info
+        );
+        let second_maker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&second_maker_account_info).unwrap();
+
+        let mut makers_and_referrers = UserMap::empty();
+        makers_and_referrers
+            .insert(maker_key, maker_account_loader)
+            .unwrap();
+        makers_and_referrers
+            .insert(second_maker_key, second_maker_account_loader)
+            .unwrap();
+
+        let mut maker_stats = UserStats {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&maker_stats_account_info).unwrap();
+
+        let second_maker_authority =
+            Pubkey::from_str("My11111111111111111111111111111111111111114").unwrap();
+        let mut second_maker_stats = UserStats {
+            authority: second_maker_authority,
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(
+            second_maker_stats,
+            UserStats,
+            second_maker_stats_account_info
+        );
+        let second_maker_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&second_maker_stats_account_info).unwrap();
+
+        let mut maker_and_referrer_stats =
+            UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+        maker_and_referrer_stats
+            .insert(second_maker_key, second_maker_stats_account_loader)
+            .unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            default_spot_auction_duration: 1,
+            ..State::default()
+        };
+
+        let mut expected_taker = taker;
+        expected_taker.orders[0] = Order::default();
+        expected_taker.spot_positions[0] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 800000000,
+            cumulative_deposits: -200000000,
+            ..SpotPosition::default()
+        };
+        expected_taker.spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 2 * SPOT_BALANCE_PRECISION_U64,
+            cumulative_deposits: 2000000000,
+            ..SpotPosition::default()
+        };
+        expected_taker.cumulative_spot_fees = -200000;
+        expected_taker.last_active_slot = clock.slot;
+
+        let mut expected_maker = maker;
+        expected_maker.orders[1] = Order::default();
+        expected_maker.spot_positions[0] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100020000000,
+            cumulative_deposits: 100000000,
+            ..SpotPosition::default()
+        };
+        expected_maker.spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 0,
+            cumulative_deposits: -1000000000,
+            ..SpotPosition::default()
+        };
+        expected_maker.cumulative_spot_fees = 20000;
+        expected_maker.last_active_slot = clock.slot;
+
+        let base_asset_amount = fill_spot_order(
+            1,
+            &state,
+            &taker_account_loader,
+            &taker_stats_account_loader,
+            &spot_market_map,
+            &perp_market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
+            None,
+            &clock,
+            &mut TestFulfillmentParams {},
+            FillMode::Fill,
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, 2000000000); // full order filled by maker
+        let taker_after = taker_account_loader.load().unwrap();
+        assert_eq!(*taker_after, expected_taker);
+
+        let taker_stats_after = taker_stats_account_loader.load().unwrap();
+        assert_eq!(taker_stats_after.fees.total_fee_paid, 200000);
+
+        let maker_after = makers_and_referrers.get_ref(&maker_key).unwrap();
+        assert_eq!(*maker_after, expected_maker);
+
+        let second_maker_after = makers_and_referrers.get_ref(&second_maker_key).unwrap();
+        assert_eq!(*second_maker_after, expected_maker);
+
+        let maker_stats_after = maker_stats_account_loader.load().unwrap();
+        assert_eq!(maker_stats_after.fees.total_fee_rebate, 40000);
+    }
+
+    #[test]
+    fn maker_insufficient_collateral() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let perp_market_map = PerpMarketMap::empty();
+
+        let mut base_market = SpotMarket {
+            market_index: 1,
+            deposit_balance: 10 * SPOT_BALANCE_PRECISION,
+            deposit_token_twap: 10 * LAMPORTS_PER_SOL_U64,
+            oracle: oracle_price_key,
+            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
+            ..SpotMarket::default_base_market()
+        };
+        create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
+        let mut quote_market = SpotMarket {
+            market_index: 0,
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            ..SpotMarket::default_quote_market()
+        };
+        create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&base_market_account_info, &quote_market_account_info],
+            true,
+        )
+        .unwrap();
+
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker_orders = [Order::default(); 32];
+        taker_orders[0] = Order {
+            order_id: 1,
+            market_index: 1,
+            market_type: MarketType::Spot,
+            order_type: OrderType::Market,
+            status: OrderStatus::Open,
+            direction: PositionDirection::Long,
+            base_asset_amount: LAMPORTS_PER_SOL_U64,
+            slot: 0,
+            auction_start_price: 100 * PRICE_PRECISION_I64,
+            auction_end_price: 200 * PRICE_PRECISION_I64,
+            auction_duration: 5,
+            price: 100 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        let mut taker = User {
+            orders: taker_orders,
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        create_anchor_account_info!(taker, User, taker_account_info);
+        let taker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&taker_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
+        let taker_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&taker_stats_account_info).unwrap();
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[2] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64 / 10,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker_orders = [Order::default(); 32];
+        maker_orders[1] = Order {
+            order_id: 1,
+            market_index: 1,
+            post_only: true,
+            market_type: MarketType::Spot,
+            order_type: OrderType::Limit,
+            status: OrderStatus::Open,
+            direction: PositionDirection::Short,
+            base_asset_amount: LAMPORTS_PER_SOL_U64,
+            price: 100 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        let mut maker = User {
+            orders: maker_orders,
+            spot_positions: maker_spot_positions,
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..User::default()
+        };
+
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let mut maker_stats = UserStats {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            default_spot_auction_duration: 1,
+            ..State::default()
+        };
+
+        let result = fill_spot_order(
+            1,
+            &state,
+            &taker_account_loader,
+            &taker_stats_account_loader,
+            &spot_market_map,
+            &perp_market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
+            None,
+            &clock,
+            &mut TestFulfillmentParams {},
+            FillMode::Fill,
+        );
+
+        assert_eq!(result, Err(ErrorCode::InsufficientCollateral));
+    }
+}
+
+pub mod fill_spot_order {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::{AccountLoader, Clock};
+
+    use crate::controller::orders::{fill_spot_order, validate_fill_or_kill_order_filled};
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::math::constants::{
+        LAMPORTS_PER_SOL_I64, LAMPORTS_PER_SOL_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64,
+        SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
+    };
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_fulfillment_params::TestFulfillmentParams;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{
+        MarketType, OrderBitFlag, OrderStatus, OrderType, SpotPosition, User, UserStats,
+    };
+    use crate::state::user_map::{UserMap, UserStatsMap};
+    use crate::test_utils::*;
+    use crate::test_utils::{create_account_info, get_orders, get_pyth_price};
+
+    use super::*;
+    use crate::error::ErrorCode;
+
+    #[test]
+    fn expire_order() {
+        let clock = Clock {
+            slot: 11,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 11,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let perp_market_map = PerpMarketMap::empty();
+
+        let mut base_market = SpotMarket {
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
+            ..SpotMarket::default_base_market()
+        };
+        create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
+        let mut quote_market = SpotMarket {
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            ..SpotMarket::default_quote_market()
+        };
+        create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&base_market_account_info, &quote_market_account_info],
+            true,
+        )
+        .unwrap();
+
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                order_id: 1,
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Market,
+                status: OrderStatus::Open,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                auction_start_price: 100 * PRICE_PRECISION_I64,
+                auction_end_price: 200 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 100 * PRICE_PRECISION_U64,
+                max_ts: 10,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        create_anchor_account_info!(taker, User, taker_account_info);
+        let taker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&taker_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
+        let taker_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&taker_stats_account_info).unwrap();
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64 / 2,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                order_id: 1,
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                status: OrderStatus::Open,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64 / 2,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            ..User::default()
+        };
+
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            default_spot_auction_duration: 1,
+            ..State::default()
+        };
+
+        let base_asset_amount = fill_spot_order(
+            1,
+            &state,
+            &taker_account_loader,
+            &taker_stats_account_loader,
+            &spot_market_map,
+            &perp_market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
+            None,
+            &clock,
+            &mut TestFulfillmentParams {},
+            FillMode::Fill,
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, 0); // half of order filled by maker
+        let taker_after = taker_account_loader.load().unwrap();
+        assert_eq!(taker_after.orders[0], Order::default()); // order expired
+    }
+
+    #[test]
+    fn fill_or_kill_order_partially_filled() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let perp_market_map = PerpMarketMap::empty();
+
+        let mut base_market = SpotMarket {
+            market_index: 1,
+            deposit_balance: 10 * SPOT_BALANCE_PRECISION,
+            deposit_token_twap: 10 * LAMPORTS_PER_SOL_U64,
+            oracle: oracle_price_key,
+            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
+            ..SpotMarket::default_base_market()
+        };
+        create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
+        let mut quote_market = SpotMarket {
+            market_index: 0,
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            ..SpotMarket::default_quote_market()
+        };
+        create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&base_market_account_info, &quote_market_account_info],
+            true,
+        )
+        .unwrap();
+
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                order_id: 1,
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                status: OrderStatus::Open,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                bit_flags: OrderBitFlag::FillOrKill as u8,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        create_anchor_account_info!(taker, User, taker_account_info);
+        let taker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&taker_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
+        let taker_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&taker_stats_account_info).unwrap();
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64 / 2,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                order_id: 1,
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                status: OrderStatus::Open,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64 / 2,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..User::default()
+        };
+
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let mut maker_stats = UserStats {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            default_spot_auction_duration: 1,
+            ..State::default()
+        };
+
+        let base_asset_amount = fill_spot_order(
+            1,
+            &state,
+            &taker_account_loader,
+            &taker_stats_account_loader,
+            &spot_market_map,
+            &perp_market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
+            None,
+            &clock,
+            &mut TestFulfillmentParams {},
+            FillMode::Fill,
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, LAMPORTS_PER_SOL_U64 / 2);
+
+        let taker = taker_account_loader.load().unwrap();
+        let result =
+            validate_fill_or_kill_order_filled(&taker, 1, LAMPORTS_PER_SOL_U64, base_asset_amount);
+        assert_eq!(result, Err(ErrorCode::FillOrKillOrderNotFilled));
+    }
+
+    #[test]
+    fn fill_or_kill_order_completely_filled() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let perp_market_map = PerpMarketMap::empty();
+
+        let mut base_market = SpotMarket {
+            market_index: 1,
+            deposit_balance: 10 * SPOT_BALANCE_PRECISION,
+            deposit_token_twap: 10 * LAMPORTS_PER_SOL_U64,
+            oracle: oracle_price_key,
+            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
+            ..SpotMarket::default_base_market()
+        };
+        create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
+        let mut quote_market = SpotMarket {
+            market_index: 0,
+            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
+            ..SpotMarket::default_quote_market()
+        };
+        create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&base_market_account_info, &quote_market_account_info],
+            true,
+        )
+        .unwrap();
+
+        let mut taker_spot_positions = [SpotPosition::default(); 8];
+        taker_spot_positions[0] = SpotPosition {
+            market_index: 0,
+            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
+            balance_type: SpotBalanceType::Deposit,
+            ..SpotPosition::default()
+        };
+        taker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            open_orders: 1,
+            open_bids: LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut taker = User {
+            orders: get_orders(Order {
+                order_id: 1,
+                market_index: 1,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                status: OrderStatus::Open,
+                direction: PositionDirection::Long,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                bit_flags: OrderBitFlag::FillOrKill as u8,
+                ..Order::default()
+            }),
+            spot_positions: taker_spot_positions,
+            ..User::default()
+        };
+
+        create_anchor_account_info!(taker, User, taker_account_info);
+        let taker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&taker_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
+        let taker_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&taker_stats_account_info).unwrap();
+
+        let mut maker_spot_positions = [SpotPosition::default(); 8];
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: SPOT_BALANCE_PRECISION_U64,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut maker = User {
+            orders: get_orders(Order {
+                order_id: 1,
+                market_index: 1,
+                post_only: true,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                status: OrderStatus::Open,
+                direction: PositionDirection::Short,
+                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            spot_positions: maker_spot_positions,
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..User::default()
+        };
+
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let mut maker_stats = UserStats {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            default_spot_auction_duration: 1,
+            ..State::default()
+        };
+
+        let base_asset_amount = fill_spot_order(
+            1,
+            &state,
+            &taker_account_loader,
+            &taker_stats_account_loader,
+            &spot_market_map,
+            &perp_market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
+            None,
+            &clock,
+            &mut TestFulfillmentParams {},
+            FillMode::Fill,
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount, LAMPORTS_PER_SOL_U64);
+
+        let taker = taker_account_loader.load().unwrap();
+        let result =
+            validate_fill_or_kill_order_filled(&taker, 1, LAMPORTS_PER_SOL_U64, base_asset_amount);
+        assert_eq!(result, Ok(()));
+    }
+}
+
+pub mod force_cancel_orders {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::{AccountLoader, Clock};
+
+    use crate::controller::orders::force_cancel_orders;
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, LAMPORTS_PER_SOL_I64,
+        LAMPORTS_PER_SOL_U64, PEG_PRECISION, PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION,
+        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::oracle::OracleSource;
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{MarketType, OrderStatus, OrderType, SpotPosition, User, UserStats};
+    use crate::test_utils::*;
+    use crate::test_utils::{
+        create_account_info, get_positions, get_pyth_price, get_spot_positions,
+    };
+
+    use super::*;
+
+    #[test]
+    fn cancel_order_after_fulfill() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                // bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                // bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                // ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                // ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+
+        let mut sol_spot_market = SpotMarket {
+            market_index: 1,
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            oracle: oracle_price_key,
+            ..SpotMarket::default_base_market()
+        };
+        create_anchor_account_info!(sol_spot_market, SpotMarket, sol_spot_market_account_info);
+
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![
+                &usdc_spot_market_account_info,
+                &sol_spot_market_account_info,
+            ],
+            true,
+        )
+        .unwrap();
+
+        let mut orders = [Order::default(); 32];
+        orders[0] = Order {
+            market_index: 0,
+            order_id: 1,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Long,
+            base_asset_amount: 100 * BASE_PRECISION_U64,
+            slot: 0,
+            price: 102 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        orders[1] = Order {
+            market_index: 0,
+            order_id: 2,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
+            slot: 0,
+            price: 102 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        orders[2] = Order {
+            market_index: 1,
+            order_id: 1,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            market_type: MarketType::Spot,
+            direction: PositionDirection::Long,
+            base_asset_amount: 100 * LAMPORTS_PER_SOL_U64,
+            slot: 0,
+            price: 102 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        orders[3] = Order {
+            market_index: 1,
+            order_id: 1,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            market_type: MarketType::Spot,
+            direction: PositionDirection::Short,
+            base_asset_amount: LAMPORTS_PER_SOL_U64,
+            slot: 0,
+            price: 102 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+
+        let mut user = User {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(), // different authority than filler
+            orders,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64,
+                open_orders: 2,
+                open_bids: 100 * BASE_PRECISION_I64,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 1,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: SPOT_BALANCE_PRECISION_U64,
+                open_orders: 2,
+                open_bids: 100 * LAMPORTS_PER_SOL_I64,
+                open_asks: -LAMPORTS_PER_SOL_I64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, user_stats_account_info);
+        let _user_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&user_stats_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let _filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            min_perp_auction_duration: 1,
+            default_market_order_time_in_force: 10,
+            ..State::default()
+        };
+
+        force_cancel_orders(
+            &state,
+            &user_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &clock,
+        )
+        .unwrap();
+
+        let user = user_account_loader.load().unwrap();
+        assert_eq!(user.orders[0], Order::default());
+        assert_ne!(user.orders[1], Order::default());
+        assert_eq!(user.orders[2], Order::default());
+        assert_ne!(user.orders[3], Order::default());
+
+        assert_eq!(user.spot_positions[0].scaled_balance, 20000001);
+        assert_eq!(user.spot_positions[0].balance_type, SpotBalanceType::Borrow,);
+    }
+}
+
+pub mod cancel_orders_after_deadline {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::{AccountLoader, Clock};
+
+    use crate::controller::orders::cancel_orders_after_deadline;
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::oracle::OracleSource;
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{MarketType, OrderStatus, OrderType, SpotPosition, User};
+    use crate::test_utils::*;
+    use crate::test_utils::{
+        create_account_info, get_orders, get_positions, get_pyth_price, get_spot_positions,
+    };
+
+    use super::*;
+
+    #[test]
+    fn cancel_orders_once_deadline_reached() {
+        let mut clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 99,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
+
+        let mut user = User {
+            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(), // different authority than filler
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                market_type: MarketType::Perp,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                price: 99 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            cancel_orders_after_ts: 100,
+            ..User::default()
+        };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
+        create_anchor_account_info!(User::default(), &filler_key, User, filler_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&filler_account_info).unwrap();
+
+        let state = State::default();
+
+        // deadline not reached
+        let result = cancel_orders_after_deadline(
+            &state,
+            &user_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &clock,
+        );
+        assert_eq!(result, Err(ErrorCode::CancelOrdersDeadlineNotReached));
+
+        {
+            let user = user_account_loader.load().unwrap();
+            assert_eq!(user.orders[0].status, OrderStatus::Open);
+            assert_eq!(user.cancel_orders_after_ts, 100);
+        }
+
+        // deadline reached
+        clock.unix_timestamp = 100;
+        cancel_orders_after_deadline(
+            &state,
+            &user_account_loader,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &filler_account_loader,
+            &clock,
+        )
+        .unwrap();
+
+        let user = user_account_loader.load().unwrap();
+        assert_eq!(user.orders[0], Order::default());
+        assert_eq!(user.perp_positions[0].open_orders, 0);
+        assert_eq!(user.perp_positions[0].open_bids, 0);
+        assert_eq!(user.cancel_orders_after_ts, 0);
+
+        // keeper is paid the flat reward by the user
+        let flat_filler_fee = state.spot_fee_structure.flat_filler_fee as u128;
+        let usdc_spot_market = spot_market_map.get_ref(&0).unwrap();
+        let filler = filler_account_loader.load().unwrap();
+        assert_eq!(
+            filler.spot_positions[0]
+                .get_token_amount(&usdc_spot_market)
+                .unwrap(),
+            flat_filler_fee
+        );
+        assert_eq!(
+            user.spot_positions[0]
+                .get_token_amount(&usdc_spot_market)
+                .unwrap(),
+            100 * 1_000_000 - flat_filler_fee
+        );
+    }
+}
+
+pub mod cancel_linked_orders {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::Clock;
+
+    use crate::controller::orders::cancel_linked_orders;
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION, SPOT_CUMULATIVE_INTEREST_PRECISION,
+        SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::SpotMarket;
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{OrderStatus, OrderTriggerCondition, OrderType, User};
+    use crate::test_utils::*;
+    use crate::test_utils::{create_account_info, get_positions, get_pyth_price};
+
+    use super::*;
+
+    #[test]
+    fn cancel_other_orders_in_group() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            deposit_balance: SPOT_BALANCE_PRECISION,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
+
+        let mut orders = [Order::default(); 32];
+        // entry
+        orders[0] = Order {
+            market_index: 0,
+            order_id: 1,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Long,
+            base_asset_amount: BASE_PRECISION_U64,
+            price: 100 * PRICE_PRECISION_U64,
+            ..Order::default()
+        };
+        // take profit
+        orders[1] = Order {
+            market_index: 0,
+            order_id: 2,
+            status: OrderStatus::Open,
+            order_type: OrderType::TriggerMarket,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
+            trigger_price: 110 * PRICE_PRECISION_U64,
+            trigger_condition: OrderTriggerCondition::Above,
+            reduce_only: true,
+            linked_order_group: 1,
+            ..Order::default()
+        };
+        // stop loss
+        orders[2] = Order {
+            market_index: 0,
+            order_id: 3,
+            status: OrderStatus::Open,
+            order_type: OrderType::TriggerMarket,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
+            trigger_price: 90 * PRICE_PRECISION_U64,
+            trigger_condition: OrderTriggerCondition::Below,
+            reduce_only: true,
+            linked_order_group: 1,
+            ..Order::default()
+        };
+        // different group
+        orders[3] = Order {
+            market_index: 0,
+            order_id: 4,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
+            price: 120 * PRICE_PRECISION_U64,
+            linked_order_group: 2,
+            ..Order::default()
+        };
+
+        let mut user = User {
+            orders,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 4,
+                open_bids: BASE_PRECISION_I64,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            open_orders: 4,
+            has_open_order: true,
+            ..User::default()
+        };
+
+        let user_key = Pubkey::default();
+
+        cancel_linked_orders(
+            &mut user,
+            &user_key,
+            2,
+            1,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            clock.unix_timestamp,
+            clock.slot,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(user.orders[0].status, OrderStatus::Open);
+        assert_eq!(user.orders[1].status, OrderStatus::Open);
+        assert_eq!(user.orders[2], Order::default());
+        assert_eq!(user.orders[3].status, OrderStatus::Open);
+        assert_eq!(user.perp_positions[0].open_orders, 3);
+        assert_eq!(user.perp_positions[0].open_bids, BASE_PRECISION_I64);
+        assert_eq!(user.perp_positions[0].open_asks, -BASE_PRECISION_I64);
+
+        // no group, nothing canceled
+        cancel_linked_orders(
+            &mut user,
+            &user_key,
+            1,
+            0,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            clock.unix_timestamp,
+            clock.slot,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(user.perp_positions[0].open_orders, 3);
+    }
+}
+
+pub mod insert_maker_order_info {
+    use crate::controller::orders::insert_maker_order_info;
+    use crate::controller::position::PositionDirection;
+    use solana_program::pubkey::Pubkey;
+
+    #[test]
+    fn bids() {
+        let mut bids = Vec::with_capacity(3);
+        bids.push((Pubkey::default(), 1, 10));
+        bids.push((Pubkey::default(), 0, 1));
+        let maker_direction = PositionDirection::Long;
+
+        insert_maker_order_info(&mut bids, (Pubkey::default(), 2, 100), maker_direction);
+
+        assert_eq!(
+            bids,
+            vec![
+                (Pubkey::default(), 2, 100),
+                (Pubkey::default(), 1, 10),
+                (Pubkey::default(), 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn asks() {
+        let mut asks = Vec::with_capacity(3);
+        asks.push((Pubkey::default(), 0, 1));
+        asks.push((Pubkey::default(), 1, 10));
+        let maker_direction = PositionDirection::Short;
+
+        insert_maker_order_info(&mut asks, (Pubkey::default(), 2, 100), maker_direction);
+
+        assert_eq!(
+            asks,
+            vec![
+                (Pubkey::default(), 0, 1),
+                (Pubkey::default(), 1, 10),
+                (Pubkey::default(), 2, 100)
+            ]
+        );
+    }
+}
+
+pub mod get_maker_orders_info {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::{AccountLoader, Clock};
+
+    use crate::controller::orders::get_maker_orders_info;
+    use crate::controller::position::PositionDirection;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION_I64, PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::oracle::OracleSource;
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{OrderStatus, OrderType, SelfTradePreventionMode, SpotPosition, User};
+    use crate::state::user_map::UserMap;
+    use crate::test_utils::*;
+    use crate::test_utils::{
+        create_account_info, get_orders, get_positions, get_pyth_price, get_spot_positions,
+    };
+    use crate::{create_account_info, get_orders};
+    use crate::{create_anchor_account_info, QUOTE_PRECISION_I64};
+
+    use super::*;
+
+    #[test]
+    fn one_maker_order_canceled_for_breaching_oracle_price_band() {
+        let clock = Clock {
+            slot: 56,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            pyth_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: pyth_price.twap as i64,
+                    last_oracle_price_twap_5min: pyth_price.twap as i64,
+                    last_oracle_price: pyth_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let taker_key = Pubkey::default();
+        let taker_authority =
+            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let user = User {
+            authority: taker_authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                auction_start_price: 0,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 50 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut maker_orders = [Order::default(); 32];
+        maker_orders[0] = Order {
+            market_index: 0,
+            order_id: 1,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
+            slot: 0,
+            price: 50 * PRICE_PRECISION_U64,
+            post_only: true,
+            ..Order::default()
+        };
+        maker_orders[1] = Order {
+            market_index: 0,
+            order_id: 2,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
+            slot: 0,
+            price: 100 * PRICE_PRECISION_U64,
+            post_only: true,
+            ..Order::default()
+        };
+
+        let mut maker = User {
+            orders: maker_orders,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                open_asks: -2 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let mut filler = User::default();
+
+        let (maker_order_price_and_indexes, _) = get_maker_orders_info(
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &makers_and_referrers,
+            &taker_key,
+            &user.authority,
+            &user.orders[0],
+            SelfTradePreventionMode::None,
+            &mut Some(&mut filler),
+            &filler_key,
+            0,
+            oracle_price,
+            None,
+            clock.unix_timestamp,
+            clock.slot,
+        )
+        .unwrap();
+
+        assert_eq!(
+            maker_order_price_and_indexes,
+            vec![(maker_key, 1, 100 * PRICE_PRECISION_U64)]
+        );
+    }
+
+    #[test]
+    fn one_maker_order_canceled_for_being_expired() {
+        let clock = Clock {
+            slot: 56,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 6,
+        };
+
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            pyth_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: pyth_price.twap as i64,
+                    last_oracle_price_twap_5min: pyth_price.twap as i64,
+                    last_oracle_price: pyth_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let taker_key = Pubkey::default();
+        let taker_authority =
+            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let user = User {
+            authority: taker_authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                auction_start_price: 0,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
 
-    use anchor_lang::prelude::{AccountLoader, Clock};
+        let mut maker_orders = [Order::default(); 32];
+        maker_orders[0] = Order {
+            market_index: 0,
+            order_id: 1,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
+            slot: 0,
+            price: 100 * PRICE_PRECISION_U64,
+            max_ts: 1,
+            post_only: true,
+            ..Order::default()
+        };
+        maker_orders[1] = Order {
+            market_index: 0,
+            order_id: 2,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
+            slot: 0,
+            price: 100 * PRICE_PRECISION_U64,
+            post_only: true,
+            ..Order::default()
+        };
 
-    use crate::controller::orders::fill_spot_order;
-    use crate::controller::position::PositionDirection;
-    use crate::create_account_info;
-    use crate::create_anchor_account_info;
-    use crate::error::ErrorCode;
-    use crate::math::constants::{
-        LAMPORTS_PER_SOL_I64, LAMPORTS_PER_SOL_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64,
-        SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
-    };
-    use crate::state::oracle::HistoricalOracleData;
-    use crate::state::perp_market_map::PerpMarketMap;
-    use crate::state::spot_fulfillment_params::TestFulfillmentParams;
-    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
-    use crate::state::spot_market_map::SpotMarketMap;
-    use crate::state::state::State;
-    use crate::state::user::{MarketType, OrderStatus, OrderType, SpotPosition, User, UserStats};
-    use crate::state::user_map::{UserMap, UserStatsMap};
-    use crate::test_utils::get_pyth_price;
-    use crate::test_utils::*;
+        let mut maker = User {
+            orders: maker_orders,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                open_asks: -2 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
 
-    use super::*;
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
 
-    // Add back if we check free collateral in fill again
-    // #[test]
-    // fn fulfill_with_negative_free_collateral() {
-    //     let clock = Clock {
-    //         slot: 6,
-    //         epoch_start_timestamp: 0,
-    //         epoch: 0,
-    //         leader_schedule_epoch: 0,
-    //         unix_timestamp: 0,
-    //     };
-    //
-    //     let mut oracle_price = get_pyth_price(100, 6);
-    //     let oracle_price_key =
-    //         Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
-    //     let pyth_program = crate::ids::pyth_program::id();
-    //     create_account_info!(
-    //         oracle_price,
-    //         &oracle_price_key,
-    //         &pyth_program,
-    //         oracle_account_info
-    //     );
-    //     let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
-    //
-    //     let perp_market_map = PerpMarketMap::empty();
-    //
-    //     let mut base_market = SpotMarket {
-    //         oracle: oracle_price_key,
-    //         market_index: 1,
-    //         deposit_balance: SPOT_BALANCE_PRECISION,
-    //         ..SpotMarket::default_base_market()
-    //     };
-    //     create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
-    //     let mut quote_market = SpotMarket {
-    //         deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-    //         ..SpotMarket::default_quote_market()
-    //     };
-    //     create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
-    //     let spot_market_map = SpotMarketMap::load_multiple(
-    //         vec![&base_market_account_info, &quote_market_account_info],
-    //         true,
-    //     )
-    //     .unwrap();
-    //
-    //     let mut taker_spot_positions = [SpotPosition::default(); 8];
-    //     taker_spot_positions[0] = SpotPosition {
-    //         market_index: 0,
-    //         scaled_balance: SPOT_BALANCE_PRECISION_U64,
-    //         balance_type: SpotBalanceType::Deposit,
-    //         ..SpotPosition::default()
-    //     };
-    //     taker_spot_positions[1] = SpotPosition {
-    //         market_index: 1,
-    //         open_orders: 1,
-    //         open_bids: LAMPORTS_PER_SOL_I64,
-    //         ..SpotPosition::default()
-    //     };
-    //     let mut taker = User {
-    //         orders: get_orders(Order {
-    //             order_id: 1,
-    //             market_index: 1,
-    //             market_type: MarketType::Spot,
-    //             order_type: OrderType::Market,
-    //             status: OrderStatus::Open,
-    //             direction: PositionDirection::Long,
-    //             base_asset_amount: LAMPORTS_PER_SOL_U64,
-    //             slot: 0,
-    //             auction_start_price: 100 * PRICE_PRECISION_U64,
-    //             auction_end_price: 200 * PRICE_PRECISION_U64,
-    //             auction_duration: 5,
-    //             price: 100 * PRICE_PRECISION_U64,
-    //             ..Order::default()
-    //         }),
-    //         spot_positions: taker_spot_positions,
-    //         ..User::default()
-    //     };
-    //
-    //     create_anchor_account_info!(taker, User, taker_account_info);
-    //     let taker_account_loader: AccountLoader<User> =
-    //         AccountLoader::try_from(&taker_account_info).unwrap();
-    //
-    //     create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
-    //     let taker_stats_account_loader: AccountLoader<UserStats> =
-    //         AccountLoader::try_from(&taker_stats_account_info).unwrap();
-    //
-    //     let mut maker_spot_positions = [SpotPosition::default(); 8];
-    //     maker_spot_positions[1] = SpotPosition {
-    //         market_index: 1,
-    //         balance_type: SpotBalanceType::Deposit,
-    //         scaled_balance: SPOT_BALANCE_PRECISION_U64,
-    //         open_orders: 1,
-    //         open_asks: -LAMPORTS_PER_SOL_I64 / 2,
-    //         ..SpotPosition::default()
-    //     };
-    //     let mut maker = User {
-    //         orders: get_orders(Order {
-    //             order_id: 1,
-    //             market_index: 1,
-    //             post_only: true,
-    //             market_type: MarketType::Spot,
-    //             order_type: OrderType::Limit,
-    //             status: OrderStatus::Open,
-    //             direction: PositionDirection::Short,
-    //             base_asset_amount: LAMPORTS_PER_SOL_U64 / 2,
-    //             price: 100 * PRICE_PRECISION_U64,
-    //             ..Order::default()
-    //         }),
-    //         spot_positions: maker_spot_positions,
-    //         ..User::default()
-    //     };
-    //
-    //     let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-    //     create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
-    //     let maker_account_loader: AccountLoader<User> =
-    //         AccountLoader::try_from(&maker_account_info).unwrap();
-    //
-    //     create_anchor_account_info!(UserStats::default(), UserStats, maker_stats_account_info);
-    //     let maker_stats_account_loader: AccountLoader<UserStats> =
-    //         AccountLoader::try_from(&maker_stats_account_info).unwrap();
-    //
-    //     let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-    //     create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
-    //     let filler_account_loader: AccountLoader<User> =
-    //         AccountLoader::try_from(&user_account_info).unwrap();
-    //
-    //     create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
-    //     let filler_stats_account_loader: AccountLoader<UserStats> =
-    //         AccountLoader::try_from(&filler_stats_account_info).unwrap();
-    //
-    //     let state = State {
-    //         default_spot_auction_duration: 1,
-    //         ..State::default()
-    //     };
-    //
-    //     let free_collateral =
-    //         calculate_free_collateral(&taker, &perp_market_map, &spot_market_map, &mut oracle_map)
-    //             .unwrap();
-    //     assert_eq!(free_collateral, -19000000);
-    //
-    //     let base_asset_amount = fill_spot_order(
-    //         1,
-    //         &state,
-    //         &taker_account_loader,
-    //         &taker_stats_account_loader,
-    //         &spot_market_map,
-    //         &perp_market_map,
-    //         &mut oracle_map,
-    //         &filler_account_loader,
-    //         &filler_stats_account_loader,
-    //         Some(&maker_account_loader),
-    //         Some(&maker_stats_account_loader),
-    //         Some(1),
-    //         &clock,
-    //         &mut None,
-    //     )
-    //     .unwrap();
-    //
-    //     assert_eq!(base_asset_amount, 0); // cancel should be canceled
-    //     let taker_after = taker_account_loader.load().unwrap();
-    //     assert_eq!(taker_after.orders[0], Order::default()); // order canceled
-    //
-    //     let free_collateral = calculate_free_collateral(
-    //         &taker_after,
-    //         &perp_market_map,
-    //         &spot_market_map,
-    //         &mut oracle_map,
-    //     )
-    //     .unwrap();
-    //     assert_eq!(free_collateral, 1000000);
-    //
-    //     let maker_after = maker_account_loader.load().unwrap();
-    //     assert_eq!(*maker_after, maker); // maker should not have changed
-    // }
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let mut filler = User::default();
+
+        let (maker_order_price_and_indexes, _) = get_maker_orders_info(
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &makers_and_referrers,
+            &taker_key,
+            &user.authority,
+            &user.orders[0],
+            SelfTradePreventionMode::None,
+            &mut Some(&mut filler),
+            &filler_key,
+            0,
+            oracle_price,
+            None,
+            clock.unix_timestamp,
+            clock.slot,
+        )
+        .unwrap();
+
+        assert_eq!(
+            maker_order_price_and_indexes,
+            vec![(maker_key, 1, 100 * PRICE_PRECISION_U64)]
+        );
+    }
 
     #[test]
-    fn fulfill_users_with_multiple_orders_and_markets() {
+    fn one_maker_order_canceled_for_being_reduce_only() {
         let clock = Clock {
             slot: 6,
             epoch_start_timestamp: 0,
@@ -8295,270 +12039,438 @@ pub mod fulfill_spot_order {
             unix_timestamp: 0,
         };
 
-        let mut oracle_price = get_pyth_price(100, 6);
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            oracle_price,
+            pyth_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let perp_market_map = PerpMarketMap::empty();
-
-        let mut base_market = SpotMarket {
-            market_index: 1,
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            oracle: oracle_price_key,
-            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
-            ..SpotMarket::default_base_market()
-        };
-        create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
-        let mut second_base_market = SpotMarket {
-            market_index: 2,
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
-            ..SpotMarket::default_base_market()
-        };
-        create_anchor_account_info!(
-            second_base_market,
-            SpotMarket,
-            second_base_market_account_info
-        );
-        let mut quote_market = SpotMarket {
-            market_index: 0,
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: pyth_price.twap as i64,
+                    last_oracle_price_twap_5min: pyth_price.twap as i64,
+                    last_oracle_price: pyth_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
         };
-        create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![
-                &base_market_account_info,
-                &quote_market_account_info,
-                &second_base_market_account_info,
-            ],
-            true,
-        )
-        .unwrap();
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
+        let mut spot_market = SpotMarket {
             market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        taker_spot_positions[2] = SpotPosition {
-            market_index: 2,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut taker_orders = [Order::default(); 32];
-        taker_orders[0] = Order {
-            order_id: 1,
-            market_index: 1,
-            market_type: MarketType::Spot,
-            order_type: OrderType::Market,
-            status: OrderStatus::Open,
-            direction: PositionDirection::Long,
-            base_asset_amount: LAMPORTS_PER_SOL_U64,
-            slot: 0,
-            auction_start_price: 100 * PRICE_PRECISION_I64,
-            auction_end_price: 200 * PRICE_PRECISION_I64,
-            auction_duration: 5,
-            price: 100 * PRICE_PRECISION_U64,
-            ..Order::default()
-        };
-        taker_orders[1] = Order {
-            order_id: 2,
-            market_index: 2,
-            market_type: MarketType::Spot,
-            order_type: OrderType::Market,
-            status: OrderStatus::Open,
-            direction: PositionDirection::Long,
-            base_asset_amount: LAMPORTS_PER_SOL_U64,
-            slot: 0,
-            auction_start_price: 100 * PRICE_PRECISION_I64,
-            auction_end_price: 200 * PRICE_PRECISION_I64,
-            auction_duration: 5,
-            price: 100 * PRICE_PRECISION_U64,
-            ..Order::default()
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
         };
-        let mut taker = User {
-            orders: taker_orders,
-            spot_positions: taker_spot_positions,
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let taker_key = Pubkey::default();
+        let taker_authority =
+            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let user = User {
+            authority: taker_authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                auction_start_price: 0,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 100 * PRICE_PRECISION_U64,
+                max_ts: 1,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
 
-        create_anchor_account_info!(taker, User, taker_account_info);
-        let taker_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&taker_account_info).unwrap();
-
-        create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
-        let taker_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&taker_stats_account_info).unwrap();
-
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 2,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64 / 2,
-            ..SpotPosition::default()
-        };
-        maker_spot_positions[2] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
         let mut maker_orders = [Order::default(); 32];
         maker_orders[0] = Order {
-            order_id: 2,
-            market_index: 2,
-            post_only: true,
-            market_type: MarketType::Spot,
-            order_type: OrderType::Limit,
-            status: OrderStatus::Open,
-            direction: PositionDirection::Short,
-            base_asset_amount: LAMPORTS_PER_SOL_U64,
-            price: 100 * PRICE_PRECISION_U64,
-            ..Order::default()
-        };
-        maker_orders[1] = Order {
+            market_index: 0,
             order_id: 1,
-            market_index: 1,
-            post_only: true,
-            market_type: MarketType::Spot,
-            order_type: OrderType::Limit,
             status: OrderStatus::Open,
+            order_type: OrderType::Limit,
             direction: PositionDirection::Short,
-            base_asset_amount: LAMPORTS_PER_SOL_U64,
+            base_asset_amount: BASE_PRECISION_U64,
+            slot: 0,
             price: 100 * PRICE_PRECISION_U64,
+            reduce_only: true,
             ..Order::default()
         };
+
         let mut maker = User {
             orders: maker_orders,
-            spot_positions: maker_spot_positions,
-            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: -BASE_PRECISION_I64,
+                open_orders: 1,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
-
-        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
         create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
-        let maker_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&maker_account_info).unwrap();
 
         let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
 
-        let mut maker_stats = UserStats {
-            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
-            ..UserStats::default()
-        };
-        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
-        let maker_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&maker_stats_account_info).unwrap();
-        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
-
         let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
-        let filler_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&user_account_info).unwrap();
+        let mut filler = User::default();
 
-        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
-        let filler_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+        let (maker_order_price_and_indexes, _) = get_maker_orders_info(
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &makers_and_referrers,
+            &taker_key,
+            &user.authority,
+            &user.orders[0],
+            SelfTradePreventionMode::None,
+            &mut Some(&mut filler),
+            &filler_key,
+            0,
+            oracle_price,
+            None,
+            clock.unix_timestamp,
+            clock.slot,
+        )
+        .unwrap();
 
-        let state = State {
-            default_spot_auction_duration: 1,
-            ..State::default()
+        assert_eq!(maker_order_price_and_indexes, vec![],);
+    }
+
+    #[test]
+    fn two_makers() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
         };
 
-        let mut expected_taker = taker;
-        expected_taker.orders[0] = Order::default();
-        expected_taker.spot_positions[0] = SpotPosition {
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            pyth_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: pyth_price.twap as i64,
+                    last_oracle_price_twap_5min: pyth_price.twap as i64,
+                    last_oracle_price: pyth_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
             market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 900000000,
-            cumulative_deposits: -100000000,
-            ..SpotPosition::default()
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
         };
-        expected_taker.spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            cumulative_deposits: 1000000000,
-            ..SpotPosition::default()
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let taker_key = Pubkey::default();
+        let taker_authority =
+            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let user = User {
+            authority: taker_authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                auction_start_price: 0,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 100 * PRICE_PRECISION_U64,
+                max_ts: 1,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
         };
-        expected_taker.cumulative_spot_fees = -100000;
-        expected_taker.last_active_slot = clock.slot;
 
-        let mut expected_maker = maker;
-        expected_maker.orders[1] = Order::default();
-        expected_maker.spot_positions[0] = SpotPosition {
-            market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 100020000000,
-            cumulative_deposits: 100000000,
-            ..SpotPosition::default()
+        let mut first_maker = User {
+            orders: get_orders!(
+                Order {
+                    market_index: 0,
+                    order_id: 1,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64,
+                    slot: 0,
+                    price: 100 * PRICE_PRECISION_U64,
+                    ..Order::default()
+                },
+                Order {
+                    market_index: 0,
+                    order_id: 1,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64,
+                    slot: 0,
+                    price: 102 * PRICE_PRECISION_U64,
+                    ..Order::default()
+                }
+            ),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
         };
-        expected_maker.spot_positions[2] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 0,
-            cumulative_deposits: -1000000000,
-            ..SpotPosition::default()
+        let first_maker_key =
+            Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        create_anchor_account_info!(
+            first_maker,
+            &first_maker_key,
+            User,
+            first_maker_account_info
+        );
+
+        let mut second_maker = User {
+            orders: get_orders!(
+                Order {
+                    market_index: 0,
+                    order_id: 1,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64,
+                    slot: 0,
+                    price: 101 * PRICE_PRECISION_U64,
+                    ..Order::default()
+                },
+                Order {
+                    market_index: 0,
+                    order_id: 1,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64,
+                    slot: 0,
+                    price: 103 * PRICE_PRECISION_U64,
+                    ..Order::default()
+                }
+            ),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
         };
-        expected_maker.cumulative_spot_fees = 20000;
-        expected_maker.last_active_slot = clock.slot;
+        let second_maker_key =
+            Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
+        create_anchor_account_info!(
+            second_maker,
+            &second_maker_key,
+            User,
+            second_maker_account_info
+        );
 
-        let base_asset_amount = fill_spot_order(
-            1,
-            &state,
-            &taker_account_loader,
-            &taker_stats_account_loader,
+        let mut makers_and_referrers = UserMap::load_one(&first_maker_account_info).unwrap();
+        makers_and_referrers
+            .insert(
+                second_maker_key,
+                AccountLoader::try_from(&second_maker_account_info).unwrap(),
+            )
+            .unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let mut filler = User::default();
+
+        let (maker_order_price_and_indexes, _) = get_maker_orders_info(
+            &market_map,
             &spot_market_map,
-            &perp_market_map,
             &mut oracle_map,
-            &filler_account_loader,
-            &filler_stats_account_loader,
             &makers_and_referrers,
-            &maker_and_referrer_stats,
+            &taker_key,
+            &user.authority,
+            &user.orders[0],
+            SelfTradePreventionMode::None,
+            &mut Some(&mut filler),
+            &filler_key,
+            0,
+            oracle_price,
             None,
-            &clock,
-            &mut TestFulfillmentParams {},
+            clock.unix_timestamp,
+            clock.slot,
         )
         .unwrap();
 
-        assert_eq!(base_asset_amount, 1000000000); // full order filled by maker
-        let taker_after = taker_account_loader.load().unwrap();
-        assert_eq!(*taker_after, expected_taker);
-
-        let taker_stats_after = taker_stats_account_loader.load().unwrap();
-        assert_eq!(taker_stats_after.fees.total_fee_paid, 100000);
-
-        let maker_after = maker_account_loader.load().unwrap();
-        assert_eq!(*maker_after, expected_maker);
-
-        let maker_stats_after = maker_stats_account_loader.load().unwrap();
-        assert_eq!(maker_stats_after.fees.total_fee_rebate, 20000);
+        assert_eq!(
+            maker_order_price_and_indexes,
+            vec![
+                (first_maker_key, 0, 100000000),
+                (second_maker_key, 0, 101000000),
+                (first_maker_key, 1, 102000000),
+                (second_maker_key, 1, 103000000),
+            ],
+        );
     }
 
     #[test]
-    fn fulfill_users_with_multiple_maker_orders() {
+    fn jit_maker_order_id() {
         let clock = Clock {
             slot: 6,
             epoch_start_timestamp: 0,
@@ -8567,282 +12479,197 @@ pub mod fulfill_spot_order {
             unix_timestamp: 0,
         };
 
-        let mut oracle_price = get_pyth_price(100, 6);
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            oracle_price,
+            pyth_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let perp_market_map = PerpMarketMap::empty();
-
-        let mut base_market = SpotMarket {
-            market_index: 1,
-            deposit_balance: 2 * SPOT_BALANCE_PRECISION,
-            oracle: oracle_price_key,
-            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
-            ..SpotMarket::default_base_market()
-        };
-        create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
-        let mut quote_market = SpotMarket {
-            market_index: 0,
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: pyth_price.twap as i64,
+                    last_oracle_price_twap_5min: pyth_price.twap as i64,
+                    last_oracle_price: pyth_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
         };
-        create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![&base_market_account_info, &quote_market_account_info],
-            true,
-        )
-        .unwrap();
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
+        let mut spot_market = SpotMarket {
             market_index: 0,
-            scaled_balance: 201 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: 2 * LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut taker_orders = [Order::default(); 32];
-        taker_orders[0] = Order {
-            order_id: 1,
-            market_index: 1,
-            market_type: MarketType::Spot,
-            order_type: OrderType::Market,
-            status: OrderStatus::Open,
-            direction: PositionDirection::Long,
-            base_asset_amount: 2 * LAMPORTS_PER_SOL_U64,
-            slot: 0,
-            auction_start_price: 100 * PRICE_PRECISION_I64,
-            auction_end_price: 200 * PRICE_PRECISION_I64,
-            auction_duration: 5,
-            price: 100 * PRICE_PRECISION_U64,
-            ..Order::default()
-        };
-        let mut taker = User {
-            orders: taker_orders,
-            spot_positions: taker_spot_positions,
-            ..User::default()
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
         };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
 
-        create_anchor_account_info!(taker, User, taker_account_info);
-        let taker_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&taker_account_info).unwrap();
-
-        create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
-        let taker_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&taker_stats_account_info).unwrap();
-
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut maker_orders = [Order::default(); 32];
-        maker_orders[1] = Order {
-            order_id: 1,
-            market_index: 1,
-            post_only: true,
-            market_type: MarketType::Spot,
-            order_type: OrderType::Limit,
-            status: OrderStatus::Open,
-            direction: PositionDirection::Short,
-            base_asset_amount: LAMPORTS_PER_SOL_U64,
-            price: 100 * PRICE_PRECISION_U64,
-            ..Order::default()
-        };
-        let mut maker = User {
-            orders: maker_orders,
-            spot_positions: maker_spot_positions,
-            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+        let taker_key = Pubkey::default();
+        let taker_authority =
+            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let user = User {
+            authority: taker_authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                auction_start_price: 0,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 100 * PRICE_PRECISION_U64,
+                max_ts: 1,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
 
-        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
-        let maker_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&maker_account_info).unwrap();
-
-        let mut second_maker_spot_positions = [SpotPosition::default(); 8];
-        second_maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut second_maker_orders = [Order::default(); 32];
-        second_maker_orders[1] = Order {
-            order_id: 1,
-            market_index: 1,
-            post_only: true,
-            market_type: MarketType::Spot,
-            order_type: OrderType::Limit,
-            status: OrderStatus::Open,
-            direction: PositionDirection::Short,
-            base_asset_amount: LAMPORTS_PER_SOL_U64,
-            price: 100 * PRICE_PRECISION_U64,
-            ..Order::default()
-        };
-        let mut second_maker = User {
-            orders: second_maker_orders,
-            spot_positions: second_maker_spot_positions,
-            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+        let mut first_maker = User {
+            orders: get_orders!(
+                Order {
+                    market_index: 0,
+                    order_id: 1,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64,
+                    slot: 0,
+                    price: 100 * PRICE_PRECISION_U64,
+                    ..Order::default()
+                },
+                Order {
+                    market_index: 0,
+                    order_id: 2,
+                    status: OrderStatus::Open,
+                    order_type: OrderType::Limit,
+                    direction: PositionDirection::Short,
+                    base_asset_amount: BASE_PRECISION_U64,
+                    slot: 0,
+                    price: 102 * PRICE_PRECISION_U64,
+                    ..Order::default()
+                }
+            ),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
-
-        let second_maker_key =
-            Pubkey::from_str("My11111111111111111111111111111111111111114").unwrap();
+        let first_maker_key =
+            Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
         create_anchor_account_info!(
-            second_maker,
-            &second_maker_key,
+            first_maker,
+            &first_maker_key,
             User,
-            second_maker_account_info
-        );
-        let second_maker_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&second_maker_account_info).unwrap();
-
-        let mut makers_and_referrers = UserMap::empty();
-        makers_and_referrers
-            .insert(maker_key, maker_account_loader)
-            .unwrap();
-        makers_and_referrers
-            .insert(second_maker_key, second_maker_account_loader)
-            .unwrap();
-
-        let mut maker_stats = UserStats {
-            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
-            ..UserStats::default()
-        };
-        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
-        let maker_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&maker_stats_account_info).unwrap();
-
-        let second_maker_authority =
-            Pubkey::from_str("My11111111111111111111111111111111111111114").unwrap();
-        let mut second_maker_stats = UserStats {
-            authority: second_maker_authority,
-            ..UserStats::default()
-        };
-        create_anchor_account_info!(
-            second_maker_stats,
-            UserStats,
-            second_maker_stats_account_info
+            first_maker_account_info
         );
-        let second_maker_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&second_maker_stats_account_info).unwrap();
 
-        let mut maker_and_referrer_stats =
-            UserStatsMap::load_one(&maker_stats_account_info).unwrap();
-        maker_and_referrer_stats
-            .insert(second_maker_key, second_maker_stats_account_loader)
-            .unwrap();
+        let makers_and_referrers = UserMap::load_one(&first_maker_account_info).unwrap();
 
         let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
-        let filler_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&user_account_info).unwrap();
-
-        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
-        let filler_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&filler_stats_account_info).unwrap();
-
-        let state = State {
-            default_spot_auction_duration: 1,
-            ..State::default()
-        };
-
-        let mut expected_taker = taker;
-        expected_taker.orders[0] = Order::default();
-        expected_taker.spot_positions[0] = SpotPosition {
-            market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 800000000,
-            cumulative_deposits: -200000000,
-            ..SpotPosition::default()
-        };
-        expected_taker.spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 2 * SPOT_BALANCE_PRECISION_U64,
-            cumulative_deposits: 2000000000,
-            ..SpotPosition::default()
-        };
-        expected_taker.cumulative_spot_fees = -200000;
-        expected_taker.last_active_slot = clock.slot;
-
-        let mut expected_maker = maker;
-        expected_maker.orders[1] = Order::default();
-        expected_maker.spot_positions[0] = SpotPosition {
-            market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 100020000000,
-            cumulative_deposits: 100000000,
-            ..SpotPosition::default()
-        };
-        expected_maker.spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 0,
-            cumulative_deposits: -1000000000,
-            ..SpotPosition::default()
-        };
-        expected_maker.cumulative_spot_fees = 20000;
-        expected_maker.last_active_slot = clock.slot;
+        let mut filler = User::default();
 
-        let base_asset_amount = fill_spot_order(
-            1,
-            &state,
-            &taker_account_loader,
-            &taker_stats_account_loader,
+        let (maker_order_price_and_indexes, _) = get_maker_orders_info(
+            &market_map,
             &spot_market_map,
-            &perp_market_map,
             &mut oracle_map,
-            &filler_account_loader,
-            &filler_stats_account_loader,
             &makers_and_referrers,
-            &maker_and_referrer_stats,
-            None,
-            &clock,
-            &mut TestFulfillmentParams {},
+            &taker_key,
+            &user.authority,
+            &user.orders[0],
+            SelfTradePreventionMode::None,
+            &mut Some(&mut filler),
+            &filler_key,
+            0,
+            oracle_price,
+            Some(2),
+            clock.unix_timestamp,
+            clock.slot,
         )
         .unwrap();
 
-        assert_eq!(base_asset_amount, 2000000000); // full order filled by maker
-        let taker_after = taker_account_loader.load().unwrap();
-        assert_eq!(*taker_after, expected_taker);
-
-        let taker_stats_after = taker_stats_account_loader.load().unwrap();
-        assert_eq!(taker_stats_after.fees.total_fee_paid, 200000);
-
-        let maker_after = makers_and_referrers.get_ref(&maker_key).unwrap();
-        assert_eq!(*maker_after, expected_maker);
-
-        let second_maker_after = makers_and_referrers.get_ref(&second_maker_key).unwrap();
-        assert_eq!(*second_maker_after, expected_maker);
-
-        let maker_stats_after = maker_stats_account_loader.load().unwrap();
-        assert_eq!(maker_stats_after.fees.total_fee_rebate, 40000);
+        assert_eq!(
+            maker_order_price_and_indexes,
+            vec![(first_maker_key, 1, 102000000),],
+        );
     }
 
     #[test]
-    fn maker_insufficient_collateral() {
+    fn two_makers_with_max_orders() {
         let clock = Clock {
             slot: 6,
             epoch_start_timestamp: 0,
@@ -8851,384 +12678,406 @@ pub mod fulfill_spot_order {
             unix_timestamp: 0,
         };
 
-        let mut oracle_price = get_pyth_price(100, 6);
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            oracle_price,
+            pyth_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let perp_market_map = PerpMarketMap::empty();
-
-        let mut base_market = SpotMarket {
-            market_index: 1,
-            deposit_balance: 10 * SPOT_BALANCE_PRECISION,
-            deposit_token_twap: 10 * LAMPORTS_PER_SOL_U64,
-            oracle: oracle_price_key,
-            historical_oracle_data: HistoricalOracleData::default_price(100 * PRICE_PRECISION_I64),
-            ..SpotMarket::default_base_market()
-        };
-        create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
-        let mut quote_market = SpotMarket {
-            market_index: 0,
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: pyth_price.twap as i64,
+                    last_oracle_price_twap_5min: pyth_price.twap as i64,
+                    last_oracle_price: pyth_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
         };
-        create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![&base_market_account_info, &quote_market_account_info],
-            true,
-        )
-        .unwrap();
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
+        let mut spot_market = SpotMarket {
             market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut taker_orders = [Order::default(); 32];
-        taker_orders[0] = Order {
-            order_id: 1,
-            market_index: 1,
-            market_type: MarketType::Spot,
-            order_type: OrderType::Market,
-            status: OrderStatus::Open,
-            direction: PositionDirection::Long,
-            base_asset_amount: LAMPORTS_PER_SOL_U64,
-            slot: 0,
-            auction_start_price: 100 * PRICE_PRECISION_I64,
-            auction_end_price: 200 * PRICE_PRECISION_I64,
-            auction_duration: 5,
-            price: 100 * PRICE_PRECISION_U64,
-            ..Order::default()
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
         };
-        let mut taker = User {
-            orders: taker_orders,
-            spot_positions: taker_spot_positions,
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let taker_key = Pubkey::default();
+        let taker_authority =
+            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let user = User {
+            authority: taker_authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Market,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                auction_start_price: 0,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 100 * PRICE_PRECISION_U64,
+                max_ts: 1,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
 
-        create_anchor_account_info!(taker, User, taker_account_info);
-        let taker_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&taker_account_info).unwrap();
-
-        create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
-        let taker_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&taker_stats_account_info).unwrap();
-
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[2] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64 / 10,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut maker_orders = [Order::default(); 32];
-        maker_orders[1] = Order {
-            order_id: 1,
-            market_index: 1,
-            post_only: true,
-            market_type: MarketType::Spot,
-            order_type: OrderType::Limit,
-            status: OrderStatus::Open,
-            direction: PositionDirection::Short,
-            base_asset_amount: LAMPORTS_PER_SOL_U64,
-            price: 100 * PRICE_PRECISION_U64,
-            ..Order::default()
-        };
-        let mut maker = User {
-            orders: maker_orders,
-            spot_positions: maker_spot_positions,
-            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
+        let mut first_maker = User {
+            orders: [Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }; 32],
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
             ..User::default()
         };
+        let first_maker_key =
+            Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        create_anchor_account_info!(
+            first_maker,
+            &first_maker_key,
+            User,
+            first_maker_account_info
+        );
 
-        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
-        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
-
-        let mut maker_stats = UserStats {
-            authority: Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap(),
-            ..UserStats::default()
+        let mut second_maker = User {
+            orders: [Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                price: 101 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }; 32],
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 2,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
         };
-        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
-        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
-
-        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
-        let filler_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&user_account_info).unwrap();
+        let second_maker_key =
+            Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
+        create_anchor_account_info!(
+            second_maker,
+            &second_maker_key,
+            User,
+            second_maker_account_info
+        );
 
-        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
-        let filler_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+        let mut makers_and_referrers = UserMap::load_one(&first_maker_account_info).unwrap();
+        makers_and_referrers
+            .insert(
+                second_maker_key,
+                AccountLoader::try_from(&second_maker_account_info).unwrap(),
+            )
+            .unwrap();
 
-        let state = State {
-            default_spot_auction_duration: 1,
-            ..State::default()
-        };
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let mut filler = User::default();
 
-        let result = fill_spot_order(
-            1,
-            &state,
-            &taker_account_loader,
-            &taker_stats_account_loader,
+        let (maker_order_price_and_indexes, _) = get_maker_orders_info(
+            &market_map,
             &spot_market_map,
-            &perp_market_map,
             &mut oracle_map,
-            &filler_account_loader,
-            &filler_stats_account_loader,
             &makers_and_referrers,
-            &maker_and_referrer_stats,
+            &taker_key,
+            &user.authority,
+            &user.orders[0],
+            SelfTradePreventionMode::None,
+            &mut Some(&mut filler),
+            &filler_key,
+            0,
+            oracle_price,
             None,
-            &clock,
-            &mut TestFulfillmentParams {},
-        );
+            clock.unix_timestamp,
+            clock.slot,
+        )
+        .unwrap();
 
-        assert_eq!(result, Err(ErrorCode::InsufficientCollateral));
+        assert_eq!(maker_order_price_and_indexes.len(), 64);
     }
-}
-
-pub mod fill_spot_order {
-    use std::str::FromStr;
-
-    use anchor_lang::prelude::{AccountLoader, Clock};
-
-    use crate::controller::orders::fill_spot_order;
-    use crate::controller::position::PositionDirection;
-    use crate::create_account_info;
-    use crate::create_anchor_account_info;
-    use crate::math::constants::{
-        LAMPORTS_PER_SOL_I64, LAMPORTS_PER_SOL_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64,
-        SPOT_BALANCE_PRECISION, SPOT_BALANCE_PRECISION_U64,
-    };
-    use crate::state::oracle::HistoricalOracleData;
-    use crate::state::perp_market_map::PerpMarketMap;
-    use crate::state::spot_fulfillment_params::TestFulfillmentParams;
-    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
-    use crate::state::spot_market_map::SpotMarketMap;
-    use crate::state::state::State;
-    use crate::state::user::{MarketType, OrderStatus, OrderType, SpotPosition, User, UserStats};
-    use crate::state::user_map::{UserMap, UserStatsMap};
-    use crate::test_utils::*;
-    use crate::test_utils::{create_account_info, get_orders, get_pyth_price};
-
-    use super::*;
 
     #[test]
-    fn expire_order() {
+    fn self_trade_decrement_and_cancel_cancels_smaller_maker_order() {
         let clock = Clock {
-            slot: 11,
+            slot: 56,
             epoch_start_timestamp: 0,
             epoch: 0,
             leader_schedule_epoch: 0,
-            unix_timestamp: 11,
+            unix_timestamp: 0,
         };
 
-        let mut oracle_price = get_pyth_price(100, 6);
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            oracle_price,
+            pyth_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let perp_market_map = PerpMarketMap::empty();
-
-        let mut base_market = SpotMarket {
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            historical_oracle_data: HistoricalOracleData::default_price(PRICE_PRECISION_I64),
-            ..SpotMarket::default_base_market()
-        };
-        create_anchor_account_info!(base_market, SpotMarket, base_market_account_info);
-        let mut quote_market = SpotMarket {
-            deposit_balance: 101 * SPOT_BALANCE_PRECISION,
-            ..SpotMarket::default_quote_market()
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: pyth_price.twap as i64,
+                    last_oracle_price_twap_5min: pyth_price.twap as i64,
+                    last_oracle_price: pyth_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
         };
-        create_anchor_account_info!(quote_market, SpotMarket, quote_market_account_info);
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![&base_market_account_info, &quote_market_account_info],
-            true,
-        )
-        .unwrap();
+        market.status = MarketStatus::Active;
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut taker_spot_positions = [SpotPosition::default(); 8];
-        taker_spot_positions[0] = SpotPosition {
+        let mut spot_market = SpotMarket {
             market_index: 0,
-            scaled_balance: 101 * SPOT_BALANCE_PRECISION_U64,
-            balance_type: SpotBalanceType::Deposit,
-            ..SpotPosition::default()
-        };
-        taker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            open_orders: 1,
-            open_bids: LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
         };
-        let mut taker = User {
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let taker_key = Pubkey::default();
+        let authority = Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
+        let user = User {
+            authority,
             orders: get_orders(Order {
+                market_index: 0,
                 order_id: 1,
-                market_index: 1,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Market,
                 status: OrderStatus::Open,
+                order_type: OrderType::Limit,
                 direction: PositionDirection::Long,
-                base_asset_amount: LAMPORTS_PER_SOL_U64,
+                base_asset_amount: 2 * BASE_PRECISION_U64,
                 slot: 0,
-                auction_start_price: 100 * PRICE_PRECISION_I64,
-                auction_end_price: 200 * PRICE_PRECISION_I64,
-                auction_duration: 5,
                 price: 100 * PRICE_PRECISION_U64,
-                max_ts: 10,
                 ..Order::default()
             }),
-            spot_positions: taker_spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: 2 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
             ..User::default()
         };
 
-        create_anchor_account_info!(taker, User, taker_account_info);
-        let taker_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&taker_account_info).unwrap();
-
-        create_anchor_account_info!(UserStats::default(), UserStats, taker_stats_account_info);
-        let taker_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&taker_stats_account_info).unwrap();
-
-        let mut maker_spot_positions = [SpotPosition::default(); 8];
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: SPOT_BALANCE_PRECISION_U64,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64 / 2,
-            ..SpotPosition::default()
-        };
         let mut maker = User {
+            authority,
             orders: get_orders(Order {
+                market_index: 0,
                 order_id: 1,
-                market_index: 1,
-                post_only: true,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
                 status: OrderStatus::Open,
+                order_type: OrderType::Limit,
                 direction: PositionDirection::Short,
-                base_asset_amount: LAMPORTS_PER_SOL_U64 / 2,
+                base_asset_amount: 1 * BASE_PRECISION_U64,
+                slot: 0,
                 price: 100 * PRICE_PRECISION_U64,
+                post_only: true,
                 ..Order::default()
             }),
-            spot_positions: maker_spot_positions,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -1 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
             ..User::default()
         };
-
-        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
         create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
-        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
 
-        create_anchor_account_info!(UserStats::default(), UserStats, maker_stats_account_info);
-        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
 
         let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
-        let filler_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&user_account_info).unwrap();
-
-        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
-        let filler_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+        let mut filler = User::default();
 
-        let state = State {
-            default_spot_auction_duration: 1,
-            ..State::default()
-        };
+        let (maker_order_price_and_indexes, taker_base_asset_amount_decrement) =
+            get_maker_orders_info(
+                &market_map,
+                &spot_market_map,
+                &mut oracle_map,
+                &makers_and_referrers,
+                &taker_key,
+                &user.authority,
+                &user.orders[0],
+                SelfTradePreventionMode::DecrementAndCancel,
+                &mut Some(&mut filler),
+                &filler_key,
+                0,
+                oracle_price,
+                None,
+                clock.unix_timestamp,
+                clock.slot,
+            )
+            .unwrap();
 
-        let base_asset_amount = fill_spot_order(
-            1,
-            &state,
-            &taker_account_loader,
-            &taker_stats_account_loader,
-            &spot_market_map,
-            &perp_market_map,
-            &mut oracle_map,
-            &filler_account_loader,
-            &filler_stats_account_loader,
-            &makers_and_referrers,
-            &maker_and_referrer_stats,
-            None,
-            &clock,
-            &mut TestFulfillmentParams {},
-        )
-        .unwrap();
+        assert!(maker_order_price_and_indexes.is_empty());
+        assert_eq!(taker_base_asset_amount_decrement, BASE_PRECISION_U64);
 
-        assert_eq!(base_asset_amount, 0); // half of order filled by maker
-        let taker_after = taker_account_loader.load().unwrap();
-        assert_eq!(taker_after.orders[0], Order::default()); // order expired
+        let maker = makers_and_referrers.get_ref(&maker_key).unwrap();
+        assert_eq!(maker.orders[0], Order::default());
+        assert_eq!(maker.perp_positions[0].open_asks, 0);
+        assert_eq!(maker.perp_positions[0].open_orders, 0);
     }
-}
-
-pub mod force_cancel_orders {
-    use std::str::FromStr;
-
-    use anchor_lang::prelude::{AccountLoader, Clock};
-
-    use crate::controller::orders::force_cancel_orders;
-    use crate::controller::position::PositionDirection;
-    use crate::create_account_info;
-    use crate::create_anchor_account_info;
-    use crate::math::constants::{
-        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, LAMPORTS_PER_SOL_I64,
-        LAMPORTS_PER_SOL_U64, PEG_PRECISION, PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION,
-        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
-    };
-    use crate::state::oracle::HistoricalOracleData;
-    use crate::state::oracle::OracleSource;
-    use crate::state::perp_market::{PerpMarket, AMM};
-    use crate::state::perp_market_map::PerpMarketMap;
-    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
-    use crate::state::spot_market_map::SpotMarketMap;
-    use crate::state::state::State;
-    use crate::state::user::{MarketType, OrderStatus, OrderType, SpotPosition, User, UserStats};
-    use crate::test_utils::*;
-    use crate::test_utils::{
-        create_account_info, get_positions, get_pyth_price, get_spot_positions,
-    };
-
-    use super::*;
 
     #[test]
-    fn cancel_order_after_fulfill() {
+    fn self_trade_decrement_and_cancel_decrements_larger_maker_order() {
         let clock = Clock {
-            slot: 6,
+            slot: 56,
             epoch_start_timestamp: 0,
             epoch: 0,
             leader_schedule_epoch: 0,
             unix_timestamp: 0,
         };
 
-        let mut oracle_price = get_pyth_price(100, 6);
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            oracle_price,
+            pyth_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
@@ -9240,10 +13089,6 @@ pub mod force_cancel_orders {
                 base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
                 quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
                 terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                // bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
-                // bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
-                // ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
-                // ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
                 sqrt_k: 100 * AMM_RESERVE_PRECISION,
                 peg_multiplier: 100 * PEG_PRECISION,
                 max_slippage_ratio: 100,
@@ -9256,9 +13101,9 @@ pub mod force_cancel_orders {
                 long_spread: 0,
                 short_spread: 0,
                 historical_oracle_data: HistoricalOracleData {
-                    last_oracle_price_twap: oracle_price.twap as i64,
-                    last_oracle_price_twap_5min: oracle_price.twap as i64,
-                    last_oracle_price: oracle_price.agg.price as i64,
+                    last_oracle_price_twap: pyth_price.twap as i64,
+                    last_oracle_price_twap_5min: pyth_price.twap as i64,
+                    last_oracle_price: pyth_price.agg.price as i64,
                     ..HistoricalOracleData::default()
                 },
                 ..AMM::default()
@@ -9290,230 +13135,105 @@ pub mod force_cancel_orders {
         create_anchor_account_info!(market, PerpMarket, market_account_info);
         let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut usdc_spot_market = SpotMarket {
+        let mut spot_market = SpotMarket {
             market_index: 0,
             oracle_source: OracleSource::QuoteAsset,
-            deposit_balance: SPOT_BALANCE_PRECISION,
             cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
-            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
             decimals: 6,
             initial_asset_weight: SPOT_WEIGHT_PRECISION,
             maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
             ..SpotMarket::default()
         };
-        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
-
-        let mut sol_spot_market = SpotMarket {
-            market_index: 1,
-            deposit_balance: SPOT_BALANCE_PRECISION,
-            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
-            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
-            oracle: oracle_price_key,
-            ..SpotMarket::default_base_market()
-        };
-        create_anchor_account_info!(sol_spot_market, SpotMarket, sol_spot_market_account_info);
-
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![
-                &usdc_spot_market_account_info,
-                &sol_spot_market_account_info,
-            ],
-            true,
-        )
-        .unwrap();
-
-        let mut orders = [Order::default(); 32];
-        orders[0] = Order {
-            market_index: 0,
-            order_id: 1,
-            status: OrderStatus::Open,
-            order_type: OrderType::Limit,
-            market_type: MarketType::Perp,
-            direction: PositionDirection::Long,
-            base_asset_amount: 100 * BASE_PRECISION_U64,
-            slot: 0,
-            price: 102 * PRICE_PRECISION_U64,
-            ..Order::default()
-        };
-        orders[1] = Order {
-            market_index: 0,
-            order_id: 2,
-            status: OrderStatus::Open,
-            order_type: OrderType::Limit,
-            market_type: MarketType::Perp,
-            direction: PositionDirection::Short,
-            base_asset_amount: BASE_PRECISION_U64,
-            slot: 0,
-            price: 102 * PRICE_PRECISION_U64,
-            ..Order::default()
-        };
-        orders[2] = Order {
-            market_index: 1,
-            order_id: 1,
-            status: OrderStatus::Open,
-            order_type: OrderType::Limit,
-            market_type: MarketType::Spot,
-            direction: PositionDirection::Long,
-            base_asset_amount: 100 * LAMPORTS_PER_SOL_U64,
-            slot: 0,
-            price: 102 * PRICE_PRECISION_U64,
-            ..Order::default()
-        };
-        orders[3] = Order {
-            market_index: 1,
-            order_id: 1,
-            status: OrderStatus::Open,
-            order_type: OrderType::Limit,
-            market_type: MarketType::Spot,
-            direction: PositionDirection::Short,
-            base_asset_amount: LAMPORTS_PER_SOL_U64,
-            slot: 0,
-            price: 102 * PRICE_PRECISION_U64,
-            ..Order::default()
-        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
 
-        let mut user = User {
-            authority: Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap(), // different authority than filler
-            orders,
+        let taker_key = Pubkey::default();
+        let authority = Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
+        let user = User {
+            authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: 2 * BASE_PRECISION_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
             perp_positions: get_positions(PerpPosition {
                 market_index: 0,
-                base_asset_amount: BASE_PRECISION_I64,
-                open_orders: 2,
-                open_bids: 100 * BASE_PRECISION_I64,
-                open_asks: -BASE_PRECISION_I64,
+                open_orders: 1,
+                open_bids: 2 * BASE_PRECISION_I64,
                 ..PerpPosition::default()
             }),
-            spot_positions: get_spot_positions(SpotPosition {
-                market_index: 1,
-                balance_type: SpotBalanceType::Deposit,
-                scaled_balance: SPOT_BALANCE_PRECISION_U64,
-                open_orders: 2,
-                open_bids: 100 * LAMPORTS_PER_SOL_I64,
-                open_asks: -LAMPORTS_PER_SOL_I64,
-                ..SpotPosition::default()
-            }),
             ..User::default()
         };
-        create_anchor_account_info!(user, User, user_account_info);
-        let user_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&user_account_info).unwrap();
-
-        create_anchor_account_info!(UserStats::default(), UserStats, user_stats_account_info);
-        let _user_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&user_stats_account_info).unwrap();
-
-        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        create_anchor_account_info!(User::default(), &filler_key, User, user_account_info);
-        let filler_account_loader: AccountLoader<User> =
-            AccountLoader::try_from(&user_account_info).unwrap();
 
-        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
-        let _filler_stats_account_loader: AccountLoader<UserStats> =
-            AccountLoader::try_from(&filler_stats_account_info).unwrap();
-
-        let state = State {
-            min_perp_auction_duration: 1,
-            default_market_order_time_in_force: 10,
-            ..State::default()
+        let mut maker = User {
+            authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: 3 * BASE_PRECISION_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                post_only: true,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -3 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
         };
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
 
-        force_cancel_orders(
-            &state,
-            &user_account_loader,
-            &spot_market_map,
-            &market_map,
-            &mut oracle_map,
-            &filler_account_loader,
-            &clock,
-        )
-        .unwrap();
-
-        let user = user_account_loader.load().unwrap();
-        assert_eq!(user.orders[0], Order::default());
-        assert_ne!(user.orders[1], Order::default());
-        assert_eq!(user.orders[2], Order::default());
-        assert_ne!(user.orders[3], Order::default());
-
-        assert_eq!(user.spot_positions[0].scaled_balance, 20000001);
-        assert_eq!(user.spot_positions[0].balance_type, SpotBalanceType::Borrow,);
-    }
-}
-
-pub mod insert_maker_order_info {
-    use crate::controller::orders::insert_maker_order_info;
-    use crate::controller::position::PositionDirection;
-    use solana_program::pubkey::Pubkey;
-
-    #[test]
-    fn bids() {
-        let mut bids = Vec::with_capacity(3);
-        bids.push((Pubkey::default(), 1, 10));
-        bids.push((Pubkey::default(), 0, 1));
-        let maker_direction = PositionDirection::Long;
-
-        insert_maker_order_info(&mut bids, (Pubkey::default(), 2, 100), maker_direction);
-
-        assert_eq!(
-            bids,
-            vec![
-                (Pubkey::default(), 2, 100),
-                (Pubkey::default(), 1, 10),
-                (Pubkey::default(), 0, 1),
-            ]
-        );
-    }
-
-    #[test]
-    fn asks() {
-        let mut asks = Vec::with_capacity(3);
-        asks.push((Pubkey::default(), 0, 1));
-        asks.push((Pubkey::default(), 1, 10));
-        let maker_direction = PositionDirection::Short;
-
-        insert_maker_order_info(&mut asks, (Pubkey::default(), 2, 100), maker_direction);
-
-        assert_eq!(
-            asks,
-            vec![
-                (Pubkey::default(), 0, 1),
-                (Pubkey::default(), 1, 10),
-                (Pubkey::default(), 2, 100)
-            ]
-        );
-    }
-}
-
-pub mod get_maker_orders_info {
-    use std::str::FromStr;
-
-    use anchor_lang::prelude::{AccountLoader, Clock};
-
-    use crate::controller::orders::get_maker_orders_info;
-    use crate::controller::position::PositionDirection;
-    use crate::math::constants::{
-        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
-        PRICE_PRECISION_I64, PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION_U64,
-        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
-    };
-    use crate::state::oracle::HistoricalOracleData;
-    use crate::state::oracle::OracleSource;
-    use crate::state::perp_market::{PerpMarket, AMM};
-    use crate::state::perp_market_map::PerpMarketMap;
-    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
-    use crate::state::spot_market_map::SpotMarketMap;
-    use crate::state::user::{OrderStatus, OrderType, SpotPosition, User};
-    use crate::state::user_map::UserMap;
-    use crate::test_utils::*;
-    use crate::test_utils::{
-        create_account_info, get_orders, get_positions, get_pyth_price, get_spot_positions,
-    };
-    use crate::{create_account_info, get_orders};
-    use crate::{create_anchor_account_info, QUOTE_PRECISION_I64};
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
 
-    use super::*;
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let mut filler = User::default();
+
+        let (maker_order_price_and_indexes, taker_base_asset_amount_decrement) =
+            get_maker_orders_info(
+                &market_map,
+                &spot_market_map,
+                &mut oracle_map,
+                &makers_and_referrers,
+                &taker_key,
+                &user.authority,
+                &user.orders[0],
+                SelfTradePreventionMode::DecrementAndCancel,
+                &mut Some(&mut filler),
+                &filler_key,
+                0,
+                oracle_price,
+                None,
+                clock.unix_timestamp,
+                clock.slot,
+            )
+            .unwrap();
+
+        assert!(maker_order_price_and_indexes.is_empty());
+        assert_eq!(taker_base_asset_amount_decrement, 2 * BASE_PRECISION_U64);
+
+        let maker = makers_and_referrers.get_ref(&maker_key).unwrap();
+        assert_eq!(maker.orders[0].status, OrderStatus::Open);
+        assert_eq!(maker.orders[0].base_asset_amount, BASE_PRECISION_U64);
+        assert_eq!(maker.perp_positions[0].open_asks, -BASE_PRECISION_I64);
+        assert_eq!(maker.perp_positions[0].open_orders, 1);
+    }
 
     #[test]
-    fn one_maker_order_canceled_for_breaching_oracle_price_band() {
+    fn self_trade_cancel_taker_leaves_maker_order() {
         let clock = Clock {
             slot: 56,
             epoch_start_timestamp: 0,
@@ -9599,16 +13319,179 @@ pub mod get_maker_orders_info {
         create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
         let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
 
+        let taker_key = Pubkey::default();
+        let authority = Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
+        let user = User {
+            authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Long,
+                base_asset_amount: 2 * BASE_PRECISION_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: 2 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+
+        let mut maker = User {
+            authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: 1 * BASE_PRECISION_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                post_only: true,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -1 * BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
+
+        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let mut filler = User::default();
+
+        let (maker_order_price_and_indexes, taker_base_asset_amount_decrement) =
+            get_maker_orders_info(
+                &market_map,
+                &spot_market_map,
+                &mut oracle_map,
+                &makers_and_referrers,
+                &taker_key,
+                &user.authority,
+                &user.orders[0],
+                SelfTradePreventionMode::CancelTaker,
+                &mut Some(&mut filler),
+                &filler_key,
+                0,
+                oracle_price,
+                None,
+                clock.unix_timestamp,
+                clock.slot,
+            )
+            .unwrap();
+
+        assert!(maker_order_price_and_indexes.is_empty());
+        assert_eq!(taker_base_asset_amount_decrement, 2 * BASE_PRECISION_U64);
+
+        let maker = makers_and_referrers.get_ref(&maker_key).unwrap();
+        assert_eq!(maker.orders[0].status, OrderStatus::Open);
+        assert_eq!(maker.orders[0].base_asset_amount, BASE_PRECISION_U64);
+    }
+}
+
+pub mod get_spot_maker_orders_info {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::{AccountLoader, Clock};
+
+    use crate::controller::orders::get_spot_maker_orders_info;
+    use crate::controller::position::PositionDirection;
+    use crate::math::constants::{
+        BASE_PRECISION_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::oracle::OracleSource;
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{OrderStatus, OrderType, SelfTradePreventionMode, SpotPosition, User};
+    use crate::state::user_map::UserMap;
+    use crate::test_utils::*;
+    use crate::test_utils::{create_account_info, get_orders, get_pyth_price, get_spot_positions};
+    use crate::{
+        create_account_info, get_orders, LAMPORTS_PER_SOL_I64, QUOTE_PRECISION_U64,
+        SPOT_BALANCE_PRECISION,
+    };
+    use crate::{create_anchor_account_info, QUOTE_PRECISION_I64};
+
+    use super::*;
+
+    #[test]
+    fn one_maker_order_canceled_for_breaching_oracle_price_band() {
+        let clock = Clock {
+            slot: 56,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            pyth_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let market_map = PerpMarketMap::empty();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+
+        let mut base_market = SpotMarket {
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            oracle: oracle_price_key,
+            ..SpotMarket::default_base_market()
+        };
+
+        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
+
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&spot_market_account_info, &base_spot_market_account_info],
+            true,
+        )
+        .unwrap();
+
         let taker_key = Pubkey::default();
         let taker_authority =
             Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
         let user = User {
             authority: taker_authority,
             orders: get_orders(Order {
-                market_index: 0,
+                market_index: 1,
                 order_id: 1,
                 status: OrderStatus::Open,
                 order_type: OrderType::Market,
+                market_type: MarketType::Spot,
                 direction: PositionDirection::Long,
                 base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
@@ -9618,12 +13501,6 @@ pub mod get_maker_orders_info {
                 price: 50 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 1,
-                open_bids: BASE_PRECISION_I64,
-                ..PerpPosition::default()
-            }),
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
@@ -9635,10 +13512,11 @@ pub mod get_maker_orders_info {
 
         let mut maker_orders = [Order::default(); 32];
         maker_orders[0] = Order {
-            market_index: 0,
+            market_index: 1,
             order_id: 1,
             status: OrderStatus::Open,
             order_type: OrderType::Limit,
+            market_type: MarketType::Spot,
             direction: PositionDirection::Short,
             base_asset_amount: BASE_PRECISION_U64,
             slot: 0,
@@ -9647,11 +13525,12 @@ pub mod get_maker_orders_info {
             ..Order::default()
         };
         maker_orders[1] = Order {
-            market_index: 0,
+            market_index: 1,
             order_id: 2,
             status: OrderStatus::Open,
             order_type: OrderType::Limit,
             direction: PositionDirection::Short,
+            market_type: MarketType::Spot,
             base_asset_amount: BASE_PRECISION_U64,
             slot: 0,
             price: 100 * PRICE_PRECISION_U64,
@@ -9659,20 +13538,22 @@ pub mod get_maker_orders_info {
             ..Order::default()
         };
 
+        let mut maker_spot_positions = get_spot_positions(SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        });
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            open_orders: 2,
+            open_asks: -2 * LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
         let mut maker = User {
             orders: maker_orders,
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 2,
-                open_asks: -2 * BASE_PRECISION_I64,
-                ..PerpPosition::default()
-            }),
-            spot_positions: get_spot_positions(SpotPosition {
-                market_index: 0,
-                balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-                ..SpotPosition::default()
-            }),
+            spot_positions: maker_spot_positions,
             ..User::default()
         };
         let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
@@ -9683,16 +13564,18 @@ pub mod get_maker_orders_info {
         let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
         let mut filler = User::default();
 
-        let maker_order_price_and_indexes = get_maker_orders_info(
+        let (maker_order_price_and_indexes, _) = get_spot_maker_orders_info(
             &market_map,
             &spot_market_map,
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
+            &user.authority,
             &user.orders[0],
+            SelfTradePreventionMode::None,
             &mut Some(&mut filler),
             &filler_key,
-            0,
+            QUOTE_PRECISION_U64 / 100,
             oracle_price,
             None,
             clock.unix_timestamp,
@@ -9713,72 +13596,23 @@ pub mod get_maker_orders_info {
             epoch_start_timestamp: 0,
             epoch: 0,
             leader_schedule_epoch: 0,
-            unix_timestamp: 6,
-        };
-
-        let mut pyth_price = get_pyth_price(100, 6);
-        let oracle_price = 100 * PRICE_PRECISION_I64;
-        let oracle_price_key =
-            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
-        let pyth_program = crate::ids::pyth_program::id();
-        create_account_info!(
-            pyth_price,
-            &oracle_price_key,
-            &pyth_program,
-            oracle_account_info
-        );
-        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
-
-        let mut market = PerpMarket {
-            amm: AMM {
-                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                sqrt_k: 100 * AMM_RESERVE_PRECISION,
-                peg_multiplier: 100 * PEG_PRECISION,
-                max_slippage_ratio: 100,
-                max_fill_reserve_fraction: 100,
-                order_step_size: 1000,
-                order_tick_size: 1,
-                oracle: oracle_price_key,
-                max_spread: 1000,
-                base_spread: 0,
-                long_spread: 0,
-                short_spread: 0,
-                historical_oracle_data: HistoricalOracleData {
-                    last_oracle_price_twap: pyth_price.twap as i64,
-                    last_oracle_price_twap_5min: pyth_price.twap as i64,
-                    last_oracle_price: pyth_price.agg.price as i64,
-                    ..HistoricalOracleData::default()
-                },
-                ..AMM::default()
-            },
-            margin_ratio_initial: 1000,
-            margin_ratio_maintenance: 500,
-            status: MarketStatus::Initialized,
-            ..PerpMarket::default()
-        };
-        market.status = MarketStatus::Active;
-        market.amm.max_base_asset_reserve = u128::MAX;
-        market.amm.min_base_asset_reserve = 0;
-        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
-            crate::math::amm_spread::calculate_spread_reserves(
-                &market.amm,
-                PositionDirection::Long,
-            )
-            .unwrap();
-        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
-            crate::math::amm_spread::calculate_spread_reserves(
-                &market.amm,
-                PositionDirection::Short,
-            )
-            .unwrap();
-        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
-        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
-        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
-        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
-        create_anchor_account_info!(market, PerpMarket, market_account_info);
-        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+            unix_timestamp: 6,
+        };
+
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            pyth_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let market_map = PerpMarketMap::empty();
 
         let mut spot_market = SpotMarket {
             market_index: 0,
@@ -9791,7 +13625,20 @@ pub mod get_maker_orders_info {
             ..SpotMarket::default()
         };
         create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
-        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut base_market = SpotMarket {
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            oracle: oracle_price_key,
+            ..SpotMarket::default_base_market()
+        };
+
+        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
+
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&spot_market_account_info, &base_spot_market_account_info],
+            true,
+        )
+        .unwrap();
 
         let taker_key = Pubkey::default();
         let taker_authority =
@@ -9799,10 +13646,11 @@ pub mod get_maker_orders_info {
         let user = User {
             authority: taker_authority,
             orders: get_orders(Order {
-                market_index: 0,
+                market_index: 1,
                 order_id: 1,
                 status: OrderStatus::Open,
                 order_type: OrderType::Market,
+                market_type: MarketType::Spot,
                 direction: PositionDirection::Long,
                 base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
@@ -9812,12 +13660,6 @@ pub mod get_maker_orders_info {
                 price: 100 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 1,
-                open_bids: BASE_PRECISION_I64,
-                ..PerpPosition::default()
-            }),
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
@@ -9829,11 +13671,12 @@ pub mod get_maker_orders_info {
 
         let mut maker_orders = [Order::default(); 32];
         maker_orders[0] = Order {
-            market_index: 0,
+            market_index: 1,
             order_id: 1,
             status: OrderStatus::Open,
             order_type: OrderType::Limit,
             direction: PositionDirection::Short,
+            market_type: MarketType::Spot,
             base_asset_amount: BASE_PRECISION_U64,
             slot: 0,
             price: 100 * PRICE_PRECISION_U64,
@@ -9842,11 +13685,12 @@ pub mod get_maker_orders_info {
             ..Order::default()
         };
         maker_orders[1] = Order {
-            market_index: 0,
+            market_index: 1,
             order_id: 2,
             status: OrderStatus::Open,
             order_type: OrderType::Limit,
             direction: PositionDirection::Short,
+            market_type: MarketType::Spot,
             base_asset_amount: BASE_PRECISION_U64,
             slot: 0,
             price: 100 * PRICE_PRECISION_U64,
@@ -9854,20 +13698,23 @@ pub mod get_maker_orders_info {
             ..Order::default()
         };
 
+        let mut maker_spot_positions = get_spot_positions(SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        });
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            open_orders: 2,
+            open_asks: -2 * LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+
         let mut maker = User {
             orders: maker_orders,
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 2,
-                open_asks: -2 * BASE_PRECISION_I64,
-                ..PerpPosition::default()
-            }),
-            spot_positions: get_spot_positions(SpotPosition {
-                market_index: 0,
-                balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-                ..SpotPosition::default()
-            }),
+            spot_positions: maker_spot_positions,
             ..User::default()
         };
         let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
@@ -9878,13 +13725,15 @@ pub mod get_maker_orders_info {
         let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
         let mut filler = User::default();
 
-        let maker_order_price_and_indexes = get_maker_orders_info(
+        let (maker_order_price_and_indexes, _) = get_spot_maker_orders_info(
             &market_map,
             &spot_market_map,
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
+            &user.authority,
             &user.orders[0],
+            SelfTradePreventionMode::None,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -9924,56 +13773,7 @@ pub mod get_maker_orders_info {
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let mut market = PerpMarket {
-            amm: AMM {
-                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                sqrt_k: 100 * AMM_RESERVE_PRECISION,
-                peg_multiplier: 100 * PEG_PRECISION,
-                max_slippage_ratio: 100,
-                max_fill_reserve_fraction: 100,
-                order_step_size: 1000,
-                order_tick_size: 1,
-                oracle: oracle_price_key,
-                max_spread: 1000,
-                base_spread: 0,
-                long_spread: 0,
-                short_spread: 0,
-                historical_oracle_data: HistoricalOracleData {
-                    last_oracle_price_twap: pyth_price.twap as i64,
-                    last_oracle_price_twap_5min: pyth_price.twap as i64,
-                    last_oracle_price: pyth_price.agg.price as i64,
-                    ..HistoricalOracleData::default()
-                },
-                ..AMM::default()
-            },
-            margin_ratio_initial: 1000,
-            margin_ratio_maintenance: 500,
-            status: MarketStatus::Initialized,
-            ..PerpMarket::default()
-        };
-        market.status = MarketStatus::Active;
-        market.amm.max_base_asset_reserve = u128::MAX;
-        market.amm.min_base_asset_reserve = 0;
-        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
-            crate::math::amm_spread::calculate_spread_reserves(
-                &market.amm,
-                PositionDirection::Long,
-            )
-            .unwrap();
-        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
-            crate::math::amm_spread::calculate_spread_reserves(
-                &market.amm,
-                PositionDirection::Short,
-            )
-            .unwrap();
-        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
-        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
-        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
-        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
-        create_anchor_account_info!(market, PerpMarket, market_account_info);
-        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+        let market_map = PerpMarketMap::empty();
 
         let mut spot_market = SpotMarket {
             market_index: 0,
@@ -9986,7 +13786,20 @@ pub mod get_maker_orders_info {
             ..SpotMarket::default()
         };
         create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
-        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut base_market = SpotMarket {
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            oracle: oracle_price_key,
+            ..SpotMarket::default_base_market()
+        };
+
+        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
+
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&spot_market_account_info, &base_spot_market_account_info],
+            true,
+        )
+        .unwrap();
 
         let taker_key = Pubkey::default();
         let taker_authority =
@@ -9994,11 +13807,12 @@ pub mod get_maker_orders_info {
         let user = User {
             authority: taker_authority,
             orders: get_orders(Order {
-                market_index: 0,
+                market_index: 1,
                 order_id: 1,
                 status: OrderStatus::Open,
                 order_type: OrderType::Market,
                 direction: PositionDirection::Long,
+                market_type: MarketType::Spot,
                 base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
                 auction_start_price: 0,
@@ -10008,12 +13822,6 @@ pub mod get_maker_orders_info {
                 max_ts: 1,
                 ..Order::default()
             }),
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 1,
-                open_bids: BASE_PRECISION_I64,
-                ..PerpPosition::default()
-            }),
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
@@ -10025,11 +13833,12 @@ pub mod get_maker_orders_info {
 
         let mut maker_orders = [Order::default(); 32];
         maker_orders[0] = Order {
-            market_index: 0,
+            market_index: 1,
             order_id: 1,
             status: OrderStatus::Open,
             order_type: OrderType::Limit,
             direction: PositionDirection::Short,
+            market_type: MarketType::Spot,
             base_asset_amount: BASE_PRECISION_U64,
             slot: 0,
             price: 100 * PRICE_PRECISION_U64,
@@ -10037,21 +13846,24 @@ pub mod get_maker_orders_info {
             ..Order::default()
         };
 
+        let mut maker_spot_positions = get_spot_positions(SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        });
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            open_orders: 1,
+            open_asks: -LAMPORTS_PER_SOL_I64,
+            scaled_balance: 0,
+            ..SpotPosition::default()
+        };
+
         let mut maker = User {
             orders: maker_orders,
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                base_asset_amount: -BASE_PRECISION_I64,
-                open_orders: 1,
-                open_asks: -BASE_PRECISION_I64,
-                ..PerpPosition::default()
-            }),
-            spot_positions: get_spot_positions(SpotPosition {
-                market_index: 0,
-                balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-                ..SpotPosition::default()
-            }),
+            spot_positions: maker_spot_positions,
             ..User::default()
         };
         let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
@@ -10062,13 +13874,15 @@ pub mod get_maker_orders_info {
         let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
         let mut filler = User::default();
 
-        let maker_order_price_and_indexes = get_maker_orders_info(
+        let (maker_order_price_and_indexes, _) = get_spot_maker_orders_info(
             &market_map,
             &spot_market_map,
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
+            &user.authority,
             &user.orders[0],
+            SelfTradePreventionMode::None,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -10105,56 +13919,7 @@ pub mod get_maker_orders_info {
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let mut market = PerpMarket {
-            amm: AMM {
-                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                sqrt_k: 100 * AMM_RESERVE_PRECISION,
-                peg_multiplier: 100 * PEG_PRECISION,
-                max_slippage_ratio: 100,
-                max_fill_reserve_fraction: 100,
-                order_step_size: 1000,
-                order_tick_size: 1,
-                oracle: oracle_price_key,
-                max_spread: 1000,
-                base_spread: 0,
-                long_spread: 0,
-                short_spread: 0,
-                historical_oracle_data: HistoricalOracleData {
-                    last_oracle_price_twap: pyth_price.twap as i64,
-                    last_oracle_price_twap_5min: pyth_price.twap as i64,
-                    last_oracle_price: pyth_price.agg.price as i64,
-                    ..HistoricalOracleData::default()
-                },
-                ..AMM::default()
-            },
-            margin_ratio_initial: 1000,
-            margin_ratio_maintenance: 500,
-            status: MarketStatus::Initialized,
-            ..PerpMarket::default()
-        };
-        market.status = MarketStatus::Active;
-        market.amm.max_base_asset_reserve = u128::MAX;
-        market.amm.min_base_asset_reserve = 0;
-        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
-            crate::math::amm_spread::calculate_spread_reserves(
-                &market.amm,
-                PositionDirection::Long,
-            )
-            .unwrap();
-        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
-            crate::math::amm_spread::calculate_spread_reserves(
-                &market.amm,
-                PositionDirection::Short,
-            )
-            .unwrap();
-        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
-        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
-        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
-        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
-        create_anchor_account_info!(market, PerpMarket, market_account_info);
-        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+        let market_map = PerpMarketMap::empty();
 
         let mut spot_market = SpotMarket {
             market_index: 0,
@@ -10167,7 +13932,20 @@ pub mod get_maker_orders_info {
             ..SpotMarket::default()
         };
         create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
-        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut base_market = SpotMarket {
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            oracle: oracle_price_key,
+            ..SpotMarket::default_base_market()
+        };
+
+        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
+
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&spot_market_account_info, &base_spot_market_account_info],
+            true,
+        )
+        .unwrap();
 
         let taker_key = Pubkey::default();
         let taker_authority =
@@ -10175,10 +13953,11 @@ pub mod get_maker_orders_info {
         let user = User {
             authority: taker_authority,
             orders: get_orders(Order {
-                market_index: 0,
+                market_index: 1,
                 order_id: 1,
                 status: OrderStatus::Open,
                 order_type: OrderType::Market,
+                market_type: MarketType::Spot,
                 direction: PositionDirection::Long,
                 base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
@@ -10189,12 +13968,6 @@ pub mod get_maker_orders_info {
                 max_ts: 1,
                 ..Order::default()
             }),
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 1,
-                open_bids: BASE_PRECISION_I64,
-                ..PerpPosition::default()
-            }),
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
@@ -10204,13 +13977,26 @@ pub mod get_maker_orders_info {
             ..User::default()
         };
 
+        let mut maker_spot_positions = get_spot_positions(SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        });
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            open_orders: 2,
+            ..SpotPosition::default()
+        };
         let mut first_maker = User {
             orders: get_orders!(
                 Order {
-                    market_index: 0,
+                    market_index: 1,
                     order_id: 1,
                     status: OrderStatus::Open,
                     order_type: OrderType::Limit,
+                    market_type: MarketType::Spot,
                     direction: PositionDirection::Short,
                     base_asset_amount: BASE_PRECISION_U64,
                     slot: 0,
@@ -10218,8 +14004,9 @@ pub mod get_maker_orders_info {
                     ..Order::default()
                 },
                 Order {
-                    market_index: 0,
+                    market_index: 1,
                     order_id: 1,
+                    market_type: MarketType::Spot,
                     status: OrderStatus::Open,
                     order_type: OrderType::Limit,
                     direction: PositionDirection::Short,
@@ -10229,17 +14016,7 @@ pub mod get_maker_orders_info {
                     ..Order::default()
                 }
             ),
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 2,
-                ..PerpPosition::default()
-            }),
-            spot_positions: get_spot_positions(SpotPosition {
-                market_index: 0,
-                balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-                ..SpotPosition::default()
-            }),
+            spot_positions: maker_spot_positions,
             ..User::default()
         };
         let first_maker_key =
@@ -10251,13 +14028,26 @@ pub mod get_maker_orders_info {
             first_maker_account_info
         );
 
+        let mut second_maker_spot_positions = get_spot_positions(SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        });
+        second_maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            open_orders: 2,
+            ..SpotPosition::default()
+        };
         let mut second_maker = User {
             orders: get_orders!(
                 Order {
-                    market_index: 0,
+                    market_index: 1,
                     order_id: 1,
                     status: OrderStatus::Open,
                     order_type: OrderType::Limit,
+                    market_type: MarketType::Spot,
                     direction: PositionDirection::Short,
                     base_asset_amount: BASE_PRECISION_U64,
                     slot: 0,
@@ -10265,10 +14055,11 @@ pub mod get_maker_orders_info {
                     ..Order::default()
                 },
                 Order {
-                    market_index: 0,
+                    market_index: 1,
                     order_id: 1,
                     status: OrderStatus::Open,
                     order_type: OrderType::Limit,
+                    market_type: MarketType::Spot,
                     direction: PositionDirection::Short,
                     base_asset_amount: BASE_PRECISION_U64,
                     slot: 0,
@@ -10276,17 +14067,7 @@ pub mod get_maker_orders_info {
                     ..Order::default()
                 }
             ),
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 2,
-                ..PerpPosition::default()
-            }),
-            spot_positions: get_spot_positions(SpotPosition {
-                market_index: 0,
-                balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-                ..SpotPosition::default()
-            }),
+            spot_positions: second_maker_spot_positions,
             ..User::default()
         };
         let second_maker_key =
@@ -10309,13 +14090,15 @@ pub mod get_maker_orders_info {
         let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
         let mut filler = User::default();
 
-        let maker_order_price_and_indexes = get_maker_orders_info(
+        let (maker_order_price_and_indexes, _) = get_spot_maker_orders_info(
             &market_map,
             &spot_market_map,
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
+            &user.authority,
             &user.orders[0],
+            SelfTradePreventionMode::None,
             &mut Some(&mut filler),
             &filler_key,
             0,
@@ -10360,56 +14143,7 @@ pub mod get_maker_orders_info {
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let mut market = PerpMarket {
-            amm: AMM {
-                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
-                sqrt_k: 100 * AMM_RESERVE_PRECISION,
-                peg_multiplier: 100 * PEG_PRECISION,
-                max_slippage_ratio: 100,
-                max_fill_reserve_fraction: 100,
-                order_step_size: 1000,
-                order_tick_size: 1,
-                oracle: oracle_price_key,
-                max_spread: 1000,
-                base_spread: 0,
-                long_spread: 0,
-                short_spread: 0,
-                historical_oracle_data: HistoricalOracleData {
-                    last_oracle_price_twap: pyth_price.twap as i64,
-                    last_oracle_price_twap_5min: pyth_price.twap as i64,
-                    last_oracle_price: pyth_price.agg.price as i64,
-                    ..HistoricalOracleData::default()
-                },
-                ..AMM::default()
-            },
-            margin_ratio_initial: 1000,
-            margin_ratio_maintenance: 500,
-            status: MarketStatus::Initialized,
-            ..PerpMarket::default()
-        };
-        market.status = MarketStatus::Active;
-        market.amm.max_base_asset_reserve = u128::MAX;
-        market.amm.min_base_asset_reserve = 0;
-        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
-            crate::math::amm_spread::calculate_spread_reserves(
-                &market.amm,
-                PositionDirection::Long,
-            )
-            .unwrap();
-        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
-            crate::math::amm_spread::calculate_spread_reserves(
-                &market.amm,
-                PositionDirection::Short,
-            )
-            .unwrap();
-        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
-        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
-        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
-        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
-        create_anchor_account_info!(market, PerpMarket, market_account_info);
-        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+        let market_map = PerpMarketMap::empty();
 
         let mut spot_market = SpotMarket {
             market_index: 0,
@@ -10422,7 +14156,20 @@ pub mod get_maker_orders_info {
             ..SpotMarket::default()
         };
         create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
-        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let mut base_market = SpotMarket {
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            oracle: oracle_price_key,
+            ..SpotMarket::default_base_market()
+        };
+
+        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
+
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&spot_market_account_info, &base_spot_market_account_info],
+            true,
+        )
+        .unwrap();
 
         let taker_key = Pubkey::default();
         let taker_authority =
@@ -10430,10 +14177,11 @@ pub mod get_maker_orders_info {
         let user = User {
             authority: taker_authority,
             orders: get_orders(Order {
-                market_index: 0,
+                market_index: 1,
                 order_id: 1,
                 status: OrderStatus::Open,
                 order_type: OrderType::Market,
+                market_type: MarketType::Spot,
                 direction: PositionDirection::Long,
                 base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
@@ -10444,12 +14192,6 @@ pub mod get_maker_orders_info {
                 max_ts: 1,
                 ..Order::default()
             }),
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 1,
-                open_bids: BASE_PRECISION_I64,
-                ..PerpPosition::default()
-            }),
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
@@ -10459,13 +14201,27 @@ pub mod get_maker_orders_info {
             ..User::default()
         };
 
+        let mut maker_spot_positions = get_spot_positions(SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        });
+        maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            open_orders: 2,
+            open_asks: -2 * LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
         let mut first_maker = User {
             orders: get_orders!(
                 Order {
-                    market_index: 0,
+                    market_index: 1,
                     order_id: 1,
                     status: OrderStatus::Open,
                     order_type: OrderType::Limit,
+                    market_type: MarketType::Spot,
                     direction: PositionDirection::Short,
                     base_asset_amount: BASE_PRECISION_U64,
                     slot: 0,
@@ -10473,9 +14229,10 @@ pub mod get_maker_orders_info {
                     ..Order::default()
                 },
                 Order {
-                    market_index: 0,
+                    market_index: 1,
                     order_id: 2,
                     status: OrderStatus::Open,
+                    market_type: MarketType::Spot,
                     order_type: OrderType::Limit,
                     direction: PositionDirection::Short,
                     base_asset_amount: BASE_PRECISION_U64,
@@ -10484,10 +14241,119 @@ pub mod get_maker_orders_info {
                     ..Order::default()
                 }
             ),
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 2,
-                ..PerpPosition::default()
+            spot_positions: maker_spot_positions,
+            ..User::default()
+        };
+        let first_maker_key =
+            Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        create_anchor_account_info!(
+            first_maker,
+            &first_maker_key,
+            User,
+            first_maker_account_info
+        );
+
+        let makers_and_referrers = UserMap::load_one(&first_maker_account_info).unwrap();
+
+        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let mut filler = User::default();
+
+        let (maker_order_price_and_indexes, _) = get_spot_maker_orders_info(
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &makers_and_referrers,
+            &taker_key,
+            &user.authority,
+            &user.orders[0],
+            SelfTradePreventionMode::None,
+            &mut Some(&mut filler),
+            &filler_key,
+            0,
+            oracle_price,
+            Some(2),
+            clock.unix_timestamp,
+            clock.slot,
+        )
+        .unwrap();
+
+        assert_eq!(
+            maker_order_price_and_indexes,
+            vec![(first_maker_key, 1, 102000000),],
+        );
+    }
+
+    #[test]
+    fn two_makers_with_max_orders() {
+        let clock = Clock {
+            slot: 6,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 0,
+        };
+
+        let mut pyth_price = get_pyth_price(100, 6);
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            pyth_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+        let market_map = PerpMarketMap::empty();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+
+        let mut base_market = SpotMarket {
+            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+            oracle: oracle_price_key,
+            ..SpotMarket::default_base_market()
+        };
+
+        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
+
+        let spot_market_map = SpotMarketMap::load_multiple(
+            vec![&spot_market_account_info, &base_spot_market_account_info],
+            true,
+        )
+        .unwrap();
+
+        let taker_key = Pubkey::default();
+        let taker_authority =
+            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
+        let user = User {
+            authority: taker_authority,
+            orders: get_orders(Order {
+                market_index: 1,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Market,
+                market_type: MarketType::Spot,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                auction_start_price: 0,
+                auction_end_price: 50 * PRICE_PRECISION_I64,
+                auction_duration: 5,
+                price: 100 * PRICE_PRECISION_U64,
+                max_ts: 1,
+                ..Order::default()
             }),
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
@@ -10497,6 +14363,36 @@ pub mod get_maker_orders_info {
             }),
             ..User::default()
         };
+
+        let mut first_maker_spot_positions = get_spot_positions(SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        });
+        first_maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            open_orders: 32,
+            open_asks: -32 * LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut first_maker = User {
+            orders: [Order {
+                market_index: 1,
+                order_id: 1,
+                status: OrderStatus::Open,
+                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                price: 100 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }; 32],
+            spot_positions: first_maker_spot_positions,
+            ..User::default()
+        };
         let first_maker_key =
             Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
         create_anchor_account_info!(
@@ -10506,36 +14402,278 @@ pub mod get_maker_orders_info {
             first_maker_account_info
         );
 
-        let makers_and_referrers = UserMap::load_one(&first_maker_account_info).unwrap();
+        let mut second_maker_spot_positions = get_spot_positions(SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        });
+        second_maker_spot_positions[1] = SpotPosition {
+            market_index: 1,
+            balance_type: SpotBalanceType::Deposit,
+            open_orders: 32,
+            open_asks: -32 * LAMPORTS_PER_SOL_I64,
+            ..SpotPosition::default()
+        };
+        let mut second_maker = User {
+            orders: [Order {
+                market_index: 1,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                market_type: MarketType::Spot,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64,
+                slot: 0,
+                price: 101 * PRICE_PRECISION_U64,
+                ..Order::default()
+            }; 32],
+            spot_positions: second_maker_spot_positions,
+            ..User::default()
+        };
+        let second_maker_key =
+            Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
+        create_anchor_account_info!(
+            second_maker,
+            &second_maker_key,
+            User,
+            second_maker_account_info
+        );
+
+        let mut makers_and_referrers = UserMap::load_one(&first_maker_account_info).unwrap();
+        makers_and_referrers
+            .insert(
+                second_maker_key,
+                AccountLoader::try_from(&second_maker_account_info).unwrap(),
+            )
+            .unwrap();
 
         let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
         let mut filler = User::default();
 
-        let maker_order_price_and_indexes = get_maker_orders_info(
+        let (maker_order_price_and_indexes, _) = get_spot_maker_orders_info(
             &market_map,
             &spot_market_map,
             &mut oracle_map,
             &makers_and_referrers,
             &taker_key,
+            &user.authority,
             &user.orders[0],
+            SelfTradePreventionMode::None,
             &mut Some(&mut filler),
             &filler_key,
             0,
             oracle_price,
-            Some(2),
+            None,
             clock.unix_timestamp,
             clock.slot,
         )
         .unwrap();
 
-        assert_eq!(
-            maker_order_price_and_indexes,
-            vec![(first_maker_key, 1, 102000000),],
-        );
-    }
+        assert_eq!(maker_order_price_and_indexes.len(), 64);
+    }
+}
+
+pub mod update_trigger_order_params {
+    use crate::controller::orders::update_trigger_order_params;
+    use crate::state::oracle::OraclePriceData;
+    use crate::state::user::{Order, OrderTriggerCondition, OrderType};
+    use crate::{PositionDirection, PRICE_PRECISION_I64, PRICE_PRECISION_U64};
+
+    #[test]
+    fn test() {
+        let mut order = Order {
+            order_type: OrderType::TriggerMarket,
+            direction: PositionDirection::Long,
+            trigger_condition: OrderTriggerCondition::Above,
+            ..Order::default()
+        };
+        let oracle_price_data = OraclePriceData {
+            price: 100 * PRICE_PRECISION_I64,
+            confidence: 100 * PRICE_PRECISION_U64,
+            ..OraclePriceData::default()
+        };
+        let slot = 10;
+        let min_auction_duration = 10;
+
+        update_trigger_order_params(
+            &mut order,
+            &oracle_price_data,
+            slot,
+            min_auction_duration,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(order.slot, slot);
+        assert_eq!(order.auction_duration, min_auction_duration);
+        assert_eq!(
+            order.trigger_condition,
+            OrderTriggerCondition::TriggeredAbove
+        );
+        assert_eq!(order.auction_start_price, 100000000);
+        assert_eq!(order.auction_end_price, 100500000);
+
+        let mut order = Order {
+            order_type: OrderType::TriggerMarket,
+            direction: PositionDirection::Short,
+            trigger_condition: OrderTriggerCondition::Below,
+            ..Order::default()
+        };
+
+        update_trigger_order_params(
+            &mut order,
+            &oracle_price_data,
+            slot,
+            min_auction_duration,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(order.slot, slot);
+        assert_eq!(order.auction_duration, min_auction_duration);
+        assert_eq!(
+            order.trigger_condition,
+            OrderTriggerCondition::TriggeredBelow
+        );
+        assert_eq!(order.auction_start_price, 100000000);
+        assert_eq!(order.auction_end_price, 99500000);
+
+        let mut order = Order {
+            order_type: OrderType::TriggerMarket,
+            direction: PositionDirection::Short,
+            trigger_condition: OrderTriggerCondition::TriggeredAbove,
+            ..Order::default()
+        };
+
+        let err = update_trigger_order_params(
+            &mut order,
+            &oracle_price_data,
+            slot,
+            min_auction_duration,
+            None,
+        );
+        assert!(err.is_err());
+
+        let mut order = Order {
+            order_type: OrderType::TriggerMarket,
+            direction: PositionDirection::Short,
+            trigger_condition: OrderTriggerCondition::TriggeredBelow,
+            ..Order::default()
+        };
+
+        let err = update_trigger_order_params(
+            &mut order,
+            &oracle_price_data,
+            slot,
+            min_auction_duration,
+            None,
+        );
+        assert!(err.is_err());
+    }
+}
+
+pub mod update_trailing_stop_trigger_price {
+    use crate::controller::orders::update_trailing_stop_trigger_price;
+    use crate::state::user::{Order, OrderTriggerCondition, OrderType};
+    use crate::{PositionDirection, PRICE_PRECISION_I64, PRICE_PRECISION_U64};
+
+    #[test]
+    fn only_updated_when_trigger_price_moves() {
+        let mut order = Order {
+            order_type: OrderType::TriggerMarket,
+            direction: PositionDirection::Short,
+            trigger_condition: OrderTriggerCondition::Below,
+            trigger_price: 95 * PRICE_PRECISION_U64,
+            oracle_price_offset: 5 * PRICE_PRECISION_I64 as i32,
+            ..Order::default()
+        };
+
+        // oracle moves up, trigger price ratchets up
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 101 * PRICE_PRECISION_I64, 1).unwrap();
+        assert!(updated);
+        assert_eq!(order.trigger_price, 96 * PRICE_PRECISION_U64);
+
+        // oracle moves down, trigger price stays
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 99 * PRICE_PRECISION_I64, 1).unwrap();
+        assert!(!updated);
+        assert_eq!(order.trigger_price, 96 * PRICE_PRECISION_U64);
+
+        // oracle unchanged, trigger price stays
+        let updated =
+            update_trailing_stop_trigger_price(&mut order, 101 * PRICE_PRECISION_I64, 1).unwrap();
+        assert!(!updated);
+        assert_eq!(order.trigger_price, 96 * PRICE_PRECISION_U64);
+    }
+}
+
+mod update_maker_fills_map {
+    use crate::controller::orders::update_maker_fills_map;
+    use crate::PositionDirection;
+    use solana_program::pubkey::Pubkey;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test() {
+        let mut map: BTreeMap<Pubkey, i64> = BTreeMap::new();
+
+        let maker_key = Pubkey::new_unique();
+        let fill = 100;
+        let direction = PositionDirection::Long;
+        update_maker_fills_map(&mut map, &maker_key, direction, fill).unwrap();
+
+        assert_eq!(*map.get(&maker_key).unwrap(), fill as i64);
+
+        update_maker_fills_map(&mut map, &maker_key, direction, fill).unwrap();
+
+        assert_eq!(*map.get(&maker_key).unwrap(), 2 * fill as i64);
+
+        let maker_key = Pubkey::new_unique();
+        let direction = PositionDirection::Short;
+        update_maker_fills_map(&mut map, &maker_key, direction, fill).unwrap();
+
+        assert_eq!(*map.get(&maker_key).unwrap(), -(fill as i64));
+
+        update_maker_fills_map(&mut map, &maker_key, direction, fill).unwrap();
+
+        assert_eq!(*map.get(&maker_key).unwrap(), -2 * fill as i64);
+    }
+}
+
+pub mod place_perp_quote_set {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::Clock;
+
+    use crate::controller::orders::place_perp_quote_set;
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION_I64, PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION,
+        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::market_maker_protection::{MarketMakerProtection, UserMarketMakerProtection};
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::oracle::OracleSource;
+    use crate::state::order_params::{QuoteLevel, QuoteSetParams};
+    use crate::state::perp_market::{ContractType, PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{MarketType, OrderStatus, OrderType, SpotPosition, User};
+    use crate::test_utils::*;
+    use crate::test_utils::{create_account_info, get_positions, get_pyth_price};
+
+    use super::*;
 
     #[test]
-    fn two_makers_with_max_orders() {
+    fn replaces_existing_quotes_in_place() {
         let clock = Clock {
             slot: 6,
             epoch_start_timestamp: 0,
@@ -10544,13 +14682,12 @@ pub mod get_maker_orders_info {
             unix_timestamp: 0,
         };
 
-        let mut pyth_price = get_pyth_price(100, 6);
-        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let mut oracle_price = get_pyth_price(100, 6);
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            pyth_price,
+            oracle_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
@@ -10574,19 +14711,18 @@ pub mod get_maker_orders_info {
                 long_spread: 0,
                 short_spread: 0,
                 historical_oracle_data: HistoricalOracleData {
-                    last_oracle_price_twap: pyth_price.twap as i64,
-                    last_oracle_price_twap_5min: pyth_price.twap as i64,
-                    last_oracle_price: pyth_price.agg.price as i64,
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
                     ..HistoricalOracleData::default()
                 },
                 ..AMM::default()
             },
             margin_ratio_initial: 1000,
             margin_ratio_maintenance: 500,
-            status: MarketStatus::Initialized,
+            status: MarketStatus::Active,
             ..PerpMarket::default()
         };
-        market.status = MarketStatus::Active;
         market.amm.max_base_asset_reserve = u128::MAX;
         market.amm.min_base_asset_reserve = 0;
         let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
@@ -10608,500 +14744,594 @@ pub mod get_maker_orders_info {
         create_anchor_account_info!(market, PerpMarket, market_account_info);
         let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut spot_market = SpotMarket {
+        let mut usdc_spot_market = SpotMarket {
             market_index: 0,
             oracle_source: OracleSource::QuoteAsset,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
             cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
             decimals: 6,
             initial_asset_weight: SPOT_WEIGHT_PRECISION,
             maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
-            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
             ..SpotMarket::default()
         };
-        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
-        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
 
-        let taker_key = Pubkey::default();
-        let taker_authority =
-            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
-            authority: taker_authority,
-            orders: get_orders(Order {
-                market_index: 0,
-                order_id: 1,
-                status: OrderStatus::Open,
-                order_type: OrderType::Market,
-                direction: PositionDirection::Long,
-                base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                auction_start_price: 0,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                price: 100 * PRICE_PRECISION_U64,
-                max_ts: 1,
-                ..Order::default()
-            }),
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 1,
-                open_bids: BASE_PRECISION_I64,
-                ..PerpPosition::default()
-            }),
-            spot_positions: get_spot_positions(SpotPosition {
-                market_index: 0,
-                balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-                ..SpotPosition::default()
-            }),
-            ..User::default()
+        let mut orders = [Order::default(); 32];
+        orders[0] = Order {
+            market_index: 0,
+            order_id: 1,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Long,
+            base_asset_amount: BASE_PRECISION_U64,
+            price: 98 * PRICE_PRECISION_U64,
+            post_only: true,
+            ..Order::default()
         };
-
-        let mut first_maker = User {
-            orders: [Order {
-                market_index: 0,
-                order_id: 1,
-                status: OrderStatus::Open,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
-                base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                price: 100 * PRICE_PRECISION_U64,
-                ..Order::default()
-            }; 32],
-            perp_positions: get_positions(PerpPosition {
-                market_index: 0,
-                open_orders: 2,
-                ..PerpPosition::default()
-            }),
-            spot_positions: get_spot_positions(SpotPosition {
-                market_index: 0,
-                balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-                ..SpotPosition::default()
-            }),
-            ..User::default()
+        orders[1] = Order {
+            market_index: 0,
+            order_id: 2,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Long,
+            base_asset_amount: BASE_PRECISION_U64,
+            price: 97 * PRICE_PRECISION_U64,
+            post_only: true,
+            ..Order::default()
+        };
+        // not post only, so not a quote
+        orders[2] = Order {
+            market_index: 0,
+            order_id: 3,
+            status: OrderStatus::Open,
+            order_type: OrderType::Limit,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Long,
+            base_asset_amount: BASE_PRECISION_U64,
+            price: 90 * PRICE_PRECISION_U64,
+            ..Order::default()
         };
-        let first_maker_key =
-            Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
-        create_anchor_account_info!(
-            first_maker,
-            &first_maker_key,
-            User,
-            first_maker_account_info
-        );
 
-        let mut second_maker = User {
-            orders: [Order {
-                market_index: 0,
-                order_id: 1,
-                status: OrderStatus::Open,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
-                base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                price: 101 * PRICE_PRECISION_U64,
-                ..Order::default()
-            }; 32],
+        let mut user = User {
+            orders,
+            next_order_id: 4,
             perp_positions: get_positions(PerpPosition {
                 market_index: 0,
-                open_orders: 2,
+                open_orders: 3,
+                open_bids: 3 * BASE_PRECISION_I64,
                 ..PerpPosition::default()
             }),
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                scaled_balance: 10000 * SPOT_BALANCE_PRECISION_U64,
                 ..SpotPosition::default()
             }),
+            open_orders: 3,
             ..User::default()
         };
-        let second_maker_key =
-            Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
-        create_anchor_account_info!(
-            second_maker,
-            &second_maker_key,
-            User,
-            second_maker_account_info
-        );
-
-        let mut makers_and_referrers = UserMap::load_one(&first_maker_account_info).unwrap();
-        makers_and_referrers
-            .insert(
-                second_maker_key,
-                AccountLoader::try_from(&second_maker_account_info).unwrap(),
-            )
-            .unwrap();
 
-        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let mut filler = User::default();
+        let params = QuoteSetParams {
+            market_index: 0,
+            reference_price: Some(100 * PRICE_PRECISION_U64),
+            bids: vec![QuoteLevel {
+                price_offset: -(PRICE_PRECISION_I64 as i32),
+                base_asset_amount: 2 * BASE_PRECISION_U64,
+            }],
+            asks: vec![
+                QuoteLevel {
+                    price_offset: PRICE_PRECISION_I64 as i32,
+                    base_asset_amount: BASE_PRECISION_U64,
+                },
+                QuoteLevel {
+                    price_offset: 2 * PRICE_PRECISION_I64 as i32,
+                    base_asset_amount: BASE_PRECISION_U64,
+                },
+            ],
+        };
 
-        let maker_order_price_and_indexes = get_maker_orders_info(
+        place_perp_quote_set(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
             &market_map,
             &spot_market_map,
             &mut oracle_map,
-            &makers_and_referrers,
-            &taker_key,
-            &user.orders[0],
-            &mut Some(&mut filler),
-            &filler_key,
-            0,
-            oracle_price,
+            &clock,
+            &params,
             None,
-            clock.unix_timestamp,
-            clock.slot,
         )
         .unwrap();
 
-        assert_eq!(maker_order_price_and_indexes.len(), 64);
-    }
-}
-
-pub mod get_spot_maker_orders_info {
-    use std::str::FromStr;
+        // first bid is updated in place and keeps its order id, second is cancelled
+        assert_eq!(user.orders[0].order_id, 1);
+        assert_eq!(user.orders[0].status, OrderStatus::Open);
+        assert_eq!(user.orders[0].price, 99 * PRICE_PRECISION_U64);
+        assert_eq!(user.orders[0].base_asset_amount, 2 * BASE_PRECISION_U64);
+        assert_eq!(user.orders[0].direction, PositionDirection::Long);
+        assert_eq!(user.orders[0].slot, clock.slot);
+        assert!(user.orders[0].post_only);
+
+        // asks are new orders in the free slots
+        assert_eq!(user.orders[1].order_id, 4);
+        assert_eq!(user.orders[1].price, 101 * PRICE_PRECISION_U64);
+        assert_eq!(user.orders[1].direction, PositionDirection::Short);
+        assert_eq!(user.orders[3].order_id, 5);
+        assert_eq!(user.orders[3].price, 102 * PRICE_PRECISION_U64);
+
+        // non quote order untouched
+        assert_eq!(user.orders[2].order_id, 3);
+
+        assert_eq!(user.perp_positions[0].open_orders, 4);
+        assert_eq!(user.open_orders, 4);
+        assert_eq!(user.perp_positions[0].open_bids, 3 * BASE_PRECISION_I64);
+        assert_eq!(user.perp_positions[0].open_asks, -2 * BASE_PRECISION_I64);
+        assert_eq!(user.next_order_id, 6);
+
+        // updating the quotes again keeps every order id
+        place_perp_quote_set(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            &params,
+            None,
+        )
+        .unwrap();
 
-    use anchor_lang::prelude::{AccountLoader, Clock};
+        assert_eq!(user.orders[0].order_id, 1);
+        assert_eq!(user.orders[1].order_id, 4);
+        assert_eq!(user.orders[3].order_id, 5);
+        assert_eq!(user.perp_positions[0].open_orders, 4);
+        assert_eq!(user.open_orders, 4);
+        assert_eq!(user.perp_positions[0].open_bids, 3 * BASE_PRECISION_I64);
+        assert_eq!(user.perp_positions[0].open_asks, -2 * BASE_PRECISION_I64);
+        assert_eq!(user.next_order_id, 6);
+
+        // quotes stay pulled while market maker protection is triggered
+        user.has_market_maker_protection = true;
+        let mut market_maker_protection = UserMarketMakerProtection::default();
+        market_maker_protection.markets[0] = MarketMakerProtection {
+            market_index: 0,
+            window_slots: 10,
+            triggered: true,
+            ..MarketMakerProtection::default()
+        };
 
-    use crate::controller::orders::get_spot_maker_orders_info;
-    use crate::controller::position::PositionDirection;
-    use crate::math::constants::{
-        BASE_PRECISION_U64, PRICE_PRECISION_I64, PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION_U64,
-        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
-    };
-    use crate::state::oracle::HistoricalOracleData;
-    use crate::state::oracle::OracleSource;
-    use crate::state::perp_market_map::PerpMarketMap;
-    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
-    use crate::state::spot_market_map::SpotMarketMap;
-    use crate::state::user::{OrderStatus, OrderType, SpotPosition, User};
-    use crate::state::user_map::UserMap;
-    use crate::test_utils::*;
-    use crate::test_utils::{create_account_info, get_orders, get_pyth_price, get_spot_positions};
-    use crate::{
-        create_account_info, get_orders, LAMPORTS_PER_SOL_I64, QUOTE_PRECISION_U64,
-        SPOT_BALANCE_PRECISION,
-    };
-    use crate::{create_anchor_account_info, QUOTE_PRECISION_I64};
+        let result = place_perp_quote_set(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            &params,
+            Some(&market_maker_protection),
+        );
+        assert_eq!(result, Err(ErrorCode::MarketMakerProtectionTriggered));
 
-    use super::*;
+        let result = place_perp_quote_set(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            &params,
+            None,
+        );
+        assert_eq!(result, Err(ErrorCode::InvalidMarketMakerProtection));
+        assert_eq!(user.next_order_id, 6);
+    }
 
     #[test]
-    fn one_maker_order_canceled_for_breaching_oracle_price_band() {
+    fn prediction_market_levels_validated_like_orders() {
         let clock = Clock {
-            slot: 56,
+            slot: 6,
             epoch_start_timestamp: 0,
             epoch: 0,
             leader_schedule_epoch: 0,
             unix_timestamp: 0,
         };
 
-        let mut pyth_price = get_pyth_price(100, 6);
-        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let mut oracle_price = get_pyth_price(100, 6);
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            pyth_price,
+            oracle_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let market_map = PerpMarketMap::empty();
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            contract_type: ContractType::Prediction,
+            ..PerpMarket::default()
+        };
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut spot_market = SpotMarket {
+        let mut usdc_spot_market = SpotMarket {
             market_index: 0,
             oracle_source: OracleSource::QuoteAsset,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
             cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
             decimals: 6,
             initial_asset_weight: SPOT_WEIGHT_PRECISION,
             maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
-            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
             ..SpotMarket::default()
         };
-        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
-
-        let mut base_market = SpotMarket {
-            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
-            oracle: oracle_price_key,
-            ..SpotMarket::default_base_market()
-        };
-
-        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
-
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![&spot_market_account_info, &base_spot_market_account_info],
-            true,
-        )
-        .unwrap();
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
 
-        let taker_key = Pubkey::default();
-        let taker_authority =
-            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
-            authority: taker_authority,
-            orders: get_orders(Order {
-                market_index: 1,
-                order_id: 1,
-                status: OrderStatus::Open,
-                order_type: OrderType::Market,
-                market_type: MarketType::Spot,
-                direction: PositionDirection::Long,
-                base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                auction_start_price: 0,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                price: 50 * PRICE_PRECISION_U64,
-                ..Order::default()
-            }),
+        let mut user = User {
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                scaled_balance: 10000 * SPOT_BALANCE_PRECISION_U64,
                 ..SpotPosition::default()
             }),
             ..User::default()
         };
 
-        let mut maker_orders = [Order::default(); 32];
-        maker_orders[0] = Order {
-            market_index: 1,
-            order_id: 1,
-            status: OrderStatus::Open,
-            order_type: OrderType::Limit,
-            market_type: MarketType::Spot,
-            direction: PositionDirection::Short,
-            base_asset_amount: BASE_PRECISION_U64,
-            slot: 0,
-            price: 50 * PRICE_PRECISION_U64,
-            post_only: true,
-            ..Order::default()
-        };
-        maker_orders[1] = Order {
-            market_index: 1,
-            order_id: 2,
-            status: OrderStatus::Open,
-            order_type: OrderType::Limit,
-            direction: PositionDirection::Short,
-            market_type: MarketType::Spot,
-            base_asset_amount: BASE_PRECISION_U64,
-            slot: 0,
-            price: 100 * PRICE_PRECISION_U64,
-            post_only: true,
-            ..Order::default()
-        };
-
-        let mut maker_spot_positions = get_spot_positions(SpotPosition {
+        // ask level above the max prediction market price
+        let params = QuoteSetParams {
             market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-            ..SpotPosition::default()
-        });
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            open_orders: 2,
-            open_asks: -2 * LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut maker = User {
-            orders: maker_orders,
-            spot_positions: maker_spot_positions,
-            ..User::default()
+            reference_price: Some(PRICE_PRECISION_U64 * 9 / 10),
+            bids: vec![QuoteLevel {
+                price_offset: -(PRICE_PRECISION_I64 as i32) / 10,
+                base_asset_amount: BASE_PRECISION_U64,
+            }],
+            asks: vec![QuoteLevel {
+                price_offset: (PRICE_PRECISION_I64 as i32) / 5,
+                base_asset_amount: BASE_PRECISION_U64,
+            }],
         };
-        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
-        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
 
-        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+        let result = place_perp_quote_set(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            &params,
+            None,
+        );
 
-        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let mut filler = User::default();
+        assert_eq!(result, Err(ErrorCode::InvalidOrderLimitPrice));
 
-        let maker_order_price_and_indexes = get_spot_maker_orders_info(
+        // prediction markets are orderbook only, so levels can't be oracle offsets
+        let params = QuoteSetParams {
+            market_index: 0,
+            reference_price: None,
+            bids: vec![QuoteLevel {
+                price_offset: -(PRICE_PRECISION_I64 as i32) / 10,
+                base_asset_amount: BASE_PRECISION_U64,
+            }],
+            asks: vec![],
+        };
+
+        let result = place_perp_quote_set(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
             &market_map,
             &spot_market_map,
             &mut oracle_map,
-            &makers_and_referrers,
-            &taker_key,
-            &user.orders[0],
-            &mut Some(&mut filler),
-            &filler_key,
-            QUOTE_PRECISION_U64 / 100,
-            oracle_price,
+            &clock,
+            &params,
             None,
-            clock.unix_timestamp,
-            clock.slot,
-        )
-        .unwrap();
-
-        assert_eq!(
-            maker_order_price_and_indexes,
-            vec![(maker_key, 1, 100 * PRICE_PRECISION_U64)]
         );
+
+        assert_eq!(result, Err(ErrorCode::InvalidOrder));
     }
+}
+
+pub mod modify_order {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::{AccountLoader, Clock};
+
+    use crate::controller::orders::{modify_order, ModifyOrderId};
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION_U64, QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION,
+        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::oracle::OracleSource;
+    use crate::state::order_params::{ModifyOrderParams, PlaceOrderOptions};
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{MarketType, OrderStatus, OrderType, SpotPosition, User};
+    use crate::test_utils::*;
+    use crate::test_utils::{create_account_info, get_orders, get_positions, get_pyth_price};
+
+    use super::*;
 
     #[test]
-    fn one_maker_order_canceled_for_being_expired() {
+    fn returns_whether_modified_order_is_risk_increasing() {
         let clock = Clock {
-            slot: 56,
+            slot: 6,
             epoch_start_timestamp: 0,
             epoch: 0,
             leader_schedule_epoch: 0,
-            unix_timestamp: 6,
+            unix_timestamp: 0,
         };
 
-        let mut pyth_price = get_pyth_price(100, 6);
-        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let mut oracle_price = get_pyth_price(100, 6);
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            pyth_price,
+            oracle_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let market_map = PerpMarketMap::empty();
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 100,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                max_spread: 1000,
+                base_spread: 0,
+                long_spread: 0,
+                short_spread: 0,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        let (new_ask_base_asset_reserve, new_ask_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Long,
+            )
+            .unwrap();
+        let (new_bid_base_asset_reserve, new_bid_quote_asset_reserve) =
+            crate::math::amm_spread::calculate_spread_reserves(
+                &market.amm,
+                PositionDirection::Short,
+            )
+            .unwrap();
+        market.amm.ask_base_asset_reserve = new_ask_base_asset_reserve;
+        market.amm.bid_base_asset_reserve = new_bid_base_asset_reserve;
+        market.amm.ask_quote_asset_reserve = new_ask_quote_asset_reserve;
+        market.amm.bid_quote_asset_reserve = new_bid_quote_asset_reserve;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut spot_market = SpotMarket {
+        let mut usdc_spot_market = SpotMarket {
             market_index: 0,
             oracle_source: OracleSource::QuoteAsset,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
             cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
             decimals: 6,
             initial_asset_weight: SPOT_WEIGHT_PRECISION,
             maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
-            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
             ..SpotMarket::default()
         };
-        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
-
-        let mut base_market = SpotMarket {
-            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
-            oracle: oracle_price_key,
-            ..SpotMarket::default_base_market()
-        };
-
-        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
-
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![&spot_market_account_info, &base_spot_market_account_info],
-            true,
-        )
-        .unwrap();
-
-        let taker_key = Pubkey::default();
-        let taker_authority =
-            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
-            authority: taker_authority,
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
+
+        let mut user = User {
             orders: get_orders(Order {
-                market_index: 1,
+                market_index: 0,
                 order_id: 1,
                 status: OrderStatus::Open,
-                order_type: OrderType::Market,
-                market_type: MarketType::Spot,
-                direction: PositionDirection::Long,
+                order_type: OrderType::Limit,
+                market_type: MarketType::Perp,
+                direction: PositionDirection::Short,
                 base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                auction_start_price: 0,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                price: 100 * PRICE_PRECISION_U64,
+                price: 101 * PRICE_PRECISION_U64,
                 ..Order::default()
             }),
+            next_order_id: 2,
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64,
+                quote_asset_amount: -100 * QUOTE_PRECISION_I64,
+                quote_entry_amount: -100 * QUOTE_PRECISION_I64,
+                quote_break_even_amount: -100 * QUOTE_PRECISION_I64,
+                open_orders: 1,
+                open_asks: -BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                scaled_balance: 10000 * SPOT_BALANCE_PRECISION_U64,
                 ..SpotPosition::default()
             }),
+            open_orders: 1,
             ..User::default()
         };
+        create_anchor_account_info!(user, User, user_account_info);
+        let user_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&user_account_info).unwrap();
 
-        let mut maker_orders = [Order::default(); 32];
-        maker_orders[0] = Order {
-            market_index: 1,
-            order_id: 1,
-            status: OrderStatus::Open,
-            order_type: OrderType::Limit,
-            direction: PositionDirection::Short,
-            market_type: MarketType::Spot,
-            base_asset_amount: BASE_PRECISION_U64,
-            slot: 0,
-            price: 100 * PRICE_PRECISION_U64,
-            max_ts: 1,
-            post_only: true,
-            ..Order::default()
-        };
-        maker_orders[1] = Order {
-            market_index: 1,
-            order_id: 2,
-            status: OrderStatus::Open,
-            order_type: OrderType::Limit,
-            direction: PositionDirection::Short,
-            market_type: MarketType::Spot,
-            base_asset_amount: BASE_PRECISION_U64,
-            slot: 0,
-            price: 100 * PRICE_PRECISION_U64,
-            post_only: true,
-            ..Order::default()
-        };
-
-        let mut maker_spot_positions = get_spot_positions(SpotPosition {
-            market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-            ..SpotPosition::default()
-        });
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            open_orders: 2,
-            open_asks: -2 * LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-
-        let mut maker = User {
-            orders: maker_orders,
-            spot_positions: maker_spot_positions,
-            ..User::default()
-        };
-        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
-        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
-
-        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
+        // still only closes the long
+        let risk_increasing = modify_order(
+            ModifyOrderId::OrderId(1),
+            ModifyOrderParams {
+                price: Some(102 * PRICE_PRECISION_U64),
+                ..ModifyOrderParams::default()
+            },
+            &user_account_loader,
+            &State::default(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            PlaceOrderOptions::default(),
+        )
+        .unwrap();
 
-        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let mut filler = User::default();
+        assert!(!risk_increasing);
 
-        let maker_order_price_and_indexes = get_spot_maker_orders_info(
+        // flips the long into a short
+        let risk_increasing = modify_order(
+            ModifyOrderId::OrderId(2),
+            ModifyOrderParams {
+                base_asset_amount: Some(2 * BASE_PRECISION_U64),
+                ..ModifyOrderParams::default()
+            },
+            &user_account_loader,
+            &State::default(),
             &market_map,
             &spot_market_map,
             &mut oracle_map,
-            &makers_and_referrers,
-            &taker_key,
-            &user.orders[0],
-            &mut Some(&mut filler),
-            &filler_key,
-            0,
-            oracle_price,
-            None,
-            clock.unix_timestamp,
-            clock.slot,
+            &clock,
+            PlaceOrderOptions::default(),
         )
         .unwrap();
 
-        assert_eq!(
-            maker_order_price_and_indexes,
-            vec![(maker_key, 1, 100 * PRICE_PRECISION_U64)]
+        assert!(risk_increasing);
+
+        let user = user_account_loader.load().unwrap();
+        assert_eq!(user.orders[0].order_id, 3);
+        assert_eq!(user.orders[0].base_asset_amount, 2 * BASE_PRECISION_U64);
+        assert_eq!(user.perp_positions[0].open_asks, -2 * BASE_PRECISION_I64);
+
+        // missing order isn't modified
+        let risk_increasing = modify_order(
+            ModifyOrderId::OrderId(1),
+            ModifyOrderParams::default(),
+            &user_account_loader,
+            &State::default(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            PlaceOrderOptions::default(),
         );
+
+        assert_eq!(risk_increasing, Ok(false));
     }
+}
+
+pub mod place_perp_order {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::Clock;
+
+    use crate::controller::orders::place_perp_order;
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::error::ErrorCode;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_U64, PEG_PRECISION, PERCENTAGE_PRECISION_I64,
+        PRICE_PRECISION_I64, PRICE_PRECISION_U64, SPOT_BALANCE_PRECISION,
+        SPOT_BALANCE_PRECISION_U64, SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::oracle::OracleSource;
+    use crate::state::order_params::{OrderParams, PlaceOrderOptions, TriggerMarketParams};
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{
+        MarketType, OrderBitFlag, OrderTriggerCondition, OrderType, SpotPosition,
+        TriggerReferencePrice, User,
+    };
+    use crate::test_utils::*;
+    use crate::test_utils::{create_account_info, get_pyth_price};
+
+    use super::*;
 
     #[test]
-    fn one_maker_order_canceled_for_being_reduce_only() {
+    fn cross_market_trigger_price_change() {
         let clock = Clock {
             slot: 6,
             epoch_start_timestamp: 0,
@@ -11110,142 +15340,140 @@ pub mod get_spot_maker_orders_info {
             unix_timestamp: 0,
         };
 
-        let mut pyth_price = get_pyth_price(100, 6);
-        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let mut oracle_price = get_pyth_price(100, 6);
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            pyth_price,
+            oracle_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let market_map = PerpMarketMap::empty();
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
 
-        let mut spot_market = SpotMarket {
+        // the reference market's 5 minute oracle twap is below the oracle price
+        let mut reference_market = PerpMarket {
+            market_index: 1,
+            ..market
+        };
+        reference_market
+            .amm
+            .historical_oracle_data
+            .last_oracle_price_twap_5min = 90 * PRICE_PRECISION_I64;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        create_anchor_account_info!(reference_market, PerpMarket, reference_market_account_info);
+        let market_map = PerpMarketMap::load_multiple(
+            vec![&market_account_info, &reference_market_account_info],
+            true,
+        )
+        .unwrap();
+
+        let mut usdc_spot_market = SpotMarket {
             market_index: 0,
             oracle_source: OracleSource::QuoteAsset,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
             cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
             decimals: 6,
             initial_asset_weight: SPOT_WEIGHT_PRECISION,
             maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
-            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
             ..SpotMarket::default()
         };
-        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
-
-        let mut base_market = SpotMarket {
-            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
-            oracle: oracle_price_key,
-            ..SpotMarket::default_base_market()
-        };
-
-        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
-
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![&spot_market_account_info, &base_spot_market_account_info],
-            true,
-        )
-        .unwrap();
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
 
-        let taker_key = Pubkey::default();
-        let taker_authority =
-            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
-            authority: taker_authority,
-            orders: get_orders(Order {
-                market_index: 1,
-                order_id: 1,
-                status: OrderStatus::Open,
-                order_type: OrderType::Market,
-                direction: PositionDirection::Long,
-                market_type: MarketType::Spot,
-                base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                auction_start_price: 0,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                price: 100 * PRICE_PRECISION_U64,
-                max_ts: 1,
-                ..Order::default()
-            }),
+        let mut user = User {
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                scaled_balance: 10000 * SPOT_BALANCE_PRECISION_U64,
                 ..SpotPosition::default()
-            }),
-            ..User::default()
-        };
-
-        let mut maker_orders = [Order::default(); 32];
-        maker_orders[0] = Order {
-            market_index: 1,
-            order_id: 1,
-            status: OrderStatus::Open,
-            order_type: OrderType::Limit,
-            direction: PositionDirection::Short,
-            market_type: MarketType::Spot,
-            base_asset_amount: BASE_PRECISION_U64,
-            slot: 0,
-            price: 100 * PRICE_PRECISION_U64,
-            reduce_only: true,
-            ..Order::default()
-        };
-
-        let mut maker_spot_positions = get_spot_positions(SpotPosition {
-            market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-            ..SpotPosition::default()
-        });
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            open_orders: 1,
-            open_asks: -LAMPORTS_PER_SOL_I64,
-            scaled_balance: 0,
-            ..SpotPosition::default()
-        };
-
-        let mut maker = User {
-            orders: maker_orders,
-            spot_positions: maker_spot_positions,
+            }),
             ..User::default()
         };
-        let maker_key = Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
-        create_anchor_account_info!(maker, &maker_key, User, maker_account_info);
-
-        let makers_and_referrers = UserMap::load_one(&maker_account_info).unwrap();
 
-        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let mut filler = User::default();
+        let params = OrderParams {
+            order_type: OrderType::TriggerMarket,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
+            market_index: 0,
+            trigger_reference_price: Some(TriggerReferencePrice::OracleTwap5Min),
+            trigger_market_params: Some(TriggerMarketParams {
+                market_type: MarketType::Perp,
+                market_index: 1,
+                trigger_price_change: Some(-PERCENTAGE_PRECISION_I64 / 10),
+            }),
+            ..OrderParams::default()
+        };
 
-        let maker_order_price_and_indexes = get_spot_maker_orders_info(
+        place_perp_order(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
             &market_map,
             &spot_market_map,
             &mut oracle_map,
-            &makers_and_referrers,
-            &taker_key,
-            &user.orders[0],
-            &mut Some(&mut filler),
-            &filler_key,
-            0,
-            oracle_price,
-            None,
-            clock.unix_timestamp,
-            clock.slot,
+            &clock,
+            params,
+            PlaceOrderOptions::default(),
         )
         .unwrap();
 
-        assert_eq!(maker_order_price_and_indexes, vec![],);
+        // 10% below the reference market's twap, not this market's
+        assert!(user.orders[0].is_cross_market_trigger());
+        assert_eq!(user.orders[0].trigger_price, 81 * PRICE_PRECISION_U64);
+        assert_eq!(
+            user.orders[0].trigger_condition,
+            OrderTriggerCondition::Below
+        );
+
+        // can't set both a trigger price and a trigger price change
+        let result = place_perp_order(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            OrderParams {
+                trigger_price: Some(80 * PRICE_PRECISION_U64),
+                ..params
+            },
+            PlaceOrderOptions::default(),
+        );
+
+        assert_eq!(result, Err(ErrorCode::InvalidOrderTrigger));
     }
 
     #[test]
-    fn two_makers() {
+    fn internal_bit_flags_rejected() {
         let clock = Clock {
             slot: 6,
             epoch_start_timestamp: 0,
@@ -11254,242 +15482,252 @@ pub mod get_spot_maker_orders_info {
             unix_timestamp: 0,
         };
 
-        let mut pyth_price = get_pyth_price(100, 6);
-        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let mut oracle_price = get_pyth_price(100, 6);
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            pyth_price,
+            oracle_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
         let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let market_map = PerpMarketMap::empty();
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price.twap as i64,
+                    last_oracle_price_twap_5min: oracle_price.twap as i64,
+                    last_oracle_price: oracle_price.agg.price as i64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let mut spot_market = SpotMarket {
+        let mut usdc_spot_market = SpotMarket {
             market_index: 0,
             oracle_source: OracleSource::QuoteAsset,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
             cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
             decimals: 6,
             initial_asset_weight: SPOT_WEIGHT_PRECISION,
             maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
-            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
             ..SpotMarket::default()
         };
-        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
-
-        let mut base_market = SpotMarket {
-            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
-            oracle: oracle_price_key,
-            ..SpotMarket::default_base_market()
-        };
-
-        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
-
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![&spot_market_account_info, &base_spot_market_account_info],
-            true,
-        )
-        .unwrap();
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
 
-        let taker_key = Pubkey::default();
-        let taker_authority =
-            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
-            authority: taker_authority,
-            orders: get_orders(Order {
-                market_index: 1,
-                order_id: 1,
-                status: OrderStatus::Open,
-                order_type: OrderType::Market,
-                market_type: MarketType::Spot,
-                direction: PositionDirection::Long,
-                base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                auction_start_price: 0,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                price: 100 * PRICE_PRECISION_U64,
-                max_ts: 1,
-                ..Order::default()
-            }),
+        let mut user = User {
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                scaled_balance: 10000 * SPOT_BALANCE_PRECISION_U64,
                 ..SpotPosition::default()
             }),
             ..User::default()
         };
 
-        let mut maker_spot_positions = get_spot_positions(SpotPosition {
+        let params = OrderParams {
+            order_type: OrderType::TriggerMarket,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION_U64,
             market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-            ..SpotPosition::default()
-        });
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            open_orders: 2,
-            ..SpotPosition::default()
-        };
-        let mut first_maker = User {
-            orders: get_orders!(
-                Order {
-                    market_index: 1,
-                    order_id: 1,
-                    status: OrderStatus::Open,
-                    order_type: OrderType::Limit,
-                    market_type: MarketType::Spot,
-                    direction: PositionDirection::Short,
-                    base_asset_amount: BASE_PRECISION_U64,
-                    slot: 0,
-                    price: 100 * PRICE_PRECISION_U64,
-                    ..Order::default()
-                },
-                Order {
-                    market_index: 1,
-                    order_id: 1,
-                    market_type: MarketType::Spot,
-                    status: OrderStatus::Open,
-                    order_type: OrderType::Limit,
-                    direction: PositionDirection::Short,
-                    base_asset_amount: BASE_PRECISION_U64,
-                    slot: 0,
-                    price: 102 * PRICE_PRECISION_U64,
-                    ..Order::default()
-                }
-            ),
-            spot_positions: maker_spot_positions,
-            ..User::default()
+            trigger_price: Some(90 * PRICE_PRECISION_U64),
+            trigger_condition: OrderTriggerCondition::Below,
+            ..OrderParams::default()
         };
-        let first_maker_key =
-            Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
-        create_anchor_account_info!(
-            first_maker,
-            &first_maker_key,
-            User,
-            first_maker_account_info
+
+        // cross market triggers can only be set through trigger_market_params
+        let result = place_perp_order(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            OrderParams {
+                bit_flags: OrderBitFlag::CrossMarketTrigger as u8,
+                ..params
+            },
+            PlaceOrderOptions::default(),
         );
 
-        let mut second_maker_spot_positions = get_spot_positions(SpotPosition {
-            market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-            ..SpotPosition::default()
-        });
-        second_maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            open_orders: 2,
-            ..SpotPosition::default()
-        };
-        let mut second_maker = User {
-            orders: get_orders!(
-                Order {
-                    market_index: 1,
-                    order_id: 1,
-                    status: OrderStatus::Open,
-                    order_type: OrderType::Limit,
-                    market_type: MarketType::Spot,
-                    direction: PositionDirection::Short,
-                    base_asset_amount: BASE_PRECISION_U64,
-                    slot: 0,
-                    price: 101 * PRICE_PRECISION_U64,
-                    ..Order::default()
-                },
-                Order {
-                    market_index: 1,
-                    order_id: 1,
-                    status: OrderStatus::Open,
-                    order_type: OrderType::Limit,
-                    market_type: MarketType::Spot,
-                    direction: PositionDirection::Short,
-                    base_asset_amount: BASE_PRECISION_U64,
-                    slot: 0,
-                    price: 103 * PRICE_PRECISION_U64,
-                    ..Order::default()
-                }
-            ),
-            spot_positions: second_maker_spot_positions,
-            ..User::default()
-        };
-        let second_maker_key =
-            Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
-        create_anchor_account_info!(
-            second_maker,
-            &second_maker_key,
-            User,
-            second_maker_account_info
+        assert_eq!(result, Err(ErrorCode::InvalidOrder));
+
+        // auction curves can only be set through auction_curve
+        let result = place_perp_order(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            OrderParams {
+                bit_flags: OrderBitFlag::AuctionCurveHigh as u8,
+                ..params
+            },
+            PlaceOrderOptions::default(),
         );
 
-        let mut makers_and_referrers = UserMap::load_one(&first_maker_account_info).unwrap();
-        makers_and_referrers
-            .insert(
-                second_maker_key,
-                AccountLoader::try_from(&second_maker_account_info).unwrap(),
-            )
-            .unwrap();
+        assert_eq!(result, Err(ErrorCode::InvalidOrder));
 
-        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let mut filler = User::default();
+        place_perp_order(
+            &State::default(),
+            &mut user,
+            Pubkey::default(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            OrderParams {
+                bit_flags: OrderBitFlag::LinkedOrderEntry as u8,
+                linked_order_group: 1,
+                ..params
+            },
+            PlaceOrderOptions::default(),
+        )
+        .unwrap();
+
+        assert!(user.orders[0].is_linked_order_entry());
+        assert!(!user.orders[0].is_cross_market_trigger());
+        assert_eq!(user.orders[0].trigger_price, 90 * PRICE_PRECISION_U64);
+    }
+}
+
+pub mod fulfill_order_with_matching_policy {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::AccountLoader;
+
+    use crate::controller::orders::fulfill_perp_order;
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION_I64, PRICE_PRECISION_U64, QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::fill_mode::FillMode;
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::perp_market::{MatchingPolicy, PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::user::{OrderStatus, OrderType, SpotPosition, User, UserStats};
+    use crate::state::user_map::{UserMap, UserStatsMap};
+    use crate::test_utils::*;
+    use crate::test_utils::{get_orders, get_positions, get_pyth_price, get_spot_positions};
 
-        let maker_order_price_and_indexes = get_spot_maker_orders_info(
-            &market_map,
-            &spot_market_map,
-            &mut oracle_map,
-            &makers_and_referrers,
-            &taker_key,
-            &user.orders[0],
-            &mut Some(&mut filler),
-            &filler_key,
-            0,
-            oracle_price,
-            None,
-            clock.unix_timestamp,
-            clock.slot,
-        )
-        .unwrap();
+    use super::*;
 
-        assert_eq!(
-            maker_order_price_and_indexes,
-            vec![
-                (first_maker_key, 0, 100000000),
-                (second_maker_key, 0, 101000000),
-                (first_maker_key, 1, 102000000),
-                (second_maker_key, 1, 103000000),
-            ],
-        );
+    fn get_maker(order_slot: u64, base_asset_amount: u64, authority: Pubkey) -> User {
+        User {
+            authority,
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction: PositionDirection::Short,
+                base_asset_amount,
+                slot: order_slot,
+                price: 100 * PRICE_PRECISION_U64,
+                post_only: true,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_asks: -(base_asset_amount as i64),
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        }
     }
 
-    #[test]
-    fn jit_maker_order_id() {
-        let clock = Clock {
-            slot: 6,
-            epoch_start_timestamp: 0,
-            epoch: 0,
-            leader_schedule_epoch: 0,
-            unix_timestamp: 0,
-        };
+    /// Fills a 1 base long against two makers quoting 100: the first for 1 base at slot 0,
+    /// the second for 3 base at slot 1. Returns the makers' base asset amounts
+    fn fill_price_level(
+        matching_policy: MatchingPolicy,
+        top_of_book_allocation: u8,
+        first_maker_has_market_maker_protection: bool,
+    ) -> (i64, i64) {
+        let now = 0_i64;
+        let slot = 2_u64;
 
-        let mut pyth_price = get_pyth_price(100, 6);
-        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let mut oracle_price = get_pyth_price(100, 6);
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            pyth_price,
+            oracle_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
-        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
 
-        let market_map = PerpMarketMap::empty();
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price: 100 * PRICE_PRECISION_I64,
+                    last_oracle_price_twap: 100 * PRICE_PRECISION_I64,
+                    last_oracle_price_twap_5min: 100 * PRICE_PRECISION_I64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            matching_policy,
+            top_of_book_allocation,
+            ..PerpMarket::default_test()
+        };
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
         let mut spot_market = SpotMarket {
             market_index: 0,
@@ -11502,42 +15740,26 @@ pub mod get_spot_maker_orders_info {
             ..SpotMarket::default()
         };
         create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
 
-        let mut base_market = SpotMarket {
-            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
-            oracle: oracle_price_key,
-            ..SpotMarket::default_base_market()
-        };
-
-        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
-
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![&spot_market_account_info, &base_spot_market_account_info],
-            true,
-        )
-        .unwrap();
-
-        let taker_key = Pubkey::default();
-        let taker_authority =
-            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
-            authority: taker_authority,
+        let mut taker = User {
             orders: get_orders(Order {
-                market_index: 1,
+                market_index: 0,
                 order_id: 1,
                 status: OrderStatus::Open,
-                order_type: OrderType::Market,
-                market_type: MarketType::Spot,
+                order_type: OrderType::Limit,
                 direction: PositionDirection::Long,
                 base_asset_amount: BASE_PRECISION_U64,
                 slot: 0,
-                auction_start_price: 0,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
                 price: 100 * PRICE_PRECISION_U64,
-                max_ts: 1,
                 ..Order::default()
             }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids: BASE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
             spot_positions: get_spot_positions(SpotPosition {
                 market_index: 0,
                 balance_type: SpotBalanceType::Deposit,
@@ -11547,110 +15769,282 @@ pub mod get_spot_maker_orders_info {
             ..User::default()
         };
 
-        let mut maker_spot_positions = get_spot_positions(SpotPosition {
-            market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-            ..SpotPosition::default()
-        });
-        maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            open_orders: 2,
-            open_asks: -2 * LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut first_maker = User {
-            orders: get_orders!(
-                Order {
-                    market_index: 1,
-                    order_id: 1,
-                    status: OrderStatus::Open,
-                    order_type: OrderType::Limit,
-                    market_type: MarketType::Spot,
-                    direction: PositionDirection::Short,
-                    base_asset_amount: BASE_PRECISION_U64,
-                    slot: 0,
-                    price: 100 * PRICE_PRECISION_U64,
-                    ..Order::default()
-                },
-                Order {
-                    market_index: 1,
-                    order_id: 2,
-                    status: OrderStatus::Open,
-                    market_type: MarketType::Spot,
-                    order_type: OrderType::Limit,
-                    direction: PositionDirection::Short,
-                    base_asset_amount: BASE_PRECISION_U64,
-                    slot: 0,
-                    price: 102 * PRICE_PRECISION_U64,
-                    ..Order::default()
-                }
-            ),
-            spot_positions: maker_spot_positions,
-            ..User::default()
-        };
+        let maker_authority =
+            Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
+
         let first_maker_key =
             Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
+        let mut first_maker = get_maker(0, BASE_PRECISION_U64, maker_authority);
+        // without a market maker protection account the maker's orders can't be filled
+        first_maker.has_market_maker_protection = first_maker_has_market_maker_protection;
         create_anchor_account_info!(
             first_maker,
             &first_maker_key,
             User,
             first_maker_account_info
         );
+        let first_maker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&first_maker_account_info).unwrap();
 
-        let makers_and_referrers = UserMap::load_one(&first_maker_account_info).unwrap();
+        let second_maker_key =
+            Pubkey::from_str("My11111111111111111111111111111111111111114").unwrap();
+        let mut second_maker = get_maker(1, 3 * BASE_PRECISION_U64, maker_authority);
+        create_anchor_account_info!(
+            second_maker,
+            &second_maker_key,
+            User,
+            second_maker_account_info
+        );
+        let second_maker_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&second_maker_account_info).unwrap();
+
+        let mut makers_and_referrers = UserMap::empty();
+        makers_and_referrers
+            .insert(first_maker_key, first_maker_account_loader)
+            .unwrap();
+        makers_and_referrers
+            .insert(second_maker_key, second_maker_account_loader)
+            .unwrap();
+
+        let mut maker_stats = UserStats {
+            authority: maker_authority,
+            ..UserStats::default()
+        };
+        create_anchor_account_info!(maker_stats, UserStats, maker_stats_account_info);
+        let maker_and_referrer_stats = UserStatsMap::load_one(&maker_stats_account_info).unwrap();
 
-        let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
         let mut filler = User::default();
+        let mut filler_stats = UserStats::default();
+        let mut taker_stats = UserStats::default();
+        let (taker_key, _, filler_key) = get_user_keys();
 
-        let maker_order_price_and_indexes = get_spot_maker_orders_info(
-            &market_map,
-            &spot_market_map,
-            &mut oracle_map,
-            &makers_and_referrers,
+        let (base_asset_amount, _) = fulfill_perp_order(
+            &mut taker,
+            0,
             &taker_key,
-            &user.orders[0],
+            &mut taker_stats,
+            &makers_and_referrers,
+            &maker_and_referrer_stats,
+            &[
+                (first_maker_key, 0, 100 * PRICE_PRECISION_U64),
+                (second_maker_key, 0, 100 * PRICE_PRECISION_U64),
+            ],
             &mut Some(&mut filler),
             &filler_key,
+            &mut Some(&mut filler_stats),
+            None,
+            &spot_market_map,
+            &market_map,
+            &mut oracle_map,
+            &get_fee_structure(),
+            100 * PRICE_PRECISION_U64,
+            Some(100 * PRICE_PRECISION_I64),
+            now,
+            slot,
             0,
-            oracle_price,
-            Some(2),
-            clock.unix_timestamp,
-            clock.slot,
+            false,
+            FillMode::Fill,
         )
         .unwrap();
 
+        assert_eq!(base_asset_amount, BASE_PRECISION_U64);
+        assert_eq!(
+            taker.perp_positions[0].base_asset_amount,
+            BASE_PRECISION_I64
+        );
+
+        let first_maker_base_asset_amount = makers_and_referrers
+            .get_ref(&first_maker_key)
+            .unwrap()
+            .perp_positions[0]
+            .base_asset_amount;
+        let second_maker_base_asset_amount = makers_and_referrers
+            .get_ref(&second_maker_key)
+            .unwrap()
+            .perp_positions[0]
+            .base_asset_amount;
+
+        (
+            first_maker_base_asset_amount,
+            second_maker_base_asset_amount,
+        )
+    }
+
+    #[test]
+    fn pro_rata() {
+        assert_eq!(
+            fill_price_level(MatchingPolicy::ProRata, 0, false),
+            (-BASE_PRECISION_I64 / 4, -BASE_PRECISION_I64 * 3 / 4)
+        );
+    }
+
+    #[test]
+    fn hybrid() {
+        // the first maker gets half, the other half is split pro rata with the rounding leftover
+        // going to the first maker
+        assert_eq!(
+            fill_price_level(MatchingPolicy::Hybrid, 50, false),
+            (-571429000, -428571000)
+        );
+    }
+
+    #[test]
+    fn price_time() {
         assert_eq!(
-            maker_order_price_and_indexes,
-            vec![(first_maker_key, 1, 102000000),],
+            fill_price_level(MatchingPolicy::PriceTime, 0, false),
+            (-BASE_PRECISION_I64, 0)
         );
     }
 
     #[test]
-    fn two_makers_with_max_orders() {
+    fn pulled_maker_share_goes_to_other_makers() {
+        assert_eq!(
+            fill_price_level(MatchingPolicy::ProRata, 0, true),
+            (0, -BASE_PRECISION_I64)
+        );
+        assert_eq!(
+            fill_price_level(MatchingPolicy::Hybrid, 50, true),
+            (0, -BASE_PRECISION_I64)
+        );
+    }
+}
+
+pub mod clear_perp_batch_auction {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::{AccountLoader, Clock};
+
+    use crate::controller::orders::clear_perp_batch_auction;
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::error::DriftResult;
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION_I64, PRICE_PRECISION_U64, QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::market_maker_protection::{MarketMakerProtection, UserMarketMakerProtection};
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::paused_operations::PerpOperation;
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{
+        OrderStatus, OrderTriggerCondition, OrderType, SpotPosition, User, UserStats,
+    };
+    use crate::state::user_map::{UserMap, UserStatsMap};
+    use crate::test_utils::*;
+    use crate::test_utils::{get_orders, get_positions, get_pyth_price, get_spot_positions};
+
+    use super::*;
+
+    fn get_user(
+        direction: PositionDirection,
+        base_asset_amount: u64,
+        price: u64,
+        order_slot: u64,
+        post_only: bool,
+        deposit: u64,
+    ) -> User {
+        let (open_bids, open_asks) = match direction {
+            PositionDirection::Long => (base_asset_amount as i64, 0),
+            PositionDirection::Short => (0, -(base_asset_amount as i64)),
+        };
+
+        User {
+            orders: get_orders(Order {
+                market_index: 0,
+                order_id: 1,
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                direction,
+                base_asset_amount,
+                slot: order_slot,
+                price,
+                post_only,
+                ..Order::default()
+            }),
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                open_orders: 1,
+                open_bids,
+                open_asks,
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: deposit * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        }
+    }
+
+    /// Clears a batch auction in a market with the amm disabled, oracle at 100 and the window
+    /// for orders placed before slot 20 complete. The market maker protection is for the last user.
+    /// Returns the result, the users and the market
+    fn clear(
+        mut users: [User; 4],
+        market_maker_protection: Option<UserMarketMakerProtection>,
+    ) -> (DriftResult, [User; 4], PerpMarket) {
+        let slot = 20_u64;
         let clock = Clock {
-            slot: 6,
+            slot,
             epoch_start_timestamp: 0,
             epoch: 0,
             leader_schedule_epoch: 0,
             unix_timestamp: 0,
         };
 
-        let mut pyth_price = get_pyth_price(100, 6);
-        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let mut oracle_price = get_pyth_price(100, 6);
         let oracle_price_key =
             Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
         let pyth_program = crate::ids::pyth_program::id();
         create_account_info!(
-            pyth_price,
+            oracle_price,
             &oracle_price_key,
             &pyth_program,
             oracle_account_info
         );
-        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
 
-        let market_map = PerpMarketMap::empty();
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price: 100 * PRICE_PRECISION_I64,
+                    last_oracle_price_twap: 100 * PRICE_PRECISION_I64,
+                    last_oracle_price_twap_5min: 100 * PRICE_PRECISION_I64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            paused_operations: PerpOperation::AmmFill as u8,
+            batch_auction_duration: 10,
+            ..PerpMarket::default_test()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
         let mut spot_market = SpotMarket {
             market_index: 0,
@@ -11663,287 +16057,538 @@ pub mod get_spot_maker_orders_info {
             ..SpotMarket::default()
         };
         create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
 
-        let mut base_market = SpotMarket {
-            deposit_balance: 100 * SPOT_BALANCE_PRECISION,
-            oracle: oracle_price_key,
-            ..SpotMarket::default_base_market()
-        };
-
-        create_anchor_account_info!(base_market, SpotMarket, base_spot_market_account_info);
-
-        let spot_market_map = SpotMarketMap::load_multiple(
-            vec![&spot_market_account_info, &base_spot_market_account_info],
-            true,
-        )
-        .unwrap();
-
-        let taker_key = Pubkey::default();
-        let taker_authority =
-            Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let user = User {
-            authority: taker_authority,
-            orders: get_orders(Order {
-                market_index: 1,
-                order_id: 1,
-                status: OrderStatus::Open,
-                order_type: OrderType::Market,
-                market_type: MarketType::Spot,
-                direction: PositionDirection::Long,
-                base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                auction_start_price: 0,
-                auction_end_price: 50 * PRICE_PRECISION_I64,
-                auction_duration: 5,
-                price: 100 * PRICE_PRECISION_U64,
-                max_ts: 1,
-                ..Order::default()
-            }),
-            spot_positions: get_spot_positions(SpotPosition {
-                market_index: 0,
-                balance_type: SpotBalanceType::Deposit,
-                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-                ..SpotPosition::default()
-            }),
-            ..User::default()
-        };
-
-        let mut first_maker_spot_positions = get_spot_positions(SpotPosition {
-            market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-            ..SpotPosition::default()
-        });
-        first_maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            open_orders: 32,
-            open_asks: -32 * LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut first_maker = User {
-            orders: [Order {
-                market_index: 1,
-                order_id: 1,
-                status: OrderStatus::Open,
-                market_type: MarketType::Spot,
-                order_type: OrderType::Limit,
-                direction: PositionDirection::Short,
-                base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                price: 100 * PRICE_PRECISION_U64,
-                ..Order::default()
-            }; 32],
-            spot_positions: first_maker_spot_positions,
-            ..User::default()
-        };
-        let first_maker_key =
-            Pubkey::from_str("My11111111111111111111111111111111111111113").unwrap();
-        create_anchor_account_info!(
-            first_maker,
-            &first_maker_key,
-            User,
-            first_maker_account_info
-        );
-
-        let mut second_maker_spot_positions = get_spot_positions(SpotPosition {
-            market_index: 0,
-            balance_type: SpotBalanceType::Deposit,
-            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
-            ..SpotPosition::default()
-        });
-        second_maker_spot_positions[1] = SpotPosition {
-            market_index: 1,
-            balance_type: SpotBalanceType::Deposit,
-            open_orders: 32,
-            open_asks: -32 * LAMPORTS_PER_SOL_I64,
-            ..SpotPosition::default()
-        };
-        let mut second_maker = User {
-            orders: [Order {
-                market_index: 1,
-                order_id: 1,
-                status: OrderStatus::Open,
-                order_type: OrderType::Limit,
-                market_type: MarketType::Spot,
-                direction: PositionDirection::Short,
-                base_asset_amount: BASE_PRECISION_U64,
-                slot: 0,
-                price: 101 * PRICE_PRECISION_U64,
-                ..Order::default()
-            }; 32],
-            spot_positions: second_maker_spot_positions,
-            ..User::default()
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let mut stats = [
+            UserStats::default(),
+            UserStats::default(),
+            UserStats::default(),
+            UserStats::default(),
+        ];
+        for (user, stats) in users.iter_mut().zip(stats.iter_mut()) {
+            user.authority = Pubkey::new_unique();
+            stats.authority = user.authority;
+        }
+
+        create_anchor_account_info!(users[0], &keys[0], User, user_0_account_info);
+        create_anchor_account_info!(users[1], &keys[1], User, user_1_account_info);
+        create_anchor_account_info!(users[2], &keys[2], User, user_2_account_info);
+        create_anchor_account_info!(users[3], &keys[3], User, user_3_account_info);
+        let mut user_map = UserMap::empty();
+        for (key, account_info) in keys.iter().zip([
+            &user_0_account_info,
+            &user_1_account_info,
+            &user_2_account_info,
+            &user_3_account_info,
+        ]) {
+            user_map
+                .insert(*key, AccountLoader::try_from(account_info).unwrap())
+                .unwrap();
+        }
+
+        let mut market_maker_protection_account = UserMarketMakerProtection {
+            user: keys[3],
+            ..market_maker_protection.unwrap_or_default()
         };
-        let second_maker_key =
-            Pubkey::from_str("My11111111111111111111111111111111111111112").unwrap();
         create_anchor_account_info!(
-            second_maker,
-            &second_maker_key,
-            User,
-            second_maker_account_info
+            market_maker_protection_account,
+            UserMarketMakerProtection,
+            market_maker_protection_account_info
         );
-
-        let mut makers_and_referrers = UserMap::load_one(&first_maker_account_info).unwrap();
-        makers_and_referrers
-            .insert(
-                second_maker_key,
-                AccountLoader::try_from(&second_maker_account_info).unwrap(),
-            )
-            .unwrap();
+        if market_maker_protection.is_some() {
+            user_map
+                .insert_market_maker_protection(
+                    keys[3],
+                    AccountLoader::try_from(&market_maker_protection_account_info).unwrap(),
+                )
+                .unwrap();
+        }
+
+        create_anchor_account_info!(stats[0], UserStats, user_stats_0_account_info);
+        create_anchor_account_info!(stats[1], UserStats, user_stats_1_account_info);
+        create_anchor_account_info!(stats[2], UserStats, user_stats_2_account_info);
+        create_anchor_account_info!(stats[3], UserStats, user_stats_3_account_info);
+        let mut user_stats_map = UserStatsMap::empty();
+        for (stats, account_info) in stats.iter().zip([
+            &user_stats_0_account_info,
+            &user_stats_1_account_info,
+            &user_stats_2_account_info,
+            &user_stats_3_account_info,
+        ]) {
+            user_stats_map
+                .insert(
+                    stats.authority,
+                    AccountLoader::try_from(account_info).unwrap(),
+                )
+                .unwrap();
+        }
 
         let filler_key = Pubkey::from_str("My11111111111111111111111111111111111111111").unwrap();
-        let mut filler = User::default();
+        create_anchor_account_info!(User::default(), &filler_key, User, filler_account_info);
+        let filler_account_loader: AccountLoader<User> =
+            AccountLoader::try_from(&filler_account_info).unwrap();
+        create_anchor_account_info!(UserStats::default(), UserStats, filler_stats_account_info);
+        let filler_stats_account_loader: AccountLoader<UserStats> =
+            AccountLoader::try_from(&filler_stats_account_info).unwrap();
+
+        let state = State {
+            perp_fee_structure: get_fee_structure(),
+            ..State::default()
+        };
 
-        let maker_order_price_and_indexes = get_spot_maker_orders_info(
+        let result = clear_perp_batch_auction(
+            0,
+            &state,
             &market_map,
             &spot_market_map,
             &mut oracle_map,
-            &makers_and_referrers,
-            &taker_key,
-            &user.orders[0],
-            &mut Some(&mut filler),
-            &filler_key,
-            0,
-            oracle_price,
-            None,
-            clock.unix_timestamp,
-            clock.slot,
-        )
-        .unwrap();
+            &user_map,
+            &user_stats_map,
+            &filler_account_loader,
+            &filler_stats_account_loader,
+            &clock,
+        );
+
+        let users = [
+            *user_map.get_ref(&keys[0]).unwrap(),
+            *user_map.get_ref(&keys[1]).unwrap(),
+            *user_map.get_ref(&keys[2]).unwrap(),
+            *user_map.get_ref(&keys[3]).unwrap(),
+        ];
+        let market = *market_map.get_ref(&0).unwrap();
+
+        (result, users, market)
+    }
+
+    fn get_users() -> [User; 4] {
+        [
+            // taker bid for 2 at 102 that crosses the taker ask and then the maker at 101
+            get_user(
+                PositionDirection::Long,
+                2 * BASE_PRECISION_U64,
+                102 * PRICE_PRECISION_U64,
+                0,
+                false,
+                100,
+            ),
+            // taker ask for 1 at 98
+            get_user(
+                PositionDirection::Short,
+                BASE_PRECISION_U64,
+                98 * PRICE_PRECISION_U64,
+                5,
+                false,
+                100,
+            ),
+            // taker bid at 100.5 that doesn't cross 101 and shouldn't push the price up to 102
+            get_user(
+                PositionDirection::Long,
+                BASE_PRECISION_U64,
+                100 * PRICE_PRECISION_U64 + PRICE_PRECISION_U64 / 2,
+                0,
+                false,
+                100,
+            ),
+            // makers asking 1 at 101, and 1 at 102 in the same account
+            {
+                let mut maker = get_user(
+                    PositionDirection::Short,
+                    BASE_PRECISION_U64,
+                    101 * PRICE_PRECISION_U64,
+                    0,
+                    true,
+                    100,
+                );
+                maker.orders[1] = Order {
+                    order_id: 2,
+                    price: 102 * PRICE_PRECISION_U64,
+                    ..maker.orders[0]
+                };
+                maker.perp_positions[0].open_orders = 2;
+                maker.perp_positions[0].open_asks = -2 * BASE_PRECISION_I64;
+                maker
+            },
+        ]
+    }
 
-        assert_eq!(maker_order_price_and_indexes.len(), 64);
+    #[test]
+    fn clears_at_limit_aware_price_and_takers_pay_taker_fees() {
+        let (result, users, market) = clear(get_users(), None);
+        assert_eq!(result, Ok(()));
+
+        // taker fee on 101 is 50500, maker rebate is 30300
+        let bidder = &users[0].perp_positions[0];
+        assert_eq!(bidder.base_asset_amount, 2 * BASE_PRECISION_I64);
+        assert_eq!(bidder.quote_asset_amount, -202_101_000);
+        assert_eq!(users[0].orders[0], Order::default());
+
+        let asker = &users[1].perp_positions[0];
+        assert_eq!(asker.base_asset_amount, -BASE_PRECISION_I64);
+        assert_eq!(asker.quote_asset_amount, 100_949_500);
+        assert_eq!(users[1].orders[0], Order::default());
+
+        assert_eq!(users[2].perp_positions[0].base_asset_amount, 0);
+        assert_eq!(users[2].orders[0].status, OrderStatus::Open);
+
+        let maker = &users[3].perp_positions[0];
+        assert_eq!(maker.base_asset_amount, -BASE_PRECISION_I64);
+        assert_eq!(maker.quote_asset_amount, 101_030_300);
+        assert_eq!(users[3].orders[1].status, OrderStatus::Open);
+        assert_eq!(market.last_fill_price, 101 * PRICE_PRECISION_U64);
     }
-}
 
-pub mod update_trigger_order_params {
-    use crate::controller::orders::update_trigger_order_params;
-    use crate::state::oracle::OraclePriceData;
-    use crate::state::user::{Order, OrderTriggerCondition, OrderType};
-    use crate::{PositionDirection, PRICE_PRECISION_I64, PRICE_PRECISION_U64};
+    #[test]
+    fn insufficient_margin_cancels_order() {
+        let mut users = get_users();
+        users[0].spot_positions[0].scaled_balance = SPOT_BALANCE_PRECISION_U64;
+
+        let (result, users, _) = clear(users, None);
+        assert_eq!(result, Ok(()));
+
+        // the bidder can't cover their order so it's cancelled instead of failing the batch,
+        // leaving the other two takers to cross at the oracle
+        assert_eq!(users[0].orders[0], Order::default());
+        assert_eq!(users[0].perp_positions[0].base_asset_amount, 0);
+
+        let asker = &users[1].perp_positions[0];
+        assert_eq!(asker.base_asset_amount, -BASE_PRECISION_I64);
+        assert_eq!(asker.quote_asset_amount, 99_950_000);
+
+        let bidder = &users[2].perp_positions[0];
+        assert_eq!(bidder.base_asset_amount, BASE_PRECISION_I64);
+        assert_eq!(bidder.quote_asset_amount, -100_050_000);
+
+        assert_eq!(users[3].perp_positions[0].base_asset_amount, 0);
+    }
 
     #[test]
-    fn test() {
-        let mut order = Order {
+    fn takers_wait_for_their_window_to_complete() {
+        let mut users = get_users();
+
+        // untriggered trigger orders aren't takers
+        users[0].orders[1] = Order {
+            market_index: 0,
+            order_id: 2,
+            status: OrderStatus::Open,
             order_type: OrderType::TriggerMarket,
             direction: PositionDirection::Long,
+            base_asset_amount: BASE_PRECISION_U64,
+            trigger_price: 200 * PRICE_PRECISION_U64,
             trigger_condition: OrderTriggerCondition::Above,
+            slot: 10,
             ..Order::default()
         };
-        let oracle_price_data = OraclePriceData {
-            price: 100 * PRICE_PRECISION_I64,
-            confidence: 100 * PRICE_PRECISION_U64,
-            ..OraclePriceData::default()
+        users[0].perp_positions[0].open_orders = 2;
+
+        // an ask in the current window waits for the next clear
+        users[1].orders[1] = Order {
+            order_id: 2,
+            slot: 20,
+            ..users[1].orders[0]
         };
-        let slot = 10;
-        let min_auction_duration = 10;
+        users[1].perp_positions[0].open_orders = 2;
+        users[1].perp_positions[0].open_asks = -2 * BASE_PRECISION_I64;
 
-        update_trigger_order_params(
-            &mut order,
-            &oracle_price_data,
-            slot,
-            min_auction_duration,
-            None,
-        )
-        .unwrap();
+        let (result, users, _) = clear(users, None);
+        assert_eq!(result, Ok(()));
 
-        assert_eq!(order.slot, slot);
-        assert_eq!(order.auction_duration, min_auction_duration);
         assert_eq!(
-            order.trigger_condition,
-            OrderTriggerCondition::TriggeredAbove
+            users[0].perp_positions[0].base_asset_amount,
+            2 * BASE_PRECISION_I64
         );
-        assert_eq!(order.auction_start_price, 100000000);
-        assert_eq!(order.auction_end_price, 100500000);
+        assert_eq!(users[0].orders[1].status, OrderStatus::Open);
+        assert_eq!(
+            users[1].perp_positions[0].base_asset_amount,
+            -BASE_PRECISION_I64
+        );
+        assert_eq!(users[1].orders[1].status, OrderStatus::Open);
+        assert_eq!(users[2].orders[0].status, OrderStatus::Open);
+    }
 
-        let mut order = Order {
-            order_type: OrderType::TriggerMarket,
-            direction: PositionDirection::Short,
-            trigger_condition: OrderTriggerCondition::Below,
-            ..Order::default()
+    #[test]
+    fn market_maker_protection_pulls_maker() {
+        let mut market_maker_protection = UserMarketMakerProtection::default();
+        market_maker_protection.markets[0] = MarketMakerProtection {
+            market_index: 0,
+            window_slots: 10,
+            max_fills: 1,
+            ..MarketMakerProtection::default()
         };
 
-        update_trigger_order_params(
-            &mut order,
-            &oracle_price_data,
-            slot,
-            min_auction_duration,
-            None,
-        )
-        .unwrap();
+        let mut users = get_users();
+        users[3].has_market_maker_protection = true;
 
-        assert_eq!(order.slot, slot);
-        assert_eq!(order.auction_duration, min_auction_duration);
+        let (result, users, _) = clear(users, Some(market_maker_protection));
+        assert_eq!(result, Ok(()));
+
+        // the maker's fill at 101 triggers the protection and their other ask is cancelled
+        let maker = &users[3].perp_positions[0];
+        assert_eq!(maker.base_asset_amount, -BASE_PRECISION_I64);
+        assert_eq!(users[3].orders[1], Order::default());
+        assert_eq!(maker.open_orders, 0);
+
+        // once triggered the maker isn't filled
+        market_maker_protection.markets[0].triggered = true;
+
+        let mut users = get_users();
+        users[3].has_market_maker_protection = true;
+
+        let (result, users, _) = clear(users, Some(market_maker_protection));
+        assert_eq!(result, Ok(()));
+
+        assert_eq!(users[3].perp_positions[0].base_asset_amount, 0);
+        assert_eq!(users[3].orders[0], Order::default());
+        assert_eq!(users[3].orders[1], Order::default());
         assert_eq!(
-            order.trigger_condition,
-            OrderTriggerCondition::TriggeredBelow
+            users[0].perp_positions[0].base_asset_amount,
+            BASE_PRECISION_I64
         );
-        assert_eq!(order.auction_start_price, 100000000);
-        assert_eq!(order.auction_end_price, 99500000);
 
-        let mut order = Order {
-            order_type: OrderType::TriggerMarket,
-            direction: PositionDirection::Short,
-            trigger_condition: OrderTriggerCondition::TriggeredAbove,
-            ..Order::default()
+        // without its protection account the maker is skipped but its orders stay open
+        let mut users = get_users();
+        users[3].has_market_maker_protection = true;
+
+        let (result, users, _) = clear(users, None);
+        assert_eq!(result, Ok(()));
+
+        assert_eq!(users[3].perp_positions[0].base_asset_amount, 0);
+        assert_eq!(users[3].orders[0].status, OrderStatus::Open);
+    }
+}
+
+pub mod fill_rfq_quote {
+    use std::str::FromStr;
+
+    use anchor_lang::prelude::Clock;
+
+    use crate::controller::orders::fill_rfq_quote;
+    use crate::controller::position::PositionDirection;
+    use crate::create_account_info;
+    use crate::create_anchor_account_info;
+    use crate::error::{DriftResult, ErrorCode};
+    use crate::math::constants::{
+        AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, PEG_PRECISION,
+        PRICE_PRECISION_I64, PRICE_PRECISION_U64, QUOTE_PRECISION_I64, SPOT_BALANCE_PRECISION_U64,
+        SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::state::market_maker_protection::{MarketMakerProtection, UserMarketMakerProtection};
+    use crate::state::oracle::{HistoricalOracleData, OracleSource};
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::perp_market_map::PerpMarketMap;
+    use crate::state::rfq::{Rfq, RfqQuote};
+    use crate::state::spot_market::{SpotBalanceType, SpotMarket};
+    use crate::state::spot_market_map::SpotMarketMap;
+    use crate::state::state::State;
+    use crate::state::user::{SpotPosition, User, UserStats};
+    use crate::test_utils::*;
+    use crate::test_utils::{get_pyth_price, get_spot_positions};
+
+    use super::*;
+
+    /// Accepts a quote at 101 for a 1 base long. Returns the result, the taker, the maker and the
+    /// maker's market maker protection
+    fn accept_quote(
+        expected_price: u64,
+        mut market_maker_protection: Option<UserMarketMakerProtection>,
+    ) -> (DriftResult, User, User, Option<UserMarketMakerProtection>) {
+        let clock = Clock {
+            slot: 10,
+            epoch_start_timestamp: 0,
+            epoch: 0,
+            leader_schedule_epoch: 0,
+            unix_timestamp: 10,
         };
 
-        let err = update_trigger_order_params(
-            &mut order,
-            &oracle_price_data,
-            slot,
-            min_auction_duration,
-            None,
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
         );
-        assert!(err.is_err());
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
 
-        let mut order = Order {
-            order_type: OrderType::TriggerMarket,
-            direction: PositionDirection::Short,
-            trigger_condition: OrderTriggerCondition::TriggeredBelow,
-            ..Order::default()
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                terminal_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 1000,
+                order_tick_size: 1,
+                oracle: oracle_price_key,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price: 100 * PRICE_PRECISION_I64,
+                    last_oracle_price_twap: 100 * PRICE_PRECISION_I64,
+                    last_oracle_price_twap_5min: 100 * PRICE_PRECISION_I64,
+                    ..HistoricalOracleData::default()
+                },
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Active,
+            ..PerpMarket::default_test()
         };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
 
-        let err = update_trigger_order_params(
-            &mut order,
-            &oracle_price_data,
-            slot,
-            min_auction_duration,
-            None,
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        let get_user = || User {
+            authority: Pubkey::new_unique(),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        let mut taker = get_user();
+        let mut maker = get_user();
+        let taker_key = Pubkey::new_unique();
+        let maker_key = Pubkey::new_unique();
+
+        if let Some(market_maker_protection) = market_maker_protection.as_mut() {
+            market_maker_protection.user = maker_key;
+            maker.has_market_maker_protection = true;
+        }
+
+        let mut rfq = Rfq {
+            user: taker_key,
+            authority: taker.authority,
+            base_asset_amount: BASE_PRECISION_U64,
+            expiry_ts: 100,
+            market_index: 0,
+            direction: PositionDirection::Long,
+            ..Rfq::default()
+        };
+        rfq.whitelisted_makers[0] = maker_key;
+        rfq.quotes[0] = RfqQuote {
+            price: 101 * PRICE_PRECISION_U64,
+            expiry_ts: 100,
+        };
+
+        let state = State {
+            perp_fee_structure: get_fee_structure(),
+            ..State::default()
+        };
+
+        let result = fill_rfq_quote(
+            &state,
+            &rfq,
+            expected_price,
+            &mut taker,
+            &taker_key,
+            &mut UserStats::default(),
+            &mut maker,
+            &maker_key,
+            &mut UserStats::default(),
+            market_maker_protection.as_mut(),
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
         );
-        assert!(err.is_err());
+
+        (result, taker, maker, market_maker_protection)
     }
-}
 
-mod update_maker_fills_map {
-    use crate::controller::orders::update_maker_fills_map;
-    use crate::PositionDirection;
-    use solana_program::pubkey::Pubkey;
-    use std::collections::BTreeMap;
+    fn get_market_maker_protection(triggered: bool) -> UserMarketMakerProtection {
+        let mut market_maker_protection = UserMarketMakerProtection::default();
+        market_maker_protection.markets[0] = MarketMakerProtection {
+            market_index: 0,
+            window_slots: 10,
+            max_fills: 1,
+            triggered,
+            ..MarketMakerProtection::default()
+        };
+        market_maker_protection
+    }
 
     #[test]
-    fn test() {
-        let mut map: BTreeMap<Pubkey, i64> = BTreeMap::new();
-
-        let maker_key = Pubkey::new_unique();
-        let fill = 100;
-        let direction = PositionDirection::Long;
-        update_maker_fills_map(&mut map, &maker_key, direction, fill).unwrap();
-
-        assert_eq!(*map.get(&maker_key).unwrap(), fill as i64);
+    fn fills_at_quote() {
+        let (result, taker, maker, _) = accept_quote(101 * PRICE_PRECISION_U64, None);
+        assert_eq!(result, Ok(()));
 
-        update_maker_fills_map(&mut map, &maker_key, direction, fill).unwrap();
+        // taker fee on 101 is 50500, maker rebate is 30300
+        assert_eq!(
+            taker.perp_positions[0].base_asset_amount,
+            BASE_PRECISION_I64
+        );
+        assert_eq!(taker.perp_positions[0].quote_asset_amount, -101_050_500);
+        assert_eq!(taker.perp_positions[0].open_orders, 0);
+        assert_eq!(taker.orders[0], Order::default());
 
-        assert_eq!(*map.get(&maker_key).unwrap(), 2 * fill as i64);
+        assert_eq!(
+            maker.perp_positions[0].base_asset_amount,
+            -BASE_PRECISION_I64
+        );
+        assert_eq!(maker.perp_positions[0].quote_asset_amount, 101_030_300);
+        assert_eq!(maker.perp_positions[0].open_orders, 0);
+        assert_eq!(maker.orders[0], Order::default());
+    }
 
-        let maker_key = Pubkey::new_unique();
-        let direction = PositionDirection::Short;
-        update_maker_fills_map(&mut map, &maker_key, direction, fill).unwrap();
+    #[test]
+    fn quote_changed_before_accept() {
+        let (result, taker, _, _) = accept_quote(100 * PRICE_PRECISION_U64, None);
+        assert_eq!(result, Err(ErrorCode::InvalidRfq));
+        assert_eq!(taker.perp_positions[0].base_asset_amount, 0);
+    }
 
-        assert_eq!(*map.get(&maker_key).unwrap(), -(fill as i64));
+    #[test]
+    fn fill_triggers_market_maker_protection() {
+        let (result, _, maker, market_maker_protection) = accept_quote(
+            101 * PRICE_PRECISION_U64,
+            Some(get_market_maker_protection(false)),
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            maker.perp_positions[0].base_asset_amount,
+            -BASE_PRECISION_I64
+        );
 
-        update_maker_fills_map(&mut map, &maker_key, direction, fill).unwrap();
+        let market_maker_protection = market_maker_protection.unwrap();
+        assert!(market_maker_protection.is_triggered(0));
+        assert_eq!(market_maker_protection.markets[0].fills, 1);
+    }
 
-        assert_eq!(*map.get(&maker_key).unwrap(), -2 * fill as i64);
+    #[test]
+    fn triggered_market_maker_protection_rejects_quote() {
+        let (result, taker, maker, _) = accept_quote(
+            101 * PRICE_PRECISION_U64,
+            Some(get_market_maker_protection(true)),
+        );
+        assert_eq!(result, Err(ErrorCode::MarketMakerProtectionTriggered));
+        assert_eq!(taker.perp_positions[0].base_asset_amount, 0);
+        assert_eq!(maker.perp_positions[0].base_asset_amount, 0);
     }
 }
@@ -9,10 +9,11 @@ use crate::controller::position::{
     update_quote_asset_and_break_even_amount, update_settled_pnl, PositionDelta,
 };
 use crate::controller::spot_balance::{
-    update_spot_balances, update_spot_market_cumulative_interest,
+    update_quote_spot_market_for_perp_market, update_spot_balances,
 };
 use crate::error::{DriftResult, ErrorCode};
 use crate::math::amm::calculate_net_user_pnl;
+use crate::math::bankruptcy::is_isolated_perp_position_bankrupt;
 use crate::math::oracle::{is_oracle_valid_for_action, DriftAction};
 
 use crate::math::casting::Cast;
@@ -24,7 +25,7 @@ use crate::math::margin::{
 use crate::math::position::calculate_base_asset_value_with_expiry_price;
 use crate::math::safe_math::SafeMath;
 use crate::math::spot_balance::get_token_amount;
-use crate::state::margin_calculation::MarginContext;
+use crate::state::margin_calculation::{MarginContext, MarketIdentifier};
 
 use crate::state::events::{OrderActionExplanation, SettlePnlExplanation, SettlePnlRecord};
 use crate::state::oracle_map::OracleMap;
@@ -63,9 +64,18 @@ pub fn settle_pnl(
 ) -> DriftResult {
     validate!(!user.is_bankrupt(), ErrorCode::UserBankrupt)?;
     let now = clock.unix_timestamp;
+    let quote_spot_market_index = perp_market_map
+        .get_ref(&market_index)?
+        .quote_spot_market_index;
     {
-        let spot_market = &mut spot_market_map.get_quote_spot_market_mut()?;
-        update_spot_market_cumulative_interest(spot_market, None, now)?;
+        let spot_market = &mut spot_market_map.get_ref_mut(&quote_spot_market_index)?;
+        update_quote_spot_market_for_perp_market(
+            &perp_market_map.get_ref(&market_index)?,
+            spot_market,
+            oracle_map,
+            now,
+            Some(DriftAction::SettlePnl),
+        )?;
     }
 
     let mut market = perp_market_map.get_ref_mut(&market_index)?;
@@ -75,10 +85,14 @@ pub fn settle_pnl(
     crate::controller::lp::settle_funding_payment_then_lp(user, user_key, &mut market, now)?;
 
     let oracle_price = oracle_map.get_price_data(&market.amm.oracle)?.price;
+    let valuation_price = market.get_valuation_price(oracle_price)?;
     drop(market);
 
     let position_index = get_position_index(&user.perp_positions, market_index)?;
-    let unrealized_pnl = user.perp_positions[position_index].get_unrealized_pnl(oracle_price)?;
+    let unrealized_pnl = user.perp_positions[position_index].get_unrealized_pnl(valuation_price)?;
+
+    // isolated perp position settles against its own collateral, so the cross margin check doesnt apply
+    let is_isolated_position = user.is_isolated_perp_market(market_index);
 
     // cannot settle negative pnl this way on a user who is in liquidation territory
     if user.perp_positions[position_index].is_lp() && !user.is_advanced_lp() {
@@ -91,7 +105,7 @@ pub fn settle_pnl(
                 .margin_buffer(state.liquidation_margin_buffer_ratio),
         )?;
 
-        if !margin_calc.meets_margin_requirement() {
+        if !margin_calc.meets_cross_margin_requirement() {
             msg!("market={} lp does not meet initial margin requirement, attempting to burn shares for risk reduction",
             market_index);
             attempt_burn_user_lp_shares_for_risk_reduction(
@@ -122,7 +136,7 @@ pub fn settle_pnl(
                 return Ok(());
             }
         }
-    } else if unrealized_pnl < 0 {
+    } else if unrealized_pnl < 0 && !is_isolated_position {
         // may already be cached
         let meets_margin_requirement = match meets_margin_requirement {
             Some(meets_margin_requirement) => meets_margin_requirement,
@@ -144,7 +158,7 @@ pub fn settle_pnl(
         }
     }
 
-    let spot_market = &mut spot_market_map.get_quote_spot_market_mut()?;
+    let spot_market = &mut spot_market_map.get_ref_mut(&quote_spot_market_index)?;
     let perp_market = &mut perp_market_map.get_ref_mut(&market_index)?;
 
     if perp_market.amm.curve_update_intensity > 0 {
@@ -239,24 +253,44 @@ pub fn settle_pnl(
     .safe_div(5)?;
 
     // add a buffer from fee pool for pnl pool balance
-    let pnl_tokens_available: i128 = pnl_pool_token_amount
-        .safe_add(fraction_of_fee_pool_token_amount)?
-        .cast()?;
+    let pnl_tokens_available: i128 = perp_market.get_quote_amount(
+        pnl_pool_token_amount
+            .safe_add(fraction_of_fee_pool_token_amount)?
+            .cast()?,
+        spot_market,
+    )?;
 
-    let net_user_pnl = calculate_net_user_pnl(&perp_market.amm, oracle_price)?;
+    let net_user_pnl = calculate_net_user_pnl(&perp_market.amm, valuation_price)?;
     let max_pnl_pool_excess = if net_user_pnl < pnl_tokens_available {
         pnl_tokens_available.safe_sub(net_user_pnl.max(0))?
     } else {
         0
     };
 
-    let user_unsettled_pnl: i128 =
-        user.perp_positions[position_index].get_claimable_pnl(oracle_price, max_pnl_pool_excess)?;
+    let mut user_unsettled_pnl: i128 = user.perp_positions[position_index]
+        .get_claimable_pnl(valuation_price, max_pnl_pool_excess)?;
+
+    let mut isolated_balance = if is_isolated_position {
+        Some(user.get_isolated_perp_position_balance(market_index)?)
+    } else {
+        None
+    };
+
+    if let Some(isolated_balance) = &isolated_balance {
+        // negative pnl can only be settled up to the isolated collateral
+        user_unsettled_pnl = user_unsettled_pnl.max(-perp_market.get_quote_amount(
+            isolated_balance.get_token_amount(spot_market)?.cast()?,
+            spot_market,
+        )?);
+    }
 
     let pnl_to_settle_with_user = update_pool_balances(
         perp_market,
         spot_market,
-        user.get_quote_spot_position(),
+        match &isolated_balance {
+            Some(isolated_balance) => isolated_balance,
+            None => user.force_get_spot_position_mut(quote_spot_market_index)?,
+        },
         user_unsettled_pnl,
         now,
     )?;
@@ -285,18 +319,28 @@ pub fn settle_pnl(
         return mode.result(ErrorCode::UserMustSettleTheirOwnPositiveUnsettledPNL, &msg);
     }
 
+    let token_amount_to_settle_with_user =
+        perp_market.get_quote_token_amount(pnl_to_settle_with_user, spot_market)?;
+
     update_spot_balances(
-        pnl_to_settle_with_user.unsigned_abs(),
-        if pnl_to_settle_with_user > 0 {
+        token_amount_to_settle_with_user.unsigned_abs(),
+        if token_amount_to_settle_with_user > 0 {
             &SpotBalanceType::Deposit
         } else {
             &SpotBalanceType::Borrow
         },
         spot_market,
-        user.get_quote_spot_position_mut(),
+        match &mut isolated_balance {
+            Some(isolated_balance) => isolated_balance,
+            None => user.force_get_spot_position_mut(quote_spot_market_index)?,
+        },
         false,
     )?;
 
+    if let Some(isolated_balance) = &isolated_balance {
+        user.update_isolated_perp_position_balance(market_index, isolated_balance)?;
+    }
+
     update_quote_asset_amount(
         &mut user.perp_positions[position_index],
         perp_market,
@@ -322,7 +366,7 @@ pub fn settle_pnl(
         base_asset_amount,
         quote_asset_amount_after,
         quote_entry_amount,
-        settle_price: oracle_price,
+        settle_price: valuation_price,
         explanation: SettlePnlExplanation::None,
     });
 
@@ -341,8 +385,18 @@ pub fn settle_expired_position(
 ) -> DriftResult {
     validate!(!user.is_bankrupt(), ErrorCode::UserBankrupt)?;
 
+    // isolated perp position settles against its own collateral, so the cross margin check doesnt apply
+    let is_isolated_position = user.is_isolated_perp_market(perp_market_index);
+
     // cannot settle pnl this way on a user who is in liquidation territory
-    if !(meets_maintenance_margin_requirement(user, perp_market_map, spot_market_map, oracle_map)?)
+    if !is_isolated_position
+        && !(meets_maintenance_margin_requirement(
+            user,
+            perp_market_map,
+            spot_market_map,
+            oracle_map,
+            MarketIdentifier::perp(perp_market_index),
+        )?)
     {
         return Err(ErrorCode::InsufficientCollateralForSettlingPNL);
     }
@@ -351,9 +405,18 @@ pub fn settle_expired_position(
     let now = clock.unix_timestamp;
     let slot = clock.slot;
 
+    let quote_spot_market_index = perp_market_map
+        .get_ref(&perp_market_index)?
+        .quote_spot_market_index;
     {
-        let quote_spot_market = &mut spot_market_map.get_quote_spot_market_mut()?;
-        update_spot_market_cumulative_interest(quote_spot_market, None, now)?;
+        let quote_spot_market = &mut spot_market_map.get_ref_mut(&quote_spot_market_index)?;
+        update_quote_spot_market_for_perp_market(
+            &perp_market_map.get_ref(&perp_market_index)?,
+            quote_spot_market,
+            oracle_map,
+            now,
+            Some(DriftAction::SettlePnl),
+        )?;
     }
 
     settle_funding_payment(
@@ -386,7 +449,7 @@ pub fn settle_expired_position(
         }
     };
 
-    let quote_spot_market = &mut spot_market_map.get_quote_spot_market_mut()?;
+    let quote_spot_market = &mut spot_market_map.get_ref_mut(&quote_spot_market_index)?;
     let perp_market = &mut perp_market_map.get_ref_mut(&perp_market_index)?;
     validate!(
         perp_market.status == MarketStatus::Settlement,
@@ -450,8 +513,38 @@ pub fn settle_expired_position(
 
     let pnl = user.perp_positions[position_index].quote_asset_amount;
 
-    let pnl_to_settle_with_user =
-        update_pnl_pool_and_user_balance(perp_market, quote_spot_market, user, pnl.cast()?)?;
+    let pnl_to_settle_with_user = if is_isolated_position {
+        let mut isolated_balance = user.get_isolated_perp_position_balance(perp_market_index)?;
+
+        // negative pnl can only be settled up to the isolated collateral, the rest is left for
+        // the isolated bankruptcy to resolve
+        let pnl_to_settle_with_user = pnl.cast::<i128>()?.max(
+            -perp_market.get_quote_amount(
+                isolated_balance
+                    .get_token_amount(quote_spot_market)?
+                    .cast()?,
+                quote_spot_market,
+            )?,
+        );
+
+        let pnl_to_settle_with_user = update_pnl_pool_and_user_balance(
+            perp_market,
+            quote_spot_market,
+            &mut isolated_balance,
+            pnl_to_settle_with_user,
+        )?;
+
+        user.update_isolated_perp_position_balance(perp_market_index, &isolated_balance)?;
+
+        pnl_to_settle_with_user
+    } else {
+        update_pnl_pool_and_user_balance(
+            perp_market,
+            quote_spot_market,
+            user.force_get_spot_position_mut(quote_spot_market_index)?,
+            pnl.cast()?,
+        )?
+    };
 
     update_quote_asset_amount(
         &mut user.perp_positions[position_index],
@@ -480,8 +573,11 @@ pub fn settle_expired_position(
         explanation: SettlePnlExplanation::ExpiredPosition,
     });
 
+    // an isolated position whose loss exceeded its collateral keeps the shortfall for resolve_perp_bankruptcy
     validate!(
-        user.perp_positions[position_index].is_available(),
+        user.perp_positions[position_index].is_available()
+            || (is_isolated_position
+                && is_isolated_perp_position_bankrupt(user, perp_market_index)),
         ErrorCode::UnableToSettleExpiredUserPosition,
         "Issue occurred in expired settlement"
     )?;
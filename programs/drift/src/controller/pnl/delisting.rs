@@ -24,6 +24,7 @@ pub mod delisting_test {
     use crate::create_account_info;
     use crate::create_anchor_account_info;
     use crate::math::amm::calculate_net_user_pnl;
+    use crate::math::bankruptcy::is_isolated_perp_position_bankrupt;
     use crate::math::constants::{
         AMM_RESERVE_PRECISION, BASE_PRECISION_I64, BASE_PRECISION_U64, LIQUIDATION_PCT_PRECISION,
         PEG_PRECISION, PERCENTAGE_PRECISION, PRICE_PRECISION, PRICE_PRECISION_I64,
@@ -827,6 +828,241 @@ pub mod delisting_test {
         assert_eq!(taker.perp_positions[0].quote_break_even_amount, 0);
     }
 
+    #[test]
+    fn delist_market_with_isolated_long_positions() {
+        let slot = 0_u64;
+        let clock = Clock {
+            slot: 6893025720,
+            epoch_start_timestamp: 1662065595 - 1000,
+            epoch: 2424,
+            leader_schedule_epoch: 1662065595 - 1,
+            unix_timestamp: 1662065595,
+        };
+
+        let mut oracle_price = get_pyth_price(100, 6);
+        let oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            oracle_price,
+            &oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                base_asset_amount_with_amm: AMM_RESERVE_PRECISION as i128,
+                base_asset_amount_long: AMM_RESERVE_PRECISION as i128,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                max_slippage_ratio: 50,
+                max_fill_reserve_fraction: 100,
+                order_step_size: 10000000,
+                oracle: oracle_price_key,
+                amm_jit_intensity: 100,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: (99 * PRICE_PRECISION) as i64,
+                    ..HistoricalOracleData::default()
+                },
+                quote_asset_amount: -(QUOTE_PRECISION_I128 * 70),
+                total_fee_minus_distributions: 0,
+                ..AMM::default()
+            },
+            number_of_users_with_base: 2,
+            number_of_users: 2,
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            pnl_pool: PoolBalance {
+                scaled_balance: (1000 * SPOT_BALANCE_PRECISION) as u128,
+                market_index: QUOTE_SPOT_MARKET_INDEX,
+                ..PoolBalance::default()
+            },
+            expiry_ts: clock.unix_timestamp - 10, // past expiry time
+
+            ..PerpMarket::default_test()
+        };
+        market.amm.max_base_asset_reserve = u128::MAX;
+        market.amm.min_base_asset_reserve = 0;
+
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_liability_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
+            borrow_balance: 100 * SPOT_BALANCE_PRECISION,
+            historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(spot_market, SpotMarket, spot_market_account_info);
+        let spot_market_map = SpotMarketMap::load_one(&spot_market_account_info, true).unwrap();
+
+        // in profit, settles into the isolated collateral
+        let mut winner = User {
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64 / 2,
+                quote_asset_amount: -(QUOTE_PRECISION_I64 * 10),
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        winner.spot_positions[1] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 20 * SPOT_BALANCE_PRECISION_U64,
+            isolated_perp_market_index: 0,
+            is_isolated_perp_collateral: true,
+            ..SpotPosition::default()
+        };
+
+        // loss is larger than the isolated collateral
+        let mut loser = User {
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64 / 2,
+                quote_asset_amount: -(QUOTE_PRECISION_I64 * 60),
+                ..PerpPosition::default()
+            }),
+            spot_positions: get_spot_positions(SpotPosition {
+                market_index: 0,
+                balance_type: SpotBalanceType::Deposit,
+                scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+                ..SpotPosition::default()
+            }),
+            ..User::default()
+        };
+        loser.spot_positions[1] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 5 * SPOT_BALANCE_PRECISION_U64,
+            isolated_perp_market_index: 0,
+            is_isolated_perp_collateral: true,
+            ..SpotPosition::default()
+        };
+
+        let (winner_key, loser_key, _filler_key) = get_user_keys();
+
+        let state = State {
+            oracle_guard_rails: OracleGuardRails {
+                validity: ValidityGuardRails {
+                    slots_before_stale_for_amm: 10,     // 5s
+                    slots_before_stale_for_margin: 120, // 60s
+                    confidence_interval_max_size: 1000,
+                    too_volatile_ratio: 5,
+                },
+                ..OracleGuardRails::default()
+            },
+            ..State::default()
+        };
+
+        // put in settlement mode
+        settle_expired_market(
+            0,
+            &market_map,
+            &mut oracle_map,
+            &spot_market_map,
+            &state,
+            &clock,
+        )
+        .unwrap();
+
+        let market = market_map.get_ref_mut(&0).unwrap();
+        assert_eq!(market.status, MarketStatus::Settlement);
+        let pnl_pool_before = market.pnl_pool.scaled_balance;
+        drop(market);
+
+        settle_expired_position(
+            0,
+            &mut winner,
+            &winner_key,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            &state,
+        )
+        .unwrap();
+
+        let isolated_pnl = winner
+            .get_isolated_perp_position_balance(0)
+            .unwrap()
+            .scaled_balance as u128
+            - 20 * SPOT_BALANCE_PRECISION;
+        assert!(isolated_pnl > 0);
+        assert_eq!(
+            winner.spot_positions[0].scaled_balance,
+            100 * SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(winner.perp_positions[0].is_available(), true);
+
+        let market = market_map.get_ref_mut(&0).unwrap();
+        assert_eq!(
+            market.pnl_pool.scaled_balance,
+            pnl_pool_before - isolated_pnl
+        );
+        let pnl_pool_before = market.pnl_pool.scaled_balance;
+        drop(market);
+
+        settle_expired_position(
+            0,
+            &mut loser,
+            &loser_key,
+            &market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            &clock,
+            &state,
+        )
+        .unwrap();
+
+        // only the isolated collateral is taken, the rest is left for resolve_perp_bankruptcy
+        assert_eq!(
+            loser
+                .get_isolated_perp_position_balance(0)
+                .unwrap()
+                .scaled_balance,
+            0
+        );
+        assert_eq!(
+            loser.spot_positions[0].scaled_balance,
+            100 * SPOT_BALANCE_PRECISION_U64
+        );
+        assert_eq!(loser.perp_positions[0].base_asset_amount, 0);
+        assert!(loser.perp_positions[0].quote_asset_amount < 0);
+        assert_eq!(is_isolated_perp_position_bankrupt(&loser, 0), true);
+
+        let market = market_map.get_ref_mut(&0).unwrap();
+        assert_eq!(
+            market.pnl_pool.scaled_balance,
+            pnl_pool_before + 5 * SPOT_BALANCE_PRECISION
+        );
+        drop(market);
+    }
+
     #[test]
     fn delist_market_with_1000_balance_long_at_target_price_w_positive_quote_long() {
         let slot = 0_u64;
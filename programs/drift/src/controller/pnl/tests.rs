@@ -15,6 +15,7 @@ use crate::math::constants::{
 use crate::math::margin::{
     meets_maintenance_margin_requirement, meets_settle_pnl_maintenance_margin_requirement,
 };
+use crate::state::margin_calculation::MarketIdentifier;
 use crate::state::oracle::{HistoricalOracleData, OracleSource};
 use crate::state::oracle_map::OracleMap;
 use crate::state::perp_market::{MarketStatus, PerpMarket, PoolBalance, AMM};
@@ -399,9 +400,14 @@ pub fn user_does_not_meet_strict_maintenance_requirement() {
 
     assert_eq!(result, Err(ErrorCode::InsufficientCollateralForSettlingPNL));
 
-    let meets_maintenance =
-        meets_maintenance_margin_requirement(&user, &market_map, &spot_market_map, &mut oracle_map)
-            .unwrap();
+    let meets_maintenance = meets_maintenance_margin_requirement(
+        &user,
+        &market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        MarketIdentifier::perp(0),
+    )
+    .unwrap();
 
     assert_eq!(meets_maintenance, true);
 
@@ -825,6 +831,326 @@ pub fn user_unsettled_positive_pnl_less_than_pool() {
     assert_eq!(expected_market, *market_map.get_ref(&0).unwrap());
 }
 
+#[test]
+pub fn user_unsettled_positive_pnl_non_usdc_quote_market() {
+    let clock = Clock {
+        slot: 0,
+        epoch_start_timestamp: 0,
+        epoch: 0,
+        leader_schedule_epoch: 0,
+        unix_timestamp: 0,
+    };
+    let state = State {
+        oracle_guard_rails: OracleGuardRails {
+            validity: ValidityGuardRails {
+                slots_before_stale_for_amm: 10,     // 5s
+                slots_before_stale_for_margin: 120, // 60s
+                confidence_interval_max_size: 1000,
+                too_volatile_ratio: 5,
+            },
+            ..OracleGuardRails::default()
+        },
+        ..State::default()
+    };
+    let mut oracle_price = get_pyth_price(100, 6);
+    let oracle_price_key =
+        Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+    let pyth_program = crate::ids::pyth_program::id();
+    create_account_info!(
+        oracle_price,
+        &oracle_price_key,
+        &pyth_program,
+        oracle_account_info
+    );
+    let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+    let mut market = PerpMarket {
+        amm: AMM {
+            base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+            quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+            bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+            bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+            ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+            ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+            sqrt_k: 100 * AMM_RESERVE_PRECISION,
+            peg_multiplier: 100 * PEG_PRECISION,
+            max_slippage_ratio: 50,
+            max_fill_reserve_fraction: 100,
+            order_step_size: 10000000,
+            quote_asset_amount: -150 * QUOTE_PRECISION_I128,
+            base_asset_amount_with_amm: BASE_PRECISION_I128,
+            base_asset_amount_long: BASE_PRECISION_I128,
+            oracle: oracle_price_key,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price: oracle_price.agg.price,
+                last_oracle_price_twap_5min: oracle_price.agg.price,
+                last_oracle_price_twap: oracle_price.agg.price,
+                ..HistoricalOracleData::default()
+            },
+            ..AMM::default()
+        },
+        margin_ratio_initial: 1000,
+        margin_ratio_maintenance: 500,
+        number_of_users_with_base: 1,
+        number_of_users: 1,
+        status: MarketStatus::Active,
+        liquidator_fee: LIQUIDATION_FEE_PRECISION / 100,
+        quote_spot_market_index: 1,
+        pnl_pool: PoolBalance {
+            scaled_balance: (50 * SPOT_BALANCE_PRECISION) as u128,
+            market_index: 1,
+            ..PoolBalance::default()
+        },
+        unrealized_pnl_maintenance_asset_weight: SPOT_WEIGHT_PRECISION.cast().unwrap(),
+        ..PerpMarket::default()
+    };
+    create_anchor_account_info!(market, PerpMarket, market_account_info);
+    let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+    let mut usdc_spot_market = SpotMarket {
+        market_index: 0,
+        oracle_source: OracleSource::QuoteAsset,
+        cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+        decimals: 6,
+        initial_asset_weight: SPOT_WEIGHT_PRECISION,
+        maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+        deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+        historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+        ..SpotMarket::default()
+    };
+    create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+    let mut usdt_spot_market = SpotMarket {
+        market_index: 1,
+        oracle_source: OracleSource::QuoteAsset,
+        cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+        decimals: 6,
+        initial_asset_weight: SPOT_WEIGHT_PRECISION,
+        maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+        deposit_balance: 50 * SPOT_BALANCE_PRECISION,
+        historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+        ..SpotMarket::default()
+    };
+    create_anchor_account_info!(usdt_spot_market, SpotMarket, usdt_spot_market_account_info);
+    let spot_market_map = SpotMarketMap::load_multiple(
+        vec![
+            &usdc_spot_market_account_info,
+            &usdt_spot_market_account_info,
+        ],
+        true,
+    )
+    .unwrap();
+
+    let mut user = User {
+        perp_positions: get_positions(PerpPosition {
+            market_index: 0,
+            quote_asset_amount: 25 * QUOTE_PRECISION_I64,
+            ..PerpPosition::default()
+        }),
+        spot_positions: get_spot_positions(SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        }),
+        ..User::default()
+    };
+
+    let user_key = Pubkey::default();
+    let authority = Pubkey::default();
+
+    settle_pnl(
+        0,
+        &mut user,
+        &authority,
+        &user_key,
+        &market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        &clock,
+        &state,
+        None,
+        SettlePnlMode::MustSettle,
+    )
+    .unwrap();
+
+    // pnl is paid out in the perp market's quote token, usdc deposit is untouched
+    assert_eq!(user.perp_positions[0].quote_asset_amount, 0);
+    assert_eq!(
+        user.get_spot_position(0).unwrap().scaled_balance,
+        100 * SPOT_BALANCE_PRECISION_U64
+    );
+    let usdt_position = user.get_spot_position(1).unwrap();
+    assert_eq!(usdt_position.balance_type, SpotBalanceType::Deposit);
+    assert_eq!(
+        usdt_position.scaled_balance,
+        25 * SPOT_BALANCE_PRECISION_U64
+    );
+
+    assert_eq!(
+        market_map.get_ref(&0).unwrap().pnl_pool.scaled_balance,
+        25 * SPOT_BALANCE_PRECISION
+    );
+}
+
+#[test]
+pub fn user_unsettled_positive_pnl_sol_quote_market() {
+    let clock = Clock {
+        slot: 0,
+        epoch_start_timestamp: 0,
+        epoch: 0,
+        leader_schedule_epoch: 0,
+        unix_timestamp: 0,
+    };
+    let state = State {
+        oracle_guard_rails: OracleGuardRails {
+            validity: ValidityGuardRails {
+                slots_before_stale_for_amm: 10,     // 5s
+                slots_before_stale_for_margin: 120, // 60s
+                confidence_interval_max_size: 1000,
+                too_volatile_ratio: 5,
+            },
+            ..OracleGuardRails::default()
+        },
+        ..State::default()
+    };
+    let mut oracle_price = get_pyth_price(100, 6);
+    let oracle_price_key =
+        Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+    let pyth_program = crate::ids::pyth_program::id();
+    create_account_info!(
+        oracle_price,
+        &oracle_price_key,
+        &pyth_program,
+        oracle_account_info
+    );
+    let mut oracle_map = OracleMap::load_one(&oracle_account_info, clock.slot, None).unwrap();
+
+    let mut market = PerpMarket {
+        amm: AMM {
+            base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+            quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+            bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+            bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+            ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+            ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+            sqrt_k: 100 * AMM_RESERVE_PRECISION,
+            peg_multiplier: 100 * PEG_PRECISION,
+            max_slippage_ratio: 50,
+            max_fill_reserve_fraction: 100,
+            order_step_size: 10000000,
+            quote_asset_amount: -150 * QUOTE_PRECISION_I128,
+            base_asset_amount_with_amm: BASE_PRECISION_I128,
+            base_asset_amount_long: BASE_PRECISION_I128,
+            oracle: oracle_price_key,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price: oracle_price.agg.price,
+                last_oracle_price_twap_5min: oracle_price.agg.price,
+                last_oracle_price_twap: oracle_price.agg.price,
+                ..HistoricalOracleData::default()
+            },
+            ..AMM::default()
+        },
+        margin_ratio_initial: 1000,
+        margin_ratio_maintenance: 500,
+        number_of_users_with_base: 1,
+        number_of_users: 1,
+        status: MarketStatus::Active,
+        liquidator_fee: LIQUIDATION_FEE_PRECISION / 100,
+        quote_spot_market_index: 1,
+        pnl_pool: PoolBalance {
+            scaled_balance: SPOT_BALANCE_PRECISION,
+            market_index: 1,
+            ..PoolBalance::default()
+        },
+        unrealized_pnl_maintenance_asset_weight: SPOT_WEIGHT_PRECISION.cast().unwrap(),
+        ..PerpMarket::default()
+    };
+    create_anchor_account_info!(market, PerpMarket, market_account_info);
+    let market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+    let mut usdc_spot_market = SpotMarket {
+        market_index: 0,
+        oracle_source: OracleSource::QuoteAsset,
+        cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+        decimals: 6,
+        initial_asset_weight: SPOT_WEIGHT_PRECISION,
+        maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+        deposit_balance: 100 * SPOT_BALANCE_PRECISION,
+        historical_oracle_data: HistoricalOracleData::default_price(QUOTE_PRECISION_I64),
+        ..SpotMarket::default()
+    };
+    create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+    let mut sol_spot_market = SpotMarket {
+        market_index: 1,
+        oracle_source: OracleSource::Pyth,
+        oracle: oracle_price_key,
+        cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+        decimals: 9,
+        initial_asset_weight: 8 * SPOT_WEIGHT_PRECISION / 10,
+        maintenance_asset_weight: 9 * SPOT_WEIGHT_PRECISION / 10,
+        deposit_balance: SPOT_BALANCE_PRECISION,
+        historical_oracle_data: HistoricalOracleData::default_price(oracle_price.agg.price),
+        ..SpotMarket::default()
+    };
+    create_anchor_account_info!(sol_spot_market, SpotMarket, sol_spot_market_account_info);
+    let spot_market_map = SpotMarketMap::load_multiple(
+        vec![
+            &usdc_spot_market_account_info,
+            &sol_spot_market_account_info,
+        ],
+        true,
+    )
+    .unwrap();
+
+    let mut user = User {
+        perp_positions: get_positions(PerpPosition {
+            market_index: 0,
+            quote_asset_amount: 25 * QUOTE_PRECISION_I64,
+            ..PerpPosition::default()
+        }),
+        spot_positions: get_spot_positions(SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        }),
+        ..User::default()
+    };
+
+    let user_key = Pubkey::default();
+    let authority = Pubkey::default();
+
+    settle_pnl(
+        0,
+        &mut user,
+        &authority,
+        &user_key,
+        &market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        &clock,
+        &state,
+        None,
+        SettlePnlMode::MustSettle,
+    )
+    .unwrap();
+
+    // $25 of pnl is paid out as .25 sol at the $100 sol oracle price
+    assert_eq!(user.perp_positions[0].quote_asset_amount, 0);
+    assert_eq!(
+        user.get_spot_position(0).unwrap().scaled_balance,
+        100 * SPOT_BALANCE_PRECISION_U64
+    );
+    let sol_position = user.get_spot_position(1).unwrap();
+    assert_eq!(sol_position.balance_type, SpotBalanceType::Deposit);
+    assert_eq!(sol_position.scaled_balance, SPOT_BALANCE_PRECISION_U64 / 4);
+
+    assert_eq!(
+        market_map.get_ref(&0).unwrap().pnl_pool.scaled_balance,
+        3 * SPOT_BALANCE_PRECISION / 4
+    );
+}
+
 #[test]
 pub fn market_fee_pool_receives_portion() {
     let clock = Clock {
@@ -668,3 +668,15 @@ pub fn decrease_open_bids_and_asks(
 
     Ok(())
 }
+
+pub fn resize_open_bids_and_asks(
+    position: &mut PerpPosition,
+    direction: &PositionDirection,
+    base_asset_amount_delta: i64,
+) -> DriftResult {
+    if base_asset_amount_delta >= 0 {
+        increase_open_bids_and_asks(position, direction, base_asset_amount_delta.unsigned_abs())
+    } else {
+        decrease_open_bids_and_asks(position, direction, base_asset_amount_delta.unsigned_abs())
+    }
+}
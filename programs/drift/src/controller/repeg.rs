@@ -5,19 +5,20 @@ use anchor_lang::prelude::*;
 use solana_program::msg;
 
 use crate::controller::amm::update_spreads;
-use crate::controller::spot_balance::update_spot_balances;
+use crate::controller::spot_balance::{
+    update_quote_spot_market_for_perp_market, update_spot_balances,
+};
 use crate::error::ErrorCode;
 use crate::error::*;
 use crate::load_mut;
 use crate::math::amm;
 use crate::math::bn;
 use crate::math::casting::Cast;
-use crate::math::constants::{
-    K_BPS_UPDATE_SCALE, MAX_SQRT_K, QUOTE_PRECISION, QUOTE_SPOT_MARKET_INDEX,
-};
+use crate::math::constants::{K_BPS_UPDATE_SCALE, MAX_SQRT_K, QUOTE_PRECISION};
 use crate::math::cp_curve;
 use crate::math::cp_curve::get_update_k_result;
 use crate::math::cp_curve::UpdateKResult;
+use crate::math::options::calculate_option_intrinsic_value;
 use crate::math::oracle;
 use crate::math::oracle::{is_oracle_valid_for_action, oracle_validity, DriftAction};
 use crate::math::repeg;
@@ -301,7 +302,7 @@ pub fn apply_cost_to_market(
 pub fn settle_expired_market(
     market_index: u16,
     market_map: &PerpMarketMap,
-    _oracle_map: &mut OracleMap,
+    oracle_map: &mut OracleMap,
     spot_market_map: &SpotMarketMap,
     _state: &State,
     clock: &Clock,
@@ -329,7 +330,15 @@ pub fn settle_expired_market(
         "Outstanding LP in market"
     )?;
 
-    let spot_market = &mut spot_market_map.get_ref_mut(&QUOTE_SPOT_MARKET_INDEX)?;
+    let spot_market = &mut spot_market_map.get_ref_mut(&market.quote_spot_market_index)?;
+    update_quote_spot_market_for_perp_market(
+        market,
+        spot_market,
+        oracle_map,
+        now,
+        Some(DriftAction::SettlePnl),
+    )?;
+
     let fee_reserved_for_protocol = repeg::get_total_fee_lower_bound(market)?
         .safe_add(market.amm.total_liquidation_fee)?
         .safe_sub(market.amm.total_fee_withdrawn)?
@@ -340,19 +349,25 @@ pub fn settle_expired_market(
         .safe_sub(fee_reserved_for_protocol)?
         .max(0);
 
-    let available_fee_pool = get_token_amount(
-        market.amm.fee_pool.scaled_balance,
-        spot_market,
-        &SpotBalanceType::Deposit,
-    )?
-    .cast::<i128>()?
-    .safe_sub(fee_reserved_for_protocol)?
-    .max(0);
+    let available_fee_pool = market
+        .get_quote_amount(
+            get_token_amount(
+                market.amm.fee_pool.scaled_balance,
+                spot_market,
+                &SpotBalanceType::Deposit,
+            )?
+            .cast()?,
+            spot_market,
+        )?
+        .safe_sub(fee_reserved_for_protocol)?
+        .max(0);
 
-    let fee_pool_transfer = budget.min(available_fee_pool);
+    let fee_pool_transfer = market
+        .get_quote_token_amount(budget.min(available_fee_pool), spot_market)?
+        .unsigned_abs();
 
     update_spot_balances(
-        fee_pool_transfer.unsigned_abs(),
+        fee_pool_transfer,
         &SpotBalanceType::Borrow,
         spot_market,
         &mut market.amm.fee_pool,
@@ -360,14 +375,15 @@ pub fn settle_expired_market(
     )?;
 
     update_spot_balances(
-        fee_pool_transfer.unsigned_abs(),
+        fee_pool_transfer,
         &SpotBalanceType::Deposit,
         spot_market,
         &mut market.pnl_pool,
         false,
     )?;
 
-    if budget > 0 {
+    // orderbook only markets have no amm to spend the budget on
+    if budget > 0 && !market.is_orderbook_only() {
         let (k_scale_numerator, k_scale_denominator) = cp_curve::calculate_budgeted_k_scale(
             market,
             budget.cast()?,
@@ -397,28 +413,49 @@ pub fn settle_expired_market(
         }
     }
 
-    let pnl_pool_amount = get_token_amount(
-        market.pnl_pool.scaled_balance,
-        spot_market,
-        &SpotBalanceType::Deposit,
-    )?;
+    let pnl_pool_amount = market
+        .get_quote_amount(
+            get_token_amount(
+                market.pnl_pool.scaled_balance,
+                spot_market,
+                &SpotBalanceType::Deposit,
+            )?
+            .cast()?,
+            spot_market,
+        )?
+        .cast::<u128>()?;
 
     validate!(
-        10_u128.pow(spot_market.decimals) == QUOTE_PRECISION,
+        market.is_quote_converted() || 10_u128.pow(spot_market.decimals) == QUOTE_PRECISION,
         ErrorCode::UnsupportedSpotMarket,
         "Only support bank.decimals == QUOTE_PRECISION"
     )?;
 
-    let target_expiry_price = market.amm.historical_oracle_data.last_oracle_price_twap;
-    validate!(
-        target_expiry_price > 0,
-        ErrorCode::MarketSettlementTargetPriceInvalid,
-        "target_expiry_price <= 0 {}",
-        target_expiry_price
-    )?;
+    let expiry_price = if market.is_prediction_market() {
+        // prediction markets settle at exactly 0 or 1
+        market.prediction_market_outcome.get_expiry_price()?
+    } else {
+        let oracle_price_twap = market.amm.historical_oracle_data.last_oracle_price_twap;
+        validate!(
+            oracle_price_twap > 0,
+            ErrorCode::MarketSettlementTargetPriceInvalid,
+            "oracle_price_twap <= 0 {}",
+            oracle_price_twap
+        )?;
+
+        // options expire at their intrinsic value, which can be 0
+        let target_expiry_price = if market.is_option() {
+            calculate_option_intrinsic_value(
+                market.option_type,
+                market.strike_price,
+                oracle_price_twap,
+            )?
+        } else {
+            oracle_price_twap
+        };
 
-    let expiry_price =
-        amm::calculate_expiry_price(&market.amm, target_expiry_price, pnl_pool_amount)?;
+        amm::calculate_expiry_price(&market.amm, target_expiry_price, pnl_pool_amount)?
+    };
 
     market.expiry_price = expiry_price;
     market.status = MarketStatus::Settlement;
@@ -22,7 +22,9 @@ use crate::math::oracle::{is_oracle_valid_for_action, DriftAction};
 use crate::math::safe_math::SafeMath;
 use crate::state::events::SpotInterestRecord;
 use crate::state::oracle::OraclePriceData;
+use crate::state::oracle_map::OracleMap;
 use crate::state::paused_operations::SpotOperation;
+use crate::state::perp_market::PerpMarket;
 use crate::state::spot_market::{SpotBalance, SpotBalanceType, SpotMarket};
 use crate::state::user::MarketType;
 use crate::validate;
@@ -418,6 +420,31 @@ pub fn update_spot_market_and_check_validity(
     Ok(())
 }
 
+/// Accrues interest on a perp market's quote spot market. Perp markets with converted quote amounts also
+/// refresh the quote spot market oracle price their pnl, fees and insurance are converted at
+pub fn update_quote_spot_market_for_perp_market(
+    perp_market: &PerpMarket,
+    quote_spot_market: &mut SpotMarket,
+    oracle_map: &mut OracleMap,
+    now: i64,
+    action: Option<DriftAction>,
+) -> DriftResult {
+    if !perp_market.is_quote_converted() {
+        return update_spot_market_cumulative_interest(quote_spot_market, None, now);
+    }
+
+    let oracle_price_data = *oracle_map.get_price_data(&quote_spot_market.oracle)?;
+    let validity_guard_rails = oracle_map.oracle_guard_rails.validity;
+
+    update_spot_market_and_check_validity(
+        quote_spot_market,
+        &oracle_price_data,
+        &validity_guard_rails,
+        now,
+        action,
+    )
+}
+
 fn increase_spot_balance(
     delta: u128,
     spot_market: &mut SpotMarket,
@@ -62,6 +62,26 @@ pub fn decrease_spot_open_bids_and_asks(
     Ok(())
 }
 
+pub fn resize_spot_open_bids_and_asks(
+    spot_position: &mut SpotPosition,
+    direction: &PositionDirection,
+    base_asset_amount_delta: i64,
+) -> DriftResult {
+    if base_asset_amount_delta >= 0 {
+        increase_spot_open_bids_and_asks(
+            spot_position,
+            direction,
+            base_asset_amount_delta.unsigned_abs(),
+        )
+    } else {
+        decrease_spot_open_bids_and_asks(
+            spot_position,
+            direction,
+            base_asset_amount_delta.unsigned_abs(),
+        )
+    }
+}
+
 pub fn update_spot_balances_and_cumulative_deposits(
     token_amount: u128,
     update_direction: &SpotBalanceType,
@@ -527,6 +527,30 @@ pub enum ErrorCode {
     NoUnsettledPnl,
     #[msg("PnlPoolCantSettleUser")]
     PnlPoolCantSettleUser,
+    #[msg("InvalidTwapOrder")]
+    InvalidTwapOrder,
+    #[msg("FillOrKillOrderNotFilled")]
+    FillOrKillOrderNotFilled,
+    #[msg("CancelOrdersDeadlineNotReached")]
+    CancelOrdersDeadlineNotReached,
+    #[msg("InvalidMarketMakerProtection")]
+    InvalidMarketMakerProtection,
+    #[msg("SigVerificationFailed")]
+    SigVerificationFailed,
+    #[msg("BatchAuctionNotEnabled")]
+    BatchAuctionNotEnabled,
+    #[msg("InvalidRfq")]
+    InvalidRfq,
+    #[msg("RfqExpired")]
+    RfqExpired,
+    #[msg("InvalidComboOrder")]
+    InvalidComboOrder,
+    #[msg("ComboOrderNotFilled")]
+    ComboOrderNotFilled,
+    #[msg("InvalidIsolatedPerpPosition")]
+    InvalidIsolatedPerpPosition,
+    #[msg("MarketMakerProtectionTriggered")]
+    MarketMakerProtectionTriggered,
 }
 
 #[macro_export]
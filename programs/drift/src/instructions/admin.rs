@@ -40,14 +40,15 @@ use crate::state::oracle::{
 };
 use crate::state::paused_operations::{InsuranceFundOperation, PerpOperation, SpotOperation};
 use crate::state::perp_market::{
-    ContractTier, ContractType, InsuranceClaim, MarketStatus, PerpMarket, PoolBalance, AMM,
+    ContractTier, ContractType, InsuranceClaim, MarketStatus, MatchingPolicy, OptionType,
+    PerpMarket, PoolBalance, PredictionMarketOutcome, AMM,
 };
 use crate::state::spot_market::{
     AssetTier, InsuranceFund, SpotBalanceType, SpotFulfillmentConfigStatus, SpotMarket,
 };
 use crate::state::state::{ExchangeStatus, FeeStructure, OracleGuardRails, State};
 use crate::state::traits::Size;
-use crate::state::user::UserStats;
+use crate::state::user::{AuctionCurve, UserStats};
 use crate::validate;
 use crate::validation::fee_structure::validate_fee_structure;
 use crate::validation::margin::{validate_margin, validate_margin_weights};
@@ -679,7 +680,17 @@ pub fn handle_initialize_perp_market(
         paused_operations: 0,
         quote_spot_market_index: QUOTE_SPOT_MARKET_INDEX,
         fee_adjustment: 0,
-        padding: [0; 46],
+        default_auction_curve: AuctionCurve::Linear,
+        matching_policy: MatchingPolicy::PriceTime,
+        top_of_book_allocation: 0,
+        batch_auction_duration: 0,
+        option_type: OptionType::Call,
+        prediction_market_outcome: PredictionMarketOutcome::Unresolved,
+        last_fill_price: 0,
+        strike_price: 0,
+        last_fill_price_twap_5min: 0,
+        last_fill_price_ts: 0,
+        padding: [0; 8],
         amm: AMM {
             oracle: *ctx.accounts.oracle.key,
             oracle_source,
@@ -1233,7 +1244,7 @@ pub fn handle_settle_expired_market_pools_to_revenue_pool(
     controller::spot_balance::update_spot_market_cumulative_interest(spot_market, None, now)?;
 
     validate!(
-        spot_market.market_index == QUOTE_SPOT_MARKET_INDEX,
+        spot_market.market_index == perp_market.quote_spot_market_index,
         ErrorCode::DefaultError,
         "spot_market must be perp market's quote asset"
     )?;
@@ -1332,6 +1343,9 @@ pub fn handle_deposit_into_perp_market_fee_pool(
     amount: u64,
 ) -> Result<()> {
     let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+    let quote_spot_market = &mut load_mut!(ctx.accounts.quote_spot_market)?;
+
+    let quote_amount = perp_market.get_quote_amount(amount.cast()?, quote_spot_market)?;
 
     msg!(
         "perp_market.amm.total_fee_minus_distributions: {:?} -> {:?}",
@@ -1339,15 +1353,13 @@ pub fn handle_deposit_into_perp_market_fee_pool(
         perp_market
             .amm
             .total_fee_minus_distributions
-            .safe_add(amount.cast()?)?,
+            .safe_add(quote_amount)?,
     );
 
     perp_market.amm.total_fee_minus_distributions = perp_market
         .amm
         .total_fee_minus_distributions
-        .safe_add(amount.cast()?)?;
-
-    let quote_spot_market = &mut load_mut!(ctx.accounts.quote_spot_market)?;
+        .safe_add(quote_amount)?;
 
     controller::spot_balance::update_spot_balances(
         amount.cast::<u128>()?,
@@ -3053,6 +3065,207 @@ pub fn handle_update_perp_market_fee_adjustment(
     Ok(())
 }
 
+pub fn handle_update_perp_market_default_auction_curve(
+    ctx: Context<AdminUpdatePerpMarket>,
+    default_auction_curve: AuctionCurve,
+) -> Result<()> {
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+
+    msg!(
+        "perp_market.default_auction_curve: {:?} -> {:?}",
+        perp_market.default_auction_curve,
+        default_auction_curve
+    );
+
+    perp_market.default_auction_curve = default_auction_curve;
+    Ok(())
+}
+
+pub fn handle_update_perp_market_matching_policy(
+    ctx: Context<AdminUpdatePerpMarket>,
+    matching_policy: MatchingPolicy,
+    top_of_book_allocation: u8,
+) -> Result<()> {
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+
+    validate!(
+        top_of_book_allocation <= 100,
+        ErrorCode::DefaultError,
+        "top of book allocation {} greater than 100",
+        top_of_book_allocation
+    )?;
+
+    msg!(
+        "perp_market.matching_policy: {:?} -> {:?}",
+        perp_market.matching_policy,
+        matching_policy
+    );
+
+    msg!(
+        "perp_market.top_of_book_allocation: {:?} -> {:?}",
+        perp_market.top_of_book_allocation,
+        top_of_book_allocation
+    );
+
+    perp_market.matching_policy = matching_policy;
+    perp_market.top_of_book_allocation = top_of_book_allocation;
+    Ok(())
+}
+
+pub fn handle_update_perp_market_contract_type(
+    ctx: Context<AdminUpdatePerpMarket>,
+    contract_type: ContractType,
+) -> Result<()> {
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+
+    validate!(
+        contract_type != ContractType::Option,
+        ErrorCode::DefaultError,
+        "option markets must be set with update_perp_market_option_params"
+    )?;
+
+    validate!(
+        perp_market.status == MarketStatus::Initialized,
+        ErrorCode::DefaultError,
+        "contract type can only be changed before perp market is activated"
+    )?;
+
+    validate!(
+        perp_market.amm.fee_pool.scaled_balance == 0 && perp_market.pnl_pool.scaled_balance == 0,
+        ErrorCode::DefaultError,
+        "perp market fee pool and pnl pool must be empty"
+    )?;
+
+    if contract_type == ContractType::Prediction {
+        validate!(
+            perp_market.amm.oracle_source == OracleSource::Prelaunch,
+            ErrorCode::DefaultError,
+            "prediction markets must use a prelaunch oracle"
+        )?;
+    }
+
+    msg!(
+        "perp_market.contract_type: {:?} -> {:?}",
+        perp_market.contract_type,
+        contract_type
+    );
+
+    perp_market.contract_type = contract_type;
+
+    Ok(())
+}
+
+pub fn handle_update_perp_market_option_params(
+    ctx: Context<AdminUpdatePerpMarket>,
+    option_type: OptionType,
+    strike_price: u64,
+    expiry_ts: i64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+
+    validate!(
+        perp_market.status == MarketStatus::Initialized,
+        ErrorCode::DefaultError,
+        "option params can only be set before perp market is activated"
+    )?;
+
+    validate!(
+        perp_market.amm.fee_pool.scaled_balance == 0 && perp_market.pnl_pool.scaled_balance == 0,
+        ErrorCode::DefaultError,
+        "perp market fee pool and pnl pool must be empty"
+    )?;
+
+    validate!(
+        strike_price > 0,
+        ErrorCode::DefaultError,
+        "option strike price must be greater than 0"
+    )?;
+
+    validate!(
+        clock.unix_timestamp < expiry_ts,
+        ErrorCode::DefaultError,
+        "Market expiry ts must later than current clock timestamp"
+    )?;
+
+    msg!(
+        "perp_market.contract_type: {:?} -> {:?}",
+        perp_market.contract_type,
+        ContractType::Option
+    );
+    msg!(
+        "perp_market.option_type: {:?} -> {:?}",
+        perp_market.option_type,
+        option_type
+    );
+    msg!(
+        "perp_market.strike_price: {} -> {}",
+        perp_market.strike_price,
+        strike_price
+    );
+    msg!(
+        "perp_market.expiry_ts {} -> {}",
+        perp_market.expiry_ts,
+        expiry_ts
+    );
+
+    perp_market.contract_type = ContractType::Option;
+    perp_market.option_type = option_type;
+    perp_market.strike_price = strike_price;
+    perp_market.expiry_ts = expiry_ts;
+
+    Ok(())
+}
+
+/// Moves a perp market's pnl pool and fee pool to another quote spot market before activation.
+/// pnl, funding and fees stay usd amounts that are converted to the quote spot market's token at its
+/// oracle price when settled
+pub fn handle_update_perp_market_quote_spot_market(
+    ctx: Context<AdminUpdatePerpMarketQuoteSpotMarket>,
+) -> Result<()> {
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+    let quote_spot_market = load!(ctx.accounts.quote_spot_market)?;
+
+    validate!(
+        perp_market.status == MarketStatus::Initialized,
+        ErrorCode::DefaultError,
+        "quote spot market can only be changed before perp market is activated"
+    )?;
+
+    validate!(
+        perp_market.amm.fee_pool.scaled_balance == 0 && perp_market.pnl_pool.scaled_balance == 0,
+        ErrorCode::DefaultError,
+        "perp market fee pool and pnl pool must be empty"
+    )?;
+
+    perp_market.validate_quote_spot_market(&quote_spot_market)?;
+
+    msg!(
+        "perp_market.quote_spot_market_index: {:?} -> {:?}",
+        perp_market.quote_spot_market_index,
+        quote_spot_market.market_index
+    );
+
+    perp_market.quote_spot_market_index = quote_spot_market.market_index;
+    Ok(())
+}
+
+pub fn handle_update_perp_market_batch_auction_duration(
+    ctx: Context<AdminUpdatePerpMarket>,
+    batch_auction_duration: u8,
+) -> Result<()> {
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+
+    msg!(
+        "perp_market.batch_auction_duration: {:?} -> {:?}",
+        perp_market.batch_auction_duration,
+        batch_auction_duration
+    );
+
+    perp_market.batch_auction_duration = batch_auction_duration;
+    Ok(())
+}
+
 pub fn handle_update_perp_market_number_of_users(
     ctx: Context<AdminUpdatePerpMarket>,
     number_of_users: Option<u32>,
@@ -3274,6 +3487,9 @@ pub fn handle_initialize_prelaunch_oracle<'info>(
     if let Some(max_price) = params.max_price {
         oracle.max_price = max_price;
     }
+    if let Some(resolver) = params.resolver {
+        oracle.resolver = resolver;
+    }
 
     oracle.validate()?;
 
@@ -3313,6 +3529,13 @@ pub fn handle_update_prelaunch_oracle_params<'info>(
         msg!("max price: unchanged")
     }
 
+    if let Some(resolver) = params.resolver {
+        msg!("resolver: {:?} -> {:?}", oracle.resolver, resolver);
+        oracle.resolver = resolver;
+    } else {
+        msg!("resolver: unchanged")
+    }
+
     oracle.validate()?;
 
     Ok(())
@@ -3333,6 +3556,76 @@ pub fn handle_delete_prelaunch_oracle<'info>(
     Ok(())
 }
 
+pub fn handle_resolve_perp_prediction_market(
+    ctx: Context<ResolvePerpPredictionMarket>,
+    outcome: PredictionMarketOutcome,
+) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let perp_market = &mut load_mut!(ctx.accounts.perp_market)?;
+    let prelaunch_oracle = load!(ctx.accounts.prelaunch_oracle)?;
+    let authority = ctx.accounts.authority.key();
+
+    validate!(
+        authority == ctx.accounts.state.admin || authority == prelaunch_oracle.resolver,
+        ErrorCode::DefaultError,
+        "signer must be admin or the prediction market resolver"
+    )?;
+
+    validate!(
+        perp_market.is_prediction_market(),
+        ErrorCode::DefaultError,
+        "perp market {} is not a prediction market",
+        perp_market.market_index
+    )?;
+
+    validate!(
+        perp_market.amm.oracle == ctx.accounts.prelaunch_oracle.key(),
+        ErrorCode::InvalidOracle,
+        "prelaunch oracle is not the perp market oracle"
+    )?;
+
+    validate!(
+        !matches!(
+            perp_market.status,
+            MarketStatus::Initialized | MarketStatus::Settlement | MarketStatus::Delisted
+        ),
+        ErrorCode::DefaultError,
+        "perp market status {:?} cant be resolved",
+        perp_market.status
+    )?;
+
+    validate!(
+        !perp_market.prediction_market_outcome.is_resolved(),
+        ErrorCode::DefaultError,
+        "prediction market already resolved"
+    )?;
+
+    validate!(
+        outcome.is_resolved(),
+        ErrorCode::DefaultError,
+        "outcome must be yes or no"
+    )?;
+
+    msg!(
+        "perp_market.prediction_market_outcome: {:?} -> {:?}",
+        perp_market.prediction_market_outcome,
+        outcome
+    );
+    msg!(
+        "perp_market.status {:?} -> {:?}",
+        perp_market.status,
+        MarketStatus::ReduceOnly
+    );
+    msg!("perp_market.expiry_ts {} -> {}", perp_market.expiry_ts, now);
+
+    // market expires immediately and settle_expired_market settles it at the outcome
+    perp_market.prediction_market_outcome = outcome;
+    perp_market.status = MarketStatus::ReduceOnly;
+    perp_market.expiry_ts = now;
+
+    Ok(())
+}
+
 #[derive(Accounts)]
 pub struct Initialize<'info> {
     #[account(mut)]
@@ -3602,6 +3895,18 @@ pub struct AdminUpdatePerpMarket<'info> {
     pub perp_market: AccountLoader<'info, PerpMarket>,
 }
 
+#[derive(Accounts)]
+pub struct AdminUpdatePerpMarketQuoteSpotMarket<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        has_one = admin
+    )]
+    pub state: Box<Account<'info, State>>,
+    #[account(mut)]
+    pub perp_market: AccountLoader<'info, PerpMarket>,
+    pub quote_spot_market: AccountLoader<'info, SpotMarket>,
+}
+
 #[derive(Accounts)]
 pub struct AdminUpdatePerpMarketAmmSummaryStats<'info> {
     pub admin: Signer<'info>,
@@ -3628,7 +3933,7 @@ pub struct SettleExpiredMarketPoolsToRevenuePool<'info> {
     pub state: Box<Account<'info, State>>,
     pub admin: Signer<'info>,
     #[account(
-        seeds = [b"spot_market", 0_u16.to_le_bytes().as_ref()],
+        seeds = [b"spot_market", perp_market.load()?.quote_spot_market_index.to_le_bytes().as_ref()],
         bump,
         mut
     )]
@@ -3659,13 +3964,13 @@ pub struct DepositIntoMarketFeePool<'info> {
     pub drift_signer: AccountInfo<'info>,
     #[account(
         mut,
-        seeds = [b"spot_market", 0_u16.to_le_bytes().as_ref()],
+        seeds = [b"spot_market", perp_market.load()?.quote_spot_market_index.to_le_bytes().as_ref()],
         bump,
     )]
     pub quote_spot_market: AccountLoader<'info, SpotMarket>,
     #[account(
         mut,
-        seeds = [b"spot_market_vault".as_ref(), 0_u16.to_le_bytes().as_ref()],
+        seeds = [b"spot_market_vault".as_ref(), perp_market.load()?.quote_spot_market_index.to_le_bytes().as_ref()],
         bump,
     )]
     pub spot_market_vault: Box<Account<'info, TokenAccount>>,
@@ -3843,3 +4148,12 @@ pub struct DeletePrelaunchOracle<'info> {
     )]
     pub state: Box<Account<'info, State>>,
 }
+
+#[derive(Accounts)]
+pub struct ResolvePerpPredictionMarket<'info> {
+    pub authority: Signer<'info>,
+    pub state: Box<Account<'info, State>>,
+    #[account(mut)]
+    pub perp_market: AccountLoader<'info, PerpMarket>,
+    pub prelaunch_oracle: AccountLoader<'info, PrelaunchOracle>,
+}
@@ -1,4 +1,5 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions;
 use anchor_spl::token::{Token, TokenAccount};
 
 use crate::error::ErrorCode;
@@ -7,6 +8,7 @@ use crate::instructions::optional_accounts::{load_maps, AccountMaps};
 use crate::math::constants::QUOTE_SPOT_MARKET_INDEX;
 use crate::math::insurance::if_shares_to_vault_amount;
 use crate::math::margin::{calculate_user_equity, meets_settle_pnl_maintenance_margin_requirement};
+use crate::math::oracle::DriftAction;
 use crate::math::orders::{estimate_price_from_side, find_bids_and_asks_from_users};
 use crate::math::spot_withdraw::validate_spot_market_vault_amount;
 use crate::optional_accounts::update_prelaunch_oracle;
@@ -16,6 +18,7 @@ use crate::state::fulfillment_params::phoenix::PhoenixFulfillmentParams;
 use crate::state::fulfillment_params::serum::SerumFulfillmentParams;
 use crate::state::insurance_fund_stake::InsuranceFundStake;
 use crate::state::oracle_map::OracleMap;
+use crate::state::order_params::{PlaceOrderOptions, PostOnlyParam, SignedOrderParams};
 use crate::state::paused_operations::PerpOperation;
 use crate::state::perp_market::{MarketStatus, PerpMarket};
 use crate::state::perp_market_map::{
@@ -31,6 +34,7 @@ use crate::state::spot_market_map::{
 use crate::state::state::State;
 use crate::state::user::{MarketType, OrderStatus, User, UserStats};
 use crate::state::user_map::{load_user_maps, UserMap, UserStatsMap};
+use crate::validation::sig_verification::verify_ed25519_ix_for_user;
 use crate::validation::user::validate_user_is_idle;
 use crate::{controller, load, math, OracleSource};
 use crate::{load_mut, QUOTE_PRECISION_U64};
@@ -116,6 +120,211 @@ fn fill_order(ctx: Context<FillOrder>, order_id: u32, market_index: u16) -> Resu
     Ok(())
 }
 
+#[access_control(
+    fill_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_place_and_match_signed_order<'info>(
+    ctx: Context<PlaceAndMatchSignedOrder>,
+    signed_order_params: SignedOrderParams,
+) -> Result<()> {
+    let clock = &Clock::get()?;
+    let state = &ctx.accounts.state;
+    let params = signed_order_params.order_params;
+    let user_key = ctx.accounts.user.key();
+
+    // the ed25519 ix verifying the taker's signature must directly precede this ix
+    let ix_sysvar = ctx.accounts.ix_sysvar.to_account_info();
+    let current_index = instructions::load_current_index_checked(&ix_sysvar)? as usize;
+    validate!(
+        current_index > 0,
+        ErrorCode::SigVerificationFailed,
+        "ed25519 ix must precede place_and_match_signed_order"
+    )?;
+    let ed25519_ix = instructions::load_instruction_at_checked(current_index - 1, &ix_sysvar)?;
+
+    {
+        let user = load!(ctx.accounts.user)?;
+
+        validate!(
+            signed_order_params.taker == user_key,
+            ErrorCode::SigVerificationFailed,
+            "signed order is for user {}",
+            signed_order_params.taker
+        )?;
+
+        let message = signed_order_params
+            .try_to_vec()
+            .map_err(|_| ErrorCode::DefaultError)?;
+        verify_ed25519_ix_for_user(&ed25519_ix, &user.authority, &user.delegate, &message)?;
+
+        validate!(
+            user.next_order_id == signed_order_params.taker_next_order_id,
+            ErrorCode::SigVerificationFailed,
+            "signed order next_order_id {} != user next_order_id {}",
+            signed_order_params.taker_next_order_id,
+            user.next_order_id
+        )?;
+
+        validate!(
+            clock.slot <= signed_order_params.max_slot,
+            ErrorCode::InvalidOrder,
+            "signed order expired at slot {}",
+            signed_order_params.max_slot
+        )?;
+
+        validate!(
+            params.market_type == MarketType::Perp,
+            ErrorCode::InvalidOrderMarketType,
+            "signed order must be perp order"
+        )?;
+
+        validate!(
+            params.post_only == PostOnlyParam::None,
+            ErrorCode::InvalidOrderPostOnly,
+            "post_only cant be used in place_and_match_signed_order"
+        )?;
+
+        validate!(
+            !params.is_fill_or_kill(),
+            ErrorCode::InvalidOrder,
+            "fill_or_kill order must be in place_and_take"
+        )?;
+    }
+
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        remaining_accounts_iter,
+        &get_writable_perp_market_set(params.market_index),
+        &MarketSet::new(),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    let (makers_and_referrer, makers_and_referrer_stats) =
+        load_user_maps(remaining_accounts_iter, true)?;
+
+    controller::repeg::update_amm(
+        params.market_index,
+        &perp_market_map,
+        &mut oracle_map,
+        state,
+        clock,
+    )?;
+
+    let order_id = {
+        let mut user = load_mut!(ctx.accounts.user)?;
+
+        controller::orders::place_perp_order(
+            state,
+            &mut user,
+            user_key,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            clock,
+            params,
+            PlaceOrderOptions::default(),
+        )?;
+
+        // placing consumes taker_next_order_id, so a signed order can only be submitted once
+        validate!(
+            user.next_order_id != signed_order_params.taker_next_order_id,
+            ErrorCode::InvalidOrder,
+            "signed order was not placed"
+        )?;
+
+        user.get_last_order_id()
+    };
+
+    controller::orders::fill_perp_order(
+        order_id,
+        state,
+        &ctx.accounts.user,
+        &ctx.accounts.user_stats,
+        &spot_market_map,
+        &perp_market_map,
+        &mut oracle_map,
+        &ctx.accounts.filler,
+        &ctx.accounts.filler_stats,
+        &makers_and_referrer,
+        &makers_and_referrer_stats,
+        None,
+        clock,
+        FillMode::Fill,
+    )?;
+
+    let order_exists = load!(ctx.accounts.user)?
+        .orders
+        .iter()
+        .any(|order| order.order_id == order_id);
+
+    if params.immediate_or_cancel && order_exists {
+        controller::orders::cancel_order_by_order_id(
+            order_id,
+            &ctx.accounts.user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            clock,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[access_control(
+    fill_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_clear_perp_batch_auction<'info>(
+    ctx: Context<ClearPerpBatchAuction>,
+    market_index: u16,
+) -> Result<()> {
+    let clock = &Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        remaining_accounts_iter,
+        &get_writable_perp_market_set(market_index),
+        &MarketSet::new(),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    let (users, users_stats) = load_user_maps(remaining_accounts_iter, true)?;
+
+    controller::repeg::update_amm(
+        market_index,
+        &perp_market_map,
+        &mut oracle_map,
+        state,
+        clock,
+    )?;
+
+    controller::orders::clear_perp_batch_auction(
+        market_index,
+        state,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        &users,
+        &users_stats,
+        &ctx.accounts.filler,
+        &ctx.accounts.filler_stats,
+        clock,
+    )?;
+
+    Ok(())
+}
+
 #[access_control(
     fill_not_paused(&ctx.accounts.state)
 )]
@@ -257,6 +466,7 @@ fn fill_spot_order<'info>(
         None,
         &clock,
         fulfillment_params.as_mut(),
+        FillMode::Fill,
     )?;
 
     let base_market = spot_market_map.get_ref(&market_index)?;
@@ -345,6 +555,35 @@ pub fn handle_force_cancel_orders<'info>(ctx: Context<ForceCancelOrder>) -> Resu
     Ok(())
 }
 
+#[access_control(
+    exchange_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_cancel_orders_after_deadline<'info>(ctx: Context<ForceCancelOrder>) -> Result<()> {
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        &mut ctx.remaining_accounts.iter().peekable(),
+        &MarketSet::new(),
+        &MarketSet::new(),
+        Clock::get()?.slot,
+        None,
+    )?;
+
+    controller::orders::cancel_orders_after_deadline(
+        &ctx.accounts.state,
+        &ctx.accounts.user,
+        &spot_market_map,
+        &perp_market_map,
+        &mut oracle_map,
+        &ctx.accounts.filler,
+        &Clock::get()?,
+    )?;
+
+    Ok(())
+}
+
 #[access_control(
     exchange_not_paused(&ctx.accounts.state)
 )]
@@ -904,7 +1143,6 @@ pub fn handle_resolve_perp_pnl_deficit(
     let clock = Clock::get()?;
     let now = clock.unix_timestamp;
 
-    validate!(spot_market_index == 0, ErrorCode::InvalidSpotMarketAccount)?;
     let state = &ctx.accounts.state;
 
     let AccountMaps {
@@ -919,6 +1157,17 @@ pub fn handle_resolve_perp_pnl_deficit(
         Some(state.oracle_guard_rails),
     )?;
 
+    validate!(
+        perp_market_map
+            .get_ref(&perp_market_index)?
+            .quote_spot_market_index
+            == spot_market_index,
+        ErrorCode::InvalidSpotMarketAccount,
+        "spot market {} is not the quote market for perp market {}",
+        spot_market_index,
+        perp_market_index
+    )?;
+
     controller::repeg::update_amm(
         perp_market_index,
         &perp_market_map,
@@ -977,6 +1226,14 @@ pub fn handle_resolve_perp_pnl_deficit(
 
         controller::orders::validate_market_within_price_band(perp_market, state, true, None)?;
 
+        controller::spot_balance::update_quote_spot_market_for_perp_market(
+            perp_market,
+            spot_market,
+            &mut oracle_map,
+            now,
+            Some(DriftAction::SettlePnl),
+        )?;
+
         controller::insurance::resolve_perp_pnl_deficit(
             spot_market_vault_amount,
             insurance_vault_amount,
@@ -1035,11 +1292,6 @@ pub fn handle_resolve_perp_bankruptcy(
         ErrorCode::UserCantLiquidateThemself
     )?;
 
-    validate!(
-        quote_spot_market_index == QUOTE_SPOT_MARKET_INDEX,
-        ErrorCode::InvalidSpotMarketAccount
-    )?;
-
     let user = &mut load_mut!(ctx.accounts.user)?;
     let liquidator = &mut load_mut!(ctx.accounts.liquidator)?;
     let state = &ctx.accounts.state;
@@ -1056,6 +1308,17 @@ pub fn handle_resolve_perp_bankruptcy(
         Some(state.oracle_guard_rails),
     )?;
 
+    validate!(
+        perp_market_map
+            .get_ref(&market_index)?
+            .quote_spot_market_index
+            == quote_spot_market_index,
+        ErrorCode::InvalidSpotMarketAccount,
+        "spot market {} is not the quote market for perp market {}",
+        quote_spot_market_index,
+        market_index
+    )?;
+
     {
         let spot_market = &mut spot_market_map.get_ref_mut(&quote_spot_market_index)?;
         controller::insurance::attempt_settle_revenue_to_insurance_fund(
@@ -1581,6 +1844,49 @@ pub struct FillOrder<'info> {
     pub user_stats: AccountLoader<'info, UserStats>,
 }
 
+#[derive(Accounts)]
+pub struct ClearPerpBatchAuction<'info> {
+    pub state: Box<Account<'info, State>>,
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        constraint = can_sign_for_user(&filler, &authority)?
+    )]
+    pub filler: AccountLoader<'info, User>,
+    #[account(
+        mut,
+        constraint = is_stats_for_user(&filler, &filler_stats)?
+    )]
+    pub filler_stats: AccountLoader<'info, UserStats>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceAndMatchSignedOrder<'info> {
+    pub state: Box<Account<'info, State>>,
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        constraint = can_sign_for_user(&filler, &authority)?
+    )]
+    pub filler: AccountLoader<'info, User>,
+    #[account(
+        mut,
+        constraint = is_stats_for_user(&filler, &filler_stats)?
+    )]
+    pub filler_stats: AccountLoader<'info, UserStats>,
+    #[account(mut)]
+    pub user: AccountLoader<'info, User>,
+    #[account(
+        mut,
+        constraint = is_stats_for_user(&user, &user_stats)?
+    )]
+    pub user_stats: AccountLoader<'info, UserStats>,
+    /// Instructions Sysvar for instruction introspection
+    /// CHECK: fixed instructions sysvar account
+    #[account(address = instructions::ID)]
+    pub ix_sysvar: AccountInfo<'info>,
+}
+
 #[derive(Accounts)]
 pub struct RevertFill<'info> {
     pub state: Box<Account<'info, State>>,
@@ -24,8 +24,9 @@ use crate::load_mut;
 use crate::math::casting::Cast;
 use crate::math::liquidation::is_user_being_liquidated;
 use crate::math::margin::{
-    calculate_max_withdrawable_amount, meets_place_order_margin_requirement,
-    meets_withdraw_margin_requirement, validate_spot_margin_trading, MarginRequirementType,
+    calculate_max_withdrawable_amount, meets_initial_margin_requirement,
+    meets_place_order_margin_requirement, meets_withdraw_margin_requirement,
+    validate_spot_margin_trading, MarginRequirementType,
 };
 use crate::math::safe_math::SafeMath;
 use crate::math::spot_balance::get_token_value;
@@ -43,23 +44,35 @@ use crate::state::fill_mode::FillMode;
 use crate::state::fulfillment_params::drift::MatchFulfillmentParams;
 use crate::state::fulfillment_params::phoenix::PhoenixFulfillmentParams;
 use crate::state::fulfillment_params::serum::SerumFulfillmentParams;
+use crate::state::margin_calculation::MarketIdentifier;
+use crate::state::market_maker_protection::UserMarketMakerProtection;
 use crate::state::oracle::StrictOraclePrice;
+use crate::state::oracle_map::OracleMap;
 use crate::state::order_params::{
-    ModifyOrderParams, OrderParams, PlaceOrderOptions, PostOnlyParam,
+    ComboOrderParams, ModifyOrderParams, ModifyOrdersParams, OrderParams, PlaceOrderOptions,
+    PostOnlyParam, QuoteSetParams,
 };
 use crate::state::paused_operations::PerpOperation;
 use crate::state::perp_market::MarketStatus;
-use crate::state::perp_market_map::{get_writable_perp_market_set, MarketSet};
+use crate::state::perp_market_map::{
+    get_writable_perp_market_set, get_writable_perp_market_set_from_vec, MarketSet, PerpMarketMap,
+};
+use crate::state::rfq::{Rfq, RfqParams, RfqQuote, MAX_RFQ_MAKERS};
 use crate::state::spot_fulfillment_params::SpotFulfillmentParams;
 use crate::state::spot_market::SpotBalanceType;
 use crate::state::spot_market::SpotMarket;
 use crate::state::spot_market_map::{
-    get_writable_spot_market_set, get_writable_spot_market_set_from_many,
+    get_writable_spot_market_set, get_writable_spot_market_set_from_many, SpotMarketMap,
 };
 use crate::state::state::State;
 use crate::state::traits::Size;
-use crate::state::user::{MarketType, OrderType, ReferrerName, User, UserStats};
-use crate::state::user_map::{load_user_maps, UserMap, UserStatsMap};
+use crate::state::user::{
+    MarketType, OrderType, ReferrerName, SelfTradePreventionMode, User, UserStats,
+};
+use crate::state::user_map::{
+    get_user_market_maker_protection, load_user_maps, load_user_market_maker_protection, UserMap,
+    UserStatsMap,
+};
 use crate::validate;
 use crate::validation::user::validate_user_deletion;
 use crate::validation::whitelist::validate_whitelist_token;
@@ -580,6 +593,137 @@ pub fn handle_withdraw(
     Ok(())
 }
 
+pub fn handle_transfer_isolated_perp_position_deposit(
+    ctx: Context<TransferIsolatedPerpPositionDeposit>,
+    spot_market_index: u16,
+    perp_market_index: u16,
+    amount: i64,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let user = &mut load_mut!(ctx.accounts.user)?;
+
+    validate!(!user.is_bankrupt(), ErrorCode::UserBankrupt)?;
+
+    validate!(
+        amount != 0,
+        ErrorCode::InvalidIsolatedPerpPosition,
+        "amount must not be 0"
+    )?;
+
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        &mut ctx.remaining_accounts.iter().peekable(),
+        &MarketSet::new(),
+        &get_writable_spot_market_set(spot_market_index),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    validate!(
+        perp_market_map
+            .get_ref(&perp_market_index)?
+            .quote_spot_market_index
+            == spot_market_index,
+        ErrorCode::InvalidIsolatedPerpPosition,
+        "isolated perp position must be collateralized by perp market {}'s quote spot market",
+        perp_market_index
+    )?;
+
+    math::liquidation::validate_user_not_being_liquidated(
+        user,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        state.liquidation_margin_buffer_ratio,
+    )?;
+
+    {
+        let spot_market = &mut spot_market_map.get_ref_mut(&spot_market_index)?;
+        controller::spot_balance::update_spot_market_cumulative_interest(
+            spot_market,
+            None,
+            clock.unix_timestamp,
+        )?;
+
+        if amount > 0 {
+            user.enter_isolated_perp_position(perp_market_index, spot_market_index)?;
+            let mut isolated_balance =
+                user.get_isolated_perp_position_balance(perp_market_index)?;
+
+            let spot_position = user.get_spot_position_mut(spot_market_index)?;
+            let token_amount = spot_position.get_signed_token_amount(spot_market)?;
+
+            validate!(
+                token_amount >= amount.cast()?,
+                ErrorCode::InsufficientCollateral,
+                "spot market {} deposit {} less than amount {}",
+                spot_market_index,
+                token_amount,
+                amount
+            )?;
+
+            controller::spot_balance::transfer_spot_balances(
+                amount.cast()?,
+                spot_market,
+                spot_position,
+                &mut isolated_balance,
+            )?;
+
+            user.update_isolated_perp_position_balance(perp_market_index, &isolated_balance)?;
+        } else {
+            let mut isolated_balance =
+                user.get_isolated_perp_position_balance(perp_market_index)?;
+
+            // withdraw at most the isolated collateral
+            let token_amount = amount
+                .unsigned_abs()
+                .cast::<u128>()?
+                .min(isolated_balance.get_token_amount(spot_market)?);
+
+            controller::spot_balance::transfer_spot_balances(
+                token_amount.cast()?,
+                spot_market,
+                &mut isolated_balance,
+                user.force_get_spot_position_mut(spot_market_index)?,
+            )?;
+
+            user.update_isolated_perp_position_balance(perp_market_index, &isolated_balance)?;
+        }
+    }
+
+    user.try_exit_isolated_perp_position(perp_market_index);
+
+    // a deposit draws on the cross margin account, a withdrawal on the isolated perp position
+    let market = if amount > 0 {
+        MarketIdentifier::spot(spot_market_index)
+    } else {
+        MarketIdentifier::perp(perp_market_index)
+    };
+
+    let meets_initial_margin_requirement = meets_initial_margin_requirement(
+        user,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        market,
+    )?;
+
+    validate!(
+        meets_initial_margin_requirement,
+        ErrorCode::InsufficientCollateral,
+        "user doesnt meet initial margin requirement after transfer"
+    )?;
+
+    user.update_last_active_slot(clock.slot);
+
+    Ok(())
+}
+
 #[access_control(
     deposit_not_paused(&ctx.accounts.state)
     withdraw_not_paused(&ctx.accounts.state)
@@ -782,24 +926,33 @@ pub fn handle_place_perp_order(ctx: Context<PlaceOrder>, params: OrderParams) ->
     let clock = &Clock::get()?;
     let state = &ctx.accounts.state;
 
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
     let AccountMaps {
         perp_market_map,
         spot_market_map,
         mut oracle_map,
     } = load_maps(
-        &mut ctx.remaining_accounts.iter().peekable(),
+        remaining_accounts_iter,
         &MarketSet::new(),
         &MarketSet::new(),
         clock.slot,
         Some(state.oracle_guard_rails),
     )?;
 
+    let user_key = ctx.accounts.user.key();
+    let market_maker_protection =
+        get_user_market_maker_protection(remaining_accounts_iter, &user_key)?;
+
     if params.immediate_or_cancel {
         msg!("immediate_or_cancel order must be in place_and_make or place_and_take");
         return Err(print_error!(ErrorCode::InvalidOrderIOC)().into());
     }
 
-    let user_key = ctx.accounts.user.key();
+    if params.is_fill_or_kill() {
+        msg!("fill_or_kill order must be in place_and_take");
+        return Err(print_error!(ErrorCode::InvalidOrder)().into());
+    }
+
     let mut user = load_mut!(ctx.accounts.user)?;
 
     controller::orders::place_perp_order(
@@ -811,7 +964,7 @@ pub fn handle_place_perp_order(ctx: Context<PlaceOrder>, params: OrderParams) ->
         &mut oracle_map,
         clock,
         params,
-        PlaceOrderOptions::default(),
+        PlaceOrderOptions::default().market_maker_protection(market_maker_protection.as_deref()),
     )?;
 
     Ok(())
@@ -962,6 +1115,31 @@ pub fn handle_cancel_orders(
     Ok(())
 }
 
+pub fn handle_update_user_cancel_orders_after(
+    ctx: Context<CancelOrder>,
+    cancel_after_secs: u32,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let mut user = load_mut!(ctx.accounts.user)?;
+
+    // 0 disarms the dead man's switch
+    let cancel_orders_after_ts = if cancel_after_secs == 0 {
+        0
+    } else {
+        clock.unix_timestamp.safe_add(cancel_after_secs.cast()?)?
+    };
+
+    msg!(
+        "cancel_orders_after_ts: {} -> {}",
+        user.cancel_orders_after_ts,
+        cancel_orders_after_ts
+    );
+
+    user.cancel_orders_after_ts = cancel_orders_after_ts;
+
+    Ok(())
+}
+
 #[access_control(
     exchange_not_paused(&ctx.accounts.state)
 )]
@@ -973,18 +1151,23 @@ pub fn handle_modify_order(
     let clock = &Clock::get()?;
     let state = &ctx.accounts.state;
 
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
     let AccountMaps {
         perp_market_map,
         spot_market_map,
         mut oracle_map,
     } = load_maps(
-        &mut ctx.remaining_accounts.iter().peekable(),
+        remaining_accounts_iter,
         &MarketSet::new(),
         &MarketSet::new(),
         clock.slot,
         Some(state.oracle_guard_rails),
     )?;
 
+    let user_key = ctx.accounts.user.key();
+    let market_maker_protection =
+        get_user_market_maker_protection(remaining_accounts_iter, &user_key)?;
+
     let order_id = match order_id {
         Some(order_id) => order_id,
         None => load!(ctx.accounts.user)?.get_last_order_id(),
@@ -999,6 +1182,7 @@ pub fn handle_modify_order(
         &spot_market_map,
         &mut oracle_map,
         clock,
+        PlaceOrderOptions::default().market_maker_protection(market_maker_protection.as_deref()),
     )?;
 
     Ok(())
@@ -1015,18 +1199,23 @@ pub fn handle_modify_order_by_user_order_id(
     let clock = &Clock::get()?;
     let state = &ctx.accounts.state;
 
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
     let AccountMaps {
         perp_market_map,
         spot_market_map,
         mut oracle_map,
     } = load_maps(
-        &mut ctx.remaining_accounts.iter().peekable(),
+        remaining_accounts_iter,
         &MarketSet::new(),
         &MarketSet::new(),
         clock.slot,
         Some(state.oracle_guard_rails),
     )?;
 
+    let user_key = ctx.accounts.user.key();
+    let market_maker_protection =
+        get_user_market_maker_protection(remaining_accounts_iter, &user_key)?;
+
     controller::orders::modify_order(
         ModifyOrderId::UserOrderId(user_order_id),
         modify_order_params,
@@ -1036,6 +1225,7 @@ pub fn handle_modify_order_by_user_order_id(
         &spot_market_map,
         &mut oracle_map,
         clock,
+        PlaceOrderOptions::default().market_maker_protection(market_maker_protection.as_deref()),
     )?;
 
     Ok(())
@@ -1044,31 +1234,413 @@ pub fn handle_modify_order_by_user_order_id(
 #[access_control(
     exchange_not_paused(&ctx.accounts.state)
 )]
-pub fn handle_place_orders(ctx: Context<PlaceOrder>, params: Vec<OrderParams>) -> Result<()> {
+pub fn handle_modify_orders(
+    ctx: Context<CancelOrder>,
+    params: Vec<ModifyOrdersParams>,
+) -> Result<()> {
     let clock = &Clock::get()?;
     let state = &ctx.accounts.state;
 
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
     let AccountMaps {
         perp_market_map,
         spot_market_map,
         mut oracle_map,
     } = load_maps(
-        &mut ctx.remaining_accounts.iter().peekable(),
+        remaining_accounts_iter,
         &MarketSet::new(),
         &MarketSet::new(),
         clock.slot,
         Some(state.oracle_guard_rails),
     )?;
 
+    let user_key = ctx.accounts.user.key();
+    let market_maker_protection =
+        get_user_market_maker_protection(remaining_accounts_iter, &user_key)?;
+
     validate!(
         params.len() <= 32,
         ErrorCode::DefaultError,
-        "max 32 order params"
+        "max 32 modify order params"
+    )?;
+
+    let mut risk_increasing = false;
+    let mut modified_markets = Vec::with_capacity(params.len());
+    for (i, params) in params.into_iter().enumerate() {
+        if let Some(order) = load!(ctx.accounts.user)?.get_order(params.order_id) {
+            modified_markets.push(MarketIdentifier {
+                market_type: order.market_type,
+                market_index: order.market_index,
+            });
+        }
+
+        // margin is checked once after all orders are modified and only try to expire on first order
+        let options = PlaceOrderOptions {
+            enforce_margin_check: false,
+            try_expire_orders: i == 0,
+            risk_increasing: false,
+            explanation: OrderActionExplanation::None,
+            market_maker_protection: market_maker_protection.as_deref(),
+            replace_order_index: None,
+        };
+
+        let order_risk_increasing = controller::orders::modify_order(
+            ModifyOrderId::OrderId(params.order_id),
+            params.modify_order_params,
+            &ctx.accounts.user,
+            state,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            clock,
+            options,
+        )?;
+
+        risk_increasing = risk_increasing || order_risk_increasing;
+    }
+
+    meets_place_order_margin_requirement(
+        &load!(ctx.accounts.user)?,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        risk_increasing,
+        &modified_markets,
+    )?;
+
+    Ok(())
+}
+
+#[access_control(
+    exchange_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_place_orders(ctx: Context<PlaceOrder>, params: Vec<OrderParams>) -> Result<()> {
+    let clock = &Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        remaining_accounts_iter,
+        &MarketSet::new(),
+        &MarketSet::new(),
+        clock.slot,
+        Some(state.oracle_guard_rails),
     )?;
 
     let user_key = ctx.accounts.user.key();
+    let market_maker_protection =
+        get_user_market_maker_protection(remaining_accounts_iter, &user_key)?;
+
     let mut user = load_mut!(ctx.accounts.user)?;
 
+    place_orders(
+        state,
+        &mut user,
+        user_key,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        clock,
+        &params,
+        market_maker_protection.as_deref(),
+    )?;
+
+    Ok(())
+}
+
+#[access_control(
+    exchange_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_cancel_and_place_orders(
+    ctx: Context<PlaceOrder>,
+    market_type: Option<MarketType>,
+    market_index: Option<u16>,
+    direction: Option<PositionDirection>,
+    order_ids: Vec<u32>,
+    params: Vec<OrderParams>,
+) -> Result<()> {
+    let clock = &Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        remaining_accounts_iter,
+        &MarketSet::new(),
+        &MarketSet::new(),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    let user_key = ctx.accounts.user.key();
+    let market_maker_protection =
+        get_user_market_maker_protection(remaining_accounts_iter, &user_key)?;
+
+    let mut user = load_mut!(ctx.accounts.user)?;
+
+    // explicit order ids take precedence over the market/direction filter
+    if order_ids.is_empty() {
+        cancel_orders(
+            &mut user,
+            &user_key,
+            None,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            clock.unix_timestamp,
+            clock.slot,
+            OrderActionExplanation::None,
+            market_type,
+            market_index,
+            direction,
+        )?;
+    } else {
+        for order_id in order_ids {
+            let order_index = match user.get_order_index(order_id) {
+                Ok(order_index) => order_index,
+                Err(_) => {
+                    msg!("could not find order id {}", order_id);
+                    continue;
+                }
+            };
+
+            controller::orders::cancel_order(
+                order_index,
+                &mut user,
+                &user_key,
+                &perp_market_map,
+                &spot_market_map,
+                &mut oracle_map,
+                clock.unix_timestamp,
+                clock.slot,
+                OrderActionExplanation::None,
+                None,
+                0,
+                false,
+            )?;
+        }
+    }
+
+    // cancels don't check margin, so margin is only evaluated on the final state by the last place
+    place_orders(
+        state,
+        &mut user,
+        user_key,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        clock,
+        &params,
+        market_maker_protection.as_deref(),
+    )?;
+
+    Ok(())
+}
+
+#[access_control(
+    exchange_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_place_quote_set(ctx: Context<PlaceOrder>, params: QuoteSetParams) -> Result<()> {
+    let clock = &Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        remaining_accounts_iter,
+        &MarketSet::new(),
+        &MarketSet::new(),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    let user_key = ctx.accounts.user.key();
+    let market_maker_protection =
+        get_user_market_maker_protection(remaining_accounts_iter, &user_key)?;
+
+    let mut user = load_mut!(ctx.accounts.user)?;
+
+    controller::orders::place_perp_quote_set(
+        state,
+        &mut user,
+        user_key,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        clock,
+        &params,
+        market_maker_protection.as_deref(),
+    )?;
+
+    Ok(())
+}
+
+pub fn handle_initialize_rfq(ctx: Context<InitializeRfq>, params: RfqParams) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let user_key = ctx.accounts.user.key();
+    let user = load!(ctx.accounts.user)?;
+
+    validate!(
+        params.base_asset_amount > 0,
+        ErrorCode::InvalidRfq,
+        "rfq base asset amount must be greater than 0"
+    )?;
+
+    validate!(
+        params.expiry_ts > now,
+        ErrorCode::InvalidRfq,
+        "rfq expiry ts {} must be in the future",
+        params.expiry_ts
+    )?;
+
+    validate!(
+        !params.whitelisted_makers.is_empty() && params.whitelisted_makers.len() <= MAX_RFQ_MAKERS,
+        ErrorCode::InvalidRfq,
+        "rfq must whitelist between 1 and {} makers",
+        MAX_RFQ_MAKERS
+    )?;
+
+    validate!(
+        !params.whitelisted_makers.contains(&user_key),
+        ErrorCode::InvalidRfq,
+        "taker can't be whitelisted as maker"
+    )?;
+
+    let mut rfq = ctx
+        .accounts
+        .rfq
+        .load_init()
+        .or(Err(ErrorCode::UnableToLoadAccountLoader))?;
+
+    rfq.user = user_key;
+    rfq.authority = user.authority;
+    rfq.market_index = params.market_index;
+    rfq.direction = params.direction;
+    rfq.base_asset_amount = params.base_asset_amount;
+    rfq.expiry_ts = params.expiry_ts;
+    for (i, maker) in params.whitelisted_makers.iter().enumerate() {
+        rfq.whitelisted_makers[i] = *maker;
+    }
+
+    Ok(())
+}
+
+pub fn handle_submit_rfq_quote(
+    ctx: Context<SubmitRfqQuote>,
+    price: u64,
+    expiry_ts: i64,
+) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let maker_key = ctx.accounts.user.key();
+    let mut rfq = load_mut!(ctx.accounts.rfq)?;
+
+    rfq.validate_not_expired(now)?;
+
+    validate!(
+        price > 0,
+        ErrorCode::InvalidRfq,
+        "rfq quote price must be greater than 0"
+    )?;
+
+    validate!(
+        expiry_ts >= now,
+        ErrorCode::RfqExpired,
+        "rfq quote expiry ts {} is in the past",
+        expiry_ts
+    )?;
+
+    let maker_index = rfq.get_maker_index(&maker_key)?;
+    rfq.quotes[maker_index] = RfqQuote { price, expiry_ts };
+
+    Ok(())
+}
+
+#[access_control(
+    fill_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_accept_rfq_quote(ctx: Context<AcceptRfqQuote>, expected_price: u64) -> Result<()> {
+    let clock = &Clock::get()?;
+    let state = &ctx.accounts.state;
+    let rfq = load!(ctx.accounts.rfq)?;
+
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        remaining_accounts_iter,
+        &get_writable_perp_market_set(rfq.market_index),
+        &MarketSet::new(),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    let user_key = ctx.accounts.user.key();
+    let mut user = load_mut!(ctx.accounts.user)?;
+    let mut user_stats = load_mut!(ctx.accounts.user_stats)?;
+
+    let maker_key = ctx.accounts.maker.key();
+    let mut maker = load_mut!(ctx.accounts.maker)?;
+    let mut maker_stats = load_mut!(ctx.accounts.maker_stats)?;
+    let maker_market_maker_protection =
+        load_user_market_maker_protection(remaining_accounts_iter, &maker_key, true)?;
+    let mut maker_market_maker_protection = maker_market_maker_protection
+        .as_ref()
+        .map(|market_maker_protection| market_maker_protection.load_mut())
+        .transpose()?;
+
+    controller::orders::fill_rfq_quote(
+        state,
+        &rfq,
+        expected_price,
+        &mut user,
+        &user_key,
+        &mut user_stats,
+        &mut maker,
+        &maker_key,
+        &mut maker_stats,
+        maker_market_maker_protection.as_deref_mut(),
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        clock,
+    )?;
+
+    Ok(())
+}
+
+pub fn handle_cancel_rfq(_ctx: Context<CancelRfq>) -> Result<()> {
+    Ok(())
+}
+
+fn place_orders(
+    state: &State,
+    user: &mut User,
+    user_key: Pubkey,
+    perp_market_map: &PerpMarketMap,
+    spot_market_map: &SpotMarketMap,
+    oracle_map: &mut OracleMap,
+    clock: &Clock,
+    params: &[OrderParams],
+    market_maker_protection: Option<&UserMarketMakerProtection>,
+) -> Result<()> {
+    validate!(
+        params.len() <= 32,
+        ErrorCode::DefaultError,
+        "max 32 order params"
+    )?;
+
     let num_orders = params.len();
     for (i, params) in params.iter().enumerate() {
         validate!(
@@ -1077,34 +1649,42 @@ pub fn handle_place_orders(ctx: Context<PlaceOrder>, params: Vec<OrderParams>) -
             "immediate_or_cancel order must be in place_and_make or place_and_take"
         )?;
 
+        validate!(
+            !params.is_fill_or_kill(),
+            ErrorCode::InvalidOrder,
+            "fill_or_kill order must be in place_and_take"
+        )?;
+
         // only enforce margin on last order and only try to expire on first order
         let options = PlaceOrderOptions {
             enforce_margin_check: i == num_orders - 1,
             try_expire_orders: i == 0,
             risk_increasing: false,
             explanation: OrderActionExplanation::None,
+            market_maker_protection,
+            replace_order_index: None,
         };
 
         if params.market_type == MarketType::Perp {
             controller::orders::place_perp_order(
-                &ctx.accounts.state,
-                &mut user,
+                state,
+                user,
                 user_key,
-                &perp_market_map,
-                &spot_market_map,
-                &mut oracle_map,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
                 clock,
                 *params,
                 options,
             )?;
         } else {
             controller::orders::place_spot_order(
-                &ctx.accounts.state,
-                &mut user,
+                state,
+                user,
                 user_key,
-                &perp_market_map,
-                &spot_market_map,
-                &mut oracle_map,
+                perp_market_map,
+                spot_market_map,
+                oracle_map,
                 clock,
                 *params,
                 options,
@@ -1148,6 +1728,7 @@ pub fn handle_place_and_take_perp_order<'info>(
         load_user_maps(remaining_accounts_iter, true)?;
 
     let is_immediate_or_cancel = params.immediate_or_cancel;
+    let is_fill_or_kill = params.is_fill_or_kill();
 
     controller::repeg::update_amm(
         params.market_index,
@@ -1176,8 +1757,11 @@ pub fn handle_place_and_take_perp_order<'info>(
 
     let user = &mut ctx.accounts.user;
     let order_id = load!(user)?.get_last_order_id();
+    let order_base_asset_amount = load!(user)?
+        .get_order(order_id)
+        .map_or(0, |order| order.base_asset_amount);
 
-    controller::orders::fill_perp_order(
+    let base_asset_amount_filled = controller::orders::fill_perp_order(
         order_id,
         &ctx.accounts.state,
         user,
@@ -1199,6 +1783,15 @@ pub fn handle_place_and_take_perp_order<'info>(
         .iter()
         .any(|order| order.order_id == order_id);
 
+    if is_fill_or_kill {
+        controller::orders::validate_fill_or_kill_order_filled(
+            &load!(ctx.accounts.user)?,
+            order_id,
+            order_base_asset_amount,
+            base_asset_amount_filled,
+        )?;
+    }
+
     if is_immediate_or_cancel && order_exists {
         controller::orders::cancel_order_by_order_id(
             order_id,
@@ -1253,27 +1846,35 @@ pub fn handle_place_and_make_perp_order<'a, 'b, 'c, 'info>(
         clock,
     )?;
 
+    // the maker's market maker protection is provided alongside the taker in the user maps
+    let (mut makers_and_referrer, mut makers_and_referrer_stats) =
+        load_user_maps(remaining_accounts_iter, true)?;
+
     let user_key = ctx.accounts.user.key();
     let mut user = load_mut!(ctx.accounts.user)?;
 
-    controller::orders::place_perp_order(
-        state,
-        &mut user,
-        user_key,
-        &perp_market_map,
-        &spot_market_map,
-        &mut oracle_map,
-        clock,
-        params,
-        PlaceOrderOptions::default(),
-    )?;
+    {
+        let market_maker_protection =
+            makers_and_referrer.get_market_maker_protection_mut(&user_key)?;
+
+        controller::orders::place_perp_order(
+            state,
+            &mut user,
+            user_key,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            clock,
+            params,
+            PlaceOrderOptions::default()
+                .market_maker_protection(market_maker_protection.as_deref()),
+        )?;
+    }
 
     let (order_id, authority) = (user.get_last_order_id(), user.authority);
 
     drop(user);
 
-    let (mut makers_and_referrer, mut makers_and_referrer_stats) =
-        load_user_maps(remaining_accounts_iter, true)?;
     makers_and_referrer.insert(ctx.accounts.user.key(), ctx.accounts.user.clone())?;
     makers_and_referrer_stats.insert(authority, ctx.accounts.user_stats.clone())?;
 
@@ -1331,6 +1932,11 @@ pub fn handle_place_spot_order(ctx: Context<PlaceOrder>, params: OrderParams) ->
         return Err(print_error!(ErrorCode::InvalidOrderIOC)().into());
     }
 
+    if params.is_fill_or_kill() {
+        msg!("fill_or_kill order must be in place_and_take");
+        return Err(print_error!(ErrorCode::InvalidOrder)().into());
+    }
+
     let user_key = ctx.accounts.user.key();
     let mut user = load_mut!(ctx.accounts.user)?;
 
@@ -1385,6 +1991,7 @@ pub fn handle_place_and_take_spot_order<'info>(
     };
 
     let is_immediate_or_cancel = params.immediate_or_cancel;
+    let is_fill_or_kill = params.is_fill_or_kill();
 
     let mut fulfillment_params: Box<dyn SpotFulfillmentParams> = match fulfillment_type {
         SpotFulfillmentType::SerumV3 => {
@@ -1438,8 +2045,11 @@ pub fn handle_place_and_take_spot_order<'info>(
 
     let user = &mut ctx.accounts.user;
     let order_id = load!(user)?.get_last_order_id();
+    let order_base_asset_amount = load!(user)?
+        .get_order(order_id)
+        .map_or(0, |order| order.base_asset_amount);
 
-    controller::orders::fill_spot_order(
+    let base_asset_amount_filled = controller::orders::fill_spot_order(
         order_id,
         &ctx.accounts.state,
         user,
@@ -1454,6 +2064,7 @@ pub fn handle_place_and_take_spot_order<'info>(
         None,
         &clock,
         fulfillment_params.as_mut(),
+        FillMode::PlaceAndTake,
     )?;
 
     let order_exists = load!(ctx.accounts.user)?
@@ -1461,6 +2072,15 @@ pub fn handle_place_and_take_spot_order<'info>(
         .iter()
         .any(|order| order.order_id == order_id);
 
+    if is_fill_or_kill {
+        controller::orders::validate_fill_or_kill_order_filled(
+            &load!(ctx.accounts.user)?,
+            order_id,
+            order_base_asset_amount,
+            base_asset_amount_filled,
+        )?;
+    }
+
     if is_immediate_or_cancel && order_exists {
         controller::orders::cancel_order_by_order_id(
             order_id,
@@ -1479,6 +2099,68 @@ pub fn handle_place_and_take_spot_order<'info>(
     Ok(())
 }
 
+#[access_control(
+    fill_not_paused(&ctx.accounts.state)
+)]
+pub fn handle_place_and_take_combo_order<'info>(
+    ctx: Context<PlaceAndTake>,
+    params: ComboOrderParams,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let state = &ctx.accounts.state;
+
+    let perp_market_indexes = params
+        .legs
+        .iter()
+        .filter(|leg| leg.market_type == MarketType::Perp)
+        .map(|leg| leg.market_index)
+        .collect::<Vec<u16>>();
+
+    let mut spot_market_indexes = vec![QUOTE_SPOT_MARKET_INDEX];
+    spot_market_indexes.extend(
+        params
+            .legs
+            .iter()
+            .filter(|leg| leg.market_type == MarketType::Spot)
+            .map(|leg| leg.market_index),
+    );
+
+    let remaining_accounts_iter = &mut ctx.remaining_accounts.iter().peekable();
+    let AccountMaps {
+        perp_market_map,
+        spot_market_map,
+        mut oracle_map,
+    } = load_maps(
+        remaining_accounts_iter,
+        &get_writable_perp_market_set_from_vec(&perp_market_indexes),
+        &get_writable_spot_market_set_from_many(spot_market_indexes),
+        clock.slot,
+        Some(state.oracle_guard_rails),
+    )?;
+
+    let (makers_and_referrer, makers_and_referrer_stats) =
+        load_user_maps(remaining_accounts_iter, true)?;
+
+    // base and quote vault for each spot leg, in leg order
+    let spot_vault_accounts = remaining_accounts_iter.cloned().collect::<Vec<_>>();
+
+    controller::orders::place_and_take_combo_order(
+        state,
+        &ctx.accounts.user,
+        &ctx.accounts.user_stats,
+        &perp_market_map,
+        &spot_market_map,
+        &mut oracle_map,
+        &makers_and_referrer,
+        &makers_and_referrer_stats,
+        &spot_vault_accounts,
+        &clock,
+        &params,
+    )?;
+
+    Ok(())
+}
+
 #[access_control(
     fill_not_paused(&ctx.accounts.state)
 )]
@@ -1589,6 +2271,7 @@ pub fn handle_place_and_make_spot_order<'info>(
         Some(order_id),
         clock,
         fulfillment_params.as_mut(),
+        FillMode::PlaceAndMake,
     )?;
 
     let order_exists = load!(ctx.accounts.user)?
@@ -1649,6 +2332,12 @@ pub fn handle_add_perp_lp_shares<'info>(
         state.liquidation_margin_buffer_ratio,
     )?;
 
+    validate!(
+        !user.is_isolated_perp_market(market_index),
+        ErrorCode::InvalidIsolatedPerpPosition,
+        "cant add lp shares to isolated perp position"
+    )?;
+
     {
         let mut market = perp_market_map.get_ref_mut(&market_index)?;
 
@@ -1664,6 +2353,12 @@ pub fn handle_add_perp_lp_shares<'info>(
             "Market amm fills paused"
         )?;
 
+        validate!(
+            !market.is_orderbook_only(),
+            ErrorCode::MarketStatusInvalidForNewLP,
+            "Orderbook only markets have no amm"
+        )?;
+
         validate!(
             n_shares >= market.amm.order_step_size,
             ErrorCode::NewLPSizeTooSmall,
@@ -1697,6 +2392,7 @@ pub fn handle_add_perp_lp_shares<'info>(
         &spot_market_map,
         &mut oracle_map,
         true,
+        &[MarketIdentifier::perp(market_index)],
     )?;
 
     user.update_last_active_slot(clock.slot);
@@ -1890,6 +2586,107 @@ pub fn handle_update_user_advanced_lp(
     Ok(())
 }
 
+pub fn handle_update_user_stats_self_trade_prevention_mode(
+    ctx: Context<UpdateUserStats>,
+    self_trade_prevention_mode: SelfTradePreventionMode,
+) -> Result<()> {
+    let mut user_stats = load_mut!(ctx.accounts.user_stats)?;
+
+    msg!(
+        "self_trade_prevention_mode: {:?} -> {:?}",
+        user_stats.self_trade_prevention_mode,
+        self_trade_prevention_mode
+    );
+
+    user_stats.self_trade_prevention_mode = self_trade_prevention_mode;
+    Ok(())
+}
+
+pub fn handle_initialize_user_market_maker_protection(
+    ctx: Context<InitializeUserMarketMakerProtection>,
+) -> Result<()> {
+    let user_key = ctx.accounts.user.key();
+    let mut user = load_mut!(ctx.accounts.user)?;
+
+    let mut user_market_maker_protection = ctx
+        .accounts
+        .user_market_maker_protection
+        .load_init()
+        .or(Err(ErrorCode::UnableToLoadAccountLoader))?;
+
+    user_market_maker_protection.user = user_key;
+    user.has_market_maker_protection = true;
+
+    Ok(())
+}
+
+pub fn handle_update_user_market_maker_protection(
+    ctx: Context<UpdateUserMarketMakerProtection>,
+    market_index: u16,
+    window_slots: u16,
+    max_base_asset_amount: u64,
+    max_delta: u64,
+    max_fills: u16,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let mut user_market_maker_protection = load_mut!(ctx.accounts.user_market_maker_protection)?;
+    let market_maker_protection =
+        user_market_maker_protection.force_get_market_mut(market_index)?;
+
+    msg!("market_index: {}", market_index);
+
+    msg!(
+        "window_slots: {} -> {}",
+        market_maker_protection.window_slots,
+        window_slots
+    );
+
+    msg!(
+        "max_base_asset_amount: {} -> {}",
+        market_maker_protection.max_base_asset_amount,
+        max_base_asset_amount
+    );
+
+    msg!(
+        "max_delta: {} -> {}",
+        market_maker_protection.max_delta,
+        max_delta
+    );
+
+    msg!(
+        "max_fills: {} -> {}",
+        market_maker_protection.max_fills,
+        max_fills
+    );
+
+    market_maker_protection.window_slots = window_slots;
+    market_maker_protection.max_base_asset_amount = max_base_asset_amount;
+    market_maker_protection.max_delta = max_delta;
+    market_maker_protection.max_fills = max_fills;
+    market_maker_protection.reset_window(clock.slot);
+
+    // window_slots == 0 frees the entry for another market, but only once it's no longer triggered
+    if window_slots == 0 {
+        market_maker_protection.triggered = false;
+    }
+
+    Ok(())
+}
+
+pub fn handle_reset_user_market_maker_protection(
+    ctx: Context<UpdateUserMarketMakerProtection>,
+    market_index: u16,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let mut user_market_maker_protection = load_mut!(ctx.accounts.user_market_maker_protection)?;
+    let market_maker_protection = user_market_maker_protection.get_market_mut(market_index)?;
+
+    market_maker_protection.triggered = false;
+    market_maker_protection.reset_window(clock.slot);
+
+    Ok(())
+}
+
 pub fn handle_delete_user(ctx: Context<DeleteUser>) -> Result<()> {
     let user = &load!(ctx.accounts.user)?;
     let user_stats = &mut load_mut!(ctx.accounts.user_stats)?;
@@ -2182,6 +2979,17 @@ pub struct TransferDeposit<'info> {
     pub spot_market_vault: Box<Account<'info, TokenAccount>>,
 }
 
+#[derive(Accounts)]
+pub struct TransferIsolatedPerpPositionDeposit<'info> {
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        mut,
+        constraint = can_sign_for_user(&user, &authority)?
+    )]
+    pub user: AccountLoader<'info, User>,
+    pub authority: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct PlaceOrder<'info> {
     pub state: Box<Account<'info, State>>,
@@ -2193,6 +3001,84 @@ pub struct PlaceOrder<'info> {
     pub authority: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct InitializeRfq<'info> {
+    #[account(
+        init,
+        seeds = [b"rfq", user.key().as_ref()],
+        space = Rfq::SIZE,
+        bump,
+        payer = payer
+    )]
+    pub rfq: AccountLoader<'info, Rfq>,
+    #[account(
+        constraint = can_sign_for_user(&user, &authority)?
+    )]
+    pub user: AccountLoader<'info, User>,
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SubmitRfqQuote<'info> {
+    #[account(mut)]
+    pub rfq: AccountLoader<'info, Rfq>,
+    #[account(
+        constraint = can_sign_for_user(&user, &authority)?
+    )]
+    pub user: AccountLoader<'info, User>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptRfqQuote<'info> {
+    pub state: Box<Account<'info, State>>,
+    #[account(
+        mut,
+        has_one = user,
+        close = authority
+    )]
+    pub rfq: AccountLoader<'info, Rfq>,
+    #[account(
+        mut,
+        constraint = can_sign_for_user(&user, &authority)?
+    )]
+    pub user: AccountLoader<'info, User>,
+    #[account(
+        mut,
+        constraint = is_stats_for_user(&user, &user_stats)?
+    )]
+    pub user_stats: AccountLoader<'info, UserStats>,
+    #[account(mut)]
+    pub maker: AccountLoader<'info, User>,
+    #[account(
+        mut,
+        constraint = is_stats_for_user(&maker, &maker_stats)?
+    )]
+    pub maker_stats: AccountLoader<'info, UserStats>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelRfq<'info> {
+    #[account(
+        mut,
+        has_one = user,
+        close = authority
+    )]
+    pub rfq: AccountLoader<'info, Rfq>,
+    #[account(
+        constraint = can_sign_for_user(&user, &authority)?
+    )]
+    pub user: AccountLoader<'info, User>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct CancelOrder<'info> {
     pub state: Box<Account<'info, State>>,
@@ -2275,6 +3161,52 @@ pub struct UpdateUser<'info> {
     pub authority: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct UpdateUserStats<'info> {
+    #[account(
+        mut,
+        has_one = authority
+    )]
+    pub user_stats: AccountLoader<'info, UserStats>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeUserMarketMakerProtection<'info> {
+    #[account(
+        init,
+        seeds = [b"user_market_maker_protection", user.key().as_ref()],
+        space = UserMarketMakerProtection::SIZE,
+        bump,
+        payer = payer
+    )]
+    pub user_market_maker_protection: AccountLoader<'info, UserMarketMakerProtection>,
+    #[account(
+        mut,
+        constraint = can_sign_for_user(&user, &authority)?
+    )]
+    pub user: AccountLoader<'info, User>,
+    pub authority: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateUserMarketMakerProtection<'info> {
+    #[account(
+        mut,
+        has_one = user
+    )]
+    pub user_market_maker_protection: AccountLoader<'info, UserMarketMakerProtection>,
+    #[account(
+        constraint = can_sign_for_user(&user, &authority)?
+    )]
+    pub user: AccountLoader<'info, User>,
+    pub authority: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct DeleteUser<'info> {
     #[account(
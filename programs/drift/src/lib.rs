@@ -12,14 +12,20 @@ use state::oracle::OracleSource;
 
 use crate::controller::position::PositionDirection;
 use crate::state::oracle::PrelaunchOracleParams;
-use crate::state::order_params::{ModifyOrderParams, OrderParams};
-use crate::state::perp_market::{ContractTier, MarketStatus};
+use crate::state::order_params::{
+    ComboOrderParams, ModifyOrderParams, ModifyOrdersParams, OrderParams, QuoteSetParams,
+    SignedOrderParams,
+};
+use crate::state::perp_market::{
+    ContractTier, ContractType, MarketStatus, MatchingPolicy, OptionType, PredictionMarketOutcome,
+};
+use crate::state::rfq::RfqParams;
 use crate::state::settle_pnl_mode::SettlePnlMode;
 use crate::state::spot_market::AssetTier;
 use crate::state::spot_market::SpotFulfillmentConfigStatus;
 use crate::state::state::FeeStructure;
 use crate::state::state::*;
-use crate::state::user::MarketType;
+use crate::state::user::{AuctionCurve, MarketType, SelfTradePreventionMode};
 
 pub mod controller;
 pub mod error;
@@ -90,6 +96,20 @@ pub mod drift {
         handle_transfer_deposit(ctx, market_index, amount)
     }
 
+    pub fn transfer_isolated_perp_position_deposit(
+        ctx: Context<TransferIsolatedPerpPositionDeposit>,
+        spot_market_index: u16,
+        perp_market_index: u16,
+        amount: i64,
+    ) -> Result<()> {
+        handle_transfer_isolated_perp_position_deposit(
+            ctx,
+            spot_market_index,
+            perp_market_index,
+            amount,
+        )
+    }
+
     pub fn place_perp_order(ctx: Context<PlaceOrder>, params: OrderParams) -> Result<()> {
         handle_place_perp_order(ctx, params)
     }
@@ -115,6 +135,13 @@ pub mod drift {
         handle_cancel_orders_by_ids(ctx, order_ids)
     }
 
+    pub fn update_user_cancel_orders_after(
+        ctx: Context<CancelOrder>,
+        cancel_after_secs: u32,
+    ) -> Result<()> {
+        handle_update_user_cancel_orders_after(ctx, cancel_after_secs)
+    }
+
     pub fn modify_order(
         ctx: Context<CancelOrder>,
         order_id: Option<u32>,
@@ -131,6 +158,10 @@ pub mod drift {
         handle_modify_order_by_user_order_id(ctx, user_order_id, modify_order_params)
     }
 
+    pub fn modify_orders(ctx: Context<CancelOrder>, params: Vec<ModifyOrdersParams>) -> Result<()> {
+        handle_modify_orders(ctx, params)
+    }
+
     pub fn place_and_take_perp_order(
         ctx: Context<PlaceAndTake>,
         params: OrderParams,
@@ -165,6 +196,13 @@ pub mod drift {
         )
     }
 
+    pub fn place_and_take_combo_order(
+        ctx: Context<PlaceAndTake>,
+        params: ComboOrderParams,
+    ) -> Result<()> {
+        handle_place_and_take_combo_order(ctx, params)
+    }
+
     pub fn place_and_make_spot_order(
         ctx: Context<PlaceAndMake>,
         params: OrderParams,
@@ -183,6 +221,41 @@ pub mod drift {
         handle_place_orders(ctx, params)
     }
 
+    pub fn cancel_and_place_orders(
+        ctx: Context<PlaceOrder>,
+        market_type: Option<MarketType>,
+        market_index: Option<u16>,
+        direction: Option<PositionDirection>,
+        order_ids: Vec<u32>,
+        params: Vec<OrderParams>,
+    ) -> Result<()> {
+        handle_cancel_and_place_orders(ctx, market_type, market_index, direction, order_ids, params)
+    }
+
+    pub fn place_quote_set(ctx: Context<PlaceOrder>, params: QuoteSetParams) -> Result<()> {
+        handle_place_quote_set(ctx, params)
+    }
+
+    pub fn initialize_rfq(ctx: Context<InitializeRfq>, params: RfqParams) -> Result<()> {
+        handle_initialize_rfq(ctx, params)
+    }
+
+    pub fn submit_rfq_quote(
+        ctx: Context<SubmitRfqQuote>,
+        price: u64,
+        expiry_ts: i64,
+    ) -> Result<()> {
+        handle_submit_rfq_quote(ctx, price, expiry_ts)
+    }
+
+    pub fn accept_rfq_quote(ctx: Context<AcceptRfqQuote>, expected_price: u64) -> Result<()> {
+        handle_accept_rfq_quote(ctx, expected_price)
+    }
+
+    pub fn cancel_rfq(ctx: Context<CancelRfq>) -> Result<()> {
+        handle_cancel_rfq(ctx)
+    }
+
     pub fn begin_swap(
         ctx: Context<Swap>,
         in_market_index: u16,
@@ -280,6 +353,44 @@ pub mod drift {
         handle_update_user_advanced_lp(ctx, _sub_account_id, advanced_lp)
     }
 
+    pub fn update_user_stats_self_trade_prevention_mode(
+        ctx: Context<UpdateUserStats>,
+        self_trade_prevention_mode: SelfTradePreventionMode,
+    ) -> Result<()> {
+        handle_update_user_stats_self_trade_prevention_mode(ctx, self_trade_prevention_mode)
+    }
+
+    pub fn initialize_user_market_maker_protection(
+        ctx: Context<InitializeUserMarketMakerProtection>,
+    ) -> Result<()> {
+        handle_initialize_user_market_maker_protection(ctx)
+    }
+
+    pub fn update_user_market_maker_protection(
+        ctx: Context<UpdateUserMarketMakerProtection>,
+        market_index: u16,
+        window_slots: u16,
+        max_base_asset_amount: u64,
+        max_delta: u64,
+        max_fills: u16,
+    ) -> Result<()> {
+        handle_update_user_market_maker_protection(
+            ctx,
+            market_index,
+            window_slots,
+            max_base_asset_amount,
+            max_delta,
+            max_fills,
+        )
+    }
+
+    pub fn reset_user_market_maker_protection(
+        ctx: Context<UpdateUserMarketMakerProtection>,
+        market_index: u16,
+    ) -> Result<()> {
+        handle_reset_user_market_maker_protection(ctx, market_index)
+    }
+
     pub fn delete_user(ctx: Context<DeleteUser>) -> Result<()> {
         handle_delete_user(ctx)
     }
@@ -298,6 +409,20 @@ pub mod drift {
         handle_fill_perp_order(ctx, order_id)
     }
 
+    pub fn place_and_match_signed_order(
+        ctx: Context<PlaceAndMatchSignedOrder>,
+        signed_order_params: SignedOrderParams,
+    ) -> Result<()> {
+        handle_place_and_match_signed_order(ctx, signed_order_params)
+    }
+
+    pub fn clear_perp_batch_auction(
+        ctx: Context<ClearPerpBatchAuction>,
+        market_index: u16,
+    ) -> Result<()> {
+        handle_clear_perp_batch_auction(ctx, market_index)
+    }
+
     pub fn revert_fill(ctx: Context<RevertFill>) -> Result<()> {
         handle_revert_fill(ctx)
     }
@@ -319,6 +444,10 @@ pub mod drift {
         handle_force_cancel_orders(ctx)
     }
 
+    pub fn cancel_orders_after_deadline(ctx: Context<ForceCancelOrder>) -> Result<()> {
+        handle_cancel_orders_after_deadline(ctx)
+    }
+
     pub fn update_user_idle(ctx: Context<UpdateUserIdle>) -> Result<()> {
         handle_update_user_idle(ctx)
     }
@@ -1173,6 +1302,50 @@ pub mod drift {
         handle_update_perp_market_fee_adjustment(ctx, fee_adjustment)
     }
 
+    pub fn update_perp_market_default_auction_curve(
+        ctx: Context<AdminUpdatePerpMarket>,
+        default_auction_curve: AuctionCurve,
+    ) -> Result<()> {
+        handle_update_perp_market_default_auction_curve(ctx, default_auction_curve)
+    }
+
+    pub fn update_perp_market_matching_policy(
+        ctx: Context<AdminUpdatePerpMarket>,
+        matching_policy: MatchingPolicy,
+        top_of_book_allocation: u8,
+    ) -> Result<()> {
+        handle_update_perp_market_matching_policy(ctx, matching_policy, top_of_book_allocation)
+    }
+
+    pub fn update_perp_market_contract_type(
+        ctx: Context<AdminUpdatePerpMarket>,
+        contract_type: ContractType,
+    ) -> Result<()> {
+        handle_update_perp_market_contract_type(ctx, contract_type)
+    }
+
+    pub fn update_perp_market_option_params(
+        ctx: Context<AdminUpdatePerpMarket>,
+        option_type: OptionType,
+        strike_price: u64,
+        expiry_ts: i64,
+    ) -> Result<()> {
+        handle_update_perp_market_option_params(ctx, option_type, strike_price, expiry_ts)
+    }
+
+    pub fn update_perp_market_quote_spot_market(
+        ctx: Context<AdminUpdatePerpMarketQuoteSpotMarket>,
+    ) -> Result<()> {
+        handle_update_perp_market_quote_spot_market(ctx)
+    }
+
+    pub fn update_perp_market_batch_auction_duration(
+        ctx: Context<AdminUpdatePerpMarket>,
+        batch_auction_duration: u8,
+    ) -> Result<()> {
+        handle_update_perp_market_batch_auction_duration(ctx, batch_auction_duration)
+    }
+
     pub fn update_spot_market_fee_adjustment(
         ctx: Context<AdminUpdateSpotMarket>,
         fee_adjustment: i16,
@@ -1257,6 +1430,13 @@ pub mod drift {
     ) -> Result<()> {
         handle_delete_prelaunch_oracle(ctx, perp_market_index)
     }
+
+    pub fn resolve_perp_prediction_market(
+        ctx: Context<ResolvePerpPredictionMarket>,
+        outcome: PredictionMarketOutcome,
+    ) -> Result<()> {
+        handle_resolve_perp_prediction_market(ctx, outcome)
+    }
 }
 
 #[cfg(not(feature = "no-entrypoint"))]
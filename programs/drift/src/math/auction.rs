@@ -1,11 +1,11 @@
 use crate::controller::position::PositionDirection;
 use crate::error::{DriftResult, ErrorCode};
 use crate::math::casting::Cast;
-use crate::math::constants::AUCTION_DERIVE_PRICE_FRACTION;
+use crate::math::constants::{AUCTION_DERIVE_PRICE_FRACTION, PERCENTAGE_PRECISION_U64};
 use crate::math::orders::standardize_price;
 use crate::math::safe_math::SafeMath;
 use crate::state::oracle::OraclePriceData;
-use crate::state::user::{Order, OrderType};
+use crate::state::user::{AuctionCurve, Order, OrderType};
 use solana_program::msg;
 
 use crate::state::perp_market::PerpMarket;
@@ -90,9 +90,8 @@ pub fn calculate_auction_price(
         OrderType::Market
         | OrderType::TriggerMarket
         | OrderType::Limit
-        | OrderType::TriggerLimit => {
-            calculate_auction_price_for_fixed_auction(order, slot, tick_size)
-        }
+        | OrderType::TriggerLimit
+        | OrderType::Twap => calculate_auction_price_for_fixed_auction(order, slot, tick_size),
         OrderType::Oracle => calculate_auction_price_for_oracle_offset_auction(
             order,
             slot,
@@ -109,8 +108,8 @@ fn calculate_auction_price_for_fixed_auction(
 ) -> DriftResult<u64> {
     let slots_elapsed = slot.safe_sub(order.slot)?;
 
-    let delta_numerator = min(slots_elapsed, order.auction_duration.cast()?);
-    let delta_denominator = order.auction_duration;
+    let (delta_numerator, delta_denominator) =
+        calculate_auction_progress(order.auction_curve(), slots_elapsed, order.auction_duration)?;
 
     let auction_start_price = order.auction_start_price.cast::<u64>()?;
     let auction_end_price = order.auction_end_price.cast::<u64>()?;
@@ -151,8 +150,8 @@ fn calculate_auction_price_for_oracle_offset_auction(
 
     let slots_elapsed = slot.safe_sub(order.slot)?;
 
-    let delta_numerator = min(slots_elapsed, order.auction_duration.cast()?);
-    let delta_denominator = order.auction_duration;
+    let (delta_numerator, delta_denominator) =
+        calculate_auction_progress(order.auction_curve(), slots_elapsed, order.auction_duration)?;
 
     let auction_start_price_offset = order.auction_start_price;
     let auction_end_price_offset = order.auction_end_price;
@@ -193,6 +192,46 @@ fn calculate_auction_price_for_oracle_offset_auction(
     Ok(price)
 }
 
+/// Returns how far through the auction price range the order is as (numerator, denominator)
+/// Progress is towards the auction end price, the worst price for the taker. See AuctionCurve
+pub fn calculate_auction_progress(
+    auction_curve: AuctionCurve,
+    slots_elapsed: u64,
+    auction_duration: u8,
+) -> DriftResult<(u64, u64)> {
+    let auction_duration = auction_duration.cast::<u64>()?;
+    let slots_elapsed = min(slots_elapsed, auction_duration);
+
+    if slots_elapsed == auction_duration {
+        return Ok((slots_elapsed, auction_duration));
+    }
+
+    let progress = match auction_curve {
+        AuctionCurve::Linear => return Ok((slots_elapsed, auction_duration)),
+        AuctionCurve::ExponentialDecay => {
+            let halvings = slots_elapsed.safe_mul(8)?.safe_div(auction_duration)?;
+            PERCENTAGE_PRECISION_U64.safe_sub(PERCENTAGE_PRECISION_U64 >> halvings)?
+        }
+        AuctionCurve::Step => {
+            let steps = slots_elapsed.safe_mul(4)?.safe_div(auction_duration)?;
+            PERCENTAGE_PRECISION_U64.safe_mul(steps)?.safe_div(4)?
+        }
+        AuctionCurve::FrontLoaded => {
+            // 1 - (1 - t)^2
+            let remaining = PERCENTAGE_PRECISION_U64
+                .safe_mul(auction_duration.safe_sub(slots_elapsed)?)?
+                .safe_div(auction_duration)?;
+            PERCENTAGE_PRECISION_U64.safe_sub(
+                remaining
+                    .safe_mul(remaining)?
+                    .safe_div(PERCENTAGE_PRECISION_U64)?,
+            )?
+        }
+    };
+
+    Ok((progress, PERCENTAGE_PRECISION_U64))
+}
+
 pub fn is_auction_complete(order_slot: u64, auction_duration: u8, slot: u64) -> DriftResult<bool> {
     if auction_duration == 0 {
         return Ok(true);
@@ -468,3 +468,86 @@ mod calculate_auction_params_for_trigger_order {
         assert_eq!(auction_end_price, 99500000);
     }
 }
+
+mod auction_curve {
+    use crate::math::auction::calculate_auction_price;
+    use crate::math::constants::{PRICE_PRECISION_I64, PRICE_PRECISION_U64};
+    use crate::state::user::{AuctionCurve, Order, OrderType};
+    use crate::PositionDirection;
+
+    fn get_price(auction_curve: AuctionCurve, slot: u64) -> u64 {
+        let mut order = Order {
+            order_type: OrderType::Market,
+            direction: PositionDirection::Long,
+            auction_duration: 8,
+            slot: 0,
+            auction_start_price: 100 * PRICE_PRECISION_I64,
+            auction_end_price: 101 * PRICE_PRECISION_I64,
+            ..Order::default()
+        };
+        order.set_auction_curve(auction_curve);
+        assert_eq!(order.auction_curve(), auction_curve);
+
+        calculate_auction_price(&order, slot, 1, None).unwrap()
+    }
+
+    #[test]
+    fn linear() {
+        assert_eq!(
+            get_price(AuctionCurve::Linear, 0),
+            100 * PRICE_PRECISION_U64
+        );
+        assert_eq!(get_price(AuctionCurve::Linear, 4), 100_500_000);
+        assert_eq!(
+            get_price(AuctionCurve::Linear, 8),
+            101 * PRICE_PRECISION_U64
+        );
+    }
+
+    #[test]
+    fn exponential_decay() {
+        assert_eq!(
+            get_price(AuctionCurve::ExponentialDecay, 0),
+            100 * PRICE_PRECISION_U64
+        );
+        assert_eq!(get_price(AuctionCurve::ExponentialDecay, 1), 100_500_000);
+        assert_eq!(get_price(AuctionCurve::ExponentialDecay, 4), 100_937_500);
+        assert_eq!(
+            get_price(AuctionCurve::ExponentialDecay, 8),
+            101 * PRICE_PRECISION_U64
+        );
+    }
+
+    #[test]
+    fn step() {
+        assert_eq!(get_price(AuctionCurve::Step, 1), 100 * PRICE_PRECISION_U64);
+        assert_eq!(get_price(AuctionCurve::Step, 2), 100_250_000);
+        assert_eq!(get_price(AuctionCurve::Step, 7), 100_750_000);
+        assert_eq!(get_price(AuctionCurve::Step, 8), 101 * PRICE_PRECISION_U64);
+    }
+
+    #[test]
+    fn front_loaded() {
+        assert_eq!(
+            get_price(AuctionCurve::FrontLoaded, 0),
+            100 * PRICE_PRECISION_U64
+        );
+        assert_eq!(get_price(AuctionCurve::FrontLoaded, 4), 100_750_000);
+        assert_eq!(
+            get_price(AuctionCurve::FrontLoaded, 8),
+            101 * PRICE_PRECISION_U64
+        );
+    }
+
+    #[test]
+    fn direction_relative_to_linear() {
+        for slot in 0..=8 {
+            let linear_price = get_price(AuctionCurve::Linear, slot);
+
+            // the end price is the worst price for a long taker
+            assert!(get_price(AuctionCurve::ExponentialDecay, slot) >= linear_price);
+            assert!(get_price(AuctionCurve::FrontLoaded, slot) >= linear_price);
+            assert!(get_price(AuctionCurve::Step, slot) <= linear_price);
+        }
+    }
+}
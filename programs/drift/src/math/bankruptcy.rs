@@ -10,6 +10,10 @@ pub fn is_user_bankrupt(user: &User) -> bool {
     let mut has_liability = false;
 
     for spot_position in user.spot_positions.iter() {
+        if spot_position.is_isolated_perp_collateral {
+            continue;
+        }
+
         if spot_position.scaled_balance > 0 {
             match spot_position.balance_type {
                 SpotBalanceType::Deposit => return false,
@@ -19,6 +23,11 @@ pub fn is_user_bankrupt(user: &User) -> bool {
     }
 
     for perp_position in user.perp_positions.iter() {
+        // isolated perp position goes bankrupt on its own, see is_isolated_perp_position_bankrupt
+        if user.is_isolated_perp_market(perp_position.market_index) {
+            continue;
+        }
+
         if perp_position.base_asset_amount != 0
             || perp_position.quote_asset_amount > 0
             || perp_position.has_open_order()
@@ -34,3 +43,22 @@ pub fn is_user_bankrupt(user: &User) -> bool {
 
     has_liability
 }
+
+pub fn is_isolated_perp_position_bankrupt(user: &User, market_index: u16) -> bool {
+    // isolated perp position is bankrupt iff its collateral is gone, it has no exposure and negative pnl
+
+    match user.get_isolated_perp_position_balance(market_index) {
+        Ok(collateral) if collateral.scaled_balance == 0 => {}
+        _ => return false,
+    }
+
+    match user.get_perp_position(market_index) {
+        Ok(perp_position) => {
+            perp_position.base_asset_amount == 0
+                && !perp_position.has_open_order()
+                && !perp_position.is_lp()
+                && perp_position.quote_asset_amount < 0
+        }
+        Err(_) => false,
+    }
+}
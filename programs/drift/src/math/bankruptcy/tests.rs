@@ -1,4 +1,4 @@
-use crate::math::bankruptcy::is_user_bankrupt;
+use crate::math::bankruptcy::{is_isolated_perp_position_bankrupt, is_user_bankrupt};
 use crate::state::spot_market::SpotBalanceType;
 use crate::state::user::{PerpPosition, SpotPosition, User};
 use crate::test_utils::{get_positions, get_spot_positions};
@@ -81,3 +81,50 @@ fn user_with_empty_position_and_balances() {
     let is_bankrupt = is_user_bankrupt(&user);
     assert!(!is_bankrupt);
 }
+
+#[test]
+fn user_with_isolated_perp_position_loss() {
+    let mut spot_positions = [SpotPosition::default(); 8];
+    spot_positions[1] = SpotPosition {
+        market_index: 0,
+        balance_type: SpotBalanceType::Deposit,
+        isolated_perp_market_index: 1,
+        is_isolated_perp_collateral: true,
+        ..SpotPosition::default()
+    };
+
+    let user = User {
+        perp_positions: get_positions(PerpPosition {
+            market_index: 1,
+            quote_asset_amount: -1,
+            ..PerpPosition::default()
+        }),
+        spot_positions,
+        ..User::default()
+    };
+
+    // rest of account isn't bankrupt because of isolated position
+    let is_bankrupt = is_user_bankrupt(&user);
+    assert!(!is_bankrupt);
+
+    let is_bankrupt = is_isolated_perp_position_bankrupt(&user, 1);
+    assert!(is_bankrupt);
+
+    // remaining isolated collateral must be used first
+    let mut user = user;
+    user.spot_positions[1].scaled_balance = 1;
+
+    let is_bankrupt = is_isolated_perp_position_bankrupt(&user, 1);
+    assert!(!is_bankrupt);
+
+    // isolated collateral doesnt back the cross margin account
+    user.spot_positions[0] = SpotPosition {
+        market_index: 0,
+        balance_type: SpotBalanceType::Borrow,
+        scaled_balance: 1,
+        ..SpotPosition::default()
+    };
+
+    let is_bankrupt = is_user_bankrupt(&user);
+    assert!(is_bankrupt);
+}
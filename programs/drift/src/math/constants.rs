@@ -159,6 +159,8 @@ pub const FEE_ADJUSTMENT_MAX: u64 = 100;
 
 // PRICE AMOUNTS
 pub const HUNDRENTH_OF_CENT: u128 = PRICE_PRECISION / 10_000; //.0001
+pub const MAX_PREDICTION_MARKET_PRICE: u64 = PRICE_PRECISION_U64; // 1
+pub const MAX_PREDICTION_MARKET_PRICE_I64: i64 = PRICE_PRECISION_I64; // 1
 
 // CONSTRAINTS
 pub const MAX_K_BPS_INCREASE: i128 = TEN_BPS;
@@ -198,6 +200,7 @@ pub const FUNDING_RATE_OFFSET_DENOMINATOR: i64 = 5000; // 5000 => 7.3% annualize
 
 // ORDERS
 pub const AUCTION_DERIVE_PRICE_FRACTION: i64 = 200;
+pub const TRAILING_STOP_MIN_PAID_UPDATE: u64 = PERCENTAGE_PRECISION_U64 / 1000; // 10 bps
 
 // WITHDRAWS
 pub const SPOT_MARKET_TOKEN_TWAP_WINDOW: i64 = TWENTY_FOUR_HOUR;
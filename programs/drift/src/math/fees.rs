@@ -327,6 +327,19 @@ pub fn calculate_fee_for_fulfillment_with_match(
     })
 }
 
+/// Fee for the resting side of a fill that was itself a taker, e.g. two takers crossed in a batch
+/// auction. Neither side provided liquidity, so it pays the taker fee for its tier instead of the rebate
+pub fn calculate_taker_fee_for_maker(
+    maker_stats: &UserStats,
+    quote_asset_amount: u64,
+    fee_structure: &FeeStructure,
+    market_type: &MarketType,
+    fee_adjustment: i16,
+) -> DriftResult<u64> {
+    let fee_tier = determine_user_fee_tier(maker_stats, fee_structure, market_type)?;
+    calculate_taker_fee(quote_asset_amount, fee_tier, fee_adjustment)
+}
+
 pub struct ExternalFillFees {
     pub user_fee: u64,
     pub fee_to_market: u64,
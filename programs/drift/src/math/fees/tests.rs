@@ -663,3 +663,24 @@ mod calculate_fee_for_fulfillment_with_serum {
         assert_eq!(filler_reward, 2000);
     }
 }
+
+mod calculate_taker_fee_for_maker {
+    use crate::math::constants::QUOTE_PRECISION_U64;
+    use crate::math::fees::calculate_taker_fee_for_maker;
+    use crate::state::state::FeeStructure;
+    use crate::state::user::{MarketType, UserStats};
+
+    #[test]
+    fn pays_taker_fee_instead_of_rebate() {
+        let fee = calculate_taker_fee_for_maker(
+            &UserStats::default(),
+            100 * QUOTE_PRECISION_U64,
+            &FeeStructure::test_default(),
+            &MarketType::Perp,
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(fee, 100000);
+    }
+}
@@ -160,6 +160,20 @@ pub fn calculate_funding_payment(
     .cast()
 }
 
+/// Funding payment owed to/by a position using the cumulative rate for the position's side
+pub fn calculate_perp_funding_payment(
+    market: &PerpMarket,
+    market_position: &PerpPosition,
+) -> DriftResult<i64> {
+    let amm_cumulative_funding_rate = if market_position.base_asset_amount > 0 {
+        market.amm.cumulative_funding_rate_long
+    } else {
+        market.amm.cumulative_funding_rate_short
+    };
+
+    calculate_funding_payment(amm_cumulative_funding_rate, market_position)
+}
+
 fn _calculate_funding_payment(
     funding_rate_delta: i128,
     base_asset_amount: i128,
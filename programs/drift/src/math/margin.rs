@@ -1,8 +1,8 @@
 use crate::error::DriftResult;
 use crate::error::ErrorCode;
 use crate::math::constants::{
-    MARGIN_PRECISION_U128, MAX_POSITIVE_UPNL_FOR_INITIAL_MARGIN, PRICE_PRECISION,
-    SPOT_IMF_PRECISION_U128, SPOT_WEIGHT_PRECISION, SPOT_WEIGHT_PRECISION_U128,
+    MARGIN_PRECISION_U128, MAX_POSITIVE_UPNL_FOR_INITIAL_MARGIN, MAX_PREDICTION_MARKET_PRICE_I64,
+    PRICE_PRECISION, SPOT_IMF_PRECISION_U128, SPOT_WEIGHT_PRECISION, SPOT_WEIGHT_PRECISION_U128,
 };
 use crate::math::position::{
     calculate_base_asset_value_and_pnl_with_oracle_price,
@@ -13,7 +13,8 @@ use crate::{validate, PRICE_PRECISION_I128};
 use crate::{validation, PRICE_PRECISION_I64};
 
 use crate::math::casting::Cast;
-use crate::math::funding::calculate_funding_payment;
+use crate::math::funding::calculate_perp_funding_payment;
+use crate::math::options::calculate_short_option_margin_price;
 use crate::math::oracle::{is_oracle_valid_for_action, DriftAction};
 
 use crate::math::spot_balance::{get_strict_token_value, get_token_value};
@@ -105,21 +106,19 @@ pub fn calculate_perp_position_value_and_pnl(
     user_custom_margin_ratio: u32,
     track_open_order_fraction: bool,
 ) -> DriftResult<(u128, i128, u128, u128)> {
-    let valuation_price = if market.status == MarketStatus::Settlement {
-        market.expiry_price
+    let valuation_price = market.get_valuation_price(oracle_price_data.price)?;
+
+    // converted quote amounts are already usd and only become quote spot market tokens when settled
+    let converted_quote_price =
+        StrictOraclePrice::new(PRICE_PRECISION_I64, PRICE_PRECISION_I64, false);
+    let strict_quote_price = if market.is_quote_converted() {
+        &converted_quote_price
     } else {
-        oracle_price_data.price
+        strict_quote_price
     };
 
     // the funding must be calculated before calculated the unrealized pnl w simulated lp position
-    let unrealized_funding = calculate_funding_payment(
-        if market_position.base_asset_amount > 0 {
-            market.amm.cumulative_funding_rate_long
-        } else {
-            market.amm.cumulative_funding_rate_short
-        },
-        market_position,
-    )?;
+    let unrealized_funding = calculate_perp_funding_payment(market, market_position)?;
 
     let market_position = market_position.simulate_settled_lp_position(market, valuation_price)?;
 
@@ -147,6 +146,36 @@ pub fn calculate_perp_position_value_and_pnl(
 
     let mut margin_requirement = if market.status == MarketStatus::Settlement {
         0
+    } else if market.is_option() {
+        // long options are margined by the premium already paid, which is counted in the pnl
+        if worst_case_base_asset_amount >= 0 {
+            0
+        } else {
+            let short_option_margin_price = calculate_short_option_margin_price(
+                market.option_type,
+                market.strike_price,
+                oracle_price_data.price,
+                margin_ratio,
+            )?;
+
+            calculate_base_asset_value_with_oracle_price(
+                worst_case_base_asset_amount,
+                short_option_margin_price.cast()?,
+            )?
+            .safe_mul(strict_quote_price.max().cast()?)?
+            .safe_div(PRICE_PRECISION)?
+        }
+    } else if market.is_prediction_market() {
+        // prediction markets are margined by their max loss, the price for longs and 1 - price for shorts
+        let max_loss_price = if worst_case_base_asset_amount >= 0 {
+            valuation_price
+        } else {
+            MAX_PREDICTION_MARKET_PRICE_I64.safe_sub(valuation_price)?
+        };
+
+        calculate_base_asset_value_with_oracle_price(worst_case_base_asset_amount, max_loss_price)?
+            .safe_mul(strict_quote_price.max().cast()?)?
+            .safe_div(PRICE_PRECISION)?
     } else {
         worse_case_base_asset_value
             .safe_mul(margin_ratio.cast()?)?
@@ -254,6 +283,12 @@ pub fn calculate_margin_requirement_and_total_collateral_and_liability_info(
     for spot_position in user.spot_positions.iter() {
         validation::position::validate_spot_position(spot_position)?;
 
+        // isolated collateral is counted with its perp position below
+        if spot_position.is_isolated_perp_collateral {
+            calculation.add_isolated_perp_market(spot_position.isolated_perp_market_index)?;
+            continue;
+        }
+
         if spot_position.is_available() {
             continue;
         }
@@ -465,6 +500,20 @@ pub fn calculate_margin_requirement_and_total_collateral_and_liability_info(
                 .last_oracle_price_twap_5min,
             calculation.context.strict,
         );
+
+        let is_isolated_position = user.is_isolated_perp_market(market_position.market_index);
+        let isolated_collateral_value = if is_isolated_position {
+            let token_amount = user
+                .get_isolated_perp_position_balance(market.market_index)?
+                .get_signed_token_amount(&quote_spot_market)?;
+            get_strict_token_value(
+                token_amount,
+                quote_spot_market.decimals,
+                &strict_quote_price,
+            )?
+        } else {
+            0
+        };
         drop(quote_spot_market);
 
         let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
@@ -490,6 +539,25 @@ pub fn calculate_margin_requirement_and_total_collateral_and_liability_info(
             calculation.track_open_orders_fraction(),
         )?;
 
+        if is_isolated_position {
+            calculation.add_isolated_margin_requirement(
+                market.market_index,
+                perp_margin_requirement,
+                worst_case_base_asset_value,
+            )?;
+            calculation.add_isolated_total_collateral(
+                market.market_index,
+                weighted_pnl.safe_add(isolated_collateral_value)?,
+            )?;
+
+            calculation.update_all_oracles_valid(is_oracle_valid_for_action(
+                oracle_validity,
+                Some(DriftAction::MarginCalc),
+            )?);
+
+            continue;
+        }
+
         calculation.add_margin_requirement(
             perp_margin_requirement,
             worst_case_base_asset_value,
@@ -599,8 +667,9 @@ pub fn meets_withdraw_margin_requirement(
 
     validate_any_isolated_tier_requirements(user, calculation)?;
 
+    // withdrawals only draw on cross margin collateral, the isolated perp position has its own
     validate!(
-        calculation.meets_margin_requirement(),
+        calculation.meets_cross_margin_requirement(),
         ErrorCode::InsufficientCollateral,
         "User attempting to withdraw where total_collateral {} is below initial_margin_requirement {}",
         calculation.total_collateral,
@@ -616,6 +685,7 @@ pub fn meets_place_order_margin_requirement(
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
     risk_increasing: bool,
+    markets: &[MarketIdentifier],
 ) -> DriftResult {
     let margin_type = if risk_increasing {
         MarginRequirementType::Initial
@@ -632,13 +702,28 @@ pub fn meets_place_order_margin_requirement(
         context,
     )?;
 
-    if !calculation.meets_margin_requirement() {
-        msg!(
-            "total_collateral={}, margin_requirement={} margin type = {:?}",
-            calculation.total_collateral,
-            calculation.margin_requirement,
-            margin_type
-        );
+    // only the collateral backing the markets the orders are in is checked
+    for market in markets {
+        if calculation.meets_market_margin_requirement(*market) {
+            continue;
+        }
+
+        match calculation.get_isolated_margin_calculation(market.market_index) {
+            Some(isolated) if market.market_type == MarketType::Perp => msg!(
+                "isolated perp market {} total_collateral={}, margin_requirement={} margin type = {:?}",
+                isolated.market_index,
+                isolated.total_collateral,
+                isolated.margin_requirement,
+                margin_type
+            ),
+            _ => msg!(
+                "total_collateral={}, margin_requirement={} margin type = {:?}",
+                calculation.total_collateral,
+                calculation.margin_requirement,
+                margin_type
+            ),
+        }
+
         return Err(ErrorCode::InsufficientCollateral);
     }
 
@@ -652,6 +737,7 @@ pub fn meets_initial_margin_requirement(
     perp_market_map: &PerpMarketMap,
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
+    market: MarketIdentifier,
 ) -> DriftResult<bool> {
     calculate_margin_requirement_and_total_collateral_and_liability_info(
         user,
@@ -660,9 +746,10 @@ pub fn meets_initial_margin_requirement(
         oracle_map,
         MarginContext::standard(MarginRequirementType::Initial),
     )
-    .map(|calc| calc.meets_margin_requirement())
+    .map(|calc| calc.meets_market_margin_requirement(market))
 }
 
+/// Isolated perp positions settle against their own collateral, so only the cross margin account is checked
 pub fn meets_settle_pnl_maintenance_margin_requirement(
     user: &User,
     perp_market_map: &PerpMarketMap,
@@ -676,7 +763,7 @@ pub fn meets_settle_pnl_maintenance_margin_requirement(
         oracle_map,
         MarginContext::standard(MarginRequirementType::Maintenance).strict(true),
     )
-    .map(|calc| calc.meets_margin_requirement())
+    .map(|calc| calc.meets_cross_margin_requirement())
 }
 
 pub fn meets_maintenance_margin_requirement(
@@ -684,6 +771,7 @@ pub fn meets_maintenance_margin_requirement(
     perp_market_map: &PerpMarketMap,
     spot_market_map: &SpotMarketMap,
     oracle_map: &mut OracleMap,
+    market: MarketIdentifier,
 ) -> DriftResult<bool> {
     calculate_margin_requirement_and_total_collateral_and_liability_info(
         user,
@@ -692,7 +780,7 @@ pub fn meets_maintenance_margin_requirement(
         oracle_map,
         MarginContext::standard(MarginRequirementType::Maintenance),
     )
-    .map(|calc| calc.meets_margin_requirement())
+    .map(|calc| calc.meets_market_margin_requirement(market))
 }
 
 pub fn calculate_max_withdrawable_amount(
@@ -870,7 +958,7 @@ pub fn calculate_user_equity(
             all_oracles_valid &=
                 is_oracle_valid_for_action(quote_oracle_validity, Some(DriftAction::MarginCalc))?;
 
-            quote_oracle_price_data.price
+            market.get_quote_asset_price(quote_oracle_price_data.price)
         };
 
         let (oracle_price_data, oracle_validity) = oracle_map.get_price_data_and_validity(
@@ -884,20 +972,9 @@ pub fn calculate_user_equity(
         all_oracles_valid &=
             is_oracle_valid_for_action(oracle_validity, Some(DriftAction::MarginCalc))?;
 
-        let valuation_price = if market.status == MarketStatus::Settlement {
-            market.expiry_price
-        } else {
-            oracle_price_data.price
-        };
+        let valuation_price = market.get_valuation_price(oracle_price_data.price)?;
 
-        let unrealized_funding = calculate_funding_payment(
-            if market_position.base_asset_amount > 0 {
-                market.amm.cumulative_funding_rate_long
-            } else {
-                market.amm.cumulative_funding_rate_short
-            },
-            market_position,
-        )?;
+        let unrealized_funding = calculate_perp_funding_payment(market, market_position)?;
 
         let market_position =
             market_position.simulate_settled_lp_position(market, valuation_price)?;
@@ -1440,7 +1440,7 @@ mod calculate_margin_requirement_and_total_collateral_and_liability_info {
     use crate::math::margin::{
         calculate_margin_requirement_and_total_collateral_and_liability_info, MarginRequirementType,
     };
-    use crate::state::margin_calculation::{MarginCalculation, MarginContext};
+    use crate::state::margin_calculation::{MarginCalculation, MarginContext, MarketIdentifier};
     use crate::state::oracle::{HistoricalOracleData, OracleSource};
     use crate::state::oracle_map::OracleMap;
     use crate::state::perp_market::{ContractTier, MarketStatus, PerpMarket, AMM};
@@ -1452,6 +1452,7 @@ mod calculate_margin_requirement_and_total_collateral_and_liability_info {
     use crate::test_utils::{get_positions, get_pyth_price};
     use crate::{create_account_info, PRICE_PRECISION_I64};
     use crate::{create_anchor_account_info, BASE_PRECISION_I64};
+    use crate::{QUOTE_PRECISION_I128, QUOTE_PRECISION_I64};
 
     #[test]
     fn no_perp_position_but_trigger_order() {
@@ -1721,6 +1722,143 @@ mod calculate_margin_requirement_and_total_collateral_and_liability_info {
         assert_eq!(calculation.with_perp_isolated_liability, true);
     }
 
+    #[test]
+    fn isolated_perp_position() {
+        let slot = 0_u64;
+
+        let mut sol_oracle_price = get_pyth_price(100, 6);
+        let sol_oracle_price_key =
+            Pubkey::from_str("J83w4HKfqxwcq3BEMMkPFSppX3gqekLyLJBexebFVkix").unwrap();
+        let pyth_program = crate::ids::pyth_program::id();
+        create_account_info!(
+            sol_oracle_price,
+            &sol_oracle_price_key,
+            &pyth_program,
+            oracle_account_info
+        );
+        let mut oracle_map = OracleMap::load_one(&oracle_account_info, slot, None).unwrap();
+
+        let mut market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                quote_asset_reserve: 100 * AMM_RESERVE_PRECISION,
+                bid_base_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                bid_quote_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_base_asset_reserve: 99 * AMM_RESERVE_PRECISION,
+                ask_quote_asset_reserve: 101 * AMM_RESERVE_PRECISION,
+                sqrt_k: 100 * AMM_RESERVE_PRECISION,
+                peg_multiplier: 100 * PEG_PRECISION,
+                order_step_size: 10000000,
+                oracle: sol_oracle_price_key,
+                ..AMM::default()
+            },
+            margin_ratio_initial: 1000,
+            margin_ratio_maintenance: 500,
+            status: MarketStatus::Initialized,
+            ..PerpMarket::default()
+        };
+        create_anchor_account_info!(market, PerpMarket, market_account_info);
+        let perp_market_map = PerpMarketMap::load_one(&market_account_info, true).unwrap();
+
+        let mut usdc_spot_market = SpotMarket {
+            market_index: 0,
+            oracle_source: OracleSource::QuoteAsset,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            decimals: 6,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            deposit_balance: 10000 * SPOT_BALANCE_PRECISION,
+            liquidator_fee: 0,
+            historical_oracle_data: HistoricalOracleData::default_quote_oracle(),
+            ..SpotMarket::default()
+        };
+        create_anchor_account_info!(usdc_spot_market, SpotMarket, usdc_spot_market_account_info);
+        let spot_market_map =
+            SpotMarketMap::load_one(&usdc_spot_market_account_info, true).unwrap();
+
+        let mut spot_positions = [SpotPosition::default(); 8];
+        spot_positions[0] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 100 * SPOT_BALANCE_PRECISION_U64,
+            ..SpotPosition::default()
+        };
+        spot_positions[1] = SpotPosition {
+            market_index: 0,
+            balance_type: SpotBalanceType::Deposit,
+            scaled_balance: 20 * SPOT_BALANCE_PRECISION_U64,
+            isolated_perp_market_index: 0,
+            is_isolated_perp_collateral: true,
+            ..SpotPosition::default()
+        };
+
+        let mut user = User {
+            perp_positions: get_positions(PerpPosition {
+                market_index: 0,
+                base_asset_amount: BASE_PRECISION_I64,
+                quote_asset_amount: -100 * QUOTE_PRECISION_I64,
+                ..PerpPosition::default()
+            }),
+            spot_positions,
+            ..User::default()
+        };
+
+        let calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial),
+        )
+        .unwrap();
+
+        // position is margined by its own collateral, not the cross deposit
+        assert_eq!(calculation.margin_requirement, 0);
+        assert_eq!(calculation.total_collateral, 100 * QUOTE_PRECISION_I128);
+        let isolated = calculation.get_isolated_margin_calculation(0).unwrap();
+        assert_eq!(isolated.margin_requirement, 10 * QUOTE_PRECISION);
+        assert_eq!(isolated.total_collateral, 20 * QUOTE_PRECISION_I128);
+        assert_eq!(calculation.get_num_of_liabilities().unwrap(), 0);
+        assert!(calculation.meets_margin_requirement());
+
+        user.spot_positions[1].scaled_balance = 5 * SPOT_BALANCE_PRECISION_U64;
+
+        let calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial),
+        )
+        .unwrap();
+
+        assert!(calculation.meets_cross_margin_requirement());
+        assert!(!calculation.meets_isolated_margin_requirement(0));
+        assert!(!calculation.meets_margin_requirement());
+
+        // the undercollateralized isolated position doesnt stop cross margin orders
+        assert!(!calculation.meets_market_margin_requirement(MarketIdentifier::perp(0)));
+        assert!(calculation.meets_market_margin_requirement(MarketIdentifier::perp(1)));
+        assert!(calculation.meets_market_margin_requirement(MarketIdentifier::spot(0)));
+
+        // without isolation the cross deposit margins the position
+        user.spot_positions[1] = SpotPosition::default();
+
+        let calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
+            &user,
+            &perp_market_map,
+            &spot_market_map,
+            &mut oracle_map,
+            MarginContext::standard(MarginRequirementType::Initial),
+        )
+        .unwrap();
+
+        assert_eq!(calculation.margin_requirement, 10 * QUOTE_PRECISION);
+        assert_eq!(calculation.total_collateral, 100 * QUOTE_PRECISION_I128);
+        assert!(calculation.get_isolated_margin_calculation(0).is_none());
+        assert!(calculation.meets_margin_requirement());
+    }
+
     #[test]
     fn no_spot_position_but_trigger_order() {
         let slot = 0_u64;
@@ -3530,3 +3668,153 @@ mod calculate_user_equity {
         assert_eq!(net_usd_value, 1000000000);
     }
 }
+
+mod calculate_perp_position_value_and_pnl_for_option {
+    use crate::math::constants::{
+        BASE_PRECISION_I64, PRICE_PRECISION_I64, PRICE_PRECISION_U64, QUOTE_PRECISION_I64,
+        SPOT_WEIGHT_PRECISION,
+    };
+    use crate::math::margin::{calculate_perp_position_value_and_pnl, MarginRequirementType};
+    use crate::state::oracle::{OraclePriceData, StrictOraclePrice};
+    use crate::state::perp_market::{ContractType, OptionType, PerpMarket};
+    use crate::state::user::PerpPosition;
+
+    #[test]
+    fn call() {
+        let market = PerpMarket {
+            contract_type: ContractType::Option,
+            option_type: OptionType::Call,
+            strike_price: 100 * PRICE_PRECISION_U64,
+            margin_ratio_initial: 2000, // 20%
+            margin_ratio_maintenance: 1000,
+            unrealized_pnl_initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            unrealized_pnl_maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            ..PerpMarket::default_test()
+        };
+
+        let oracle_price_data = OraclePriceData {
+            price: 120 * PRICE_PRECISION_I64,
+            confidence: 0,
+            delay: 0,
+            has_sufficient_number_of_data_points: true,
+        };
+        let strict_quote_price = StrictOraclePrice::test(PRICE_PRECISION_I64);
+
+        // long 1 call for $25 premium, marked at $20 intrinsic with no margin requirement
+        let long_position = PerpPosition {
+            base_asset_amount: BASE_PRECISION_I64,
+            quote_asset_amount: -25 * QUOTE_PRECISION_I64,
+            ..PerpPosition::default()
+        };
+
+        let (margin_requirement, upnl, base_asset_value, _) =
+            calculate_perp_position_value_and_pnl(
+                &long_position,
+                &market,
+                &oracle_price_data,
+                &strict_quote_price,
+                MarginRequirementType::Initial,
+                0,
+                false,
+            )
+            .unwrap();
+
+        assert_eq!(margin_requirement, 0);
+        assert_eq!(upnl, -5 * QUOTE_PRECISION_I64 as i128);
+        assert_eq!(base_asset_value, 20_000_000);
+
+        // short 1 call for $25 premium, in the money so margined at 20% of the underlying
+        let short_position = PerpPosition {
+            base_asset_amount: -BASE_PRECISION_I64,
+            quote_asset_amount: 25 * QUOTE_PRECISION_I64,
+            ..PerpPosition::default()
+        };
+
+        let (margin_requirement, upnl, base_asset_value, _) =
+            calculate_perp_position_value_and_pnl(
+                &short_position,
+                &market,
+                &oracle_price_data,
+                &strict_quote_price,
+                MarginRequirementType::Initial,
+                0,
+                false,
+            )
+            .unwrap();
+
+        assert_eq!(margin_requirement, 24_000_000);
+        assert_eq!(upnl, 5 * QUOTE_PRECISION_I64 as i128);
+        assert_eq!(base_asset_value, 20_000_000);
+    }
+}
+
+mod calculate_perp_position_value_and_pnl_for_prediction_market {
+    use crate::math::constants::{
+        BASE_PRECISION_I64, PRICE_PRECISION_I64, QUOTE_PRECISION_I64, SPOT_WEIGHT_PRECISION,
+    };
+    use crate::math::margin::{calculate_perp_position_value_and_pnl, MarginRequirementType};
+    use crate::state::oracle::{OraclePriceData, StrictOraclePrice};
+    use crate::state::perp_market::{ContractType, PerpMarket};
+    use crate::state::user::PerpPosition;
+
+    #[test]
+    fn max_loss() {
+        let market = PerpMarket {
+            contract_type: ContractType::Prediction,
+            unrealized_pnl_initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            unrealized_pnl_maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            ..PerpMarket::default_test()
+        };
+
+        // 30% chance of yes
+        let oracle_price_data = OraclePriceData {
+            price: 300_000,
+            confidence: 0,
+            delay: 0,
+            has_sufficient_number_of_data_points: true,
+        };
+        let strict_quote_price = StrictOraclePrice::test(PRICE_PRECISION_I64);
+
+        // long 100 yes bought at .25, can lose the $30 they're worth
+        let long_position = PerpPosition {
+            base_asset_amount: 100 * BASE_PRECISION_I64,
+            quote_asset_amount: -25 * QUOTE_PRECISION_I64,
+            ..PerpPosition::default()
+        };
+
+        let (margin_requirement, upnl, _, _) = calculate_perp_position_value_and_pnl(
+            &long_position,
+            &market,
+            &oracle_price_data,
+            &strict_quote_price,
+            MarginRequirementType::Maintenance,
+            0,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(margin_requirement, 30 * QUOTE_PRECISION_I64 as u128);
+        assert_eq!(upnl, 5 * QUOTE_PRECISION_I64 as i128);
+
+        // short 100 yes sold at .25, can lose up to $70 more if it resolves yes
+        let short_position = PerpPosition {
+            base_asset_amount: -100 * BASE_PRECISION_I64,
+            quote_asset_amount: 25 * QUOTE_PRECISION_I64,
+            ..PerpPosition::default()
+        };
+
+        let (margin_requirement, upnl, _, _) = calculate_perp_position_value_and_pnl(
+            &short_position,
+            &market,
+            &oracle_price_data,
+            &strict_quote_price,
+            MarginRequirementType::Maintenance,
+            0,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(margin_requirement, 70 * QUOTE_PRECISION_I64 as u128);
+        assert_eq!(upnl, -5 * QUOTE_PRECISION_I64 as i128);
+    }
+}
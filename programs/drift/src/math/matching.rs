@@ -4,9 +4,10 @@ use crate::controller::position::PositionDirection;
 use crate::error::DriftResult;
 use crate::math::casting::Cast;
 use crate::math::constants::{BID_ASK_SPREAD_PRECISION_I128, TEN_BPS_I64};
-use crate::math::orders::calculate_quote_asset_amount_for_maker_order;
+use crate::math::orders::{calculate_quote_asset_amount_for_maker_order, standardize_price};
 use crate::math::safe_math::SafeMath;
 
+use crate::state::perp_market::MatchingPolicy;
 use crate::state::user::Order;
 
 #[cfg(test)]
@@ -102,3 +103,202 @@ pub fn calculate_filler_multiplier_for_matched_orders(
 
     multiplier.cast()
 }
+
+/// Splits the taker base asset amount between makers quoting the same price level.
+/// Makers are expected in time priority, the first maker receives the top of book allocation
+pub fn calculate_maker_fill_allocations(
+    matching_policy: MatchingPolicy,
+    maker_base_asset_amounts: &[u64],
+    taker_base_asset_amount: u64,
+    top_of_book_allocation: u8,
+    step_size: u64,
+) -> DriftResult<Vec<u64>> {
+    let total_maker_base_asset_amount = maker_base_asset_amounts
+        .iter()
+        .try_fold(0_u64, |total, amount| total.safe_add(*amount))?;
+
+    if matching_policy == MatchingPolicy::PriceTime
+        || taker_base_asset_amount >= total_maker_base_asset_amount
+    {
+        return Ok(maker_base_asset_amounts.to_vec());
+    }
+
+    let mut allocations = vec![0_u64; maker_base_asset_amounts.len()];
+    let mut taker_base_asset_amount_remaining = taker_base_asset_amount;
+
+    if matching_policy == MatchingPolicy::Hybrid && !maker_base_asset_amounts.is_empty() {
+        let top_of_book_base_asset_amount = taker_base_asset_amount
+            .safe_mul(top_of_book_allocation.cast()?)?
+            .safe_div(100)?
+            .min(maker_base_asset_amounts[0]);
+        let top_of_book_base_asset_amount =
+            top_of_book_base_asset_amount.safe_sub(top_of_book_base_asset_amount % step_size)?;
+
+        allocations[0] = top_of_book_base_asset_amount;
+        taker_base_asset_amount_remaining =
+            taker_base_asset_amount_remaining.safe_sub(top_of_book_base_asset_amount)?;
+    }
+
+    let total_maker_base_asset_amount_remaining =
+        total_maker_base_asset_amount.safe_sub(allocations[0])?;
+
+    for (i, maker_base_asset_amount) in maker_base_asset_amounts.iter().enumerate() {
+        let maker_base_asset_amount_remaining = maker_base_asset_amount.safe_sub(allocations[i])?;
+
+        let pro_rata_base_asset_amount = taker_base_asset_amount_remaining
+            .cast::<u128>()?
+            .safe_mul(maker_base_asset_amount_remaining.cast()?)?
+            .safe_div(total_maker_base_asset_amount_remaining.cast()?)?
+            .cast::<u64>()?;
+
+        allocations[i] = allocations[i].safe_add(
+            pro_rata_base_asset_amount.safe_sub(pro_rata_base_asset_amount % step_size)?,
+        )?;
+    }
+
+    // rounding leftovers go to makers in time priority
+    let mut leftover_base_asset_amount = taker_base_asset_amount.safe_sub(
+        allocations
+            .iter()
+            .try_fold(0_u64, |total, amount| total.safe_add(*amount))?,
+    )?;
+
+    for (i, maker_base_asset_amount) in maker_base_asset_amounts.iter().enumerate() {
+        if leftover_base_asset_amount == 0 {
+            break;
+        }
+
+        let additional_base_asset_amount = min(
+            leftover_base_asset_amount,
+            maker_base_asset_amount.safe_sub(allocations[i])?,
+        );
+        allocations[i] = allocations[i].safe_add(additional_base_asset_amount)?;
+        leftover_base_asset_amount =
+            leftover_base_asset_amount.safe_sub(additional_base_asset_amount)?;
+    }
+
+    Ok(allocations)
+}
+
+/// The uniform price a perp batch auction clears at, or None if nothing crosses. Every limit price,
+/// the amm bid/ask and the oracle are candidates; the one executing the most volume wins, then the
+/// one leaving the smallest surplus, then the one closest to the oracle. Takers cross each other
+/// first and only the imbalance trades with makers and the amm, so volume is counted the same way.
+/// Orders are (limit price, base asset amount), taker orders without a limit price cross at any price
+pub fn calculate_batch_auction_clearing_price(
+    taker_bids: &[(Option<u64>, u64)],
+    taker_asks: &[(Option<u64>, u64)],
+    maker_bids: &[(u64, u64)],
+    maker_asks: &[(u64, u64)],
+    amm_bid_price: Option<u64>,
+    amm_ask_price: Option<u64>,
+    oracle_price: u64,
+    tick_size: u64,
+) -> DriftResult<Option<u64>> {
+    let mut candidates: Vec<u64> = taker_bids
+        .iter()
+        .chain(taker_asks.iter())
+        .filter_map(|(limit_price, _)| *limit_price)
+        .chain(
+            maker_bids
+                .iter()
+                .chain(maker_asks.iter())
+                .map(|(limit_price, _)| *limit_price),
+        )
+        .collect();
+
+    // round so the amm still crosses the candidate
+    if let Some(amm_bid_price) = amm_bid_price {
+        candidates.push(standardize_price(
+            amm_bid_price,
+            tick_size,
+            PositionDirection::Long,
+        )?);
+    }
+    if let Some(amm_ask_price) = amm_ask_price {
+        candidates.push(standardize_price(
+            amm_ask_price,
+            tick_size,
+            PositionDirection::Short,
+        )?);
+    }
+    candidates.push(standardize_price(
+        oracle_price,
+        tick_size,
+        PositionDirection::Long,
+    )?);
+
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let mut best: Option<(u64, u64, u64, u64)> = None;
+    for price in candidates {
+        if price == 0 {
+            continue;
+        }
+
+        let taker_demand = taker_bids
+            .iter()
+            .filter(|(limit_price, _)| limit_price.map_or(true, |limit| limit >= price))
+            .try_fold(0_u64, |total, (_, base)| total.safe_add(*base))?;
+        let taker_supply = taker_asks
+            .iter()
+            .filter(|(limit_price, _)| limit_price.map_or(true, |limit| limit <= price))
+            .try_fold(0_u64, |total, (_, base)| total.safe_add(*base))?;
+
+        // the amm is treated as unlimited liquidity at or through its quote
+        let maker_demand = if amm_bid_price.map_or(false, |amm_bid| price <= amm_bid) {
+            u64::MAX
+        } else {
+            maker_bids
+                .iter()
+                .filter(|(limit_price, _)| *limit_price >= price)
+                .try_fold(0_u64, |total, (_, base)| total.safe_add(*base))?
+        };
+        let maker_supply = if amm_ask_price.map_or(false, |amm_ask| price >= amm_ask) {
+            u64::MAX
+        } else {
+            maker_asks
+                .iter()
+                .filter(|(limit_price, _)| *limit_price <= price)
+                .try_fold(0_u64, |total, (_, base)| total.safe_add(*base))?
+        };
+
+        let taker_volume = taker_demand.min(taker_supply);
+        let volume = if taker_demand > taker_supply {
+            taker_volume.safe_add(taker_demand.safe_sub(taker_supply)?.min(maker_supply))?
+        } else {
+            taker_volume.safe_add(taker_supply.safe_sub(taker_demand)?.min(maker_demand))?
+        };
+
+        if volume == 0 {
+            continue;
+        }
+
+        let surplus = taker_demand
+            .saturating_add(maker_demand)
+            .abs_diff(taker_supply.saturating_add(maker_supply));
+        let distance_to_oracle = price.abs_diff(oracle_price);
+
+        let is_better = match best {
+            None => true,
+            Some((_, best_volume, best_surplus, best_distance_to_oracle)) => {
+                (
+                    volume,
+                    std::cmp::Reverse(surplus),
+                    std::cmp::Reverse(distance_to_oracle),
+                ) > (
+                    best_volume,
+                    std::cmp::Reverse(best_surplus),
+                    std::cmp::Reverse(best_distance_to_oracle),
+                )
+            }
+        };
+
+        if is_better {
+            best = Some((price, volume, surplus, distance_to_oracle));
+        }
+    }
+
+    Ok(best.map(|(price, ..)| price))
+}
@@ -255,3 +255,219 @@ fn filler_multiplier_maker_short() {
 
     assert_eq!(mult, 2100); // 2.1x
 }
+
+mod calculate_maker_fill_allocations {
+    use crate::math::constants::BASE_PRECISION_U64;
+    use crate::math::matching::calculate_maker_fill_allocations;
+    use crate::state::perp_market::MatchingPolicy;
+
+    #[test]
+    fn price_time() {
+        let allocations = calculate_maker_fill_allocations(
+            MatchingPolicy::PriceTime,
+            &[BASE_PRECISION_U64, 3 * BASE_PRECISION_U64],
+            2 * BASE_PRECISION_U64,
+            0,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(
+            allocations,
+            vec![BASE_PRECISION_U64, 3 * BASE_PRECISION_U64]
+        );
+    }
+
+    #[test]
+    fn pro_rata() {
+        let allocations = calculate_maker_fill_allocations(
+            MatchingPolicy::ProRata,
+            &[BASE_PRECISION_U64, 3 * BASE_PRECISION_U64],
+            2 * BASE_PRECISION_U64,
+            0,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(
+            allocations,
+            vec![BASE_PRECISION_U64 / 2, 3 * BASE_PRECISION_U64 / 2]
+        );
+
+        // leftover from step size rounding goes to the first maker
+        let allocations =
+            calculate_maker_fill_allocations(MatchingPolicy::ProRata, &[3, 3, 3], 4, 0, 1).unwrap();
+
+        assert_eq!(allocations, vec![2, 1, 1]);
+
+        // taker larger than the level fills every maker
+        let allocations = calculate_maker_fill_allocations(
+            MatchingPolicy::ProRata,
+            &[BASE_PRECISION_U64, 3 * BASE_PRECISION_U64],
+            5 * BASE_PRECISION_U64,
+            0,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(
+            allocations,
+            vec![BASE_PRECISION_U64, 3 * BASE_PRECISION_U64]
+        );
+    }
+
+    #[test]
+    fn hybrid() {
+        let allocations = calculate_maker_fill_allocations(
+            MatchingPolicy::Hybrid,
+            &[2 * BASE_PRECISION_U64, 2 * BASE_PRECISION_U64],
+            2 * BASE_PRECISION_U64,
+            50,
+            1,
+        )
+        .unwrap();
+
+        // first maker gets 1 from top of book and then 1/3 of the remaining 1
+        assert_eq!(allocations, vec![1333333334, 666666666]);
+        assert_eq!(allocations.iter().sum::<u64>(), 2 * BASE_PRECISION_U64);
+    }
+}
+
+mod calculate_batch_auction_clearing_price {
+    use crate::math::constants::{BASE_PRECISION_U64, PRICE_PRECISION_U64};
+    use crate::math::matching::calculate_batch_auction_clearing_price;
+
+    #[test]
+    fn balanced_takers_clear_at_oracle() {
+        let price = calculate_batch_auction_clearing_price(
+            &[(None, BASE_PRECISION_U64)],
+            &[(None, BASE_PRECISION_U64)],
+            &[],
+            &[],
+            Some(99 * PRICE_PRECISION_U64),
+            Some(101 * PRICE_PRECISION_U64),
+            100 * PRICE_PRECISION_U64,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(price, Some(100 * PRICE_PRECISION_U64));
+    }
+
+    #[test]
+    fn buy_imbalance_walks_maker_asks() {
+        let maker_asks = [
+            (100 * PRICE_PRECISION_U64, BASE_PRECISION_U64),
+            (102 * PRICE_PRECISION_U64, BASE_PRECISION_U64),
+        ];
+
+        // imbalance filled by first maker
+        let price = calculate_batch_auction_clearing_price(
+            &[(None, 2 * BASE_PRECISION_U64)],
+            &[(None, BASE_PRECISION_U64)],
+            &[],
+            &maker_asks,
+            None,
+            Some(101 * PRICE_PRECISION_U64),
+            100 * PRICE_PRECISION_U64,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(price, Some(100 * PRICE_PRECISION_U64));
+
+        // imbalance larger than first maker is cheaper at the amm than the second maker
+        let price = calculate_batch_auction_clearing_price(
+            &[(None, 3 * BASE_PRECISION_U64)],
+            &[(None, BASE_PRECISION_U64)],
+            &[],
+            &maker_asks,
+            None,
+            Some(101 * PRICE_PRECISION_U64),
+            100 * PRICE_PRECISION_U64,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(price, Some(101 * PRICE_PRECISION_U64));
+
+        // without the amm it needs the second maker
+        let price = calculate_batch_auction_clearing_price(
+            &[(None, 3 * BASE_PRECISION_U64)],
+            &[(None, BASE_PRECISION_U64)],
+            &[],
+            &maker_asks,
+            None,
+            None,
+            100 * PRICE_PRECISION_U64,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(price, Some(102 * PRICE_PRECISION_U64));
+    }
+
+    #[test]
+    fn taker_limit_that_does_not_cross_does_not_move_price() {
+        let maker_asks = [
+            (101 * PRICE_PRECISION_U64, BASE_PRECISION_U64),
+            (102 * PRICE_PRECISION_U64, BASE_PRECISION_U64),
+        ];
+
+        let price = calculate_batch_auction_clearing_price(
+            &[
+                (None, BASE_PRECISION_U64),
+                (
+                    Some(100 * PRICE_PRECISION_U64 + PRICE_PRECISION_U64 / 2),
+                    BASE_PRECISION_U64,
+                ),
+            ],
+            &[],
+            &[],
+            &maker_asks,
+            None,
+            None,
+            100 * PRICE_PRECISION_U64,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(price, Some(101 * PRICE_PRECISION_U64));
+    }
+
+    #[test]
+    fn sell_imbalance_walks_maker_bids() {
+        let maker_bids = [(99 * PRICE_PRECISION_U64, BASE_PRECISION_U64)];
+
+        let price = calculate_batch_auction_clearing_price(
+            &[],
+            &[(None, 2 * BASE_PRECISION_U64)],
+            &maker_bids,
+            &[],
+            Some(98 * PRICE_PRECISION_U64),
+            None,
+            100 * PRICE_PRECISION_U64,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(price, Some(98 * PRICE_PRECISION_U64));
+    }
+
+    #[test]
+    fn nothing_crosses() {
+        let price = calculate_batch_auction_clearing_price(
+            &[(Some(99 * PRICE_PRECISION_U64), BASE_PRECISION_U64)],
+            &[],
+            &[],
+            &[(101 * PRICE_PRECISION_U64, BASE_PRECISION_U64)],
+            None,
+            None,
+            100 * PRICE_PRECISION_U64,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(price, None);
+    }
+}
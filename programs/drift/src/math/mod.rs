@@ -18,6 +18,7 @@ pub mod liquidation;
 pub mod lp;
 pub mod margin;
 pub mod matching;
+pub mod options;
 pub mod oracle;
 pub mod orders;
 pub mod pnl;
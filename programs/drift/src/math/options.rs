@@ -0,0 +1,91 @@
+use crate::error::DriftResult;
+use crate::math::casting::Cast;
+use crate::math::constants::MARGIN_PRECISION_U128;
+use crate::math::safe_math::SafeMath;
+use crate::state::perp_market::OptionType;
+
+#[cfg(test)]
+mod tests;
+
+/// value of exercising the option at the underlying price
+/// precision: PRICE_PRECISION
+pub fn calculate_option_intrinsic_value(
+    option_type: OptionType,
+    strike_price: u64,
+    underlying_price: i64,
+) -> DriftResult<i64> {
+    let strike_price = strike_price.cast::<i64>()?;
+
+    let intrinsic_value = match option_type {
+        OptionType::Call => underlying_price.safe_sub(strike_price)?,
+        OptionType::Put => strike_price.safe_sub(underlying_price)?,
+    };
+
+    Ok(intrinsic_value.max(0))
+}
+
+/// margin required per unit of a short option on top of its intrinsic value, which is already
+/// counted in the position's pnl. It's the margin ratio of the underlying price less the amount the
+/// option is out of the money, floored at half the margin ratio of the underlying price
+/// e.g. with a margin ratio of .2, a short call needs 20% of the underlying less the out of the money amount, min 10%
+/// precision: PRICE_PRECISION
+pub fn calculate_short_option_margin_price(
+    option_type: OptionType,
+    strike_price: u64,
+    underlying_price: i64,
+    margin_ratio: u32,
+) -> DriftResult<u64> {
+    let underlying_price = underlying_price.max(0).cast::<u64>()?;
+
+    let underlying_margin = underlying_price
+        .cast::<u128>()?
+        .safe_mul(margin_ratio.cast()?)?
+        .safe_div(MARGIN_PRECISION_U128)?
+        .cast::<u64>()?;
+
+    let out_of_the_money_amount = match option_type {
+        OptionType::Call => strike_price.saturating_sub(underlying_price),
+        OptionType::Put => underlying_price.saturating_sub(strike_price),
+    };
+
+    let margin_price = underlying_margin
+        .saturating_sub(out_of_the_money_amount)
+        .max(underlying_margin.safe_div(2)?);
+
+    // a short put can never lose more than its strike
+    match option_type {
+        OptionType::Call => Ok(margin_price),
+        OptionType::Put => Ok(margin_price.min(strike_price)),
+    }
+}
+
+/// range an option's premium can fill in at the underlying price. a european option is worth at least
+/// about its intrinsic value and at most the underlying for a call or the strike for a put. the bands
+/// are widened by the margin ratio of the underlying price, except a put's strike which is exact
+/// precision: PRICE_PRECISION
+pub fn calculate_option_premium_bands(
+    option_type: OptionType,
+    strike_price: u64,
+    underlying_price: i64,
+    margin_ratio: u32,
+) -> DriftResult<(u64, u64)> {
+    let intrinsic_value =
+        calculate_option_intrinsic_value(option_type, strike_price, underlying_price)?
+            .cast::<u64>()?;
+
+    let underlying_price = underlying_price.max(0).cast::<u64>()?;
+
+    let tolerance = underlying_price
+        .cast::<u128>()?
+        .safe_mul(margin_ratio.cast()?)?
+        .safe_div(MARGIN_PRECISION_U128)?
+        .cast::<u64>()?;
+
+    let min_premium = intrinsic_value.saturating_sub(tolerance);
+    let max_premium = match option_type {
+        OptionType::Call => underlying_price.safe_add(tolerance)?,
+        OptionType::Put => strike_price,
+    };
+
+    Ok((min_premium, max_premium))
+}
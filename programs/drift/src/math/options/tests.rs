@@ -0,0 +1,152 @@
+mod calculate_option_intrinsic_value {
+    use crate::math::constants::PRICE_PRECISION_U64;
+    use crate::math::options::calculate_option_intrinsic_value;
+    use crate::state::perp_market::OptionType;
+
+    #[test]
+    fn call() {
+        let strike_price = 100 * PRICE_PRECISION_U64;
+
+        let value =
+            calculate_option_intrinsic_value(OptionType::Call, strike_price, 120_000_000).unwrap();
+        assert_eq!(value, 20_000_000);
+
+        let value =
+            calculate_option_intrinsic_value(OptionType::Call, strike_price, 80_000_000).unwrap();
+        assert_eq!(value, 0);
+    }
+
+    #[test]
+    fn put() {
+        let strike_price = 100 * PRICE_PRECISION_U64;
+
+        let value =
+            calculate_option_intrinsic_value(OptionType::Put, strike_price, 120_000_000).unwrap();
+        assert_eq!(value, 0);
+
+        let value =
+            calculate_option_intrinsic_value(OptionType::Put, strike_price, 80_000_000).unwrap();
+        assert_eq!(value, 20_000_000);
+    }
+}
+
+mod calculate_short_option_margin_price {
+    use crate::math::constants::{MARGIN_PRECISION, PRICE_PRECISION_U64};
+    use crate::math::options::calculate_short_option_margin_price;
+    use crate::state::perp_market::OptionType;
+
+    #[test]
+    fn call() {
+        let strike_price = 100 * PRICE_PRECISION_U64;
+        let margin_ratio = MARGIN_PRECISION / 5; // 20%
+
+        // in the money, full 20% of underlying
+        let margin_price = calculate_short_option_margin_price(
+            OptionType::Call,
+            strike_price,
+            120_000_000,
+            margin_ratio,
+        )
+        .unwrap();
+        assert_eq!(margin_price, 24_000_000);
+
+        // 5 out of the money, 20% of underlying less 5
+        let margin_price = calculate_short_option_margin_price(
+            OptionType::Call,
+            strike_price,
+            95_000_000,
+            margin_ratio,
+        )
+        .unwrap();
+        assert_eq!(margin_price, 14_000_000);
+
+        // far out of the money, floored at 10% of underlying
+        let margin_price = calculate_short_option_margin_price(
+            OptionType::Call,
+            strike_price,
+            50_000_000,
+            margin_ratio,
+        )
+        .unwrap();
+        assert_eq!(margin_price, 5_000_000);
+    }
+
+    #[test]
+    fn put() {
+        let strike_price = 100 * PRICE_PRECISION_U64;
+        let margin_ratio = MARGIN_PRECISION / 5; // 20%
+
+        // 5 out of the money, 20% of underlying less 5
+        let margin_price = calculate_short_option_margin_price(
+            OptionType::Put,
+            strike_price,
+            105_000_000,
+            margin_ratio,
+        )
+        .unwrap();
+        assert_eq!(margin_price, 16_000_000);
+
+        // capped at strike
+        let margin_price = calculate_short_option_margin_price(
+            OptionType::Put,
+            strike_price,
+            100_000_000,
+            MARGIN_PRECISION * 2,
+        )
+        .unwrap();
+        assert_eq!(margin_price, strike_price);
+    }
+}
+
+mod calculate_option_premium_bands {
+    use crate::math::constants::{MARGIN_PRECISION, PRICE_PRECISION_U64};
+    use crate::math::options::calculate_option_premium_bands;
+    use crate::state::perp_market::OptionType;
+
+    #[test]
+    fn call() {
+        let strike_price = 100 * PRICE_PRECISION_U64;
+        let margin_ratio = MARGIN_PRECISION / 10; // 10%
+
+        // 20 in the money, intrinsic value less 10% of underlying to underlying plus 10%
+        let bands = calculate_option_premium_bands(
+            OptionType::Call,
+            strike_price,
+            120_000_000,
+            margin_ratio,
+        )
+        .unwrap();
+        assert_eq!(bands, (8_000_000, 132_000_000));
+
+        // out of the money, floored at 0
+        let bands = calculate_option_premium_bands(
+            OptionType::Call,
+            strike_price,
+            80_000_000,
+            margin_ratio,
+        )
+        .unwrap();
+        assert_eq!(bands, (0, 88_000_000));
+    }
+
+    #[test]
+    fn put() {
+        let strike_price = 100 * PRICE_PRECISION_U64;
+        let margin_ratio = MARGIN_PRECISION / 10; // 10%
+
+        // 20 in the money, capped at the strike
+        let bands =
+            calculate_option_premium_bands(OptionType::Put, strike_price, 80_000_000, margin_ratio)
+                .unwrap();
+        assert_eq!(bands, (12_000_000, strike_price));
+
+        let bands = calculate_option_premium_bands(
+            OptionType::Put,
+            strike_price,
+            120_000_000,
+            margin_ratio,
+        )
+        .unwrap();
+        assert_eq!(bands, (0, strike_price));
+    }
+}
@@ -11,14 +11,18 @@ use crate::math::auction::is_amm_available_liquidity_source;
 use crate::math::casting::Cast;
 use crate::{
     load, math, FeeTier, State, BASE_PRECISION_I128, FEE_ADJUSTMENT_MAX,
-    OPEN_ORDER_MARGIN_REQUIREMENT, PERCENTAGE_PRECISION, PERCENTAGE_PRECISION_U64,
-    PRICE_PRECISION_I128, QUOTE_PRECISION_I128, SPOT_WEIGHT_PRECISION, SPOT_WEIGHT_PRECISION_I128,
+    OPEN_ORDER_MARGIN_REQUIREMENT, PERCENTAGE_PRECISION, PERCENTAGE_PRECISION_I64,
+    PERCENTAGE_PRECISION_U64, PRICE_PRECISION_I128, QUOTE_PRECISION_I128, SPOT_WEIGHT_PRECISION,
+    SPOT_WEIGHT_PRECISION_I128,
 };
 
-use crate::math::constants::MARGIN_PRECISION_U128;
+use crate::math::constants::{
+    MARGIN_PRECISION_U128, MAX_PREDICTION_MARKET_PRICE, TRAILING_STOP_MIN_PAID_UPDATE,
+};
 use crate::math::margin::{
     calculate_margin_requirement_and_total_collateral_and_liability_info, MarginRequirementType,
 };
+use crate::math::options::calculate_option_premium_bands;
 use crate::math::safe_math::SafeMath;
 use crate::math::spot_balance::get_strict_token_value;
 use crate::math::spot_withdraw::get_max_withdraw_for_market_with_token_amount;
@@ -28,12 +32,13 @@ use crate::state::margin_calculation::{MarginCalculation, MarginContext};
 use crate::state::oracle::{OraclePriceData, StrictOraclePrice};
 use crate::state::oracle_map::OracleMap;
 use crate::state::order_params::PostOnlyParam;
-use crate::state::perp_market::{PerpMarket, AMM};
+use crate::state::perp_market::{OptionType, PerpMarket, AMM};
 use crate::state::perp_market_map::PerpMarketMap;
 use crate::state::spot_market::SpotMarket;
 use crate::state::spot_market_map::SpotMarketMap;
 use crate::state::user::{
-    MarketType, Order, OrderFillSimulation, OrderStatus, OrderTriggerCondition, PerpPosition, User,
+    MarketType, Order, OrderBitFlag, OrderFillSimulation, OrderStatus, OrderTriggerCondition,
+    PerpPosition, TriggerReferencePrice, User,
 };
 use crate::state::user_map::UserMap;
 use crate::validate;
@@ -224,6 +229,45 @@ pub fn is_multiple_of_step_size(base_asset_amount: u64, step_size: u64) -> Drift
     Ok(remainder == 0)
 }
 
+pub fn calculate_base_asset_amount_for_quote_asset_amount(
+    quote_asset_amount: u64,
+    price: u64,
+    base_precision: u64,
+) -> DriftResult<u64> {
+    validate!(
+        price > 0,
+        ErrorCode::InvalidOrderLimitPrice,
+        "price must be greater than 0 to convert quote to base"
+    )?;
+
+    quote_asset_amount
+        .cast::<u128>()?
+        .safe_mul(base_precision.cast()?)?
+        .safe_div(price.cast()?)?
+        .cast()
+}
+
+/// Converts the unfilled quote of a quote sized order to base at the price of the last fill
+pub fn calculate_quote_sized_order_base_asset_amount_unfilled(
+    order: &Order,
+    fill_base_asset_amount: u64,
+    fill_quote_asset_amount: u64,
+    step_size: u64,
+) -> DriftResult<u64> {
+    if fill_base_asset_amount == 0 || fill_quote_asset_amount == 0 {
+        return order.get_base_asset_amount_unfilled(None);
+    }
+
+    let base_asset_amount_unfilled = order
+        .get_quote_asset_amount_unfilled()?
+        .cast::<u128>()?
+        .safe_mul(fill_base_asset_amount.cast()?)?
+        .safe_div(fill_quote_asset_amount.cast()?)?
+        .cast::<u64>()?;
+
+    standardize_base_asset_amount(base_asset_amount_unfilled, step_size)
+}
+
 pub fn standardize_price(
     price: u64,
     tick_size: u64,
@@ -344,11 +388,16 @@ pub fn should_expire_order_before_fill(
     user: &User,
     order_index: usize,
     now: i64,
+    slot: u64,
 ) -> DriftResult<bool> {
-    let should_order_be_expired = should_expire_order(user, order_index, now)?;
-    if should_order_be_expired && user.orders[order_index].is_limit_order() {
+    let should_order_be_expired = should_expire_order(user, order_index, now, slot)?;
+    // slots dont drift like timestamps, so good til slot orders can be expired without a buffer
+    if should_order_be_expired
+        && user.orders[order_index].is_limit_order()
+        && !user.orders[order_index].is_good_til_slot()
+    {
         let now_sub_buffer = now.safe_sub(15)?;
-        if !should_expire_order(user, order_index, now_sub_buffer)? {
+        if !should_expire_order(user, order_index, now_sub_buffer, slot)? {
             msg!("invalid fill. cant force expire limit order until 15s after max_ts. max ts {}, now {}, now plus buffer {}", user.orders[order_index].max_ts, now, now_sub_buffer);
             return Err(ErrorCode::ImpossibleFill);
         }
@@ -358,13 +407,18 @@ pub fn should_expire_order_before_fill(
 }
 
 #[inline(always)]
-pub fn should_expire_order(user: &User, user_order_index: usize, now: i64) -> DriftResult<bool> {
+pub fn should_expire_order(
+    user: &User,
+    user_order_index: usize,
+    now: i64,
+    slot: u64,
+) -> DriftResult<bool> {
     let order = &user.orders[user_order_index];
     if order.status != OrderStatus::Open || order.max_ts == 0 || order.must_be_triggered() {
         return Ok(false);
     }
 
-    Ok(now > order.max_ts)
+    order.is_past_max_ts(now, slot)
 }
 
 pub fn should_cancel_reduce_only_order(
@@ -550,6 +604,46 @@ pub fn validate_fill_price_within_price_bands(
     Ok(())
 }
 
+/// option premiums aren't near the underlying oracle price, so they're banded by what the option can be worth
+pub fn validate_fill_price_within_option_premium_bands(
+    fill_price: u64,
+    option_type: OptionType,
+    strike_price: u64,
+    oracle_price: i64,
+    margin_ratio_initial: u32,
+) -> DriftResult {
+    let (min_premium, max_premium) = calculate_option_premium_bands(
+        option_type,
+        strike_price,
+        oracle_price,
+        margin_ratio_initial,
+    )?;
+
+    validate!(
+        (min_premium..=max_premium).contains(&fill_price),
+        ErrorCode::PriceBandsBreached,
+        "Fill Price Breaches Option Premium Bands: fill {} outside [{}, {}] (oracle: {})",
+        fill_price,
+        min_premium,
+        max_premium,
+        oracle_price
+    )?;
+
+    Ok(())
+}
+
+pub fn validate_fill_price_within_prediction_market_bounds(fill_price: u64) -> DriftResult {
+    validate!(
+        fill_price <= MAX_PREDICTION_MARKET_PRICE,
+        ErrorCode::PriceBandsBreached,
+        "Fill Price Breaches Prediction Market Bounds: fill {} > {}",
+        fill_price,
+        MAX_PREDICTION_MARKET_PRICE
+    )?;
+
+    Ok(())
+}
+
 pub fn is_oracle_too_divergent_with_twap_5min(
     oracle_price: i64,
     oracle_twap_5min: i64,
@@ -574,6 +668,96 @@ pub fn is_oracle_too_divergent_with_twap_5min(
     Ok(too_divergent)
 }
 
+pub fn get_perp_trigger_reference_price(
+    order: &Order,
+    oracle_price: i64,
+    market: &PerpMarket,
+) -> DriftResult<i64> {
+    let reference_price = match order.trigger_reference_price {
+        TriggerReferencePrice::Oracle => oracle_price,
+        TriggerReferencePrice::OracleTwap5Min => {
+            market
+                .amm
+                .historical_oracle_data
+                .last_oracle_price_twap_5min
+        }
+        TriggerReferencePrice::MarkTwap5Min => market.amm.last_mark_price_twap_5min.cast()?,
+        TriggerReferencePrice::LastFillPrice => market.last_fill_price.cast()?,
+        TriggerReferencePrice::LastFillPriceTwap5Min => market.last_fill_price_twap_5min.cast()?,
+    };
+
+    validate!(
+        reference_price > 0,
+        ErrorCode::OrderDidNotSatisfyTriggerCondition,
+        "trigger reference price {:?} not available",
+        order.trigger_reference_price
+    )?;
+
+    Ok(reference_price)
+}
+
+/// The trigger price and condition for an order triggered once the reference price moves by
+/// price_change from its current value
+/// price_change precision: PERCENTAGE_PRECISION
+pub fn calculate_trigger_price_for_price_change(
+    reference_price: i64,
+    price_change: i64,
+) -> DriftResult<(u64, OrderTriggerCondition)> {
+    validate!(
+        price_change != 0 && price_change > -PERCENTAGE_PRECISION_I64,
+        ErrorCode::InvalidOrderTrigger,
+        "invalid trigger price change {}",
+        price_change
+    )?;
+
+    let trigger_price = reference_price
+        .cast::<i128>()?
+        .safe_mul(PERCENTAGE_PRECISION_I64.safe_add(price_change)?.cast()?)?
+        .safe_div(PERCENTAGE_PRECISION_I64.cast()?)?
+        .cast::<u64>()?;
+
+    let trigger_condition = if price_change > 0 {
+        OrderTriggerCondition::Above
+    } else {
+        OrderTriggerCondition::Below
+    };
+
+    Ok((trigger_price, trigger_condition))
+}
+
+pub fn get_spot_trigger_reference_price(
+    order: &Order,
+    oracle_price: i64,
+    spot_market: &SpotMarket,
+) -> DriftResult<i64> {
+    let reference_price = match order.trigger_reference_price {
+        TriggerReferencePrice::Oracle => oracle_price,
+        TriggerReferencePrice::OracleTwap5Min => {
+            spot_market
+                .historical_oracle_data
+                .last_oracle_price_twap_5min
+        }
+        TriggerReferencePrice::MarkTwap5Min
+        | TriggerReferencePrice::LastFillPrice
+        | TriggerReferencePrice::LastFillPriceTwap5Min => {
+            msg!(
+                "trigger reference price {:?} not supported for spot",
+                order.trigger_reference_price
+            );
+            return Err(ErrorCode::InvalidOrder);
+        }
+    };
+
+    validate!(
+        reference_price > 0,
+        ErrorCode::OrderDidNotSatisfyTriggerCondition,
+        "trigger reference price {:?} not available",
+        order.trigger_reference_price
+    )?;
+
+    Ok(reference_price)
+}
+
 pub fn order_satisfies_trigger_condition(order: &Order, oracle_price: u64) -> DriftResult<bool> {
     match order.trigger_condition {
         OrderTriggerCondition::Above => Ok(oracle_price > order.trigger_price),
@@ -582,6 +766,61 @@ pub fn order_satisfies_trigger_condition(order: &Order, oracle_price: u64) -> Dr
     }
 }
 
+pub fn calculate_trailing_stop_trigger_price(
+    order: &Order,
+    oracle_price: i64,
+    tick_size: u64,
+) -> DriftResult<u64> {
+    let trailing_offset = if order.is_bit_flag_set(OrderBitFlag::TrailingStopPercentageOffset) {
+        oracle_price
+            .safe_mul(order.oracle_price_offset.cast()?)?
+            .safe_div(PERCENTAGE_PRECISION_I64)?
+    } else {
+        order.oracle_price_offset.cast::<i64>()?
+    };
+
+    // trigger price can only move towards the oracle price, never away from it
+    match order.trigger_condition {
+        OrderTriggerCondition::Below => {
+            let trailing_trigger_price = oracle_price.safe_sub(trailing_offset)?;
+            if trailing_trigger_price <= 0 {
+                return Ok(order.trigger_price);
+            }
+
+            Ok(order.trigger_price.max(standardize_price(
+                trailing_trigger_price.cast()?,
+                tick_size,
+                order.direction,
+            )?))
+        }
+        OrderTriggerCondition::Above => {
+            let trailing_trigger_price = oracle_price.safe_add(trailing_offset)?;
+
+            Ok(order.trigger_price.min(standardize_price(
+                trailing_trigger_price.cast()?,
+                tick_size,
+                order.direction,
+            )?))
+        }
+        _ => Err(print_error!(ErrorCode::InvalidTriggerOrderCondition)()),
+    }
+}
+
+/// Keepers are paid for moving a trailing stop's trigger price by at least TRAILING_STOP_MIN_PAID_UPDATE
+/// of the oracle price, smaller updates are persisted without a reward
+pub fn is_trailing_stop_update_paid(
+    trigger_price_before: u64,
+    trigger_price_after: u64,
+    oracle_price: i64,
+) -> DriftResult<bool> {
+    let min_paid_update = oracle_price
+        .unsigned_abs()
+        .safe_mul(TRAILING_STOP_MIN_PAID_UPDATE)?
+        .safe_div(PERCENTAGE_PRECISION_U64)?;
+
+    Ok(trigger_price_after.abs_diff(trigger_price_before) >= min_paid_update)
+}
+
 pub fn is_new_order_risk_increasing(
     order: &Order,
     position_base_asset_amount: i64,
@@ -797,14 +1036,16 @@ pub fn calculate_max_perp_order_size(
     let oracle_price_data_price = oracle_map.get_price_data(&perp_market.amm.oracle)?.price;
 
     let quote_spot_market = spot_market_map.get_ref(&perp_market.quote_spot_market_index)?;
-    let quote_oracle_price = oracle_map
-        .get_price_data(&quote_spot_market.oracle)?
-        .price
-        .max(
-            quote_spot_market
-                .historical_oracle_data
-                .last_oracle_price_twap_5min,
-        );
+    let quote_oracle_price = perp_market.get_quote_asset_price(
+        oracle_map
+            .get_price_data(&quote_spot_market.oracle)?
+            .price
+            .max(
+                quote_spot_market
+                    .historical_oracle_data
+                    .last_oracle_price_twap_5min,
+            ),
+    );
     drop(quote_spot_market);
 
     let perp_position: &PerpPosition = &user.perp_positions[position_index];
@@ -1222,7 +1463,7 @@ pub fn find_bids_and_asks_from_users(
                 continue;
             }
 
-            if now > order.max_ts && order.max_ts != 0 {
+            if order.max_ts != 0 && order.is_past_max_ts(now, slot)? {
                 continue;
             }
 
@@ -327,7 +327,7 @@ mod order_breaches_oracle_price_limits {
 
 mod should_expire_order {
     use crate::math::orders::should_expire_order;
-    use crate::state::user::{Order, OrderStatus, OrderType, User};
+    use crate::state::user::{Order, OrderBitFlag, OrderStatus, OrderType, User};
     use crate::test_utils::get_orders;
 
     #[test]
@@ -344,7 +344,7 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(!is_expired);
     }
@@ -363,7 +363,7 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(!is_expired);
     }
@@ -382,7 +382,7 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(is_expired);
     }
@@ -401,7 +401,7 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(!is_expired);
     }
@@ -420,7 +420,7 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(!is_expired);
     }
@@ -439,10 +439,33 @@ mod should_expire_order {
 
         let now = 100;
 
-        let is_expired = should_expire_order(&user, 0, now).unwrap();
+        let is_expired = should_expire_order(&user, 0, now, 0).unwrap();
 
         assert!(!is_expired);
     }
+
+    #[test]
+    fn good_til_slot() {
+        let user = User {
+            orders: get_orders(Order {
+                status: OrderStatus::Open,
+                order_type: OrderType::Limit,
+                max_ts: 1000,
+                bit_flags: OrderBitFlag::GoodTilSlot as u8,
+                ..Order::default()
+            }),
+            ..User::default()
+        };
+
+        // timestamp past max_ts doesnt expire the order
+        let now = 2000;
+
+        let is_expired = should_expire_order(&user, 0, now, 1000).unwrap();
+        assert!(!is_expired);
+
+        let is_expired = should_expire_order(&user, 0, now, 1001).unwrap();
+        assert!(is_expired);
+    }
 }
 
 mod get_max_fill_amounts {
@@ -3611,6 +3634,89 @@ pub mod calculate_limit_price_with_buffer {
     }
 }
 
+mod validate_fill_price_within_option_premium_bands {
+    use crate::math::orders::validate_fill_price_within_option_premium_bands;
+    use crate::state::perp_market::OptionType;
+    use crate::{MARGIN_PRECISION, PRICE_PRECISION_I64, PRICE_PRECISION_U64};
+
+    #[test]
+    fn call() {
+        let strike_price = 100 * PRICE_PRECISION_U64;
+        let oracle_price = 120 * PRICE_PRECISION_I64;
+        let margin_ratio_initial = MARGIN_PRECISION / 10;
+
+        // premium far from the underlying oracle price is fine for an option
+        assert!(validate_fill_price_within_option_premium_bands(
+            25 * PRICE_PRECISION_U64,
+            OptionType::Call,
+            strike_price,
+            oracle_price,
+            margin_ratio_initial,
+        )
+        .is_ok());
+
+        // well below intrinsic value
+        assert!(validate_fill_price_within_option_premium_bands(
+            5 * PRICE_PRECISION_U64,
+            OptionType::Call,
+            strike_price,
+            oracle_price,
+            margin_ratio_initial,
+        )
+        .is_err());
+
+        // worth more than the underlying
+        assert!(validate_fill_price_within_option_premium_bands(
+            140 * PRICE_PRECISION_U64,
+            OptionType::Call,
+            strike_price,
+            oracle_price,
+            margin_ratio_initial,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn put() {
+        let strike_price = 100 * PRICE_PRECISION_U64;
+        let oracle_price = 120 * PRICE_PRECISION_I64;
+        let margin_ratio_initial = MARGIN_PRECISION / 10;
+
+        assert!(validate_fill_price_within_option_premium_bands(
+            PRICE_PRECISION_U64,
+            OptionType::Put,
+            strike_price,
+            oracle_price,
+            margin_ratio_initial,
+        )
+        .is_ok());
+
+        // worth more than the strike
+        assert!(validate_fill_price_within_option_premium_bands(
+            strike_price + 1,
+            OptionType::Put,
+            strike_price,
+            oracle_price,
+            margin_ratio_initial,
+        )
+        .is_err());
+    }
+}
+
+mod validate_fill_price_within_prediction_market_bounds {
+    use crate::math::orders::validate_fill_price_within_prediction_market_bounds;
+    use crate::PRICE_PRECISION_U64;
+
+    #[test]
+    fn bounded() {
+        assert!(validate_fill_price_within_prediction_market_bounds(0).is_ok());
+        assert!(validate_fill_price_within_prediction_market_bounds(PRICE_PRECISION_U64).is_ok());
+        assert!(
+            validate_fill_price_within_prediction_market_bounds(PRICE_PRECISION_U64 + 1).is_err()
+        );
+    }
+}
+
 mod select_margin_type_for_perp_maker {
     use crate::math::margin::MarginRequirementType;
     use crate::math::orders::select_margin_type_for_perp_maker;
@@ -3861,3 +3967,333 @@ mod fallback_price_logic {
         assert_eq!(result, 99500000);
     }
 }
+
+mod calculate_trailing_stop_trigger_price {
+    use crate::controller::position::PositionDirection;
+    use crate::math::orders::calculate_trailing_stop_trigger_price;
+    use crate::state::user::{Order, OrderBitFlag, OrderTriggerCondition, OrderType};
+    use crate::{PERCENTAGE_PRECISION_I64, PRICE_PRECISION_I64, PRICE_PRECISION_U64};
+
+    #[test]
+    fn below_absolute_offset() {
+        let order = Order {
+            order_type: OrderType::TriggerMarket,
+            direction: PositionDirection::Short,
+            trigger_condition: OrderTriggerCondition::Below,
+            trigger_price: 90 * PRICE_PRECISION_U64,
+            oracle_price_offset: 5 * PRICE_PRECISION_I64 as i32,
+            ..Order::default()
+        };
+
+        // oracle moves up, trigger price ratchets up
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let trigger_price = calculate_trailing_stop_trigger_price(&order, oracle_price, 1).unwrap();
+        assert_eq!(trigger_price, 95 * PRICE_PRECISION_U64);
+
+        // oracle moves down, trigger price stays
+        let oracle_price = 91 * PRICE_PRECISION_I64;
+        let trigger_price = calculate_trailing_stop_trigger_price(&order, oracle_price, 1).unwrap();
+        assert_eq!(trigger_price, 90 * PRICE_PRECISION_U64);
+
+        // offset larger than oracle price, trigger price stays
+        let oracle_price = 4 * PRICE_PRECISION_I64;
+        let trigger_price = calculate_trailing_stop_trigger_price(&order, oracle_price, 1).unwrap();
+        assert_eq!(trigger_price, 90 * PRICE_PRECISION_U64);
+    }
+
+    #[test]
+    fn above_absolute_offset() {
+        let order = Order {
+            order_type: OrderType::TriggerMarket,
+            direction: PositionDirection::Long,
+            trigger_condition: OrderTriggerCondition::Above,
+            trigger_price: 110 * PRICE_PRECISION_U64,
+            oracle_price_offset: 5 * PRICE_PRECISION_I64 as i32,
+            ..Order::default()
+        };
+
+        // oracle moves down, trigger price ratchets down
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+        let trigger_price = calculate_trailing_stop_trigger_price(&order, oracle_price, 1).unwrap();
+        assert_eq!(trigger_price, 105 * PRICE_PRECISION_U64);
+
+        // oracle moves up, trigger price stays
+        let oracle_price = 108 * PRICE_PRECISION_I64;
+        let trigger_price = calculate_trailing_stop_trigger_price(&order, oracle_price, 1).unwrap();
+        assert_eq!(trigger_price, 110 * PRICE_PRECISION_U64);
+    }
+
+    #[test]
+    fn below_percentage_offset() {
+        let order = Order {
+            order_type: OrderType::TriggerLimit,
+            direction: PositionDirection::Short,
+            trigger_condition: OrderTriggerCondition::Below,
+            trigger_price: 90 * PRICE_PRECISION_U64,
+            price: 80 * PRICE_PRECISION_U64,
+            oracle_price_offset: (PERCENTAGE_PRECISION_I64 / 20) as i32, // 5%
+            bit_flags: OrderBitFlag::TrailingStopPercentageOffset as u8,
+            ..Order::default()
+        };
+
+        let oracle_price = 200 * PRICE_PRECISION_I64;
+        let trigger_price = calculate_trailing_stop_trigger_price(&order, oracle_price, 1).unwrap();
+        assert_eq!(trigger_price, 190 * PRICE_PRECISION_U64);
+
+        // rounds to tick size
+        let oracle_price = 200 * PRICE_PRECISION_I64 + 123;
+        let trigger_price =
+            calculate_trailing_stop_trigger_price(&order, oracle_price, 100).unwrap();
+        assert_eq!(trigger_price, 190 * PRICE_PRECISION_U64 + 200);
+    }
+}
+
+mod is_trailing_stop_update_paid {
+    use crate::math::orders::is_trailing_stop_update_paid;
+    use crate::{PRICE_PRECISION_I64, PRICE_PRECISION_U64};
+
+    #[test]
+    fn update_must_move_trigger_price_by_10_bps() {
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+
+        // 0.1 is 10 bps of 100
+        let paid = is_trailing_stop_update_paid(
+            90 * PRICE_PRECISION_U64,
+            90 * PRICE_PRECISION_U64 + PRICE_PRECISION_U64 / 10,
+            oracle_price,
+        )
+        .unwrap();
+        assert!(paid);
+
+        let paid = is_trailing_stop_update_paid(
+            90 * PRICE_PRECISION_U64,
+            90 * PRICE_PRECISION_U64 + PRICE_PRECISION_U64 / 10 - 1,
+            oracle_price,
+        )
+        .unwrap();
+        assert!(!paid);
+
+        // trigger prices ratcheting down for above conditions are paid the same
+        let paid = is_trailing_stop_update_paid(
+            110 * PRICE_PRECISION_U64,
+            105 * PRICE_PRECISION_U64,
+            oracle_price,
+        )
+        .unwrap();
+        assert!(paid);
+    }
+}
+
+mod calculate_quote_sized_order_base_asset_amount_unfilled {
+    use crate::math::constants::{BASE_PRECISION_U64, PRICE_PRECISION_U64, QUOTE_PRECISION_U64};
+    use crate::math::orders::{
+        calculate_base_asset_amount_for_quote_asset_amount,
+        calculate_quote_sized_order_base_asset_amount_unfilled,
+    };
+    use crate::state::user::{Order, OrderBitFlag};
+
+    #[test]
+    fn quote_to_base() {
+        let base_asset_amount = calculate_base_asset_amount_for_quote_asset_amount(
+            10000 * QUOTE_PRECISION_U64,
+            100 * PRICE_PRECISION_U64,
+            BASE_PRECISION_U64,
+        )
+        .unwrap();
+        assert_eq!(base_asset_amount, 100 * BASE_PRECISION_U64);
+
+        // spot market with 6 decimals
+        let base_asset_amount = calculate_base_asset_amount_for_quote_asset_amount(
+            10000 * QUOTE_PRECISION_U64,
+            100 * PRICE_PRECISION_U64,
+            1_000_000,
+        )
+        .unwrap();
+        assert_eq!(base_asset_amount, 100 * 1_000_000);
+
+        assert!(calculate_base_asset_amount_for_quote_asset_amount(
+            10000 * QUOTE_PRECISION_U64,
+            0,
+            BASE_PRECISION_U64,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn resized_at_fill_price() {
+        // $10,000 order, $4,750 filled buying 50 at $95
+        let order = Order {
+            base_asset_amount: 100 * BASE_PRECISION_U64,
+            base_asset_amount_filled: 50 * BASE_PRECISION_U64,
+            quote_asset_amount_filled: 4750 * QUOTE_PRECISION_U64,
+            trigger_price: 10000 * QUOTE_PRECISION_U64,
+            bit_flags: OrderBitFlag::QuoteSize as u8,
+            ..Order::default()
+        };
+
+        let base_asset_amount_unfilled = calculate_quote_sized_order_base_asset_amount_unfilled(
+            &order,
+            50 * BASE_PRECISION_U64,
+            4750 * QUOTE_PRECISION_U64,
+            BASE_PRECISION_U64 / 10,
+        )
+        .unwrap();
+
+        // $5,250 left at $95 = 55.26, rounded down to step size
+        assert_eq!(base_asset_amount_unfilled, 552 * BASE_PRECISION_U64 / 10);
+    }
+
+    #[test]
+    fn quote_fully_filled() {
+        let order = Order {
+            base_asset_amount: 100 * BASE_PRECISION_U64,
+            base_asset_amount_filled: 90 * BASE_PRECISION_U64,
+            quote_asset_amount_filled: 10000 * QUOTE_PRECISION_U64,
+            trigger_price: 10000 * QUOTE_PRECISION_U64,
+            bit_flags: OrderBitFlag::QuoteSize as u8,
+            ..Order::default()
+        };
+
+        let base_asset_amount_unfilled = calculate_quote_sized_order_base_asset_amount_unfilled(
+            &order,
+            90 * BASE_PRECISION_U64,
+            10000 * QUOTE_PRECISION_U64,
+            BASE_PRECISION_U64 / 10,
+        )
+        .unwrap();
+
+        assert_eq!(base_asset_amount_unfilled, 0);
+    }
+}
+
+mod get_perp_trigger_reference_price {
+    use crate::math::constants::{PRICE_PRECISION_I64, PRICE_PRECISION_U64};
+    use crate::math::orders::{get_perp_trigger_reference_price, get_spot_trigger_reference_price};
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::perp_market::{PerpMarket, AMM};
+    use crate::state::spot_market::SpotMarket;
+    use crate::state::user::{Order, OrderType, TriggerReferencePrice};
+
+    #[test]
+    fn reference_prices() {
+        let market = PerpMarket {
+            amm: AMM {
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap_5min: 99 * PRICE_PRECISION_I64,
+                    ..HistoricalOracleData::default()
+                },
+                last_mark_price_twap_5min: 98 * PRICE_PRECISION_U64,
+                ..AMM::default()
+            },
+            last_fill_price: 97 * PRICE_PRECISION_U64,
+            last_fill_price_twap_5min: 96 * PRICE_PRECISION_U64,
+            ..PerpMarket::default()
+        };
+
+        let oracle_price = 100 * PRICE_PRECISION_I64;
+
+        let mut order = Order {
+            order_type: OrderType::TriggerMarket,
+            ..Order::default()
+        };
+
+        let expected = [
+            (TriggerReferencePrice::Oracle, 100 * PRICE_PRECISION_I64),
+            (
+                TriggerReferencePrice::OracleTwap5Min,
+                99 * PRICE_PRECISION_I64,
+            ),
+            (
+                TriggerReferencePrice::MarkTwap5Min,
+                98 * PRICE_PRECISION_I64,
+            ),
+            (
+                TriggerReferencePrice::LastFillPrice,
+                97 * PRICE_PRECISION_I64,
+            ),
+            (
+                TriggerReferencePrice::LastFillPriceTwap5Min,
+                96 * PRICE_PRECISION_I64,
+            ),
+        ];
+
+        for (trigger_reference_price, expected_price) in expected {
+            order.trigger_reference_price = trigger_reference_price;
+            let price = get_perp_trigger_reference_price(&order, oracle_price, &market).unwrap();
+            assert_eq!(price, expected_price);
+        }
+    }
+
+    #[test]
+    fn no_fills_yet() {
+        let market = PerpMarket::default();
+
+        let order = Order {
+            order_type: OrderType::TriggerMarket,
+            trigger_reference_price: TriggerReferencePrice::LastFillPrice,
+            ..Order::default()
+        };
+
+        assert!(
+            get_perp_trigger_reference_price(&order, 100 * PRICE_PRECISION_I64, &market).is_err()
+        );
+    }
+
+    #[test]
+    fn spot_mark_twap_not_supported() {
+        let spot_market = SpotMarket::default();
+
+        let order = Order {
+            order_type: OrderType::TriggerMarket,
+            trigger_reference_price: TriggerReferencePrice::MarkTwap5Min,
+            ..Order::default()
+        };
+
+        assert!(
+            get_spot_trigger_reference_price(&order, 100 * PRICE_PRECISION_I64, &spot_market)
+                .is_err()
+        );
+    }
+}
+
+mod calculate_trigger_price_for_price_change {
+    use crate::math::constants::{
+        PERCENTAGE_PRECISION_I64, PRICE_PRECISION_I64, PRICE_PRECISION_U64,
+    };
+    use crate::math::orders::calculate_trigger_price_for_price_change;
+    use crate::state::user::OrderTriggerCondition;
+
+    #[test]
+    fn price_increase() {
+        let (trigger_price, trigger_condition) = calculate_trigger_price_for_price_change(
+            100 * PRICE_PRECISION_I64,
+            PERCENTAGE_PRECISION_I64 / 20,
+        )
+        .unwrap();
+
+        assert_eq!(trigger_price, 105 * PRICE_PRECISION_U64);
+        assert_eq!(trigger_condition, OrderTriggerCondition::Above);
+    }
+
+    #[test]
+    fn price_decrease() {
+        let (trigger_price, trigger_condition) = calculate_trigger_price_for_price_change(
+            100 * PRICE_PRECISION_I64,
+            -PERCENTAGE_PRECISION_I64 / 20,
+        )
+        .unwrap();
+
+        assert_eq!(trigger_price, 95 * PRICE_PRECISION_U64);
+        assert_eq!(trigger_condition, OrderTriggerCondition::Below);
+    }
+
+    #[test]
+    fn invalid_price_change() {
+        assert!(calculate_trigger_price_for_price_change(100 * PRICE_PRECISION_I64, 0).is_err());
+        assert!(calculate_trigger_price_for_price_change(
+            100 * PRICE_PRECISION_I64,
+            -PERCENTAGE_PRECISION_I64
+        )
+        .is_err());
+    }
+}
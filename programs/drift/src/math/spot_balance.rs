@@ -5,6 +5,7 @@ use crate::math::safe_math::{SafeDivFloor, SafeMath};
 use crate::state::oracle::{OraclePriceData, StrictOraclePrice};
 use crate::state::spot_market::{SpotBalanceType, SpotMarket};
 use crate::state::user::SpotPosition;
+use crate::validate;
 
 pub fn get_spot_balance(
     token_amount: u128,
@@ -253,6 +254,32 @@ pub fn get_token_value(
     }
 }
 
+pub fn get_token_amount_from_value(
+    token_value: i128,
+    spot_decimals: u32,
+    oracle_price: i64,
+) -> DriftResult<i128> {
+    if token_value == 0 {
+        return Ok(0);
+    }
+
+    validate!(
+        oracle_price > 0,
+        ErrorCode::InvalidOracle,
+        "oracle_price={} (<= 0)",
+        oracle_price
+    )?;
+
+    let precision_increase = 10_i128.pow(spot_decimals);
+    let value_with_precision = token_value.safe_mul(precision_increase)?;
+
+    if value_with_precision < 0 {
+        value_with_precision.safe_div_floor(oracle_price.cast()?)
+    } else {
+        value_with_precision.safe_div(oracle_price.cast()?)
+    }
+}
+
 pub fn get_balance_value(
     spot_position: &SpotPosition,
     spot_market: &SpotMarket,
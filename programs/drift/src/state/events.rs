@@ -343,6 +343,11 @@ pub enum OrderActionExplanation {
     OrderFilledWithAMMJitLPSplit,
     OrderFilledWithLPJit,
     DeriskLp,
+    LinkedOrderFilledOrTriggered,
+    SelfTradePrevention,
+    CancelOrdersAfterDeadline,
+    MarketMakerProtectionTriggered,
+    TrailingStopTriggerPriceUpdated,
 }
 
 impl Default for OrderAction {
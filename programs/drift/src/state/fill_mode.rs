@@ -12,6 +12,8 @@ pub enum FillMode {
     Fill,
     PlaceAndMake,
     PlaceAndTake,
+    /// A leg of a combo order. The taker's margin is checked once every leg is filled
+    PlaceAndTakeCombo,
 }
 
 impl FillMode {
@@ -26,7 +28,7 @@ impl FillMode {
             FillMode::Fill | FillMode::PlaceAndMake => {
                 order.get_limit_price(valid_oracle_price, None, slot, tick_size)
             }
-            FillMode::PlaceAndTake => {
+            FillMode::PlaceAndTake | FillMode::PlaceAndTakeCombo => {
                 if order.has_auction() {
                     calculate_auction_price(
                         order,
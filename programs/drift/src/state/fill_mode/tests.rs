@@ -48,4 +48,12 @@ fn test() {
         .unwrap();
 
     assert_eq!(limit_price, Some(120 * PRICE_PRECISION_U64));
+
+    let place_and_take_combo_mode = FillMode::PlaceAndTakeCombo;
+
+    let limit_price = place_and_take_combo_mode
+        .get_limit_price(&limit_order, oracle_price, slot, tick_size)
+        .unwrap();
+
+    assert_eq!(limit_price, Some(120 * PRICE_PRECISION_U64));
 }
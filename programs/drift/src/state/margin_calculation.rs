@@ -133,6 +133,23 @@ pub struct MarginCalculation {
     pub total_perp_pnl: i128,
     pub open_orders_margin_requirement: u128,
     tracked_market_margin_requirement: u128,
+    /// isolated perp positions are margined separately from the rest of the account and each other
+    isolated_margin_calculations: [Option<IsolatedMarginCalculation>; 8],
+}
+
+/// The margin of an isolated perp position, which is only backed by its own collateral
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IsolatedMarginCalculation {
+    pub market_index: u16,
+    pub total_collateral: i128,
+    pub margin_requirement: u128,
+    margin_requirement_plus_buffer: u128,
+}
+
+impl IsolatedMarginCalculation {
+    pub fn meets_margin_requirement(&self) -> bool {
+        self.total_collateral >= self.margin_requirement as i128
+    }
 }
 
 impl MarginCalculation {
@@ -153,6 +170,7 @@ impl MarginCalculation {
             total_perp_pnl: 0,
             open_orders_margin_requirement: 0,
             tracked_market_margin_requirement: 0,
+            isolated_margin_calculations: [None; 8],
         }
     }
 
@@ -189,6 +207,66 @@ impl MarginCalculation {
         Ok(())
     }
 
+    pub fn add_isolated_perp_market(
+        &mut self,
+        market_index: u16,
+    ) -> DriftResult<&mut IsolatedMarginCalculation> {
+        let index = match self.isolated_margin_calculations.iter().position(
+            |isolated| matches!(isolated, Some(isolated) if isolated.market_index == market_index),
+        ) {
+            Some(index) => index,
+            None => {
+                let index = self
+                    .isolated_margin_calculations
+                    .iter()
+                    .position(|isolated| isolated.is_none())
+                    .ok_or(ErrorCode::InvalidMarginCalculation)?;
+
+                self.isolated_margin_calculations[index] = Some(IsolatedMarginCalculation {
+                    market_index,
+                    ..IsolatedMarginCalculation::default()
+                });
+
+                index
+            }
+        };
+
+        self.isolated_margin_calculations[index]
+            .as_mut()
+            .ok_or(ErrorCode::InvalidMarginCalculation)
+    }
+
+    pub fn add_isolated_total_collateral(
+        &mut self,
+        market_index: u16,
+        total_collateral: i128,
+    ) -> DriftResult {
+        let isolated = self.add_isolated_perp_market(market_index)?;
+        isolated.total_collateral = isolated.total_collateral.safe_add(total_collateral)?;
+        Ok(())
+    }
+
+    pub fn add_isolated_margin_requirement(
+        &mut self,
+        market_index: u16,
+        margin_requirement: u128,
+        liability_value: u128,
+    ) -> DriftResult {
+        let margin_buffer = self.context.margin_buffer;
+        let isolated = self.add_isolated_perp_market(market_index)?;
+        isolated.margin_requirement = isolated.margin_requirement.safe_add(margin_requirement)?;
+
+        if margin_buffer > 0 {
+            isolated.margin_requirement_plus_buffer = isolated
+                .margin_requirement_plus_buffer
+                .safe_add(margin_requirement.safe_add(
+                    liability_value.safe_mul(margin_buffer)? / MARGIN_PRECISION_U128,
+                )?)?;
+        }
+
+        Ok(())
+    }
+
     pub fn add_open_orders_margin_requirement(&mut self, margin_requirement: u128) -> DriftResult {
         self.open_orders_margin_requirement = self
             .open_orders_margin_requirement
@@ -263,10 +341,40 @@ impl MarginCalculation {
             .safe_add(self.num_perp_liabilities)
     }
 
+    /// Whether the whole account, the cross margin account and every isolated perp position, is healthy
     pub fn meets_margin_requirement(&self) -> bool {
+        self.meets_cross_margin_requirement()
+            && self
+                .isolated_margin_calculations
+                .iter()
+                .flatten()
+                .all(|isolated| isolated.meets_margin_requirement())
+    }
+
+    pub fn meets_cross_margin_requirement(&self) -> bool {
         self.total_collateral >= self.margin_requirement as i128
     }
 
+    pub fn meets_isolated_margin_requirement(&self, market_index: u16) -> bool {
+        self.get_isolated_margin_calculation(market_index)
+            .map_or(true, |isolated| isolated.meets_margin_requirement())
+    }
+
+    /// An isolated perp market is only backed by its own collateral, every other market by the cross
+    /// margin account, so only the one backing the market is checked
+    pub fn meets_market_margin_requirement(&self, market: MarketIdentifier) -> bool {
+        match market.market_type {
+            MarketType::Perp
+                if self
+                    .get_isolated_margin_calculation(market.market_index)
+                    .is_some() =>
+            {
+                self.meets_isolated_margin_requirement(market.market_index)
+            }
+            _ => self.meets_cross_margin_requirement(),
+        }
+    }
+
     pub fn positions_meets_margin_requirement(&self) -> DriftResult<bool> {
         Ok(self.total_collateral
             >= self
@@ -284,6 +392,19 @@ impl MarginCalculation {
         Ok(self.total_collateral >= self.margin_requirement_plus_buffer as i128)
     }
 
+    pub fn can_exit_isolated_liquidation(&self, market_index: u16) -> DriftResult<bool> {
+        if !self.is_liquidation_mode() {
+            msg!("liquidation mode not enabled");
+            return Err(ErrorCode::InvalidMarginCalculation);
+        }
+
+        Ok(self
+            .get_isolated_margin_calculation(market_index)
+            .map_or(true, |isolated| {
+                isolated.total_collateral >= isolated.margin_requirement_plus_buffer as i128
+            }))
+    }
+
     pub fn margin_shortage(&self) -> DriftResult<u128> {
         if self.context.margin_buffer == 0 {
             msg!("margin buffer mode not enabled");
@@ -297,6 +418,24 @@ impl MarginCalculation {
             .unsigned_abs())
     }
 
+    pub fn isolated_margin_shortage(&self, market_index: u16) -> DriftResult<u128> {
+        if self.context.margin_buffer == 0 {
+            msg!("margin buffer mode not enabled");
+            return Err(ErrorCode::InvalidMarginCalculation);
+        }
+
+        let isolated = match self.get_isolated_margin_calculation(market_index) {
+            Some(isolated) => isolated,
+            None => return Ok(0),
+        };
+
+        Ok(isolated
+            .margin_requirement_plus_buffer
+            .cast::<i128>()?
+            .safe_sub(isolated.total_collateral)?
+            .unsigned_abs())
+    }
+
     pub fn tracked_market_margin_shortage(&self, margin_shortage: u128) -> DriftResult<u128> {
         if self.market_to_track_margin_requirement().is_none() {
             msg!("cant call tracked_market_margin_shortage");
@@ -343,4 +482,14 @@ impl MarginCalculation {
             }
         )
     }
+
+    pub fn get_isolated_margin_calculation(
+        &self,
+        market_index: u16,
+    ) -> Option<&IsolatedMarginCalculation> {
+        self.isolated_margin_calculations
+            .iter()
+            .flatten()
+            .find(|isolated| isolated.market_index == market_index)
+    }
 }
@@ -0,0 +1,182 @@
+use anchor_lang::prelude::*;
+
+use crate::controller::position::PositionDirection;
+use crate::error::{DriftResult, ErrorCode};
+use crate::math::casting::Cast;
+use crate::math::safe_math::SafeMath;
+use crate::state::traits::Size;
+use solana_program::msg;
+
+#[cfg(test)]
+mod tests;
+
+pub const MAX_MARKET_MAKER_PROTECTION_MARKETS: usize = 8;
+
+/// Tracks a user's maker fills per perp market. Once a market's thresholds are breached within a
+/// window, the user's orders in that market are cancelled and their maker orders in that market
+/// can't be filled until the user resets it
+#[account(zero_copy(unsafe))]
+#[derive(Default, Eq, PartialEq, Debug)]
+#[repr(C)]
+pub struct UserMarketMakerProtection {
+    /// The user account the protection is for
+    pub user: Pubkey,
+    pub markets: [MarketMakerProtection; 8],
+}
+
+impl Size for UserMarketMakerProtection {
+    const SIZE: usize = 488;
+}
+
+#[zero_copy(unsafe)]
+#[derive(Default, Eq, PartialEq, Debug)]
+#[repr(C)]
+pub struct MarketMakerProtection {
+    /// The slot the current window started
+    pub window_start_slot: u64,
+    /// Max base filled as maker within a window. 0 if disabled
+    /// precision: BASE_PRECISION
+    pub max_base_asset_amount: u64,
+    /// Max absolute net base filled as maker within a window. 0 if disabled
+    /// precision: BASE_PRECISION
+    pub max_delta: u64,
+    /// Base filled as maker in the current window
+    /// precision: BASE_PRECISION
+    pub base_asset_amount: u64,
+    /// Net base filled as maker in the current window
+    /// precision: BASE_PRECISION
+    pub delta: i64,
+    pub market_index: u16,
+    /// The length of the window in slots. 0 if the entry is unused
+    pub window_slots: u16,
+    /// Max number of maker fills within a window. 0 if disabled
+    pub max_fills: u16,
+    /// Number of maker fills in the current window
+    pub fills: u16,
+    /// Whether a threshold was breached. Maker orders in the market can't be filled until reset
+    pub triggered: bool,
+    pub padding: [u8; 7],
+}
+
+impl MarketMakerProtection {
+    pub fn is_available(&self) -> bool {
+        self.window_slots == 0 && !self.triggered
+    }
+
+    /// Records a maker fill in the current window
+    /// Returns true if one of the window's thresholds was breached
+    pub fn update(
+        &mut self,
+        base_asset_amount: u64,
+        direction: PositionDirection,
+        slot: u64,
+    ) -> DriftResult<bool> {
+        if self.window_slots == 0 {
+            return Ok(false);
+        }
+
+        if slot.safe_sub(self.window_start_slot)? >= self.window_slots.cast()? {
+            self.reset_window(slot);
+        }
+
+        let delta = match direction {
+            PositionDirection::Long => base_asset_amount.cast::<i64>()?,
+            PositionDirection::Short => -base_asset_amount.cast::<i64>()?,
+        };
+
+        self.base_asset_amount = self.base_asset_amount.safe_add(base_asset_amount)?;
+        self.delta = self.delta.safe_add(delta)?;
+        self.fills = self.fills.saturating_add(1);
+
+        let breached_base_asset_amount =
+            self.max_base_asset_amount != 0 && self.base_asset_amount >= self.max_base_asset_amount;
+        let breached_delta = self.max_delta != 0 && self.delta.unsigned_abs() >= self.max_delta;
+        let breached_fills = self.max_fills != 0 && self.fills >= self.max_fills;
+
+        if breached_base_asset_amount || breached_delta || breached_fills {
+            self.triggered = true;
+        }
+
+        Ok(self.triggered)
+    }
+
+    pub fn reset_window(&mut self, slot: u64) {
+        self.window_start_slot = slot;
+        self.base_asset_amount = 0;
+        self.delta = 0;
+        self.fills = 0;
+    }
+}
+
+impl UserMarketMakerProtection {
+    pub fn get_market(&self, market_index: u16) -> Option<&MarketMakerProtection> {
+        self.markets
+            .iter()
+            .find(|market| market.market_index == market_index && !market.is_available())
+    }
+
+    pub fn get_market_mut(&mut self, market_index: u16) -> DriftResult<&mut MarketMakerProtection> {
+        self.markets
+            .iter_mut()
+            .find(|market| market.market_index == market_index && !market.is_available())
+            .ok_or_else(|| {
+                msg!(
+                    "market maker protection not found for market {}",
+                    market_index
+                );
+                ErrorCode::InvalidMarketMakerProtection
+            })
+    }
+
+    pub fn force_get_market_mut(
+        &mut self,
+        market_index: u16,
+    ) -> DriftResult<&mut MarketMakerProtection> {
+        let index = match self
+            .markets
+            .iter()
+            .position(|market| market.market_index == market_index && !market.is_available())
+        {
+            Some(index) => index,
+            None => self
+                .markets
+                .iter()
+                .position(|market| market.is_available())
+                .ok_or_else(|| {
+                    msg!(
+                        "market maker protection already enabled for {} markets",
+                        MAX_MARKET_MAKER_PROTECTION_MARKETS
+                    );
+                    ErrorCode::InvalidMarketMakerProtection
+                })?,
+        };
+
+        let market = &mut self.markets[index];
+        market.market_index = market_index;
+
+        Ok(market)
+    }
+
+    /// Records a maker fill in the market. Returns true if the market's protection is triggered
+    pub fn update_market(
+        &mut self,
+        market_index: u16,
+        base_asset_amount: u64,
+        direction: PositionDirection,
+        slot: u64,
+    ) -> DriftResult<bool> {
+        match self
+            .markets
+            .iter_mut()
+            .find(|market| market.market_index == market_index && !market.is_available())
+        {
+            Some(market) => market.update(base_asset_amount, direction, slot),
+            None => Ok(false),
+        }
+    }
+
+    pub fn is_triggered(&self, market_index: u16) -> bool {
+        self.get_market(market_index)
+            .map_or(false, |market| market.triggered)
+    }
+}
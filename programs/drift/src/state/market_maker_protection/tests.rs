@@ -0,0 +1,199 @@
+mod update {
+    use crate::controller::position::PositionDirection;
+    use crate::state::market_maker_protection::MarketMakerProtection;
+    use crate::BASE_PRECISION_U64;
+
+    #[test]
+    fn disabled() {
+        let mut market_maker_protection = MarketMakerProtection {
+            max_fills: 1,
+            ..MarketMakerProtection::default()
+        };
+
+        let triggered = market_maker_protection
+            .update(BASE_PRECISION_U64, PositionDirection::Long, 1)
+            .unwrap();
+
+        assert!(!triggered);
+        assert_eq!(market_maker_protection.fills, 0);
+    }
+
+    #[test]
+    fn breach_delta() {
+        let mut market_maker_protection = MarketMakerProtection {
+            window_slots: 10,
+            max_delta: 2 * BASE_PRECISION_U64,
+            ..MarketMakerProtection::default()
+        };
+
+        let triggered = market_maker_protection
+            .update(BASE_PRECISION_U64, PositionDirection::Long, 10)
+            .unwrap();
+        assert!(!triggered);
+        assert_eq!(market_maker_protection.window_start_slot, 10);
+
+        // opposite fill nets out delta
+        let triggered = market_maker_protection
+            .update(BASE_PRECISION_U64, PositionDirection::Short, 11)
+            .unwrap();
+        assert!(!triggered);
+        assert_eq!(market_maker_protection.delta, 0);
+        assert_eq!(
+            market_maker_protection.base_asset_amount,
+            2 * BASE_PRECISION_U64
+        );
+
+        let triggered = market_maker_protection
+            .update(2 * BASE_PRECISION_U64, PositionDirection::Short, 12)
+            .unwrap();
+        assert!(triggered);
+        assert!(market_maker_protection.triggered);
+        assert_eq!(market_maker_protection.fills, 3);
+    }
+
+    #[test]
+    fn breach_base_asset_amount_and_fills() {
+        let mut market_maker_protection = MarketMakerProtection {
+            window_slots: 10,
+            max_base_asset_amount: 3 * BASE_PRECISION_U64,
+            max_fills: 3,
+            ..MarketMakerProtection::default()
+        };
+
+        let triggered = market_maker_protection
+            .update(2 * BASE_PRECISION_U64, PositionDirection::Long, 10)
+            .unwrap();
+        assert!(!triggered);
+
+        let triggered = market_maker_protection
+            .update(BASE_PRECISION_U64, PositionDirection::Short, 11)
+            .unwrap();
+        assert!(triggered);
+
+        // stays triggered in a new window until reset
+        let triggered = market_maker_protection
+            .update(1, PositionDirection::Long, 20)
+            .unwrap();
+        assert!(triggered);
+
+        market_maker_protection.triggered = false;
+        market_maker_protection.reset_window(20);
+
+        let triggered = market_maker_protection
+            .update(BASE_PRECISION_U64, PositionDirection::Long, 20)
+            .unwrap();
+        assert!(!triggered);
+        assert_eq!(market_maker_protection.fills, 1);
+
+        // new window resets counters
+        let triggered = market_maker_protection
+            .update(1, PositionDirection::Long, 30)
+            .unwrap();
+        assert!(!triggered);
+        assert_eq!(market_maker_protection.window_start_slot, 30);
+        assert_eq!(market_maker_protection.fills, 1);
+
+        let triggered = market_maker_protection
+            .update(1, PositionDirection::Long, 31)
+            .unwrap();
+        assert!(!triggered);
+
+        let triggered = market_maker_protection
+            .update(1, PositionDirection::Long, 32)
+            .unwrap();
+        assert!(triggered);
+    }
+}
+
+mod force_get_market_mut {
+    use crate::controller::position::PositionDirection;
+    use crate::state::market_maker_protection::{
+        UserMarketMakerProtection, MAX_MARKET_MAKER_PROTECTION_MARKETS,
+    };
+    use crate::BASE_PRECISION_U64;
+
+    #[test]
+    fn markets_tracked_separately() {
+        let mut user_market_maker_protection = UserMarketMakerProtection::default();
+
+        for market_index in 0..2 {
+            let market = user_market_maker_protection
+                .force_get_market_mut(market_index)
+                .unwrap();
+            market.window_slots = 10;
+            market.max_fills = 2;
+        }
+
+        for slot in 0..2 {
+            user_market_maker_protection
+                .get_market_mut(0)
+                .unwrap()
+                .update(BASE_PRECISION_U64, PositionDirection::Long, slot)
+                .unwrap();
+        }
+
+        assert!(user_market_maker_protection.is_triggered(0));
+        assert!(!user_market_maker_protection.is_triggered(1));
+        assert_eq!(user_market_maker_protection.get_market(1).unwrap().fills, 0);
+
+        // same entry is returned for a market already enabled
+        user_market_maker_protection
+            .force_get_market_mut(1)
+            .unwrap()
+            .max_fills = 3;
+        assert_eq!(user_market_maker_protection.markets[1].max_fills, 3);
+    }
+
+    #[test]
+    fn no_available_market() {
+        let mut user_market_maker_protection = UserMarketMakerProtection::default();
+
+        for market_index in 0..MAX_MARKET_MAKER_PROTECTION_MARKETS as u16 {
+            user_market_maker_protection
+                .force_get_market_mut(market_index)
+                .unwrap()
+                .window_slots = 10;
+        }
+
+        assert!(user_market_maker_protection
+            .force_get_market_mut(MAX_MARKET_MAKER_PROTECTION_MARKETS as u16)
+            .is_err());
+        assert!(user_market_maker_protection.get_market(8).is_none());
+        assert!(user_market_maker_protection.get_market_mut(8).is_err());
+    }
+
+    #[test]
+    fn disabled_market_reused() {
+        let mut user_market_maker_protection = UserMarketMakerProtection::default();
+
+        for market_index in 0..MAX_MARKET_MAKER_PROTECTION_MARKETS as u16 {
+            user_market_maker_protection
+                .force_get_market_mut(market_index)
+                .unwrap()
+                .window_slots = 10;
+        }
+
+        // triggered entry isn't freed by disabling the window
+        let market = user_market_maker_protection.get_market_mut(3).unwrap();
+        market.triggered = true;
+        market.window_slots = 0;
+        assert!(user_market_maker_protection
+            .force_get_market_mut(MAX_MARKET_MAKER_PROTECTION_MARKETS as u16)
+            .is_err());
+
+        user_market_maker_protection.markets[3].triggered = false;
+        assert!(user_market_maker_protection.get_market(3).is_none());
+
+        let market = user_market_maker_protection
+            .force_get_market_mut(MAX_MARKET_MAKER_PROTECTION_MARKETS as u16)
+            .unwrap();
+        market.window_slots = 10;
+        assert_eq!(
+            user_market_maker_protection.markets[3].market_index,
+            MAX_MARKET_MAKER_PROTECTION_MARKETS as u16
+        );
+        assert!(user_market_maker_protection
+            .get_market(MAX_MARKET_MAKER_PROTECTION_MARKETS as u16)
+            .is_some());
+    }
+}
@@ -4,12 +4,14 @@ pub mod fulfillment;
 pub mod fulfillment_params;
 pub mod insurance_fund_stake;
 pub mod margin_calculation;
+pub mod market_maker_protection;
 pub mod oracle;
 pub mod oracle_map;
 pub mod order_params;
 pub mod paused_operations;
 pub mod perp_market;
 pub mod perp_market_map;
+pub mod rfq;
 pub mod settle_pnl_mode;
 pub mod spot_fulfillment_params;
 pub mod spot_market;
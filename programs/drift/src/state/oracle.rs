@@ -385,7 +385,9 @@ pub struct PrelaunchOracle {
     // amm.last_update_slot at time oracle was updated
     pub amm_last_update_slot: u64,
     pub perp_market_index: u16,
-    pub padding: [u8; 70],
+    // can resolve the outcome of a prediction market using this oracle, in addition to the admin
+    pub resolver: Pubkey,
+    pub padding: [u8; 38],
 }
 
 impl Default for PrelaunchOracle {
@@ -397,7 +399,8 @@ impl Default for PrelaunchOracle {
             last_update_slot: 0,
             amm_last_update_slot: 0,
             perp_market_index: 0,
-            padding: [0; 70],
+            resolver: Pubkey::default(),
+            padding: [0; 38],
         }
     }
 }
@@ -467,4 +470,5 @@ pub struct PrelaunchOracleParams {
     pub perp_market_index: u16,
     pub price: Option<i64>,
     pub max_price: Option<i64>,
+    pub resolver: Option<Pubkey>,
 }
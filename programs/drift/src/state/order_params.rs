@@ -1,11 +1,16 @@
 use crate::controller::position::PositionDirection;
-use crate::error::DriftResult;
+use crate::error::{DriftResult, ErrorCode};
 use crate::math::casting::Cast;
+use crate::math::orders::calculate_base_asset_amount_for_quote_asset_amount;
 use crate::math::safe_math::SafeMath;
 use crate::math::safe_unwrap::SafeUnwrap;
 use crate::state::events::OrderActionExplanation;
+use crate::state::market_maker_protection::UserMarketMakerProtection;
 use crate::state::perp_market::{ContractTier, PerpMarket};
-use crate::state::user::{MarketType, OrderTriggerCondition, OrderType};
+use crate::state::user::{
+    AuctionCurve, MarketType, OrderBitFlag, OrderTriggerCondition, OrderType, TriggerReferencePrice,
+};
+use crate::validate;
 use crate::{
     OracleSource, PERCENTAGE_PRECISION_I64, PERCENTAGE_PRECISION_U64, PRICE_PRECISION_I64,
 };
@@ -35,6 +40,12 @@ pub struct OrderParams {
     pub auction_duration: Option<u8>,     // specified in slots
     pub auction_start_price: Option<i64>, // specified in price or oracle_price_offset
     pub auction_end_price: Option<i64>,   // specified in price or oracle_price_offset
+    pub bit_flags: u8,                    // see OrderBitFlag::ORDER_PARAMS
+    pub linked_order_group: u8,           // 0 if order isn't linked to other orders
+    pub twap_params: Option<TwapParams>,  // only for twap orders
+    pub trigger_reference_price: Option<TriggerReferencePrice>, // only for trigger orders, oracle if none
+    pub trigger_market_params: Option<TriggerMarketParams>, // only for cross market trigger orders
+    pub auction_curve: Option<AuctionCurve>,                // market default if none
 }
 
 impl OrderParams {
@@ -621,6 +632,45 @@ impl OrderParams {
 
         Ok(params)
     }
+
+    pub fn is_fill_or_kill(&self) -> bool {
+        self.bit_flags & OrderBitFlag::FillOrKill as u8 != 0
+    }
+
+    pub fn is_quote_sized(&self) -> bool {
+        self.bit_flags & OrderBitFlag::QuoteSize as u8 != 0
+    }
+
+    pub fn is_good_til_slot(&self) -> bool {
+        self.bit_flags & OrderBitFlag::GoodTilSlot as u8 != 0
+    }
+
+    pub fn is_linked_order_entry(&self) -> bool {
+        self.bit_flags & OrderBitFlag::LinkedOrderEntry as u8 != 0
+    }
+
+    /// For quote sized orders, base_asset_amount is the quote asset amount. Converts it to base using
+    /// the limit price, or the oracle price plus offset if there is no limit price
+    pub fn get_base_asset_amount_for_quote_size(
+        &self,
+        oracle_price: i64,
+        base_precision: u64,
+    ) -> DriftResult<u64> {
+        let price = if self.price != 0 {
+            self.price
+        } else {
+            oracle_price
+                .safe_add(self.oracle_price_offset.unwrap_or(0).cast()?)?
+                .max(1)
+                .cast()?
+        };
+
+        calculate_base_asset_amount_for_quote_asset_amount(
+            self.base_asset_amount,
+            price,
+            base_precision,
+        )
+    }
 }
 
 fn get_auction_duration(
@@ -656,6 +706,27 @@ impl Default for PostOnlyParam {
     }
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Copy, Eq, PartialEq, Debug)]
+pub struct TwapParams {
+    /// The number of slots between each slice
+    pub slice_interval: u64,
+    /// The base asset amount released each slice
+    /// precision: BASE_PRECISION
+    pub slice_base_asset_amount: u64,
+}
+
+/// The market whose reference price a cross market trigger order is triggered off
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Copy, Eq, PartialEq, Debug)]
+pub struct TriggerMarketParams {
+    pub market_type: MarketType,
+    pub market_index: u16,
+    /// If set, the order is triggered once the reference price moves this much from its value
+    /// when the order is placed. Positive triggers above, negative below. Replaces the trigger
+    /// price and condition
+    /// precision: PERCENTAGE_PRECISION
+    pub trigger_price_change: Option<i64>,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
 pub struct ModifyOrderParams {
     pub direction: Option<PositionDirection>,
@@ -674,6 +745,127 @@ pub struct ModifyOrderParams {
     pub policy: Option<ModifyOrderPolicy>,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct ModifyOrdersParams {
+    pub order_id: u32,
+    pub modify_order_params: ModifyOrderParams,
+}
+
+/// Compact two-sided quote for a single perp market. Each level is placed as a post only limit order,
+/// reusing the slots of the user's existing post only limit orders in the market
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct QuoteSetParams {
+    pub market_index: u16,
+    /// if set, level prices are reference_price + price_offset. otherwise levels are oracle offset orders
+    pub reference_price: Option<u64>,
+    pub bids: Vec<QuoteLevel>,
+    pub asks: Vec<QuoteLevel>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Copy, Eq, PartialEq, Debug)]
+pub struct QuoteLevel {
+    pub price_offset: i32,
+    pub base_asset_amount: u64,
+}
+
+/// Order signed off-chain by the taker's authority (or delegate) and placed by a filler or maker
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Copy, Eq, PartialEq, Debug)]
+pub struct SignedOrderParams {
+    pub order_params: OrderParams,
+    /// user account the order is placed for
+    pub taker: Pubkey,
+    /// must equal the taker's next_order_id, so a signed order can only be placed once
+    pub taker_next_order_id: u32,
+    /// last slot the signed order can be placed in
+    pub max_slot: u64,
+}
+
+pub const MAX_COMBO_LEGS: usize = 4;
+
+/// Orders across perp and spot markets that are taken atomically. If any leg isn't completely
+/// filled within its limit price, the whole instruction fails
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Eq, PartialEq, Debug)]
+pub struct ComboOrderParams {
+    pub legs: Vec<OrderParams>,
+}
+
+impl ComboOrderParams {
+    pub fn validate(&self) -> DriftResult {
+        validate!(
+            self.legs.len() >= 2 && self.legs.len() <= MAX_COMBO_LEGS,
+            ErrorCode::InvalidComboOrder,
+            "combo order must have between 2 and {} legs",
+            MAX_COMBO_LEGS
+        )?;
+
+        for (i, leg) in self.legs.iter().enumerate() {
+            validate!(
+                leg.order_type == OrderType::Limit,
+                ErrorCode::InvalidComboOrder,
+                "combo leg {} must be a limit order",
+                i
+            )?;
+
+            validate!(
+                leg.price > 0 && leg.oracle_price_offset.is_none(),
+                ErrorCode::InvalidComboOrder,
+                "combo leg {} must have a fixed limit price",
+                i
+            )?;
+
+            validate!(
+                leg.post_only == PostOnlyParam::None,
+                ErrorCode::InvalidComboOrder,
+                "combo leg {} cant be post only",
+                i
+            )?;
+
+            validate!(
+                leg.base_asset_amount > 0,
+                ErrorCode::InvalidComboOrder,
+                "combo leg {} must have a base asset amount",
+                i
+            )?;
+
+            validate!(
+                leg.twap_params.is_none() && leg.trigger_market_params.is_none(),
+                ErrorCode::InvalidComboOrder,
+                "combo leg {} cant be a twap or trigger order",
+                i
+            )?;
+
+            let is_duplicate = self.legs[..i].iter().any(|other| {
+                other.market_type == leg.market_type && other.market_index == leg.market_index
+            });
+
+            validate!(
+                !is_duplicate,
+                ErrorCode::InvalidComboOrder,
+                "combo leg {} market is used by another leg",
+                i
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Each leg is placed as an immediate or cancel limit order without an auction
+    pub fn get_leg_order_params(&self, leg_index: usize) -> DriftResult<OrderParams> {
+        let leg = self
+            .legs
+            .get(leg_index)
+            .ok_or(ErrorCode::InvalidComboOrder)?;
+
+        Ok(OrderParams {
+            immediate_or_cancel: true,
+            auction_duration: None,
+            auction_start_price: None,
+            auction_end_price: None,
+            ..*leg
+        })
+    }
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Eq, PartialEq)]
 pub enum ModifyOrderPolicy {
     TryModify,
@@ -686,25 +878,33 @@ impl Default for ModifyOrderPolicy {
     }
 }
 
-pub struct PlaceOrderOptions {
+pub struct PlaceOrderOptions<'a> {
     pub try_expire_orders: bool,
     pub enforce_margin_check: bool,
     pub risk_increasing: bool,
     pub explanation: OrderActionExplanation,
+    /// The user's market maker protection. Users with market maker protection can only place post
+    /// only orders in markets where it isn't triggered
+    pub market_maker_protection: Option<&'a UserMarketMakerProtection>,
+    /// Index of a resting post only order in user.orders to update in place. The order keeps its
+    /// order id and its slot instead of taking a new one
+    pub replace_order_index: Option<usize>,
 }
 
-impl Default for PlaceOrderOptions {
+impl Default for PlaceOrderOptions<'_> {
     fn default() -> Self {
         Self {
             try_expire_orders: true,
             enforce_margin_check: true,
             risk_increasing: false,
             explanation: OrderActionExplanation::None,
+            market_maker_protection: None,
+            replace_order_index: None,
         }
     }
 }
 
-impl PlaceOrderOptions {
+impl<'a> PlaceOrderOptions<'a> {
     pub fn update_risk_increasing(&mut self, risk_increasing: bool) {
         self.risk_increasing = self.risk_increasing || risk_increasing;
     }
@@ -713,4 +913,12 @@ impl PlaceOrderOptions {
         self.explanation = explanation;
         self
     }
+
+    pub fn market_maker_protection(
+        mut self,
+        market_maker_protection: Option<&'a UserMarketMakerProtection>,
+    ) -> Self {
+        self.market_maker_protection = market_maker_protection;
+        self
+    }
 }
@@ -1185,7 +1185,9 @@ mod get_close_perp_params {
             auction_end_price: params.auction_end_price.unwrap_or(0),
             auction_duration: params.auction_duration.unwrap_or(0),
             max_ts: 100,
-            padding: [0; 3],
+            bit_flags: params.bit_flags,
+            linked_order_group: params.linked_order_group,
+            trigger_reference_price: params.trigger_reference_price.unwrap_or_default(),
         }
     }
 
@@ -1250,3 +1252,102 @@ mod get_close_perp_params {
         validate_order(&order, &perp_market, Some(oracle_price), slot).unwrap();
     }
 }
+
+mod combo_order_params {
+    use crate::state::order_params::{ComboOrderParams, OrderParams, PostOnlyParam};
+    use crate::state::user::{MarketType, OrderType};
+    use crate::{PositionDirection, BASE_PRECISION_U64, PRICE_PRECISION_U64};
+
+    fn get_legs() -> Vec<OrderParams> {
+        vec![
+            OrderParams {
+                order_type: OrderType::Limit,
+                market_type: MarketType::Perp,
+                direction: PositionDirection::Long,
+                base_asset_amount: BASE_PRECISION_U64,
+                price: 100 * PRICE_PRECISION_U64,
+                market_index: 0,
+                ..OrderParams::default()
+            },
+            OrderParams {
+                order_type: OrderType::Limit,
+                market_type: MarketType::Spot,
+                direction: PositionDirection::Short,
+                base_asset_amount: BASE_PRECISION_U64,
+                price: 99 * PRICE_PRECISION_U64,
+                market_index: 1,
+                ..OrderParams::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn validate() {
+        let params = ComboOrderParams { legs: get_legs() };
+        assert!(params.validate().is_ok());
+
+        // single leg
+        let params = ComboOrderParams {
+            legs: get_legs()[..1].to_vec(),
+        };
+        assert!(params.validate().is_err());
+
+        // too many legs
+        let mut legs = get_legs();
+        for market_index in 2..5 {
+            legs.push(OrderParams {
+                market_index,
+                ..legs[0]
+            });
+        }
+        let params = ComboOrderParams { legs };
+        assert!(params.validate().is_err());
+
+        // same market twice
+        let mut legs = get_legs();
+        legs[1].market_type = MarketType::Perp;
+        legs[1].market_index = 0;
+        let params = ComboOrderParams { legs };
+        assert!(params.validate().is_err());
+
+        // same index in perp and spot is fine
+        let mut legs = get_legs();
+        legs[1].market_index = 0;
+        let params = ComboOrderParams { legs };
+        assert!(params.validate().is_ok());
+
+        let mut legs = get_legs();
+        legs[0].order_type = OrderType::Market;
+        let params = ComboOrderParams { legs };
+        assert!(params.validate().is_err());
+
+        let mut legs = get_legs();
+        legs[0].price = 0;
+        legs[0].oracle_price_offset = Some(100);
+        let params = ComboOrderParams { legs };
+        assert!(params.validate().is_err());
+
+        let mut legs = get_legs();
+        legs[1].post_only = PostOnlyParam::MustPostOnly;
+        let params = ComboOrderParams { legs };
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn get_leg_order_params() {
+        let mut legs = get_legs();
+        legs[0].auction_duration = Some(10);
+        legs[0].auction_start_price = Some(99 * PRICE_PRECISION_U64 as i64);
+        legs[0].auction_end_price = Some(100 * PRICE_PRECISION_U64 as i64);
+        let params = ComboOrderParams { legs };
+
+        let leg_params = params.get_leg_order_params(0).unwrap();
+        assert!(leg_params.immediate_or_cancel);
+        assert_eq!(leg_params.auction_duration, None);
+        assert_eq!(leg_params.auction_start_price, None);
+        assert_eq!(leg_params.auction_end_price, None);
+        assert_eq!(leg_params.price, 100 * PRICE_PRECISION_U64);
+
+        assert!(params.get_leg_order_params(2).is_err());
+    }
+}
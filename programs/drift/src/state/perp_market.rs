@@ -7,15 +7,14 @@ use crate::error::{DriftResult, ErrorCode};
 use crate::math::amm;
 use crate::math::casting::Cast;
 #[cfg(test)]
-use crate::math::constants::{
-    AMM_RESERVE_PRECISION, MAX_CONCENTRATION_COEFFICIENT, PRICE_PRECISION_I64,
-};
+use crate::math::constants::{AMM_RESERVE_PRECISION, MAX_CONCENTRATION_COEFFICIENT};
 use crate::math::constants::{
     AMM_RESERVE_PRECISION_I128, AMM_TO_QUOTE_PRECISION_RATIO, BID_ASK_SPREAD_PRECISION,
-    BID_ASK_SPREAD_PRECISION_U128, DEFAULT_REVENUE_SINCE_LAST_FUNDING_SPREAD_RETREAT,
-    LP_FEE_SLICE_DENOMINATOR, LP_FEE_SLICE_NUMERATOR, MARGIN_PRECISION_U128, PERCENTAGE_PRECISION,
-    PERCENTAGE_PRECISION_I128, PERCENTAGE_PRECISION_I64, PERCENTAGE_PRECISION_U64, PRICE_PRECISION,
-    SPOT_WEIGHT_PRECISION, TWENTY_FOUR_HOUR,
+    BID_ASK_SPREAD_PRECISION_U128, DEFAULT_REVENUE_SINCE_LAST_FUNDING_SPREAD_RETREAT, FIVE_MINUTE,
+    LP_FEE_SLICE_DENOMINATOR, LP_FEE_SLICE_NUMERATOR, MARGIN_PRECISION_U128,
+    MAX_PREDICTION_MARKET_PRICE_I64, PERCENTAGE_PRECISION, PERCENTAGE_PRECISION_I128,
+    PERCENTAGE_PRECISION_I64, PERCENTAGE_PRECISION_U64, PRICE_PRECISION, PRICE_PRECISION_I64,
+    QUOTE_SPOT_MARKET_INDEX, SPOT_WEIGHT_PRECISION, TWENTY_FOUR_HOUR,
 };
 use crate::math::helpers::get_proportion_i128;
 
@@ -23,18 +22,22 @@ use crate::math::margin::{
     calculate_size_discount_asset_weight, calculate_size_premium_liability_weight,
     MarginRequirementType,
 };
+use crate::math::options::calculate_option_intrinsic_value;
 use crate::math::safe_math::SafeMath;
+use crate::math::spot_balance::{get_token_amount_from_value, get_token_value};
 use crate::math::stats;
 use crate::state::events::OrderActionExplanation;
 
 use crate::state::oracle::{
     get_prelaunch_price, get_switchboard_price, HistoricalOracleData, OracleSource,
 };
-use crate::state::spot_market::{AssetTier, SpotBalance, SpotBalanceType};
+use crate::state::spot_market::{AssetTier, SpotBalance, SpotBalanceType, SpotMarket};
 use crate::state::traits::{MarketIndexOffset, Size};
 use borsh::{BorshDeserialize, BorshSerialize};
 
 use crate::state::paused_operations::PerpOperation;
+use crate::state::user::AuctionCurve;
+use crate::validate;
 use drift_macros::assert_no_slop;
 use static_assertions::const_assert_eq;
 
@@ -90,6 +93,10 @@ impl MarketStatus {
 pub enum ContractType {
     Perpetual,
     Future,
+    /// european option on the oracle asset. orderbook only, settled at its intrinsic value on expiry
+    Option,
+    /// binary contract priced between 0 and 1. orderbook only, settled at exactly 0 or 1 once the outcome is resolved
+    Prediction,
 }
 
 impl Default for ContractType {
@@ -98,6 +105,65 @@ impl Default for ContractType {
     }
 }
 
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+pub enum OptionType {
+    Call,
+    Put,
+}
+
+impl Default for OptionType {
+    fn default() -> Self {
+        OptionType::Call
+    }
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+pub enum PredictionMarketOutcome {
+    Unresolved,
+    Yes,
+    No,
+}
+
+impl Default for PredictionMarketOutcome {
+    fn default() -> Self {
+        PredictionMarketOutcome::Unresolved
+    }
+}
+
+impl PredictionMarketOutcome {
+    pub fn is_resolved(&self) -> bool {
+        *self != PredictionMarketOutcome::Unresolved
+    }
+
+    pub fn get_expiry_price(&self) -> DriftResult<i64> {
+        match self {
+            PredictionMarketOutcome::Yes => Ok(MAX_PREDICTION_MARKET_PRICE_I64),
+            PredictionMarketOutcome::No => Ok(0),
+            PredictionMarketOutcome::Unresolved => {
+                msg!("prediction market outcome is unresolved");
+                Err(ErrorCode::MarketSettlementTargetPriceInvalid)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+pub enum MatchingPolicy {
+    /// Makers are filled by price and then in the order they are passed in
+    PriceTime,
+    /// Makers at the same price level are filled in proportion to their size
+    ProRata,
+    /// The earliest maker order at a price level gets top_of_book_allocation of the fill,
+    /// the rest is split pro rata
+    Hybrid,
+}
+
+impl Default for MatchingPolicy {
+    fn default() -> Self {
+        MatchingPolicy::PriceTime
+    }
+}
+
 #[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq, PartialOrd, Ord)]
 pub enum ContractTier {
     /// max insurance capped at A level
@@ -175,6 +241,7 @@ pub struct PerpMarket {
     /// precision = QUOTE_PRECISION
     pub unrealized_pnl_max_imbalance: u64,
     /// The ts when the market will be expired. Only set if market is in reduce only mode
+    /// or if the market is an option, in which case it is the option's expiry
     pub expiry_ts: i64,
     /// The price at which positions will be settled. Only set if market is expired
     /// precision = PRICE_PRECISION
@@ -232,7 +299,31 @@ pub struct PerpMarket {
     /// E.g. if this is -50 and the fee is 5bps, the new fee will be 2.5bps
     /// if this is 50 and the fee is 5bps, the new fee will be 7.5bps
     pub fee_adjustment: i16,
-    pub padding: [u8; 46],
+    /// The auction curve used for orders that don't specify one
+    pub default_auction_curve: AuctionCurve,
+    /// How fills are split between makers quoting the same price
+    pub matching_policy: MatchingPolicy,
+    /// The percentage of a fill given to the earliest maker order at a price level for the hybrid matching policy
+    pub top_of_book_allocation: u8,
+    /// The number of slots in a batch auction window. Taker orders are only cleared by
+    /// clear_perp_batch_auction once their window has passed. 0 for continuous matching
+    pub batch_auction_duration: u8,
+    /// Whether an option market is a call or a put. Only used if contract type is Option
+    pub option_type: OptionType,
+    /// The resolved outcome of a prediction market. Only used if contract type is Prediction
+    pub prediction_market_outcome: PredictionMarketOutcome,
+    /// The average price of the last fill in the market
+    /// precision: PRICE_PRECISION
+    pub last_fill_price: u64,
+    /// The strike price of an option market. Only used if contract type is Option
+    /// precision: PRICE_PRECISION
+    pub strike_price: u64,
+    /// The 5 minute twap of fill prices in the market
+    /// precision: PRICE_PRECISION
+    pub last_fill_price_twap_5min: u64,
+    /// The unix timestamp of the last fill in the market
+    pub last_fill_price_ts: i64,
+    pub padding: [u8; 8],
 }
 
 impl Default for PerpMarket {
@@ -266,7 +357,17 @@ impl Default for PerpMarket {
             paused_operations: 0,
             quote_spot_market_index: 0,
             fee_adjustment: 0,
-            padding: [0; 46],
+            default_auction_curve: AuctionCurve::Linear,
+            matching_policy: MatchingPolicy::PriceTime,
+            top_of_book_allocation: 0,
+            batch_auction_duration: 0,
+            option_type: OptionType::default(),
+            prediction_market_outcome: PredictionMarketOutcome::default(),
+            last_fill_price: 0,
+            strike_price: 0,
+            last_fill_price_twap_5min: 0,
+            last_fill_price_ts: 0,
+            padding: [0; 8],
         }
     }
 }
@@ -280,6 +381,111 @@ impl MarketIndexOffset for PerpMarket {
 }
 
 impl PerpMarket {
+    /// Records the price of a fill in the market and updates the 5 minute twap of fill prices
+    pub fn update_last_fill_price(&mut self, fill_price: u64, now: i64) -> DriftResult {
+        self.last_fill_price_twap_5min = if self.last_fill_price_ts == 0 {
+            fill_price
+        } else {
+            stats::calculate_new_twap(
+                fill_price.cast()?,
+                now,
+                self.last_fill_price_twap_5min.cast()?,
+                self.last_fill_price_ts,
+                FIVE_MINUTE as i64,
+            )?
+            .cast()?
+        };
+        self.last_fill_price = fill_price;
+        self.last_fill_price_ts = now;
+
+        Ok(())
+    }
+
+    /// pnl, funding, fees and insurance of markets quoted in the usdc spot market are tracked in its token.
+    /// markets quoted in another spot market track them in usd (QUOTE_PRECISION) and convert them to the
+    /// quote spot market's token at its last oracle price whenever they move through a pool or user balance
+    pub fn is_quote_converted(&self) -> bool {
+        self.quote_spot_market_index != QUOTE_SPOT_MARKET_INDEX
+    }
+
+    /// price to value the market's quote amounts at in margin calculations
+    pub fn get_quote_asset_price(&self, quote_oracle_price: i64) -> i64 {
+        if self.is_quote_converted() {
+            PRICE_PRECISION_I64
+        } else {
+            quote_oracle_price
+        }
+    }
+
+    /// quote spot market token amount for a quote amount
+    pub fn get_quote_token_amount(
+        &self,
+        quote_amount: i128,
+        quote_spot_market: &SpotMarket,
+    ) -> DriftResult<i128> {
+        if !self.is_quote_converted() {
+            return Ok(quote_amount);
+        }
+
+        get_token_amount_from_value(
+            quote_amount,
+            quote_spot_market.decimals,
+            quote_spot_market.historical_oracle_data.last_oracle_price,
+        )
+    }
+
+    /// quote amount for a quote spot market token amount
+    pub fn get_quote_amount(
+        &self,
+        token_amount: i128,
+        quote_spot_market: &SpotMarket,
+    ) -> DriftResult<i128> {
+        if !self.is_quote_converted() {
+            return Ok(token_amount);
+        }
+
+        get_token_value(
+            token_amount,
+            quote_spot_market.decimals,
+            quote_spot_market.historical_oracle_data.last_oracle_price,
+        )
+    }
+
+    pub fn is_option(&self) -> bool {
+        self.contract_type == ContractType::Option
+    }
+
+    pub fn is_prediction_market(&self) -> bool {
+        self.contract_type == ContractType::Prediction
+    }
+
+    /// options and prediction markets only trade against makers, the amm is never used
+    pub fn is_orderbook_only(&self) -> bool {
+        self.is_option() || self.is_prediction_market()
+    }
+
+    /// price the market's positions are marked at. options are marked at their intrinsic value,
+    /// prediction markets are bounded to [0, 1] and settled markets are marked at the expiry price
+    pub fn get_valuation_price(&self, oracle_price: i64) -> DriftResult<i64> {
+        if self.status == MarketStatus::Settlement {
+            Ok(self.expiry_price)
+        } else if self.is_option() {
+            calculate_option_intrinsic_value(self.option_type, self.strike_price, oracle_price)
+        } else if self.is_prediction_market() {
+            Ok(oracle_price.clamp(0, MAX_PREDICTION_MARKET_PRICE_I64))
+        } else {
+            Ok(oracle_price)
+        }
+    }
+
+    pub fn is_in_batch_auction_mode(&self) -> bool {
+        self.batch_auction_duration != 0
+    }
+
+    pub fn get_batch_auction_window(&self, slot: u64) -> DriftResult<u32> {
+        slot.safe_div(self.batch_auction_duration.cast()?)?.cast()
+    }
+
     pub fn is_in_settlement(&self, now: i64) -> bool {
         let in_settlement = matches!(
             self.status,
@@ -578,6 +784,21 @@ impl PerpMarket {
 
         Ok(true)
     }
+
+    pub fn validate_quote_spot_market(&self, quote_spot_market: &SpotMarket) -> DriftResult {
+        validate!(
+            !matches!(
+                quote_spot_market.status,
+                MarketStatus::Settlement | MarketStatus::Delisted
+            ),
+            ErrorCode::InvalidSpotMarketAccount,
+            "quote spot market {} is {:?}",
+            quote_spot_market.market_index,
+            quote_spot_market.status
+        )?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
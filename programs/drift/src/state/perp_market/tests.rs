@@ -40,3 +40,260 @@ mod amm {
         assert_eq!(discount, 10000000); // $1
     }
 }
+
+mod validate_quote_spot_market {
+    use crate::state::perp_market::{MarketStatus, PerpMarket};
+    use crate::state::spot_market::SpotMarket;
+
+    #[test]
+    fn quote_decimals() {
+        let perp_market = PerpMarket {
+            quote_spot_market_index: 1,
+            ..PerpMarket::default()
+        };
+
+        let usdt_spot_market = SpotMarket {
+            market_index: 1,
+            decimals: 6,
+            status: MarketStatus::Active,
+            ..SpotMarket::default()
+        };
+        assert!(perp_market
+            .validate_quote_spot_market(&usdt_spot_market)
+            .is_ok());
+
+        let sol_spot_market = SpotMarket {
+            market_index: 1,
+            decimals: 9,
+            status: MarketStatus::Active,
+            ..SpotMarket::default()
+        };
+        assert!(perp_market
+            .validate_quote_spot_market(&sol_spot_market)
+            .is_ok());
+    }
+
+    #[test]
+    fn quote_status() {
+        let perp_market = PerpMarket {
+            quote_spot_market_index: 1,
+            ..PerpMarket::default()
+        };
+
+        let spot_market = SpotMarket {
+            market_index: 1,
+            decimals: 6,
+            status: MarketStatus::Settlement,
+            ..SpotMarket::default()
+        };
+        assert!(perp_market
+            .validate_quote_spot_market(&spot_market)
+            .is_err());
+    }
+}
+
+mod get_quote_token_amount {
+    use crate::math::constants::{
+        PRICE_PRECISION_I64, QUOTE_PRECISION_I128, QUOTE_SPOT_MARKET_INDEX,
+    };
+    use crate::state::oracle::HistoricalOracleData;
+    use crate::state::perp_market::PerpMarket;
+    use crate::state::spot_market::SpotMarket;
+
+    #[test]
+    fn usdc_quote() {
+        let perp_market = PerpMarket {
+            quote_spot_market_index: QUOTE_SPOT_MARKET_INDEX,
+            ..PerpMarket::default()
+        };
+
+        let usdc_spot_market = SpotMarket {
+            decimals: 6,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price: 99 * PRICE_PRECISION_I64 / 100,
+                ..HistoricalOracleData::default()
+            },
+            ..SpotMarket::default()
+        };
+
+        assert!(!perp_market.is_quote_converted());
+        assert_eq!(
+            perp_market
+                .get_quote_token_amount(25 * QUOTE_PRECISION_I128, &usdc_spot_market)
+                .unwrap(),
+            25 * QUOTE_PRECISION_I128
+        );
+        assert_eq!(
+            perp_market
+                .get_quote_amount(-25 * QUOTE_PRECISION_I128, &usdc_spot_market)
+                .unwrap(),
+            -25 * QUOTE_PRECISION_I128
+        );
+        assert_eq!(
+            perp_market.get_quote_asset_price(99 * PRICE_PRECISION_I64 / 100),
+            99 * PRICE_PRECISION_I64 / 100
+        );
+    }
+
+    #[test]
+    fn sol_quote() {
+        let perp_market = PerpMarket {
+            quote_spot_market_index: 1,
+            ..PerpMarket::default()
+        };
+
+        let sol_spot_market = SpotMarket {
+            market_index: 1,
+            decimals: 9,
+            historical_oracle_data: HistoricalOracleData {
+                last_oracle_price: 100 * PRICE_PRECISION_I64,
+                ..HistoricalOracleData::default()
+            },
+            ..SpotMarket::default()
+        };
+
+        assert!(perp_market.is_quote_converted());
+        assert_eq!(
+            perp_market.get_quote_asset_price(100 * PRICE_PRECISION_I64),
+            PRICE_PRECISION_I64
+        );
+
+        // $250 = 2.5 sol
+        assert_eq!(
+            perp_market
+                .get_quote_token_amount(250 * QUOTE_PRECISION_I128, &sol_spot_market)
+                .unwrap(),
+            2_500_000_000
+        );
+        assert_eq!(
+            perp_market
+                .get_quote_amount(2_500_000_000, &sol_spot_market)
+                .unwrap(),
+            250 * QUOTE_PRECISION_I128
+        );
+
+        // losses round against the user
+        assert_eq!(
+            perp_market
+                .get_quote_token_amount(-1, &sol_spot_market)
+                .unwrap(),
+            -10
+        );
+        assert_eq!(
+            perp_market.get_quote_amount(-9, &sol_spot_market).unwrap(),
+            -1
+        );
+    }
+}
+
+mod get_valuation_price {
+    use crate::math::constants::{MAX_PREDICTION_MARKET_PRICE_I64, PRICE_PRECISION_U64};
+    use crate::state::perp_market::{ContractType, MarketStatus, OptionType, PerpMarket};
+
+    #[test]
+    fn option() {
+        let perpetual = PerpMarket::default();
+        assert_eq!(
+            perpetual.get_valuation_price(80_000_000).unwrap(),
+            80_000_000
+        );
+
+        let mut put = PerpMarket {
+            contract_type: ContractType::Option,
+            option_type: OptionType::Put,
+            strike_price: 100 * PRICE_PRECISION_U64,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        assert_eq!(put.get_valuation_price(80_000_000).unwrap(), 20_000_000);
+        assert_eq!(put.get_valuation_price(120_000_000).unwrap(), 0);
+
+        put.status = MarketStatus::Settlement;
+        put.expiry_price = 15_000_000;
+        assert_eq!(put.get_valuation_price(80_000_000).unwrap(), 15_000_000);
+    }
+
+    #[test]
+    fn prediction_market() {
+        let mut market = PerpMarket {
+            contract_type: ContractType::Prediction,
+            status: MarketStatus::Active,
+            ..PerpMarket::default()
+        };
+        assert_eq!(market.get_valuation_price(400_000).unwrap(), 400_000);
+        assert_eq!(
+            market.get_valuation_price(1_200_000).unwrap(),
+            MAX_PREDICTION_MARKET_PRICE_I64
+        );
+        assert_eq!(market.get_valuation_price(-1).unwrap(), 0);
+
+        market.status = MarketStatus::Settlement;
+        market.expiry_price = MAX_PREDICTION_MARKET_PRICE_I64;
+        assert_eq!(
+            market.get_valuation_price(400_000).unwrap(),
+            MAX_PREDICTION_MARKET_PRICE_I64
+        );
+    }
+}
+
+mod prediction_market_outcome {
+    use crate::math::constants::MAX_PREDICTION_MARKET_PRICE_I64;
+    use crate::state::perp_market::PredictionMarketOutcome;
+
+    #[test]
+    fn get_expiry_price() {
+        assert_eq!(
+            PredictionMarketOutcome::Yes.get_expiry_price().unwrap(),
+            MAX_PREDICTION_MARKET_PRICE_I64
+        );
+        assert_eq!(PredictionMarketOutcome::No.get_expiry_price().unwrap(), 0);
+        assert!(PredictionMarketOutcome::Unresolved
+            .get_expiry_price()
+            .is_err());
+    }
+}
+
+mod update_last_fill_price {
+    use crate::math::constants::PRICE_PRECISION_U64;
+    use crate::state::perp_market::PerpMarket;
+
+    #[test]
+    fn first_fill_sets_twap() {
+        let mut market = PerpMarket::default();
+
+        market
+            .update_last_fill_price(100 * PRICE_PRECISION_U64, 1000)
+            .unwrap();
+
+        assert_eq!(market.last_fill_price, 100 * PRICE_PRECISION_U64);
+        assert_eq!(market.last_fill_price_twap_5min, 100 * PRICE_PRECISION_U64);
+        assert_eq!(market.last_fill_price_ts, 1000);
+    }
+
+    #[test]
+    fn twap_weights_by_time_since_last_fill() {
+        let mut market = PerpMarket {
+            last_fill_price: 100 * PRICE_PRECISION_U64,
+            last_fill_price_twap_5min: 100 * PRICE_PRECISION_U64,
+            last_fill_price_ts: 1000,
+            ..PerpMarket::default()
+        };
+
+        // a minute after the last fill, the new price gets 60 / 300 of the weight (rounded up)
+        market
+            .update_last_fill_price(110 * PRICE_PRECISION_U64, 1060)
+            .unwrap();
+
+        assert_eq!(market.last_fill_price, 110 * PRICE_PRECISION_U64);
+        assert_eq!(market.last_fill_price_twap_5min, 102000001);
+        assert_eq!(market.last_fill_price_ts, 1060);
+
+        // fills in the same second don't move the twap
+        market
+            .update_last_fill_price(200 * PRICE_PRECISION_U64, 1060)
+            .unwrap();
+
+        assert_eq!(market.last_fill_price, 200 * PRICE_PRECISION_U64);
+        assert_eq!(market.last_fill_price_twap_5min, 102000001);
+    }
+}
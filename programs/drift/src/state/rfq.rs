@@ -0,0 +1,107 @@
+use anchor_lang::prelude::*;
+
+use crate::controller::position::PositionDirection;
+use crate::error::{DriftResult, ErrorCode};
+use crate::state::traits::Size;
+use crate::validate;
+use solana_program::msg;
+
+#[cfg(test)]
+mod tests;
+
+pub const MAX_RFQ_MAKERS: usize = 4;
+
+/// A request for quote for a perp block trade. The taker names the makers allowed to quote and
+/// accepts one quote, which is settled as a single maker/taker fill
+#[account(zero_copy(unsafe))]
+#[derive(Default, Eq, PartialEq, Debug)]
+#[repr(C)]
+pub struct Rfq {
+    /// The taker user account
+    pub user: Pubkey,
+    /// The taker user's authority
+    pub authority: Pubkey,
+    /// The maker user accounts allowed to quote
+    pub whitelisted_makers: [Pubkey; 4],
+    /// The quote from each whitelisted maker, by index
+    pub quotes: [RfqQuote; 4],
+    /// The size of the block trade
+    /// precision: BASE_PRECISION
+    pub base_asset_amount: u64,
+    /// Unix timestamp after which quotes can no longer be submitted or accepted
+    pub expiry_ts: i64,
+    pub market_index: u16,
+    /// The taker's direction
+    pub direction: PositionDirection,
+    pub padding: [u8; 5],
+}
+
+impl Size for Rfq {
+    const SIZE: usize = 288;
+}
+
+#[zero_copy(unsafe)]
+#[derive(Default, Eq, PartialEq, Debug)]
+#[repr(C)]
+pub struct RfqQuote {
+    /// The price the maker will trade the full size at. 0 if the maker hasn't quoted
+    /// precision: PRICE_PRECISION
+    pub price: u64,
+    /// Unix timestamp after which the quote can't be accepted
+    pub expiry_ts: i64,
+}
+
+impl Rfq {
+    pub fn get_maker_index(&self, maker: &Pubkey) -> DriftResult<usize> {
+        self.whitelisted_makers
+            .iter()
+            .position(|whitelisted_maker| {
+                whitelisted_maker == maker && *whitelisted_maker != Pubkey::default()
+            })
+            .ok_or_else(|| {
+                msg!("maker {} not whitelisted for rfq", maker);
+                ErrorCode::InvalidRfq
+            })
+    }
+
+    pub fn validate_not_expired(&self, now: i64) -> DriftResult {
+        validate!(
+            now <= self.expiry_ts,
+            ErrorCode::RfqExpired,
+            "rfq expired at {}",
+            self.expiry_ts
+        )
+    }
+
+    pub fn get_quote(&self, maker: &Pubkey, now: i64) -> DriftResult<RfqQuote> {
+        self.validate_not_expired(now)?;
+
+        let quote = self.quotes[self.get_maker_index(maker)?];
+
+        validate!(
+            quote.price > 0,
+            ErrorCode::InvalidRfq,
+            "maker {} has not quoted",
+            maker
+        )?;
+
+        validate!(
+            now <= quote.expiry_ts,
+            ErrorCode::RfqExpired,
+            "quote from maker {} expired at {}",
+            maker,
+            quote.expiry_ts
+        )?;
+
+        Ok(quote)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct RfqParams {
+    pub market_index: u16,
+    pub direction: PositionDirection,
+    pub base_asset_amount: u64,
+    pub expiry_ts: i64,
+    pub whitelisted_makers: Vec<Pubkey>,
+}
@@ -0,0 +1,44 @@
+mod get_quote {
+    use crate::error::ErrorCode;
+    use crate::math::constants::PRICE_PRECISION_U64;
+    use crate::state::rfq::{Rfq, RfqQuote};
+    use anchor_lang::prelude::Pubkey;
+
+    #[test]
+    fn test() {
+        let maker = Pubkey::new_unique();
+        let other_maker = Pubkey::new_unique();
+
+        let mut rfq = Rfq {
+            expiry_ts: 100,
+            ..Rfq::default()
+        };
+        rfq.whitelisted_makers[1] = maker;
+        rfq.whitelisted_makers[2] = other_maker;
+        rfq.quotes[1] = RfqQuote {
+            price: 100 * PRICE_PRECISION_U64,
+            expiry_ts: 50,
+        };
+
+        assert_eq!(rfq.get_maker_index(&maker).unwrap(), 1);
+        assert_eq!(
+            rfq.get_maker_index(&Pubkey::new_unique()),
+            Err(ErrorCode::InvalidRfq)
+        );
+        assert_eq!(
+            rfq.get_maker_index(&Pubkey::default()),
+            Err(ErrorCode::InvalidRfq)
+        );
+
+        assert_eq!(rfq.get_quote(&maker, 50).unwrap(), rfq.quotes[1]);
+
+        // quote expired
+        assert_eq!(rfq.get_quote(&maker, 51), Err(ErrorCode::RfqExpired));
+
+        // rfq expired
+        assert_eq!(rfq.get_quote(&maker, 101), Err(ErrorCode::RfqExpired));
+
+        // maker hasn't quoted
+        assert_eq!(rfq.get_quote(&other_maker, 50), Err(ErrorCode::InvalidRfq));
+    }
+}
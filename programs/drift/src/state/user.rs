@@ -117,7 +117,14 @@ pub struct User {
     pub open_auctions: u8,
     /// Whether or not user has open order with auction
     pub has_open_auction: bool,
-    pub padding: [u8; 21],
+    /// Whether the user has a UserMarketMakerProtection account. Its maker orders are only filled
+    /// when that account is provided
+    pub has_market_maker_protection: bool,
+    pub padding1: [u8; 4],
+    /// The unix timestamp after which anyone can cancel the user's open orders. 0 if not set
+    /// Acts as a dead man's switch, kept in the future by heartbeats from the user
+    pub cancel_orders_after_ts: i64,
+    pub padding: [u8; 8],
 }
 
 impl User {
@@ -133,6 +140,10 @@ impl User {
         self.status & (UserStatus::ReduceOnly as u8) > 0
     }
 
+    pub fn can_cancel_orders_after_deadline(&self, now: i64) -> bool {
+        self.cancel_orders_after_ts != 0 && now >= self.cancel_orders_after_ts
+    }
+
     pub fn is_advanced_lp(&self) -> bool {
         self.status & (UserStatus::AdvancedLp as u8) > 0
     }
@@ -158,7 +169,10 @@ impl User {
 
         self.spot_positions
             .iter()
-            .position(|spot_position| spot_position.market_index == market_index)
+            .position(|spot_position| {
+                spot_position.market_index == market_index
+                    && !spot_position.is_isolated_perp_collateral
+            })
             .ok_or(ErrorCode::CouldNotFindSpotPosition)
     }
 
@@ -186,6 +200,129 @@ impl User {
         }
     }
 
+    fn get_isolated_perp_collateral_index(&self, perp_market_index: u16) -> Option<usize> {
+        self.spot_positions.iter().position(|spot_position| {
+            spot_position.is_isolated_perp_collateral
+                && spot_position.isolated_perp_market_index == perp_market_index
+        })
+    }
+
+    pub fn is_isolated_perp_market(&self, market_index: u16) -> bool {
+        self.get_isolated_perp_collateral_index(market_index)
+            .is_some()
+    }
+
+    pub fn has_isolated_perp_position(&self) -> bool {
+        self.spot_positions
+            .iter()
+            .any(|spot_position| spot_position.is_isolated_perp_collateral)
+    }
+
+    /// Isolated perp positions keep their collateral in a spot position of the perp market's quote
+    /// spot market that is set aside from the cross margin account
+    pub fn enter_isolated_perp_position(
+        &mut self,
+        market_index: u16,
+        quote_spot_market_index: u16,
+    ) -> DriftResult {
+        if self.is_isolated_perp_market(market_index) {
+            return Ok(());
+        }
+
+        if let Ok(position) = self.get_perp_position(market_index) {
+            validate!(
+                !position.is_lp(),
+                ErrorCode::InvalidIsolatedPerpPosition,
+                "lp position cant be isolated"
+            )?;
+        }
+
+        let new_spot_position_index = self
+            .spot_positions
+            .iter()
+            .enumerate()
+            .position(|(index, spot_position)| index != 0 && spot_position.is_available())
+            .ok_or(ErrorCode::NoSpotPositionAvailable)?;
+
+        self.spot_positions[new_spot_position_index] = SpotPosition {
+            market_index: quote_spot_market_index,
+            balance_type: SpotBalanceType::Deposit,
+            isolated_perp_market_index: market_index,
+            is_isolated_perp_collateral: true,
+            ..SpotPosition::default()
+        };
+
+        Ok(())
+    }
+
+    /// Isolation ends once the collateral has been withdrawn and the position is closed
+    pub fn try_exit_isolated_perp_position(&mut self, market_index: u16) -> bool {
+        let collateral_index = match self.get_isolated_perp_collateral_index(market_index) {
+            Some(collateral_index) => collateral_index,
+            None => return false,
+        };
+
+        if self.spot_positions[collateral_index].scaled_balance != 0 {
+            return false;
+        }
+
+        let is_position_closed = self
+            .get_perp_position(market_index)
+            .map_or(true, |position| position.is_available());
+
+        if is_position_closed {
+            self.spot_positions[collateral_index] = SpotPosition::default();
+        }
+
+        is_position_closed
+    }
+
+    pub fn get_isolated_perp_position_balance(
+        &self,
+        market_index: u16,
+    ) -> DriftResult<SpotPosition> {
+        self.get_isolated_perp_collateral_index(market_index)
+            .map(|collateral_index| self.spot_positions[collateral_index])
+            .ok_or_else(|| {
+                msg!(
+                    "user has no isolated perp position in market {}",
+                    market_index
+                );
+                ErrorCode::InvalidIsolatedPerpPosition
+            })
+    }
+
+    pub fn update_isolated_perp_position_balance(
+        &mut self,
+        market_index: u16,
+        balance: &SpotPosition,
+    ) -> DriftResult {
+        validate!(
+            balance.balance_type == SpotBalanceType::Deposit || balance.scaled_balance == 0,
+            ErrorCode::InvalidIsolatedPerpPosition,
+            "isolated perp position collateral cant be borrowed"
+        )?;
+
+        let collateral_index = self
+            .get_isolated_perp_collateral_index(market_index)
+            .ok_or(ErrorCode::InvalidIsolatedPerpPosition)?;
+
+        let collateral = &mut self.spot_positions[collateral_index];
+
+        validate!(
+            collateral.market_index == balance.market_index,
+            ErrorCode::InvalidIsolatedPerpPosition,
+            "isolated perp position collateral is in spot market {} not {}",
+            collateral.market_index,
+            balance.market_index
+        )?;
+
+        collateral.scaled_balance = balance.scaled_balance;
+        collateral.balance_type = SpotBalanceType::Deposit;
+
+        Ok(())
+    }
+
     pub fn add_spot_position(
         &mut self,
         market_index: u16,
@@ -469,7 +606,11 @@ pub struct SpotPosition {
     pub balance_type: SpotBalanceType,
     /// Number of open orders
     pub open_orders: u8,
-    pub padding: [u8; 4],
+    /// The perp market whose isolated position is collateralized by this balance
+    pub isolated_perp_market_index: u16,
+    /// Whether the balance is the collateral of an isolated perp position instead of cross margin collateral
+    pub is_isolated_perp_collateral: bool,
+    pub padding: [u8; 1],
 }
 
 impl SpotBalance for SpotPosition {
@@ -569,7 +710,7 @@ impl OrderFillSimulation {
 
 impl SpotPosition {
     pub fn is_available(&self) -> bool {
-        self.scaled_balance == 0 && self.open_orders == 0
+        self.scaled_balance == 0 && self.open_orders == 0 && !self.is_isolated_perp_collateral
     }
 
     pub fn has_open_order(&self) -> bool {
@@ -1004,6 +1145,19 @@ impl PerpPosition {
 
 pub type PerpPositions = [PerpPosition; 8];
 
+/// Order has no padding left, so some fields are a tagged union: the order type or a bit flag (the tag)
+/// replaces the field's meaning. Read and write them through the Order accessors, never directly.
+///
+/// | field               | tag                              | meaning                         | accessors              |
+/// |---------------------|----------------------------------|---------------------------------|------------------------|
+/// | trigger_price       | OrderType::Twap                  | slots between slices            | get/set_twap_params    |
+/// | auction_start_price | OrderType::Twap                  | base asset amount of each slice | get/set_twap_params    |
+/// | trigger_price       | OrderBitFlag::QuoteSize          | quote asset amount of the order | get/set_quote_size     |
+/// | max_ts              | OrderBitFlag::GoodTilSlot        | last slot the order is valid    | is_past_max_ts         |
+/// | auction_start_price | OrderBitFlag::CrossMarketTrigger | reference market index          | get/set_trigger_market |
+/// | auction_end_price   | OrderBitFlag::CrossMarketTrigger | reference market type           | get/set_trigger_market |
+///
+/// validate_order rejects orders that set more than one tag for the same field
 #[zero_copy(unsafe)]
 #[repr(C)]
 #[derive(AnchorSerialize, AnchorDeserialize, PartialEq, Debug, Eq)]
@@ -1026,15 +1180,21 @@ pub struct Order {
     /// precision: QUOTE_PRECISION
     pub quote_asset_amount_filled: u64,
     /// At what price the order will be triggered. Only relevant for trigger orders
+    /// Tagged union, see Order. For twap orders, the number of slots between slices
+    /// For quote sized orders, the quote asset amount of the order
     /// precision: PRICE_PRECISION
     pub trigger_price: u64,
     /// The start price for the auction. Only relevant for market/oracle orders
+    /// Tagged union, see Order. For twap orders, the base asset amount released each slice
+    /// For untriggered cross market trigger orders, the reference market index
     /// precision: PRICE_PRECISION
     pub auction_start_price: i64,
     /// The end price for the auction. Only relevant for market/oracle orders
+    /// Tagged union, see Order. For untriggered cross market trigger orders, the reference market type (0 for perp, 1 for spot)
     /// precision: PRICE_PRECISION
     pub auction_end_price: i64,
     /// The time when the order will expire
+    /// Tagged union, see Order. For good til slot orders, the last slot the order is valid for
     pub max_ts: i64,
     /// If set, the order limit price is the oracle price + this offset
     /// precision: PRICE_PRECISION
@@ -1065,7 +1225,14 @@ pub struct Order {
     pub trigger_condition: OrderTriggerCondition,
     /// How many slots the auction lasts
     pub auction_duration: u8,
-    pub padding: [u8; 3],
+    /// Bitflags for further order configuration. See OrderBitFlag
+    pub bit_flags: u8,
+    /// Orders in the same non-zero group are one-cancels-other
+    /// When one of them is completely filled or triggered, the others are canceled
+    /// unless it is the group's entry order (see OrderBitFlag::LinkedOrderEntry)
+    pub linked_order_group: u8,
+    /// The price the trigger price is compared against. Only relevant for trigger orders
+    pub trigger_reference_price: TriggerReferencePrice,
 }
 
 #[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Eq, Debug)]
@@ -1075,14 +1242,128 @@ pub enum AssetType {
 }
 
 impl Order {
-    pub fn seconds_til_expiry(self, now: i64) -> i64 {
-        (self.max_ts - now).max(0)
+    pub fn seconds_til_expiry(self, now: i64, slot: u64) -> DriftResult<i64> {
+        if self.is_good_til_slot() {
+            // assumes 400ms slots
+            Ok(self
+                .max_ts
+                .safe_sub(slot.cast()?)?
+                .max(0)
+                .safe_mul(2)?
+                .safe_div(5)?)
+        } else {
+            Ok(self.max_ts.safe_sub(now)?.max(0))
+        }
     }
 
     pub fn has_oracle_price_offset(self) -> bool {
         self.oracle_price_offset != 0
     }
 
+    pub fn is_fill_or_kill(&self) -> bool {
+        self.is_bit_flag_set(OrderBitFlag::FillOrKill)
+    }
+
+    pub fn is_quote_sized(&self) -> bool {
+        self.is_bit_flag_set(OrderBitFlag::QuoteSize)
+    }
+
+    /// The quote asset amount of a quote sized order
+    /// precision: QUOTE_PRECISION
+    pub fn get_quote_size(&self) -> u64 {
+        self.trigger_price
+    }
+
+    /// Quote sized orders can't have a trigger, so their quote asset amount is kept in trigger_price
+    pub fn set_quote_size(&mut self, quote_asset_amount: u64) {
+        self.bit_flags |= OrderBitFlag::QuoteSize as u8;
+        self.trigger_price = quote_asset_amount;
+    }
+
+    pub fn is_good_til_slot(&self) -> bool {
+        self.is_bit_flag_set(OrderBitFlag::GoodTilSlot)
+    }
+
+    pub fn is_cross_market_trigger(&self) -> bool {
+        self.is_bit_flag_set(OrderBitFlag::CrossMarketTrigger)
+    }
+
+    /// Cross market trigger orders keep the reference market in the auction params until triggered
+    pub fn get_trigger_market(&self) -> DriftResult<(MarketType, u16)> {
+        let market_type = if self.auction_end_price == 0 {
+            MarketType::Perp
+        } else {
+            MarketType::Spot
+        };
+
+        Ok((market_type, self.auction_start_price.cast()?))
+    }
+
+    pub fn set_trigger_market(&mut self, market_type: MarketType, market_index: u16) {
+        self.bit_flags |= OrderBitFlag::CrossMarketTrigger as u8;
+        self.auction_start_price = market_index as i64;
+        self.auction_end_price = match market_type {
+            MarketType::Perp => 0,
+            MarketType::Spot => 1,
+        };
+    }
+
+    pub fn auction_curve(&self) -> AuctionCurve {
+        match (
+            self.is_bit_flag_set(OrderBitFlag::AuctionCurveLow),
+            self.is_bit_flag_set(OrderBitFlag::AuctionCurveHigh),
+        ) {
+            (false, false) => AuctionCurve::Linear,
+            (true, false) => AuctionCurve::ExponentialDecay,
+            (false, true) => AuctionCurve::Step,
+            (true, true) => AuctionCurve::FrontLoaded,
+        }
+    }
+
+    pub fn set_auction_curve(&mut self, auction_curve: AuctionCurve) {
+        self.bit_flags &=
+            !(OrderBitFlag::AuctionCurveLow as u8 | OrderBitFlag::AuctionCurveHigh as u8);
+
+        let (low, high) = match auction_curve {
+            AuctionCurve::Linear => (false, false),
+            AuctionCurve::ExponentialDecay => (true, false),
+            AuctionCurve::Step => (false, true),
+            AuctionCurve::FrontLoaded => (true, true),
+        };
+
+        if low {
+            self.bit_flags |= OrderBitFlag::AuctionCurveLow as u8;
+        }
+
+        if high {
+            self.bit_flags |= OrderBitFlag::AuctionCurveHigh as u8;
+        }
+    }
+
+    /// Whether max_ts has passed. For good til slot orders max_ts is checked against the slot
+    pub fn is_past_max_ts(&self, now: i64, slot: u64) -> DriftResult<bool> {
+        if self.is_good_til_slot() {
+            Ok(slot.cast::<i64>()? > self.max_ts)
+        } else {
+            Ok(now > self.max_ts)
+        }
+    }
+
+    pub fn get_quote_asset_amount_unfilled(&self) -> DriftResult<u64> {
+        Ok(self
+            .get_quote_size()
+            .saturating_sub(self.quote_asset_amount_filled))
+    }
+
+    pub fn is_bit_flag_set(&self, flag: OrderBitFlag) -> bool {
+        self.bit_flags & flag as u8 != 0
+    }
+
+    /// Untriggered trigger orders use the oracle_price_offset as the trailing distance
+    pub fn is_trailing_stop(&self) -> bool {
+        self.must_be_triggered() && !self.triggered() && self.has_oracle_price_offset()
+    }
+
     pub fn get_limit_price(
         &self,
         valid_oracle_price: Option<i64>,
@@ -1242,6 +1523,65 @@ impl Order {
         )
     }
 
+    pub fn is_twap(&self) -> bool {
+        self.order_type == OrderType::Twap
+    }
+
+    pub fn get_twap_slice_interval(&self) -> u64 {
+        self.trigger_price
+    }
+
+    pub fn get_twap_slice_base_asset_amount(&self) -> DriftResult<u64> {
+        self.auction_start_price.cast()
+    }
+
+    /// Twap orders can't have a trigger or an auction, so their slice params are kept in those fields
+    pub fn set_twap_params(
+        &mut self,
+        slice_interval: u64,
+        slice_base_asset_amount: u64,
+    ) -> DriftResult {
+        self.trigger_price = slice_interval;
+        self.auction_start_price = slice_base_asset_amount.cast()?;
+        Ok(())
+    }
+
+    /// The base asset amount released by the twap slices so far that has yet to be filled
+    pub fn get_twap_base_asset_amount_available(&self, slot: u64) -> DriftResult<u64> {
+        let slices_released = slot
+            .saturating_sub(self.slot)
+            .safe_div(self.get_twap_slice_interval())?
+            .safe_add(1)?;
+
+        let base_asset_amount_released = slices_released
+            .saturating_mul(self.get_twap_slice_base_asset_amount()?)
+            .min(self.base_asset_amount);
+
+        Ok(base_asset_amount_released.saturating_sub(self.base_asset_amount_filled))
+    }
+
+    pub fn is_linked(&self) -> bool {
+        self.linked_order_group != 0
+    }
+
+    pub fn is_linked_order_entry(&self) -> bool {
+        self.is_bit_flag_set(OrderBitFlag::LinkedOrderEntry)
+    }
+
+    pub fn set_linked_order_entry(&mut self) {
+        self.bit_flags |= OrderBitFlag::LinkedOrderEntry as u8;
+    }
+
+    /// The linked order group to cancel once the order is completely filled or triggered
+    /// Returns 0 if the order isn't linked or is the group's entry order
+    pub fn get_linked_order_group_to_cancel(&self) -> u8 {
+        if self.is_linked() && !self.is_linked_order_entry() {
+            self.linked_order_group
+        } else {
+            0
+        }
+    }
+
     pub fn is_jit_maker(&self) -> bool {
         self.post_only && self.immediate_or_cancel
     }
@@ -1319,7 +1659,9 @@ impl Default for Order {
             auction_end_price: 0,
             auction_duration: 0,
             max_ts: 0,
-            padding: [0; 3],
+            bit_flags: 0,
+            linked_order_group: 0,
+            trigger_reference_price: TriggerReferencePrice::Oracle,
         }
     }
 }
@@ -1344,6 +1686,8 @@ pub enum OrderType {
     TriggerLimit,
     /// Market order where the auction prices are oracle offsets
     Oracle,
+    /// Taker order where the base asset amount is released in slices at a fixed slot interval
+    Twap,
 }
 
 impl Default for OrderType {
@@ -1366,6 +1710,106 @@ impl Default for OrderTriggerCondition {
     }
 }
 
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+pub enum TriggerReferencePrice {
+    /// The current oracle price
+    Oracle,
+    /// The 5 minute oracle twap
+    OracleTwap5Min,
+    /// The 5 minute amm mark twap. Only for perp markets
+    MarkTwap5Min,
+    /// The price of the last fill in the market. Only for perp markets
+    LastFillPrice,
+    /// The 5 minute twap of fill prices in the market. Only for perp markets
+    LastFillPriceTwap5Min,
+}
+
+impl Default for TriggerReferencePrice {
+    fn default() -> Self {
+        TriggerReferencePrice::Oracle
+    }
+}
+
+/// How the auction price moves from the auction start price to the auction end price.
+/// The end price is the worst price for the taker, so curves ahead of linear fill takers at worse
+/// prices sooner (favoring makers) and curves behind linear hold the better prices longer
+/// (favoring takers). Stored in two order bit flags, so there can be at most four curves
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+pub enum AuctionCurve {
+    /// Price moves linearly from auction start price to auction end price
+    Linear,
+    /// Distance to the auction end price halves every eighth of the auction. Ahead of linear
+    ExponentialDecay,
+    /// Price moves in four equal steps at the end of each quarter of the auction. Behind linear
+    Step,
+    /// Price moves quickly at the start of the auction and slows towards the end. Ahead of linear
+    FrontLoaded,
+}
+
+impl Default for AuctionCurve {
+    fn default() -> Self {
+        AuctionCurve::Linear
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Eq)]
+pub enum OrderBitFlag {
+    /// The trailing stop offset is a percentage of the oracle price
+    /// precision: PERCENTAGE_PRECISION
+    TrailingStopPercentageOffset = 0b00000001,
+    /// The order must be completely filled in the instruction it is placed in or the instruction reverts
+    /// only valid for place_and_take
+    FillOrKill = 0b00000010,
+    /// The order is sized in quote. The base asset amount is converted from the unfilled quote
+    /// at placement and again after every fill using the fill price
+    QuoteSize = 0b00000100,
+    /// max_ts is the last slot the order is valid for rather than a unix timestamp
+    GoodTilSlot = 0b00001000,
+    /// The trigger condition is checked against the reference price of another market
+    CrossMarketTrigger = 0b00010000,
+    /// Low bit of the order's AuctionCurve
+    AuctionCurveLow = 0b00100000,
+    /// High bit of the order's AuctionCurve
+    AuctionCurveHigh = 0b01000000,
+    /// The order is the entry of its linked order group, e.g. the entry of a bracket order
+    /// Filling or triggering it doesn't cancel the other orders in the group
+    LinkedOrderEntry = 0b10000000,
+}
+
+impl OrderBitFlag {
+    /// Flags copied from the order params onto the order as is
+    pub const USER_SETTABLE: u8 = OrderBitFlag::TrailingStopPercentageOffset as u8
+        | OrderBitFlag::FillOrKill as u8
+        | OrderBitFlag::GoodTilSlot as u8;
+
+    /// Flags the order params can set. QuoteSize and LinkedOrderEntry are only set on the order
+    /// through Order::set_quote_size and Order::set_linked_order_entry, the other flags through
+    /// their typed params (trigger_market_params, auction_curve)
+    pub const ORDER_PARAMS: u8 = OrderBitFlag::USER_SETTABLE
+        | OrderBitFlag::QuoteSize as u8
+        | OrderBitFlag::LinkedOrderEntry as u8;
+}
+
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
+pub enum SelfTradePreventionMode {
+    /// Orders from the same authority can match
+    None,
+    /// Cancel the taker order
+    CancelTaker,
+    /// Cancel the maker order
+    CancelMaker,
+    /// Cancel both the taker and maker order
+    CancelBoth,
+    /// Decrement both orders by the smaller remaining size, cancelling the smaller order
+    DecrementAndCancel,
+}
+
+impl Default for SelfTradePreventionMode {
+    fn default() -> Self {
+        SelfTradePreventionMode::None
+    }
+}
+
 #[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug, Eq)]
 pub enum MarketType {
     Spot,
@@ -1428,7 +1872,9 @@ pub struct UserStats {
     /// Whether the user is a referrer. Sub account 0 can not be deleted if user is a referrer
     pub is_referrer: bool,
     pub disable_update_perp_bid_ask_twap: bool,
-    pub padding: [u8; 50],
+    /// How orders from this authority's sub accounts are handled when they would match each other
+    pub self_trade_prevention_mode: SelfTradePreventionMode,
+    pub padding: [u8; 49],
 }
 
 impl Default for UserStats {
@@ -1449,7 +1895,8 @@ impl Default for UserStats {
             number_of_sub_accounts_created: 0,
             is_referrer: false,
             disable_update_perp_bid_ask_twap: false,
-            padding: [0; 50],
+            self_trade_prevention_mode: SelfTradePreventionMode::None,
+            padding: [0; 49],
         }
     }
 }
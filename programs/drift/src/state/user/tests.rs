@@ -1626,6 +1626,54 @@ mod get_base_asset_amount_unfilled {
     }
 }
 
+mod get_twap_base_asset_amount_available {
+    use crate::state::user::{Order, OrderType};
+    use crate::BASE_PRECISION_U64;
+
+    #[test]
+    fn releases_slice_every_interval() {
+        let mut order = Order {
+            order_type: OrderType::Twap,
+            slot: 100,
+            base_asset_amount: 10 * BASE_PRECISION_U64,
+            trigger_price: 10,                                  // slice interval
+            auction_start_price: 3 * BASE_PRECISION_U64 as i64, // slice size
+            ..Order::default()
+        };
+
+        // first slice released on placement
+        assert_eq!(
+            order.get_twap_base_asset_amount_available(100).unwrap(),
+            3 * BASE_PRECISION_U64
+        );
+        assert_eq!(
+            order.get_twap_base_asset_amount_available(109).unwrap(),
+            3 * BASE_PRECISION_U64
+        );
+        assert_eq!(
+            order.get_twap_base_asset_amount_available(110).unwrap(),
+            6 * BASE_PRECISION_U64
+        );
+
+        order.base_asset_amount_filled = 3 * BASE_PRECISION_U64;
+        assert_eq!(order.get_twap_base_asset_amount_available(105).unwrap(), 0);
+        assert_eq!(
+            order.get_twap_base_asset_amount_available(110).unwrap(),
+            3 * BASE_PRECISION_U64
+        );
+
+        // last slice capped by order size
+        assert_eq!(
+            order.get_twap_base_asset_amount_available(130).unwrap(),
+            7 * BASE_PRECISION_U64
+        );
+        assert_eq!(
+            order.get_twap_base_asset_amount_available(1000).unwrap(),
+            7 * BASE_PRECISION_U64
+        );
+    }
+}
+
 mod open_orders {
     use crate::state::user::User;
 
@@ -1940,3 +1988,215 @@ mod get_user_stats_age_ts {
         assert_eq!(age, 0);
     }
 }
+
+mod can_cancel_orders_after_deadline {
+    use crate::state::user::User;
+
+    #[test]
+    fn test() {
+        let user = User::default();
+        assert!(!user.can_cancel_orders_after_deadline(100));
+
+        let user = User {
+            cancel_orders_after_ts: 100,
+            ..User::default()
+        };
+        assert!(!user.can_cancel_orders_after_deadline(99));
+        assert!(user.can_cancel_orders_after_deadline(100));
+        assert!(user.can_cancel_orders_after_deadline(101));
+    }
+}
+
+mod seconds_til_expiry {
+    use crate::state::user::{Order, OrderBitFlag};
+
+    #[test]
+    fn good_til_slot_order() {
+        let order = Order {
+            max_ts: 110,
+            bit_flags: OrderBitFlag::GoodTilSlot as u8,
+            ..Order::default()
+        };
+
+        assert_eq!(order.seconds_til_expiry(0, 100).unwrap(), 4);
+        assert_eq!(order.seconds_til_expiry(0, 111).unwrap(), 0);
+        assert!(order.seconds_til_expiry(0, u64::MAX).is_err());
+
+        let order = Order {
+            max_ts: 50,
+            ..Order::default()
+        };
+
+        assert_eq!(order.seconds_til_expiry(20, 100).unwrap(), 30);
+        assert_eq!(order.seconds_til_expiry(60, 100).unwrap(), 0);
+    }
+}
+
+mod quote_size {
+    use crate::state::user::{Order, OrderType};
+    use crate::QUOTE_PRECISION_U64;
+
+    #[test]
+    fn set_and_get_quote_size() {
+        let mut order = Order {
+            order_type: OrderType::Limit,
+            quote_asset_amount_filled: 40 * QUOTE_PRECISION_U64,
+            ..Order::default()
+        };
+
+        assert!(!order.is_quote_sized());
+
+        order.set_quote_size(100 * QUOTE_PRECISION_U64);
+        assert!(order.is_quote_sized());
+        assert_eq!(order.get_quote_size(), 100 * QUOTE_PRECISION_U64);
+        assert_eq!(
+            order.get_quote_asset_amount_unfilled().unwrap(),
+            60 * QUOTE_PRECISION_U64
+        );
+    }
+}
+
+mod cross_market_trigger {
+    use crate::state::user::{MarketType, Order, OrderTriggerCondition, OrderType};
+
+    #[test]
+    fn set_and_get_trigger_market() {
+        let mut order = Order {
+            order_type: OrderType::TriggerMarket,
+            trigger_condition: OrderTriggerCondition::Above,
+            ..Order::default()
+        };
+
+        assert!(!order.is_cross_market_trigger());
+
+        order.set_trigger_market(MarketType::Spot, 7);
+        assert!(order.is_cross_market_trigger());
+        assert_eq!(order.get_trigger_market().unwrap(), (MarketType::Spot, 7));
+
+        order.set_trigger_market(MarketType::Perp, 3);
+        assert!(order.is_cross_market_trigger());
+        assert_eq!(order.get_trigger_market().unwrap(), (MarketType::Perp, 3));
+    }
+}
+
+mod get_linked_order_group_to_cancel {
+    use crate::state::user::{Order, OrderBitFlag};
+
+    #[test]
+    fn entry_order_doesnt_cancel_group() {
+        let order = Order::default();
+        assert!(!order.is_linked());
+        assert_eq!(order.get_linked_order_group_to_cancel(), 0);
+
+        let exit_order = Order {
+            linked_order_group: 1,
+            ..Order::default()
+        };
+        assert!(exit_order.is_linked());
+        assert_eq!(exit_order.get_linked_order_group_to_cancel(), 1);
+
+        let entry_order = Order {
+            linked_order_group: 1,
+            bit_flags: OrderBitFlag::LinkedOrderEntry as u8,
+            ..Order::default()
+        };
+        assert!(entry_order.is_linked());
+        assert!(entry_order.is_linked_order_entry());
+        assert_eq!(entry_order.get_linked_order_group_to_cancel(), 0);
+    }
+}
+
+mod isolated_perp_position {
+    use crate::state::spot_market::SpotBalanceType;
+    use crate::state::user::{PerpPosition, SpotPosition, User};
+    use crate::test_utils::get_positions;
+
+    #[test]
+    fn enter_and_exit() {
+        let mut user = User::default();
+
+        user.enter_isolated_perp_position(1, 0).unwrap();
+        assert!(user.is_isolated_perp_market(1));
+        assert!(!user.is_isolated_perp_market(0));
+
+        // same market is a no-op
+        user.enter_isolated_perp_position(1, 0).unwrap();
+        assert_eq!(
+            user.spot_positions
+                .iter()
+                .filter(|spot_position| spot_position.is_isolated_perp_collateral)
+                .count(),
+            1
+        );
+
+        // cant exit while collateral remains
+        let mut isolated_balance = user.get_isolated_perp_position_balance(1).unwrap();
+        isolated_balance.scaled_balance = 1;
+        user.update_isolated_perp_position_balance(1, &isolated_balance)
+            .unwrap();
+        assert!(!user.try_exit_isolated_perp_position(1));
+
+        // cant exit while position is open
+        isolated_balance.scaled_balance = 0;
+        user.update_isolated_perp_position_balance(1, &isolated_balance)
+            .unwrap();
+        user.perp_positions = get_positions(PerpPosition {
+            market_index: 1,
+            base_asset_amount: 1,
+            ..PerpPosition::default()
+        });
+        assert!(!user.try_exit_isolated_perp_position(1));
+        assert!(user.is_isolated_perp_market(1));
+
+        user.perp_positions = get_positions(PerpPosition::default());
+        assert!(user.try_exit_isolated_perp_position(1));
+        assert!(!user.is_isolated_perp_market(1));
+        assert!(!user.has_isolated_perp_position());
+    }
+
+    #[test]
+    fn isolated_positions_per_market() {
+        let mut user = User::default();
+
+        user.enter_isolated_perp_position(1, 0).unwrap();
+        user.enter_isolated_perp_position(2, 1).unwrap();
+        assert!(user.is_isolated_perp_market(1));
+        assert!(user.is_isolated_perp_market(2));
+
+        // each market has its own collateral in its quote spot market
+        let isolated_balance = user.get_isolated_perp_position_balance(2).unwrap();
+        assert_eq!(isolated_balance.market_index, 1);
+        assert!(user
+            .update_isolated_perp_position_balance(
+                1,
+                &SpotPosition {
+                    scaled_balance: 1,
+                    ..isolated_balance
+                }
+            )
+            .is_err());
+
+        // isolated collateral is kept apart from the cross margin spot positions
+        assert!(user.get_spot_position(1).is_err());
+        let spot_position_index = user.force_get_spot_position_index(1).unwrap();
+        assert!(!user.spot_positions[spot_position_index].is_isolated_perp_collateral);
+        assert_eq!(
+            user.get_spot_position(1).unwrap().balance_type,
+            SpotBalanceType::Deposit
+        );
+    }
+
+    #[test]
+    fn lp_position_cant_be_isolated() {
+        let mut user = User {
+            perp_positions: get_positions(PerpPosition {
+                market_index: 1,
+                lp_shares: 1,
+                ..PerpPosition::default()
+            }),
+            ..User::default()
+        };
+
+        assert!(user.enter_isolated_perp_position(1, 0).is_err());
+    }
+}
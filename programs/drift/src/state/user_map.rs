@@ -1,5 +1,6 @@
 use crate::error::{DriftResult, ErrorCode};
 use crate::math::safe_unwrap::SafeUnwrap;
+use crate::state::market_maker_protection::UserMarketMakerProtection;
 use crate::state::traits::Size;
 use crate::state::user::{User, UserStats};
 use crate::validate;
@@ -16,7 +17,10 @@ use std::iter::Peekable;
 use std::panic::Location;
 use std::slice::Iter;
 
-pub struct UserMap<'a>(pub BTreeMap<Pubkey, AccountLoader<'a, User>>);
+pub struct UserMap<'a>(
+    pub BTreeMap<Pubkey, AccountLoader<'a, User>>,
+    pub BTreeMap<Pubkey, AccountLoader<'a, UserMarketMakerProtection>>,
+);
 
 impl<'a> UserMap<'a> {
     #[track_caller]
@@ -98,15 +102,50 @@ impl<'a> UserMap<'a> {
         Ok(())
     }
 
+    /// Returns None if the user's market maker protection account wasn't provided
+    pub fn get_market_maker_protection_mut(
+        &self,
+        user: &Pubkey,
+    ) -> DriftResult<Option<RefMut<UserMarketMakerProtection>>> {
+        match self.1.get(user) {
+            Some(loader) => match loader.load_mut() {
+                Ok(market_maker_protection) => Ok(Some(market_maker_protection)),
+                Err(e) => {
+                    msg!("{:?}", e);
+                    msg!("Could not load market maker protection for user {}", user);
+                    Err(ErrorCode::InvalidMarketMakerProtection)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert_market_maker_protection(
+        &mut self,
+        user: Pubkey,
+        account_loader: AccountLoader<'a, UserMarketMakerProtection>,
+    ) -> DriftResult {
+        validate!(
+            !self.1.contains_key(&user),
+            ErrorCode::InvalidMarketMakerProtection,
+            "Market maker protection already exists in map {:?}",
+            user
+        )?;
+
+        self.1.insert(user, account_loader);
+
+        Ok(())
+    }
+
     pub fn empty() -> UserMap<'a> {
-        UserMap(BTreeMap::new())
+        UserMap(BTreeMap::new(), BTreeMap::new())
     }
 }
 
 #[cfg(test)]
 impl<'a> UserMap<'a> {
     pub fn load_one<'b>(account_info: &'b AccountInfo<'a>) -> DriftResult<UserMap<'a>> {
-        let mut user_map = UserMap(BTreeMap::new());
+        let mut user_map = UserMap(BTreeMap::new(), BTreeMap::new());
 
         let user_discriminator: [u8; 8] = User::discriminator();
 
@@ -282,6 +321,8 @@ pub fn load_user_maps<'a>(
 
     let user_discriminator: [u8; 8] = User::discriminator();
     let user_stats_discriminator: [u8; 8] = UserStats::discriminator();
+    let user_market_maker_protection_discriminator: [u8; 8] =
+        UserMarketMakerProtection::discriminator();
     while let Some(user_account_info) = account_info_iter.peek() {
         let user_key = user_account_info.key;
 
@@ -289,6 +330,30 @@ pub fn load_user_maps<'a>(
             .try_borrow_data()
             .or(Err(ErrorCode::CouldNotLoadUserData))?;
 
+        // a user's market maker protection account can be provided alongside its user account
+        if data.len() == UserMarketMakerProtection::SIZE
+            && array_ref![data, 0, 8] == &user_market_maker_protection_discriminator
+        {
+            let user_slice = array_ref![data, 8, 32];
+            let user = Pubkey::from(*user_slice);
+
+            let market_maker_protection_account_info = account_info_iter.next().safe_unwrap()?;
+
+            let is_writable = market_maker_protection_account_info.is_writable;
+            if !is_writable && must_be_writable {
+                return Err(ErrorCode::InvalidMarketMakerProtection);
+            }
+
+            let market_maker_protection_account_loader: AccountLoader<UserMarketMakerProtection> =
+                AccountLoader::try_from(market_maker_protection_account_info)
+                    .or(Err(ErrorCode::InvalidMarketMakerProtection))?;
+
+            user_map
+                .insert_market_maker_protection(user, market_maker_protection_account_loader)?;
+
+            continue;
+        }
+
         let expected_data_len = User::SIZE;
         if data.len() < expected_data_len {
             break;
@@ -355,3 +420,59 @@ pub fn load_user_maps<'a>(
 
     Ok((user_map, user_stats_map))
 }
+
+/// Loads the user's market maker protection account if it's the next remaining account
+pub fn load_user_market_maker_protection<'a>(
+    account_info_iter: &mut Peekable<Iter<AccountInfo<'a>>>,
+    user: &Pubkey,
+    must_be_writable: bool,
+) -> DriftResult<Option<AccountLoader<'a, UserMarketMakerProtection>>> {
+    let user_market_maker_protection_discriminator: [u8; 8] =
+        UserMarketMakerProtection::discriminator();
+
+    let is_market_maker_protection = match account_info_iter.peek() {
+        Some(account_info) => {
+            let data = account_info
+                .try_borrow_data()
+                .or(Err(ErrorCode::InvalidMarketMakerProtection))?;
+
+            data.len() == UserMarketMakerProtection::SIZE
+                && array_ref![data, 0, 8] == &user_market_maker_protection_discriminator
+                && array_ref![data, 8, 32] == &user.to_bytes()
+        }
+        None => false,
+    };
+
+    if !is_market_maker_protection {
+        return Ok(None);
+    }
+
+    let market_maker_protection_account_info = account_info_iter.next().safe_unwrap()?;
+
+    let is_writable = market_maker_protection_account_info.is_writable;
+    if !is_writable && must_be_writable {
+        return Err(ErrorCode::InvalidMarketMakerProtection);
+    }
+
+    let market_maker_protection_account_loader: AccountLoader<UserMarketMakerProtection> =
+        AccountLoader::try_from(market_maker_protection_account_info)
+            .or(Err(ErrorCode::InvalidMarketMakerProtection))?;
+
+    Ok(Some(market_maker_protection_account_loader))
+}
+
+/// Reads the user's market maker protection account if it's the next remaining account, for handlers
+/// that only check it when placing or modifying the user's own orders
+pub fn get_user_market_maker_protection<'a>(
+    account_info_iter: &mut Peekable<Iter<AccountInfo<'a>>>,
+    user: &Pubkey,
+) -> DriftResult<Option<Box<UserMarketMakerProtection>>> {
+    load_user_market_maker_protection(account_info_iter, user, false)?
+        .map(|market_maker_protection| {
+            market_maker_protection
+                .load()
+                .map(|market_maker_protection| Box::new(*market_maker_protection))
+                .or(Err(ErrorCode::InvalidMarketMakerProtection))
+        })
+        .transpose()
+}
@@ -3,6 +3,7 @@ pub mod margin;
 pub mod order;
 pub mod perp_market;
 pub mod position;
+pub mod sig_verification;
 pub mod spot_market;
 pub mod user;
 pub mod whitelist;
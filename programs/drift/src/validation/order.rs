@@ -8,8 +8,11 @@ use crate::math::orders::{
     calculate_base_asset_amount_to_fill_up_to_limit_price, is_multiple_of_step_size,
 };
 use crate::state::perp_market::PerpMarket;
-use crate::state::user::{Order, OrderTriggerCondition, OrderType};
+use crate::state::user::{
+    Order, OrderBitFlag, OrderTriggerCondition, OrderType, TriggerReferencePrice,
+};
 use crate::validate;
+use crate::PERCENTAGE_PRECISION_I64;
 
 pub fn validate_order(
     order: &Order,
@@ -35,8 +38,13 @@ pub fn validate_order(
         OrderType::Oracle => {
             validate_oracle_order(order, market.amm.order_step_size, market.amm.min_order_size)?
         }
+        OrderType::Twap => {
+            validate_twap_order(order, market.amm.order_step_size, market.amm.min_order_size)?
+        }
     }
 
+    validate_order_bit_flags(order)?;
+
     Ok(())
 }
 
@@ -51,7 +59,8 @@ fn validate_market_order(order: &Order, step_size: u64, min_order_size: u64) ->
 
     validate_auction_params(order)?;
 
-    if order.trigger_price > 0 {
+    // quote sized orders store their quote asset amount in trigger_price
+    if order.trigger_price > 0 && !order.is_quote_sized() {
         msg!("Market should not have trigger price");
         return Err(ErrorCode::InvalidOrderTrigger);
     }
@@ -122,7 +131,8 @@ fn validate_oracle_order(order: &Order, step_size: u64, min_order_size: u64) ->
         }
     }
 
-    if order.trigger_price > 0 {
+    // quote sized orders store their quote asset amount in trigger_price
+    if order.trigger_price > 0 && !order.is_quote_sized() {
         msg!("Oracle order should not have trigger price");
         return Err(ErrorCode::InvalidOrderTrigger);
     }
@@ -145,6 +155,63 @@ fn validate_oracle_order(order: &Order, step_size: u64, min_order_size: u64) ->
     Ok(())
 }
 
+fn validate_twap_order(order: &Order, step_size: u64, min_order_size: u64) -> DriftResult {
+    validate_base_asset_amount(order, step_size, min_order_size, order.reduce_only)?;
+
+    if order.price == 0 && !order.has_oracle_price_offset() {
+        msg!("Twap order price == 0");
+        return Err(ErrorCode::InvalidOrderLimitPrice);
+    }
+
+    if order.has_oracle_price_offset() && order.price != 0 {
+        msg!("Twap order price must be 0 for oracle offset order");
+        return Err(ErrorCode::InvalidOrderLimitPrice);
+    }
+
+    validate!(
+        order.get_twap_slice_interval() > 0,
+        ErrorCode::InvalidTwapOrder,
+        "Twap slice interval must be greater than 0"
+    )?;
+
+    let slice_base_asset_amount = order.get_twap_slice_base_asset_amount()?;
+    validate!(
+        slice_base_asset_amount >= step_size.max(min_order_size)
+            && slice_base_asset_amount <= order.base_asset_amount,
+        ErrorCode::InvalidTwapOrder,
+        "Twap slice base asset amount ({}) must be between min order size ({}) and order base asset amount ({})",
+        slice_base_asset_amount,
+        step_size.max(min_order_size),
+        order.base_asset_amount
+    )?;
+
+    validate!(
+        is_multiple_of_step_size(slice_base_asset_amount, step_size)?,
+        ErrorCode::InvalidTwapOrder,
+        "Twap slice base asset amount ({}) not a multiple of the step size ({})",
+        slice_base_asset_amount,
+        step_size
+    )?;
+
+    validate!(
+        !order.has_auction() && order.auction_end_price == 0,
+        ErrorCode::InvalidTwapOrder,
+        "Twap order can not have an auction"
+    )?;
+
+    if order.post_only {
+        msg!("Twap order can not be post only");
+        return Err(ErrorCode::InvalidOrderPostOnly);
+    }
+
+    if order.immediate_or_cancel {
+        msg!("Twap order can not be immediate or cancel");
+        return Err(ErrorCode::InvalidOrderIOC);
+    }
+
+    Ok(())
+}
+
 fn validate_limit_order(
     order: &Order,
     market: &PerpMarket,
@@ -168,7 +235,8 @@ fn validate_limit_order(
         return Err(ErrorCode::InvalidOrderLimitPrice);
     }
 
-    if order.trigger_price > 0 {
+    // quote sized orders store their quote asset amount in trigger_price
+    if order.trigger_price > 0 && !order.is_quote_sized() {
         msg!("Limit order should not have trigger price");
         return Err(ErrorCode::InvalidOrderTrigger);
     }
@@ -220,8 +288,8 @@ fn validate_post_only_order(
     valid_oracle_price: Option<i64>,
     slot: u64,
 ) -> DriftResult {
-    // jit maker can fill against amm
-    if order.is_jit_maker() {
+    // jit maker can fill against amm, orderbook only markets have no amm to cross
+    if order.is_jit_maker() || market.is_orderbook_only() {
         return Ok(());
     }
 
@@ -295,8 +363,7 @@ fn validate_trigger_limit_order(order: &Order, step_size: u64, min_order_size: u
     }
 
     if order.has_oracle_price_offset() {
-        msg!("Trigger limit can not have oracle offset");
-        return Err(ErrorCode::InvalidOrderOracleOffset);
+        validate_trailing_stop_offset(order)?;
     }
 
     Ok(())
@@ -333,8 +400,117 @@ fn validate_trigger_market_order(
     }
 
     if order.has_oracle_price_offset() {
-        msg!("Trigger market order can not have oracle offset");
-        return Err(ErrorCode::InvalidOrderOracleOffset);
+        validate_trailing_stop_offset(order)?;
+    }
+
+    Ok(())
+}
+
+fn validate_trailing_stop_offset(order: &Order) -> DriftResult {
+    validate!(
+        order.oracle_price_offset > 0,
+        ErrorCode::InvalidOrderOracleOffset,
+        "Trailing stop offset ({}) must be greater than 0",
+        order.oracle_price_offset
+    )?;
+
+    if order.is_bit_flag_set(OrderBitFlag::TrailingStopPercentageOffset) {
+        validate!(
+            order.oracle_price_offset.cast::<i64>()? < PERCENTAGE_PRECISION_I64,
+            ErrorCode::InvalidOrderOracleOffset,
+            "Trailing stop percentage offset ({}) must be less than 100%",
+            order.oracle_price_offset
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Order params can only set the flags users choose directly. The flags that change how other order
+/// fields are read are set from their typed params
+pub fn validate_order_params_bit_flags(bit_flags: u8) -> DriftResult {
+    validate!(
+        bit_flags & !OrderBitFlag::ORDER_PARAMS == 0,
+        ErrorCode::InvalidOrder,
+        "Order params bit flags {:#010b} can only set {:#010b}",
+        bit_flags,
+        OrderBitFlag::ORDER_PARAMS
+    )?;
+
+    Ok(())
+}
+
+fn validate_order_bit_flags(order: &Order) -> DriftResult {
+    if order.is_bit_flag_set(OrderBitFlag::TrailingStopPercentageOffset) {
+        validate!(
+            order.is_trailing_stop(),
+            ErrorCode::InvalidOrder,
+            "Only trailing stop orders can have a percentage offset"
+        )?;
+    }
+
+    if order.is_fill_or_kill() {
+        validate!(
+            matches!(order.order_type, OrderType::Market | OrderType::Limit),
+            ErrorCode::InvalidOrder,
+            "Fill or kill order must be market or limit order"
+        )?;
+
+        validate!(
+            !order.post_only,
+            ErrorCode::InvalidOrderPostOnly,
+            "Fill or kill order can not be post only"
+        )?;
+    }
+
+    // twap orders keep their slice params in trigger_price and auction_start_price (see Order)
+    if order.is_twap() {
+        validate!(
+            !order.is_quote_sized() && !order.is_cross_market_trigger(),
+            ErrorCode::InvalidTwapOrder,
+            "Twap order can not be quote sized or a cross market trigger"
+        )?;
+    }
+
+    if order.is_quote_sized() {
+        validate!(
+            matches!(
+                order.order_type,
+                OrderType::Market | OrderType::Limit | OrderType::Oracle
+            ),
+            ErrorCode::InvalidOrder,
+            "Quote sized order must be market, limit or oracle order"
+        )?;
+
+        validate!(
+            order.get_quote_size() > 0,
+            ErrorCode::InvalidOrder,
+            "Quote sized order must have quote asset amount"
+        )?;
+    }
+
+    if order.is_cross_market_trigger() {
+        validate!(
+            order.must_be_triggered() && !order.triggered(),
+            ErrorCode::InvalidOrder,
+            "Cross market trigger order must be untriggered trigger market or trigger limit order"
+        )?;
+    }
+
+    if order.is_good_til_slot() {
+        validate!(
+            order.max_ts > 0,
+            ErrorCode::InvalidOrder,
+            "Good til slot order must have max_ts"
+        )?;
+    }
+
+    if order.is_linked_order_entry() {
+        validate!(
+            order.is_linked(),
+            ErrorCode::InvalidOrder,
+            "Linked order entry must have a linked order group"
+        )?;
     }
 
     Ok(())
@@ -436,8 +612,23 @@ pub fn validate_spot_order(order: &Order, step_size: u64, min_order_size: u64) -
         }
         OrderType::TriggerLimit => validate_trigger_limit_order(order, step_size, min_order_size)?,
         OrderType::Oracle => validate_oracle_order(order, step_size, min_order_size)?,
+        OrderType::Twap => {
+            msg!("Twap orders not supported for spot");
+            return Err(ErrorCode::InvalidTwapOrder);
+        }
     }
 
+    validate!(
+        matches!(
+            order.trigger_reference_price,
+            TriggerReferencePrice::Oracle | TriggerReferencePrice::OracleTwap5Min
+        ),
+        ErrorCode::InvalidOrder,
+        "Spot trigger orders can only reference the oracle price or oracle twap"
+    )?;
+
+    validate_order_bit_flags(order)?;
+
     Ok(())
 }
 
@@ -454,7 +645,8 @@ fn validate_spot_limit_order(order: &Order, step_size: u64, min_order_size: u64)
         return Err(ErrorCode::InvalidOrderOracleOffset);
     }
 
-    if order.trigger_price > 0 {
+    // quote sized orders store their quote asset amount in trigger_price
+    if order.trigger_price > 0 && !order.is_quote_sized() {
         msg!("Limit order should not have trigger price");
         return Err(ErrorCode::InvalidOrderTrigger);
     }
@@ -0,0 +1,125 @@
+use solana_program::ed25519_program;
+use solana_program::instruction::Instruction;
+use solana_program::msg;
+use solana_program::pubkey::Pubkey;
+
+use crate::error::{DriftResult, ErrorCode};
+use crate::math::safe_math::SafeMath;
+use crate::validate;
+
+#[cfg(test)]
+mod tests;
+
+const SIGNATURE_OFFSETS_START: usize = 2;
+const SIGNATURE_OFFSETS_SIZE: usize = 14;
+const PUBKEY_SIZE: usize = 32;
+const SIGNATURE_SIZE: usize = 64;
+
+/// Offsets into the ed25519 precompile instruction data, see solana_sdk::ed25519_instruction
+struct Ed25519SignatureOffsets {
+    signature_offset: u16,
+    signature_instruction_index: u16,
+    public_key_offset: u16,
+    public_key_instruction_index: u16,
+    message_data_offset: u16,
+    message_data_size: u16,
+    message_instruction_index: u16,
+}
+
+impl Ed25519SignatureOffsets {
+    fn unpack(data: &[u8]) -> Ed25519SignatureOffsets {
+        let read_u16 = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        Ed25519SignatureOffsets {
+            signature_offset: read_u16(0),
+            signature_instruction_index: read_u16(2),
+            public_key_offset: read_u16(4),
+            public_key_instruction_index: read_u16(6),
+            message_data_offset: read_u16(8),
+            message_data_size: read_u16(10),
+            message_instruction_index: read_u16(12),
+        }
+    }
+}
+
+/// Checks that ix is an ed25519 precompile instruction verifying a single signature by pubkey over message.
+/// The precompile fails the tx if the signature is invalid, so only the pubkey and message need to be checked
+pub fn verify_ed25519_ix(ix: &Instruction, pubkey: &[u8], message: &[u8]) -> DriftResult {
+    validate!(
+        ix.program_id == ed25519_program::id(),
+        ErrorCode::SigVerificationFailed,
+        "ix must be ed25519 program"
+    )?;
+
+    validate!(
+        ix.accounts.is_empty(),
+        ErrorCode::SigVerificationFailed,
+        "ed25519 ix must not have accounts"
+    )?;
+
+    let data = &ix.data;
+    validate!(
+        data.len() >= SIGNATURE_OFFSETS_START.safe_add(SIGNATURE_OFFSETS_SIZE)?,
+        ErrorCode::SigVerificationFailed,
+        "ed25519 ix data too short"
+    )?;
+
+    validate!(
+        data[0] == 1,
+        ErrorCode::SigVerificationFailed,
+        "ed25519 ix must verify exactly one signature"
+    )?;
+
+    let offsets = Ed25519SignatureOffsets::unpack(
+        &data[SIGNATURE_OFFSETS_START..SIGNATURE_OFFSETS_START + SIGNATURE_OFFSETS_SIZE],
+    );
+
+    // signature, pubkey and message must all be in the ed25519 ix itself
+    validate!(
+        offsets.signature_instruction_index == u16::MAX
+            && offsets.public_key_instruction_index == u16::MAX
+            && offsets.message_instruction_index == u16::MAX,
+        ErrorCode::SigVerificationFailed,
+        "ed25519 ix must reference its own data"
+    )?;
+
+    let signature_end = (offsets.signature_offset as usize).safe_add(SIGNATURE_SIZE)?;
+    let pubkey_start = offsets.public_key_offset as usize;
+    let pubkey_end = pubkey_start.safe_add(PUBKEY_SIZE)?;
+    let message_start = offsets.message_data_offset as usize;
+    let message_end = message_start.safe_add(offsets.message_data_size as usize)?;
+
+    validate!(
+        signature_end <= data.len() && pubkey_end <= data.len() && message_end <= data.len(),
+        ErrorCode::SigVerificationFailed,
+        "ed25519 ix offsets out of bounds"
+    )?;
+
+    validate!(
+        &data[pubkey_start..pubkey_end] == pubkey,
+        ErrorCode::SigVerificationFailed,
+        "ed25519 ix pubkey does not match signer"
+    )?;
+
+    validate!(
+        &data[message_start..message_end] == message,
+        ErrorCode::SigVerificationFailed,
+        "ed25519 ix message does not match"
+    )?;
+
+    Ok(())
+}
+
+/// Checks that ix verifies a signature over message by the user's authority or, if one is set, its delegate
+pub fn verify_ed25519_ix_for_user(
+    ix: &Instruction,
+    authority: &Pubkey,
+    delegate: &Pubkey,
+    message: &[u8],
+) -> DriftResult {
+    match verify_ed25519_ix(ix, authority.as_ref(), message) {
+        Ok(()) => Ok(()),
+        // no delegate is the default pubkey, which must never be accepted as a signer
+        Err(err) if *delegate == Pubkey::default() => Err(err),
+        Err(_) => verify_ed25519_ix(ix, delegate.as_ref(), message),
+    }
+}
@@ -0,0 +1,120 @@
+use anchor_lang::prelude::Pubkey;
+use solana_program::ed25519_program;
+use solana_program::instruction::Instruction;
+
+use crate::error::ErrorCode;
+use crate::validation::sig_verification::{verify_ed25519_ix, verify_ed25519_ix_for_user};
+
+fn new_ed25519_ix(pubkey: &[u8], message: &[u8]) -> Instruction {
+    let public_key_offset: u16 = 16;
+    let signature_offset: u16 = public_key_offset + 32;
+    let message_data_offset: u16 = signature_offset + 64;
+
+    let mut data = vec![1_u8, 0];
+    for value in [
+        signature_offset,
+        u16::MAX,
+        public_key_offset,
+        u16::MAX,
+        message_data_offset,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(pubkey);
+    data.extend_from_slice(&[0; 64]);
+    data.extend_from_slice(message);
+
+    Instruction {
+        program_id: ed25519_program::id(),
+        accounts: vec![],
+        data,
+    }
+}
+
+#[test]
+fn valid_ed25519_ix() {
+    let pubkey = Pubkey::new_unique();
+    let message = b"order params";
+
+    let ix = new_ed25519_ix(pubkey.as_ref(), message);
+
+    verify_ed25519_ix(&ix, pubkey.as_ref(), message).unwrap();
+}
+
+#[test]
+fn wrong_pubkey() {
+    let pubkey = Pubkey::new_unique();
+    let message = b"order params";
+
+    let ix = new_ed25519_ix(pubkey.as_ref(), message);
+
+    let result = verify_ed25519_ix(&ix, Pubkey::new_unique().as_ref(), message);
+    assert_eq!(result, Err(ErrorCode::SigVerificationFailed));
+}
+
+#[test]
+fn wrong_message() {
+    let pubkey = Pubkey::new_unique();
+    let message = b"order params";
+
+    let ix = new_ed25519_ix(pubkey.as_ref(), message);
+
+    let result = verify_ed25519_ix(&ix, pubkey.as_ref(), b"other params");
+    assert_eq!(result, Err(ErrorCode::SigVerificationFailed));
+}
+
+#[test]
+fn wrong_program() {
+    let pubkey = Pubkey::new_unique();
+    let message = b"order params";
+
+    let mut ix = new_ed25519_ix(pubkey.as_ref(), message);
+    ix.program_id = Pubkey::new_unique();
+
+    let result = verify_ed25519_ix(&ix, pubkey.as_ref(), message);
+    assert_eq!(result, Err(ErrorCode::SigVerificationFailed));
+}
+
+#[test]
+fn data_in_other_instruction() {
+    let pubkey = Pubkey::new_unique();
+    let message = b"order params";
+
+    let mut ix = new_ed25519_ix(pubkey.as_ref(), message);
+    // message_instruction_index
+    ix.data[14..16].copy_from_slice(&0_u16.to_le_bytes());
+
+    let result = verify_ed25519_ix(&ix, pubkey.as_ref(), message);
+    assert_eq!(result, Err(ErrorCode::SigVerificationFailed));
+}
+
+#[test]
+fn signed_by_user_authority_or_delegate() {
+    let authority = Pubkey::new_unique();
+    let delegate = Pubkey::new_unique();
+    let message = b"order params";
+
+    let ix = new_ed25519_ix(authority.as_ref(), message);
+    verify_ed25519_ix_for_user(&ix, &authority, &delegate, message).unwrap();
+
+    let ix = new_ed25519_ix(delegate.as_ref(), message);
+    verify_ed25519_ix_for_user(&ix, &authority, &delegate, message).unwrap();
+
+    let ix = new_ed25519_ix(Pubkey::new_unique().as_ref(), message);
+    let result = verify_ed25519_ix_for_user(&ix, &authority, &delegate, message);
+    assert_eq!(result, Err(ErrorCode::SigVerificationFailed));
+}
+
+#[test]
+fn forged_signature_for_unset_delegate() {
+    let authority = Pubkey::new_unique();
+    let message = b"order params";
+
+    // user without a delegate has delegate == Pubkey::default()
+    let ix = new_ed25519_ix(Pubkey::default().as_ref(), message);
+
+    let result = verify_ed25519_ix_for_user(&ix, &authority, &Pubkey::default(), message);
+    assert_eq!(result, Err(ErrorCode::SigVerificationFailed));
+}
@@ -28,6 +28,12 @@ pub fn validate_user_deletion(
         "user being liquidated"
     )?;
 
+    validate!(
+        !user.has_isolated_perp_position(),
+        ErrorCode::UserCantBeDeleted,
+        "user has isolated perp position"
+    )?;
+
     for perp_position in &user.perp_positions {
         validate!(
             perp_position.is_available(),
@@ -98,6 +104,12 @@ pub fn validate_user_is_idle(user: &User, slot: u64, accelerated: bool) -> Drift
         "user being liquidated"
     )?;
 
+    validate!(
+        !user.has_isolated_perp_position(),
+        ErrorCode::UserNotInactive,
+        "user has isolated perp position"
+    )?;
+
     for perp_position in &user.perp_positions {
         validate!(
             perp_position.is_available(),
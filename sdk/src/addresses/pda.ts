@@ -236,3 +236,31 @@ export function getPrelaunchOraclePublicKey(
 		programId
 	)[0];
 }
+
+export function getRfqAccountPublicKey(
+	programId: PublicKey,
+	userAccountPublicKey: PublicKey
+): PublicKey {
+	return PublicKey.findProgramAddressSync(
+		[
+			Buffer.from(anchor.utils.bytes.utf8.encode('rfq')),
+			userAccountPublicKey.toBuffer(),
+		],
+		programId
+	)[0];
+}
+
+export function getUserMarketMakerProtectionAccountPublicKey(
+	programId: PublicKey,
+	userAccountPublicKey: PublicKey
+): PublicKey {
+	return PublicKey.findProgramAddressSync(
+		[
+			Buffer.from(
+				anchor.utils.bytes.utf8.encode('user_market_maker_protection')
+			),
+			userAccountPublicKey.toBuffer(),
+		],
+		programId
+	)[0];
+}
@@ -3349,13 +3349,15 @@ export class AdminClient extends DriftClient {
 	public async initializePrelaunchOracle(
 		perpMarketIndex: number,
 		price?: BN,
-		maxPrice?: BN
+		maxPrice?: BN,
+		resolver?: PublicKey
 	): Promise<TransactionSignature> {
 		const initializePrelaunchOracleIx =
 			await this.getInitializePrelaunchOracleIx(
 				perpMarketIndex,
 				price,
-				maxPrice
+				maxPrice,
+				resolver
 			);
 
 		const tx = await this.buildTransaction(initializePrelaunchOracleIx);
@@ -3368,12 +3370,14 @@ export class AdminClient extends DriftClient {
 	public async getInitializePrelaunchOracleIx(
 		perpMarketIndex: number,
 		price?: BN,
-		maxPrice?: BN
+		maxPrice?: BN,
+		resolver?: PublicKey
 	): Promise<TransactionInstruction> {
 		const params = {
 			perpMarketIndex,
 			price: price || null,
 			maxPrice: maxPrice || null,
+			resolver: resolver || null,
 		};
 
 		return await this.program.instruction.initializePrelaunchOracle(params, {
@@ -3395,13 +3399,15 @@ export class AdminClient extends DriftClient {
 	public async updatePrelaunchOracleParams(
 		perpMarketIndex: number,
 		price?: BN,
-		maxPrice?: BN
+		maxPrice?: BN,
+		resolver?: PublicKey
 	): Promise<TransactionSignature> {
 		const updatePrelaunchOracleParamsIx =
 			await this.getUpdatePrelaunchOracleParamsIx(
 				perpMarketIndex,
 				price,
-				maxPrice
+				maxPrice,
+				resolver
 			);
 
 		const tx = await this.buildTransaction(updatePrelaunchOracleParamsIx);
@@ -3414,12 +3420,14 @@ export class AdminClient extends DriftClient {
 	public async getUpdatePrelaunchOracleParamsIx(
 		perpMarketIndex: number,
 		price?: BN,
-		maxPrice?: BN
+		maxPrice?: BN,
+		resolver?: PublicKey
 	): Promise<TransactionInstruction> {
 		const params = {
 			perpMarketIndex,
 			price: price || null,
 			maxPrice: maxPrice || null,
+			resolver: resolver || null,
 		};
 
 		const perpMarketPublicKey = await getPerpMarketPublicKey(
@@ -8,6 +8,7 @@ import {
 	PositionDirection,
 	SpotBalanceType,
 	SpotPosition,
+	TriggerReferencePrice,
 	UserAccount,
 } from '../types';
 import { PublicKey } from '@solana/web3.js';
@@ -44,7 +45,12 @@ export function decodeUser(buffer: Buffer): UserAccount {
 	for (let i = 0; i < 8; i++) {
 		const scaledBalance = readUnsignedBigInt64LE(buffer, offset);
 		const openOrders = buffer.readUInt8(offset + 35);
-		if (scaledBalance.eq(ZERO) && openOrders === 0) {
+		const isIsolatedPerpCollateral = buffer.readUInt8(offset + 38) === 1;
+		if (
+			scaledBalance.eq(ZERO) &&
+			openOrders === 0 &&
+			!isIsolatedPerpCollateral
+		) {
 			offset += 40;
 			continue;
 		}
@@ -65,7 +71,9 @@ export function decodeUser(buffer: Buffer): UserAccount {
 		} else {
 			balanceType = SpotBalanceType.BORROW;
 		}
-		offset += 6;
+		offset += 2;
+		const isolatedPerpMarketIndex = buffer.readUInt16LE(offset);
+		offset += 4;
 		spotPositions.push({
 			scaledBalance,
 			openBids,
@@ -74,6 +82,8 @@ export function decodeUser(buffer: Buffer): UserAccount {
 			marketIndex,
 			balanceType,
 			openOrders,
+			isolatedPerpMarketIndex,
+			isIsolatedPerpCollateral,
 		});
 	}
 
@@ -189,6 +199,8 @@ export function decodeUser(buffer: Buffer): UserAccount {
 			orderType = OrderType.TRIGGER_LIMIT;
 		} else if (orderTypeNum === 4) {
 			orderType = OrderType.ORACLE;
+		} else if (orderTypeNum === 5) {
+			orderType = OrderType.TWAP;
 		}
 		offset += 1;
 		const marketTypeNum = buffer.readUInt8(offset);
@@ -237,7 +249,24 @@ export function decodeUser(buffer: Buffer): UserAccount {
 		offset += 1;
 		const auctionDuration = buffer.readUInt8(offset);
 		offset += 1;
-		offset += 3; // padding
+		const bitFlags = buffer.readUInt8(offset);
+		offset += 1;
+		const linkedOrderGroup = buffer.readUInt8(offset);
+		offset += 1;
+		const triggerReferencePriceNum = buffer.readUInt8(offset);
+		let triggerReferencePrice: TriggerReferencePrice;
+		if (triggerReferencePriceNum === 0) {
+			triggerReferencePrice = TriggerReferencePrice.ORACLE;
+		} else if (triggerReferencePriceNum === 1) {
+			triggerReferencePrice = TriggerReferencePrice.ORACLE_TWAP_5MIN;
+		} else if (triggerReferencePriceNum === 2) {
+			triggerReferencePrice = TriggerReferencePrice.MARK_TWAP_5MIN;
+		} else if (triggerReferencePriceNum === 3) {
+			triggerReferencePrice = TriggerReferencePrice.LAST_FILL_PRICE;
+		} else if (triggerReferencePriceNum === 4) {
+			triggerReferencePrice = TriggerReferencePrice.LAST_FILL_PRICE_TWAP_5MIN;
+		}
+		offset += 1;
 		orders.push({
 			slot,
 			price,
@@ -263,6 +292,9 @@ export function decodeUser(buffer: Buffer): UserAccount {
 			immediateOrCancel,
 			triggerCondition,
 			auctionDuration,
+			bitFlags,
+			linkedOrderGroup,
+			triggerReferencePrice,
 		});
 	}
 
@@ -326,6 +358,12 @@ export function decodeUser(buffer: Buffer): UserAccount {
 	const hasOpenAuction = buffer.readUInt8(offset) === 1;
 	offset += 1;
 
+	const hasMarketMakerProtection = buffer.readUInt8(offset) === 1;
+	offset += 5;
+
+	const cancelOrdersAfterTs = readSignedBigInt64LE(buffer, offset);
+	offset += 8;
+
 	// @ts-ignore
 	return {
 		authority,
@@ -354,5 +392,7 @@ export function decodeUser(buffer: Buffer): UserAccount {
 		hasOpenOrder,
 		openAuctions,
 		hasOpenAuction,
+		hasMarketMakerProtection,
+		cancelOrdersAfterTs,
 	};
 }
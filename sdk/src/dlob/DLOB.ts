@@ -8,13 +8,17 @@ import {
 	DLOBNodeType,
 	DriftClient,
 	getLimitPrice,
+	getTriggerMarket,
+	getTwapBaseAssetAmountAvailable,
 	getVariant,
+	isCrossMarketTrigger,
 	isFallbackAvailableLiquiditySource,
 	isOneOfVariant,
 	isOrderExpired,
 	isRestingLimitOrder,
 	isTakingOrder,
 	isTriggered,
+	isTwap,
 	isVariant,
 	MarketType,
 	MarketTypeStr,
@@ -95,6 +99,7 @@ const SUPPORTED_ORDER_TYPES = [
 	'triggerMarket',
 	'triggerLimit',
 	'oracle',
+	'twap',
 ];
 
 export class DLOB {
@@ -542,7 +547,8 @@ export class DLOB {
 		const expiredNodesToFill = this.findExpiredNodesToFill(
 			marketIndex,
 			ts,
-			marketType
+			marketType,
+			slot
 		);
 
 		return this.mergeNodesToFill(
@@ -840,6 +846,14 @@ export class DLOB {
 		const nodesToFill = new Array<NodeToFill>();
 
 		for (const takerNode of takerNodeGenerator) {
+			// twap orders can only fill the slices released so far
+			if (
+				isTwap(takerNode.order) &&
+				getTwapBaseAssetAmountAvailable(takerNode.order, slot).eq(ZERO)
+			) {
+				continue;
+			}
+
 			const makerNodeGenerator = makerNodeGeneratorFn(
 				marketIndex,
 				slot,
@@ -875,9 +889,9 @@ export class DLOB {
 				const makerBaseRemaining = makerOrder.baseAssetAmount.sub(
 					makerOrder.baseAssetAmountFilled
 				);
-				const takerBaseRemaining = takerOrder.baseAssetAmount.sub(
-					takerOrder.baseAssetAmountFilled
-				);
+				const takerBaseRemaining = isTwap(takerOrder)
+					? getTwapBaseAssetAmountAvailable(takerOrder, slot)
+					: takerOrder.baseAssetAmount.sub(takerOrder.baseAssetAmountFilled);
 
 				const baseFilled = BN.min(makerBaseRemaining, takerBaseRemaining);
 
@@ -897,9 +911,7 @@ export class DLOB {
 					takerNode.userAccount
 				);
 
-				if (
-					newTakerOrder.baseAssetAmountFilled.eq(takerOrder.baseAssetAmount)
-				) {
+				if (baseFilled.eq(takerBaseRemaining)) {
 					break;
 				}
 			}
@@ -927,6 +939,14 @@ export class DLOB {
 				continue;
 			}
 
+			if (
+				isTwap(node.order) &&
+				getTwapBaseAssetAmountAvailable(node.order, slot).eq(ZERO)
+			) {
+				nextNode = nodeGenerator.next();
+				continue;
+			}
+
 			const nodePrice = getLimitPrice(node.order, oraclePriceData, slot);
 
 			// order crosses if there is no limit price or it crosses fallback price
@@ -957,7 +977,8 @@ export class DLOB {
 	public findExpiredNodesToFill(
 		marketIndex: number,
 		ts: number,
-		marketType: MarketType
+		marketType: MarketType,
+		slot?: number
 	): NodeToFill[] {
 		const nodesToFill = new Array<NodeToFill>();
 
@@ -985,7 +1006,7 @@ export class DLOB {
 
 		for (const bidGenerator of bidGenerators) {
 			for (const bid of bidGenerator) {
-				if (isOrderExpired(bid.order, ts, true, 25)) {
+				if (isOrderExpired(bid.order, ts, true, 25, slot)) {
 					nodesToFill.push({
 						node: bid,
 						makerNodes: [],
@@ -996,7 +1017,7 @@ export class DLOB {
 
 		for (const askGenerator of askGenerators) {
 			for (const ask of askGenerator) {
-				if (isOrderExpired(ask.order, ts, true, 25)) {
+				if (isOrderExpired(ask.order, ts, true, 25, slot)) {
 					nodesToFill.push({
 						node: ask,
 						makerNodes: [],
@@ -1624,6 +1645,11 @@ export class DLOB {
 			: undefined;
 		if (triggerAboveList) {
 			for (const node of triggerAboveList.getGenerator()) {
+				// cross market trigger orders are triggered off another market's price
+				if (isCrossMarketTrigger(node.order)) {
+					continue;
+				}
+
 				if (oraclePrice.gt(node.order.triggerPrice)) {
 					nodesToTrigger.push({
 						node: node,
@@ -1639,6 +1665,10 @@ export class DLOB {
 			: undefined;
 		if (triggerBelowList) {
 			for (const node of triggerBelowList.getGenerator()) {
+				if (isCrossMarketTrigger(node.order)) {
+					continue;
+				}
+
 				if (oraclePrice.lt(node.order.triggerPrice)) {
 					nodesToTrigger.push({
 						node: node,
@@ -1652,6 +1682,63 @@ export class DLOB {
 		return nodesToTrigger;
 	}
 
+	/**
+	 * Finds untriggered cross market trigger orders whose reference market price crosses their trigger price
+	 * @param getReferencePrice price of the order's reference market, undefined if it isn't available
+	 */
+	public findCrossMarketNodesToTrigger(
+		marketIndex: number,
+		marketType: MarketType,
+		stateAccount: StateAccount,
+		getReferencePrice: (
+			marketType: MarketType,
+			marketIndex: number
+		) => BN | undefined
+	): NodeToTrigger[] {
+		if (exchangePaused(stateAccount)) {
+			return [];
+		}
+
+		const nodesToTrigger = [];
+		const marketTypeStr = getVariant(marketType) as MarketTypeStr;
+		const marketNodeLists = this.orderLists.get(marketTypeStr).get(marketIndex);
+
+		if (!marketNodeLists) {
+			return nodesToTrigger;
+		}
+
+		for (const triggerList of [
+			marketNodeLists.trigger.above,
+			marketNodeLists.trigger.below,
+		]) {
+			for (const node of triggerList.getGenerator()) {
+				if (!isCrossMarketTrigger(node.order)) {
+					continue;
+				}
+
+				const triggerMarket = getTriggerMarket(node.order);
+				const referencePrice = getReferencePrice(
+					triggerMarket.marketType,
+					triggerMarket.marketIndex
+				);
+				if (referencePrice === undefined) {
+					continue;
+				}
+
+				const canTrigger = isVariant(node.order.triggerCondition, 'above')
+					? referencePrice.gt(node.order.triggerPrice)
+					: referencePrice.lt(node.order.triggerPrice);
+				if (canTrigger) {
+					nodesToTrigger.push({
+						node: node,
+					});
+				}
+			}
+		}
+
+		return nodesToTrigger;
+	}
+
 	public printTop(
 		driftClient: DriftClient,
 		slotSubscriber: SlotSubscriber,
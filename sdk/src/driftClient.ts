@@ -25,6 +25,7 @@ import {
 	SpotMarketAccount,
 	SpotPosition,
 	MakerInfo,
+	BatchAuctionUserInfo,
 	TakerInfo,
 	OptionalOrderParams,
 	OrderType,
@@ -39,11 +40,18 @@ import {
 	PerpMarketExtendedInfo,
 	UserStatsAccount,
 	ModifyOrderParams,
+	ModifyOrdersParams,
+	QuoteSetParams,
+	ComboOrderParams,
+	SignedOrderParams,
 	PhoenixV1FulfillmentConfigAccount,
 	ModifyOrderPolicy,
 	SwapReduceOnly,
 	SettlePnlMode,
 	SignedTxData,
+	RfqAccount,
+	RfqParams,
+	UserMarketMakerProtectionAccount,
 } from './types';
 import * as anchor from '@coral-xyz/anchor';
 import driftIDL from './idl/drift.json';
@@ -64,6 +72,8 @@ import {
 	TransactionVersion,
 	VersionedTransaction,
 	BlockhashWithExpiryBlockHeight,
+	Ed25519Program,
+	SYSVAR_INSTRUCTIONS_PUBKEY,
 } from '@solana/web3.js';
 
 import { TokenFaucet } from './tokenFaucet';
@@ -76,11 +86,13 @@ import {
 	getPerpMarketPublicKey,
 	getPhoenixFulfillmentConfigPublicKey,
 	getReferrerNamePublicKeySync,
+	getRfqAccountPublicKey,
 	getSerumFulfillmentConfigPublicKey,
 	getSerumSignerPublicKey,
 	getSpotMarketPublicKey,
 	getUserAccountPublicKey,
 	getUserAccountPublicKeySync,
+	getUserMarketMakerProtectionAccountPublicKey,
 	getUserStatsAccountPublicKey,
 } from './addresses/pda';
 import {
@@ -1501,6 +1513,31 @@ export class DriftClient {
 		});
 	}
 
+	/**
+	 * A user with market maker protection passes its protection account after the market accounts when placing
+	 * or modifying orders. Makers pass theirs writable so fills count towards the protection thresholds
+	 */
+	getMarketMakerProtectionRemainingAccounts(
+		userAccountPublicKey: PublicKey,
+		userAccount: UserAccount,
+		isWritable = false
+	): AccountMeta[] {
+		if (!userAccount.hasMarketMakerProtection) {
+			return [];
+		}
+
+		return [
+			{
+				pubkey: getUserMarketMakerProtectionAccountPublicKey(
+					this.program.programId,
+					userAccountPublicKey
+				),
+				isWritable,
+				isSigner: false,
+			},
+		];
+	}
+
 	getRemainingAccounts(params: RemainingAccountParams): AccountMeta[] {
 		const { oracleAccountMap, spotMarketAccountMap, perpMarketAccountMap } =
 			this.getRemainingAccountMapsForUsers(params.userAccounts);
@@ -2473,6 +2510,67 @@ export class DriftClient {
 		});
 	}
 
+	/**
+	 * Moves collateral between the user's cross margin spot balance and an isolated perp position
+	 * @param spotMarketIndex quote spot market of the perp market
+	 * @param perpMarketIndex
+	 * @param amount positive moves collateral into the isolated position, negative moves it out
+	 * @param txParams
+	 * @param subAccountId
+	 */
+	public async transferIsolatedPerpPositionDeposit(
+		spotMarketIndex: number,
+		perpMarketIndex: number,
+		amount: BN,
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig, slot } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getTransferIsolatedPerpPositionDepositIx(
+					spotMarketIndex,
+					perpMarketIndex,
+					amount,
+					subAccountId
+				),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		this.spotMarketLastSlotCache.set(spotMarketIndex, slot);
+		this.perpMarketLastSlotCache.set(perpMarketIndex, slot);
+		return txSig;
+	}
+
+	public async getTransferIsolatedPerpPositionDepositIx(
+		spotMarketIndex: number,
+		perpMarketIndex: number,
+		amount: BN,
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		const remainingAccounts = this.getRemainingAccounts({
+			userAccounts: [this.getUserAccount(subAccountId)],
+			useMarketLastSlotCache: true,
+			writableSpotMarketIndexes: [spotMarketIndex],
+			readablePerpMarketIndex: perpMarketIndex,
+		});
+
+		return await this.program.instruction.transferIsolatedPerpPositionDeposit(
+			spotMarketIndex,
+			perpMarketIndex,
+			amount,
+			{
+				accounts: {
+					state: await this.getStatePublicKey(),
+					user: await this.getUserAccountPublicKey(subAccountId),
+					authority: this.wallet.publicKey,
+				},
+				remainingAccounts,
+			}
+		);
+	}
+
 	public async updateSpotMarketCumulativeInterest(
 		marketIndex: number,
 		txParams?: TxParams
@@ -2912,6 +3010,12 @@ export class DriftClient {
 			useMarketLastSlotCache: true,
 			readablePerpMarketIndex: orderParams.marketIndex,
 		});
+		remainingAccounts.push(
+			...this.getMarketMakerProtectionRemainingAccounts(
+				user,
+				this.getUserAccount(subAccountId)
+			)
+		);
 
 		return await this.program.instruction.placePerpOrder(orderParams, {
 			accounts: {
@@ -3239,30 +3343,149 @@ export class DriftClient {
 		);
 	}
 
+	/**
+	 * Arms the dead man's switch: after cancelAfterSecs, any keeper can cancel all of the user's orders.
+	 * Call again before the deadline to push it back, or with 0 to disarm it
+	 */
+	public async updateUserCancelOrdersAfter(
+		cancelAfterSecs: number,
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getUpdateUserCancelOrdersAfterIx(
+					cancelAfterSecs,
+					subAccountId
+				),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getUpdateUserCancelOrdersAfterIx(
+		cancelAfterSecs: number,
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		const user = await this.getUserAccountPublicKey(subAccountId);
+
+		return await this.program.instruction.updateUserCancelOrdersAfter(
+			cancelAfterSecs,
+			{
+				accounts: {
+					state: await this.getStatePublicKey(),
+					user,
+					authority: this.wallet.publicKey,
+				},
+			}
+		);
+	}
+
+	/**
+	 * Cancels the orders matching the filters or in orderIds and places the new orders in one instruction
+	 */
 	public async cancelAndPlaceOrders(
 		cancelOrderParams: {
 			marketType?: MarketType;
 			marketIndex?: number;
 			direction?: PositionDirection;
+			orderIds?: number[];
 		},
 		placeOrderParams: OrderParams[],
 		txParams?: TxParams,
 		subAccountId?: number
 	): Promise<TransactionSignature> {
-		const ixs = [
-			await this.getCancelOrdersIx(
-				cancelOrderParams.marketType,
-				cancelOrderParams.marketIndex,
-				cancelOrderParams.direction,
-				subAccountId
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getCancelAndPlaceOrdersIx(
+					cancelOrderParams,
+					placeOrderParams,
+					subAccountId
+				),
+				txParams
 			),
-			await this.getPlaceOrdersIx(placeOrderParams, subAccountId),
-		];
-		const tx = await this.buildTransaction(ixs, txParams);
-		const { txSig } = await this.sendTransaction(tx, [], this.opts);
+			[],
+			this.opts
+		);
 		return txSig;
 	}
 
+	public async getCancelAndPlaceOrdersIx(
+		cancelOrderParams: {
+			marketType?: MarketType;
+			marketIndex?: number;
+			direction?: PositionDirection;
+			orderIds?: number[];
+		},
+		placeOrderParams: OptionalOrderParams[],
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		const user = await this.getUserAccountPublicKey(subAccountId);
+
+		const readablePerpMarketIndex: number[] = [];
+		const readableSpotMarketIndexes: number[] = [];
+		if (typeof cancelOrderParams.marketIndex === 'number') {
+			if (
+				cancelOrderParams.marketType &&
+				isVariant(cancelOrderParams.marketType, 'perp')
+			) {
+				readablePerpMarketIndex.push(cancelOrderParams.marketIndex);
+			} else if (
+				cancelOrderParams.marketType &&
+				isVariant(cancelOrderParams.marketType, 'spot')
+			) {
+				readableSpotMarketIndexes.push(cancelOrderParams.marketIndex);
+			}
+		}
+
+		for (const param of placeOrderParams) {
+			if (!param.marketType) {
+				throw new Error('must set param.marketType');
+			}
+			if (isVariant(param.marketType, 'perp')) {
+				readablePerpMarketIndex.push(param.marketIndex);
+			} else {
+				readableSpotMarketIndexes.push(param.marketIndex);
+			}
+		}
+
+		const remainingAccounts = this.getRemainingAccounts({
+			userAccounts: [this.getUserAccount(subAccountId)],
+			readablePerpMarketIndex,
+			readableSpotMarketIndexes,
+			useMarketLastSlotCache: true,
+		});
+		remainingAccounts.push(
+			...this.getMarketMakerProtectionRemainingAccounts(
+				user,
+				this.getUserAccount(subAccountId)
+			)
+		);
+
+		const formattedParams = placeOrderParams.map((item) =>
+			getOrderParams(item)
+		);
+
+		return await this.program.instruction.cancelAndPlaceOrders(
+			cancelOrderParams.marketType ?? null,
+			cancelOrderParams.marketIndex ?? null,
+			cancelOrderParams.direction ?? null,
+			cancelOrderParams.orderIds ?? [],
+			formattedParams,
+			{
+				accounts: {
+					state: await this.getStatePublicKey(),
+					user,
+					authority: this.wallet.publicKey,
+				},
+				remainingAccounts,
+			}
+		);
+	}
+
 	public async placeOrders(
 		params: OrderParams[],
 		txParams?: TxParams,
@@ -3304,6 +3527,12 @@ export class DriftClient {
 			readableSpotMarketIndexes,
 			useMarketLastSlotCache: true,
 		});
+		remainingAccounts.push(
+			...this.getMarketMakerProtectionRemainingAccounts(
+				user,
+				this.getUserAccount(subAccountId)
+			)
+		);
 
 		const formattedParams = params.map((item) => getOrderParams(item));
 
@@ -3318,6 +3547,54 @@ export class DriftClient {
 		});
 	}
 
+	/**
+	 * Places a two-sided quote in a perp market as post only limit orders, replacing the user's existing
+	 * post only limit orders in the market
+	 */
+	public async placeQuoteSet(
+		params: QuoteSetParams,
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getPlaceQuoteSetIx(params, subAccountId),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getPlaceQuoteSetIx(
+		params: QuoteSetParams,
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		const user = await this.getUserAccountPublicKey(subAccountId);
+
+		const remainingAccounts = this.getRemainingAccounts({
+			userAccounts: [this.getUserAccount(subAccountId)],
+			readablePerpMarketIndex: params.marketIndex,
+			useMarketLastSlotCache: true,
+		});
+		remainingAccounts.push(
+			...this.getMarketMakerProtectionRemainingAccounts(
+				user,
+				this.getUserAccount(subAccountId)
+			)
+		);
+
+		return await this.program.instruction.placeQuoteSet(params, {
+			accounts: {
+				state: await this.getStatePublicKey(),
+				user,
+				authority: this.wallet.publicKey,
+			},
+			remainingAccounts,
+		});
+	}
+
 	public async fillPerpOrder(
 		userAccountPublicKey: PublicKey,
 		user: UserAccount,
@@ -3393,6 +3670,13 @@ export class DriftClient {
 				isWritable: true,
 				isSigner: false,
 			});
+			remainingAccounts.push(
+				...this.getMarketMakerProtectionRemainingAccounts(
+					maker.maker,
+					maker.makerUserAccount,
+					true
+				)
+			);
 		}
 
 		if (referrerInfo) {
@@ -3427,62 +3711,130 @@ export class DriftClient {
 		});
 	}
 
-	public async getRevertFillIx(
-		fillerPublicKey?: PublicKey
-	): Promise<TransactionInstruction> {
-		const filler = fillerPublicKey ?? (await this.getUserAccountPublicKey());
-		const fillerStatsPublicKey = this.getUserStatsAccountPublicKey();
-
-		return this.program.instruction.revertFill({
-			accounts: {
-				state: await this.getStatePublicKey(),
-				filler,
-				fillerStats: fillerStatsPublicKey,
-				authority: this.wallet.publicKey,
-			},
-		});
-	}
-
-	public async placeSpotOrder(
-		orderParams: OptionalOrderParams,
+	public async clearPerpBatchAuction(
+		marketIndex: number,
+		users: BatchAuctionUserInfo[],
 		txParams?: TxParams,
-		subAccountId?: number
+		fillerSubAccountId?: number
 	): Promise<TransactionSignature> {
-		const { txSig, slot } = await this.sendTransaction(
+		const { txSig } = await this.sendTransaction(
 			await this.buildTransaction(
-				await this.getPlaceSpotOrderIx(orderParams, subAccountId),
+				await this.getClearPerpBatchAuctionIx(
+					marketIndex,
+					users,
+					fillerSubAccountId
+				),
 				txParams
 			),
 			[],
 			this.opts
 		);
-		this.spotMarketLastSlotCache.set(orderParams.marketIndex, slot);
-		this.spotMarketLastSlotCache.set(QUOTE_SPOT_MARKET_INDEX, slot);
 		return txSig;
 	}
 
-	public async getPlaceSpotOrderIx(
-		orderParams: OptionalOrderParams,
-		subAccountId?: number
+	/**
+	 * Only the users passed in are cleared, takers left out wait for a later clear
+	 */
+	public async getClearPerpBatchAuctionIx(
+		marketIndex: number,
+		users: BatchAuctionUserInfo[],
+		fillerSubAccountId?: number
 	): Promise<TransactionInstruction> {
-		orderParams = getOrderParams(orderParams, { marketType: MarketType.SPOT });
-		const userAccountPublicKey = await this.getUserAccountPublicKey(
-			subAccountId
-		);
+		const filler = await this.getUserAccountPublicKey(fillerSubAccountId);
+		const fillerStatsPublicKey = this.getUserStatsAccountPublicKey();
 
 		const remainingAccounts = this.getRemainingAccounts({
-			userAccounts: [this.getUserAccount(subAccountId)],
-			useMarketLastSlotCache: true,
-			readableSpotMarketIndexes: [
-				orderParams.marketIndex,
-				QUOTE_SPOT_MARKET_INDEX,
-			],
+			userAccounts: users.map((user) => user.userAccount),
+			writablePerpMarketIndexes: [marketIndex],
 		});
 
-		return await this.program.instruction.placeSpotOrder(orderParams, {
-			accounts: {
-				state: await this.getStatePublicKey(),
-				user: userAccountPublicKey,
+		for (const user of users) {
+			remainingAccounts.push({
+				pubkey: user.user,
+				isWritable: true,
+				isSigner: false,
+			});
+			remainingAccounts.push({
+				pubkey: user.userStats,
+				isWritable: true,
+				isSigner: false,
+			});
+			if (user.marketMakerProtection) {
+				remainingAccounts.push({
+					pubkey: user.marketMakerProtection,
+					isWritable: true,
+					isSigner: false,
+				});
+			}
+		}
+
+		return await this.program.instruction.clearPerpBatchAuction(marketIndex, {
+			accounts: {
+				state: await this.getStatePublicKey(),
+				filler,
+				fillerStats: fillerStatsPublicKey,
+				authority: this.wallet.publicKey,
+			},
+			remainingAccounts,
+		});
+	}
+
+	public async getRevertFillIx(
+		fillerPublicKey?: PublicKey
+	): Promise<TransactionInstruction> {
+		const filler = fillerPublicKey ?? (await this.getUserAccountPublicKey());
+		const fillerStatsPublicKey = this.getUserStatsAccountPublicKey();
+
+		return this.program.instruction.revertFill({
+			accounts: {
+				state: await this.getStatePublicKey(),
+				filler,
+				fillerStats: fillerStatsPublicKey,
+				authority: this.wallet.publicKey,
+			},
+		});
+	}
+
+	public async placeSpotOrder(
+		orderParams: OptionalOrderParams,
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig, slot } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getPlaceSpotOrderIx(orderParams, subAccountId),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		this.spotMarketLastSlotCache.set(orderParams.marketIndex, slot);
+		this.spotMarketLastSlotCache.set(QUOTE_SPOT_MARKET_INDEX, slot);
+		return txSig;
+	}
+
+	public async getPlaceSpotOrderIx(
+		orderParams: OptionalOrderParams,
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		orderParams = getOrderParams(orderParams, { marketType: MarketType.SPOT });
+		const userAccountPublicKey = await this.getUserAccountPublicKey(
+			subAccountId
+		);
+
+		const remainingAccounts = this.getRemainingAccounts({
+			userAccounts: [this.getUserAccount(subAccountId)],
+			useMarketLastSlotCache: true,
+			readableSpotMarketIndexes: [
+				orderParams.marketIndex,
+				QUOTE_SPOT_MARKET_INDEX,
+			],
+		});
+
+		return await this.program.instruction.placeSpotOrder(orderParams, {
+			accounts: {
+				state: await this.getStatePublicKey(),
+				user: userAccountPublicKey,
 				userStats: this.getUserStatsAccountPublicKey(),
 				authority: this.wallet.publicKey,
 			},
@@ -4405,6 +4757,50 @@ export class DriftClient {
 		});
 	}
 
+	public async cancelOrdersAfterDeadline(
+		userAccountPublicKey: PublicKey,
+		user: UserAccount,
+		txParams?: TxParams,
+		fillerPublicKey?: PublicKey
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getCancelOrdersAfterDeadlineIx(
+					userAccountPublicKey,
+					user,
+					fillerPublicKey
+				),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getCancelOrdersAfterDeadlineIx(
+		userAccountPublicKey: PublicKey,
+		userAccount: UserAccount,
+		fillerPublicKey?: PublicKey
+	): Promise<TransactionInstruction> {
+		const filler = fillerPublicKey ?? (await this.getUserAccountPublicKey());
+
+		const remainingAccounts = this.getRemainingAccounts({
+			userAccounts: [userAccount],
+			writableSpotMarketIndexes: [QUOTE_SPOT_MARKET_INDEX],
+		});
+
+		return await this.program.instruction.cancelOrdersAfterDeadline({
+			accounts: {
+				state: await this.getStatePublicKey(),
+				filler,
+				user: userAccountPublicKey,
+				authority: this.wallet.publicKey,
+			},
+			remainingAccounts,
+		});
+	}
+
 	public async updateUserIdle(
 		userAccountPublicKey: PublicKey,
 		user: UserAccount,
@@ -4753,6 +5149,13 @@ export class DriftClient {
 				isWritable: true,
 				isSigner: false,
 			});
+			remainingAccounts.push(
+				...this.getMarketMakerProtectionRemainingAccounts(
+					maker.maker,
+					maker.makerUserAccount,
+					true
+				)
+			);
 		}
 
 		if (referrerInfo) {
@@ -4788,6 +5191,154 @@ export class DriftClient {
 		);
 	}
 
+	/**
+	 * The message the taker's authority (or delegate) signs for placeAndMatchSignedOrder
+	 */
+	public encodeSignedOrderParams(params: SignedOrderParams): Buffer {
+		return this.program.coder.types.encode('SignedOrderParams', {
+			...params,
+			orderParams: getOrderParams(params.orderParams, {
+				marketType: MarketType.PERP,
+			}),
+		});
+	}
+
+	public async placeAndMatchSignedOrder(
+		signedOrderParams: SignedOrderParams,
+		signature: Buffer,
+		signer: PublicKey,
+		takerInfo: Omit<TakerInfo, 'order'>,
+		makerInfo?: MakerInfo | MakerInfo[],
+		referrerInfo?: ReferrerInfo,
+		txParams?: TxParams,
+		fillerSubAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig, slot } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getPlaceAndMatchSignedOrderIxs(
+					signedOrderParams,
+					signature,
+					signer,
+					takerInfo,
+					makerInfo,
+					referrerInfo,
+					fillerSubAccountId
+				),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		this.perpMarketLastSlotCache.set(
+			signedOrderParams.orderParams.marketIndex,
+			slot
+		);
+		return txSig;
+	}
+
+	/**
+	 * Returns the ed25519 signature verification ix followed by the placeAndMatchSignedOrder ix,
+	 * the program requires the verification ix to immediately precede it
+	 */
+	public async getPlaceAndMatchSignedOrderIxs(
+		signedOrderParams: SignedOrderParams,
+		signature: Buffer,
+		signer: PublicKey,
+		takerInfo: Omit<TakerInfo, 'order'>,
+		makerInfo?: MakerInfo | MakerInfo[],
+		referrerInfo?: ReferrerInfo,
+		fillerSubAccountId?: number
+	): Promise<TransactionInstruction[]> {
+		const filler = await this.getUserAccountPublicKey(fillerSubAccountId);
+		const fillerStatsPublicKey = this.getUserStatsAccountPublicKey();
+
+		makerInfo = Array.isArray(makerInfo)
+			? makerInfo
+			: makerInfo
+			? [makerInfo]
+			: [];
+
+		const userAccounts = [takerInfo.takerUserAccount];
+		for (const maker of makerInfo) {
+			userAccounts.push(maker.makerUserAccount);
+		}
+
+		const remainingAccounts = this.getRemainingAccounts({
+			userAccounts,
+			useMarketLastSlotCache: true,
+			writablePerpMarketIndexes: [signedOrderParams.orderParams.marketIndex],
+		});
+
+		for (const maker of makerInfo) {
+			remainingAccounts.push({
+				pubkey: maker.maker,
+				isWritable: true,
+				isSigner: false,
+			});
+			remainingAccounts.push({
+				pubkey: maker.makerStats,
+				isWritable: true,
+				isSigner: false,
+			});
+			remainingAccounts.push(
+				...this.getMarketMakerProtectionRemainingAccounts(
+					maker.maker,
+					maker.makerUserAccount,
+					true
+				)
+			);
+		}
+
+		if (referrerInfo) {
+			const referrerIsMaker =
+				makerInfo.find((maker) => maker.maker.equals(referrerInfo.referrer)) !==
+				undefined;
+			if (!referrerIsMaker) {
+				remainingAccounts.push({
+					pubkey: referrerInfo.referrer,
+					isWritable: true,
+					isSigner: false,
+				});
+				remainingAccounts.push({
+					pubkey: referrerInfo.referrerStats,
+					isWritable: true,
+					isSigner: false,
+				});
+			}
+		}
+
+		const message = this.encodeSignedOrderParams(signedOrderParams);
+		const verifySignatureIx = Ed25519Program.createInstructionWithPublicKey({
+			publicKey: signer.toBytes(),
+			message,
+			signature,
+		});
+
+		const placeAndMatchIx =
+			await this.program.instruction.placeAndMatchSignedOrder(
+				{
+					...signedOrderParams,
+					orderParams: getOrderParams(signedOrderParams.orderParams, {
+						marketType: MarketType.PERP,
+					}),
+				},
+				{
+					accounts: {
+						state: await this.getStatePublicKey(),
+						authority: this.wallet.publicKey,
+						filler,
+						fillerStats: fillerStatsPublicKey,
+						user: takerInfo.taker,
+						userStats: takerInfo.takerStats,
+						ixSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+					},
+					remainingAccounts,
+				}
+			);
+
+		return [verifySignatureIx, placeAndMatchIx];
+	}
+
 	public async placeAndMakePerpOrder(
 		orderParams: OptionalOrderParams,
 		takerInfo: TakerInfo,
@@ -4864,48 +5415,362 @@ export class DriftClient {
 		);
 	}
 
-	public async placeAndTakeSpotOrder(
-		orderParams: OptionalOrderParams,
-		fulfillmentConfig?: SerumV3FulfillmentConfigAccount,
-		makerInfo?: MakerInfo,
-		referrerInfo?: ReferrerInfo,
+	public async getUserMarketMakerProtectionAccountPublicKey(
+		subAccountId?: number
+	): Promise<PublicKey> {
+		return getUserMarketMakerProtectionAccountPublicKey(
+			this.program.programId,
+			await this.getUserAccountPublicKey(subAccountId)
+		);
+	}
+
+	public async fetchUserMarketMakerProtectionAccount(
+		userMarketMakerProtection: PublicKey
+	): Promise<UserMarketMakerProtectionAccount> {
+		return (await this.program.account.userMarketMakerProtection.fetch(
+			userMarketMakerProtection
+		)) as UserMarketMakerProtectionAccount;
+	}
+
+	public async initializeUserMarketMakerProtection(
 		txParams?: TxParams,
 		subAccountId?: number
 	): Promise<TransactionSignature> {
-		const { txSig, slot } = await this.sendTransaction(
+		const { txSig } = await this.sendTransaction(
 			await this.buildTransaction(
-				await this.getPlaceAndTakeSpotOrderIx(
-					orderParams,
-					fulfillmentConfig,
-					makerInfo,
-					referrerInfo,
-					subAccountId
-				),
+				await this.getInitializeUserMarketMakerProtectionIx(subAccountId),
 				txParams
 			),
 			[],
 			this.opts
 		);
-		this.spotMarketLastSlotCache.set(orderParams.marketIndex, slot);
-		this.spotMarketLastSlotCache.set(QUOTE_SPOT_MARKET_INDEX, slot);
 		return txSig;
 	}
 
-	public async getPlaceAndTakeSpotOrderIx(
-		orderParams: OptionalOrderParams,
-		fulfillmentConfig?: SerumV3FulfillmentConfigAccount,
-		makerInfo?: MakerInfo,
-		referrerInfo?: ReferrerInfo,
+	public async getInitializeUserMarketMakerProtectionIx(
 		subAccountId?: number
 	): Promise<TransactionInstruction> {
-		orderParams = getOrderParams(orderParams, { marketType: MarketType.SPOT });
-		const userStatsPublicKey = this.getUserStatsAccountPublicKey();
-		const user = await this.getUserAccountPublicKey(subAccountId);
-
-		const userAccounts = [this.getUserAccount(subAccountId)];
-		if (makerInfo !== undefined) {
-			userAccounts.push(makerInfo.makerUserAccount);
-		}
+		return await this.program.instruction.initializeUserMarketMakerProtection({
+			accounts: {
+				userMarketMakerProtection:
+					await this.getUserMarketMakerProtectionAccountPublicKey(subAccountId),
+				user: await this.getUserAccountPublicKey(subAccountId),
+				authority: this.wallet.publicKey,
+				payer: this.wallet.publicKey,
+				rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+				systemProgram: anchor.web3.SystemProgram.programId,
+			},
+		});
+	}
+
+	/**
+	 * Sets the maker fill thresholds for a market over a window of slots. Once one is breached, the user's
+	 * maker orders in the market can't be filled until reset. A windowSlots of 0 disables protection for the market
+	 */
+	public async updateUserMarketMakerProtection(
+		marketIndex: number,
+		windowSlots: number,
+		maxBaseAssetAmount: BN,
+		maxDelta: BN,
+		maxFills: number,
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getUpdateUserMarketMakerProtectionIx(
+					marketIndex,
+					windowSlots,
+					maxBaseAssetAmount,
+					maxDelta,
+					maxFills,
+					subAccountId
+				),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getUpdateUserMarketMakerProtectionIx(
+		marketIndex: number,
+		windowSlots: number,
+		maxBaseAssetAmount: BN,
+		maxDelta: BN,
+		maxFills: number,
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		return await this.program.instruction.updateUserMarketMakerProtection(
+			marketIndex,
+			windowSlots,
+			maxBaseAssetAmount,
+			maxDelta,
+			maxFills,
+			{
+				accounts: {
+					userMarketMakerProtection:
+						await this.getUserMarketMakerProtectionAccountPublicKey(
+							subAccountId
+						),
+					user: await this.getUserAccountPublicKey(subAccountId),
+					authority: this.wallet.publicKey,
+				},
+			}
+		);
+	}
+
+	public async resetUserMarketMakerProtection(
+		marketIndex: number,
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getResetUserMarketMakerProtectionIx(
+					marketIndex,
+					subAccountId
+				),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getResetUserMarketMakerProtectionIx(
+		marketIndex: number,
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		return await this.program.instruction.resetUserMarketMakerProtection(
+			marketIndex,
+			{
+				accounts: {
+					userMarketMakerProtection:
+						await this.getUserMarketMakerProtectionAccountPublicKey(
+							subAccountId
+						),
+					user: await this.getUserAccountPublicKey(subAccountId),
+					authority: this.wallet.publicKey,
+				},
+			}
+		);
+	}
+
+	public async getRfqAccountPublicKey(
+		subAccountId?: number
+	): Promise<PublicKey> {
+		return getRfqAccountPublicKey(
+			this.program.programId,
+			await this.getUserAccountPublicKey(subAccountId)
+		);
+	}
+
+	public async fetchRfqAccount(rfq: PublicKey): Promise<RfqAccount> {
+		return (await this.program.account.rfq.fetch(rfq)) as RfqAccount;
+	}
+
+	public async initializeRfq(
+		params: RfqParams,
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getInitializeRfqIx(params, subAccountId),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getInitializeRfqIx(
+		params: RfqParams,
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		return await this.program.instruction.initializeRfq(params, {
+			accounts: {
+				rfq: await this.getRfqAccountPublicKey(subAccountId),
+				user: await this.getUserAccountPublicKey(subAccountId),
+				authority: this.wallet.publicKey,
+				payer: this.wallet.publicKey,
+				rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+				systemProgram: anchor.web3.SystemProgram.programId,
+			},
+		});
+	}
+
+	public async submitRfqQuote(
+		rfq: PublicKey,
+		price: BN,
+		expiryTs: BN,
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getSubmitRfqQuoteIx(rfq, price, expiryTs, subAccountId),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getSubmitRfqQuoteIx(
+		rfq: PublicKey,
+		price: BN,
+		expiryTs: BN,
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		return await this.program.instruction.submitRfqQuote(price, expiryTs, {
+			accounts: {
+				rfq,
+				user: await this.getUserAccountPublicKey(subAccountId),
+				authority: this.wallet.publicKey,
+			},
+		});
+	}
+
+	/**
+	 * Accepts a maker's quote on the user's rfq. Fails if the maker's quote is no longer expectedPrice
+	 */
+	public async acceptRfqQuote(
+		rfqAccount: RfqAccount,
+		makerInfo: MakerInfo,
+		expectedPrice: BN,
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig, slot } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getAcceptRfqQuoteIx(
+					rfqAccount,
+					makerInfo,
+					expectedPrice,
+					subAccountId
+				),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		this.perpMarketLastSlotCache.set(rfqAccount.marketIndex, slot);
+		return txSig;
+	}
+
+	public async getAcceptRfqQuoteIx(
+		rfqAccount: RfqAccount,
+		makerInfo: MakerInfo,
+		expectedPrice: BN,
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		const rfq = getRfqAccountPublicKey(this.program.programId, rfqAccount.user);
+
+		const remainingAccounts = this.getRemainingAccounts({
+			userAccounts: [
+				this.getUserAccount(subAccountId),
+				makerInfo.makerUserAccount,
+			],
+			useMarketLastSlotCache: true,
+			writablePerpMarketIndexes: [rfqAccount.marketIndex],
+		});
+		remainingAccounts.push(
+			...this.getMarketMakerProtectionRemainingAccounts(
+				makerInfo.maker,
+				makerInfo.makerUserAccount,
+				true
+			)
+		);
+
+		return await this.program.instruction.acceptRfqQuote(expectedPrice, {
+			accounts: {
+				state: await this.getStatePublicKey(),
+				rfq,
+				user: await this.getUserAccountPublicKey(subAccountId),
+				userStats: this.getUserStatsAccountPublicKey(),
+				maker: makerInfo.maker,
+				makerStats: makerInfo.makerStats,
+				authority: this.wallet.publicKey,
+			},
+			remainingAccounts,
+		});
+	}
+
+	public async cancelRfq(
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getCancelRfqIx(subAccountId),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getCancelRfqIx(
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		return await this.program.instruction.cancelRfq({
+			accounts: {
+				rfq: await this.getRfqAccountPublicKey(subAccountId),
+				user: await this.getUserAccountPublicKey(subAccountId),
+				authority: this.wallet.publicKey,
+			},
+		});
+	}
+
+	public async placeAndTakeSpotOrder(
+		orderParams: OptionalOrderParams,
+		fulfillmentConfig?: SerumV3FulfillmentConfigAccount,
+		makerInfo?: MakerInfo,
+		referrerInfo?: ReferrerInfo,
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig, slot } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getPlaceAndTakeSpotOrderIx(
+					orderParams,
+					fulfillmentConfig,
+					makerInfo,
+					referrerInfo,
+					subAccountId
+				),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		this.spotMarketLastSlotCache.set(orderParams.marketIndex, slot);
+		this.spotMarketLastSlotCache.set(QUOTE_SPOT_MARKET_INDEX, slot);
+		return txSig;
+	}
+
+	public async getPlaceAndTakeSpotOrderIx(
+		orderParams: OptionalOrderParams,
+		fulfillmentConfig?: SerumV3FulfillmentConfigAccount,
+		makerInfo?: MakerInfo,
+		referrerInfo?: ReferrerInfo,
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		orderParams = getOrderParams(orderParams, { marketType: MarketType.SPOT });
+		const userStatsPublicKey = this.getUserStatsAccountPublicKey();
+		const user = await this.getUserAccountPublicKey(subAccountId);
+
+		const userAccounts = [this.getUserAccount(subAccountId)];
+		if (makerInfo !== undefined) {
+			userAccounts.push(makerInfo.makerUserAccount);
+		}
 		const remainingAccounts = this.getRemainingAccounts({
 			userAccounts,
 			useMarketLastSlotCache: true,
@@ -4965,6 +5830,106 @@ export class DriftClient {
 		);
 	}
 
+	/**
+	 * Takes every leg against the makers passed in and the amm, the tx fails if any leg isn't
+	 * completely filled within its limit price
+	 */
+	public async placeAndTakeComboOrder(
+		params: ComboOrderParams,
+		makerInfo?: MakerInfo | MakerInfo[],
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig, slot } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getPlaceAndTakeComboOrderIx(params, makerInfo, subAccountId),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		for (const leg of params.legs) {
+			if (isVariant(leg.marketType, 'perp')) {
+				this.perpMarketLastSlotCache.set(leg.marketIndex, slot);
+			} else {
+				this.spotMarketLastSlotCache.set(leg.marketIndex, slot);
+			}
+		}
+		return txSig;
+	}
+
+	public async getPlaceAndTakeComboOrderIx(
+		params: ComboOrderParams,
+		makerInfo?: MakerInfo | MakerInfo[],
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		const legs = params.legs.map((leg) => getOrderParams(leg));
+		const userStatsPublicKey = this.getUserStatsAccountPublicKey();
+		const user = await this.getUserAccountPublicKey(subAccountId);
+
+		makerInfo = Array.isArray(makerInfo)
+			? makerInfo
+			: makerInfo
+			? [makerInfo]
+			: [];
+
+		const userAccounts = [this.getUserAccount(subAccountId)];
+		for (const maker of makerInfo) {
+			userAccounts.push(maker.makerUserAccount);
+		}
+
+		const perpLegs = legs.filter((leg) => isVariant(leg.marketType, 'perp'));
+		const spotLegs = legs.filter((leg) => isVariant(leg.marketType, 'spot'));
+
+		const remainingAccounts = this.getRemainingAccounts({
+			userAccounts,
+			useMarketLastSlotCache: true,
+			writablePerpMarketIndexes: perpLegs.map((leg) => leg.marketIndex),
+			writableSpotMarketIndexes: [
+				QUOTE_SPOT_MARKET_INDEX,
+				...spotLegs.map((leg) => leg.marketIndex),
+			],
+		});
+
+		for (const maker of makerInfo) {
+			remainingAccounts.push({
+				pubkey: maker.maker,
+				isWritable: true,
+				isSigner: false,
+			});
+			remainingAccounts.push({
+				pubkey: maker.makerStats,
+				isWritable: true,
+				isSigner: false,
+			});
+			remainingAccounts.push(
+				...this.getMarketMakerProtectionRemainingAccounts(
+					maker.maker,
+					maker.makerUserAccount,
+					true
+				)
+			);
+		}
+
+		// base and quote vault for each spot leg, in leg order
+		for (const leg of spotLegs) {
+			this.addSpotFulfillmentAccounts(leg.marketIndex, remainingAccounts);
+		}
+
+		return await this.program.instruction.placeAndTakeComboOrder(
+			{ legs },
+			{
+				accounts: {
+					state: await this.getStatePublicKey(),
+					user,
+					userStats: userStatsPublicKey,
+					authority: this.wallet.publicKey,
+				},
+				remainingAccounts,
+			}
+		);
+	}
+
 	public async placeAndMakeSpotOrder(
 		orderParams: OptionalOrderParams,
 		takerInfo: TakerInfo,
@@ -5221,6 +6186,12 @@ export class DriftClient {
 			userAccounts: [this.getUserAccount(subAccountId)],
 			useMarketLastSlotCache: true,
 		});
+		remainingAccounts.push(
+			...this.getMarketMakerProtectionRemainingAccounts(
+				user,
+				this.getUserAccount(subAccountId)
+			)
+		);
 
 		const orderParams: ModifyOrderParams = {
 			baseAssetAmount: newBaseAmount || null,
@@ -5344,6 +6315,12 @@ export class DriftClient {
 			userAccounts: [this.getUserAccount(subAccountId)],
 			useMarketLastSlotCache: true,
 		});
+		remainingAccounts.push(
+			...this.getMarketMakerProtectionRemainingAccounts(
+				user,
+				this.getUserAccount(subAccountId)
+			)
+		);
 
 		const orderParams: ModifyOrderParams = {
 			baseAssetAmount: newBaseAmount || null,
@@ -5377,6 +6354,52 @@ export class DriftClient {
 		);
 	}
 
+	/**
+	 * Modifies many open orders in one instruction. Each order is modified the same way as modifyOrder
+	 */
+	public async modifyOrders(
+		params: ModifyOrdersParams[],
+		txParams?: TxParams,
+		subAccountId?: number
+	): Promise<TransactionSignature> {
+		const { txSig } = await this.sendTransaction(
+			await this.buildTransaction(
+				await this.getModifyOrdersIx(params, subAccountId),
+				txParams
+			),
+			[],
+			this.opts
+		);
+		return txSig;
+	}
+
+	public async getModifyOrdersIx(
+		params: ModifyOrdersParams[],
+		subAccountId?: number
+	): Promise<TransactionInstruction> {
+		const user = await this.getUserAccountPublicKey(subAccountId);
+
+		const remainingAccounts = this.getRemainingAccounts({
+			userAccounts: [this.getUserAccount(subAccountId)],
+			useMarketLastSlotCache: true,
+		});
+		remainingAccounts.push(
+			...this.getMarketMakerProtectionRemainingAccounts(
+				user,
+				this.getUserAccount(subAccountId)
+			)
+		);
+
+		return await this.program.instruction.modifyOrders(params, {
+			accounts: {
+				state: await this.getStatePublicKey(),
+				user,
+				authority: this.wallet.publicKey,
+			},
+			remainingAccounts,
+		});
+	}
+
 	public async settlePNLs(
 		users: {
 			settleeUserAccountPublicKey: PublicKey;
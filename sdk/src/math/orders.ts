@@ -4,7 +4,9 @@ import {
 	isVariant,
 	PerpMarketAccount,
 	AMM,
+	MarketType,
 	Order,
+	OrderBitFlag,
 	PositionDirection,
 } from '../types';
 import { ZERO, TWO, ONE } from '../constants/numericConstants';
@@ -182,6 +184,11 @@ export function hasLimitPrice(order: Order, slot: number): boolean {
 }
 
 export function hasAuctionPrice(order: Order, slot: number): boolean {
+	// twap and cross market trigger orders keep other params in the auction prices
+	if (isTwap(order) || isCrossMarketTrigger(order)) {
+		return false;
+	}
+
 	return (
 		!isAuctionComplete(order, slot) &&
 		(!order.auctionStartPrice.eq(ZERO) || !order.auctionEndPrice.eq(ZERO))
@@ -204,7 +211,7 @@ export function isFillableByVAMM(
 				oraclePriceData,
 				slot
 			).gte(market.amm.minOrderSize)) ||
-		isOrderExpired(order, ts)
+		isOrderExpired(order, ts, false, 15, slot)
 	);
 }
 
@@ -233,6 +240,13 @@ export function calculateBaseAssetAmountForAmmToFulfill(
 		baseAssetAmount = order.baseAssetAmount.sub(order.baseAssetAmountFilled);
 	}
 
+	if (isTwap(order)) {
+		baseAssetAmount = BN.min(
+			baseAssetAmount,
+			getTwapBaseAssetAmountAvailable(order, slot)
+		);
+	}
+
 	const maxBaseAssetAmount = calculateMaxBaseAssetAmountFillable(
 		updatedAMM,
 		order.direction
@@ -291,7 +305,8 @@ export function isOrderExpired(
 	order: Order,
 	ts: number,
 	enforceBuffer = false,
-	bufferSeconds = 15
+	bufferSeconds = 15,
+	slot?: number
 ): boolean {
 	if (
 		mustBeTriggered(order) ||
@@ -301,6 +316,11 @@ export function isOrderExpired(
 		return false;
 	}
 
+	// good til slot orders keep a slot in maxTs
+	if (isGoodTilSlot(order)) {
+		return slot !== undefined && new BN(slot).gt(order.maxTs);
+	}
+
 	let maxTs;
 	if (enforceBuffer && isLimitOrder(order)) {
 		maxTs = order.maxTs.addn(bufferSeconds);
@@ -341,3 +361,93 @@ export function isRestingLimitOrder(order: Order, slot: number): boolean {
 export function isTakingOrder(order: Order, slot: number): boolean {
 	return isMarketOrder(order) || !isRestingLimitOrder(order, slot);
 }
+
+/**
+ * Order has no padding left, so some fields are a tagged union: the order type or a bit flag replaces the
+ * field's meaning. Read them through the functions below rather than directly (see Order in the program)
+ *
+ * | field             | tag                               | meaning                         |
+ * |-------------------|-----------------------------------|---------------------------------|
+ * | triggerPrice      | OrderType.TWAP                    | slots between slices            |
+ * | auctionStartPrice | OrderType.TWAP                    | base asset amount of each slice |
+ * | triggerPrice      | OrderBitFlag.QUOTE_SIZE           | quote asset amount of the order |
+ * | maxTs             | OrderBitFlag.GOOD_TIL_SLOT        | last slot the order is valid    |
+ * | auctionStartPrice | OrderBitFlag.CROSS_MARKET_TRIGGER | reference market index          |
+ * | auctionEndPrice   | OrderBitFlag.CROSS_MARKET_TRIGGER | reference market type           |
+ */
+export function isOrderBitFlagSet(order: Order, flag: OrderBitFlag): boolean {
+	return (order.bitFlags & flag) !== 0;
+}
+
+export function isQuoteSized(order: Order): boolean {
+	return isOrderBitFlagSet(order, OrderBitFlag.QUOTE_SIZE);
+}
+
+/**
+ * The quote asset amount of a quote sized order
+ * precision: QUOTE_PRECISION
+ */
+export function getQuoteSize(order: Order): BN {
+	return isQuoteSized(order) ? order.triggerPrice : ZERO;
+}
+
+export function getQuoteAssetAmountUnfilled(order: Order): BN {
+	return BN.max(getQuoteSize(order).sub(order.quoteAssetAmountFilled), ZERO);
+}
+
+export function isGoodTilSlot(order: Order): boolean {
+	return isOrderBitFlagSet(order, OrderBitFlag.GOOD_TIL_SLOT);
+}
+
+export function isCrossMarketTrigger(order: Order): boolean {
+	return isOrderBitFlagSet(order, OrderBitFlag.CROSS_MARKET_TRIGGER);
+}
+
+/**
+ * The market whose price an untriggered cross market trigger order is triggered off
+ */
+export function getTriggerMarket(order: Order): {
+	marketType: MarketType;
+	marketIndex: number;
+} {
+	if (!isCrossMarketTrigger(order)) {
+		return { marketType: order.marketType, marketIndex: order.marketIndex };
+	}
+
+	return {
+		marketType: order.auctionEndPrice.eq(ZERO)
+			? MarketType.PERP
+			: MarketType.SPOT,
+		marketIndex: order.auctionStartPrice.toNumber(),
+	};
+}
+
+export function isTwap(order: Order): boolean {
+	return isVariant(order.orderType, 'twap');
+}
+
+export function getTwapSliceInterval(order: Order): BN {
+	return order.triggerPrice;
+}
+
+export function getTwapSliceBaseAssetAmount(order: Order): BN {
+	return order.auctionStartPrice;
+}
+
+/**
+ * The base asset amount released by the twap slices so far that has yet to be filled
+ */
+export function getTwapBaseAssetAmountAvailable(
+	order: Order,
+	slot: number
+): BN {
+	const slotsElapsed = BN.max(new BN(slot).sub(order.slot), ZERO);
+	const slicesReleased = slotsElapsed.div(getTwapSliceInterval(order)).add(ONE);
+
+	const baseAssetAmountReleased = BN.min(
+		slicesReleased.mul(getTwapSliceBaseAssetAmount(order)),
+		order.baseAssetAmount
+	);
+
+	return BN.max(baseAssetAmountReleased.sub(order.baseAssetAmountFilled), ZERO);
+}
@@ -16,7 +16,11 @@ import {
 import { StrictOraclePrice } from '../oracles/strictOraclePrice';
 
 export function isSpotPositionAvailable(position: SpotPosition): boolean {
-	return position.scaledBalance.eq(ZERO) && position.openOrders === 0;
+	return (
+		position.scaledBalance.eq(ZERO) &&
+		position.openOrders === 0 &&
+		!position.isIsolatedPerpCollateral
+	);
 }
 
 export type OrderFillSimulation = {
@@ -60,6 +60,32 @@ export enum UserStatus {
 export class ContractType {
 	static readonly PERPETUAL = { perpetual: {} };
 	static readonly FUTURE = { future: {} };
+	static readonly OPTION = { option: {} };
+	static readonly PREDICTION = { prediction: {} };
+}
+
+export class OptionType {
+	static readonly CALL = { call: {} };
+	static readonly PUT = { put: {} };
+}
+
+export class PredictionMarketOutcome {
+	static readonly UNRESOLVED = { unresolved: {} };
+	static readonly YES = { yes: {} };
+	static readonly NO = { no: {} };
+}
+
+export class MatchingPolicy {
+	static readonly PRICE_TIME = { priceTime: {} };
+	static readonly PRO_RATA = { proRata: {} };
+	static readonly HYBRID = { hybrid: {} };
+}
+
+export class AuctionCurve {
+	static readonly LINEAR = { linear: {} };
+	static readonly EXPONENTIAL_DECAY = { exponentialDecay: {} };
+	static readonly STEP = { step: {} };
+	static readonly FRONT_LOADED = { frontLoaded: {} };
 }
 
 export class ContractTier {
@@ -115,6 +141,34 @@ export class OrderType {
 	static readonly TRIGGER_LIMIT = { triggerLimit: {} };
 	static readonly MARKET = { market: {} };
 	static readonly ORACLE = { oracle: {} };
+	static readonly TWAP = { twap: {} };
+}
+
+export enum OrderBitFlag {
+	TRAILING_STOP_PERCENTAGE_OFFSET = 1,
+	FILL_OR_KILL = 2,
+	QUOTE_SIZE = 4,
+	GOOD_TIL_SLOT = 8,
+	CROSS_MARKET_TRIGGER = 16,
+	AUCTION_CURVE_LOW = 32,
+	AUCTION_CURVE_HIGH = 64,
+	LINKED_ORDER_ENTRY = 128,
+}
+
+export class TriggerReferencePrice {
+	static readonly ORACLE = { oracle: {} };
+	static readonly ORACLE_TWAP_5MIN = { oracleTwap5Min: {} };
+	static readonly MARK_TWAP_5MIN = { markTwap5Min: {} };
+	static readonly LAST_FILL_PRICE = { lastFillPrice: {} };
+	static readonly LAST_FILL_PRICE_TWAP_5MIN = { lastFillPriceTwap5Min: {} };
+}
+
+export class SelfTradePreventionMode {
+	static readonly NONE = { none: {} };
+	static readonly CANCEL_TAKER = { cancelTaker: {} };
+	static readonly CANCEL_MAKER = { cancelMaker: {} };
+	static readonly CANCEL_BOTH = { cancelBoth: {} };
+	static readonly DECREMENT_AND_CANCEL = { decrementAndCancel: {} };
 }
 
 export declare type MarketTypeStr = 'perp' | 'spot';
@@ -189,6 +243,21 @@ export class OrderActionExplanation {
 	static readonly DERISK_LP = {
 		deriskLp: {},
 	};
+	static readonly LINKED_ORDER_FILLED_OR_TRIGGERED = {
+		linkedOrderFilledOrTriggered: {},
+	};
+	static readonly SELF_TRADE_PREVENTION = {
+		selfTradePrevention: {},
+	};
+	static readonly CANCEL_ORDERS_AFTER_DEADLINE = {
+		cancelOrdersAfterDeadline: {},
+	};
+	static readonly MARKET_MAKER_PROTECTION_TRIGGERED = {
+		marketMakerProtectionTriggered: {},
+	};
+	static readonly TRAILING_STOP_TRIGGER_PRICE_UPDATED = {
+		trailingStopTriggerPriceUpdated: {},
+	};
 }
 
 export class OrderTriggerCondition {
@@ -627,6 +696,16 @@ export type PerpMarketAccount = {
 	quoteSpotMarketIndex: number;
 	feeAdjustment: number;
 	pausedOperations: number;
+	defaultAuctionCurve: AuctionCurve;
+	matchingPolicy: MatchingPolicy;
+	topOfBookAllocation: number;
+	batchAuctionDuration: number;
+	optionType: OptionType;
+	predictionMarketOutcome: PredictionMarketOutcome;
+	lastFillPrice: BN;
+	strikePrice: BN;
+	lastFillPriceTwap5min: BN;
+	lastFillPriceTs: BN;
 };
 
 export type HistoricalOracleData = {
@@ -864,6 +943,7 @@ export type UserStatsAccount = {
 	isReferrer: boolean;
 	authority: PublicKey;
 	ifStakedQuoteAssetAmount: BN;
+	selfTradePreventionMode: SelfTradePreventionMode;
 };
 
 export type UserAccount = {
@@ -893,6 +973,8 @@ export type UserAccount = {
 	hasOpenOrder: boolean;
 	openAuctions: number;
 	hasOpenAuction: boolean;
+	hasMarketMakerProtection: boolean;
+	cancelOrdersAfterTs: BN;
 };
 
 export type SpotPosition = {
@@ -903,6 +985,8 @@ export type SpotPosition = {
 	openBids: BN;
 	openAsks: BN;
 	cumulativeDeposits: BN;
+	isolatedPerpMarketIndex: number;
+	isIsolatedPerpCollateral: boolean;
 };
 
 export type Order = {
@@ -930,6 +1014,20 @@ export type Order = {
 	auctionStartPrice: BN;
 	auctionEndPrice: BN;
 	maxTs: BN;
+	bitFlags: number;
+	linkedOrderGroup: number;
+	triggerReferencePrice: TriggerReferencePrice;
+};
+
+export type TwapParams = {
+	sliceInterval: BN;
+	sliceBaseAssetAmount: BN;
+};
+
+export type TriggerMarketParams = {
+	marketType: MarketType;
+	marketIndex: number;
+	triggerPriceChange: BN | null;
 };
 
 export type OrderParams = {
@@ -950,6 +1048,12 @@ export type OrderParams = {
 	maxTs: BN | null;
 	auctionStartPrice: BN | null;
 	auctionEndPrice: BN | null;
+	bitFlags: number;
+	linkedOrderGroup: number;
+	twapParams: TwapParams | null;
+	triggerReferencePrice: TriggerReferencePrice | null;
+	triggerMarketParams: TriggerMarketParams | null;
+	auctionCurve: AuctionCurve | null;
 };
 
 export class PostOnlyParams {
@@ -974,6 +1078,49 @@ export type ModifyOrderParams = {
 	[Property in keyof OrderParams]?: OrderParams[Property] | null;
 } & { policy?: ModifyOrderPolicy };
 
+export type ModifyOrdersParams = {
+	orderId: number;
+	modifyOrderParams: ModifyOrderParams;
+};
+
+export type QuoteLevel = {
+	priceOffset: number;
+	baseAssetAmount: BN;
+};
+
+export type QuoteSetParams = {
+	marketIndex: number;
+	/**
+	 * If set, level prices are referencePrice + priceOffset. Otherwise levels are oracle offset orders
+	 */
+	referencePrice: BN | null;
+	bids: QuoteLevel[];
+	asks: QuoteLevel[];
+};
+
+/**
+ * Limit orders across perp and spot markets that are taken atomically, each leg must set its marketType
+ */
+export type ComboOrderParams = {
+	legs: OptionalOrderParams[];
+};
+
+export type SignedOrderParams = {
+	orderParams: OptionalOrderParams;
+	/**
+	 * User account the order is placed for
+	 */
+	taker: PublicKey;
+	/**
+	 * Must equal the taker's nextOrderId, so a signed order can only be placed once
+	 */
+	takerNextOrderId: number;
+	/**
+	 * Last slot the signed order can be placed in
+	 */
+	maxSlot: BN;
+};
+
 export class ModifyOrderPolicy {
 	static readonly MUST_MODIFY = { mustModify: {} };
 	static readonly TRY_MODIFY = { tryModify: {} };
@@ -997,6 +1144,12 @@ export const DefaultOrderParams: OrderParams = {
 	maxTs: null,
 	auctionStartPrice: null,
 	auctionEndPrice: null,
+	bitFlags: 0,
+	linkedOrderGroup: 0,
+	twapParams: null,
+	triggerReferencePrice: null,
+	triggerMarketParams: null,
+	auctionCurve: null,
 };
 
 export type MakerInfo = {
@@ -1018,6 +1171,13 @@ export type ReferrerInfo = {
 	referrerStats: PublicKey;
 };
 
+export type BatchAuctionUserInfo = {
+	user: PublicKey;
+	userStats: PublicKey;
+	userAccount: UserAccount;
+	marketMakerProtection?: PublicKey;
+};
+
 type ExactType<T> = Pick<T, keyof T>;
 
 export type BaseTxParams = ExactType<{
@@ -1099,6 +1259,7 @@ export type PrelaunchOracle = {
 	ammLastUpdateSlot: BN;
 	lastUpdateSlot: BN;
 	perpMarketIndex: number;
+	resolver: PublicKey;
 };
 
 export type MarginCategory = 'Initial' | 'Maintenance';
@@ -1153,6 +1314,48 @@ export type ReferrerNameAccount = {
 	userStats: PublicKey;
 };
 
+export type MarketMakerProtection = {
+	windowStartSlot: BN;
+	maxBaseAssetAmount: BN;
+	maxDelta: BN;
+	baseAssetAmount: BN;
+	delta: BN;
+	marketIndex: number;
+	windowSlots: number;
+	maxFills: number;
+	fills: number;
+	triggered: boolean;
+};
+
+export type UserMarketMakerProtectionAccount = {
+	user: PublicKey;
+	markets: MarketMakerProtection[];
+};
+
+export type RfqQuote = {
+	price: BN;
+	expiryTs: BN;
+};
+
+export type RfqAccount = {
+	user: PublicKey;
+	authority: PublicKey;
+	whitelistedMakers: PublicKey[];
+	quotes: RfqQuote[];
+	baseAssetAmount: BN;
+	expiryTs: BN;
+	marketIndex: number;
+	direction: PositionDirection;
+};
+
+export type RfqParams = {
+	marketIndex: number;
+	direction: PositionDirection;
+	baseAssetAmount: BN;
+	expiryTs: BN;
+	whitelistedMakers: PublicKey[];
+};
+
 export type PerpMarketExtendedInfo = {
 	marketIndex: number;
 	/**
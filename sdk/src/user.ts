@@ -200,7 +200,9 @@ export class User {
 		marketIndex: number
 	): SpotPosition | undefined {
 		return userAccount.spotPositions.find(
-			(position) => position.marketIndex === marketIndex
+			(position) =>
+				position.marketIndex === marketIndex &&
+				!position.isIsolatedPerpCollateral
 		);
 	}
 
@@ -237,6 +239,8 @@ export class User {
 			openAsks: ZERO,
 			openBids: ZERO,
 			openOrders: 0,
+			isolatedPerpMarketIndex: 0,
+			isIsolatedPerpCollateral: false,
 		};
 	}
 
@@ -890,8 +894,10 @@ export class User {
 				marketIndex === undefined ||
 				marketIndex === QUOTE_SPOT_MARKET_INDEX ||
 				(includeOpenOrders && spotPosition.openOrders !== 0);
+			// isolated perp collateral only margins its own perp position
 			if (
 				isSpotPositionAvailable(spotPosition) ||
+				spotPosition.isIsolatedPerpCollateral ||
 				(!countForBase && !countForQuote)
 			) {
 				continue;
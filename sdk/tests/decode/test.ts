@@ -146,6 +146,15 @@ function testUserAccountDecode(program: Program, buffer: Buffer, i: number) {
 	assert(anchorUserAccount.hasOpenOrder === customUserAccount.hasOpenOrder);
 	assert(anchorUserAccount.openAuctions === customUserAccount.openAuctions);
 	assert(anchorUserAccount.hasOpenAuction === customUserAccount.hasOpenAuction);
+	assert(
+		anchorUserAccount.hasMarketMakerProtection ===
+			customUserAccount.hasMarketMakerProtection
+	);
+	assert(
+		anchorUserAccount.cancelOrdersAfterTs.eq(
+			customUserAccount.cancelOrdersAfterTs
+		)
+	);
 
 	return [anchorSize, customSize, anchorTime, customTime];
 }
@@ -166,6 +175,8 @@ function testSpotPosition(anchor: SpotPosition, custom: SpotPosition) {
 	assert(anchor.openBids.eq(custom.openBids));
 	assert(anchor.openAsks.eq(custom.openAsks));
 	assert(anchor.cumulativeDeposits.eq(custom.cumulativeDeposits));
+	assert(anchor.isolatedPerpMarketIndex === custom.isolatedPerpMarketIndex);
+	assert(anchor.isIsolatedPerpCollateral === custom.isIsolatedPerpCollateral);
 }
 
 function* getPerpPositions(perpPositions: PerpPosition[]) {
@@ -230,6 +241,11 @@ function testOrder(anchor: Order, custom: Order) {
 	assert(anchor.auctionStartPrice.eq(custom.auctionStartPrice));
 	assert(anchor.auctionEndPrice.eq(custom.auctionEndPrice));
 	assert(anchor.maxTs.eq(custom.maxTs));
+	assert(anchor.bitFlags === custom.bitFlags);
+	assert(anchor.linkedOrderGroup === custom.linkedOrderGroup);
+	assert(
+		enumsAreEqual(anchor.triggerReferencePrice, custom.triggerReferencePrice)
+	);
 }
 
 function enumsAreEqual(e1: any, e2: any) {
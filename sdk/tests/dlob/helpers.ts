@@ -10,6 +10,10 @@ import {
 	SpotMarketAccount,
 	MarketStatus,
 	ContractType,
+	AuctionCurve,
+	MatchingPolicy,
+	OptionType,
+	PredictionMarketOutcome,
 	OracleSource,
 	DevnetSpotMarkets,
 	BASE_PRECISION,
@@ -187,6 +191,16 @@ export const mockPerpMarkets: Array<PerpMarketAccount> = [
 		quoteSpotMarketIndex: 0,
 		feeAdjustment: 0,
 		pausedOperations: 0,
+		defaultAuctionCurve: AuctionCurve.LINEAR,
+		matchingPolicy: MatchingPolicy.PRICE_TIME,
+		topOfBookAllocation: 0,
+		batchAuctionDuration: 0,
+		optionType: OptionType.CALL,
+		predictionMarketOutcome: PredictionMarketOutcome.UNRESOLVED,
+		lastFillPrice: ZERO,
+		strikePrice: ZERO,
+		lastFillPriceTwap5min: ZERO,
+		lastFillPriceTs: ZERO,
 	},
 	{
 		status: MarketStatus.INITIALIZED,
@@ -226,6 +240,16 @@ export const mockPerpMarkets: Array<PerpMarketAccount> = [
 		quoteSpotMarketIndex: 0,
 		feeAdjustment: 0,
 		pausedOperations: 0,
+		defaultAuctionCurve: AuctionCurve.LINEAR,
+		matchingPolicy: MatchingPolicy.PRICE_TIME,
+		topOfBookAllocation: 0,
+		batchAuctionDuration: 0,
+		optionType: OptionType.CALL,
+		predictionMarketOutcome: PredictionMarketOutcome.UNRESOLVED,
+		lastFillPrice: ZERO,
+		strikePrice: ZERO,
+		lastFillPriceTwap5min: ZERO,
+		lastFillPriceTs: ZERO,
 	},
 	{
 		status: MarketStatus.INITIALIZED,
@@ -265,6 +289,16 @@ export const mockPerpMarkets: Array<PerpMarketAccount> = [
 		quoteSpotMarketIndex: 0,
 		feeAdjustment: 0,
 		pausedOperations: 0,
+		defaultAuctionCurve: AuctionCurve.LINEAR,
+		matchingPolicy: MatchingPolicy.PRICE_TIME,
+		topOfBookAllocation: 0,
+		batchAuctionDuration: 0,
+		optionType: OptionType.CALL,
+		predictionMarketOutcome: PredictionMarketOutcome.UNRESOLVED,
+		lastFillPrice: ZERO,
+		strikePrice: ZERO,
+		lastFillPriceTwap5min: ZERO,
+		lastFillPriceTs: ZERO,
 	},
 ];
 
@@ -11,6 +11,7 @@ import {
 	OrderStatus,
 	OrderType,
 	OrderTriggerCondition,
+	TriggerReferencePrice,
 	PRICE_PRECISION,
 	DLOBNode,
 	OraclePriceData,
@@ -25,6 +26,14 @@ import {
 	isVariant,
 	uncrossL2,
 	L2Level,
+	OrderBitFlag,
+	getQuoteSize,
+	getQuoteAssetAmountUnfilled,
+	getTriggerMarket,
+	getTwapSliceInterval,
+	getTwapSliceBaseAssetAmount,
+	getTwapBaseAssetAmountAvailable,
+	hasAuctionPrice,
 } from '../../src';
 
 import { mockPerpMarkets, mockSpotMarkets, mockStateAccount } from './helpers';
@@ -95,6 +104,9 @@ function insertOrderToDLOB(
 			auctionStartPrice,
 			auctionEndPrice,
 			maxTs,
+			bitFlags: 0,
+			linkedOrderGroup: 0,
+			triggerReferencePrice: TriggerReferencePrice.ORACLE,
 		},
 		userAccount.toString(),
 		slot.toNumber()
@@ -146,6 +158,9 @@ function insertTriggerOrderToDLOB(
 			auctionStartPrice,
 			auctionEndPrice,
 			maxTs,
+			bitFlags: 0,
+			linkedOrderGroup: 0,
+			triggerReferencePrice: TriggerReferencePrice.ORACLE,
 		},
 		userAccount.toString(),
 		slot.toNumber()
@@ -6797,3 +6812,244 @@ describe('Uncross L2', () => {
 			.true;
 	});
 });
+
+function mockOrder(order: Partial<Order>): Order {
+	return {
+		status: OrderStatus.OPEN,
+		orderType: OrderType.LIMIT,
+		marketType: MarketType.PERP,
+		slot: new BN(1),
+		orderId: 1,
+		userOrderId: 0,
+		marketIndex: 0,
+		price: PRICE_PRECISION,
+		baseAssetAmount: BASE_PRECISION,
+		baseAssetAmountFilled: ZERO,
+		quoteAssetAmount: ZERO,
+		quoteAssetAmountFilled: ZERO,
+		direction: PositionDirection.LONG,
+		reduceOnly: false,
+		triggerPrice: ZERO,
+		triggerCondition: OrderTriggerCondition.ABOVE,
+		existingPositionDirection: PositionDirection.LONG,
+		postOnly: false,
+		immediateOrCancel: false,
+		oraclePriceOffset: 0,
+		auctionDuration: 0,
+		auctionStartPrice: ZERO,
+		auctionEndPrice: ZERO,
+		maxTs: ZERO,
+		bitFlags: 0,
+		linkedOrderGroup: 0,
+		triggerReferencePrice: TriggerReferencePrice.ORACLE,
+		...order,
+	};
+}
+
+describe('DLOB Order Decoder Tests', () => {
+	it('Decodes quote sized orders', () => {
+		const order = mockOrder({
+			orderType: OrderType.MARKET,
+			triggerPrice: new BN(100).mul(QUOTE_PRECISION),
+			quoteAssetAmountFilled: new BN(40).mul(QUOTE_PRECISION),
+			bitFlags: OrderBitFlag.QUOTE_SIZE,
+		});
+
+		expect(getQuoteSize(order).eq(new BN(100).mul(QUOTE_PRECISION))).to.be
+			.true;
+		expect(
+			getQuoteAssetAmountUnfilled(order).eq(new BN(60).mul(QUOTE_PRECISION))
+		).to.be.true;
+
+		// trigger price isn't a quote size without the flag
+		expect(getQuoteSize({ ...order, bitFlags: 0 }).eq(ZERO)).to.be.true;
+	});
+
+	it('Decodes twap orders', () => {
+		const order = mockOrder({
+			orderType: OrderType.TWAP,
+			slot: new BN(100),
+			baseAssetAmount: BASE_PRECISION.muln(10),
+			baseAssetAmountFilled: BASE_PRECISION.muln(2),
+			triggerPrice: new BN(5),
+			auctionStartPrice: BASE_PRECISION.muln(3),
+			auctionDuration: 10,
+		});
+
+		expect(getTwapSliceInterval(order).eqn(5)).to.be.true;
+		expect(getTwapSliceBaseAssetAmount(order).eq(BASE_PRECISION.muln(3))).to
+			.be.true;
+		expect(hasAuctionPrice(order, 101)).to.be.false;
+
+		// first slice released when placed
+		expect(getTwapBaseAssetAmountAvailable(order, 104).eq(BASE_PRECISION)).to
+			.be.true;
+		expect(
+			getTwapBaseAssetAmountAvailable(order, 105).eq(BASE_PRECISION.muln(4))
+		).to.be.true;
+		// capped at the order size
+		expect(
+			getTwapBaseAssetAmountAvailable(order, 200).eq(BASE_PRECISION.muln(8))
+		).to.be.true;
+		// nothing available until the next slice
+		expect(
+			getTwapBaseAssetAmountAvailable(
+				{ ...order, baseAssetAmountFilled: BASE_PRECISION.muln(3) },
+				104
+			).eq(ZERO)
+		).to.be.true;
+	});
+
+	it('Decodes cross market trigger orders', () => {
+		const order = mockOrder({
+			orderType: OrderType.TRIGGER_MARKET,
+			triggerPrice: new BN(50).mul(PRICE_PRECISION),
+			auctionStartPrice: new BN(1),
+			auctionEndPrice: new BN(1),
+			auctionDuration: 10,
+			bitFlags: OrderBitFlag.CROSS_MARKET_TRIGGER,
+		});
+
+		const triggerMarket = getTriggerMarket(order);
+		expect(isVariant(triggerMarket.marketType, 'spot')).to.be.true;
+		expect(triggerMarket.marketIndex).to.equal(1);
+		expect(hasAuctionPrice(order, 2)).to.be.false;
+
+		const perpTriggerMarket = getTriggerMarket({
+			...order,
+			auctionEndPrice: ZERO,
+		});
+		expect(isVariant(perpTriggerMarket.marketType, 'perp')).to.be.true;
+
+		// orders without the flag trigger off their own market
+		const ownMarket = getTriggerMarket({ ...order, bitFlags: 0 });
+		expect(isVariant(ownMarket.marketType, 'perp')).to.be.true;
+		expect(ownMarket.marketIndex).to.equal(0);
+	});
+
+	it('Decodes good til slot orders', () => {
+		const order = mockOrder({
+			maxTs: new BN(1000),
+			bitFlags: OrderBitFlag.GOOD_TIL_SLOT,
+		});
+
+		expect(isOrderExpired(order, 2000)).to.be.false;
+		expect(isOrderExpired(order, 2000, false, 15, 1000)).to.be.false;
+		expect(isOrderExpired(order, 0, false, 15, 1001)).to.be.true;
+	});
+
+	it('Triggers cross market orders off the reference market', () => {
+		const dlob = new DLOB();
+		const user = Keypair.generate();
+		const slot = 10;
+
+		const order = mockOrder({
+			orderType: OrderType.TRIGGER_MARKET,
+			triggerPrice: new BN(50).mul(PRICE_PRECISION),
+			triggerCondition: OrderTriggerCondition.BELOW,
+			auctionStartPrice: new BN(1),
+			bitFlags: OrderBitFlag.CROSS_MARKET_TRIGGER,
+		});
+		dlob.insertOrder(order, user.publicKey.toString(), slot);
+
+		// the order's own market price doesn't trigger it
+		expect(
+			dlob.findNodesToTrigger(
+				0,
+				slot,
+				new BN(10).mul(PRICE_PRECISION),
+				MarketType.PERP,
+				mockStateAccount
+			).length
+		).to.equal(0);
+
+		const getReferencePrice = (price: BN) => {
+			return (marketType: MarketType, marketIndex: number) =>
+				isVariant(marketType, 'perp') && marketIndex === 1 ? price : undefined;
+		};
+
+		expect(
+			dlob.findCrossMarketNodesToTrigger(
+				0,
+				MarketType.PERP,
+				mockStateAccount,
+				getReferencePrice(new BN(60).mul(PRICE_PRECISION))
+			).length
+		).to.equal(0);
+
+		const nodesToTrigger = dlob.findCrossMarketNodesToTrigger(
+			0,
+			MarketType.PERP,
+			mockStateAccount,
+			getReferencePrice(new BN(40).mul(PRICE_PRECISION))
+		);
+		expect(nodesToTrigger.length).to.equal(1);
+		expect(nodesToTrigger[0].node.order.orderId).to.equal(1);
+	});
+
+	it('Only fills released twap slices', () => {
+		const dlob = new DLOB();
+		const taker = Keypair.generate();
+		const maker = Keypair.generate();
+		const marketIndex = 0;
+		const slot = 12;
+		const oracle = {
+			price: new BN(10).mul(PRICE_PRECISION),
+			slot: new BN(slot),
+			confidence: new BN(1),
+			hasSufficientNumberOfDataPoints: true,
+		};
+
+		dlob.insertOrder(
+			mockOrder({
+				orderType: OrderType.TWAP,
+				slot: new BN(10),
+				price: new BN(11).mul(PRICE_PRECISION),
+				baseAssetAmount: BASE_PRECISION.muln(10),
+				baseAssetAmountFilled: BASE_PRECISION,
+				triggerPrice: new BN(5),
+				auctionStartPrice: BASE_PRECISION,
+			}),
+			taker.publicKey.toString(),
+			slot
+		);
+		dlob.insertOrder(
+			mockOrder({
+				orderId: 2,
+				direction: PositionDirection.SHORT,
+				price: new BN(10).mul(PRICE_PRECISION),
+				baseAssetAmount: BASE_PRECISION.muln(10),
+				postOnly: true,
+			}),
+			maker.publicKey.toString(),
+			slot
+		);
+
+		// first slice is already filled
+		expect(
+			dlob.findTakingNodesToFill(
+				marketIndex,
+				slot,
+				MarketType.PERP,
+				oracle,
+				true,
+				10,
+				undefined,
+				undefined
+			).length
+		).to.equal(0);
+
+		const nodesToFill = dlob.findTakingNodesToFill(
+			marketIndex,
+			slot + 5,
+			MarketType.PERP,
+			oracle,
+			true,
+			10,
+			undefined,
+			undefined
+		);
+		expect(nodesToFill.length).to.equal(1);
+		expect(nodesToFill[0].makerNodes[0].order.orderId).to.equal(2);
+	});
+});
@@ -9,6 +9,7 @@ import {
 	OrderType,
 	PositionDirection,
 	OrderTriggerCondition,
+	TriggerReferencePrice,
 	UserAccount,
 	ZERO,
 } from '../../src';
@@ -40,6 +41,9 @@ export const mockOrder: Order = {
 	auctionStartPrice: ZERO,
 	auctionEndPrice: ZERO,
 	maxTs: ZERO,
+	bitFlags: 0,
+	linkedOrderGroup: 0,
+	triggerReferencePrice: TriggerReferencePrice.ORACLE,
 };
 
 export const mockSpotPosition: SpotPosition = {
@@ -50,6 +54,8 @@ export const mockSpotPosition: SpotPosition = {
 	openBids: ZERO,
 	openAsks: ZERO,
 	cumulativeDeposits: ZERO,
+	isolatedPerpMarketIndex: 0,
+	isIsolatedPerpCollateral: false,
 };
 
 export const mockUserAccount: UserAccount = {
@@ -85,4 +91,6 @@ export const mockUserAccount: UserAccount = {
 	hasOpenOrder: false,
 	openAuctions: 0,
 	hasOpenAuction: false,
+	hasMarketMakerProtection: false,
+	cancelOrdersAfterTs: ZERO,
 };
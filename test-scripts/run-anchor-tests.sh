@@ -67,6 +67,7 @@ test_files=(
   maxDeposit.ts
   cancelAllOrders.ts
   modifyOrder.ts
+  rfq.ts
 )
 
 for test_file in ${test_files[@]}; do
@@ -0,0 +1,220 @@
+import * as anchor from '@coral-xyz/anchor';
+import { assert } from 'chai';
+
+import { Program } from '@coral-xyz/anchor';
+
+import { Keypair } from '@solana/web3.js';
+
+import {
+	BN,
+	PRICE_PRECISION,
+	TestClient,
+	PositionDirection,
+	User,
+	Wallet,
+	EventSubscriber,
+	BASE_PRECISION,
+	OracleSource,
+} from '../sdk/src';
+
+import {
+	initializeQuoteSpotMarket,
+	mockOracle,
+	mockUSDCMint,
+	mockUserUSDCAccount,
+	printTxLogs,
+	sleep,
+} from './testHelpers';
+import { BulkAccountLoader, PEG_PRECISION } from '../sdk';
+
+describe('rfq', () => {
+	const provider = anchor.AnchorProvider.local(undefined, {
+		commitment: 'confirmed',
+		preflightCommitment: 'confirmed',
+	});
+	const connection = provider.connection;
+	anchor.setProvider(provider);
+	const chProgram = anchor.workspace.Drift as Program;
+
+	let makerDriftClient: TestClient;
+	let makerDriftClientUser: User;
+	const eventSubscriber = new EventSubscriber(connection, chProgram, {
+		commitment: 'recent',
+	});
+	eventSubscriber.subscribe();
+
+	const bulkAccountLoader = new BulkAccountLoader(connection, 'confirmed', 1);
+
+	// ammInvariant == k == x * y
+	const mantissaSqrtScale = new BN(Math.sqrt(PRICE_PRECISION.toNumber()));
+	const ammInitialQuoteAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+	const ammInitialBaseAssetReserve = new anchor.BN(5 * 10 ** 13).mul(
+		mantissaSqrtScale
+	);
+
+	let usdcMint;
+	let userUSDCAccount;
+
+	const usdcAmount = new BN(100 * 10 ** 6);
+
+	let solUsd;
+	let marketIndexes;
+	let spotMarketIndexes;
+	let oracleInfos;
+
+	before(async () => {
+		usdcMint = await mockUSDCMint(provider);
+		userUSDCAccount = await mockUserUSDCAccount(usdcMint, usdcAmount, provider);
+
+		solUsd = await mockOracle(32.821);
+
+		marketIndexes = [0];
+		spotMarketIndexes = [0, 1];
+		oracleInfos = [{ publicKey: solUsd, source: OracleSource.PYTH }];
+
+		makerDriftClient = new TestClient({
+			connection,
+			wallet: provider.wallet,
+			programID: chProgram.programId,
+			opts: {
+				commitment: 'confirmed',
+			},
+			activeSubAccountId: 0,
+			perpMarketIndexes: marketIndexes,
+			spotMarketIndexes: spotMarketIndexes,
+			oracleInfos,
+			accountSubscription: {
+				type: 'polling',
+				accountLoader: bulkAccountLoader,
+			},
+		});
+		await makerDriftClient.initialize(usdcMint.publicKey, true);
+		await makerDriftClient.subscribe();
+		await initializeQuoteSpotMarket(makerDriftClient, usdcMint.publicKey);
+
+		const periodicity = new BN(0);
+		await makerDriftClient.initializePerpMarket(
+			0,
+			solUsd,
+			ammInitialBaseAssetReserve,
+			ammInitialQuoteAssetReserve,
+			periodicity,
+			new BN(32 * PEG_PRECISION.toNumber())
+		);
+
+		await makerDriftClient.initializeUserAccountAndDepositCollateral(
+			usdcAmount,
+			userUSDCAccount.publicKey
+		);
+
+		makerDriftClientUser = new User({
+			driftClient: makerDriftClient,
+			userAccountPublicKey: await makerDriftClient.getUserAccountPublicKey(),
+		});
+		await makerDriftClientUser.subscribe();
+	});
+
+	after(async () => {
+		await makerDriftClient.unsubscribe();
+		await makerDriftClientUser.unsubscribe();
+		await eventSubscriber.unsubscribe();
+	});
+
+	it('quote and accept', async () => {
+		const keypair = new Keypair();
+		await provider.connection.requestAirdrop(keypair.publicKey, 10 ** 9);
+		await sleep(1000);
+		const wallet = new Wallet(keypair);
+		const userUSDCAccount = await mockUserUSDCAccount(
+			usdcMint,
+			usdcAmount,
+			provider,
+			keypair.publicKey
+		);
+		const takerDriftClient = new TestClient({
+			connection,
+			wallet,
+			programID: chProgram.programId,
+			opts: {
+				commitment: 'confirmed',
+			},
+			activeSubAccountId: 0,
+			perpMarketIndexes: marketIndexes,
+			spotMarketIndexes: spotMarketIndexes,
+			oracleInfos,
+			userStats: true,
+			accountSubscription: {
+				type: 'polling',
+				accountLoader: bulkAccountLoader,
+			},
+		});
+		await takerDriftClient.subscribe();
+		await takerDriftClient.initializeUserAccountAndDepositCollateral(
+			usdcAmount,
+			userUSDCAccount.publicKey
+		);
+
+		const now = await connection.getBlockTime(await connection.getSlot());
+		const expiryTs = new BN(now + 60);
+		const maker = await makerDriftClient.getUserAccountPublicKey();
+
+		await takerDriftClient.initializeRfq({
+			marketIndex: 0,
+			direction: PositionDirection.LONG,
+			baseAssetAmount: BASE_PRECISION,
+			expiryTs,
+			whitelistedMakers: [maker],
+		});
+
+		const rfq = await takerDriftClient.getRfqAccountPublicKey();
+		let rfqAccount = await takerDriftClient.fetchRfqAccount(rfq);
+		assert(rfqAccount.whitelistedMakers[0].equals(maker));
+		assert(rfqAccount.baseAssetAmount.eq(BASE_PRECISION));
+
+		const price = new BN(33).mul(PRICE_PRECISION);
+		await makerDriftClient.submitRfqQuote(rfq, price, expiryTs);
+
+		rfqAccount = await takerDriftClient.fetchRfqAccount(rfq);
+		assert(rfqAccount.quotes[0].price.eq(price));
+
+		const makerInfo = {
+			maker,
+			makerStats: makerDriftClient.getUserStatsAccountPublicKey(),
+			makerUserAccount: makerDriftClient.getUserAccount(),
+		};
+
+		try {
+			await takerDriftClient.acceptRfqQuote(
+				rfqAccount,
+				makerInfo,
+				new BN(32).mul(PRICE_PRECISION)
+			);
+			assert(false, 'accepted a quote at a different price');
+		} catch (e) {
+			assert(e.message.includes('0x187b'));
+		}
+
+		const txSig = await takerDriftClient.acceptRfqQuote(
+			rfqAccount,
+			makerInfo,
+			price
+		);
+
+		await printTxLogs(connection, txSig);
+
+		await makerDriftClient.fetchAccounts();
+		await takerDriftClient.fetchAccounts();
+
+		const makerPosition = makerDriftClient.getUser().getPerpPosition(0);
+		assert(makerPosition.baseAssetAmount.eq(BASE_PRECISION.neg()));
+
+		const takerPosition = takerDriftClient.getUser().getPerpPosition(0);
+		assert(takerPosition.baseAssetAmount.eq(BASE_PRECISION));
+
+		assert((await connection.getAccountInfo(rfq)) === null);
+
+		await takerDriftClient.unsubscribe();
+	});
+});